@@ -0,0 +1,106 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Measures the cost of building, queueing and delivering notifications of various sizes.
+//!
+//! Alongside the timings, the number of heap allocations performed for each notification is
+//! printed. Notifications whose body is small enough should be built and delivered without
+//! touching the heap.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use redshirt_core::{EncodedMessage, MessageId, Pid};
+use redshirt_syscalls::ffi::{build_interface_notification, NotificationBuilder};
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    collections::VecDeque,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// Wraps around the system allocator and counts the number of allocations.
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+/// Builds a notification, pushes it to `queue`, then pops it and copies it to `out`, similar to
+/// what the kernel does when delivering a message to a process.
+fn round_trip(
+    queue: &mut VecDeque<NotificationBuilder>,
+    message: &EncodedMessage,
+    out: &mut [u8],
+) -> usize {
+    let notification = build_interface_notification(
+        &From::from([0xca; 32]),
+        Some(MessageId::from(0x1234)),
+        Pid::from(5),
+        0,
+        message,
+    );
+    queue.push_back(From::from(notification));
+
+    let mut notification = queue.pop_front().unwrap();
+    notification.set_index_in_list(3);
+    let bytes = notification.as_bytes();
+    out[..bytes.len()].copy_from_slice(bytes);
+    bytes.len()
+}
+
+fn bench(c: &mut Criterion) {
+    let mut group = c.benchmark_group("notification-round-trip");
+
+    for size in &[8, 32, 64, 256, 4096] {
+        let message = EncodedMessage(vec![0xaa; *size]);
+        let mut queue = VecDeque::with_capacity(16);
+        let mut out = vec![0; 8192];
+
+        let before = ALLOCATIONS.load(Ordering::Relaxed);
+        for _ in 0..1000 {
+            round_trip(&mut queue, &message, &mut out);
+        }
+        let after = ALLOCATIONS.load(Ordering::Relaxed);
+        println!(
+            "{}-bytes body: {} heap allocation(s) per notification",
+            size,
+            (after - before) as f64 / 1000.0
+        );
+
+        group.bench_with_input(BenchmarkId::from_parameter(size), &message, |b, message| {
+            b.iter(|| round_trip(&mut queue, message, &mut out))
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench);
+criterion_main!(benches);
@@ -0,0 +1,333 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Table of the resources held on behalf of processes.
+//!
+//! Each resource in a [`HandleTable`] belongs to a process, has a [`ResourceKind`], and is
+//! designated by a [`Handle`]. Accessing a resource requires indicating which process is making
+//! the access and which kind of resource is expected, which prevents a process from using a
+//! resource that belongs to another process, or from passing a socket where a file is expected.
+//!
+//! A [`Handle`] contains a generation counter. Once a resource has been removed, its handle
+//! stays invalid even after its slot has been reused for a different resource.
+
+use crate::native::ResourceKind;
+
+use alloc::vec::Vec;
+use core::{convert::TryFrom as _, fmt};
+use hashbrown::HashMap;
+use nohash_hasher::BuildNoHashHasher;
+use redshirt_syscalls::{Handle, Pid};
+
+/// Collection of resources, each belonging to a process.
+pub struct HandleTable<T> {
+    /// Storage for the resources. The index within this list is part of the [`Handle`].
+    /// We assume that this never contains more than 2^32 elements.
+    slots: Vec<Slot<T>>,
+    /// Indices within `slots` that are empty.
+    free_slots: Vec<u32>,
+    /// Number of resources owned by each process. Processes that don't own any resource aren't
+    /// in this map.
+    owned: HashMap<Pid, usize, BuildNoHashHasher<u64>>,
+}
+
+/// Entry in [`HandleTable::slots`].
+struct Slot<T> {
+    /// Incremented every time the resource in this slot is removed.
+    generation: u32,
+    /// Resource in this slot, or `None` if the slot is free.
+    entry: Option<Entry<T>>,
+}
+
+/// Resource in a [`Slot`].
+struct Entry<T> {
+    owner: Pid,
+    kind: ResourceKind,
+    value: T,
+}
+
+/// Error when accessing a resource in a [`HandleTable`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HandleErr {
+    /// The handle doesn't correspond to any resource, or the resource has been removed.
+    Invalid,
+    /// The resource belongs to a different process.
+    WrongOwner,
+    /// The resource isn't of the expected kind.
+    WrongKind {
+        /// Actual kind of the resource.
+        actual: ResourceKind,
+    },
+}
+
+impl<T> HandleTable<T> {
+    /// Builds a new empty table.
+    pub fn new() -> Self {
+        HandleTable {
+            slots: Vec::new(),
+            free_slots: Vec::new(),
+            owned: HashMap::with_hasher(Default::default()),
+        }
+    }
+
+    /// Inserts a new resource in the table and returns its handle.
+    pub fn insert(&mut self, owner: Pid, kind: ResourceKind, value: T) -> Handle {
+        let entry = Some(Entry { owner, kind, value });
+        *self.owned.entry(owner).or_insert(0) += 1;
+
+        if let Some(index) = self.free_slots.pop() {
+            let slot = &mut self.slots[index as usize];
+            debug_assert!(slot.entry.is_none());
+            slot.entry = entry;
+            return handle(index, slot.generation);
+        }
+
+        let index = u32::try_from(self.slots.len()).unwrap();
+        self.slots.push(Slot {
+            generation: 0,
+            entry,
+        });
+        handle(index, 0)
+    }
+
+    /// Returns the resource designated by the handle.
+    pub fn get(&self, owner: Pid, handle: Handle, kind: ResourceKind) -> Result<&T, HandleErr> {
+        let index = self.check(owner, handle, kind)?;
+        match &self.slots[index].entry {
+            Some(entry) => Ok(&entry.value),
+            None => unreachable!(),
+        }
+    }
+
+    /// Returns the resource designated by the handle.
+    pub fn get_mut(
+        &mut self,
+        owner: Pid,
+        handle: Handle,
+        kind: ResourceKind,
+    ) -> Result<&mut T, HandleErr> {
+        let index = self.check(owner, handle, kind)?;
+        match &mut self.slots[index].entry {
+            Some(entry) => Ok(&mut entry.value),
+            None => unreachable!(),
+        }
+    }
+
+    /// Removes the resource designated by the handle from the table. The handle becomes invalid.
+    pub fn remove(
+        &mut self,
+        owner: Pid,
+        handle: Handle,
+        kind: ResourceKind,
+    ) -> Result<T, HandleErr> {
+        let index = self.check(owner, handle, kind)?;
+        Ok(self.remove_index(index).value)
+    }
+
+    /// Gives the ownership of the resource designated by the handle to another process.
+    ///
+    /// The handle stays the same, but can from now on only be used by `new_owner`.
+    pub fn transfer(
+        &mut self,
+        owner: Pid,
+        handle: Handle,
+        kind: ResourceKind,
+        new_owner: Pid,
+    ) -> Result<(), HandleErr> {
+        let index = self.check(owner, handle, kind)?;
+        match &mut self.slots[index].entry {
+            Some(entry) => entry.owner = new_owner,
+            None => unreachable!(),
+        }
+        self.decrease_owned(owner);
+        *self.owned.entry(new_owner).or_insert(0) += 1;
+        Ok(())
+    }
+
+    /// Removes from the table all the resources that belong to the given process.
+    ///
+    /// This is meant to be called when a process terminates, in order to release the resources
+    /// it didn't clean up.
+    pub fn remove_process(&mut self, owner: Pid) -> Vec<(Handle, ResourceKind, T)> {
+        if !self.owned.contains_key(&owner) {
+            return Vec::new();
+        }
+
+        let indices = self
+            .slots
+            .iter()
+            .enumerate()
+            .filter(|(_, slot)| slot.entry.as_ref().map_or(false, |e| e.owner == owner))
+            .map(|(index, _)| index)
+            .collect::<Vec<_>>();
+
+        indices
+            .into_iter()
+            .map(|index| {
+                let handle = handle(u32::try_from(index).unwrap(), self.slots[index].generation);
+                let entry = self.remove_index(index);
+                (handle, entry.kind, entry.value)
+            })
+            .collect()
+    }
+
+    /// Returns the number of resources that belong to the given process.
+    pub fn num_owned(&self, owner: Pid) -> usize {
+        self.owned.get(&owner).cloned().unwrap_or(0)
+    }
+
+    /// Returns the total number of resources in the table.
+    pub fn len(&self) -> usize {
+        self.slots.len() - self.free_slots.len()
+    }
+
+    /// Returns true if the table doesn't contain any resource.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Checks whether the handle can be used, and returns its index within `slots`.
+    fn check(&self, owner: Pid, handle: Handle, kind: ResourceKind) -> Result<usize, HandleErr> {
+        let (index, generation) = split(handle);
+        let slot = self.slots.get(index as usize).ok_or(HandleErr::Invalid)?;
+        let entry = match &slot.entry {
+            Some(entry) if slot.generation == generation => entry,
+            _ => return Err(HandleErr::Invalid),
+        };
+
+        if entry.owner != owner {
+            return Err(HandleErr::WrongOwner);
+        }
+        if entry.kind != kind {
+            return Err(HandleErr::WrongKind { actual: entry.kind });
+        }
+        Ok(index as usize)
+    }
+
+    /// Empties the slot at the given index, which must contain a resource.
+    fn remove_index(&mut self, index: usize) -> Entry<T> {
+        let slot = &mut self.slots[index];
+        let entry = match slot.entry.take() {
+            Some(entry) => entry,
+            None => unreachable!(),
+        };
+        slot.generation = slot.generation.wrapping_add(1);
+        self.free_slots.push(u32::try_from(index).unwrap());
+        self.decrease_owned(entry.owner);
+        entry
+    }
+
+    fn decrease_owned(&mut self, owner: Pid) {
+        match self.owned.get_mut(&owner) {
+            Some(n) if *n == 1 => {
+                self.owned.remove(&owner);
+            }
+            Some(n) => *n -= 1,
+            None => unreachable!(),
+        }
+    }
+}
+
+impl<T> Default for HandleTable<T> {
+    fn default() -> Self {
+        HandleTable::new()
+    }
+}
+
+impl<T> fmt::Debug for HandleTable<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("HandleTable")
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+impl fmt::Display for HandleErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HandleErr::Invalid => write!(f, "Invalid handle"),
+            HandleErr::WrongOwner => write!(f, "Handle belongs to a different process"),
+            HandleErr::WrongKind { actual } => write!(f, "Handle designates a {:?}", actual),
+        }
+    }
+}
+
+/// Builds a [`Handle`] from an index within the slots and a generation.
+fn handle(index: u32, generation: u32) -> Handle {
+    Handle::from(u64::from(generation) << 32 | u64::from(index))
+}
+
+/// Opposite of [`handle`].
+fn split(handle: Handle) -> (u32, u32) {
+    let raw = u64::from(handle);
+    (raw as u32, (raw >> 32) as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{HandleErr, HandleTable};
+    use crate::native::ResourceKind;
+    use redshirt_syscalls::Pid;
+
+    #[test]
+    fn stale_handle_rejected() {
+        let owner = Pid::from(1);
+        let mut table = HandleTable::new();
+
+        let first = table.insert(owner, ResourceKind::Socket, 'a');
+        assert_eq!(table.remove(owner, first, ResourceKind::Socket), Ok('a'));
+
+        // The slot is reused, but the old handle stays invalid.
+        let second = table.insert(owner, ResourceKind::Socket, 'b');
+        assert_ne!(first, second);
+        assert_eq!(
+            table.get(owner, first, ResourceKind::Socket),
+            Err(HandleErr::Invalid)
+        );
+        assert_eq!(table.get(owner, second, ResourceKind::Socket), Ok(&'b'));
+        assert_eq!(
+            table.get(owner, second, ResourceKind::File),
+            Err(HandleErr::WrongKind {
+                actual: ResourceKind::Socket
+            })
+        );
+    }
+
+    #[test]
+    fn transfer_and_remove_process() {
+        let (a, b) = (Pid::from(1), Pid::from(2));
+        let mut table = HandleTable::new();
+
+        let file = table.insert(a, ResourceKind::File, 1);
+        let timer = table.insert(a, ResourceKind::Timer, 2);
+        table.insert(b, ResourceKind::Pipe, 3);
+
+        table.transfer(a, file, ResourceKind::File, b).unwrap();
+        assert_eq!(
+            table.get(a, file, ResourceKind::File),
+            Err(HandleErr::WrongOwner)
+        );
+        assert_eq!((table.num_owned(a), table.num_owned(b)), (1, 2));
+
+        let released = table.remove_process(a);
+        assert_eq!(released.len(), 1);
+        assert_eq!(released[0].0, timer);
+        assert_eq!(table.num_owned(a), 0);
+        assert_eq!(table.len(), 2);
+
+        assert_eq!(table.remove_process(b).len(), 2);
+        assert!(table.is_empty());
+    }
+}
@@ -13,7 +13,7 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use core::fmt;
+use core::{fmt, sync::atomic};
 use crossbeam_queue::SegQueue;
 use rand::distributions::{Distribution as _, Uniform};
 use rand_chacha::ChaCha20Rng;
@@ -27,6 +27,11 @@ use spinning_top::Spinlock;
 // where `n` is the number of generated IDs, `b` number of bits in the ID (64 here)
 
 /// Lock-free pool of identifiers. Can assign new identifiers from it.
+///
+/// Identifiers are chosen randomly. A pool created with [`IdPool::new`] can, in principle,
+/// assign the same identifier twice. A pool created with [`IdPool::without_reuse`] instead
+/// guarantees that it never does, by assigning identifiers sequentially and shuffling them with
+/// a random permutation.
 pub struct IdPool {
     /// Sources of randomness.
     /// Every time we need a random number, we pop a state from this list, then push it back when
@@ -39,6 +44,10 @@ pub struct IdPool {
     // TODO: is it actually needed to have a different algorithm, or is this comment bullshit?
     //       using a different algorithm doesn't hurt, but it'd be better if the comment was correct
     master_rng: Spinlock<Hc128Rng>,
+    /// If `Some`, identifiers are derived from a counter rather than generated randomly. Contains
+    /// the number of identifiers assigned so far, and the key of the permutation applied to the
+    /// counter.
+    sequential: Option<(atomic::AtomicU64, u64)>,
 }
 
 impl IdPool {
@@ -48,15 +57,42 @@ impl IdPool {
             rngs: SegQueue::new(),
             distribution: Uniform::from(0..=u64::max_value()),
             master_rng: Spinlock::new(Hc128Rng::from_seed([0; 32])), // FIXME: proper seed
+            sequential: None,
+        }
+    }
+
+    /// Initializes a new pool that never assigns the same identifier twice, even after the
+    /// object that an identifier was assigned to has been destroyed.
+    ///
+    /// This guarantees that an identifier that has been stored somewhere can't later refer to a
+    /// different object. Identifiers are still unpredictable for someone who doesn't know the
+    /// key of the permutation, but they are no longer independent from each other.
+    pub fn without_reuse() -> Self {
+        let pool = IdPool::new();
+        let key = pool.random_id();
+        IdPool {
+            sequential: Some((atomic::AtomicU64::new(0), key)),
+            ..pool
         }
     }
 
     /// Assigns a new PID from this pool.
     pub fn assign<T: From<u64>>(&self) -> T {
+        if let Some((counter, key)) = &self.sequential {
+            // The counter would need to be incremented 2^64 times before wrapping.
+            let n = counter.fetch_add(1, atomic::Ordering::Relaxed);
+            return T::from(permute(n, *key));
+        }
+
+        T::from(self.random_id())
+    }
+
+    /// Generates a random identifier, without checking whether it has already been assigned.
+    fn random_id(&self) -> u64 {
         if let Ok(mut rng) = self.rngs.pop() {
             let id = self.distribution.sample(&mut rng);
             self.rngs.push(rng);
-            return T::from(id);
+            return id;
         }
 
         let mut master_rng = self.master_rng.lock();
@@ -66,10 +102,19 @@ impl IdPool {
         };
         let id = self.distribution.sample(&mut new_rng);
         self.rngs.push(new_rng);
-        T::from(id)
+        id
     }
 }
 
+/// Bijective function from `u64` to `u64` whose output looks random. Based on the finalizer of
+/// SplitMix64, where each step is invertible.
+fn permute(n: u64, key: u64) -> u64 {
+    let mut x = n ^ key;
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
+
 impl fmt::Debug for IdPool {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_tuple("IdPool").finish()
@@ -88,4 +133,25 @@ mod tests {
             assert!(ids.insert(pool.assign()));
         }
     }
+
+    #[test]
+    fn without_reuse_ids_different() {
+        let mut ids = hashbrown::HashSet::<u64, BuildNoHashHasher<u64>>::default();
+        let pool = super::IdPool::without_reuse();
+        for _ in 0..5000 {
+            assert!(ids.insert(pool.assign()));
+        }
+
+        // Only a counter is kept, no matter how many identifiers have been assigned.
+        let (counter, _) = pool.sequential.as_ref().unwrap();
+        assert_eq!(counter.load(core::sync::atomic::Ordering::Relaxed), 5000);
+    }
+
+    #[test]
+    fn permutation_bijective_on_sample() {
+        let mut outputs = hashbrown::HashSet::<u64, BuildNoHashHasher<u64>>::default();
+        for n in (0..1000).chain(u64::max_value() - 1000..=u64::max_value()) {
+            assert!(outputs.insert(super::permute(n, 0x1234_5678_9abc_def0)));
+        }
+    }
 }
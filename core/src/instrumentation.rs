@@ -0,0 +1,237 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Transformations applied on modules before they are instantiated.
+//!
+//! An [`InstrumentationPass`] is given the parsed representation of a module and can freely
+//! modify it, for example in order to insert calls to an import at the start of each function.
+//! Passes are grouped in an [`Instrumentation`], which applies them in the order in which they
+//! have been added.
+//!
+//! Instrumentation happens entirely before the module is handed to the virtual machine. The
+//! virtual machine isn't aware of passes and doesn't need to be modified in order to support
+//! new ones.
+//!
+//! In order to apply passes, build the [`Module`](crate::module::Module) with
+//! [`Module::from_bytes_instrumented`](crate::module::Module::from_bytes_instrumented) or
+//! pass them to [`SystemBuilder::with_instrumentation_pass`](crate::SystemBuilder::with_instrumentation_pass).
+//!
+//! > **Note**: The hash of a module is always calculated from the original bytes, before any
+//! >           instrumentation.
+
+use crate::module::ModuleHash;
+
+use alloc::{boxed::Box, string::String, vec::Vec};
+use core::fmt;
+
+pub mod coverage;
+
+/// Representation of a parsed module that passes operate upon.
+///
+/// Re-exported so that implementers of [`InstrumentationPass`] are guaranteed to use the same
+/// version of `parity-wasm` as this crate.
+pub use parity_wasm::elements;
+
+/// Transformation applied on a module before it is instantiated.
+pub trait InstrumentationPass: Send + Sync {
+    /// Returns a human-readable name for this pass. Used when reporting errors.
+    fn name(&self) -> &str;
+
+    /// Applies the pass on the given module.
+    ///
+    /// `module_hash` is the hash of the original module, before any pass has been applied. It
+    /// can be used by passes that need to keep track of the modules they have modified.
+    ///
+    /// The module must still be valid after the pass has been applied. Passes are not supposed
+    /// to fail, except if the module contains something that the pass can't handle.
+    fn apply(
+        &self,
+        module_hash: &ModuleHash,
+        module: &mut elements::Module,
+    ) -> Result<(), PassError>;
+}
+
+/// Error that an [`InstrumentationPass`] can return.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PassError {
+    /// Explanation of what went wrong.
+    pub reason: String,
+}
+
+/// Ordered list of [`InstrumentationPass`]es.
+#[derive(Default)]
+pub struct Instrumentation {
+    passes: Vec<Box<dyn InstrumentationPass>>,
+}
+
+/// Error that can happen when instrumenting a module.
+#[derive(Debug)]
+pub enum InstrumentError {
+    /// Error while parsing the input bytes.
+    Parse,
+    /// One of the passes has returned an error.
+    Pass {
+        /// Name of the pass, as returned by [`InstrumentationPass::name`].
+        pass: String,
+        /// Error returned by the pass.
+        error: PassError,
+    },
+    /// The module produced by the passes is invalid.
+    InvalidOutput,
+    /// The input module has a signature that isn't valid. See
+    /// [`ModuleErr::BadSignature`](crate::module::ModuleErr::BadSignature).
+    BadSignature,
+}
+
+impl Instrumentation {
+    /// Builds an empty list of passes.
+    pub fn new() -> Self {
+        Instrumentation { passes: Vec::new() }
+    }
+
+    /// Adds a pass at the end of the list.
+    pub fn with_pass(mut self, pass: impl InstrumentationPass + 'static) -> Self {
+        self.push(pass);
+        self
+    }
+
+    /// Adds a pass at the end of the list.
+    pub fn push(&mut self, pass: impl InstrumentationPass + 'static) {
+        self.passes.push(Box::new(pass));
+    }
+
+    /// Returns true if the list doesn't contain any pass.
+    pub fn is_empty(&self) -> bool {
+        self.passes.is_empty()
+    }
+
+    /// Returns the names of the passes, in the order in which they are applied.
+    pub fn pass_names(&self) -> impl Iterator<Item = &str> {
+        self.passes.iter().map(|p| p.name())
+    }
+
+    /// Applies all the passes, in order, on the given module.
+    pub(crate) fn instrument(
+        &self,
+        module_hash: &ModuleHash,
+        module: &mut elements::Module,
+    ) -> Result<(), InstrumentError> {
+        for pass in &self.passes {
+            pass.apply(module_hash, module)
+                .map_err(|error| InstrumentError::Pass {
+                    pass: pass.name().into(),
+                    error,
+                })?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Instrumentation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.pass_names()).finish()
+    }
+}
+
+impl fmt::Display for PassError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.reason)
+    }
+}
+
+impl fmt::Display for InstrumentError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InstrumentError::Parse => write!(f, "Failed to parse module"),
+            InstrumentError::Pass { pass, error } => write!(f, "Pass {} failed: {}", pass, error),
+            InstrumentError::InvalidOutput => write!(f, "Instrumented module is invalid"),
+            InstrumentError::BadSignature => write!(f, "Invalid module signature"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{elements, Instrumentation, InstrumentationPass, PassError};
+    use crate::module::{Module, ModuleHash};
+
+    /// Pass that adds an exported global named `instrumented`.
+    struct AddGlobal;
+
+    impl InstrumentationPass for AddGlobal {
+        fn name(&self) -> &str {
+            "add-global"
+        }
+
+        fn apply(&self, _: &ModuleHash, module: &mut elements::Module) -> Result<(), PassError> {
+            // The modules used in these tests don't have any other global.
+            *module = parity_wasm::builder::from_module(module.clone())
+                .global()
+                .value_type()
+                .i32()
+                .init_expr(elements::Instruction::I32Const(5))
+                .build()
+                .export()
+                .field("instrumented")
+                .internal()
+                .global(0)
+                .build()
+                .build();
+            Ok(())
+        }
+    }
+
+    struct Failing;
+
+    impl InstrumentationPass for Failing {
+        fn name(&self) -> &str {
+            "failing"
+        }
+
+        fn apply(&self, _: &ModuleHash, _: &mut elements::Module) -> Result<(), PassError> {
+            Err(PassError {
+                reason: "nope".into(),
+            })
+        }
+    }
+
+    #[test]
+    fn passes_applied() {
+        let bytes = wat_to_bin!("(module)");
+        let instrumentation = Instrumentation::new().with_pass(AddGlobal);
+        let module = Module::from_bytes_instrumented(bytes, &instrumentation).unwrap();
+        let instance =
+            wasmi::ModuleInstance::new(module.as_ref(), &wasmi::ImportsBuilder::default())
+                .unwrap()
+                .assert_no_start();
+        assert!(instance.export_by_name("instrumented").is_some());
+        assert_eq!(module.hash(), Module::from_bytes(bytes).unwrap().hash());
+    }
+
+    #[test]
+    fn pass_error_reported() {
+        let instrumentation = Instrumentation::new()
+            .with_pass(AddGlobal)
+            .with_pass(Failing);
+        match Module::from_bytes_instrumented(wat_to_bin!("(module)"), &instrumentation) {
+            Err(super::InstrumentError::Pass { pass, error }) => {
+                assert_eq!(pass, "failing");
+                assert_eq!(error.reason, "nope");
+            }
+            _ => panic!(),
+        }
+    }
+}
@@ -0,0 +1,449 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Code coverage collection.
+//!
+//! The [`CoveragePass`] inserts a counter at the start of each function and of each block
+//! (`block`, `loop`, `if` and `else`) of a module. Each counter is a mutable global that the
+//! pass appends to the module, and that is incremented every time the corresponding location is
+//! reached.
+//!
+//! When a process whose module has been instrumented finishes, the values of these globals are
+//! reported to the [`CoverageCollector`] that created the pass. The [`System`](crate::System)
+//! does this automatically if the collector has been passed to
+//! [`SystemBuilder::with_coverage`](crate::SystemBuilder::with_coverage).
+//!
+//! Since the source code of programs isn't available, the produced reports refer to modules by
+//! their hash and to locations by the index of their counter. A function is considered executed
+//! if its first counter is non-zero.
+
+use super::{elements, InstrumentationPass, PassError};
+use crate::{module::ModuleHash, Pid, WasmValue};
+
+use alloc::{format, string::String, sync::Arc, vec, vec::Vec};
+use core::{convert::TryFrom, fmt::Write as _, mem};
+use fnv::FnvBuildHasher;
+use hashbrown::HashMap;
+use nohash_hasher::BuildNoHashHasher;
+use spinning_top::Spinlock;
+
+/// Collects the coverage information of the processes whose module has been instrumented with
+/// one of its [`CoveragePass`]es.
+///
+/// Cloning a [`CoverageCollector`] returns a handle to the same collector.
+#[derive(Clone, Default)]
+pub struct CoverageCollector {
+    inner: Arc<Spinlock<CollectorInner>>,
+}
+
+/// Pass that inserts coverage counters in modules. Created with [`CoverageCollector::pass`].
+pub struct CoveragePass {
+    collector: CoverageCollector,
+}
+
+#[derive(Default)]
+struct CollectorInner {
+    /// Modules that have been instrumented.
+    modules: HashMap<ModuleHash, ModuleCounters, FnvBuildHasher>,
+    /// Processes that are running an instrumented module.
+    running: HashMap<Pid, ModuleHash, BuildNoHashHasher<u64>>,
+    /// Counters of the processes that have finished.
+    finished: Vec<(Pid, ModuleHash, Vec<u64>)>,
+}
+
+/// Counters that the pass has inserted in a module.
+struct ModuleCounters {
+    /// Index of the global corresponding to the first counter.
+    first_global: usize,
+    /// Total number of counters.
+    num_counters: usize,
+    /// List of functions that have a body, in order.
+    functions: Vec<FunctionCounters>,
+}
+
+struct FunctionCounters {
+    /// Name of the function, as found in the names section or generated from its index.
+    name: String,
+    /// Index of the first counter of this function.
+    first_counter: usize,
+    /// Number of counters of this function.
+    num_counters: usize,
+}
+
+/// Coverage of one or more processes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CoverageReport {
+    /// Coverage of each instrumented module that has been executed.
+    pub modules: Vec<ModuleReport>,
+}
+
+/// Coverage of a single module.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModuleReport {
+    /// Hash of the module, before instrumentation.
+    pub hash: ModuleHash,
+    /// Functions of the module, in order.
+    pub functions: Vec<FunctionReport>,
+}
+
+/// Coverage of a single function.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FunctionReport {
+    /// Name of the function.
+    pub name: String,
+    /// Index of the first counter of this function within the module.
+    pub first_counter: usize,
+    /// Number of times each block of the function has been entered. The first element
+    /// corresponds to the function itself.
+    pub blocks: Vec<u64>,
+}
+
+impl CoverageCollector {
+    /// Builds a new empty collector.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Returns a pass that instruments modules and reports to this collector.
+    pub fn pass(&self) -> CoveragePass {
+        CoveragePass {
+            collector: self.clone(),
+        }
+    }
+
+    /// Notifies the collector that a process has been started with the given module.
+    ///
+    /// Has no effect if the module hasn't been instrumented by one of the passes of this
+    /// collector.
+    pub fn process_started(&self, pid: Pid, module_hash: &ModuleHash) {
+        let mut inner = self.inner.lock();
+        if inner.modules.contains_key(module_hash) {
+            inner.running.insert(pid, module_hash.clone());
+        }
+    }
+
+    /// Notifies the collector that a process has finished. `globals` must be the values of the
+    /// globals of the process when it finished.
+    ///
+    /// Has no effect if [`CoverageCollector::process_started`] hasn't been called for this
+    /// process.
+    pub fn process_finished(&self, pid: Pid, globals: &[WasmValue]) {
+        let mut inner = self.inner.lock();
+        let inner = &mut *inner;
+
+        let module_hash = match inner.running.remove(&pid) {
+            Some(h) => h,
+            None => return,
+        };
+
+        let module = &inner.modules[&module_hash];
+        let counters = (0..module.num_counters)
+            .map(|n| match globals.get(module.first_global + n) {
+                Some(WasmValue::I32(v)) => u64::from(*v as u32),
+                _ => 0,
+            })
+            .collect();
+
+        inner.finished.push((pid, module_hash, counters));
+    }
+
+    /// Returns the coverage of a single process that has finished.
+    ///
+    /// Returns `None` if the process isn't known or hasn't finished yet.
+    pub fn process_report(&self, pid: Pid) -> Option<CoverageReport> {
+        let inner = self.inner.lock();
+        let (_, hash, counters) = inner.finished.iter().find(|(p, _, _)| *p == pid)?;
+        Some(CoverageReport {
+            modules: vec![build_module_report(&inner.modules[hash], hash, counters)],
+        })
+    }
+
+    /// Returns the coverage of all the processes that have finished, summed per module.
+    pub fn report(&self) -> CoverageReport {
+        let inner = self.inner.lock();
+
+        let mut totals: Vec<(&ModuleHash, Vec<u64>)> = Vec::new();
+        for (_, hash, counters) in &inner.finished {
+            match totals.iter_mut().find(|(h, _)| *h == hash) {
+                Some((_, total)) => {
+                    for (t, c) in total.iter_mut().zip(counters) {
+                        *t = t.saturating_add(*c);
+                    }
+                }
+                None => totals.push((hash, counters.clone())),
+            }
+        }
+
+        CoverageReport {
+            modules: totals
+                .into_iter()
+                .map(|(hash, counters)| build_module_report(&inner.modules[hash], hash, &counters))
+                .collect(),
+        }
+    }
+}
+
+fn build_module_report(
+    module: &ModuleCounters,
+    hash: &ModuleHash,
+    counters: &[u64],
+) -> ModuleReport {
+    ModuleReport {
+        hash: hash.clone(),
+        functions: module
+            .functions
+            .iter()
+            .map(|f| FunctionReport {
+                name: f.name.clone(),
+                first_counter: f.first_counter,
+                blocks: counters[f.first_counter..f.first_counter + f.num_counters].to_vec(),
+            })
+            .collect(),
+    }
+}
+
+impl CoverageReport {
+    /// Formats the report in the format used by `lcov`'s tracefiles.
+    ///
+    /// Each module is reported as a source file named after the base58 encoding of its hash.
+    /// Lines correspond to counters, starting from line 1.
+    pub fn to_lcov(&self) -> String {
+        let mut out = String::new();
+
+        for module in &self.modules {
+            let _ = writeln!(
+                out,
+                "SF:{}",
+                bs58::encode(&<[u8; 32]>::from(module.hash.clone())).into_string()
+            );
+
+            for function in &module.functions {
+                let _ = writeln!(out, "FN:{},{}", function.first_counter + 1, function.name);
+            }
+            for function in &module.functions {
+                let _ = writeln!(out, "FNDA:{},{}", function.blocks[0], function.name);
+            }
+            let _ = writeln!(out, "FNF:{}", module.functions.len());
+            let _ = writeln!(
+                out,
+                "FNH:{}",
+                module.functions.iter().filter(|f| f.blocks[0] != 0).count()
+            );
+
+            let mut lines_found = 0;
+            let mut lines_hit = 0;
+            for function in &module.functions {
+                for (n, hits) in function.blocks.iter().enumerate() {
+                    let _ = writeln!(out, "DA:{},{}", function.first_counter + n + 1, hits);
+                    lines_found += 1;
+                    if *hits != 0 {
+                        lines_hit += 1;
+                    }
+                }
+            }
+            let _ = writeln!(out, "LF:{}", lines_found);
+            let _ = writeln!(out, "LH:{}", lines_hit);
+            out.push_str("end_of_record\n");
+        }
+
+        out
+    }
+}
+
+impl InstrumentationPass for CoveragePass {
+    fn name(&self) -> &str {
+        "coverage"
+    }
+
+    fn apply(
+        &self,
+        module_hash: &ModuleHash,
+        module: &mut elements::Module,
+    ) -> Result<(), PassError> {
+        // Failing to parse the names section isn't a problem, as names are only informative.
+        *module = match mem::take(module).parse_names() {
+            Ok(m) => m,
+            Err((_, m)) => m,
+        };
+
+        let num_imported_functions = module.import_count(elements::ImportCountType::Function);
+        let first_global = module.import_count(elements::ImportCountType::Global)
+            + module
+                .global_section()
+                .map(|s| s.entries().len())
+                .unwrap_or(0);
+
+        let function_names = module
+            .names_section()
+            .and_then(|n| n.functions())
+            .map(|f| f.names().clone());
+
+        let mut num_counters = 0;
+        let mut functions = Vec::new();
+
+        if let Some(code) = module.code_section_mut() {
+            for (n, body) in code.bodies_mut().iter_mut().enumerate() {
+                let function_index = num_imported_functions + n;
+                let first_counter = num_counters;
+
+                let original = mem::replace(body.code_mut().elements_mut(), Vec::new());
+                let mut instrumented = Vec::with_capacity(original.len() + 4);
+                push_increment(&mut instrumented, first_global + num_counters)?;
+                num_counters += 1;
+
+                for instruction in original {
+                    let opens_block = match instruction {
+                        elements::Instruction::Block(_)
+                        | elements::Instruction::Loop(_)
+                        | elements::Instruction::If(_)
+                        | elements::Instruction::Else => true,
+                        _ => false,
+                    };
+
+                    instrumented.push(instruction);
+                    if opens_block {
+                        push_increment(&mut instrumented, first_global + num_counters)?;
+                        num_counters += 1;
+                    }
+                }
+
+                *body.code_mut().elements_mut() = instrumented;
+
+                let name = u32::try_from(function_index)
+                    .ok()
+                    .and_then(|i| function_names.as_ref()?.get(i).cloned())
+                    .unwrap_or_else(|| format!("func{}", function_index));
+                functions.push(FunctionCounters {
+                    name,
+                    first_counter,
+                    num_counters: num_counters - first_counter,
+                });
+            }
+        }
+
+        let counters = (0..num_counters).map(|_| {
+            elements::GlobalEntry::new(
+                elements::GlobalType::new(elements::ValueType::I32, true),
+                elements::InitExpr::new(vec![
+                    elements::Instruction::I32Const(0),
+                    elements::Instruction::End,
+                ]),
+            )
+        });
+
+        match module.global_section_mut() {
+            Some(section) => section.entries_mut().extend(counters),
+            None => module
+                .insert_section(elements::Section::Global(
+                    elements::GlobalSection::with_entries(counters.collect()),
+                ))
+                .map_err(|err| PassError {
+                    reason: format!("{}", err),
+                })?,
+        }
+
+        self.collector.inner.lock().modules.insert(
+            module_hash.clone(),
+            ModuleCounters {
+                first_global,
+                num_counters,
+                functions,
+            },
+        );
+
+        Ok(())
+    }
+}
+
+/// Pushes to `out` the instructions that increment the global with the given index.
+fn push_increment(out: &mut Vec<elements::Instruction>, global: usize) -> Result<(), PassError> {
+    let global = u32::try_from(global).map_err(|_| PassError {
+        reason: "too many globals".into(),
+    })?;
+    out.push(elements::Instruction::GetGlobal(global));
+    out.push(elements::Instruction::I32Const(1));
+    out.push(elements::Instruction::I32Add);
+    out.push(elements::Instruction::SetGlobal(global));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CoverageCollector;
+    use crate::{
+        instrumentation::Instrumentation, module::Module, SystemBuilder, SystemRunOutcome,
+    };
+    use alloc::vec;
+
+    #[test]
+    fn counts_functions_and_blocks() {
+        let collector = CoverageCollector::new();
+        let module = Module::from_bytes_instrumented(
+            wat_to_bin!(
+                r#"
+            (module
+                (func $used (param i32) (result i32)
+                    get_local 0
+                    if (result i32)
+                        i32.const 1
+                    else
+                        i32.const 2
+                    end)
+                (func $unused)
+                (func $main (param $p0 i32) (param $p1 i32) (result i32)
+                    i32.const 0
+                    call $used
+                    drop
+                    i32.const 0
+                    call $used)
+                (export "main" (func $main)))
+            "#
+            ),
+            &Instrumentation::new().with_pass(collector.pass()),
+        )
+        .unwrap();
+
+        let system = SystemBuilder::new()
+            .with_coverage(&collector)
+            .build()
+            .unwrap();
+        let pid = system.execute(&module).unwrap();
+
+        match futures::executor::block_on(system.run()) {
+            SystemRunOutcome::ProgramFinished {
+                pid: finished,
+                outcome: Ok(()),
+                crash_report: None,
+                ..
+            } => assert_eq!(finished, pid),
+            _ => panic!(),
+        }
+
+        let report = collector.process_report(pid).unwrap();
+        assert_eq!(report, collector.report());
+        assert_eq!(report.modules.len(), 1);
+        let functions = &report.modules[0].functions;
+        assert_eq!(functions.len(), 3);
+        assert_eq!(functions[0].name, "used");
+        assert_eq!(functions[0].blocks, vec![2, 0, 2]);
+        assert_eq!(functions[1].blocks, vec![0]);
+        assert_eq!(functions[2].blocks, vec![1]);
+
+        let lcov = report.to_lcov();
+        assert!(lcov.contains("FNDA:2,used\n"));
+        assert!(lcov.contains("FNH:2\n"));
+        assert!(lcov.ends_with("end_of_record\n"));
+    }
+}
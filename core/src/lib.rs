@@ -59,6 +59,14 @@
 //! - `interface`. The interface named `interface` allows programs to register themselves as
 //! provider of an interface. If a program then emits a message targetting the interface, then
 //! the registered program will be in charge of treating the message.
+//! - `perf-self`. The interface named `perf-self` lets programs query counters about their own
+//! activity, such as the number of messages they have emitted.
+//! - `lifecycle`. The interface named `lifecycle` lets programs be notified when they are asked
+//! to stop, in order for them to finish their work before being aborted.
+//! - `crash`. The interface named `crash` lets programs report why they are about to crash. The
+//! report is then returned alongside with the end of the program.
+//! - `process-management`. The interface named `process-management` lets programs kill other
+//! programs, or wait for them to end and retrieve their exit status.
 //! - `threads`. The interface named `threads` provides a few utilities related to multithreading
 //! (TODO: this isn't really done yet)
 //!
@@ -119,8 +127,9 @@ extern crate alloc;
 
 pub use self::module::Module;
 pub use self::system::{System, SystemBuilder, SystemRunOutcome};
+pub use self::trap::{BacktraceFrame, TrapError, TrapKind};
 pub use redshirt_syscalls::{
-    Decode, Encode, EncodedMessage, InterfaceHash, MessageId, Pid, ThreadId,
+    Decode, Encode, EncodedMessage, Handle, InterfaceHash, MessageId, Pid, ThreadId,
 };
 pub use wasm_value::{ValueType, WasmValue};
 
@@ -149,11 +158,15 @@ macro_rules! from_wat {
 }
 
 mod id_pool;
+mod trap;
 mod wasm_value;
 
 pub mod extrinsics;
+pub mod handles;
+pub mod instrumentation;
 pub mod module;
 pub mod native;
 pub mod scheduler;
+pub mod schema;
 pub mod signature;
 pub mod system;
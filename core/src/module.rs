@@ -13,7 +13,47 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use core::fmt;
+use crate::instrumentation::{InstrumentError, Instrumentation};
+use crate::signature::Signature;
+use crate::InterfaceHash;
+
+use alloc::{
+    string::{String, ToString as _},
+    sync::Arc,
+    vec::Vec,
+};
+use core::{convert::TryFrom as _, fmt};
+use ed25519_dalek::Verifier as _;
+use fnv::FnvBuildHasher;
+use hashbrown::HashMap;
+use parity_wasm::elements;
+
+pub use self::abi::{Abi, AbiReport, ImportKind, ModuleImport, UnresolvedImport, UnresolvedReason};
+pub use self::streaming::StreamingLoader;
+
+mod abi;
+pub(crate) mod debug;
+pub(crate) mod fuel;
+pub(crate) mod stack;
+mod streaming;
+pub(crate) mod trace;
+
+/// Name under which the start function of a module, if any, is exported. See
+/// [`export_start`].
+pub(crate) const START_EXPORT: &str = "redshirt-start";
+
+/// Name of the custom section in which a module declares the interfaces it emits messages on.
+///
+/// The content of this section is the concatenation of the 32-bytes hashes of these interfaces.
+/// See [`Module::required_interfaces`].
+pub const INTERFACES_SECTION: &str = "redshirt-interfaces";
+
+/// Name of the custom section containing the signature of a module.
+///
+/// The content of this section is a 32-bytes ed25519 public key followed with a 64-bytes
+/// signature, by this key, of all the bytes of the module that precede the section. In other
+/// words, a module is signed by appending this section at its end. See [`Module::signer`].
+pub const SIGNATURE_SECTION: &str = "redshirt-signature";
 
 /// Represents a successfully-parsed binary.
 ///
@@ -22,15 +62,110 @@ use core::fmt;
 pub struct Module {
     inner: wasmi::Module,
     hash: ModuleHash,
+    /// Imports of the module, in the order in which they are declared.
+    imports: Vec<ModuleImport>,
+    /// Sizes of the module, checked against the limits when it is instantiated.
+    stats: ModuleStats,
+    /// Index of the global containing the remaining fuel. See the [`fuel`] module.
+    fuel_global: Option<u32>,
+    /// Index of the global containing the remaining stack depth. See the [`stack`] module.
+    stack_global: Option<u32>,
+    /// Index of the global containing the index of the function being executed. See the
+    /// [`trace`] module.
+    trace_global: Option<u32>,
+    /// Index of the global containing the debugging mode, if the module has been loaded with
+    /// [`Module::from_bytes_debuggable`]. See the [`debug`] module.
+    debug_global: Option<u32>,
+    /// Names of the functions found in the name section, indexed by function index. Shared with
+    /// the virtual machines instantiated from this module.
+    function_names: Arc<HashMap<u32, String, FnvBuildHasher>>,
+    /// Name of the module found in its name section, if any.
+    name: Option<String>,
+    /// Interfaces found in the [`INTERFACES_SECTION`], or `None` if the module doesn't have
+    /// such a section.
+    required_interfaces: Option<Vec<InterfaceHash>>,
+    /// Public key that has signed the module, if it has a [`SIGNATURE_SECTION`].
+    signer: Option<[u8; 32]>,
+}
+
+/// Sizes of a [`Module`].
+#[derive(Debug, Clone)]
+pub(crate) struct ModuleStats {
+    /// Size, in bytes, of the encoded module, before instrumentation.
+    pub(crate) encoded_size: usize,
+    /// Total size, in bytes, of the data segments.
+    pub(crate) data_size: usize,
+    /// Number of functions defined in the module, not including imports.
+    pub(crate) num_functions: usize,
+    /// Number of instructions of the largest function.
+    pub(crate) max_function_instructions: usize,
+}
+
+impl ModuleStats {
+    /// Measures the given module. `encoded_size` is the size of the module before it has been
+    /// parsed.
+    pub(crate) fn measure(parsed: &elements::Module, encoded_size: usize) -> Self {
+        let bodies = parsed.code_section().map_or(&[][..], |c| c.bodies());
+        ModuleStats {
+            encoded_size,
+            data_size: parsed
+                .data_section()
+                .map_or(0, |d| d.entries().iter().map(|e| e.value().len()).sum()),
+            num_functions: bodies.len(),
+            max_function_instructions: bodies
+                .iter()
+                .map(|b| b.code().elements().len())
+                .max()
+                .unwrap_or(0),
+        }
+    }
 }
 
 /// Hash of a module.
 #[derive(Clone, PartialEq, Eq, Hash)]
 pub struct ModuleHash([u8; 32]);
 
-/// Error that can happen when calling [`ModuleHash::from_bytes`].
-#[derive(Debug)]
-pub struct FromBytesError {}
+/// Error that can happen when calling [`Module::from_bytes`].
+///
+/// > **Note**: Imports that can't be resolved, such as imported tables that aren't provided by a
+/// >           library, aren't reported here but in the [`AbiReport`] of the module.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ModuleErr {
+    /// The bytes aren't a valid WASM module.
+    Malformed,
+    /// The module uses the exception-handling proposal, which isn't supported.
+    ///
+    /// Such modules are typically produced by compiling with `panic = "unwind"`. Compiling with
+    /// `panic = "abort"` instead produces a module that can be loaded.
+    ExceptionHandling,
+    /// The module contains functions that return more than one value, as allowed by the
+    /// multi-value proposal, which isn't supported.
+    ///
+    /// Such modules are typically produced by compiling with the `multivalue` target feature
+    /// enabled.
+    MultiValue,
+    /// The module defines or imports more than one memory.
+    MultipleMemories {
+        /// Number of memories of the module.
+        count: usize,
+    },
+    /// The module defines or imports more than one table.
+    MultipleTables {
+        /// Number of tables of the module.
+        count: usize,
+    },
+    /// The [`INTERFACES_SECTION`] of the module is present more than once, or its length isn't
+    /// a multiple of 32 bytes.
+    InvalidInterfacesSection,
+    /// The module has a [`SIGNATURE_SECTION`], but this section isn't the last one, is
+    /// malformed, or contains a signature that doesn't match the module.
+    BadSignature,
+    /// The module is well-formed but has been rejected by the interpreter.
+    Invalid {
+        /// Explanation provided by the interpreter.
+        reason: String,
+    },
+}
 
 /// Error that can happen when calling [`ModuleHash::from_base58`].
 #[derive(Debug)]
@@ -38,11 +173,125 @@ pub struct FromBase58Error {}
 
 impl Module {
     /// Parses a module from WASM bytes.
-    pub fn from_bytes(buffer: impl AsRef<[u8]>) -> Result<Self, FromBytesError> {
-        let inner = wasmi::Module::from_buffer(buffer.as_ref()).map_err(|_| FromBytesError {})?;
-        let hash = ModuleHash::from_bytes(buffer);
+    ///
+    /// Returns an error explaining why the module is rejected if it is malformed or relies on
+    /// features that aren't supported.
+    ///
+    /// If the module has a [`SIGNATURE_SECTION`], its signature is verified.
+    ///
+    /// See also [`StreamingLoader`] in order to start validating a module before all its bytes
+    /// are available.
+    pub fn from_bytes(buffer: impl AsRef<[u8]>) -> Result<Self, ModuleErr> {
+        let hash = ModuleHash::from_bytes(buffer.as_ref());
+        Module::from_bytes_with_hash(buffer.as_ref(), hash, false)
+    }
+
+    /// Same as [`Module::from_bytes`], but the processes running the module can additionally be
+    /// paused at the start of its functions or before each of its instructions, for debugging
+    /// purposes.
+    ///
+    /// > **Note**: The code of the module is considerably larger, and running it slower, than if
+    /// >           it had been loaded with [`Module::from_bytes`].
+    pub fn from_bytes_debuggable(buffer: impl AsRef<[u8]>) -> Result<Self, ModuleErr> {
+        let hash = ModuleHash::from_bytes(buffer.as_ref());
+        Module::from_bytes_with_hash(buffer.as_ref(), hash, true)
+    }
+
+    /// Parses a module from WASM bytes, then applies the given instrumentation passes on it.
+    ///
+    /// The [`hash`](Module::hash) and the [`signer`](Module::signer) of the returned module are
+    /// the same as if no instrumentation had been applied.
+    pub fn from_bytes_instrumented(
+        buffer: impl AsRef<[u8]>,
+        instrumentation: &Instrumentation,
+    ) -> Result<Self, InstrumentError> {
+        let hash = ModuleHash::from_bytes(buffer.as_ref());
+        let mut parsed: elements::Module =
+            parity_wasm::deserialize_buffer(buffer.as_ref()).map_err(|_| InstrumentError::Parse)?;
+        let signer = signer(buffer.as_ref()).map_err(|_| InstrumentError::BadSignature)?;
+        instrumentation.instrument(&hash, &mut parsed)?;
 
-        Ok(Module { inner, hash })
+        Module::from_parsed(parsed, hash, buffer.as_ref().len(), signer, false).map_err(|_| {
+            if instrumentation.is_empty() {
+                InstrumentError::Parse
+            } else {
+                InstrumentError::InvalidOutput
+            }
+        })
+    }
+
+    /// Implementation of [`Module::from_bytes`] and [`Module::from_bytes_debuggable`]. `hash`
+    /// must be the hash of `bytes`.
+    fn from_bytes_with_hash(
+        bytes: &[u8],
+        hash: ModuleHash,
+        debuggable: bool,
+    ) -> Result<Self, ModuleErr> {
+        let parsed: elements::Module =
+            parity_wasm::deserialize_buffer(bytes).map_err(|_| ModuleErr::unparsable(bytes))?;
+        let signer = signer(bytes)?;
+        Module::from_parsed(parsed, hash, bytes.len(), signer, debuggable)
+    }
+
+    fn from_parsed(
+        parsed: elements::Module,
+        hash: ModuleHash,
+        encoded_size: usize,
+        signer: Option<[u8; 32]>,
+        debuggable: bool,
+    ) -> Result<Self, ModuleErr> {
+        check_supported(&parsed)?;
+        let required_interfaces = required_interfaces(&parsed)?;
+
+        // The name section is only informative, and a malformed one is simply ignored.
+        let mut parsed = parsed.parse_names().unwrap_or_else(|(_, parsed)| parsed);
+        let name = parsed
+            .names_section()
+            .and_then(|names| names.module())
+            .map(|module| String::from(module.name()));
+        let function_names = parsed
+            .names_section()
+            .and_then(|names| names.functions())
+            .map(|functions| {
+                functions
+                    .names()
+                    .iter()
+                    .map(|(index, name)| (index, name.clone()))
+                    .collect()
+            })
+            .unwrap_or_default();
+        let imports = abi::imports(&parsed);
+        let stats = ModuleStats::measure(&parsed, encoded_size);
+        let first_function =
+            u32::try_from(parsed.import_count(elements::ImportCountType::Function))
+                .unwrap_or(u32::max_value());
+        let debug_global = if debuggable {
+            debug::inject(&mut parsed, first_function)
+        } else {
+            None
+        };
+        let trace_global = trace::inject(&mut parsed, first_function);
+        let stack_global = stack::inject(&mut parsed);
+        let fuel_global = fuel::inject(&mut parsed);
+        export_start(&mut parsed);
+        let inner =
+            wasmi::Module::from_parity_wasm_module(parsed).map_err(|err| ModuleErr::Invalid {
+                reason: err.to_string(),
+            })?;
+        Ok(Module {
+            inner,
+            hash,
+            imports,
+            stats,
+            fuel_global,
+            stack_global,
+            trace_global,
+            debug_global,
+            function_names: Arc::new(function_names),
+            name,
+            required_interfaces,
+            signer,
+        })
     }
 
     /// Returns a reference to the internal module.
@@ -56,6 +305,111 @@ impl Module {
     pub fn hash(&self) -> &ModuleHash {
         &self.hash
     }
+
+    /// Returns the name of the module, as found in its name section.
+    ///
+    /// Returns `None` if the module doesn't have a name section, or if this section doesn't
+    /// contain the name of the module.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_ref().map(|n| &n[..])
+    }
+
+    /// Returns the interfaces that the module has declared in its [`INTERFACES_SECTION`].
+    ///
+    /// Returns `None` if the module doesn't have such a section, in which case it is allowed to
+    /// emit messages on any interface. Otherwise, the scheduler refuses the messages that the
+    /// module emits on interfaces that aren't in the list.
+    pub fn required_interfaces(&self) -> Option<&[InterfaceHash]> {
+        self.required_interfaces.as_ref().map(|i| &i[..])
+    }
+
+    /// Returns the public key that has signed the module, as found in its
+    /// [`SIGNATURE_SECTION`].
+    ///
+    /// Returns `None` if the module isn't signed. Modules whose signature is invalid are
+    /// refused when they are parsed, and the returned key has therefore always been verified.
+    pub fn signer(&self) -> Option<&[u8; 32]> {
+        self.signer.as_ref()
+    }
+
+    /// Returns the sizes of the module.
+    pub(crate) fn stats(&self) -> &ModuleStats {
+        &self.stats
+    }
+
+    /// Returns the index of the global containing the remaining fuel, or `None` if the module
+    /// doesn't contain any code.
+    pub(crate) fn fuel_global(&self) -> Option<u32> {
+        self.fuel_global
+    }
+
+    /// Returns the index of the global containing the remaining stack depth, or `None` if the
+    /// module doesn't contain any code.
+    pub(crate) fn stack_global(&self) -> Option<u32> {
+        self.stack_global
+    }
+
+    /// Returns the index of the global containing the index of the function being executed, or
+    /// `None` if the module doesn't contain any code.
+    pub(crate) fn trace_global(&self) -> Option<u32> {
+        self.trace_global
+    }
+
+    /// Returns the index of the global containing the debugging mode, or `None` if the module
+    /// hasn't been loaded with [`Module::from_bytes_debuggable`] or doesn't contain any code.
+    pub(crate) fn debug_global(&self) -> Option<u32> {
+        self.debug_global
+    }
+
+    /// Returns the names of the functions of the module, found in its name section.
+    pub(crate) fn function_names(&self) -> &Arc<HashMap<u32, String, FnvBuildHasher>> {
+        &self.function_names
+    }
+
+    /// Returns the name of the function with the given index, as found in the name section of
+    /// the module.
+    pub fn function_name(&self, index: u32) -> Option<&str> {
+        self.function_names.get(&index).map(|n| &n[..])
+    }
+
+    /// Returns the list of imports of the module, after instrumentation.
+    pub fn imports(&self) -> &[ModuleImport] {
+        &self.imports
+    }
+
+    /// Builds a report about the compatibility of this module with a set of functions.
+    ///
+    /// The closure is called with a namespace and a function name, and must return the signature
+    /// of the corresponding function, if it exists. See also
+    /// [`System::abi_report`](crate::System::abi_report) in order to check a module against the
+    /// functions provided by a [`System`](crate::System).
+    pub fn abi_report(&self, provided: impl FnMut(&str, &str) -> Option<Signature>) -> AbiReport {
+        AbiReport::new(&self.imports, provided)
+    }
+}
+
+/// Removes the start section of the module, if any, and exports the start function under the
+/// name [`START_EXPORT`] instead.
+///
+/// The interpreter runs the start function to completion while instantiating the module, which
+/// would make it impossible to pause it. Exporting it makes it possible to execute it like any
+/// other function.
+fn export_start(module: &mut elements::Module) {
+    let start = match module.start_section() {
+        Some(s) => s,
+        None => return,
+    };
+
+    module.clear_start_section();
+    if module.export_section().is_none() {
+        let _ = module.insert_section(elements::Section::Export(Default::default()));
+    }
+    if let Some(exports) = module.export_section_mut() {
+        exports.entries_mut().push(elements::ExportEntry::new(
+            START_EXPORT.into(),
+            elements::Internal::Function(start),
+        ));
+    }
 }
 
 impl From<[u8; 32]> for ModuleHash {
@@ -95,6 +449,11 @@ impl ModuleHash {
         out2[32 - written..].copy_from_slice(&out[..written]);
         Ok(ModuleHash(out2))
     }
+
+    /// Returns the base58 encoding of the hash. Opposite of [`ModuleHash::from_base58`].
+    pub fn to_base58(&self) -> String {
+        bs58::encode(&self.0).into_string()
+    }
 }
 
 impl fmt::Debug for ModuleHash {
@@ -109,15 +468,341 @@ impl fmt::Display for FromBase58Error {
     }
 }
 
-impl fmt::Display for FromBytesError {
+impl ModuleErr {
+    /// Builds the error to report when the given bytes have failed to parse.
+    fn unparsable(bytes: &[u8]) -> Self {
+        if find_section(bytes, TAG_SECTION_ID).is_some() {
+            ModuleErr::ExceptionHandling
+        } else if find_section(bytes, TYPE_SECTION_ID).map_or(false, has_multi_value_type) {
+            ModuleErr::MultiValue
+        } else {
+            ModuleErr::Malformed
+        }
+    }
+}
+
+impl fmt::Display for ModuleErr {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "FromBytesError")
+        match self {
+            ModuleErr::Malformed => write!(f, "Malformed WASM module"),
+            ModuleErr::ExceptionHandling => write!(f, "Exception handling isn't supported"),
+            ModuleErr::MultiValue => {
+                write!(f, "Functions returning multiple values aren't supported")
+            }
+            ModuleErr::MultipleMemories { count } => {
+                write!(
+                    f,
+                    "Module has {} memories, but at most one is supported",
+                    count
+                )
+            }
+            ModuleErr::MultipleTables { count } => {
+                write!(
+                    f,
+                    "Module has {} tables, but at most one is supported",
+                    count
+                )
+            }
+            ModuleErr::InvalidInterfacesSection => {
+                write!(f, "Malformed {} custom section", INTERFACES_SECTION)
+            }
+            ModuleErr::BadSignature => write!(f, "Invalid module signature"),
+            ModuleErr::Invalid { reason } => write!(f, "Invalid module: {}", reason),
+        }
     }
 }
 
+/// Checks that the given module only uses features that are supported, before it is passed to
+/// the interpreter, which would reject it with a less precise error.
+fn check_supported(module: &elements::Module) -> Result<(), ModuleErr> {
+    let memories = module.import_count(elements::ImportCountType::Memory)
+        + module.memory_section().map_or(0, |s| s.entries().len());
+    if memories > 1 {
+        return Err(ModuleErr::MultipleMemories { count: memories });
+    }
+
+    let tables = module.import_count(elements::ImportCountType::Table)
+        + module.table_section().map_or(0, |s| s.entries().len());
+    if tables > 1 {
+        return Err(ModuleErr::MultipleTables { count: tables });
+    }
+
+    Ok(())
+}
+
+/// Decodes the [`INTERFACES_SECTION`] of the given module, if any.
+fn required_interfaces(module: &elements::Module) -> Result<Option<Vec<InterfaceHash>>, ModuleErr> {
+    let mut sections = module
+        .custom_sections()
+        .filter(|s| s.name() == INTERFACES_SECTION);
+    let section = match sections.next() {
+        Some(s) => s,
+        None => return Ok(None),
+    };
+    if sections.next().is_some() || section.payload().len() % 32 != 0 {
+        return Err(ModuleErr::InvalidInterfacesSection);
+    }
+
+    let interfaces = section
+        .payload()
+        .chunks(32)
+        .map(|chunk| {
+            let mut hash = [0; 32];
+            hash.copy_from_slice(chunk);
+            InterfaceHash::from_raw_hash(hash)
+        })
+        .collect();
+    Ok(Some(interfaces))
+}
+
+/// Verifies the [`SIGNATURE_SECTION`] of the given WASM binary, if any, and returns the public
+/// key that has signed it.
+///
+/// Must only be called with a binary that has been successfully parsed.
+fn signer(bytes: &[u8]) -> Result<Option<[u8; 32]>, ModuleErr> {
+    let mut remaining = bytes.get(8..).unwrap_or(&[]);
+
+    while let Some((&id, rest)) = remaining.split_first() {
+        let section_start = bytes.len() - remaining.len();
+        remaining = rest;
+        let size = match read_leb128(&mut remaining).and_then(|s| usize::try_from(s).ok()) {
+            Some(s) if s <= remaining.len() => s,
+            _ => return Ok(None),
+        };
+        let (mut content, rest) = remaining.split_at(size);
+        remaining = rest;
+
+        if id != CUSTOM_SECTION_ID {
+            continue;
+        }
+        let name_len = match read_leb128(&mut content).and_then(|l| usize::try_from(l).ok()) {
+            Some(l) if l <= content.len() => l,
+            _ => return Ok(None),
+        };
+        if &content[..name_len] != SIGNATURE_SECTION.as_bytes() {
+            continue;
+        }
+
+        let payload = &content[name_len..];
+        if !remaining.is_empty() || payload.len() != 96 {
+            return Err(ModuleErr::BadSignature);
+        }
+        let public_key = ed25519_dalek::PublicKey::from_bytes(&payload[..32])
+            .map_err(|_| ModuleErr::BadSignature)?;
+        let signature = ed25519_dalek::Signature::try_from(&payload[32..])
+            .map_err(|_| ModuleErr::BadSignature)?;
+        public_key
+            .verify(&bytes[..section_start], &signature)
+            .map_err(|_| ModuleErr::BadSignature)?;
+        return Ok(Some(public_key.to_bytes()));
+    }
+
+    Ok(None)
+}
+
+/// Identifier of the custom sections of a WASM binary.
+const CUSTOM_SECTION_ID: u8 = 0;
+
+/// Identifier of the type section of a WASM binary.
+const TYPE_SECTION_ID: u8 = 1;
+
+/// Identifier of the tag section of a WASM binary, which only exists in modules that use the
+/// exception-handling proposal.
+const TAG_SECTION_ID: u8 = 13;
+
+/// Returns the content of the first section of the given WASM binary with the given identifier.
+///
+/// Returns `None` if the binary is malformed before any such section is found.
+// TODO: the VM doesn't support exception handling and multiple return values, and this only
+// allows reporting a better error
+fn find_section(mut bytes: &[u8], section_id: u8) -> Option<&[u8]> {
+    if bytes.len() < 8 || &bytes[..4] != b"\0asm" {
+        return None;
+    }
+    bytes = &bytes[8..];
+
+    while let Some((&id, rest)) = bytes.split_first() {
+        bytes = rest;
+        let size = usize::try_from(read_leb128(&mut bytes)?).ok()?;
+        if bytes.len() < size {
+            return None;
+        }
+        if id == section_id {
+            return Some(&bytes[..size]);
+        }
+        bytes = &bytes[size..];
+    }
+
+    None
+}
+
+/// Returns true if the given content of a type section contains a function type with more than
+/// one result.
+///
+/// Returns false if the section is malformed before any such type is found.
+fn has_multi_value_type(mut section: &[u8]) -> bool {
+    const FUNCTION_FORM: u8 = 0x60;
+
+    let num_types = match read_leb128(&mut section) {
+        Some(n) => n,
+        None => return false,
+    };
+
+    for _ in 0..num_types {
+        match section.split_first() {
+            Some((&FUNCTION_FORM, rest)) => section = rest,
+            _ => return false,
+        }
+
+        // Value types are encoded as a single byte.
+        let num_params = match read_leb128(&mut section) {
+            Some(n) => n as usize,
+            None => return false,
+        };
+        if section.len() < num_params {
+            return false;
+        }
+        section = &section[num_params..];
+
+        let num_results = match read_leb128(&mut section) {
+            Some(n) => n as usize,
+            None => return false,
+        };
+        if num_results > 1 {
+            return true;
+        }
+        if section.len() < num_results {
+            return false;
+        }
+        section = &section[num_results..];
+    }
+
+    false
+}
+
+/// Reads an unsigned LEB128-encoded 32-bits integer at the start of `bytes`, and advances `bytes`
+/// past it.
+fn read_leb128(bytes: &mut &[u8]) -> Option<u32> {
+    let mut value: u64 = 0;
+    let mut consumed = 0;
+    loop {
+        let byte = *bytes.get(consumed)?;
+        value |= u64::from(byte & 0x7f) << (7 * consumed);
+        consumed += 1;
+        if byte & 0x80 == 0 {
+            break;
+        }
+        if consumed >= 5 {
+            return None;
+        }
+    }
+
+    *bytes = &bytes[consumed..];
+    u32::try_from(value).ok()
+}
+
 #[cfg(test)]
 mod tests {
-    use super::Module;
+    use super::{Module, ModuleErr};
+    use crate::InterfaceHash;
+
+    #[test]
+    fn exception_handling_reported() {
+        // Empty type section, followed with a tag section containing no tag.
+        let bytes = b"\0asm\x01\0\0\0\x01\x01\0\x0d\x01\0";
+        let err = Module::from_bytes(&bytes[..]).err().unwrap();
+        assert_eq!(err, ModuleErr::ExceptionHandling);
+
+        let err = Module::from_bytes(&b"\0asm\x01\0\0\0\x01\x05\0"[..])
+            .err()
+            .unwrap();
+        assert_eq!(err, ModuleErr::Malformed);
+    }
+
+    #[test]
+    fn multi_value_reported() {
+        // Type section containing a function type with two `i32` results.
+        let bytes = b"\0asm\x01\0\0\0\x01\x06\x01\x60\x00\x02\x7f\x7f";
+        let err = Module::from_bytes(&bytes[..]).err().unwrap();
+        assert_eq!(err, ModuleErr::MultiValue);
+    }
+
+    #[test]
+    fn multiple_memories_reported() {
+        let bytes = wat_to_bin!(
+            r#"(module
+            (import "foo" "memory" (memory 1))
+            (memory 1))
+        "#
+        );
+        let err = Module::from_bytes(bytes).err().unwrap();
+        assert_eq!(err, ModuleErr::MultipleMemories { count: 2 });
+    }
+
+    #[test]
+    fn invalid_module_reported() {
+        let bytes = wat_to_bin!(
+            r#"(module
+            (func (result i32)
+                i64.const 0))
+        "#
+        );
+        match Module::from_bytes(bytes) {
+            Err(ModuleErr::Invalid { .. }) => {}
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn required_interfaces_parsed() {
+        let module = Module::from_bytes(&b"\0asm\x01\0\0\0"[..]).unwrap();
+        assert!(module.required_interfaces().is_none());
+
+        let mut bytes = b"\0asm\x01\0\0\0\0\x34\x13redshirt-interfaces".to_vec();
+        bytes.extend_from_slice(&[0x5; 32]);
+        let module = Module::from_bytes(&bytes).unwrap();
+        assert_eq!(
+            module.required_interfaces(),
+            Some(&[InterfaceHash::from_raw_hash([0x5; 32])][..])
+        );
+    }
+
+    #[test]
+    fn invalid_interfaces_section_reported() {
+        let bytes = b"\0asm\x01\0\0\0\0\x17\x13redshirt-interfaces\x01\x02\x03";
+        let err = Module::from_bytes(&bytes[..]).err().unwrap();
+        assert_eq!(err, ModuleErr::InvalidInterfacesSection);
+    }
+
+    #[test]
+    fn signature_verified() {
+        use ed25519_dalek::Signer as _;
+
+        let secret = ed25519_dalek::SecretKey::from_bytes(&[7; 32]).unwrap();
+        let public = ed25519_dalek::PublicKey::from(&secret);
+        let keypair = ed25519_dalek::Keypair { secret, public };
+
+        let mut bytes = b"\0asm\x01\0\0\0".to_vec();
+        let signature = keypair.sign(&bytes);
+        bytes.extend_from_slice(b"\0\x73\x12redshirt-signature");
+        bytes.extend_from_slice(&public.to_bytes());
+        bytes.extend_from_slice(&signature.to_bytes());
+
+        let module = Module::from_bytes(&bytes).unwrap();
+        assert_eq!(module.signer(), Some(&public.to_bytes()));
+
+        // Signature not matching the content.
+        let mut tampered = bytes.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        let err = Module::from_bytes(&tampered).err().unwrap();
+        assert_eq!(err, ModuleErr::BadSignature);
+
+        // Signature section followed with another section.
+        let mut not_last = bytes;
+        not_last.extend_from_slice(b"\0\x02\x01a");
+        let err = Module::from_bytes(&not_last).err().unwrap();
+        assert_eq!(err, ModuleErr::BadSignature);
+    }
 
     #[test]
     fn empty_wat_works() {
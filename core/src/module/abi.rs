@@ -0,0 +1,316 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Analysis of the imports of a module.
+//!
+//! A WASM module communicates with the kernel exclusively through the functions it imports.
+//! These imports are grouped by namespace, and each namespace corresponds to a specific version
+//! of an ABI. For example, the `redshirt` namespace contains the syscalls of redshirt, and the
+//! `wasi_snapshot_preview1` namespace the functions of the first preview of WASI.
+//!
+//! The [`AbiReport`] of a module indicates which ABIs the module relies on, and whether all of
+//! its imports can be resolved by a given kernel. Checking the report before instantiating a
+//! module makes it possible to reject incompatible binaries with an explanation, rather than
+//! with an instantiation failure.
+//!
+//! > **Note**: The interfaces a program communicates with are determined at runtime by the
+//! >           messages that it emits, and are therefore not part of this report.
+
+use crate::{signature::Signature, ValueType};
+
+use alloc::{string::String, vec::Vec};
+use core::fmt;
+use parity_wasm::elements;
+
+/// Import of a module.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModuleImport {
+    /// Namespace of the import. Also called "module name" in the WASM specifications.
+    pub namespace: String,
+    /// Name of the import within its namespace.
+    pub name: String,
+    /// What is being imported.
+    pub kind: ImportKind,
+}
+
+/// Kind of an import.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImportKind {
+    /// Function with the given signature.
+    Function(Signature),
+    /// Global variable.
+    Global,
+    /// Linear memory.
+    Memory,
+    /// Table of function references.
+    Table,
+}
+
+/// ABI that a module relies on, deduced from the namespace of its imports.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Abi {
+    /// Syscalls of redshirt, in the `redshirt` namespace.
+    Redshirt,
+    /// First preview of WASI, in the `wasi_snapshot_preview1` namespace.
+    WasiSnapshotPreview1,
+    /// Namespace that doesn't correspond to any known ABI.
+    Unknown(String),
+}
+
+/// Report about the compatibility of a module with the functions provided by a kernel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AbiReport {
+    /// List of ABIs used by the module, without duplicates.
+    abis: Vec<Abi>,
+    /// Imports that the kernel can't provide.
+    unresolved: Vec<UnresolvedImport>,
+}
+
+/// Import that a kernel can't provide.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnresolvedImport {
+    /// Namespace of the import.
+    pub namespace: String,
+    /// Name of the import within its namespace.
+    pub name: String,
+    /// Why the import can't be resolved.
+    pub reason: UnresolvedReason,
+}
+
+/// Reason why an import can't be resolved.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UnresolvedReason {
+    /// No function with this name exists.
+    UnknownFunction,
+    /// A function with this name exists, but with a different signature.
+    SignatureMismatch {
+        /// Signature of the function provided by the kernel.
+        expected: Signature,
+        /// Signature that the module expects.
+        obtained: Signature,
+    },
+    /// Importing something other than a function or a memory isn't supported.
+    UnsupportedKind,
+}
+
+impl Abi {
+    /// Returns the ABI corresponding to the given namespace.
+    pub fn from_namespace(namespace: &str) -> Self {
+        match namespace {
+            "redshirt" => Abi::Redshirt,
+            "wasi_snapshot_preview1" => Abi::WasiSnapshotPreview1,
+            other => Abi::Unknown(other.into()),
+        }
+    }
+}
+
+impl AbiReport {
+    /// Builds the report for the given imports.
+    ///
+    /// The closure is called with a namespace and a function name, and must return the signature
+    /// of the corresponding function provided by the kernel, if any.
+    pub(crate) fn new(
+        imports: &[ModuleImport],
+        mut provided: impl FnMut(&str, &str) -> Option<Signature>,
+    ) -> Self {
+        let mut abis = Vec::new();
+        let mut unresolved = Vec::new();
+
+        for import in imports {
+            // Imported memories are allocated by the virtual machine rather than provided by an
+            // ABI.
+            if let ImportKind::Memory = import.kind {
+                continue;
+            }
+
+            let abi = Abi::from_namespace(&import.namespace);
+            if !abis.contains(&abi) {
+                abis.push(abi);
+            }
+
+            let reason = match (&import.kind, provided(&import.namespace, &import.name)) {
+                (ImportKind::Function(obtained), Some(expected)) if *obtained == expected => {
+                    continue
+                }
+                (ImportKind::Function(obtained), Some(expected)) => {
+                    UnresolvedReason::SignatureMismatch {
+                        expected,
+                        obtained: obtained.clone(),
+                    }
+                }
+                (ImportKind::Function(_), None) => UnresolvedReason::UnknownFunction,
+                _ => UnresolvedReason::UnsupportedKind,
+            };
+
+            unresolved.push(UnresolvedImport {
+                namespace: import.namespace.clone(),
+                name: import.name.clone(),
+                reason,
+            });
+        }
+
+        AbiReport { abis, unresolved }
+    }
+
+    /// Returns the list of ABIs that the module relies on.
+    pub fn abis(&self) -> &[Abi] {
+        &self.abis
+    }
+
+    /// Returns the list of imports that can't be resolved.
+    pub fn unresolved(&self) -> &[UnresolvedImport] {
+        &self.unresolved
+    }
+
+    /// Returns true if all the imports of the module can be resolved.
+    pub fn is_compatible(&self) -> bool {
+        self.unresolved.is_empty()
+    }
+}
+
+/// Returns the list of imports of the given module.
+pub(super) fn imports(module: &elements::Module) -> Vec<ModuleImport> {
+    let types = module.type_section().map(|s| s.types()).unwrap_or(&[]);
+    let entries = module.import_section().map(|s| s.entries()).unwrap_or(&[]);
+
+    entries
+        .iter()
+        .filter_map(|entry| {
+            let kind = match entry.external() {
+                elements::External::Function(type_index) => match types.get(*type_index as usize) {
+                    Some(elements::Type::Function(ty)) => ImportKind::Function(Signature::new(
+                        ty.params().iter().cloned().map(value_type),
+                        ty.return_type().map(value_type),
+                    )),
+                    // The module is invalid and will be rejected by the interpreter.
+                    None => return None,
+                },
+                elements::External::Global(_) => ImportKind::Global,
+                elements::External::Memory(_) => ImportKind::Memory,
+                elements::External::Table(_) => ImportKind::Table,
+            };
+
+            Some(ModuleImport {
+                namespace: entry.module().into(),
+                name: entry.field().into(),
+                kind,
+            })
+        })
+        .collect()
+}
+
+fn value_type(ty: elements::ValueType) -> ValueType {
+    match ty {
+        elements::ValueType::I32 => ValueType::I32,
+        elements::ValueType::I64 => ValueType::I64,
+        elements::ValueType::F32 => ValueType::F32,
+        elements::ValueType::F64 => ValueType::F64,
+    }
+}
+
+impl fmt::Display for Abi {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Abi::Redshirt => write!(f, "redshirt"),
+            Abi::WasiSnapshotPreview1 => write!(f, "wasi_snapshot_preview1"),
+            Abi::Unknown(namespace) => write!(f, "unknown ({})", namespace),
+        }
+    }
+}
+
+impl fmt::Display for UnresolvedImport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.reason {
+            UnresolvedReason::UnknownFunction => {
+                write!(f, "`{}`:`{}` doesn't exist", self.namespace, self.name)
+            }
+            UnresolvedReason::SignatureMismatch { expected, obtained } => write!(
+                f,
+                "`{}`:`{}` has signature {:?} but the module expects {:?}",
+                self.namespace, self.name, expected, obtained
+            ),
+            UnresolvedReason::UnsupportedKind => write!(
+                f,
+                "`{}`:`{}` isn't a function, which isn't supported",
+                self.namespace, self.name
+            ),
+        }
+    }
+}
+
+impl fmt::Display for AbiReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ABIs:")?;
+        if self.abis.is_empty() {
+            write!(f, " none")?;
+        }
+        for (n, abi) in self.abis.iter().enumerate() {
+            write!(f, "{}{}", if n == 0 { " " } else { ", " }, abi)?;
+        }
+        for unresolved in &self.unresolved {
+            write!(f, "; {}", unresolved)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Abi, ImportKind, UnresolvedReason};
+    use crate::sig;
+
+    #[test]
+    fn imports_are_reported() {
+        let module = from_wat!(
+            local,
+            r#"(module
+                (import "redshirt" "cancel_message" (func $cancel (param i64)))
+                (import "foo" "bar" (func $bar (result i32)))
+                (import "redshirt" "emit_message_error" (func $err (param i64)))
+                (func $_start (result i32)
+                    i32.const 0)
+                (export "_start" (func $_start)))
+            "#
+        );
+
+        assert_eq!(module.imports().len(), 3);
+        assert_eq!(
+            module.imports()[1].kind,
+            ImportKind::Function(sig!(() -> I32))
+        );
+
+        let report = module.abi_report(|namespace, name| match (namespace, name) {
+            ("redshirt", "cancel_message") => Some(sig!((I64))),
+            ("redshirt", "emit_message_error") => Some(sig!((I32))),
+            _ => None,
+        });
+
+        assert_eq!(report.abis(), &[Abi::Redshirt, Abi::Unknown("foo".into())]);
+        assert!(!report.is_compatible());
+        assert_eq!(report.unresolved().len(), 2);
+        assert_eq!(report.unresolved()[0].name, "bar");
+        assert_eq!(
+            report.unresolved()[0].reason,
+            UnresolvedReason::UnknownFunction
+        );
+        assert_eq!(
+            report.unresolved()[1].reason,
+            UnresolvedReason::SignatureMismatch {
+                expected: sig!((I32)),
+                obtained: sig!((I64)),
+            }
+        );
+    }
+}
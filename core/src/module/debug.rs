@@ -0,0 +1,122 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Breakpoints and single-stepping.
+//!
+//! The interpreter can only be paused when the module calls an imported function. In order to be
+//! able to pause a module at a specific location, modules loaded with
+//! [`Module::from_bytes_debuggable`](super::Module::from_bytes_debuggable) are modified: a
+//! mutable `i32` global containing the debugging mode is added, and an imported function is
+//! called with the index of the function and of the instruction that are about to be executed,
+//! depending on the value of this global:
+//!
+//! - [`MODE_DISABLED`]: the function is never called.
+//! - [`MODE_FUNCTIONS`]: the function is called at the start of each function.
+//! - [`MODE_INSTRUCTIONS`]: the function is called before each instruction.
+//!
+//! Function indices and instruction indices are the ones of the original module, before any
+//! instrumentation.
+
+use super::fuel;
+
+use alloc::{vec, vec::Vec};
+use core::{convert::TryFrom as _, mem};
+use parity_wasm::elements;
+
+/// Namespace of the function that modules call before executing an instruction.
+pub(crate) const NAMESPACE: &str = "redshirt-debug";
+
+/// Name of the function that modules call before executing an instruction.
+pub(crate) const FUNCTION: &str = "breakpoint";
+
+/// Value of the global that disables debugging.
+pub(crate) const MODE_DISABLED: i32 = 0;
+
+/// Value of the global that makes modules call the imported function at the start of each
+/// function.
+pub(crate) const MODE_FUNCTIONS: i32 = 1;
+
+/// Value of the global that makes modules call the imported function before each instruction.
+pub(crate) const MODE_INSTRUCTIONS: i32 = 2;
+
+/// Instruments the given module. Returns the index of the global containing the debugging mode,
+/// or `None` if the module doesn't contain any code and has been left untouched.
+///
+/// `first_function` must be the index of the first function defined in the module before any
+/// instrumentation. Must be called before the other instrumentations, so that instruction indices
+/// match the original code.
+pub(crate) fn inject(module: &mut elements::Module, first_function: u32) -> Option<u32> {
+    if module
+        .code_section()
+        .map_or(true, |c| c.bodies().is_empty())
+    {
+        return None;
+    }
+
+    let breakpoint = fuel::import_function(
+        module,
+        NAMESPACE,
+        FUNCTION,
+        vec![elements::ValueType::I32, elements::ValueType::I32],
+    )?;
+    let mode_global = fuel::add_global(
+        module,
+        elements::ValueType::I32,
+        elements::Instruction::I32Const(MODE_DISABLED),
+    )?;
+
+    for (n, body) in module
+        .code_section_mut()?
+        .bodies_mut()
+        .iter_mut()
+        .enumerate()
+    {
+        // Indices are passed as `i32`s, and are converted back by the virtual machine.
+        let function = first_function.checked_add(u32::try_from(n).ok()?)? as i32;
+        let original = mem::replace(body.code_mut().elements_mut(), Vec::new());
+        let mut instrumented = Vec::with_capacity(original.len() * 9 + 6);
+
+        // At the start of the function, any mode other than `MODE_DISABLED` reports the first
+        // instruction.
+        instrumented.extend_from_slice(&[
+            elements::Instruction::GetGlobal(mode_global),
+            elements::Instruction::If(elements::BlockType::NoResult),
+            elements::Instruction::I32Const(function),
+            elements::Instruction::I32Const(0),
+            elements::Instruction::Call(breakpoint),
+            elements::Instruction::End,
+        ]);
+
+        for (index, instruction) in original.into_iter().enumerate() {
+            if index != 0 {
+                instrumented.extend_from_slice(&[
+                    elements::Instruction::GetGlobal(mode_global),
+                    elements::Instruction::I32Const(MODE_INSTRUCTIONS),
+                    elements::Instruction::I32Eq,
+                    elements::Instruction::If(elements::BlockType::NoResult),
+                    elements::Instruction::I32Const(function),
+                    elements::Instruction::I32Const(u32::try_from(index).ok()? as i32),
+                    elements::Instruction::Call(breakpoint),
+                    elements::Instruction::End,
+                ]);
+            }
+            instrumented.push(instruction);
+        }
+
+        *body.code_mut().elements_mut() = instrumented;
+    }
+
+    Some(mode_global)
+}
@@ -0,0 +1,272 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Fuel metering.
+//!
+//! The interpreter doesn't provide any way to interrupt a thread that doesn't call any imported
+//! function. In order to be able to do so, modules are modified when they are loaded: a mutable
+//! `i64` global containing the remaining fuel is added, and the start of each function and of
+//! each loop subtracts from this global the number of instructions that follow. If the global
+//! becomes negative, an imported function is called, which gives the virtual machine the
+//! opportunity to pause the thread.
+//!
+//! The cost of a function or of a loop doesn't include the instructions of the loops that it
+//! contains, since these loops are charged separately.
+//!
+//! > **Note**: The fuel global is initialized to `i64::max_value()`, meaning that modules whose
+//! >           fuel is never refilled are in practice never interrupted.
+
+use alloc::{vec, vec::Vec};
+use core::{convert::TryFrom as _, mem};
+use parity_wasm::elements;
+
+/// Namespace of the function that modules call when they run out of fuel.
+pub(crate) const NAMESPACE: &str = "redshirt-fuel";
+
+/// Name of the function that modules call when they run out of fuel.
+pub(crate) const FUNCTION: &str = "out_of_fuel";
+
+/// Instruments the given module. Returns the index of the fuel global, or `None` if the module
+/// doesn't contain any code and has been left untouched.
+pub(crate) fn inject(module: &mut elements::Module) -> Option<u32> {
+    if module
+        .code_section()
+        .map_or(true, |c| c.bodies().is_empty())
+    {
+        return None;
+    }
+
+    let out_of_fuel = import_function(module, NAMESPACE, FUNCTION, Vec::new())?;
+
+    let fuel_global = add_global(
+        module,
+        elements::ValueType::I64,
+        elements::Instruction::I64Const(i64::max_value()),
+    )?;
+
+    for body in module.code_section_mut()?.bodies_mut() {
+        let original = mem::replace(body.code_mut().elements_mut(), Vec::new());
+        let mut costs = costs(&original).into_iter();
+        let mut instrumented = Vec::with_capacity(original.len() + 10);
+        push_charge(
+            &mut instrumented,
+            fuel_global,
+            out_of_fuel,
+            costs.next().unwrap_or(0),
+        );
+
+        for instruction in original {
+            let is_loop = match instruction {
+                elements::Instruction::Loop(_) => true,
+                _ => false,
+            };
+
+            instrumented.push(instruction);
+            if is_loop {
+                push_charge(
+                    &mut instrumented,
+                    fuel_global,
+                    out_of_fuel,
+                    costs.next().unwrap_or(0),
+                );
+            }
+        }
+
+        *body.code_mut().elements_mut() = instrumented;
+    }
+
+    Some(fuel_global)
+}
+
+/// Adds to the module an imported function that takes the given parameters and returns nothing,
+/// and returns its index.
+///
+/// The imported function is added after the other imported functions, and the indices of all
+/// the functions defined in the module are shifted by one.
+pub(super) fn import_function(
+    module: &mut elements::Module,
+    namespace: &str,
+    name: &str,
+    params: Vec<elements::ValueType>,
+) -> Option<u32> {
+    if module.type_section().is_none() {
+        let _ = module.insert_section(elements::Section::Type(Default::default()));
+    }
+    let type_index = {
+        let types = module.type_section_mut()?.types_mut();
+        types.push(elements::Type::Function(elements::FunctionType::new(
+            params, None,
+        )));
+        u32::try_from(types.len() - 1).ok()?
+    };
+
+    let index = u32::try_from(module.import_count(elements::ImportCountType::Function)).ok()?;
+    if module.import_section().is_none() {
+        let _ = module.insert_section(elements::Section::Import(Default::default()));
+    }
+    module
+        .import_section_mut()?
+        .entries_mut()
+        .push(elements::ImportEntry::new(
+            namespace.into(),
+            name.into(),
+            elements::External::Function(type_index),
+        ));
+    shift_functions(module, index);
+    Some(index)
+}
+
+/// Adds to the module a mutable global of the given type, initialized with the given constant
+/// instruction, and returns its index.
+pub(super) fn add_global(
+    module: &mut elements::Module,
+    ty: elements::ValueType,
+    init: elements::Instruction,
+) -> Option<u32> {
+    let index = u32::try_from(
+        module.import_count(elements::ImportCountType::Global)
+            + module
+                .global_section()
+                .map(|s| s.entries().len())
+                .unwrap_or(0),
+    )
+    .ok()?;
+    if module.global_section().is_none() {
+        let _ = module.insert_section(elements::Section::Global(Default::default()));
+    }
+    module
+        .global_section_mut()?
+        .entries_mut()
+        .push(elements::GlobalEntry::new(
+            elements::GlobalType::new(ty, true),
+            elements::InitExpr::new(vec![init, elements::Instruction::End]),
+        ));
+    Some(index)
+}
+
+/// Increments by one all the references to functions whose index is superior or equal to
+/// `first`.
+///
+/// > **Note**: The names section, if any, isn't updated, as names are only informative.
+fn shift_functions(module: &mut elements::Module, first: u32) {
+    let shift = |index: &mut u32| {
+        if *index >= first {
+            *index += 1;
+        }
+    };
+
+    if let Some(code) = module.code_section_mut() {
+        for body in code.bodies_mut() {
+            for instruction in body.code_mut().elements_mut() {
+                if let elements::Instruction::Call(index) = instruction {
+                    shift(index);
+                }
+            }
+        }
+    }
+
+    if let Some(exports) = module.export_section_mut() {
+        for export in exports.entries_mut() {
+            if let elements::Internal::Function(index) = export.internal_mut() {
+                shift(index);
+            }
+        }
+    }
+
+    if let Some(elements) = module.elements_section_mut() {
+        for segment in elements.entries_mut() {
+            for index in segment.members_mut() {
+                shift(index);
+            }
+        }
+    }
+
+    if let Some(mut start) = module.start_section() {
+        shift(&mut start);
+        module.set_start_section(start);
+    }
+}
+
+/// Returns the cost of the function whose code is passed, followed with the cost of each of its
+/// loops in the order in which they appear.
+fn costs(code: &[elements::Instruction]) -> Vec<u64> {
+    let mut costs = vec![0];
+    // For each block that is currently open, index within `costs` of the entry that the
+    // instructions of this block are charged to.
+    let mut stack = vec![0];
+
+    for instruction in code {
+        let current = stack.last().copied().unwrap_or(0);
+        costs[current] += 1;
+
+        match instruction {
+            elements::Instruction::Block(_) | elements::Instruction::If(_) => stack.push(current),
+            elements::Instruction::Loop(_) => {
+                costs.push(0);
+                stack.push(costs.len() - 1);
+            }
+            elements::Instruction::End => {
+                stack.pop();
+            }
+            _ => {}
+        }
+    }
+
+    costs
+}
+
+/// Pushes instructions that subtract `cost` from the fuel, and call the `out_of_fuel` function if
+/// the fuel is then negative.
+fn push_charge(
+    out: &mut Vec<elements::Instruction>,
+    fuel_global: u32,
+    out_of_fuel: u32,
+    cost: u64,
+) {
+    let cost = i64::try_from(cost).unwrap_or(i64::max_value());
+    out.extend_from_slice(&[
+        elements::Instruction::GetGlobal(fuel_global),
+        elements::Instruction::I64Const(cost),
+        elements::Instruction::I64Sub,
+        elements::Instruction::SetGlobal(fuel_global),
+        elements::Instruction::GetGlobal(fuel_global),
+        elements::Instruction::I64Const(0),
+        elements::Instruction::I64LtS,
+        elements::Instruction::If(elements::BlockType::NoResult),
+        elements::Instruction::Call(out_of_fuel),
+        elements::Instruction::End,
+    ]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::costs;
+    use parity_wasm::elements::{BlockType, Instruction};
+
+    #[test]
+    fn loops_charged_separately() {
+        let code = [
+            Instruction::Nop,
+            Instruction::Block(BlockType::NoResult),
+            Instruction::Loop(BlockType::NoResult),
+            Instruction::Nop,
+            Instruction::Br(0),
+            Instruction::End,
+            Instruction::End,
+            Instruction::End,
+        ];
+        assert_eq!(costs(&code), [5, 3]);
+    }
+}
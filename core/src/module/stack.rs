@@ -0,0 +1,98 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Limiting the depth of the stack.
+//!
+//! The interpreter has its own hardcoded limits on the size of the stack, and reports a generic
+//! trap when they are reached. In order to enforce a configurable limit, modules are modified
+//! when they are loaded: a mutable `i32` global containing the number of nested calls that remain
+//! allowed is added, and every call instruction decrements this global before the call and
+//! increments it back afterwards. If the global becomes negative, an imported function is called,
+//! which gives the virtual machine the opportunity to stop the thread.
+//!
+//! > **Note**: The global is initialized to `i32::max_value()`, meaning that the depth is in
+//! >           practice only limited by the interpreter unless the virtual machine sets it.
+
+use super::fuel;
+
+use alloc::vec::Vec;
+use core::mem;
+use parity_wasm::elements;
+
+/// Namespace of the function that modules call when they exceed the maximum stack depth.
+pub(crate) const NAMESPACE: &str = "redshirt-stack";
+
+/// Name of the function that modules call when they exceed the maximum stack depth.
+pub(crate) const FUNCTION: &str = "stack_overflow";
+
+/// Instruments the given module. Returns the index of the global containing the remaining depth,
+/// or `None` if the module doesn't contain any code and has been left untouched.
+///
+/// Must be called before [`fuel::inject`], otherwise the calls to the out-of-fuel function would
+/// be instrumented as well.
+pub(crate) fn inject(module: &mut elements::Module) -> Option<u32> {
+    if module
+        .code_section()
+        .map_or(true, |c| c.bodies().is_empty())
+    {
+        return None;
+    }
+
+    let overflow = fuel::import_function(module, NAMESPACE, FUNCTION, Vec::new())?;
+    let depth_global = fuel::add_global(
+        module,
+        elements::ValueType::I32,
+        elements::Instruction::I32Const(i32::max_value()),
+    )?;
+
+    for body in module.code_section_mut()?.bodies_mut() {
+        let original = mem::replace(body.code_mut().elements_mut(), Vec::new());
+        let mut instrumented = Vec::with_capacity(original.len());
+
+        for instruction in original {
+            let is_call = match instruction {
+                elements::Instruction::Call(_) | elements::Instruction::CallIndirect(_, _) => true,
+                _ => false,
+            };
+
+            if !is_call {
+                instrumented.push(instruction);
+                continue;
+            }
+
+            instrumented.extend_from_slice(&[
+                elements::Instruction::GetGlobal(depth_global),
+                elements::Instruction::I32Const(1),
+                elements::Instruction::I32Sub,
+                elements::Instruction::SetGlobal(depth_global),
+                elements::Instruction::GetGlobal(depth_global),
+                elements::Instruction::I32Const(0),
+                elements::Instruction::I32LtS,
+                elements::Instruction::If(elements::BlockType::NoResult),
+                elements::Instruction::Call(overflow),
+                elements::Instruction::End,
+                instruction,
+                elements::Instruction::GetGlobal(depth_global),
+                elements::Instruction::I32Const(1),
+                elements::Instruction::I32Add,
+                elements::Instruction::SetGlobal(depth_global),
+            ]);
+        }
+
+        *body.code_mut().elements_mut() = instrumented;
+    }
+
+    Some(depth_global)
+}
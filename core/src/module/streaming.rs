@@ -0,0 +1,191 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Loading of a module whose bytes arrive progressively.
+//!
+//! The bytes are hashed as they are fed, and each section is checked as soon as it is complete.
+//! A module that is malformed or that relies on unsupported features is therefore rejected
+//! without having to wait for the rest of it, for example while it is still being downloaded.
+
+use super::{
+    has_multi_value_type, read_leb128, Module, ModuleErr, ModuleHash, TAG_SECTION_ID,
+    TYPE_SECTION_ID,
+};
+
+use alloc::vec::Vec;
+use core::convert::TryFrom as _;
+
+/// Header that all WASM binaries start with, including the version number.
+const HEADER: &[u8] = b"\0asm\x01\0\0\0";
+
+/// Highest identifier of a section defined by the WASM specification.
+const LAST_SECTION_ID: u8 = 12;
+
+/// Builds a [`Module`] from bytes that are provided in multiple chunks.
+///
+/// Call [`StreamingLoader::feed`] with each chunk, in order, then [`StreamingLoader::finish`]
+/// once all the bytes have been fed.
+pub struct StreamingLoader {
+    /// Bytes fed so far.
+    buffer: Vec<u8>,
+    /// Hash of the bytes fed so far.
+    hasher: blake3::Hasher,
+    /// Number of bytes at the start of `buffer` that have been checked. Always points to the
+    /// start of a section, or to the end of the header.
+    checked: usize,
+    /// If true, the module is loaded with [`Module::from_bytes_debuggable`].
+    debuggable: bool,
+}
+
+impl StreamingLoader {
+    /// Initializes a new loader, with no bytes yet.
+    pub fn new() -> Self {
+        StreamingLoader {
+            buffer: Vec::new(),
+            hasher: blake3::Hasher::new(),
+            checked: 0,
+            debuggable: false,
+        }
+    }
+
+    /// Makes the module debuggable. See [`Module::from_bytes_debuggable`].
+    pub fn with_debuggable(mut self) -> Self {
+        self.debuggable = true;
+        self
+    }
+
+    /// Returns the number of bytes fed so far.
+    pub fn len(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Returns true if no byte has been fed yet.
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
+    /// Adds bytes at the end of the module, and checks the sections that are now complete.
+    ///
+    /// Returns an error if the bytes fed so far can't be the beginning of a module that is
+    /// accepted by [`Module::from_bytes`]. Not all the errors are detected here, and
+    /// [`StreamingLoader::finish`] can return an error even if this method never has.
+    pub fn feed(&mut self, bytes: &[u8]) -> Result<(), ModuleErr> {
+        self.buffer.extend_from_slice(bytes);
+        self.hasher.update(bytes);
+
+        if self.checked == 0 {
+            let len = self.buffer.len().min(HEADER.len());
+            if self.buffer[..len] != HEADER[..len] {
+                return Err(ModuleErr::Malformed);
+            }
+            if len < HEADER.len() {
+                return Ok(());
+            }
+            self.checked = HEADER.len();
+        }
+
+        while let Some((&id, mut rest)) = self.buffer[self.checked..].split_first() {
+            let size = match read_leb128(&mut rest) {
+                Some(s) => usize::try_from(s).map_err(|_| ModuleErr::Malformed)?,
+                // A LEB128-encoded 32-bits integer is at most 5 bytes long.
+                None if rest.len() >= 5 => return Err(ModuleErr::Malformed),
+                None => break,
+            };
+            if rest.len() < size {
+                break;
+            }
+
+            if id == TAG_SECTION_ID {
+                return Err(ModuleErr::ExceptionHandling);
+            }
+            if id > LAST_SECTION_ID {
+                return Err(ModuleErr::Malformed);
+            }
+            if id == TYPE_SECTION_ID && has_multi_value_type(&rest[..size]) {
+                return Err(ModuleErr::MultiValue);
+            }
+
+            self.checked = self.buffer.len() - (rest.len() - size);
+        }
+
+        Ok(())
+    }
+
+    /// Builds the [`Module`] from all the bytes that have been fed.
+    ///
+    /// The returned module is the same as if all the bytes had been passed to
+    /// [`Module::from_bytes`].
+    pub fn finish(self) -> Result<Module, ModuleErr> {
+        if self.checked != self.buffer.len() {
+            return Err(ModuleErr::Malformed);
+        }
+
+        let hash = ModuleHash::from(<[u8; 32]>::from(self.hasher.finalize()));
+        Module::from_bytes_with_hash(&self.buffer, hash, self.debuggable)
+    }
+}
+
+impl Default for StreamingLoader {
+    fn default() -> Self {
+        StreamingLoader::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Module, ModuleErr, StreamingLoader};
+
+    #[test]
+    fn same_as_from_bytes() {
+        let bytes = wat_to_bin!(
+            r#"(module
+            (func $add (param i32 i32) (result i32)
+                get_local 0
+                get_local 1
+                i32.add)
+            (export "add" (func $add)))
+        "#
+        );
+
+        let mut loader = StreamingLoader::new();
+        for byte in bytes.iter() {
+            loader.feed(&[*byte]).unwrap();
+        }
+        let module = loader.finish().unwrap();
+        assert_eq!(module.hash(), Module::from_bytes(bytes).unwrap().hash());
+    }
+
+    #[test]
+    fn errors_reported_early() {
+        let mut loader = StreamingLoader::new();
+        assert_eq!(loader.feed(b"\0as"), Ok(()));
+        assert_eq!(loader.feed(b"n"), Err(ModuleErr::Malformed));
+
+        // Tag section, followed with bytes that never arrive.
+        let mut loader = StreamingLoader::new();
+        loader.feed(b"\0asm\x01\0\0\0").unwrap();
+        assert_eq!(
+            loader.feed(b"\x0d\x01\0\x01\x50"),
+            Err(ModuleErr::ExceptionHandling)
+        );
+    }
+
+    #[test]
+    fn truncated_module_refused() {
+        let mut loader = StreamingLoader::new();
+        loader.feed(b"\0asm\x01\0\0\0\x01\x05\0").unwrap();
+        assert_eq!(loader.finish().err(), Some(ModuleErr::Malformed));
+    }
+}
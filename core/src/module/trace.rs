@@ -0,0 +1,87 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Tracking of the function being executed.
+//!
+//! The interpreter doesn't report where an error has happened. In order to be able to report
+//! it, modules are modified when they are loaded: a mutable `i32` global is added, and the start
+//! of each function, as well as the instruction following each call, sets this global to the
+//! index of the function. When an error happens, the global contains the index of the function
+//! that was being executed.
+//!
+//! The indices are the ones of the original module, before any other instrumentation, so that
+//! they match the name section.
+
+use super::fuel;
+
+use alloc::{vec, vec::Vec};
+use core::{convert::TryFrom as _, mem};
+use parity_wasm::elements;
+
+/// Instruments the given module. Returns the index of the global containing the index of the
+/// current function, or `None` if the module doesn't contain any code and has been left
+/// untouched.
+///
+/// `first_function` must be the index of the first function defined in the module before any
+/// instrumentation.
+pub(crate) fn inject(module: &mut elements::Module, first_function: u32) -> Option<u32> {
+    if module
+        .code_section()
+        .map_or(true, |c| c.bodies().is_empty())
+    {
+        return None;
+    }
+
+    let global = fuel::add_global(
+        module,
+        elements::ValueType::I32,
+        elements::Instruction::I32Const(-1),
+    )?;
+
+    for (n, body) in module
+        .code_section_mut()?
+        .bodies_mut()
+        .iter_mut()
+        .enumerate()
+    {
+        // Function indices are stored as `i32`s, and are converted back when read.
+        let index = first_function.checked_add(u32::try_from(n).ok()?)? as i32;
+        let original = mem::replace(body.code_mut().elements_mut(), Vec::new());
+        let mut instrumented = vec![
+            elements::Instruction::I32Const(index),
+            elements::Instruction::SetGlobal(global),
+        ];
+        instrumented.reserve(original.len());
+
+        for instruction in original {
+            let is_call = match instruction {
+                elements::Instruction::Call(_) | elements::Instruction::CallIndirect(_, _) => true,
+                _ => false,
+            };
+
+            instrumented.push(instruction);
+            if is_call {
+                instrumented.extend_from_slice(&[
+                    elements::Instruction::I32Const(index),
+                    elements::Instruction::SetGlobal(global),
+                ]);
+            }
+        }
+
+        *body.code_mut().elements_mut() = instrumented;
+    }
+
+    Some(global)
+}
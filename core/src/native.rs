@@ -18,6 +18,7 @@ pub use self::collection::{
 };
 pub use self::traits::{
     DummyMessageIdWrite, NativeProgramEvent, NativeProgramMessageIdWrite, NativeProgramRef,
+    ResourceHandle, ResourceKind,
 };
 
 mod collection;
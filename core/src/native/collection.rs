@@ -13,13 +13,15 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use crate::native::traits::{NativeProgramEvent, NativeProgramMessageIdWrite, NativeProgramRef};
+use crate::native::traits::{
+    NativeProgramEvent, NativeProgramMessageIdWrite, NativeProgramRef, ResourceHandle,
+};
 
 use alloc::{boxed::Box, vec::Vec};
 use core::{mem, task::Context, task::Poll};
 use fnv::FnvBuildHasher;
 use futures::prelude::*;
-use hashbrown::HashSet;
+use hashbrown::{HashMap, HashSet};
 use nohash_hasher::BuildNoHashHasher;
 use redshirt_interface_interface::ffi::InterfaceMessage;
 use redshirt_syscalls::{Decode as _, EncodedMessage, InterfaceHash, MessageId, Pid};
@@ -29,6 +31,11 @@ use spinning_top::Spinlock;
 pub struct NativeProgramsCollection<'ext> {
     /// Collection of processes and their `Pid`.
     processes: Vec<(Pid, Box<dyn AdapterAbstract + Send + 'ext>)>,
+
+    /// Programs that have been replaced using [`NativeProgramsCollection::replace`], and that
+    /// still have messages to answer or responses to receive. Continue to be polled, but no
+    /// longer receive interface messages.
+    draining: Vec<(Pid, Box<dyn AdapterAbstract + Send + 'ext>)>,
 }
 
 /// Event generated by a [`NativeProgramRef`].
@@ -71,6 +78,10 @@ struct Adapter<T> {
     inner: T,
     registered_interfaces: Spinlock<HashSet<InterfaceHash, FnvBuildHasher>>,
     expected_responses: Spinlock<HashSet<MessageId, BuildNoHashHasher<u64>>>,
+    /// Messages delivered to the program and that it hasn't answered yet.
+    pending_answers: Spinlock<HashSet<MessageId, BuildNoHashHasher<u64>>>,
+    /// Resources that the program holds on behalf of other programs, indexed by owner.
+    resources: Spinlock<HashMap<Pid, Vec<ResourceHandle>, BuildNoHashHasher<u64>>>,
 }
 
 /// Abstracts over [`Adapter`] so that we can box it.
@@ -92,6 +103,10 @@ trait AdapterAbstract {
         response: Result<EncodedMessage, ()>,
     ) -> Result<(), Result<EncodedMessage, ()>>;
     fn process_destroyed(&self, pid: Pid);
+    fn has_registered(&self, interface: &InterfaceHash) -> bool;
+    fn take_registered_interfaces(&self) -> HashSet<InterfaceHash, FnvBuildHasher>;
+    /// Returns true if the program has no message to answer and doesn't expect any response.
+    fn is_idle(&self) -> bool;
 }
 
 trait AbstractMessageIdWrite {
@@ -110,6 +125,7 @@ impl<'ext> NativeProgramsCollection<'ext> {
     pub fn new() -> Self {
         NativeProgramsCollection {
             processes: Vec::new(),
+            draining: Vec::new(),
         }
     }
 
@@ -124,11 +140,7 @@ impl<'ext> NativeProgramsCollection<'ext> {
         T: Send + 'ext,
         for<'r> &'r T: NativeProgramRef<'r>,
     {
-        let adapter = Box::new(Adapter {
-            inner: program,
-            registered_interfaces: Spinlock::new(HashSet::with_hasher(Default::default())),
-            expected_responses: Spinlock::new(HashSet::with_hasher(Default::default())),
-        });
+        let adapter = Box::new(Adapter::new(program, Default::default()));
 
         assert!(!self
             .processes
@@ -140,12 +152,43 @@ impl<'ext> NativeProgramsCollection<'ext> {
         self.processes.shrink_to_fit();
     }
 
+    /// Replaces the program that has registered the given interface with a new one.
+    ///
+    /// The new program takes over the [`Pid`] and all the interfaces of the old one, and
+    /// receives all the messages on these interfaces from now on. The new program doesn't need
+    /// to register these interfaces.
+    ///
+    /// The old program continues to be polled until it has answered all the messages that have
+    /// been delivered to it, and has received the responses to all the messages that it has
+    /// emitted. It is then destroyed during a later call to this method.
+    ///
+    /// Returns an error if no program has registered this interface.
+    pub fn replace<T>(&mut self, interface: &InterfaceHash, program: T) -> Result<(), ()>
+    where
+        T: Send + 'ext,
+        for<'r> &'r T: NativeProgramRef<'r>,
+    {
+        self.draining.retain(|(_, process)| !process.is_idle());
+
+        let (pid, process) = self
+            .processes
+            .iter_mut()
+            .find(|(_, process)| process.has_registered(interface))
+            .ok_or(())?;
+
+        let adapter = Box::new(Adapter::new(program, process.take_registered_interfaces()));
+        let old = mem::replace(process, adapter);
+        self.draining.push((*pid, old));
+        Ok(())
+    }
+
     /// Returns a `Future` that yields the next event generated by one of the programs.
     pub fn next_event<'collec>(
         &'collec self,
     ) -> impl Future<Output = NativeProgramsCollectionEvent<'collec>> + 'collec {
         future::poll_fn(move |cx| {
-            for (pid, process) in self.processes.iter() {
+            let draining = self.draining.iter().filter(|(_, p)| !p.is_idle());
+            for (pid, process) in self.processes.iter().chain(draining) {
                 match process.poll_next_event(cx) {
                     Poll::Pending => {}
                     Poll::Ready(NativeProgramEvent::Emit {
@@ -172,6 +215,9 @@ impl<'ext> NativeProgramsCollection<'ext> {
                             answer,
                         })
                     }
+                    // Resource events are handled by the adapter.
+                    Poll::Ready(NativeProgramEvent::AcquireResource { .. })
+                    | Poll::Ready(NativeProgramEvent::ReleaseResource { .. }) => unreachable!(),
                 }
             }
 
@@ -201,8 +247,11 @@ impl<'ext> NativeProgramsCollection<'ext> {
     }
 
     /// Notify the [`NativeProgramRef`]s that the program with the given [`Pid`] has terminated.
+    ///
+    /// The [`NativeProgramRef`]s that hold resources on behalf of this program are additionally
+    /// notified of these resources through [`NativeProgramRef::resources_released`].
     pub fn process_destroyed(&self, pid: Pid) {
-        for (_, process) in &self.processes {
+        for (_, process) in self.processes.iter().chain(self.draining.iter()) {
             process.process_destroyed(pid);
         }
     }
@@ -214,7 +263,7 @@ impl<'ext> NativeProgramsCollection<'ext> {
         message_id: MessageId,
         mut response: Result<EncodedMessage, ()>,
     ) {
-        for (_, process) in &self.processes {
+        for (_, process) in self.processes.iter().chain(self.draining.iter()) {
             let msg = mem::replace(&mut response, Ok(EncodedMessage(Vec::new())));
             match process.deliver_response(message_id, msg) {
                 Ok(_) => return,
@@ -226,6 +275,18 @@ impl<'ext> NativeProgramsCollection<'ext> {
     }
 }
 
+impl<T> Adapter<T> {
+    fn new(inner: T, registered_interfaces: HashSet<InterfaceHash, FnvBuildHasher>) -> Self {
+        Adapter {
+            inner,
+            registered_interfaces: Spinlock::new(registered_interfaces),
+            expected_responses: Spinlock::new(HashSet::with_hasher(Default::default())),
+            pending_answers: Spinlock::new(HashSet::with_hasher(Default::default())),
+            resources: Spinlock::new(HashMap::with_hasher(Default::default())),
+        }
+    }
+}
+
 impl<T> AdapterAbstract for Adapter<T>
 where
     for<'r> &'r T: NativeProgramRef<'r>,
@@ -234,43 +295,64 @@ where
         &'col self,
         cx: &mut Context,
     ) -> Poll<NativeProgramEvent<Box<dyn AbstractMessageIdWrite + 'col>>> {
-        let future = (&self.inner).next_event();
-        futures::pin_mut!(future);
-        match future.poll(cx) {
-            Poll::Ready(NativeProgramEvent::Emit {
-                interface,
-                message_id_write,
-                message,
-            }) => {
-                if interface == redshirt_interface_interface::ffi::INTERFACE {
-                    // TODO: check whether registration succeeds, but hard if `message_id_write` is `None
-                    if let Ok(msg) = InterfaceMessage::decode(message.clone()) {
-                        let InterfaceMessage::Register(to_reg) = msg;
-                        let mut registered_interfaces = self.registered_interfaces.lock();
-                        registered_interfaces.insert(to_reg);
+        loop {
+            let future = (&self.inner).next_event();
+            futures::pin_mut!(future);
+            match future.poll(cx) {
+                Poll::Ready(NativeProgramEvent::AcquireResource { owner, resource }) => {
+                    let mut resources = self.resources.lock();
+                    resources
+                        .entry(owner)
+                        .or_insert_with(Vec::new)
+                        .push(resource);
+                }
+                Poll::Ready(NativeProgramEvent::ReleaseResource { owner, resource }) => {
+                    let mut resources = self.resources.lock();
+                    if let Some(owned) = resources.get_mut(&owner) {
+                        if let Some(pos) = owned.iter().position(|r| *r == resource) {
+                            owned.swap_remove(pos);
+                        }
+                        if owned.is_empty() {
+                            resources.remove(&owner);
+                        }
                     }
                 }
-
-                let message_id_write = message_id_write.map(|inner| {
-                    Box::new(MessageIdWriteAdapter {
-                        inner: Some(inner),
-                        expected_responses: &self.expected_responses,
-                    }) as Box<_>
-                });
-
                 Poll::Ready(NativeProgramEvent::Emit {
                     interface,
-                    message,
                     message_id_write,
-                })
-            }
-            Poll::Ready(NativeProgramEvent::CancelMessage { message_id }) => {
-                Poll::Ready(NativeProgramEvent::CancelMessage { message_id })
-            }
-            Poll::Ready(NativeProgramEvent::Answer { message_id, answer }) => {
-                Poll::Ready(NativeProgramEvent::Answer { message_id, answer })
+                    message,
+                }) => {
+                    if interface == redshirt_interface_interface::ffi::INTERFACE {
+                        // TODO: check whether registration succeeds, but hard if `message_id_write` is `None
+                        if let Ok(msg) = InterfaceMessage::decode(message.clone()) {
+                            let InterfaceMessage::Register(to_reg) = msg;
+                            let mut registered_interfaces = self.registered_interfaces.lock();
+                            registered_interfaces.insert(to_reg);
+                        }
+                    }
+
+                    let message_id_write = message_id_write.map(|inner| {
+                        Box::new(MessageIdWriteAdapter {
+                            inner: Some(inner),
+                            expected_responses: &self.expected_responses,
+                        }) as Box<_>
+                    });
+
+                    return Poll::Ready(NativeProgramEvent::Emit {
+                        interface,
+                        message,
+                        message_id_write,
+                    });
+                }
+                Poll::Ready(NativeProgramEvent::CancelMessage { message_id }) => {
+                    return Poll::Ready(NativeProgramEvent::CancelMessage { message_id })
+                }
+                Poll::Ready(NativeProgramEvent::Answer { message_id, answer }) => {
+                    self.pending_answers.lock().remove(&message_id);
+                    return Poll::Ready(NativeProgramEvent::Answer { message_id, answer });
+                }
+                Poll::Pending => return Poll::Pending,
             }
-            Poll::Pending => Poll::Pending,
         }
     }
 
@@ -283,6 +365,9 @@ where
     ) -> Result<(), EncodedMessage> {
         let registered_interfaces = self.registered_interfaces.lock();
         if registered_interfaces.contains(&interface) {
+            if let Some(message_id) = message_id {
+                self.pending_answers.lock().insert(message_id);
+            }
             self.inner
                 .interface_message(interface, message_id, emitter_pid, message);
             Ok(())
@@ -306,8 +391,27 @@ where
     }
 
     fn process_destroyed(&self, pid: Pid) {
+        let resources = self.resources.lock().remove(&pid);
+        if let Some(resources) = resources {
+            self.inner.resources_released(pid, resources);
+        }
         self.inner.process_destroyed(pid);
     }
+
+    fn has_registered(&self, interface: &InterfaceHash) -> bool {
+        self.registered_interfaces.lock().contains(interface)
+    }
+
+    fn take_registered_interfaces(&self) -> HashSet<InterfaceHash, FnvBuildHasher> {
+        mem::replace(
+            &mut *self.registered_interfaces.lock(),
+            HashSet::with_hasher(Default::default()),
+        )
+    }
+
+    fn is_idle(&self) -> bool {
+        self.pending_answers.lock().is_empty() && self.expected_responses.lock().is_empty()
+    }
 }
 
 impl<'col, T> AbstractMessageIdWrite for MessageIdWriteAdapter<'col, T>
@@ -334,11 +438,210 @@ impl<'col> NativeProgramMessageIdWrite for NativeProgramsCollectionMessageIdWrit
 
 #[cfg(test)]
 mod tests {
-    use super::NativeProgramsCollection;
+    use super::{NativeProgramsCollection, NativeProgramsCollectionEvent};
+    use crate::native::{
+        DummyMessageIdWrite, NativeProgramEvent, NativeProgramRef, ResourceHandle, ResourceKind,
+    };
+    use alloc::{boxed::Box, sync::Arc, vec, vec::Vec};
+    use core::{pin::Pin, sync::atomic};
+    use futures::prelude::*;
+    use redshirt_syscalls::{Encode as _, EncodedMessage, InterfaceHash, MessageId, Pid};
+    use spinning_top::Spinlock;
 
     #[test]
     fn is_send() {
         fn req_send<T: Send>() {}
         req_send::<NativeProgramsCollection>();
     }
+
+    /// Native program that registers an interface, then answers the messages it receives.
+    struct Echo {
+        registered: atomic::AtomicBool,
+        received: Spinlock<Vec<MessageId>>,
+    }
+
+    impl Echo {
+        fn new(registered: bool) -> Self {
+            Echo {
+                registered: atomic::AtomicBool::new(registered),
+                received: Spinlock::new(Vec::new()),
+            }
+        }
+    }
+
+    impl<'a> NativeProgramRef<'a> for &'a Echo {
+        type Future =
+            Pin<Box<dyn Future<Output = NativeProgramEvent<Self::MessageIdWrite>> + Send + 'a>>;
+        type MessageIdWrite = DummyMessageIdWrite;
+
+        fn next_event(self) -> Self::Future {
+            if !self.registered.swap(true, atomic::Ordering::Relaxed) {
+                return Box::pin(future::ready(NativeProgramEvent::Emit {
+                    interface: redshirt_interface_interface::ffi::INTERFACE,
+                    message_id_write: None,
+                    message: redshirt_interface_interface::ffi::InterfaceMessage::Register(
+                        interface(),
+                    )
+                    .encode(),
+                }));
+            }
+
+            match self.received.lock().pop() {
+                Some(message_id) => Box::pin(future::ready(NativeProgramEvent::Answer {
+                    message_id,
+                    answer: Ok(EncodedMessage(Vec::new())),
+                })),
+                None => Box::pin(future::pending()),
+            }
+        }
+
+        fn interface_message(
+            self,
+            _: InterfaceHash,
+            message_id: Option<MessageId>,
+            _: Pid,
+            _: EncodedMessage,
+        ) {
+            self.received.lock().push(message_id.unwrap());
+        }
+
+        fn process_destroyed(self, _: Pid) {}
+
+        fn message_response(self, _: MessageId, _: Result<EncodedMessage, ()>) {
+            unreachable!()
+        }
+    }
+
+    fn interface() -> InterfaceHash {
+        InterfaceHash::from_raw_hash([0xa5; 32])
+    }
+
+    fn answered(collection: &NativeProgramsCollection) -> Option<MessageId> {
+        match collection.next_event().now_or_never()? {
+            NativeProgramsCollectionEvent::Answer { message_id, .. } => Some(message_id),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn replace_drains_old_program() {
+        let pid = Pid::from(1);
+        let mut collection = NativeProgramsCollection::new();
+        collection.push(pid, Echo::new(false));
+
+        match collection.next_event().now_or_never() {
+            Some(NativeProgramsCollectionEvent::Emit {
+                emitter_pid,
+                message_id_write: None,
+                ..
+            }) => assert_eq!(emitter_pid, pid),
+            _ => panic!(),
+        }
+
+        collection.interface_message(
+            interface(),
+            Some(MessageId::from(10)),
+            Pid::from(2),
+            EncodedMessage(Vec::new()),
+        );
+
+        assert!(collection
+            .replace(&InterfaceHash::from_raw_hash([0; 32]), Echo::new(true))
+            .is_err());
+        collection.replace(&interface(), Echo::new(true)).unwrap();
+
+        // Messages are now delivered to the new program, while the old one answers the message
+        // it has already received.
+        collection.interface_message(
+            interface(),
+            Some(MessageId::from(11)),
+            Pid::from(2),
+            EncodedMessage(Vec::new()),
+        );
+        assert_eq!(answered(&collection), Some(MessageId::from(11)));
+        assert_eq!(answered(&collection), Some(MessageId::from(10)));
+        assert_eq!(answered(&collection), None);
+        assert!(collection.draining.iter().all(|(_, p)| p.is_idle()));
+    }
+
+    /// Native program that emits a predefined list of events, and records the resources it is
+    /// asked to release.
+    struct ResourceHolder {
+        events: Spinlock<Vec<NativeProgramEvent<DummyMessageIdWrite>>>,
+        released: Arc<Spinlock<Vec<(Pid, Vec<ResourceHandle>)>>>,
+    }
+
+    impl<'a> NativeProgramRef<'a> for &'a ResourceHolder {
+        type Future =
+            Pin<Box<dyn Future<Output = NativeProgramEvent<Self::MessageIdWrite>> + Send + 'a>>;
+        type MessageIdWrite = DummyMessageIdWrite;
+
+        fn next_event(self) -> Self::Future {
+            match self.events.lock().pop() {
+                Some(event) => Box::pin(future::ready(event)),
+                None => Box::pin(future::pending()),
+            }
+        }
+
+        fn interface_message(
+            self,
+            _: InterfaceHash,
+            _: Option<MessageId>,
+            _: Pid,
+            _: EncodedMessage,
+        ) {
+            unreachable!()
+        }
+
+        fn process_destroyed(self, _: Pid) {}
+
+        fn resources_released(self, pid: Pid, resources: Vec<ResourceHandle>) {
+            self.released.lock().push((pid, resources));
+        }
+
+        fn message_response(self, _: MessageId, _: Result<EncodedMessage, ()>) {
+            unreachable!()
+        }
+    }
+
+    #[test]
+    fn resources_released_on_process_destroyed() {
+        let owner = Pid::from(7);
+        let socket = |id| ResourceHandle {
+            kind: ResourceKind::Socket,
+            id,
+        };
+
+        let holder = ResourceHolder {
+            // Events are popped from the end.
+            events: Spinlock::new(vec![
+                NativeProgramEvent::ReleaseResource {
+                    owner,
+                    resource: socket(1),
+                },
+                NativeProgramEvent::AcquireResource {
+                    owner,
+                    resource: socket(2),
+                },
+                NativeProgramEvent::AcquireResource {
+                    owner,
+                    resource: socket(1),
+                },
+            ]),
+            released: Arc::new(Spinlock::new(Vec::new())),
+        };
+        let released = holder.released.clone();
+
+        let mut collection = NativeProgramsCollection::new();
+        collection.push(Pid::from(1), holder);
+
+        // Resource events are consumed by the collection.
+        assert!(collection.next_event().now_or_never().is_none());
+
+        collection.process_destroyed(Pid::from(8));
+        collection.process_destroyed(owner);
+        collection.process_destroyed(owner);
+
+        assert_eq!(*released.lock(), vec![(owner, vec![socket(2)])]);
+    }
 }
@@ -13,6 +13,7 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use alloc::vec::Vec;
 use core::future::Future;
 use redshirt_syscalls::{EncodedMessage, InterfaceHash, MessageId, Pid};
 
@@ -31,7 +32,7 @@ pub trait NativeProgramRef<'a>: Clone {
 
     /// When the [`NativeProgramRef`] emits a message, this item is used by the caller to notify
     /// of the [`MessageId`] that has been emitted.
-    type MessageIdWrite: NativeProgramMessageIdWrite;
+    type MessageIdWrite: NativeProgramMessageIdWrite + Send;
 
     /// Returns a `Future` resolving to when the [`NativeProgramRef`] wants to do something.
     fn next_event(self) -> Self::Future;
@@ -49,6 +50,18 @@ pub trait NativeProgramRef<'a>: Clone {
     /// Notify the [`NativeProgramRef`] that the program with the given [`Pid`] has terminated.
     fn process_destroyed(self, pid: Pid);
 
+    /// Notify the [`NativeProgramRef`] that the program with the given [`Pid`] has terminated
+    /// while owning the given resources.
+    ///
+    /// The list contains all the resources that the [`NativeProgramRef`] has previously reported
+    /// with [`NativeProgramEvent::AcquireResource`] for this [`Pid`], and that haven't been
+    /// released with [`NativeProgramEvent::ReleaseResource`]. It is never empty.
+    ///
+    /// This is always called before [`NativeProgramRef::process_destroyed`].
+    fn resources_released(self, pid: Pid, resources: Vec<ResourceHandle>) {
+        let _ = (pid, resources);
+    }
+
     /// Notify the [`NativeProgramRef`] of a response to a message that it has previously emitted.
     fn message_response(self, message_id: MessageId, response: Result<EncodedMessage, ()>);
 }
@@ -81,6 +94,50 @@ pub enum NativeProgramEvent<TMsgIdWrite> {
         /// Answer to the message. Can be an error if the message is invalid.
         answer: Result<EncodedMessage, ()>,
     },
+    /// Indicates that a resource has been allocated on behalf of a program.
+    ///
+    /// If the program terminates before the resource is released, the resource is passed back
+    /// through [`NativeProgramRef::resources_released`].
+    AcquireResource {
+        /// Program that owns the resource.
+        owner: Pid,
+        /// Resource that has been allocated.
+        resource: ResourceHandle,
+    },
+    /// Indicates that a resource previously reported with
+    /// [`NativeProgramEvent::AcquireResource`] has been freed.
+    ReleaseResource {
+        /// Program that owned the resource.
+        owner: Pid,
+        /// Resource that has been freed.
+        resource: ResourceHandle,
+    },
+}
+
+/// Resource that a [`NativeProgramRef`] holds on behalf of a program.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ResourceHandle {
+    /// What kind of resource this is.
+    pub kind: ResourceKind,
+    /// Identifier of the resource. Its meaning is specific to the [`NativeProgramRef`].
+    pub id: u64,
+}
+
+/// Kind of a [`ResourceHandle`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ResourceKind {
+    /// Network socket.
+    Socket,
+    /// Opened file.
+    File,
+    /// Pipe between programs.
+    Pipe,
+    /// Region of memory shared between programs.
+    SharedMemory,
+    /// Timer.
+    Timer,
+    /// Any other kind of resource.
+    Other,
 }
 
 /// Trait used to write back the [`MessageId`] when the program emits a message.
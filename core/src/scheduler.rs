@@ -14,11 +14,25 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 mod extrinsics;
+mod inbox;
 mod ipc;
+mod observer;
 mod processes;
+mod self_check;
+mod snapshot;
 mod tests;
 mod vm;
 
 // TODO: move definition?
-pub use self::ipc::{Core, CoreBuilder, CoreProcess, CoreRunOutcome};
-pub use self::vm::NewErr;
+pub use self::inbox::{InboxConfig, OverflowPolicy};
+pub use self::ipc::{
+    Core, CoreBuilder, CorePreparedProcess, CoreProcess, CoreRunOutcome, ProcessCounters,
+};
+pub use self::observer::SchedulerObserver;
+pub use self::processes::{
+    CachedModule, ExtrinsicsAllowlist, LimitExceeded, ModuleCache, OrphanPolicy, ProcessConfig,
+    ResourceUsage,
+};
+pub use self::self_check::{SelfCheckConfig, Violation};
+pub use self::snapshot::{MemoryDiff, MemoryRegion, MemorySnapshot, SNAPSHOT_PAGE_SIZE};
+pub use self::vm::{ModuleLimits, NewErr};
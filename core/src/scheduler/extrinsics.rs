@@ -16,14 +16,15 @@
 use crate::extrinsics::{
     Extrinsics, ExtrinsicsAction, ExtrinsicsMemoryAccess, ExtrinsicsMemoryAccessErr,
 };
-use crate::module::Module;
-use crate::scheduler::{processes, vm};
+use crate::module::{AbiReport, Module};
+use crate::scheduler::{processes, self_check::Violation, snapshot::MemorySnapshot, vm};
 use crate::sig;
-use crate::{InterfaceHash, MessageId};
+use crate::{InterfaceHash, MessageId, TrapError, TrapKind};
 
-use alloc::{sync::Arc, vec::Vec};
+use alloc::{string::String, sync::Arc, vec::Vec};
 use core::{cell::RefCell, convert::TryFrom as _, fmt, iter, mem, ops::Range};
 use crossbeam_queue::SegQueue;
+use redshirt_process_management_interface::ffi::ExitStatus;
 use redshirt_syscalls::{EncodedMessage, Pid, ThreadId};
 
 mod calls;
@@ -47,17 +48,34 @@ pub struct ProcessesCollectionExtrinsics<TPud, TTud, TExt: Extrinsics> {
     /// have to process the external extrinsics for this thread.
     ///
     /// The threads here must always be in the [`LocalThreadState::OtherExtrinsicApplyAction`]
-    /// state.
+    /// or [`LocalThreadState::EmitMessagesBatch`] state.
     local_run_queue: SegQueue<ThreadId>,
+
+    /// List of processes that have been [aborted](ProcessesCollectionExtrinsicsProc::abort), that
+    /// have called `exit`, or that have exceeded one of their limits, and that must be killed
+    /// once they are no longer locked. Might contain processes that no longer exist.
+    processes_to_kill: SegQueue<(Pid, KillReason)>,
+
+    /// Fuel passed to [`ProcessesCollection::run`](processes::ProcessesCollection::run). See
+    /// [`ProcessesCollectionExtrinsicsBuilder::with_fuel_per_run`].
+    fuel_per_run: u64,
     // TODO: implement
     /*/// List of processes that have died but that we haven't reported yet to the outside because
     /// they are locked.
     dead_processes: ,*/
 }
 
+/// Process that has been instantiated with [`ProcessesCollectionExtrinsics::prepare`], but
+/// that isn't running yet.
+pub struct PreparedProcess<TTud, TExt: Extrinsics> {
+    inner: processes::PreparedProcess<LocalThreadUserData<TTud, TExt::Context>>,
+}
+
 /// Prototype for a `ProcessesCollectionExtrinsics` under construction.
 pub struct ProcessesCollectionExtrinsicsBuilder<TExt: Extrinsics> {
     inner: processes::ProcessesCollectionBuilder<Extrinsic<TExt::ExtrinsicId>>,
+    /// See the corresponding field in `ProcessesCollectionExtrinsics`.
+    fuel_per_run: u64,
 }
 
 /// Access to a process within the collection.
@@ -103,6 +121,34 @@ pub struct ProcessesCollectionExtrinsicsThreadWaitNotification<'a, TPud, TTud, T
     thread_user_data: Option<TTud>,
 }
 
+/// Access to a thread that has called `delegate_capability`.
+///
+/// The thread is paused until either [`accept`](Self::accept) or [`refuse`](Self::refuse) is
+/// called.
+pub struct ProcessesCollectionExtrinsicsThreadDelegateCapability<'a, TPud, TTud, TExt: Extrinsics> {
+    parent: &'a ProcessesCollectionExtrinsics<TPud, TTud, TExt>,
+    tid: ThreadId,
+    pid: Pid,
+    /// Process that must receive the capability.
+    target: Pid,
+    /// Interface that the capability applies to.
+    interface: InterfaceHash,
+}
+
+/// Access to a thread that has called `emit_answer_chunk`.
+///
+/// The thread is paused until either [`accept`](Self::accept) or [`refuse`](Self::refuse) is
+/// called.
+pub struct ProcessesCollectionExtrinsicsThreadEmitAnswerChunk<'a, TPud, TTud, TExt: Extrinsics> {
+    parent: &'a ProcessesCollectionExtrinsics<TPud, TTud, TExt>,
+    tid: ThreadId,
+    pid: Pid,
+    /// Message to answer.
+    message_id: MessageId,
+    /// The answer itself.
+    response: EncodedMessage,
+}
+
 /// Common trait amongst all the thread accessor structs.
 pub trait ProcessesCollectionExtrinsicsThreadAccess<'a> {
     type ProcessUserData;
@@ -137,14 +183,42 @@ pub enum ThreadByIdErr {
     AlreadyLocked,
 }
 
+/// Returns true if the given trap has been caused by a process exceeding one of its limits.
+fn is_limit_exceeded(trap: &TrapError) -> bool {
+    match trap.kind() {
+        TrapKind::LimitExceeded(_) => true,
+        _ => false,
+    }
+}
+
+/// Reason why a process in [`ProcessesCollectionExtrinsics::processes_to_kill`] must be killed.
+#[derive(Debug)]
+enum KillReason {
+    /// The process has been [aborted](ProcessesCollectionExtrinsicsProc::abort).
+    Aborted,
+    /// The process has called `exit` with the given code.
+    Exited(u32),
+    /// The process has exceeded one of its limits.
+    LimitExceeded(processes::LimitExceeded),
+}
+
+/// Default value for [`ProcessesCollectionExtrinsicsBuilder::with_fuel_per_run`].
+const DEFAULT_FUEL_PER_RUN: u64 = 1_000_000;
+
 /// Possible function available to processes.
 #[derive(Debug, Clone, PartialEq, Eq)]
 enum Extrinsic<TExtId> {
     NextMessage,
     EmitMessage,
+    EmitMessagesBatch,
     EmitMessageError,
     EmitAnswer,
+    EmitAnswerChunk,
+    CloseAnswer,
     CancelMessage,
+    Yield,
+    Exit,
+    DelegateCapability,
     Other(TExtId),
 }
 
@@ -213,6 +287,11 @@ enum LocalThreadState<TExtCtxt> {
     /// The thread called `emit_message` and wants to emit a message on an interface.
     EmitMessage(calls::EmitMessage),
 
+    /// The thread called `emit_messages_batch` and wants to emit the first message of the
+    /// batch. Once it is emitted, the thread is pushed to the local run queue in order to emit
+    /// the next one.
+    EmitMessagesBatch(calls::EmitMessagesBatch),
+
     /// Temporary state while we move things around. If encountered unexpectedly, that indicates
     /// a bug in the code.
     Poisoned,
@@ -236,8 +315,16 @@ pub enum RunOneOutcome<'a, TPud, TTud, TExt: Extrinsics> {
         /// These threads no longer exist.
         dead_threads: Vec<(ThreadId, TTud)>,
 
+        /// Values of the globals of the process at the time it finished, in index order.
+        globals: Vec<crate::WasmValue>,
+
         /// Value returned by the main thread that has finished, or error that happened.
-        outcome: Result<Option<crate::WasmValue>, wasmi::Trap>,
+        ///
+        /// If the process has called `exit`, this is `Ok(None)`.
+        outcome: Result<Option<crate::WasmValue>, TrapError>,
+
+        /// How the process has ended.
+        exit_status: ExitStatus,
     },
 
     /// A thread in a process has finished.
@@ -278,6 +365,22 @@ pub enum RunOneOutcome<'a, TPud, TTud, TExt: Extrinsics> {
         response: EncodedMessage,
     },
 
+    /// A thread in a process wants to send one of the answers to a message, without preventing
+    /// further answers.
+    ThreadEmitAnswerChunk(ProcessesCollectionExtrinsicsThreadEmitAnswerChunk<'a, TPud, TTud, TExt>),
+
+    /// A thread in a process indicates that it won't send any more answer to a message.
+    ThreadCloseAnswer {
+        /// Thread that wants to close the answers.
+        thread_id: ThreadId,
+
+        /// Process that the thread belongs to.
+        process: ProcessesCollectionExtrinsicsProc<'a, TPud, TTud, TExt>,
+
+        /// Message whose answers are closed.
+        message_id: MessageId,
+    },
+
     /// A thread in a process wants to notify that a message is erroneous.
     ThreadEmitMessageError {
         /// Thread that wants to emit a message error.
@@ -302,6 +405,17 @@ pub enum RunOneOutcome<'a, TPud, TTud, TExt: Extrinsics> {
         message_id: MessageId,
     },
 
+    /// A thread in a process wants to hand over to another process its right to emit messages
+    /// on an interface.
+    ThreadDelegateCapability(
+        ProcessesCollectionExtrinsicsThreadDelegateCapability<'a, TPud, TTud, TExt>,
+    ),
+
+    /// A thread has run out of fuel and has been put back in the queue. This gives the caller
+    /// the chance to do something else before calling `run` again, so that a thread that never
+    /// stops can't prevent the rest of the system from running.
+    Preempted,
+
     /// No thread is ready to run. Nothing was done.
     Idle,
 }
@@ -319,30 +433,168 @@ where
     ///
     /// A single main thread (whose user data is passed by parameter) is automatically created and
     /// is paused at the start of the "_start" function of the module.
+    ///
+    /// If `parent` is `Some`, the new process is a child of the given process. The process is
+    /// stopped if it exceeds the limits of `config`.
     pub fn execute(
         &self,
         module: &Module,
+        parent: Option<Pid>,
+        config: processes::ProcessConfig,
         proc_user_data: TPud,
         main_thread_user_data: TTud,
     ) -> Result<ProcessesCollectionExtrinsicsProc<TPud, TTud, TExt>, vm::NewErr> {
-        let proc_user_data = Arc::new(LocalProcessUserData {
-            external_user_data: proc_user_data,
-            extrinsics: Default::default(),
-        });
+        let prepared = self.prepare(module, main_thread_user_data)?;
+        Ok(self.execute_prepared(prepared, parent, config, proc_user_data))
+    }
+
+    /// Same as [`ProcessesCollectionExtrinsics::execute`], but the process can only import the
+    /// extrinsics that are in the given allowlist.
+    ///
+    /// The syscalls of the `redshirt` interface are always allowed, as no program could work
+    /// without them.
+    pub fn execute_with_allowlist(
+        &self,
+        module: &Module,
+        allowlist: &processes::ExtrinsicsAllowlist,
+        parent: Option<Pid>,
+        config: processes::ProcessConfig,
+        proc_user_data: TPud,
+        main_thread_user_data: TTud,
+    ) -> Result<ProcessesCollectionExtrinsicsProc<TPud, TTud, TExt>, vm::NewErr> {
+        let prepared = self.prepare_with_allowlist(module, allowlist, main_thread_user_data)?;
+        Ok(self.execute_prepared(prepared, parent, config, proc_user_data))
+    }
+
+    /// Checks whether the imports of the given module can be resolved.
+    pub fn abi_report(&self, module: &Module) -> AbiReport {
+        self.inner.borrow().abi_report(module)
+    }
+
+    /// Same as [`ProcessesCollectionExtrinsics::abi_report`], but imports that aren't in the
+    /// allowlist are reported as unknown.
+    pub fn abi_report_with_allowlist(
+        &self,
+        module: &Module,
+        allowlist: &processes::ExtrinsicsAllowlist,
+    ) -> AbiReport {
+        let allowlist = allowlist.clone().with_interface("redshirt");
+        self.inner
+            .borrow()
+            .abi_report_with_allowlist(module, &allowlist)
+    }
+
+    /// Instantiates a process from the given module, without starting it.
+    ///
+    /// Pass the result to [`ProcessesCollectionExtrinsics::execute_prepared`] in order to start
+    /// the process.
+    pub fn prepare(
+        &self,
+        module: &Module,
+        main_thread_user_data: TTud,
+    ) -> Result<PreparedProcess<TTud, TExt>, vm::NewErr> {
         let main_thread_user_data = LocalThreadUserData {
             state: LocalThreadState::ReadyToRun,
             external_user_data: Some(main_thread_user_data),
         };
+        let inner = self
+            .inner
+            .borrow_mut()
+            .prepare(module, main_thread_user_data)?;
+        Ok(PreparedProcess { inner })
+    }
+
+    /// Same as [`ProcessesCollectionExtrinsics::prepare`], but the process can only import the
+    /// extrinsics that are in the given allowlist, in addition to the syscalls of the `redshirt`
+    /// interface.
+    pub fn prepare_with_allowlist(
+        &self,
+        module: &Module,
+        allowlist: &processes::ExtrinsicsAllowlist,
+        main_thread_user_data: TTud,
+    ) -> Result<PreparedProcess<TTud, TExt>, vm::NewErr> {
+        let allowlist = allowlist.clone().with_interface("redshirt");
+        let main_thread_user_data = LocalThreadUserData {
+            state: LocalThreadState::ReadyToRun,
+            external_user_data: Some(main_thread_user_data),
+        };
+        let inner = self.inner.borrow_mut().prepare_with_allowlist(
+            module,
+            &allowlist,
+            main_thread_user_data,
+        )?;
+        Ok(PreparedProcess { inner })
+    }
+
+    /// Same as [`ProcessesCollectionExtrinsics::prepare`], but the process is additionally
+    /// composed of the given library modules.
+    /// See [`ProcessStateMachine::new_linked`](vm::ProcessStateMachine::new_linked).
+    pub fn prepare_linked(
+        &self,
+        module: &Module,
+        libraries: &[(&str, &Module)],
+        main_thread_user_data: TTud,
+    ) -> Result<PreparedProcess<TTud, TExt>, vm::NewErr> {
+        let main_thread_user_data = LocalThreadUserData {
+            state: LocalThreadState::ReadyToRun,
+            external_user_data: Some(main_thread_user_data),
+        };
+        let inner =
+            self.inner
+                .borrow_mut()
+                .prepare_linked(module, libraries, main_thread_user_data)?;
+        Ok(PreparedProcess { inner })
+    }
+
+    /// Checks the imports of the given module, and returns a [`processes::CachedModule`] that
+    /// can later be passed to [`ProcessesCollectionExtrinsics::prepare_cached`] any number of
+    /// times.
+    pub fn cache_module(&self, module: Module) -> Result<processes::CachedModule, vm::NewErr> {
+        self.inner.borrow().cache_module(module)
+    }
+
+    /// Same as [`ProcessesCollectionExtrinsics::prepare`], but for a module that has been cached
+    /// with [`ProcessesCollectionExtrinsics::cache_module`].
+    pub fn prepare_cached(
+        &self,
+        cached: &processes::CachedModule,
+        main_thread_user_data: TTud,
+    ) -> Result<PreparedProcess<TTud, TExt>, vm::NewErr> {
+        let main_thread_user_data = LocalThreadUserData {
+            state: LocalThreadState::ReadyToRun,
+            external_user_data: Some(main_thread_user_data),
+        };
+        let inner = self
+            .inner
+            .borrow_mut()
+            .prepare_cached(cached, main_thread_user_data)?;
+        Ok(PreparedProcess { inner })
+    }
+
+    /// Starts a process that has been created with [`ProcessesCollectionExtrinsics::prepare`].
+    ///
+    /// See [`ProcessesCollectionExtrinsics::execute`] for the meaning of `parent` and `config`.
+    pub fn execute_prepared(
+        &self,
+        prepared: PreparedProcess<TTud, TExt>,
+        parent: Option<Pid>,
+        config: processes::ProcessConfig,
+        proc_user_data: TPud,
+    ) -> ProcessesCollectionExtrinsicsProc<TPud, TTud, TExt> {
+        let proc_user_data = Arc::new(LocalProcessUserData {
+            external_user_data: proc_user_data,
+            extrinsics: Default::default(),
+        });
         let pid = self
             .inner
             .borrow_mut()
-            .execute(module, proc_user_data.clone(), main_thread_user_data)?
+            .execute_prepared(prepared.inner, parent, config, proc_user_data.clone())
             .pid();
-        Ok(ProcessesCollectionExtrinsicsProc {
+        ProcessesCollectionExtrinsicsProc {
             parent: self,
             pid,
             user_data: proc_user_data,
-        })
+        }
     }
 
     /// Runs one thread amongst the collection.
@@ -361,6 +613,55 @@ where
     fn run_once(&self) -> Option<RunOneOutcome<TPud, TTud, TExt>> {
         let mut inner = self.inner.borrow_mut();
 
+        // Kill the processes that have been aborted or that have exited. The ones that are still
+        // locked are put back in the list and tried again later.
+        for _ in 0..self.processes_to_kill.len() {
+            let (pid, reason) = match self.processes_to_kill.pop() {
+                Ok(p) => p,
+                Err(_) => break,
+            };
+
+            let is_locked = match inner.process_by_id(pid) {
+                Some(process) => Arc::strong_count(process.user_data()) != 1,
+                None => continue,
+            };
+            if is_locked {
+                self.processes_to_kill.push((pid, reason));
+                continue;
+            }
+
+            let killed = match inner.kill_process(pid) {
+                Some(k) => k,
+                None => unreachable!(),
+            };
+            for child in killed.children_to_kill {
+                self.processes_to_kill.push((child, KillReason::Aborted));
+            }
+            let (outcome, exit_status) = match reason {
+                KillReason::Aborted => (Err(TrapKind::Aborted.into()), ExitStatus::Killed),
+                KillReason::Exited(code) => (Ok(None), ExitStatus::Exited(code)),
+                KillReason::LimitExceeded(limit) => (
+                    Err(TrapKind::LimitExceeded(limit).into()),
+                    ExitStatus::LimitExceeded,
+                ),
+            };
+            return Some(RunOneOutcome::ProcessFinished {
+                pid,
+                user_data: match Arc::try_unwrap(killed.user_data) {
+                    Ok(ud) => ud.external_user_data,
+                    Err(_) => unreachable!(),
+                },
+                dead_threads: killed
+                    .dead_threads
+                    .into_iter()
+                    .map(|(id, state)| (id, state.external_user_data.unwrap()))
+                    .collect(),
+                globals: killed.globals,
+                outcome,
+                exit_status,
+            });
+        }
+
         while let Ok(tid) = self.local_run_queue.pop() {
             // It is possible that the thread no longer exists, for example if the process crashed.
             let mut thread = inner.thread_by_id(tid)?;
@@ -395,17 +696,37 @@ where
                         ));
                     }
                 },
+                LocalThreadState::EmitMessagesBatch(batch) => {
+                    debug_assert!(!batch.messages.is_empty());
+                    thread.user_data().state = LocalThreadState::EmitMessagesBatch(batch);
+                    let process_user_data = thread.process_user_data().clone();
+                    let thread_user_data = thread.user_data().external_user_data.take().unwrap();
+                    return Some(RunOneOutcome::ThreadEmitMessage(
+                        ProcessesCollectionExtrinsicsThreadEmitMessage {
+                            parent: self,
+                            tid: thread.tid(),
+                            process_user_data,
+                            thread_user_data: Some(thread_user_data),
+                        },
+                    ));
+                }
                 _ => unreachable!(),
             }
         }
 
-        match inner.run() {
+        match inner.run(self.fuel_per_run) {
             processes::RunOneOutcome::ProcessFinished {
                 pid,
                 user_data,
                 dead_threads,
+                globals,
+                children_to_kill,
                 outcome,
             } => {
+                for child in children_to_kill {
+                    self.processes_to_kill.push((child, KillReason::Aborted));
+                }
+
                 // If the process isn't locked, we immediately report that the process has
                 // finished.
                 if Arc::strong_count(&user_data) == 1 {
@@ -419,6 +740,15 @@ where
                             .into_iter()
                             .map(|(id, state)| (id, state.external_user_data.unwrap()))
                             .collect(), // TODO: meh for allocation
+                        globals,
+                        exit_status: match &outcome {
+                            Ok(Some(crate::WasmValue::I32(code))) => {
+                                ExitStatus::Exited(*code as u32)
+                            }
+                            Ok(_) => ExitStatus::Exited(0),
+                            Err(trap) if is_limit_exceeded(trap) => ExitStatus::LimitExceeded,
+                            Err(_) => ExitStatus::Crashed,
+                        },
                         outcome,
                     });
                 }
@@ -446,6 +776,21 @@ where
             }
             processes::RunOneOutcome::Idle => Some(RunOneOutcome::Idle),
 
+            // The thread is put back in the queue, which gives the other threads the chance to
+            // run, and we return to the caller.
+            processes::RunOneOutcome::OutOfFuel { mut thread } => {
+                debug_assert!(thread.user_data().state.is_ready_to_run());
+                thread.resume(None);
+                Some(RunOneOutcome::Preempted)
+            }
+
+            // Breakpoints can't be set through this layer. The thread is simply resumed.
+            processes::RunOneOutcome::Breakpoint { mut thread, .. } => {
+                debug_assert!(thread.user_data().state.is_ready_to_run());
+                thread.resume(None);
+                None
+            }
+
             processes::RunOneOutcome::Interrupted {
                 mut thread,
                 id: Extrinsic::NextMessage,
@@ -492,6 +837,39 @@ where
                 ))
             }
 
+            processes::RunOneOutcome::Interrupted {
+                mut thread,
+                id: Extrinsic::EmitMessagesBatch,
+                params,
+            } => {
+                debug_assert!(thread.user_data().state.is_ready_to_run());
+                let batch = match calls::parse_extrinsic_emit_messages_batch(&mut thread, params) {
+                    Ok(b) => b,
+                    Err(_) => {
+                        // The thread is never resumed, and the process is killed at the next
+                        // call to `run_once`.
+                        self.processes_to_kill
+                            .push((thread.pid(), KillReason::Aborted));
+                        return None;
+                    }
+                };
+                if batch.messages.is_empty() {
+                    thread.resume(Some(crate::WasmValue::I32(0)));
+                    return None;
+                }
+                thread.user_data().state = LocalThreadState::EmitMessagesBatch(batch);
+                let process_user_data = thread.process_user_data().clone();
+                let thread_user_data = thread.user_data().external_user_data.take().unwrap();
+                Some(RunOneOutcome::ThreadEmitMessage(
+                    ProcessesCollectionExtrinsicsThreadEmitMessage {
+                        parent: self,
+                        tid: thread.tid(),
+                        process_user_data,
+                        thread_user_data: Some(thread_user_data),
+                    },
+                ))
+            }
+
             processes::RunOneOutcome::Interrupted {
                 mut thread,
                 id: Extrinsic::EmitAnswer,
@@ -519,6 +897,64 @@ where
                 })
             }
 
+            processes::RunOneOutcome::Interrupted {
+                mut thread,
+                id: Extrinsic::EmitAnswerChunk,
+                params,
+            } => {
+                debug_assert!(thread.user_data().state.is_ready_to_run());
+                debug_assert!(thread.user_data().external_user_data.is_some());
+                // The parameters are the same as the ones of `emit_answer`.
+                match calls::parse_extrinsic_emit_answer(&mut thread, params) {
+                    Ok(emit_resp) => Some(RunOneOutcome::ThreadEmitAnswerChunk(
+                        ProcessesCollectionExtrinsicsThreadEmitAnswerChunk {
+                            parent: self,
+                            tid: thread.tid(),
+                            pid: thread.pid(),
+                            message_id: emit_resp.message_id,
+                            response: emit_resp.response,
+                        },
+                    )),
+                    Err(_) => {
+                        self.processes_to_kill
+                            .push((thread.pid(), KillReason::Aborted));
+                        None
+                    }
+                }
+            }
+
+            processes::RunOneOutcome::Interrupted {
+                mut thread,
+                id: Extrinsic::CloseAnswer,
+                params,
+            } => {
+                debug_assert!(thread.user_data().state.is_ready_to_run());
+                debug_assert!(thread.user_data().external_user_data.is_some());
+                // The parameters are the same as the ones of `emit_message_error`.
+                let message_id =
+                    match calls::parse_extrinsic_emit_message_error(&mut thread, params) {
+                        Ok(m) => m,
+                        Err(_) => {
+                            self.processes_to_kill
+                                .push((thread.pid(), KillReason::Aborted));
+                            return None;
+                        }
+                    };
+                thread.resume(None);
+                let pid = thread.pid();
+                let thread_id = thread.tid();
+                let proc_user_data = inner.process_by_id(pid).unwrap().user_data().clone();
+                Some(RunOneOutcome::ThreadCloseAnswer {
+                    process: ProcessesCollectionExtrinsicsProc {
+                        parent: self,
+                        pid,
+                        user_data: proc_user_data,
+                    },
+                    thread_id,
+                    message_id,
+                })
+            }
+
             processes::RunOneOutcome::Interrupted {
                 mut thread,
                 id: Extrinsic::EmitMessageError,
@@ -572,6 +1008,60 @@ where
                 })
             }
 
+            processes::RunOneOutcome::Interrupted {
+                mut thread,
+                id: Extrinsic::Yield,
+                ..
+            } => {
+                debug_assert!(thread.user_data().state.is_ready_to_run());
+                thread.resume(None);
+                thread.defer();
+                None
+            }
+
+            // The thread is never resumed. The process is killed at the next call to `run_once`,
+            // or later if it is locked.
+            processes::RunOneOutcome::Interrupted {
+                mut thread,
+                id: Extrinsic::Exit,
+                params,
+            } => {
+                debug_assert!(thread.user_data().state.is_ready_to_run());
+                // The signature of the function is checked by the VM.
+                assert_eq!(params.len(), 1);
+                // Wasm has no unsigned integers. The code is reinterpreted as a `u32`.
+                let code = match params[0].into_i32() {
+                    Some(c) => c as u32,
+                    None => unreachable!(),
+                };
+                self.processes_to_kill
+                    .push((thread.pid(), KillReason::Exited(code)));
+                None
+            }
+
+            processes::RunOneOutcome::Interrupted {
+                mut thread,
+                id: Extrinsic::DelegateCapability,
+                params,
+            } => {
+                debug_assert!(thread.user_data().state.is_ready_to_run());
+                match calls::parse_extrinsic_delegate_capability(&mut thread, params) {
+                    Ok((target, interface)) => Some(RunOneOutcome::ThreadDelegateCapability(
+                        ProcessesCollectionExtrinsicsThreadDelegateCapability {
+                            parent: self,
+                            tid: thread.tid(),
+                            pid: thread.pid(),
+                            target,
+                            interface,
+                        },
+                    )),
+                    Err(_) => {
+                        thread.resume(Some(crate::WasmValue::I32(1)));
+                        None
+                    }
+                }
+            }
+
             processes::RunOneOutcome::Interrupted {
                 ref mut thread,
                 id: Extrinsic::Other(ext_id),
@@ -629,7 +1119,36 @@ where
         id: ThreadId,
     ) -> Result<ProcessesCollectionExtrinsicsThread<TPud, TTud, TExt>, ThreadByIdErr> {
         let mut inner = self.inner.borrow_mut();
-        let mut inner = inner.thread_by_id(id).ok_or(ThreadByIdErr::RunningOrDead)?;
+        let inner = inner.thread_by_id(id).ok_or(ThreadByIdErr::RunningOrDead)?;
+        self.lock_interrupted_thread(inner)
+    }
+
+    /// Same as [`ProcessesCollectionExtrinsics::interrupted_thread_by_id`], but only looks for
+    /// the thread among the threads of the given process.
+    fn interrupted_thread_of_process(
+        &self,
+        pid: Pid,
+        id: ThreadId,
+    ) -> Result<ProcessesCollectionExtrinsicsThread<'_, TPud, TTud, TExt>, ThreadByIdErr> {
+        let mut inner = self.inner.borrow_mut();
+        let inner = inner
+            .process_by_id(pid)
+            .and_then(|p| p.thread_by_id(id))
+            .ok_or(ThreadByIdErr::RunningOrDead)?;
+        self.lock_interrupted_thread(inner)
+    }
+
+    /// Common implementation of [`ProcessesCollectionExtrinsics::interrupted_thread_by_id`] and
+    /// [`ProcessesCollectionExtrinsics::interrupted_thread_of_process`], once the thread has been
+    /// found.
+    fn lock_interrupted_thread(
+        &self,
+        mut inner: processes::ProcessesCollectionThread<
+            Arc<LocalProcessUserData<TPud, TExt>>,
+            LocalThreadUserData<TTud, TExt::Context>,
+        >,
+    ) -> Result<ProcessesCollectionExtrinsicsThread<'_, TPud, TTud, TExt>, ThreadByIdErr> {
+        let id = inner.tid();
 
         // Checking thread locked state.
         if inner.user_data().external_user_data.is_none() {
@@ -641,7 +1160,9 @@ where
                 debug_assert!(inner.user_data().external_user_data.is_some());
                 Err(ThreadByIdErr::RunningOrDead)
             }
-            LocalThreadState::EmitMessage(_) | LocalThreadState::OtherExtrinsicEmit { .. } => {
+            LocalThreadState::EmitMessage(_)
+            | LocalThreadState::EmitMessagesBatch(_)
+            | LocalThreadState::OtherExtrinsicEmit { .. } => {
                 let process_user_data = inner.process_user_data().clone();
                 let thread_user_data = inner.user_data().external_user_data.take().unwrap();
 
@@ -668,6 +1189,57 @@ where
             LocalThreadState::Poisoned => panic!(),
         }
     }
+
+    /// Returns the list of all the [`Pid`]s of the processes in the collection.
+    pub fn pids(&self) -> Vec<Pid> {
+        self.inner.borrow().pids().collect()
+    }
+
+    /// Returns true if there is a process with the given [`Pid`]. See
+    /// [`ProcessesCollection::is_process_alive`](processes::ProcessesCollection::is_process_alive).
+    pub fn is_process_alive(&self, pid: Pid) -> bool {
+        self.inner.borrow().is_process_alive(pid)
+    }
+
+    /// Returns the resources used by all the processes of the collection.
+    pub fn resource_usage(&self) -> processes::ResourceUsage {
+        self.inner.borrow().resource_usage()
+    }
+
+    /// Returns the [`ThreadId`]s of all the threads of all the processes, alongside with the
+    /// [`Pid`] of the process they belong to.
+    pub fn thread_ids(&self) -> Vec<(Pid, ThreadId)> {
+        self.inner.borrow().thread_ids().collect()
+    }
+
+    /// Checks the internal consistency of the collection, and returns the problems found.
+    pub fn self_check(&self) -> Vec<Violation> {
+        let mut inner = self.inner.borrow_mut();
+        let mut violations = inner.self_check();
+
+        // Every thread in `local_run_queue` must exist and be in the appropriate state. We empty
+        // the queue and then refill it in order to preserve the order of its elements.
+        let mut run_queue = Vec::new();
+        while let Ok(tid) = self.local_run_queue.pop() {
+            run_queue.push(tid);
+        }
+        for tid in run_queue {
+            let is_valid = match inner.thread_by_id(tid) {
+                Some(mut thread) => match thread.user_data().state {
+                    LocalThreadState::OtherExtrinsicApplyAction { .. }
+                    | LocalThreadState::EmitMessagesBatch(_) => true,
+                    _ => false,
+                },
+                None => false,
+            };
+            if !is_valid {
+                violations.push(Violation::InvalidRunQueueEntry { thread_id: tid });
+            }
+            self.local_run_queue.push(tid);
+        }
+
+        violations
+    }
 }
 
 impl<TExt> Default for ProcessesCollectionExtrinsicsBuilder<TExt>
@@ -688,6 +1260,12 @@ where
                 sig!((I32, I32, I32, I32, I32, I32) -> I32),
                 Extrinsic::EmitMessage,
             )
+            .with_extrinsic(
+                "redshirt",
+                "emit_messages_batch",
+                sig!((I32, I32, I32) -> I32),
+                Extrinsic::EmitMessagesBatch,
+            )
             .with_extrinsic(
                 "redshirt",
                 "emit_message_error",
@@ -700,11 +1278,31 @@ where
                 sig!((I32, I32, I32)),
                 Extrinsic::EmitAnswer,
             )
+            .with_extrinsic(
+                "redshirt",
+                "emit_answer_chunk",
+                sig!((I32, I32, I32) -> I32),
+                Extrinsic::EmitAnswerChunk,
+            )
+            .with_extrinsic(
+                "redshirt",
+                "close_answer",
+                sig!((I32)),
+                Extrinsic::CloseAnswer,
+            )
             .with_extrinsic(
                 "redshirt",
                 "cancel_message",
                 sig!((I32)),
                 Extrinsic::CancelMessage,
+            )
+            .with_extrinsic("redshirt", "yield_now", sig!(()), Extrinsic::Yield)
+            .with_extrinsic("redshirt", "exit", sig!((I32)), Extrinsic::Exit)
+            .with_extrinsic(
+                "redshirt",
+                "delegate_capability",
+                sig!((I64, I32) -> I32),
+                Extrinsic::DelegateCapability,
             );
 
         for supported in TExt::supported_extrinsics() {
@@ -716,7 +1314,10 @@ where
             );
         }
 
-        ProcessesCollectionExtrinsicsBuilder { inner }
+        ProcessesCollectionExtrinsicsBuilder {
+            inner,
+            fuel_per_run: DEFAULT_FUEL_PER_RUN,
+        }
     }
 }
 
@@ -731,11 +1332,32 @@ impl<TExt: Extrinsics> ProcessesCollectionExtrinsicsBuilder<TExt> {
         self.inner.reserve_pid()
     }
 
+    /// Sets the limits that modules must respect in order to be instantiated.
+    pub fn with_module_limits(mut self, limits: vm::ModuleLimits) -> Self {
+        self.inner = self.inner.with_module_limits(limits);
+        self
+    }
+
+    /// Sets what happens to the children of a process when it ends.
+    pub fn with_orphan_policy(mut self, policy: processes::OrphanPolicy) -> Self {
+        self.inner = self.inner.with_orphan_policy(policy);
+        self
+    }
+
+    /// Sets the approximate number of instructions that a thread can execute without calling
+    /// any extrinsic before it is paused in order to let the other threads run.
+    pub fn with_fuel_per_run(mut self, fuel: u64) -> Self {
+        self.fuel_per_run = fuel;
+        self
+    }
+
     /// Turns the builder into a [`ProcessesCollectionExtrinsics`].
     pub fn build<TPud, TTud>(self) -> ProcessesCollectionExtrinsics<TPud, TTud, TExt> {
         ProcessesCollectionExtrinsics {
             inner: RefCell::new(self.inner.build()),
             local_run_queue: SegQueue::new(),
+            processes_to_kill: SegQueue::new(),
+            fuel_per_run: self.fuel_per_run,
         }
     }
 }
@@ -750,13 +1372,32 @@ where
         self.pid
     }
 
+    /// Returns the parent of the process, if any.
+    pub fn parent(&self) -> Option<Pid> {
+        self.parent.inner.borrow().parent(self.pid)
+    }
+
+    /// Returns the processes whose parent is this process.
+    pub fn children(&self) -> Vec<Pid> {
+        self.parent.inner.borrow().children(self.pid).collect()
+    }
+
     /// Returns the user data that is associated to the process.
     pub fn user_data(&self) -> &TPud {
         &self.user_data.external_user_data
     }
 
+    /// Sets the priority of the process. See [`ProcessesCollectionProc::set_priority`].
+    ///
+    /// [`ProcessesCollectionProc::set_priority`]: processes::ProcessesCollectionProc::set_priority
+    pub fn set_priority(&self, priority: u32) {
+        let mut inner = self.parent.inner.borrow_mut();
+        let mut inner = inner.process_by_id(self.pid).unwrap();
+        inner.set_priority(priority);
+    }
+
     /// Adds a new thread to the process, starting the function with the given index and passing
-    /// the given parameters.
+    /// the given parameters. The name, if any, is used for diagnostic purposes.
     ///
     /// > **Note**: The "function ID" is the index of the function in the WASM module. WASM
     /// >           doesn't have function pointers. Instead, all the functions are part of a single
@@ -766,6 +1407,7 @@ where
         &self,
         fn_index: u32,
         params: Vec<crate::WasmValue>,
+        name: Option<String>,
         user_data: TTud,
     ) -> Result<(), vm::StartErr> {
         let mut inner = self.parent.inner.borrow_mut();
@@ -774,6 +1416,7 @@ where
         inner.start_thread(
             fn_index,
             params,
+            name,
             LocalThreadUserData {
                 state: LocalThreadState::ReadyToRun,
                 external_user_data: Some(user_data),
@@ -783,26 +1426,42 @@ where
         Ok(())
     }
 
+    /// Returns the size of the memory of the process, in bytes.
+    pub fn memory_size(&self) -> u64 {
+        let mut inner = self.parent.inner.borrow_mut();
+        inner.process_by_id(self.pid).unwrap().memory_size()
+    }
+
+    /// Returns the number of threads of the process, including the main thread.
+    pub fn num_threads(&self) -> usize {
+        let mut inner = self.parent.inner.borrow_mut();
+        inner.process_by_id(self.pid).unwrap().num_threads()
+    }
+
+    /// Captures a snapshot of the memory of the process.
+    pub fn memory_snapshot(&self) -> MemorySnapshot {
+        let mut inner = self.parent.inner.borrow_mut();
+        let mut inner = inner.process_by_id(self.pid).unwrap();
+        let memory_size = inner.memory_size();
+        MemorySnapshot::capture(memory_size, |offset, size| inner.read_memory(offset, size))
+    }
+
     /// Returns a list of all threads that are in an interrupted state.
     // TODO: what about the threads that are interrupted by already locked?
     // TODO: implement better
     pub fn interrupted_threads(
         &self,
     ) -> impl Iterator<Item = ProcessesCollectionExtrinsicsThread<'a, TPud, TTud, TExt>> {
-        let mut inner = self.parent.inner.borrow_mut();
-        let inner = inner.process_by_id(self.pid).unwrap();
-
-        let mut out = Vec::new();
-
-        let mut thread = Some(inner.main_thread());
-        while let Some(mut thread_inner) = thread.take() {
-            out.push(thread_inner.tid());
-            thread = thread_inner.next_thread();
-        }
+        let thread_ids = {
+            let mut inner = self.parent.inner.borrow_mut();
+            let inner = inner.process_by_id(self.pid).unwrap();
+            inner.thread_ids().collect::<Vec<_>>()
+        };
 
         let parent = self.parent;
-        out.into_iter().filter_map(move |tid| {
-            match parent.interrupted_thread_by_id(tid) {
+        let pid = self.pid;
+        thread_ids.into_iter().filter_map(move |tid| {
+            match parent.interrupted_thread_of_process(pid, tid) {
                 Ok(t) => Some(t),
                 Err(ThreadByIdErr::AlreadyLocked) => unimplemented!(), // TODO: what to do here?
                 Err(ThreadByIdErr::RunningOrDead) => None,
@@ -812,12 +1471,25 @@ where
 
     /// Marks the process as aborting.
     ///
-    /// The termination will happen after all locks to this process have been released.
+    /// The termination will happen after all locks to this process have been released. It is
+    /// then reported as a [`RunOneOutcome::ProcessFinished`] whose outcome is an error.
     ///
     /// Calling [`abort`](ProcessesCollectionExtrinsicsProc::abort) a second time or more has no
     /// effect.
     pub fn abort(&self) {
-        unimplemented!() // TODO:
+        self.parent
+            .processes_to_kill
+            .push((self.pid, KillReason::Aborted));
+    }
+
+    /// Marks the process as having exceeded one of its limits.
+    ///
+    /// Similar to [`abort`](ProcessesCollectionExtrinsicsProc::abort), except that the process is
+    /// reported with an [`ExitStatus::LimitExceeded`], and its outcome is the given error.
+    pub fn exceed_limit(&self, limit: processes::LimitExceeded) {
+        self.parent
+            .processes_to_kill
+            .push((self.pid, KillReason::LimitExceeded(limit)));
     }
 }
 
@@ -912,6 +1584,9 @@ impl<'a, TPud, TTud, TExt: Extrinsics>
 
         match inner.user_data().state {
             LocalThreadState::EmitMessage(ref emit) => emit.message_id_write.is_some(),
+            LocalThreadState::EmitMessagesBatch(ref batch) => {
+                batch.messages[0].message_id_write.is_some()
+            }
             LocalThreadState::OtherExtrinsicEmit {
                 response_expected, ..
             } => response_expected,
@@ -927,6 +1602,7 @@ impl<'a, TPud, TTud, TExt: Extrinsics>
         // TODO: cloning :-/
         match inner.user_data().state {
             LocalThreadState::EmitMessage(ref emit) => emit.interface.clone(),
+            LocalThreadState::EmitMessagesBatch(ref batch) => batch.messages[0].interface.clone(),
             LocalThreadState::OtherExtrinsicEmit { ref interface, .. } => interface.clone(),
             _ => unreachable!(),
         }
@@ -939,6 +1615,7 @@ impl<'a, TPud, TTud, TExt: Extrinsics>
 
         match inner.user_data().state {
             LocalThreadState::EmitMessage(ref emit) => emit.allow_delay,
+            LocalThreadState::EmitMessagesBatch(ref batch) => batch.messages[0].allow_delay,
             LocalThreadState::OtherExtrinsicEmit { .. } => true,
             _ => unreachable!(),
         }
@@ -974,6 +1651,33 @@ impl<'a, TPud, TTud, TExt: Extrinsics>
                 inner.resume(Some(crate::WasmValue::I32(0)));
                 emit.message
             }
+            LocalThreadState::EmitMessagesBatch(mut batch) => {
+                let emit = batch.messages.pop_front().unwrap();
+                if let Some(message_id_write) = emit.message_id_write {
+                    let message_id = match message_id {
+                        Some(m) => m,
+                        None => panic!(),
+                    };
+
+                    inner
+                        .write_memory(message_id_write, &u64::from(message_id).to_le_bytes())
+                        .unwrap();
+                } else {
+                    assert!(message_id.is_none());
+                }
+
+                batch.num_emitted += 1;
+                if batch.messages.is_empty() {
+                    let num_emitted = batch.num_emitted;
+                    inner.user_data().state = LocalThreadState::ReadyToRun;
+                    inner.resume(Some(crate::WasmValue::I32(num_emitted as i32)));
+                } else {
+                    inner.user_data().state = LocalThreadState::EmitMessagesBatch(batch);
+                    self.parent.local_run_queue.push(inner.tid());
+                }
+
+                emit.message
+            }
             LocalThreadState::OtherExtrinsicEmit {
                 mut context,
                 message,
@@ -1018,6 +1722,11 @@ impl<'a, TPud, TTud, TExt: Extrinsics>
                 inner.user_data().state = LocalThreadState::ReadyToRun;
                 inner.resume(Some(crate::WasmValue::I32(1)));
             }
+            LocalThreadState::EmitMessagesBatch(batch) => {
+                // The messages that follow the refused one aren't emitted.
+                inner.user_data().state = LocalThreadState::ReadyToRun;
+                inner.resume(Some(crate::WasmValue::I32(batch.num_emitted as i32)));
+            }
             LocalThreadState::OtherExtrinsicEmit { context, .. } => {
                 // TODO: don't know what else to do here than crash the program
                 inner.user_data().state = LocalThreadState::OtherExtrinsicApplyAction {
@@ -1131,6 +1840,14 @@ impl<'a, TPud, TTud, TExt: Extrinsics>
         }
     }
 
+    /// Makes the thread run in priority once it is resumed.
+    ///
+    /// See [`ProcessesCollectionThread::boost`](processes::ProcessesCollectionThread::boost).
+    pub fn boost(&mut self) {
+        let mut inner = self.parent.inner.borrow_mut();
+        inner.thread_by_id(self.tid).unwrap().boost();
+    }
+
     /// Resume the thread, sending back a notification.
     ///
     /// `index` must be the index within the list returned by
@@ -1142,18 +1859,18 @@ impl<'a, TPud, TTud, TExt: Extrinsics>
     /// calling this function.
     /// - Panics if `index` is too large.
     ///
-    pub fn resume_notification(self, index: usize, notif: EncodedMessage) {
+    pub fn resume_notification(self, index: usize, notif: &[u8]) {
         let mut inner = self.parent.inner.borrow_mut();
         let mut inner = inner.thread_by_id(self.tid).unwrap();
 
         match mem::replace(&mut inner.user_data().state, LocalThreadState::Poisoned) {
             LocalThreadState::NotificationWait(wait) => {
                 assert!(index < wait.notifs_ids.len());
-                let notif_size_u32 = u32::try_from(notif.0.len()).unwrap();
+                let notif_size_u32 = u32::try_from(notif.len()).unwrap();
                 assert!(wait.out_size >= notif_size_u32);
 
                 // Write the notification in the process's memory.
-                match inner.write_memory(wait.out_pointer, &notif.0) {
+                match inner.write_memory(wait.out_pointer, notif) {
                     Ok(()) => {}
                     Err(_) => panic!(), // TODO: can legit happen
                 };
@@ -1175,7 +1892,7 @@ impl<'a, TPud, TTud, TExt: Extrinsics>
             LocalThreadState::OtherExtrinsicWait { mut context, .. } => {
                 // TODO: the way this is handled is clearly not great; the API of this method
                 // should be improved
-                let decoded = redshirt_syscalls::ffi::decode_notification(&notif.0).unwrap();
+                let decoded = redshirt_syscalls::ffi::decode_notification(notif).unwrap();
                 let message = match decoded {
                     redshirt_syscalls::ffi::DecodedNotification::Response(response) => {
                         response.actual_data.unwrap()
@@ -1292,6 +2009,111 @@ where
     }
 }
 
+impl<'a, TPud, TTud, TExt: Extrinsics>
+    ProcessesCollectionExtrinsicsThreadDelegateCapability<'a, TPud, TTud, TExt>
+{
+    /// Returns the id of the thread that has called `delegate_capability`.
+    pub fn tid(&self) -> ThreadId {
+        self.tid
+    }
+
+    /// Returns the process that the thread belongs to, in other words the delegator.
+    pub fn pid(&self) -> Pid {
+        self.pid
+    }
+
+    /// Returns the process that must receive the capability.
+    pub fn target(&self) -> Pid {
+        self.target
+    }
+
+    /// Returns the interface that the capability applies to.
+    pub fn interface(&self) -> &InterfaceHash {
+        &self.interface
+    }
+
+    /// Resumes the thread, signalling that the capability has been delegated.
+    pub fn accept(self) {
+        self.resume(0)
+    }
+
+    /// Resumes the thread, signalling an error in the delegation.
+    pub fn refuse(self) {
+        self.resume(1)
+    }
+
+    fn resume(self, code: i32) {
+        let mut inner = self.parent.inner.borrow_mut();
+        let mut inner = inner.thread_by_id(self.tid).unwrap();
+        debug_assert!(inner.user_data().state.is_ready_to_run());
+        inner.resume(Some(crate::WasmValue::I32(code)));
+    }
+}
+
+impl<'a, TPud, TTud, TExt: Extrinsics> fmt::Debug
+    for ProcessesCollectionExtrinsicsThreadDelegateCapability<'a, TPud, TTud, TExt>
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ProcessesCollectionExtrinsicsThreadDelegateCapability")
+            .field("tid", &self.tid)
+            .field("target", &self.target)
+            .field("interface", &self.interface)
+            .finish()
+    }
+}
+
+impl<'a, TPud, TTud, TExt: Extrinsics>
+    ProcessesCollectionExtrinsicsThreadEmitAnswerChunk<'a, TPud, TTud, TExt>
+{
+    /// Returns the id of the thread that has called `emit_answer_chunk`.
+    pub fn tid(&self) -> ThreadId {
+        self.tid
+    }
+
+    /// Returns the process that the thread belongs to.
+    pub fn pid(&self) -> Pid {
+        self.pid
+    }
+
+    /// Returns the message to answer.
+    pub fn message_id(&self) -> MessageId {
+        self.message_id
+    }
+
+    /// Returns the answer to deliver.
+    pub fn response(&self) -> &EncodedMessage {
+        &self.response
+    }
+
+    /// Resumes the thread, signalling that the answer has been delivered.
+    pub fn accept(self) {
+        self.resume(0)
+    }
+
+    /// Resumes the thread, signalling that the answer couldn't be delivered.
+    pub fn refuse(self) {
+        self.resume(1)
+    }
+
+    fn resume(self, code: i32) {
+        let mut inner = self.parent.inner.borrow_mut();
+        let mut inner = inner.thread_by_id(self.tid).unwrap();
+        debug_assert!(inner.user_data().state.is_ready_to_run());
+        inner.resume(Some(crate::WasmValue::I32(code)));
+    }
+}
+
+impl<'a, TPud, TTud, TExt: Extrinsics> fmt::Debug
+    for ProcessesCollectionExtrinsicsThreadEmitAnswerChunk<'a, TPud, TTud, TExt>
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ProcessesCollectionExtrinsicsThreadEmitAnswerChunk")
+            .field("tid", &self.tid)
+            .field("message_id", &self.message_id)
+            .finish()
+    }
+}
+
 impl<TExtCtxt> LocalThreadState<TExtCtxt> {
     /// True if `self` is equal to [`LocalThreadState::ReadyToRun`].
     fn is_ready_to_run(&self) -> bool {
@@ -16,9 +16,9 @@
 //! Helpers for parsing the hardcoded functions that can be called by the WASM program.
 
 use crate::scheduler::processes;
-use crate::{InterfaceHash, MessageId};
+use crate::{InterfaceHash, MessageId, Pid};
 
-use alloc::{vec, vec::Vec};
+use alloc::{collections::VecDeque, vec, vec::Vec};
 use core::convert::TryFrom as _;
 use redshirt_syscalls::EncodedMessage;
 
@@ -54,15 +54,19 @@ pub fn parse_extrinsic_next_notification<TPud, TTud>(
             // TODO: arbitrary limit in order to not allocate too much memory below; a bit crappy
             return Err(ExtrinsicNextNotificationErr::TooManyNotificationIds { requested: len });
         }
-        let mem = thread
-            .read_memory(notifs_ids_ptr, len * 8)
-            .map_err(|_| ExtrinsicNextNotificationErr::BadParameter)?;
         let len_usize = usize::try_from(len)
             .map_err(|_| ExtrinsicNextNotificationErr::TooManyNotificationIds { requested: len })?;
         let mut out = vec![MessageId::from(0u64); len_usize];
-        for (o, i) in out.iter_mut().zip(mem.chunks(8)) {
-            *o = MessageId::from(u64::from_le_bytes(<[u8; 8]>::try_from(i).unwrap()));
-        }
+        let end = notifs_ids_ptr
+            .checked_add(len * 8)
+            .ok_or(ExtrinsicNextNotificationErr::BadParameter)?;
+        thread
+            .with_memory(notifs_ids_ptr..end, |mem| {
+                for (o, i) in out.iter_mut().zip(mem.chunks(8)) {
+                    *o = MessageId::from(u64::from_le_bytes(<[u8; 8]>::try_from(i).unwrap()));
+                }
+            })
+            .map_err(|_| ExtrinsicNextNotificationErr::BadParameter)?;
         out
     };
 
@@ -142,14 +146,11 @@ pub fn parse_extrinsic_emit_message<TPud, TTud>(
                 .ok_or(ExtrinsicEmitMessageErr::BadParameter)?,
         )
         .map_err(|_| ExtrinsicEmitMessageErr::BadParameter)?;
-        InterfaceHash::from(
-            <[u8; 32]>::try_from(
-                &thread
-                    .read_memory(addr, 32)
-                    .map_err(|_| ExtrinsicEmitMessageErr::BadParameter)?[..],
-            )
-            .map_err(|_| ExtrinsicEmitMessageErr::BadParameter)?,
-        )
+        let mut interface = [0; 32];
+        thread
+            .read_memory_into(addr, &mut interface)
+            .map_err(|_| ExtrinsicEmitMessageErr::BadParameter)?;
+        InterfaceHash::from(interface)
     };
 
     let message = {
@@ -167,14 +168,12 @@ pub fn parse_extrinsic_emit_message<TPud, TTud>(
         .map_err(|_| ExtrinsicEmitMessageErr::BadParameter)?;
         let mut out_msg = Vec::new();
         for buf_n in 0..num_bufs {
-            let sub_buf_ptr = thread
-                .read_memory(addr + 8 * buf_n, 4)
-                .map_err(|_| ExtrinsicEmitMessageErr::BadParameter)?;
-            let sub_buf_ptr = u32::from_le_bytes(<[u8; 4]>::try_from(&sub_buf_ptr[..]).unwrap());
-            let sub_buf_sz = thread
-                .read_memory(addr + 8 * buf_n + 4, 4)
+            let mut sub_buf = [0; 8];
+            thread
+                .read_memory_into(addr + 8 * buf_n, &mut sub_buf)
                 .map_err(|_| ExtrinsicEmitMessageErr::BadParameter)?;
-            let sub_buf_sz = u32::from_le_bytes(<[u8; 4]>::try_from(&sub_buf_sz[..]).unwrap());
+            let sub_buf_ptr = u32::from_le_bytes(<[u8; 4]>::try_from(&sub_buf[..4]).unwrap());
+            let sub_buf_sz = u32::from_le_bytes(<[u8; 4]>::try_from(&sub_buf[4..]).unwrap());
             if out_msg.len()
                 + usize::try_from(sub_buf_sz).map_err(|_| ExtrinsicEmitMessageErr::BadParameter)?
                 >= 16 * 1024 * 1024
@@ -183,11 +182,14 @@ pub fn parse_extrinsic_emit_message<TPud, TTud>(
                 panic!("Max message length reached");
                 //return Err(());
             }
-            out_msg.extend_from_slice(
-                &thread
-                    .read_memory(sub_buf_ptr, sub_buf_sz)
-                    .map_err(|_| ExtrinsicEmitMessageErr::BadParameter)?,
-            );
+            let sub_buf_end = sub_buf_ptr
+                .checked_add(sub_buf_sz)
+                .ok_or(ExtrinsicEmitMessageErr::BadParameter)?;
+            thread
+                .with_memory(sub_buf_ptr..sub_buf_end, |buf| {
+                    out_msg.extend_from_slice(buf)
+                })
+                .map_err(|_| ExtrinsicEmitMessageErr::BadParameter)?;
         }
         EncodedMessage(out_msg)
     };
@@ -243,6 +245,124 @@ pub enum ExtrinsicEmitMessageErr {
     BadParameter,
 }
 
+/// Maximum number of messages in a single call to `emit_messages_batch`, in order to bound the
+/// memory allocated when parsing the call.
+pub const MAX_BATCH_LEN: u32 = 512;
+
+/// Analyzes a call to `emit_messages_batch` made by the given thread.
+///
+/// The `thread` parameter is only used in order to read memory from the process. This function
+/// has no side effect.
+///
+/// Returns an error if the call is invalid.
+pub fn parse_extrinsic_emit_messages_batch<TPud, TTud>(
+    thread: &mut processes::ProcessesCollectionThread<TPud, TTud>,
+    params: Vec<crate::WasmValue>,
+) -> Result<EmitMessagesBatch, ExtrinsicEmitMessagesBatchErr> {
+    // We use an assert here rather than a runtime check because the WASM VM (rather than us) is
+    // supposed to check the function signature.
+    assert_eq!(params.len(), 3);
+
+    let addr = u32::try_from(
+        params[0]
+            .into_i32()
+            .ok_or(ExtrinsicEmitMessagesBatchErr::BadParameter)?,
+    )
+    .map_err(|_| ExtrinsicEmitMessagesBatchErr::BadParameter)?;
+    let num_msgs = u32::try_from(
+        params[1]
+            .into_i32()
+            .ok_or(ExtrinsicEmitMessagesBatchErr::BadParameter)?,
+    )
+    .map_err(|_| ExtrinsicEmitMessagesBatchErr::BadParameter)?;
+    if num_msgs > MAX_BATCH_LEN {
+        return Err(ExtrinsicEmitMessagesBatchErr::TooManyMessages {
+            requested: num_msgs,
+        });
+    }
+    let message_ids_out = u32::try_from(
+        params[2]
+            .into_i32()
+            .ok_or(ExtrinsicEmitMessagesBatchErr::BadParameter)?,
+    )
+    .map_err(|_| ExtrinsicEmitMessagesBatchErr::BadParameter)?;
+
+    let entries = thread
+        .read_memory(addr, num_msgs * 16)
+        .map_err(|_| ExtrinsicEmitMessagesBatchErr::BadParameter)?;
+
+    let mut messages = VecDeque::with_capacity(entries.len() / 16);
+    let mut total_len = 0;
+    for (msg_n, entry) in entries.chunks(16).enumerate() {
+        let read_u32 =
+            |n: usize| u32::from_le_bytes(<[u8; 4]>::try_from(&entry[n * 4..][..4]).unwrap());
+
+        let mut interface = [0; 32];
+        thread
+            .read_memory_into(read_u32(0), &mut interface)
+            .map_err(|_| ExtrinsicEmitMessagesBatchErr::BadParameter)?;
+        let interface = InterfaceHash::from(interface);
+
+        let msg_len = read_u32(2);
+        total_len +=
+            usize::try_from(msg_len).map_err(|_| ExtrinsicEmitMessagesBatchErr::BadParameter)?;
+        if total_len >= 16 * 1024 * 1024 {
+            // TODO: arbitrary maximum length, same as for `emit_message`
+            return Err(ExtrinsicEmitMessagesBatchErr::BadParameter);
+        }
+        let message = EncodedMessage(
+            thread
+                .read_memory(read_u32(1), msg_len)
+                .map_err(|_| ExtrinsicEmitMessagesBatchErr::BadParameter)?,
+        );
+
+        let message_id_write = if read_u32(3) != 0 {
+            // `msg_n` is inferior to `MAX_BATCH_LEN`, and this can't overflow.
+            Some(
+                message_ids_out
+                    .checked_add(u32::try_from(msg_n).unwrap() * 8)
+                    .ok_or(ExtrinsicEmitMessagesBatchErr::BadParameter)?,
+            )
+        } else {
+            None
+        };
+
+        messages.push_back(EmitMessage {
+            interface,
+            message_id_write,
+            message,
+            allow_delay: true,
+        });
+    }
+
+    Ok(EmitMessagesBatch {
+        messages,
+        num_emitted: 0,
+    })
+}
+
+/// How a process is emitting a batch of messages.
+#[derive(Debug, PartialEq, Eq)]
+pub struct EmitMessagesBatch {
+    /// Messages that remain to be emitted, in order. The first element is the message currently
+    /// being emitted.
+    pub messages: VecDeque<EmitMessage>,
+    /// Number of messages of the batch that have already been emitted.
+    pub num_emitted: u32,
+}
+
+/// Error that [`parse_extrinsic_emit_messages_batch`] can return.
+#[derive(Debug)]
+pub enum ExtrinsicEmitMessagesBatchErr {
+    /// Too many messages in the batch.
+    TooManyMessages {
+        /// Number of messages in the batch.
+        requested: u32,
+    },
+    /// Bad type or invalid value for a parameter.
+    BadParameter,
+}
+
 /// Analyzes a call to `emit_answer` made by the given thread.
 ///
 /// The `thread` parameter is only used in order to read memory from the process. This function
@@ -264,10 +384,11 @@ pub fn parse_extrinsic_emit_answer<TPud, TTud>(
                 .ok_or(ExtrinsicEmitAnswerErr::BadParameter)?,
         )
         .map_err(|_| ExtrinsicEmitAnswerErr::BadParameter)?;
-        let buf = thread
-            .read_memory(addr, 8)
+        let mut buf = [0; 8];
+        thread
+            .read_memory_into(addr, &mut buf)
             .map_err(|_| ExtrinsicEmitAnswerErr::BadParameter)?;
-        MessageId::from(u64::from_le_bytes(<[u8; 8]>::try_from(&buf[..]).unwrap()))
+        MessageId::from(u64::from_le_bytes(buf))
     };
 
     let response = {
@@ -334,10 +455,11 @@ pub fn parse_extrinsic_emit_message_error<TPud, TTud>(
                 .ok_or(ExtrinsicEmitMessageErrorErr::BadParameter)?,
         )
         .map_err(|_| ExtrinsicEmitMessageErrorErr::BadParameter)?;
-        let buf = thread
-            .read_memory(addr, 8)
+        let mut buf = [0; 8];
+        thread
+            .read_memory_into(addr, &mut buf)
             .map_err(|_| ExtrinsicEmitMessageErrorErr::BadParameter)?;
-        MessageId::from(u64::from_le_bytes(<[u8; 8]>::try_from(&buf[..]).unwrap()))
+        MessageId::from(u64::from_le_bytes(buf))
     };
 
     Ok(msg_id)
@@ -372,10 +494,11 @@ pub fn parse_extrinsic_cancel_message<TPud, TTud>(
                 .ok_or(ExtrinsicCancelMessageErr::BadParameter)?,
         )
         .map_err(|_| ExtrinsicCancelMessageErr::BadParameter)?;
-        let buf = thread
-            .read_memory(addr, 8)
+        let mut buf = [0; 8];
+        thread
+            .read_memory_into(addr, &mut buf)
             .map_err(|_| ExtrinsicCancelMessageErr::BadParameter)?;
-        MessageId::from(u64::from_le_bytes(<[u8; 8]>::try_from(&buf[..]).unwrap()))
+        MessageId::from(u64::from_le_bytes(buf))
     };
 
     Ok(msg_id)
@@ -387,3 +510,48 @@ pub enum ExtrinsicCancelMessageErr {
     /// Bad type or invalid value for a parameter.
     BadParameter,
 }
+
+/// Analyzes a call to `delegate_capability` made by the given thread.
+/// Returns the process that must receive the capability, and the interface it applies to.
+///
+/// The `thread` parameter is only used in order to read memory from the process. This function
+/// has no side effect.
+///
+/// Returns an error if the call is invalid.
+pub fn parse_extrinsic_delegate_capability<TPud, TTud>(
+    thread: &mut processes::ProcessesCollectionThread<TPud, TTud>,
+    params: Vec<crate::WasmValue>,
+) -> Result<(Pid, InterfaceHash), ExtrinsicDelegateCapabilityErr> {
+    // We use an assert here rather than a runtime check because the WASM VM (rather than us) is
+    // supposed to check the function signature.
+    assert_eq!(params.len(), 2);
+
+    // Wasm has no unsigned integers. The pid is reinterpreted as a `u64`.
+    let target = params[0]
+        .into_i64()
+        .ok_or(ExtrinsicDelegateCapabilityErr::BadParameter)?;
+    let target = Pid::from(target as u64);
+
+    let interface = {
+        let addr = u32::try_from(
+            params[1]
+                .into_i32()
+                .ok_or(ExtrinsicDelegateCapabilityErr::BadParameter)?,
+        )
+        .map_err(|_| ExtrinsicDelegateCapabilityErr::BadParameter)?;
+        let mut interface = [0; 32];
+        thread
+            .read_memory_into(addr, &mut interface)
+            .map_err(|_| ExtrinsicDelegateCapabilityErr::BadParameter)?;
+        InterfaceHash::from(interface)
+    };
+
+    Ok((target, interface))
+}
+
+/// Error that [`parse_extrinsic_delegate_capability`] can return.
+#[derive(Debug)]
+pub enum ExtrinsicDelegateCapabilityErr {
+    /// Bad type or invalid value for a parameter.
+    BadParameter,
+}
@@ -0,0 +1,48 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Limits on the number of messages waiting to be processed by a process.
+//!
+//! By default, the number of interface messages that can be queued for a process is unbounded.
+//! If an interface handler processes its messages slower than they arrive, the kernel keeps
+//! allocating memory for them. Setting an [`InboxConfig`] on a process caps the size of its
+//! queue, and the [`OverflowPolicy`] decides what happens to the messages that don't fit.
+//!
+//! Only the messages emitted on interfaces are subject to this limit. Answers to the messages
+//! emitted by the process and notifications about destroyed processes are always delivered.
+
+/// Configuration of the inbox of a process.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InboxConfig {
+    /// Maximum number of interface messages waiting for the process to pick them up.
+    pub capacity: usize,
+    /// What to do with a message that arrives when the inbox is full.
+    pub overflow: OverflowPolicy,
+}
+
+/// What to do with a message that arrives when the inbox is full.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// The thread that emits the message is paused until there is space in the inbox.
+    ///
+    /// > **Note**: Native programs can't be paused. Their messages are refused, as with
+    /// >           [`OverflowPolicy::Error`].
+    Block,
+    /// The oldest message in the inbox is discarded to make space. If the discarded message
+    /// expects an answer, its emitter receives an error as the answer.
+    DropOldest,
+    /// The emission of the message fails.
+    Error,
+}
@@ -14,19 +14,27 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 use crate::id_pool::IdPool;
-use crate::module::Module;
+use crate::module::{AbiReport, Module};
 use crate::scheduler::{
     extrinsics::{self, ProcessesCollectionExtrinsicsThreadAccess as _},
+    inbox::{InboxConfig, OverflowPolicy},
+    processes::{
+        CachedModule, ExtrinsicsAllowlist, LimitExceeded, OrphanPolicy, ProcessConfig,
+        ResourceUsage,
+    },
+    self_check::{SelfCheckConfig, Violation},
+    snapshot::MemorySnapshot,
     vm,
 };
-use crate::InterfaceHash;
+use crate::{InterfaceHash, TrapError};
 
-use alloc::{collections::VecDeque, vec::Vec};
+use alloc::{collections::VecDeque, string::String, vec::Vec};
 use core::{cell::RefCell, convert::TryFrom, iter, mem};
 use crossbeam_queue::SegQueue;
 use fnv::FnvBuildHasher;
 use hashbrown::{hash_map::Entry, HashMap, HashSet};
 use nohash_hasher::BuildNoHashHasher;
+use redshirt_process_management_interface::ffi::ExitStatus;
 use redshirt_syscalls::{Encode, EncodedMessage, MessageId, Pid, ThreadId};
 use smallvec::SmallVec;
 
@@ -51,6 +59,10 @@ pub struct Core {
     /// For each interface, which program is fulfilling it.
     interfaces: RefCell<HashMap<InterfaceHash, InterfaceState, FnvBuildHasher>>,
 
+    /// Handlers that replace the ones of [`Core::interfaces`] for the messages emitted by a
+    /// specific process. Keys are the emitter and the interface, and values the handler.
+    interface_overrides: RefCell<HashMap<(Pid, InterfaceHash), Pid, FnvBuildHasher>>,
+
     /// Pool of identifiers for messages.
     message_id_pool: IdPool,
 
@@ -58,6 +70,15 @@ pub struct Core {
     // TODO: doc about hash safety
     // TODO: call shrink_to from time to time
     messages_to_answer: RefCell<HashMap<MessageId, Pid, BuildNoHashHasher<u64>>>,
+
+    /// Configuration of the inbox of newly-created processes.
+    default_inbox: Option<InboxConfig>,
+
+    /// Limits of newly-created processes.
+    process_config: ProcessConfig,
+
+    /// List of processes whose [`Process::blocked_emitters`] might not be empty.
+    blocked_inboxes: RefCell<HashSet<Pid, BuildNoHashHasher<u64>>>,
 }
 
 /// Which way an interface is handled.
@@ -81,6 +102,10 @@ pub struct CoreBuilder {
     /// Builder for the [`processes`][Core::processes] field in `Core`.
     inner_builder:
         extrinsics::ProcessesCollectionExtrinsicsBuilder<crate::extrinsics::wasi::WasiExtrinsics>,
+    /// See the corresponding field in `Core`.
+    default_inbox: Option<InboxConfig>,
+    /// See the corresponding field in `Core`.
+    process_config: ProcessConfig,
 }
 
 /// Outcome of calling [`run`](Core::run).
@@ -103,10 +128,16 @@ pub enum CoreRunOutcome {
         /// List of interfaces that were registered by th process and no longer are.
         unregistered_interfaces: Vec<InterfaceHash>,
 
+        /// Values of the globals of the program at the time it stopped, in index order.
+        globals: Vec<crate::WasmValue>,
+
         /// How the program ended. If `Ok`, it has gracefully terminated. If `Err`, something
         /// bad happened.
         // TODO: force Ok to i32?
-        outcome: Result<Option<crate::WasmValue>, wasmi::Trap>,
+        outcome: Result<Option<crate::WasmValue>, TrapError>,
+
+        /// How the program ended, as reported to the programs waiting for it to end.
+        exit_status: ExitStatus,
     },
 
     /// Thread has tried to emit a message on an interface that isn't registered. The thread is
@@ -134,6 +165,10 @@ pub enum CoreRunOutcome {
         response: Result<EncodedMessage, ()>,
     },
 
+    /// A thread has run out of fuel and has been paused. Calling [`Core::run`] again resumes
+    /// it, possibly after other threads. Gives the caller the chance to process other events.
+    Preempted,
+
     /// Nothing to do. No thread is ready to run.
     Idle,
 }
@@ -156,11 +191,62 @@ struct Process {
     /// handlers about it.
     used_interfaces: HashSet<InterfaceHash, FnvBuildHasher>,
 
+    /// Interfaces that the module of the process has declared, as returned by
+    /// [`Module::required_interfaces`]. Messages emitted on other interfaces are refused. If
+    /// `None`, the process can emit messages on any interface.
+    declared_interfaces: Option<HashSet<InterfaceHash, FnvBuildHasher>>,
+
+    /// Interfaces that the process holds a capability for, either granted when it was spawned
+    /// with [`CorePreparedProcess::with_capabilities`] or delegated by another process with the
+    /// `delegate_capability` extrinsic. Messages emitted on other interfaces are refused. If
+    /// `None`, the process isn't restricted and can emit messages on any interface.
+    capabilities: Option<HashSet<InterfaceHash, FnvBuildHasher>>,
+
     /// List of messages that the process has emitted and that are waiting for an answer.
     emitted_messages: SmallVec<[MessageId; 8]>,
 
     /// List of messages that the process is expected to answer.
     messages_to_answer: SmallVec<[MessageId; 8]>,
+
+    /// Limits on the number of interface messages in `notifications_queue`. If `None`, the
+    /// number is unbounded.
+    inbox: Option<InboxConfig>,
+
+    /// Maximum length of `notifications_queue`, including the answers and the other
+    /// notifications that aren't subject to `inbox`. The process is killed if it is exceeded.
+    /// Copy of [`ProcessConfig::max_pending_messages`].
+    max_pending_messages: Option<usize>,
+
+    /// Threads that have emitted a message towards this process while its inbox was full, and
+    /// that are paused until there is space in the inbox. Only ever non-empty if `inbox` uses
+    /// [`OverflowPolicy::Block`].
+    blocked_emitters: VecDeque<ThreadId>,
+
+    /// Statistics about the activity of the process.
+    counters: ProcessCounters,
+}
+
+/// Statistics about the activity of a process since it has started.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProcessCounters {
+    /// Number of messages emitted by the process on interfaces.
+    pub messages_emitted: u64,
+    /// Total size, in bytes, of the messages emitted by the process.
+    pub message_bytes_emitted: u64,
+    /// Number of notifications delivered to the process.
+    pub notifications_received: u64,
+    /// Total size, in bytes, of the notifications delivered to the process.
+    pub notification_bytes_received: u64,
+}
+
+/// Process that has been instantiated with [`Core::prepare`] but isn't running yet.
+pub struct CorePreparedProcess {
+    /// The process within the inner collection.
+    inner: extrinsics::PreparedProcess<(), crate::extrinsics::wasi::WasiExtrinsics>,
+    /// Interfaces declared by the module. See [`Process::declared_interfaces`].
+    declared_interfaces: Option<HashSet<InterfaceHash, FnvBuildHasher>>,
+    /// See [`Process::capabilities`].
+    capabilities: Option<HashSet<InterfaceHash, FnvBuildHasher>>,
 }
 
 /// Access to a process within the core.
@@ -180,6 +266,8 @@ impl Core {
         CoreBuilder {
             reserved_pids: HashSet::with_hasher(Default::default()),
             inner_builder: extrinsics::ProcessesCollectionExtrinsicsBuilder::default(),
+            default_inbox: None,
+            process_config: Default::default(),
         }
     }
 
@@ -200,6 +288,8 @@ impl Core {
             return Some(ev);
         }
 
+        self.unblock_emitters();
+
         // Note: we use a temporary `run_outcome` variable in order to solve weird borrowing
         // issues. Feel free to try to remove it if you manage.
         let run_outcome = self.processes.run();
@@ -208,7 +298,9 @@ impl Core {
                 pid,
                 outcome,
                 dead_threads,
+                globals,
                 user_data,
+                exit_status,
             } => {
                 for (dead_thread_id, dead_thread_state) in dead_threads {
                     match dead_thread_state {
@@ -218,6 +310,22 @@ impl Core {
 
                 let user_data = user_data.into_inner();
 
+                // Resume with an error the threads that were waiting for space in the inbox.
+                for thread_id in user_data.blocked_emitters {
+                    if let Ok(extrinsics::ProcessesCollectionExtrinsicsThread::EmitMessage(
+                        thread,
+                    )) = self.processes.interrupted_thread_by_id(thread_id)
+                    {
+                        thread.refuse_emit();
+                    }
+                }
+                self.blocked_inboxes.borrow_mut().remove(&pid);
+
+                // Remove the overrides that apply to or redirect to this program.
+                self.interface_overrides
+                    .borrow_mut()
+                    .retain(|(emitter, _), handler| *emitter != pid && *handler != pid);
+
                 // Unregister the interfaces this program had registered.
                 let mut unregistered_interfaces = Vec::new();
                 for interface in user_data.registered_interfaces {
@@ -250,11 +358,7 @@ impl Core {
                                     ),
                                 );
 
-                                process
-                                    .user_data()
-                                    .borrow_mut()
-                                    .notifications_queue
-                                    .push_back(notif);
+                                push_notification(&process, notif);
                                 try_resume_notification_wait(process);
                             } // TODO: notify externals as well?
                         }
@@ -271,7 +375,9 @@ impl Core {
                     // TODO: this only handles messages emitted through the external API
                     unhandled_messages: user_data.messages_to_answer.to_vec(), // TODO: to_vec overhead
                     cancelled_messages,
+                    globals,
                     outcome,
+                    exit_status,
                 })
             }
 
@@ -281,43 +387,80 @@ impl Core {
             }
 
             extrinsics::RunOneOutcome::ThreadWaitNotification(thread) => {
-                try_resume_notification_wait_thread(thread);
+                try_resume_notification_wait_thread(thread, false);
                 None
             }
 
             extrinsics::RunOneOutcome::ThreadEmitMessage(mut thread) => {
                 let emitter_pid = thread.pid();
                 let interface = thread.emit_interface().clone();
+
+                let is_declared = match &thread.process_user_data().borrow().declared_interfaces {
+                    Some(declared) => declared.contains(&interface),
+                    None => true,
+                };
+                let is_capable = has_capability(&thread.process_user_data().borrow(), &interface);
+                if !is_declared || !is_capable {
+                    thread.refuse_emit();
+                    return None;
+                }
+
                 thread
                     .process_user_data()
                     .borrow_mut()
                     .used_interfaces
                     .insert(interface.clone());
 
+                let overridden = self
+                    .interface_overrides
+                    .borrow()
+                    .get(&(emitter_pid, interface.clone()))
+                    .cloned();
+                let mut override_state;
                 let mut self_interfaces_borrow = self.interfaces.borrow_mut();
-                match (
-                    self_interfaces_borrow.get_mut(&interface),
-                    thread.allow_delay(),
-                ) {
+                let state = match overridden {
+                    Some(handler) => {
+                        override_state = InterfaceState::Process(handler);
+                        Some(&mut override_state)
+                    }
+                    None => self_interfaces_borrow.get_mut(&interface),
+                };
+                match (state, thread.allow_delay()) {
                     (Some(InterfaceState::Process(pid)), _) => {
-                        let message_id = if thread.needs_answer() {
-                            Some(loop {
-                                let id: MessageId = self.message_id_pool.assign();
-                                if u64::from(id) == 0 || u64::from(id) == 1 {
-                                    continue;
+                        let pid = *pid;
+
+                        if let Some(process) = self.processes.process_by_id(pid) {
+                            let overflow = process.user_data().borrow().inbox_overflow();
+                            match overflow {
+                                None => {}
+                                Some(OverflowPolicy::Block) => {
+                                    process
+                                        .user_data()
+                                        .borrow_mut()
+                                        .blocked_emitters
+                                        .push_back(thread.tid());
+                                    self.blocked_inboxes.borrow_mut().insert(pid);
+                                    return None;
                                 }
-                                match self.messages_to_answer.borrow_mut().entry(id) {
-                                    Entry::Occupied(_) => continue,
-                                    Entry::Vacant(e) => e.insert(emitter_pid),
-                                };
-                                break id;
-                            })
+                                Some(OverflowPolicy::DropOldest) => {
+                                    self.drop_oldest_interface_message(&process)
+                                }
+                                Some(OverflowPolicy::Error) => {
+                                    thread.refuse_emit();
+                                    return None;
+                                }
+                            }
+                        }
+
+                        let message_id = if thread.needs_answer() {
+                            Some(self.allocate_message_id(emitter_pid))
                         } else {
                             None
                         };
 
                         let message = thread.accept_emit(message_id);
-                        if let Some(process) = self.processes.process_by_id(*pid) {
+                        self.record_emitted(emitter_pid, &message);
+                        if let Some(process) = self.processes.process_by_id(pid) {
                             let notif = redshirt_syscalls::ffi::build_interface_notification(
                                 &interface,
                                 message_id,
@@ -327,14 +470,10 @@ impl Core {
                             )
                             .into();
 
-                            process
-                                .user_data()
-                                .borrow_mut()
-                                .notifications_queue
-                                .push_back(notif);
+                            push_notification(&process, notif);
                             try_resume_notification_wait(process);
                             None
-                        } else if self.reserved_pids.contains(pid) {
+                        } else if self.reserved_pids.contains(&pid) {
                             Some(CoreRunOutcome::ReservedPidInterfaceMessage {
                                 pid: emitter_pid,
                                 message_id,
@@ -386,6 +525,22 @@ impl Core {
                 self.answer_message_inner(message_id, Ok(response))
             }
 
+            extrinsics::RunOneOutcome::ThreadEmitAnswerChunk(thread) => {
+                // TODO: check ownership of the message
+                if self.answer_message_chunk_inner(thread.message_id(), thread.response()) {
+                    thread.accept();
+                } else {
+                    thread.refuse();
+                }
+                None
+            }
+
+            extrinsics::RunOneOutcome::ThreadCloseAnswer { message_id, .. } => {
+                // TODO: check ownership of the message
+                drop(run_outcome);
+                self.close_answer_inner(message_id)
+            }
+
             extrinsics::RunOneOutcome::ThreadEmitMessageError { message_id, .. } => {
                 // TODO: check ownership of the message
                 drop(run_outcome);
@@ -399,6 +554,29 @@ impl Core {
                 None
             }
 
+            extrinsics::RunOneOutcome::ThreadDelegateCapability(thread) => {
+                let interface = thread.interface().clone();
+
+                let is_capable = match self.processes.process_by_id(thread.pid()) {
+                    Some(p) => has_capability(&p.user_data().borrow(), &interface),
+                    None => false,
+                };
+                let target = match self.processes.process_by_id(thread.target()) {
+                    Some(p) if is_capable => p,
+                    _ => {
+                        thread.refuse();
+                        return None;
+                    }
+                };
+
+                if let Some(capabilities) = &mut target.user_data().borrow_mut().capabilities {
+                    capabilities.insert(interface);
+                }
+                thread.accept();
+                None
+            }
+
+            extrinsics::RunOneOutcome::Preempted => Some(CoreRunOutcome::Preempted),
             extrinsics::RunOneOutcome::Idle => Some(CoreRunOutcome::Idle),
         }
     }
@@ -409,6 +587,163 @@ impl Core {
         Some(CoreProcess { process: p })
     }
 
+    /// Returns true if the process with the given [`Pid`] is still running.
+    ///
+    /// [`Pid`]s are never reused. If this returns true, then the [`Pid`] designates the same
+    /// process as the one it was originally assigned to.
+    pub fn is_process_alive(&self, pid: Pid) -> bool {
+        self.processes.is_process_alive(pid)
+    }
+
+    /// Returns the resources used by all the processes.
+    pub fn resource_usage(&self) -> ResourceUsage {
+        self.processes.resource_usage()
+    }
+
+    /// Verifies the internal invariants of the [`Core`], and returns the list of violations.
+    ///
+    /// This is a costly operation that goes through all the processes, threads, and messages,
+    /// and should only be called from time to time, for example in long-running tests.
+    pub fn self_check(&self, config: &SelfCheckConfig) -> Vec<Violation> {
+        let mut violations = self.processes.self_check();
+
+        let pids = self
+            .processes
+            .pids()
+            .into_iter()
+            .collect::<HashSet<_, BuildNoHashHasher<u64>>>();
+        let thread_ids = self
+            .processes
+            .thread_ids()
+            .into_iter()
+            .map(|(_, tid)| tid)
+            .collect::<HashSet<_, BuildNoHashHasher<u64>>>();
+        let is_alive = |pid: &Pid| pids.contains(pid) || self.reserved_pids.contains(pid);
+
+        for pid in &pids {
+            if self.reserved_pids.contains(pid) {
+                violations.push(Violation::ReservedPidInUse { pid: *pid });
+            }
+
+            let process = match self.processes.process_by_id(*pid) {
+                Some(p) => p,
+                None => unreachable!(),
+            };
+            let queue_len = process.user_data().borrow().notifications_queue.len();
+            if queue_len > config.max_notifications_queue_len {
+                violations.push(Violation::NotificationsQueueTooLong {
+                    pid: *pid,
+                    len: queue_len,
+                    max: config.max_notifications_queue_len,
+                });
+            }
+        }
+
+        {
+            let messages_to_answer = self.messages_to_answer.borrow();
+            if messages_to_answer.len() > config.max_pending_answers {
+                violations.push(Violation::TooManyPendingAnswers {
+                    len: messages_to_answer.len(),
+                    max: config.max_pending_answers,
+                });
+            }
+            for (message_id, emitter) in messages_to_answer.iter() {
+                if u64::from(*message_id) == 0 || u64::from(*message_id) == 1 {
+                    violations.push(Violation::ReservedMessageId {
+                        message_id: *message_id,
+                    });
+                }
+                if !is_alive(emitter) {
+                    violations.push(Violation::OrphanedMessage {
+                        message_id: *message_id,
+                        emitter: *emitter,
+                    });
+                }
+            }
+        }
+
+        for (interface, state) in self.interfaces.borrow().iter() {
+            match state {
+                InterfaceState::Process(pid) => {
+                    if !is_alive(pid) {
+                        violations.push(Violation::StaleInterfaceHandler {
+                            interface: interface.clone(),
+                            pid: *pid,
+                        });
+                    }
+                }
+                InterfaceState::Requested { threads, other } => {
+                    for thread_id in threads {
+                        if !thread_ids.contains(thread_id) {
+                            violations.push(Violation::StaleWaitingThread {
+                                interface: interface.clone(),
+                                thread_id: *thread_id,
+                            });
+                        }
+                    }
+                    if other.len() > config.max_interface_queue_len {
+                        violations.push(Violation::InterfaceQueueTooLong {
+                            interface: interface.clone(),
+                            len: other.len(),
+                            max: config.max_interface_queue_len,
+                        });
+                    }
+                }
+            }
+        }
+
+        violations
+    }
+
+    /// Returns the process that handles the given interface, or `None` if the interface hasn't
+    /// been registered with [`Core::set_interface_handler`].
+    pub fn interface_handler(&self, interface: &InterfaceHash) -> Option<Pid> {
+        match self.interfaces.borrow().get(interface) {
+            Some(InterfaceState::Process(pid)) => Some(*pid),
+            Some(InterfaceState::Requested { .. }) | None => None,
+        }
+    }
+
+    /// Routes the messages that `emitter` emits on `interface` to `handler`, instead of to the
+    /// process registered with [`Core::set_interface_handler`]. Replaces any previous override
+    /// for this emitter and interface.
+    ///
+    /// This makes it possible, for example, to give a sandboxed process a filtering proxy in
+    /// place of the actual handler. Other processes aren't affected.
+    ///
+    /// The override is removed when either `emitter` or `handler` terminates.
+    ///
+    /// Returns an error if `emitter` or `handler` isn't a running process.
+    pub fn set_interface_override(
+        &self,
+        emitter: Pid,
+        interface: InterfaceHash,
+        handler: Pid,
+    ) -> Result<(), ()> {
+        if self.processes.process_by_id(emitter).is_none()
+            || self.processes.process_by_id(handler).is_none()
+        {
+            return Err(());
+        }
+
+        self.interface_overrides
+            .borrow_mut()
+            .insert((emitter, interface), handler);
+        Ok(())
+    }
+
+    /// Removes an override added with [`Core::set_interface_override`]. Returns the handler
+    /// that the messages were routed to, or `None` if there wasn't any override.
+    pub fn remove_interface_override(
+        &self,
+        emitter: Pid,
+        interface: &InterfaceHash,
+    ) -> Option<Pid> {
+        self.interface_overrides
+            .borrow_mut()
+            .remove(&(emitter, interface.clone()))
+    }
+
     // TODO: better API
     pub fn set_interface_handler(&self, interface: InterfaceHash, process: Pid) -> Result<(), ()> {
         if self.processes.process_by_id(process).is_none() {
@@ -450,11 +785,7 @@ impl Core {
             ));
 
             match self.processes.process_by_id(process) {
-                Some(p) => p
-                    .user_data()
-                    .borrow_mut()
-                    .notifications_queue
-                    .push_back(notif),
+                Some(p) => push_notification(&p, notif),
                 None => unreachable!(),
             }
         }
@@ -470,22 +801,13 @@ impl Core {
             let emitter_pid = thread.pid().into();
 
             let message_id = if thread.needs_answer() {
-                Some(loop {
-                    let id: MessageId = self.message_id_pool.assign();
-                    if u64::from(id) == 0 || u64::from(id) == 1 {
-                        continue;
-                    }
-                    match self.messages_to_answer.borrow_mut().entry(id) {
-                        Entry::Occupied(_) => continue,
-                        Entry::Vacant(e) => e.insert(emitter_pid),
-                    };
-                    break id;
-                })
+                Some(self.allocate_message_id(emitter_pid))
             } else {
                 None
             };
 
             let message = thread.accept_emit(message_id);
+            self.record_emitted(emitter_pid, &message);
 
             if let Some(interface_handler_proc) = self.processes.process_by_id(process) {
                 let notif = From::from(redshirt_syscalls::ffi::build_interface_notification(
@@ -496,11 +818,7 @@ impl Core {
                     &message,
                 ));
 
-                interface_handler_proc
-                    .user_data()
-                    .borrow_mut()
-                    .notifications_queue
-                    .push_back(notif);
+                push_notification(&interface_handler_proc, notif);
             } else {
                 debug_assert!(self.reserved_pids.contains(&process));
                 self.pending_events
@@ -566,21 +884,10 @@ impl Core {
         message: impl Encode,
         needs_answer: bool,
     ) -> Option<MessageId> {
-        let mut messages_to_answer = self.messages_to_answer.borrow_mut();
-
-        let (message_id, messages_to_answer_entry) = if needs_answer {
-            loop {
-                let id: MessageId = self.message_id_pool.assign();
-                if u64::from(id) == 0 || u64::from(id) == 1 {
-                    continue;
-                }
-                match messages_to_answer.entry(id) {
-                    Entry::Vacant(e) => break (Some(id), Some(e)),
-                    Entry::Occupied(_) => continue,
-                };
-            }
+        let message_id = if needs_answer {
+            Some(self.allocate_message_id(emitter_pid))
         } else {
-            (None, None)
+            None
         };
 
         let pid = match self
@@ -599,6 +906,23 @@ impl Core {
         };
 
         if let Some(process) = self.processes.process_by_id(pid) {
+            let overflow = process.user_data().borrow().inbox_overflow();
+            match overflow {
+                None => {}
+                Some(OverflowPolicy::DropOldest) => self.drop_oldest_interface_message(&process),
+                // Native programs can't be paused. Blocking is treated as an error.
+                Some(OverflowPolicy::Block) | Some(OverflowPolicy::Error) => {
+                    if let Some(message_id) = message_id {
+                        self.messages_to_answer.borrow_mut().remove(&message_id);
+                        self.pending_events.push(CoreRunOutcome::MessageResponse {
+                            message_id,
+                            response: Err(()),
+                        });
+                    }
+                    return message_id;
+                }
+            }
+
             let notif = redshirt_syscalls::ffi::build_interface_notification(
                 &interface,
                 message_id,
@@ -607,17 +931,13 @@ impl Core {
                 &message.encode(),
             );
 
-            process
-                .user_data()
-                .borrow_mut()
-                .notifications_queue
-                .push_back(From::from(notif));
+            push_notification(&process, From::from(notif));
             try_resume_notification_wait(process);
         } else if self.reserved_pids.contains(&emitter_pid) {
             self.pending_events
                 .push(CoreRunOutcome::ReservedPidInterfaceMessage {
                     pid: emitter_pid,
-                    message_id: None,
+                    message_id,
                     interface,
                     message: message.encode(),
                 });
@@ -625,12 +945,136 @@ impl Core {
             unimplemented!()
         };
 
-        if let Some(messages_to_answer_entry) = messages_to_answer_entry {
-            messages_to_answer_entry.insert(emitter_pid);
-        }
         message_id
     }
 
+    /// Updates the counters of the given process after it has emitted a message.
+    fn record_emitted(&self, emitter_pid: Pid, message: &EncodedMessage) {
+        if let Some(process) = self.processes.process_by_id(emitter_pid) {
+            let counters = &mut process.user_data().borrow_mut().counters;
+            counters.messages_emitted += 1;
+            counters.message_bytes_emitted += u64::try_from(message.0.len()).unwrap();
+        }
+    }
+
+    /// Assigns a new [`MessageId`] to a message that expects an answer, and marks the given
+    /// process as its emitter.
+    fn allocate_message_id(&self, emitter_pid: Pid) -> MessageId {
+        loop {
+            let id: MessageId = self.message_id_pool.assign();
+            if u64::from(id) == 0 || u64::from(id) == 1 {
+                continue;
+            }
+            match self.messages_to_answer.borrow_mut().entry(id) {
+                Entry::Occupied(_) => continue,
+                Entry::Vacant(e) => e.insert(emitter_pid),
+            };
+            break id;
+        }
+    }
+
+    /// Changes the configuration of the inbox of the given process. Pass `None` for an
+    /// unbounded inbox.
+    ///
+    /// The new configuration only applies to the messages emitted afterwards. Messages already
+    /// in the inbox are never discarded because of this change.
+    ///
+    /// Returns an error if there is no process with this [`Pid`].
+    pub fn set_inbox_config(&self, pid: Pid, config: Option<InboxConfig>) -> Result<(), ()> {
+        let process = self.processes.process_by_id(pid).ok_or(())?;
+        process.user_data().borrow_mut().inbox = config;
+        // Threads blocked with the previous configuration are unblocked in `run`, if possible.
+        Ok(())
+    }
+
+    /// Removes the oldest interface message from the inbox of the given process, and answers it
+    /// with an error.
+    fn drop_oldest_interface_message(
+        &self,
+        process: &extrinsics::ProcessesCollectionExtrinsicsProc<
+            RefCell<Process>,
+            (),
+            crate::extrinsics::wasi::WasiExtrinsics,
+        >,
+    ) {
+        let message_id = process
+            .user_data()
+            .borrow_mut()
+            .drop_oldest_interface_message();
+        if let Some(message_id) = message_id {
+            if let Some(event) = self.answer_message_inner(message_id, Err(())) {
+                self.pending_events.push(event);
+            }
+        }
+    }
+
+    /// Delivers the messages of the threads paused because of [`OverflowPolicy::Block`] to the
+    /// inboxes that now have space for them.
+    fn unblock_emitters(&self) {
+        if self.blocked_inboxes.borrow().is_empty() {
+            return;
+        }
+
+        let pids = self
+            .blocked_inboxes
+            .borrow()
+            .iter()
+            .cloned()
+            .collect::<Vec<_>>();
+        for pid in pids {
+            let process = match self.processes.process_by_id(pid) {
+                Some(p) => p,
+                None => {
+                    self.blocked_inboxes.borrow_mut().remove(&pid);
+                    continue;
+                }
+            };
+
+            loop {
+                let thread_id = {
+                    let mut user_data = process.user_data().borrow_mut();
+                    if user_data.inbox_is_full() {
+                        break;
+                    }
+                    match user_data.blocked_emitters.pop_front() {
+                        Some(t) => t,
+                        None => break,
+                    }
+                };
+
+                // The emitter might have been killed in the meantime.
+                let mut thread = match self.processes.interrupted_thread_by_id(thread_id) {
+                    Ok(extrinsics::ProcessesCollectionExtrinsicsThread::EmitMessage(t)) => t,
+                    _ => continue,
+                };
+
+                let emitter_pid = thread.pid();
+                let interface = thread.emit_interface().clone();
+                let message_id = if thread.needs_answer() {
+                    Some(self.allocate_message_id(emitter_pid))
+                } else {
+                    None
+                };
+
+                let message = thread.accept_emit(message_id);
+                self.record_emitted(emitter_pid, &message);
+                let notif = From::from(redshirt_syscalls::ffi::build_interface_notification(
+                    &interface,
+                    message_id,
+                    emitter_pid,
+                    0,
+                    &message,
+                ));
+                push_notification(&process, notif);
+            }
+
+            if process.user_data().borrow().blocked_emitters.is_empty() {
+                self.blocked_inboxes.borrow_mut().remove(&pid);
+            }
+            try_resume_notification_wait(process);
+        }
+    }
+
     ///
     ///
     /// It is forbidden to answer messages created using [`Core::emit_interface_message_answer`] or
@@ -638,9 +1082,11 @@ impl Core {
     /// answered through this method.
     // TODO: better API
     pub fn answer_message(&self, message_id: MessageId, response: Result<EncodedMessage, ()>) {
-        let ret = self.answer_message_inner(message_id, response);
-        // TODO: ret can be none if message has been cancelled
-        //assert!(ret.is_none());
+        // Answers to messages emitted by the kernel itself are reported back through `run`.
+        // Note that `None` is also returned if the message has been cancelled.
+        if let Some(event) = self.answer_message_inner(message_id, response) {
+            self.pending_events.push(event);
+        }
     }
 
     // TODO: better API
@@ -661,11 +1107,7 @@ impl Core {
                     },
                 ));
 
-                process
-                    .user_data()
-                    .borrow_mut()
-                    .notifications_queue
-                    .push_back(notif);
+                push_notification(&process, notif);
                 process
                     .user_data()
                     .borrow_mut()
@@ -686,23 +1128,239 @@ impl Core {
         }
     }
 
+    /// Delivers one of the answers to a message, without removing the message from the list of
+    /// messages to answer. The emitter receives it as a normal response notification.
+    ///
+    /// Returns `false` if the message doesn't exist (for example because it has been cancelled),
+    /// or if its emitter isn't a process. Native programs can only receive a single answer.
+    fn answer_message_chunk_inner(&self, message_id: MessageId, response: &EncodedMessage) -> bool {
+        let emitter_pid = match self.messages_to_answer.borrow().get(&message_id) {
+            Some(pid) => *pid,
+            None => return false,
+        };
+
+        let process = match self.processes.process_by_id(emitter_pid) {
+            Some(p) => p,
+            None => return false,
+        };
+
+        let notif = From::from(redshirt_syscalls::ffi::build_response_notification(
+            message_id,
+            // We a dummy value here and fill it up later when actually delivering the notif.
+            0,
+            Ok(response),
+        ));
+
+        push_notification(&process, notif);
+        try_resume_notification_wait(process);
+        true
+    }
+
+    /// Indicates to the emitter of a message that no more answer will be delivered, and removes
+    /// the message from the list of messages to answer.
+    ///
+    /// Emitters that aren't processes never receive answer chunks, and are delivered an error
+    /// instead.
+    fn close_answer_inner(&self, message_id: MessageId) -> Option<CoreRunOutcome> {
+        let emitter_pid = self.messages_to_answer.borrow_mut().remove(&message_id)?;
+
+        if let Some(process) = self.processes.process_by_id(emitter_pid) {
+            let notif = From::from(redshirt_syscalls::ffi::build_response_closed_notification(
+                message_id,
+                // We a dummy value here and fill it up later when actually delivering the notif.
+                0,
+            ));
+
+            push_notification(&process, notif);
+            process
+                .user_data()
+                .borrow_mut()
+                .emitted_messages
+                .retain(|m| *m != message_id);
+            try_resume_notification_wait(process);
+            None
+        } else {
+            Some(CoreRunOutcome::MessageResponse {
+                message_id,
+                response: Err(()),
+            })
+        }
+    }
+
     /// Start executing the module passed as parameter.
     ///
     /// Each import of the [`Module`](crate::module::Module) is resolved.
     pub fn execute(&self, module: &Module) -> Result<CoreProcess, vm::NewErr> {
+        let prepared = self.prepare(module)?;
+        Ok(self.execute_prepared(prepared))
+    }
+
+    /// Same as [`Core::execute`], but the process can only import the extrinsics that are in
+    /// the given allowlist, in addition to the syscalls of the `redshirt` interface.
+    pub fn execute_with_allowlist(
+        &self,
+        module: &Module,
+        allowlist: &ExtrinsicsAllowlist,
+    ) -> Result<CoreProcess, vm::NewErr> {
+        let prepared = self.prepare_with_allowlist(module, allowlist)?;
+        Ok(self.execute_prepared(prepared))
+    }
+
+    /// Start executing a process composed of the main module passed as parameter and of the
+    /// given libraries.
+    ///
+    /// The imports of the modules whose namespace is the name of a library are resolved with the
+    /// exports of that library. See
+    /// [`ProcessStateMachine::new_linked`](vm::ProcessStateMachine::new_linked) for details.
+    pub fn execute_linked(
+        &self,
+        module: &Module,
+        libraries: &[(&str, &Module)],
+    ) -> Result<CoreProcess, vm::NewErr> {
+        let inner = self.processes.prepare_linked(module, libraries, ())?;
+        Ok(self.execute_prepared(CorePreparedProcess {
+            inner,
+            declared_interfaces: declared_interfaces(module),
+            capabilities: None,
+        }))
+    }
+
+    /// Checks whether the imports of the given module can be resolved.
+    ///
+    /// Modules for which the report isn't compatible are refused by [`Core::execute`] and
+    /// [`Core::prepare`].
+    pub fn abi_report(&self, module: &Module) -> AbiReport {
+        self.processes.abi_report(module)
+    }
+
+    /// Same as [`Core::abi_report`], but imports that aren't in the allowlist are reported as
+    /// unknown.
+    pub fn abi_report_with_allowlist(
+        &self,
+        module: &Module,
+        allowlist: &ExtrinsicsAllowlist,
+    ) -> AbiReport {
+        self.processes.abi_report_with_allowlist(module, allowlist)
+    }
+
+    /// Instantiates the module passed as parameter, without starting it.
+    ///
+    /// The returned process can later be started with [`Core::execute_prepared`]. Since the
+    /// instantiation is the costly part of starting a process, this makes it possible to do the
+    /// work in advance.
+    pub fn prepare(&self, module: &Module) -> Result<CorePreparedProcess, vm::NewErr> {
+        let inner = self.processes.prepare(module, ())?;
+        Ok(CorePreparedProcess {
+            inner,
+            declared_interfaces: declared_interfaces(module),
+            capabilities: None,
+        })
+    }
+
+    /// Same as [`Core::prepare`], but the process can only import the extrinsics that are in
+    /// the given allowlist.
+    pub fn prepare_with_allowlist(
+        &self,
+        module: &Module,
+        allowlist: &ExtrinsicsAllowlist,
+    ) -> Result<CorePreparedProcess, vm::NewErr> {
+        let inner = self
+            .processes
+            .prepare_with_allowlist(module, allowlist, ())?;
+        Ok(CorePreparedProcess {
+            inner,
+            declared_interfaces: declared_interfaces(module),
+            capabilities: None,
+        })
+    }
+
+    /// Checks the imports of the given module, and returns a [`CachedModule`] that can then be
+    /// passed to [`Core::execute_cached`].
+    ///
+    /// Starting many processes from the same module is cheaper with a cached module than with
+    /// [`Core::execute`].
+    pub fn cache_module(&self, module: Module) -> Result<CachedModule, vm::NewErr> {
+        self.processes.cache_module(module)
+    }
+
+    /// Same as [`Core::execute`], but for a module that has been cached with
+    /// [`Core::cache_module`].
+    pub fn execute_cached(&self, cached: &CachedModule) -> Result<CoreProcess<'_>, vm::NewErr> {
+        let inner = self.processes.prepare_cached(cached, ())?;
+        Ok(self.execute_prepared(CorePreparedProcess {
+            inner,
+            declared_interfaces: declared_interfaces(cached.module()),
+            capabilities: None,
+        }))
+    }
+
+    /// Same as [`Core::execute`], but the new process is a child of `parent`.
+    ///
+    /// When `parent` ends, the new process is either killed or becomes a child of the parent of
+    /// `parent`, depending on the [`OrphanPolicy`].
+    pub fn execute_child(&self, module: &Module, parent: Pid) -> Result<CoreProcess, vm::NewErr> {
+        let prepared = self.prepare(module)?;
+        Ok(self.execute_prepared_inner(prepared, Some(parent)))
+    }
+
+    /// Starts executing a process created with [`Core::prepare`].
+    pub fn execute_prepared(&self, prepared: CorePreparedProcess) -> CoreProcess {
+        self.execute_prepared_inner(prepared, None)
+    }
+
+    /// Same as [`Core::execute_prepared`], but the new process is a child of `parent`. See
+    /// [`Core::execute_child`].
+    pub fn execute_prepared_child(
+        &self,
+        prepared: CorePreparedProcess,
+        parent: Pid,
+    ) -> CoreProcess<'_> {
+        self.execute_prepared_inner(prepared, Some(parent))
+    }
+
+    /// Implementation of [`Core::execute_prepared`], [`Core::execute_prepared_child`] and
+    /// [`Core::execute_child`].
+    fn execute_prepared_inner(
+        &self,
+        prepared: CorePreparedProcess,
+        parent: Option<Pid>,
+    ) -> CoreProcess {
         let proc_metadata = Process {
             notifications_queue: VecDeque::new(),
             registered_interfaces: SmallVec::new(),
             used_interfaces: HashSet::with_hasher(Default::default()),
+            declared_interfaces: prepared.declared_interfaces,
+            capabilities: prepared.capabilities,
             emitted_messages: SmallVec::new(),
             messages_to_answer: SmallVec::new(),
+            inbox: self.default_inbox.clone(),
+            max_pending_messages: self.process_config.max_pending_messages,
+            blocked_emitters: VecDeque::new(),
+            counters: Default::default(),
         };
 
-        let process = self
-            .processes
-            .execute(module, RefCell::new(proc_metadata), ())?;
+        let process = self.processes.execute_prepared(
+            prepared.inner,
+            parent,
+            self.process_config.clone(),
+            RefCell::new(proc_metadata),
+        );
 
-        Ok(CoreProcess { process })
+        CoreProcess { process }
+    }
+}
+
+impl CorePreparedProcess {
+    /// Restricts the process to the given interfaces. Once started, the process can only emit
+    /// messages on these interfaces and on the ones that other processes delegate to it.
+    ///
+    /// Processes for which this method isn't called can emit messages on any interface.
+    pub fn with_capabilities(
+        mut self,
+        interfaces: impl IntoIterator<Item = InterfaceHash>,
+    ) -> Self {
+        self.capabilities = Some(interfaces.into_iter().collect());
+        self
     }
 }
 
@@ -712,21 +1370,117 @@ impl<'a> CoreProcess<'a> {
         self.process.pid()
     }
 
+    /// Returns the interfaces that the process is restricted to, or `None` if it isn't
+    /// restricted. See [`CorePreparedProcess::with_capabilities`].
+    pub fn capabilities(&self) -> Option<Vec<InterfaceHash>> {
+        let user_data = self.process.user_data().borrow();
+        let capabilities = user_data.capabilities.as_ref()?;
+        Some(capabilities.iter().cloned().collect())
+    }
+
     /// Adds a new thread to the process, starting the function with the given index and passing
-    /// the given parameters.
+    /// the given parameters. The name, if any, is used for diagnostic purposes.
     // TODO: don't expose crate::WasmValue
     pub fn start_thread(
         self,
         fn_index: u32,
         params: Vec<crate::WasmValue>,
+        name: Option<String>,
     ) -> Result<(), vm::StartErr> {
-        self.process.start_thread(fn_index, params, ())?;
+        self.process.start_thread(fn_index, params, name, ())?;
         Ok(())
     }
 
-    /// Kills the process immediately.
+    /// Sets the priority of the process. Processes with a higher priority run more often than
+    /// processes with a lower priority. Defaults to
+    /// [`DEFAULT_PRIORITY`](crate::scheduler::processes::DEFAULT_PRIORITY).
+    pub fn set_priority(&self, priority: u32) {
+        self.process.set_priority(priority);
+    }
+
+    /// Returns the parent of this process, if any. See [`Core::execute_child`].
+    pub fn parent(&self) -> Option<Pid> {
+        self.process.parent()
+    }
+
+    /// Returns the processes whose parent is this one. See [`Core::execute_child`].
+    pub fn children(&self) -> Vec<Pid> {
+        self.process.children()
+    }
+
+    /// Returns the statistics about the activity of the process.
+    pub fn counters(&self) -> ProcessCounters {
+        self.process.user_data().borrow().counters.clone()
+    }
+
+    /// Returns the size of the memory of the process, in bytes, or 0 if it doesn't have any.
+    pub fn memory_size(&self) -> u64 {
+        self.process.memory_size()
+    }
+
+    /// Returns the number of threads of the process, including the main thread.
+    pub fn num_threads(&self) -> usize {
+        self.process.num_threads()
+    }
+
+    /// Captures a snapshot of the memory of the process.
+    ///
+    /// Compare two snapshots with [`MemorySnapshot::diff`] in order to find out how the memory
+    /// of the process has evolved in between.
+    pub fn memory_snapshot(&self) -> MemorySnapshot {
+        self.process.memory_snapshot()
+    }
+
+    /// Kills the process.
+    ///
+    /// The process is destroyed the next time [`Core::run`] is called, and is then reported as
+    /// a [`CoreRunOutcome::ProgramFinished`] whose outcome is an error.
     pub fn abort(&self) {
-        self.process.abort(); // TODO: clean up
+        self.process.abort();
+    }
+}
+
+impl Process {
+    /// Returns `true` if no more interface message can be pushed to the inbox.
+    fn inbox_is_full(&self) -> bool {
+        let config = match &self.inbox {
+            Some(c) => c,
+            None => return false,
+        };
+
+        let len = self
+            .notifications_queue
+            .iter()
+            .filter(|n| matches!(n, redshirt_syscalls::ffi::NotificationBuilder::Interface(_)))
+            .count();
+        len >= config.capacity.max(1)
+    }
+
+    /// Returns the policy to apply to a new interface message, or `None` if it can be pushed to
+    /// the inbox.
+    fn inbox_overflow(&self) -> Option<OverflowPolicy> {
+        let config = self.inbox.as_ref()?;
+        // If threads are already blocked, new messages must wait behind them.
+        if self.inbox_is_full() || !self.blocked_emitters.is_empty() {
+            Some(config.overflow)
+        } else {
+            None
+        }
+    }
+
+    /// Removes the oldest interface message from the inbox. Returns its identifier if it was
+    /// expecting an answer.
+    fn drop_oldest_interface_message(&mut self) -> Option<MessageId> {
+        let index = self
+            .notifications_queue
+            .iter()
+            .position(|n| matches!(n, redshirt_syscalls::ffi::NotificationBuilder::Interface(_)))?;
+        match self.notifications_queue.remove(index) {
+            Some(redshirt_syscalls::ffi::NotificationBuilder::Interface(notif)) => {
+                notif.message_id()
+            }
+            _ => unreachable!(),
+        }
     }
 }
 
@@ -744,6 +1498,37 @@ impl CoreBuilder {
         pid
     }
 
+    /// Sets the configuration of the inbox of the processes created afterwards. By default, the
+    /// inbox is unbounded.
+    pub fn with_default_inbox(mut self, config: InboxConfig) -> Self {
+        self.default_inbox = Some(config);
+        self
+    }
+
+    /// Sets the limits on the resources that the processes created afterwards can use. By
+    /// default, the resources are unbounded.
+    ///
+    /// Processes that exceed these limits are stopped, and reported as a
+    /// [`CoreRunOutcome::ProgramFinished`] with an [`ExitStatus::LimitExceeded`].
+    pub fn with_process_config(mut self, config: ProcessConfig) -> Self {
+        self.process_config = config;
+        self
+    }
+
+    /// Sets the limits that modules must respect in order to be instantiated. Modules that
+    /// exceed them are rejected with an error.
+    pub fn with_module_limits(mut self, limits: vm::ModuleLimits) -> Self {
+        self.inner_builder = self.inner_builder.with_module_limits(limits);
+        self
+    }
+
+    /// Sets what happens to the processes started with [`Core::execute_child`] when their
+    /// parent ends. Defaults to [`OrphanPolicy::Reparent`].
+    pub fn with_orphan_policy(mut self, policy: OrphanPolicy) -> Self {
+        self.inner_builder = self.inner_builder.with_orphan_policy(policy);
+        self
+    }
+
     /// Turns the builder into a [`Core`].
     pub fn build(mut self) -> Core {
         self.reserved_pids.shrink_to_fit();
@@ -752,15 +1537,60 @@ impl CoreBuilder {
             pending_events: SegQueue::new(),
             processes: self.inner_builder.build(),
             interfaces: RefCell::new(Default::default()),
+            interface_overrides: RefCell::new(Default::default()),
             reserved_pids: self.reserved_pids,
             message_id_pool: IdPool::new(),
             messages_to_answer: RefCell::new(HashMap::default()),
+            default_inbox: self.default_inbox,
+            process_config: self.process_config,
+            blocked_inboxes: RefCell::new(HashSet::with_hasher(Default::default())),
+        }
+    }
+}
+
+/// Returns the interfaces that processes running the given module are allowed to emit messages
+/// on. See [`Process::declared_interfaces`].
+fn declared_interfaces(module: &Module) -> Option<HashSet<InterfaceHash, FnvBuildHasher>> {
+    module
+        .required_interfaces()
+        .map(|interfaces| interfaces.iter().cloned().collect())
+}
+
+/// Returns true if the given process is allowed to emit messages on the given interface. See
+/// [`Process::capabilities`].
+fn has_capability(process: &Process, interface: &InterfaceHash) -> bool {
+    match &process.capabilities {
+        Some(capabilities) => capabilities.contains(interface),
+        None => true,
+    }
+}
+
+/// Pushes a notification at the back of the queue of the given process.
+///
+/// If the queue is then longer than [`ProcessConfig::max_pending_messages`], the process is
+/// killed with [`LimitExceeded::PendingMessages`].
+fn push_notification(
+    process: &extrinsics::ProcessesCollectionExtrinsicsProc<
+        RefCell<Process>,
+        (),
+        crate::extrinsics::wasi::WasiExtrinsics,
+    >,
+    notif: redshirt_syscalls::ffi::NotificationBuilder,
+) {
+    let mut user_data = process.user_data().borrow_mut();
+    user_data.notifications_queue.push_back(notif);
+    if let Some(max) = user_data.max_pending_messages {
+        if user_data.notifications_queue.len() > max {
+            process.exceed_limit(LimitExceeded::PendingMessages { max });
         }
     }
 }
 
 /// If any of the threads of the given process is waiting for a message to arrive, checks the
 /// queue and tries to resume said thread.
+///
+/// Threads resumed this way have been woken up by the delivery of a notification, and are
+/// boosted in order to reduce the latency of their reaction.
 fn try_resume_notification_wait(
     process: extrinsics::ProcessesCollectionExtrinsicsProc<
         RefCell<Process>,
@@ -772,13 +1602,16 @@ fn try_resume_notification_wait(
     //       round-robin-ness instead?
     for thread in process.interrupted_threads() {
         if let extrinsics::ProcessesCollectionExtrinsicsThread::WaitNotification(t) = thread {
-            try_resume_notification_wait_thread(t)
+            try_resume_notification_wait_thread(t, true)
         }
     }
 }
 
 /// If the given thread is waiting for a notification to arrive, checks the queue and tries to
 /// resume said thread.
+///
+/// If `boost` is true and the thread is resumed with a notification, the thread is
+/// [boosted](extrinsics::ProcessesCollectionExtrinsicsThreadWaitNotification::boost).
 // TODO: in order to call this function, we essentially have to put the state machine in a "bad"
 // state (notifications in queue and thread would accept said notification); not great
 fn try_resume_notification_wait_thread(
@@ -787,6 +1620,7 @@ fn try_resume_notification_wait_thread(
         (),
         crate::extrinsics::wasi::WasiExtrinsics,
     >,
+    boost: bool,
 ) {
     // Try to find a notification in the queue that matches something the user is waiting for.
     let mut index_in_queue = 0;
@@ -826,6 +1660,10 @@ fn try_resume_notification_wait_thread(
 
     // If we reach here, we have found a notification that matches what the user wants.
 
+    if boost {
+        thread.boost();
+    }
+
     let notif_length =
         thread.process_user_data().borrow_mut().notifications_queue[index_in_queue].len();
 
@@ -841,8 +1679,13 @@ fn try_resume_notification_wait_thread(
 
         // Adjust the `index_in_list` field of the notification to match what we have.
         notification.set_index_in_list(u32::try_from(index_in_msg_ids).unwrap());
-        // TODO: crappy to pass an EncodedMessage
-        thread.resume_notification(index_in_msg_ids, EncodedMessage(notification.into_bytes()))
+
+        let mut user_data = thread.process_user_data().borrow_mut();
+        user_data.counters.notifications_received += 1;
+        user_data.counters.notification_bytes_received += u64::try_from(notif_length).unwrap();
+        drop(user_data);
+
+        thread.resume_notification(index_in_msg_ids, notification.as_bytes())
     } else {
         thread.resume_notification_too_big(notif_length)
     }
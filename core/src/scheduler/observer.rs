@@ -0,0 +1,69 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Hooks for observing the activity of the scheduler.
+//!
+//! An observer registered with
+//! [`ProcessesCollectionBuilder::with_observer`](crate::scheduler::processes::ProcessesCollectionBuilder::with_observer)
+//! is informed of everything the scheduler does, which makes it possible to build tracing or
+//! profiling tools without modifying the scheduler.
+
+use redshirt_syscalls::{Pid, ThreadId};
+
+/// Receives events about the activity of a
+/// [`ProcessesCollection`](crate::scheduler::processes::ProcessesCollection).
+///
+/// All the methods do nothing by default.
+pub trait SchedulerObserver<TExtr> {
+    /// Returns the current time, in an arbitrary unit. Used to compute the durations passed to
+    /// [`SchedulerObserver::extrinsic_returned`].
+    ///
+    /// Always returns 0 by default, in which case all the durations are 0.
+    fn now(&mut self) -> u64 {
+        0
+    }
+
+    /// A process has been created. See [`ProcessesCollectionProc::name`] for the meaning of
+    /// `name`.
+    ///
+    /// [`ProcessesCollectionProc::name`]: crate::scheduler::processes::ProcessesCollectionProc::name
+    fn process_created(&mut self, _pid: Pid, _name: Option<&str>) {}
+
+    /// A process has stopped, either because its main thread has finished, because of an
+    /// error, or because it has been killed.
+    fn process_destroyed(&mut self, _pid: Pid) {}
+
+    /// A thread is about to run. `name` is the name the thread has been started with, if any.
+    fn thread_scheduled(&mut self, _pid: Pid, _thread_id: ThreadId, _name: Option<&str>) {}
+
+    /// A thread has been paused because it has used up its fuel.
+    fn thread_interrupted(&mut self, _pid: Pid, _thread_id: ThreadId) {}
+
+    /// A thread has finished.
+    ///
+    /// > **Note**: This isn't called for the threads that are still alive when their process is
+    /// >           destroyed.
+    fn thread_finished(&mut self, _pid: Pid, _thread_id: ThreadId) {}
+
+    /// A thread has called an extrinsic, and is paused until it is resumed with the result.
+    fn extrinsic_called(&mut self, _pid: Pid, _thread_id: ThreadId, _extrinsic: &TExtr) {}
+
+    /// A thread that has called an extrinsic is running again. `duration` is the difference
+    /// between the values of [`SchedulerObserver::now`] when the extrinsic was called and now.
+    ///
+    /// > **Note**: The duration includes the time the thread has spent waiting for its turn
+    /// >           after having been resumed.
+    fn extrinsic_returned(&mut self, _pid: Pid, _thread_id: ThreadId, _duration: u64) {}
+}
@@ -14,15 +14,23 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 use crate::id_pool::IdPool;
-use crate::module::Module;
-use crate::scheduler::vm;
+use crate::module::{AbiReport, ImportKind, Module, ModuleHash};
+use crate::scheduler::{observer::SchedulerObserver, self_check::Violation, vm};
 use crate::signature::Signature;
-use alloc::{borrow::Cow, vec::Vec};
-use core::fmt;
+use crate::{TrapError, TrapKind};
+use alloc::{borrow::Cow, boxed::Box, collections::VecDeque, string::String, vec::Vec};
+use core::{
+    cmp, fmt,
+    future::Future,
+    iter,
+    ops::Range,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
 use fnv::FnvBuildHasher;
 use hashbrown::{
     hash_map::{Entry, OccupiedEntry},
-    HashMap,
+    HashMap, HashSet,
 };
 use nohash_hasher::BuildNoHashHasher;
 use redshirt_syscalls::{Pid, ThreadId};
@@ -36,10 +44,11 @@ use redshirt_syscalls::{Pid, ThreadId};
 /// process and per thread, and allows the user to put extra information associated to a process
 /// or a thread.
 pub struct ProcessesCollection<TExtr, TPud, TTud> {
-    /// Allocations of process IDs.
+    /// Allocations of process IDs. A [`Pid`] is never assigned twice, which guarantees that a
+    /// [`Pid`] that is still in use refers to the same process as when it was stored.
     pid_pool: IdPool,
 
-    /// Allocation of thread IDs.
+    /// Allocation of thread IDs. Same as for `pid_pool`, a [`ThreadId`] is never assigned twice.
     tid_pool: IdPool,
 
     /// List of running processes.
@@ -56,6 +65,85 @@ pub struct ProcessesCollection<TExtr, TPud, TTud> {
     /// This field is never modified after the [`ProcessesCollection`] is created.
     extrinsics_id_assign:
         HashMap<(Cow<'static, str>, Cow<'static, str>), (usize, Signature), FnvBuildHasher>,
+
+    /// Number of times [`ProcessesCollection::run`] has been called. Used to determine whether
+    /// a thread is resumed without any other thread having run in between.
+    run_counter: u64,
+
+    /// Number of consecutive times a thread must be resumed without blocking before it is
+    /// demoted. See [`ProcessesCollectionBuilder::with_demotion_threshold`].
+    demotion_threshold: u32,
+
+    /// Limits enforced when instantiating modules.
+    module_limits: vm::ModuleLimits,
+
+    /// See [`ProcessesCollectionBuilder::with_max_memory_pages`].
+    max_memory_pages: Option<u32>,
+
+    /// See [`ProcessesCollectionBuilder::with_max_stack_depth`].
+    max_stack_depth: Option<u32>,
+
+    /// Parent and children of each process.
+    ///
+    /// > **Note**: This is kept separate from [`ProcessesCollection::processes`] so that it can
+    /// >           be updated while a process is borrowed.
+    lineages: Lineages,
+
+    /// Threads that are ready to run.
+    ready: ReadyQueues,
+
+    /// Informed of the activity of the collection, if any.
+    /// See [`ProcessesCollectionBuilder::with_observer`].
+    observer: Option<Box<dyn SchedulerObserver<TExtr>>>,
+}
+
+/// Parent and children of the processes of a [`ProcessesCollection`].
+struct Lineages {
+    /// Every process of the collection has an entry.
+    entries: HashMap<Pid, Lineage, BuildNoHashHasher<u64>>,
+
+    /// What happens to the children of a process when it ends.
+    orphan_policy: OrphanPolicy,
+}
+
+/// Parent and children of a process.
+struct Lineage {
+    /// Process that has started this one, if any. Always refers to a process of the collection.
+    parent: Option<Pid>,
+
+    /// Processes whose [`Lineage::parent`] is this one.
+    children: Vec<Pid>,
+}
+
+/// Queues of threads that are ready to run, in the order in which they should run.
+///
+/// Entries aren't removed when a thread stops being ready, when it is moved to a different
+/// queue, or when its process stops. Instead, entries whose ticket doesn't match
+/// [`Thread::ticket`] are ignored when they are popped.
+#[derive(Default)]
+struct ReadyQueues {
+    /// Threads that have been [boosted](ProcessesCollectionThread::boost).
+    boosted: VecDeque<ReadyEntry>,
+    /// Threads that haven't been boosted. Threads that turn out to be deferred or demoted when
+    /// they are popped are moved to `background`.
+    normal: VecDeque<ReadyEntry>,
+    /// Threads that have been [deferred](ProcessesCollectionThread::defer) or demoted.
+    background: VecDeque<ReadyEntry>,
+    /// Ticket to assign to the next entry.
+    next_ticket: u64,
+    /// Waker of the [`RunAsync`] future that is waiting for a thread to become ready, if any.
+    /// Woken up when an entry is pushed.
+    waker: Option<Waker>,
+}
+
+/// Entry in one of the [`ReadyQueues`].
+struct ReadyEntry {
+    /// Process the thread belongs to.
+    pid: Pid,
+    /// Thread that is ready.
+    thread_id: ThreadId,
+    /// Must match [`Thread::ticket`] for the entry to be valid.
+    ticket: u64,
 }
 
 /// Prototype for a `ProcessesCollection` under construction.
@@ -67,6 +155,193 @@ pub struct ProcessesCollectionBuilder<TExtr> {
     /// See the corresponding field in `ProcessesCollection`.
     extrinsics_id_assign:
         HashMap<(Cow<'static, str>, Cow<'static, str>), (usize, Signature), FnvBuildHasher>,
+    /// See the corresponding field in `ProcessesCollection`.
+    demotion_threshold: u32,
+    /// See the corresponding field in `ProcessesCollection`.
+    module_limits: vm::ModuleLimits,
+    /// See the corresponding field in `ProcessesCollection`.
+    max_memory_pages: Option<u32>,
+    /// See the corresponding field in `ProcessesCollection`.
+    max_stack_depth: Option<u32>,
+    /// See the corresponding field in `ProcessesCollection`.
+    orphan_policy: OrphanPolicy,
+    /// See the corresponding field in `ProcessesCollection`.
+    observer: Option<Box<dyn SchedulerObserver<TExtr>>>,
+}
+
+/// Subset of the extrinsics registered in a [`ProcessesCollectionBuilder`] that a process is
+/// allowed to import.
+///
+/// Passing an allowlist when starting a process makes it possible to register extrinsics that
+/// only some designated processes can use. Imports that aren't in the allowlist are treated
+/// the same way as imports that don't correspond to any extrinsic.
+#[derive(Debug, Clone, Default)]
+pub struct ExtrinsicsAllowlist {
+    /// Interfaces whose functions are all allowed.
+    interfaces: HashSet<Cow<'static, str>, FnvBuildHasher>,
+    /// Individual functions that are allowed, in addition to the ones in `interfaces`.
+    functions: HashSet<(Cow<'static, str>, Cow<'static, str>), FnvBuildHasher>,
+}
+
+/// What happens to the children of a process when this process ends.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum OrphanPolicy {
+    /// The children become children of the parent of the process that has ended, or have no
+    /// parent if it didn't have one.
+    Reparent,
+    /// The children no longer have any parent, and are reported in the outcome of
+    /// [`ProcessesCollection::run`] or [`ProcessesCollection::kill_process`] so that they can
+    /// be killed in turn.
+    Kill,
+}
+
+/// Configuration of a single process, mostly consisting of limits on the resources that it is
+/// allowed to use.
+///
+/// A limit set to `None` means that the corresponding resource is unbounded.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProcessConfig {
+    /// Human-readable name of the process, used for diagnostic purposes. If `None`, the
+    /// [name of the module](Module::name) is used, if any.
+    pub name: Option<String>,
+
+    /// Maximum number of 64kiB pages of memory. The process is stopped with
+    /// [`LimitExceeded::MemoryPages`] if its memory grows beyond this value.
+    ///
+    /// The limit passed to [`ProcessesCollectionBuilder::with_max_memory_pages`], if any, applies
+    /// as well.
+    pub max_memory_pages: Option<u32>,
+
+    /// Maximum number of threads, including the main thread. Starting a thread beyond this value
+    /// fails with [`vm::StartErr::TooManyThreads`].
+    pub max_threads: Option<usize>,
+
+    /// Maximum number of messages waiting to be picked up by the process.
+    ///
+    /// > **Note**: This collection doesn't know about messages. This limit is enforced by the
+    /// >           layers above, which stop the process with
+    /// >           [`LimitExceeded::PendingMessages`].
+    pub max_pending_messages: Option<usize>,
+}
+
+/// Error reported as the outcome of a process that has exceeded one of the limits of its
+/// [`ProcessConfig`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LimitExceeded {
+    /// The memory has grown beyond [`ProcessConfig::max_memory_pages`].
+    MemoryPages {
+        /// Maximum allowed number of pages.
+        max: u32,
+    },
+    /// A thread has made too many nested function calls. See
+    /// [`ProcessesCollectionBuilder::with_max_stack_depth`].
+    StackDepth {
+        /// Maximum allowed number of nested calls, or `None` if it is the limits of the
+        /// interpreter on the size of the stack that have been reached.
+        max: Option<u32>,
+    },
+    /// Too many messages were waiting for the process. See
+    /// [`ProcessConfig::max_pending_messages`].
+    PendingMessages {
+        /// Maximum allowed number of messages.
+        max: usize,
+    },
+}
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LimitExceeded::MemoryPages { max } => {
+                write!(f, "Memory grew beyond the limit of {} pages", max)
+            }
+            LimitExceeded::StackDepth { max: Some(max) } => {
+                write!(f, "More than {} nested function calls", max)
+            }
+            LimitExceeded::StackDepth { max: None } => write!(f, "Stack overflow"),
+            LimitExceeded::PendingMessages { max } => {
+                write!(f, "More than {} messages waiting to be processed", max)
+            }
+        }
+    }
+}
+
+/// Resources used by all the processes of a collection. See
+/// [`ProcessesCollection::resource_usage`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ResourceUsage {
+    /// Number of processes.
+    pub num_processes: usize,
+    /// Total number of threads, including the main threads.
+    pub num_threads: usize,
+    /// Total size, in bytes, of the memory of the processes.
+    pub memory_bytes: u64,
+}
+
+/// Process whose virtual machine has been instantiated, but that hasn't been inserted in a
+/// [`ProcessesCollection`] yet.
+///
+/// Its main thread is paused at the start of the "_start" function of the module.
+pub struct PreparedProcess<TTud> {
+    /// State of the process.
+    state_machine: vm::ProcessStateMachine<Thread<TTud>>,
+
+    /// Name of the main module, if any.
+    name: Option<String>,
+
+    /// Hash of the main module.
+    module_hash: ModuleHash,
+}
+
+/// Error that can happen when calling [`ProcessesCollection::fork`].
+#[derive(Debug)]
+pub enum ForkErr {
+    /// There is no process with the given [`Pid`].
+    ProcessNotFound,
+    /// The module isn't the one the process has been started from, or the process is composed
+    /// of libraries.
+    ModuleMismatch,
+    /// Error while instantiating the module.
+    New(vm::NewErr),
+    /// Error while starting the entry point.
+    Start(vm::StartErr),
+}
+
+impl fmt::Display for ForkErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ForkErr::ProcessNotFound => write!(f, "Process not found"),
+            ForkErr::ModuleMismatch => write!(f, "Module doesn't match the forked process"),
+            ForkErr::New(err) => write!(f, "{}", err),
+            ForkErr::Start(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+/// Module whose imports have been resolved against the extrinsics of a [`ProcessesCollection`].
+/// Obtained by calling [`ProcessesCollection::cache_module`].
+///
+/// Starting a process from a cached module skips verifying the module against the extrinsics
+/// and looking up the extrinsics by name, which makes it cheaper to start many processes from
+/// the same module.
+///
+/// > **Note**: The interpreter doesn't support duplicating an instantiated module. Each process
+/// >           started from a cached module is still instantiated, with a fresh memory.
+///
+/// A cached module must only be used with the [`ProcessesCollection`] that has created it.
+pub struct CachedModule {
+    /// The module itself.
+    module: Module,
+
+    /// Interface, function name, and key in [`ProcessesCollection::extrinsics`] of each function
+    /// imported by the module that corresponds to an extrinsic. Looked up linearly, as modules
+    /// typically have few imports.
+    extrinsics: Vec<(String, String, usize)>,
+}
+
+/// Collection of [`CachedModule`]s, indexed by the hash of their module.
+#[derive(Default)]
+pub struct ModuleCache {
+    modules: HashMap<ModuleHash, CachedModule, FnvBuildHasher>,
 }
 
 /// Single running process in the list.
@@ -76,6 +351,23 @@ struct Process<TPud, TTud> {
 
     /// User-chosen data (opaque to us) that describes the process.
     user_data: TPud,
+
+    /// See [`ProcessesCollectionProc::name`].
+    name: Option<String>,
+
+    /// Hash of the main module of the process. See [`ProcessesCollection::fork`].
+    module_hash: ModuleHash,
+
+    /// Limits that the process must respect.
+    config: ProcessConfig,
+
+    /// See [`ProcessesCollectionProc::set_priority`].
+    priority: u32,
+
+    /// Increased by the priority every time a thread of the process reaches the front of a
+    /// queue without there being enough of it, and decreased by [`RUN_COST`] every time a thread
+    /// of the process runs. A thread can only run if this is superior or equal to [`RUN_COST`].
+    deficit: u32,
 }
 
 /// Additional data associated to a thread.
@@ -86,9 +378,37 @@ struct Thread<TTud> {
     /// Identifier of the thread.
     thread_id: ThreadId,
 
+    /// See [`ProcessesCollectionThread::name`].
+    name: Option<String>,
+
     /// Value to use when resuming. If `Some`, the process is ready for a round of running. If
     /// `None`, then we're waiting for the user to call `resume`.
     value_back: Option<Option<crate::WasmValue>>,
+
+    /// If true, the thread runs before the other threads that are ready. Reset to `false` when
+    /// the thread runs. See [`ProcessesCollectionThread::boost`].
+    boosted: bool,
+
+    /// If true, the thread runs after the other threads that are ready. Reset to `false` when
+    /// the thread runs. See [`ProcessesCollectionThread::defer`].
+    deferred: bool,
+
+    /// Value of [`ProcessesCollection::run_counter`] the last time this thread has run.
+    last_run: u64,
+
+    /// Number of consecutive times the thread has been resumed without any other thread running
+    /// in between. If this reaches the demotion threshold, the thread is run after the other
+    /// threads, the same way as if it was [deferred](ProcessesCollectionThread::defer).
+    busy_streak: u32,
+
+    /// Ticket of the entry in the [`ReadyQueues`] that corresponds to this thread, or `None` if
+    /// the thread isn't in any queue.
+    ticket: Option<u64>,
+
+    /// If the thread is waiting for the result of an extrinsic, value returned by
+    /// [`SchedulerObserver::now`] when the extrinsic was called. Always `None` if there is no
+    /// observer.
+    extrinsic_called_at: Option<u64>,
 }
 
 /// Access to a process within the collection.
@@ -98,6 +418,12 @@ pub struct ProcessesCollectionProc<'a, TPud, TTud> {
 
     /// Reference to the same field in [`ProcessesCollection`].
     tid_pool: &'a mut IdPool,
+
+    /// Reference to the same field in [`ProcessesCollection`].
+    ready: &'a mut ReadyQueues,
+
+    /// Copy of the same field in [`ProcessesCollection`].
+    run_counter: u64,
 }
 
 /// Access to a thread within the collection.
@@ -107,6 +433,12 @@ pub struct ProcessesCollectionThread<'a, TPud, TTud> {
 
     /// Index of the thread within the [`vm::ProcessStateMachine`].
     thread_index: usize,
+
+    /// Reference to the same field in [`ProcessesCollection`].
+    ready: &'a mut ReadyQueues,
+
+    /// Copy of the same field in [`ProcessesCollection`].
+    run_counter: u64,
 }
 
 /// Outcome of the [`run`](ProcessesCollection::run) function.
@@ -127,8 +459,15 @@ pub enum RunOneOutcome<'a, TExtr, TPud, TTud> {
         /// These threads no longer exist.
         dead_threads: Vec<(ThreadId, TTud)>,
 
+        /// Values of the globals of the process at the time it finished, in index order.
+        globals: Vec<crate::WasmValue>,
+
+        /// Children of the process that must now be killed. Always empty if the orphan policy
+        /// isn't [`OrphanPolicy::Kill`].
+        children_to_kill: Vec<Pid>,
+
         /// Value returned by the main thread that has finished, or error that happened.
-        outcome: Result<Option<crate::WasmValue>, wasmi::Trap>,
+        outcome: Result<Option<crate::WasmValue>, TrapError>,
     },
 
     /// A thread in a process has finished.
@@ -163,16 +502,87 @@ pub enum RunOneOutcome<'a, TExtr, TPud, TTud> {
         params: Vec<crate::WasmValue>,
     },
 
+    /// A thread has been paused because it has used up the fuel passed to
+    /// [`run`](ProcessesCollection::run).
+    ///
+    /// Call [`resume`](ProcessesCollectionThread::resume) with `None` in order for the thread to
+    /// be able to continue running.
+    OutOfFuel {
+        /// Thread that has been paused.
+        thread: ProcessesCollectionThread<'a, TPud, TTud>,
+    },
+
+    /// A thread has been paused because it has reached a
+    /// [breakpoint](ProcessesCollectionProc::add_breakpoint), or because
+    /// [single-stepping](ProcessesCollectionProc::set_single_step) is enabled.
+    ///
+    /// Call [`resume`](ProcessesCollectionThread::resume) with `None` in order for the thread to
+    /// be able to continue running.
+    Breakpoint {
+        /// Thread that has been paused.
+        thread: ProcessesCollectionThread<'a, TPud, TTud>,
+
+        /// Index of the function being executed, as found in the name section of the module.
+        function_index: u32,
+
+        /// Index, within the original code of the function, of the instruction that is about to
+        /// be executed.
+        instruction: u32,
+    },
+
     /// No thread is ready to run. Nothing was done.
     Idle,
 }
 
+/// Future returned by [`ProcessesCollection::run_async`].
+#[must_use = "futures do nothing unless polled"]
+pub struct RunAsync<'a, TExtr, TPud, TTud> {
+    /// Collection to run. `None` once the future has returned `Ready`.
+    collection: Option<&'a mut ProcessesCollection<TExtr, TPud, TTud>>,
+
+    /// Fuel to pass to [`ProcessesCollection::run`].
+    fuel: u64,
+}
+
+/// Process that has been killed with [`ProcessesCollection::kill_process`].
+#[derive(Debug)]
+pub struct ProcessKilled<TPud, TTud> {
+    /// Pid of the process that has been killed.
+    pub pid: Pid,
+
+    /// User data of the process.
+    pub user_data: TPud,
+
+    /// Id and user datas of all the threads of the process. The first element is the main
+    /// thread's.
+    /// These threads no longer exist.
+    pub dead_threads: Vec<(ThreadId, TTud)>,
+
+    /// Values of the globals of the process at the time it was killed, in index order.
+    pub globals: Vec<crate::WasmValue>,
+
+    /// Children of the process that must now be killed. Always empty if the orphan policy isn't
+    /// [`OrphanPolicy::Kill`].
+    pub children_to_kill: Vec<Pid>,
+}
+
 /// Minimum capacity of the container of the list of processes.
 ///
 /// If we shrink the container too much, then it will have to perform lots of allocations in order
 /// to grow again in the future. We therefore avoid that situation.
 const PROCESSES_MIN_CAPACITY: usize = 128;
 
+/// Default value for [`ProcessesCollectionBuilder::with_demotion_threshold`].
+const DEFAULT_DEMOTION_THRESHOLD: u32 = 256;
+
+/// Priority of processes that haven't had [`ProcessesCollectionProc::set_priority`] called.
+pub const DEFAULT_PRIORITY: u32 = 8;
+
+/// Amount by which [`Process::deficit`] is decreased every time a thread of the process runs.
+/// Processes with the default priority therefore run one thread every time they reach the front
+/// of a queue.
+const RUN_COST: u32 = DEFAULT_PRIORITY;
+
 impl<TExtr, TPud, TTud> ProcessesCollection<TExtr, TPud, TTud> {
     /// Creates a new process state machine from the given module.
     ///
@@ -183,112 +593,530 @@ impl<TExtr, TPud, TTud> ProcessesCollection<TExtr, TPud, TTud> {
     ///
     /// A single main thread (whose user data is passed by parameter) is automatically created and
     /// is paused at the start of the "_start" function of the module.
+    ///
+    /// If `parent` is `Some`, the new process is a child of the given process. See
+    /// [`ProcessesCollection::children`]. The new process has no parent if `parent` isn't in the
+    /// collection.
+    ///
+    /// The process is stopped if it exceeds the limits of the given [`ProcessConfig`].
     pub fn execute(
         &mut self,
         module: &Module,
+        parent: Option<Pid>,
+        config: ProcessConfig,
         proc_user_data: TPud,
         main_thread_user_data: TTud,
     ) -> Result<ProcessesCollectionProc<TPud, TTud>, vm::NewErr> {
-        let main_thread_id = self.tid_pool.assign(); // TODO: check for duplicates
-        let main_thread_data = Thread {
-            user_data: main_thread_user_data,
-            thread_id: main_thread_id,
-            value_back: Some(None),
-        };
+        let prepared = self.prepare(module, main_thread_user_data)?;
+        Ok(self.execute_prepared(prepared, parent, config, proc_user_data))
+    }
+
+    /// Same as [`ProcessesCollection::execute`], but the process can only import the extrinsics
+    /// that are in the given allowlist.
+    pub fn execute_with_allowlist(
+        &mut self,
+        module: &Module,
+        allowlist: &ExtrinsicsAllowlist,
+        parent: Option<Pid>,
+        config: ProcessConfig,
+        proc_user_data: TPud,
+        main_thread_user_data: TTud,
+    ) -> Result<ProcessesCollectionProc<TPud, TTud>, vm::NewErr> {
+        let prepared = self.prepare_with_allowlist(module, allowlist, main_thread_user_data)?;
+        Ok(self.execute_prepared(prepared, parent, config, proc_user_data))
+    }
+
+    /// Instantiates a process from the given module, without inserting it in the collection.
+    ///
+    /// This performs the expensive part of [`ProcessesCollection::execute`] ahead of time. Pass
+    /// the result to [`ProcessesCollection::execute_prepared`] in order to start the process.
+    pub fn prepare(
+        &mut self,
+        module: &Module,
+        main_thread_user_data: TTud,
+    ) -> Result<PreparedProcess<TTud>, vm::NewErr> {
+        self.prepare_inner(module, &[], None, main_thread_user_data)
+    }
+
+    /// Same as [`ProcessesCollection::prepare`], but the process can only import the extrinsics
+    /// that are in the given allowlist.
+    pub fn prepare_with_allowlist(
+        &mut self,
+        module: &Module,
+        allowlist: &ExtrinsicsAllowlist,
+        main_thread_user_data: TTud,
+    ) -> Result<PreparedProcess<TTud>, vm::NewErr> {
+        self.prepare_inner(module, &[], Some(allowlist), main_thread_user_data)
+    }
+
+    /// Same as [`ProcessesCollection::prepare`], but the process is additionally composed of the
+    /// given library modules. See [`vm::ProcessStateMachine::new_linked`].
+    ///
+    /// The imports whose namespace is the name of a library aren't checked against the
+    /// extrinsics of this collection.
+    pub fn prepare_linked(
+        &mut self,
+        module: &Module,
+        libraries: &[(&str, &Module)],
+        main_thread_user_data: TTud,
+    ) -> Result<PreparedProcess<TTud>, vm::NewErr> {
+        self.prepare_inner(module, libraries, None, main_thread_user_data)
+    }
+
+    /// Implementation of [`ProcessesCollection::prepare`] and
+    /// [`ProcessesCollection::prepare_with_allowlist`]. If `allowlist` is `None`, all the
+    /// extrinsics can be imported.
+    fn prepare_inner(
+        &mut self,
+        module: &Module,
+        libraries: &[(&str, &Module)],
+        allowlist: Option<&ExtrinsicsAllowlist>,
+        main_thread_user_data: TTud,
+    ) -> Result<PreparedProcess<TTud>, vm::NewErr> {
+        for checked in libraries.iter().map(|(_, m)| *m).chain(iter::once(module)) {
+            let abi_report = if libraries.is_empty() {
+                self.abi_report_inner(checked, allowlist)
+            } else {
+                // Imports coming from a library are checked at instantiation.
+                let imports = checked
+                    .imports()
+                    .iter()
+                    .filter(|import| !libraries.iter().any(|(n, _)| *n == import.namespace))
+                    .cloned()
+                    .collect::<Vec<_>>();
+                AbiReport::new(&imports, |interface, function| {
+                    self.provided_signature(allowlist, interface, function)
+                })
+            };
+
+            if !abi_report.is_compatible() {
+                return Err(vm::NewErr::IncompatibleAbi(abi_report));
+            }
+        }
+
+        let main_thread_data = self.new_main_thread(main_thread_user_data);
 
         let state_machine = {
             let extrinsics_id_assign = &mut self.extrinsics_id_assign;
-            vm::ProcessStateMachine::new(
+            vm::ProcessStateMachine::new_linked(
                 module,
+                libraries,
+                &self.module_limits,
                 main_thread_data,
                 move |interface, function, obtained_signature| {
-                    if let Some((index, expected_signature)) =
-                        extrinsics_id_assign.get(&(interface.into(), function.into()))
-                    {
-                        if expected_signature.matches_wasmi(obtained_signature) {
-                            return Ok(*index);
-                        } else {
-                            // TODO: way to report the signature mismatch?
-                        }
+                    if allowlist.map_or(false, |a| !a.is_allowed(interface, function)) {
+                        return Err(None);
                     }
 
-                    Err(())
+                    match extrinsics_id_assign.get(&(interface.into(), function.into())) {
+                        Some((index, expected_signature))
+                            if expected_signature == obtained_signature =>
+                        {
+                            Ok(*index)
+                        }
+                        // Mismatches have normally been reported by the ABI check above.
+                        Some((_, expected_signature)) => Err(Some(expected_signature.clone())),
+                        None => Err(None),
+                    }
                 },
             )?
         };
 
-        // We only modify `self` at the very end.
+        Ok(PreparedProcess {
+            state_machine,
+            name: module.name().map(String::from),
+            module_hash: module.hash().clone(),
+        })
+    }
+
+    /// Checks the imports of the given module against the extrinsics of this collection, and
+    /// returns a [`CachedModule`] that can later be passed to
+    /// [`ProcessesCollection::execute_cached`] any number of times.
+    ///
+    /// Returns an error if the module isn't compatible with the extrinsics of this collection.
+    pub fn cache_module(&self, module: Module) -> Result<CachedModule, vm::NewErr> {
+        let abi_report = self.abi_report_inner(&module, None);
+        if !abi_report.is_compatible() {
+            return Err(vm::NewErr::IncompatibleAbi(abi_report));
+        }
+
+        let extrinsics = module
+            .imports()
+            .iter()
+            .filter(|import| match import.kind {
+                ImportKind::Function(_) => true,
+                _ => false,
+            })
+            .filter_map(|import| {
+                let key = (
+                    Cow::Owned(import.namespace.clone()),
+                    Cow::Owned(import.name.clone()),
+                );
+                let (index, _) = self.extrinsics_id_assign.get(&key)?;
+                Some((import.namespace.clone(), import.name.clone(), *index))
+            })
+            .collect();
+
+        Ok(CachedModule { module, extrinsics })
+    }
+
+    /// Same as [`ProcessesCollection::prepare`], but for a module that has been cached with
+    /// [`ProcessesCollection::cache_module`].
+    pub fn prepare_cached(
+        &mut self,
+        cached: &CachedModule,
+        main_thread_user_data: TTud,
+    ) -> Result<PreparedProcess<TTud>, vm::NewErr> {
+        let main_thread_data = self.new_main_thread(main_thread_user_data);
+        let state_machine = vm::ProcessStateMachine::new(
+            &cached.module,
+            &self.module_limits,
+            main_thread_data,
+            |interface, function, _| {
+                cached
+                    .extrinsics
+                    .iter()
+                    .find(|(i, f, _)| i == interface && f == function)
+                    .map(|(_, _, index)| *index)
+                    .ok_or(None)
+            },
+        )?;
+
+        Ok(PreparedProcess {
+            state_machine,
+            name: cached.module.name().map(String::from),
+            module_hash: cached.module.hash().clone(),
+        })
+    }
+
+    /// Same as [`ProcessesCollection::execute`], but for a module that has been cached with
+    /// [`ProcessesCollection::cache_module`].
+    pub fn execute_cached(
+        &mut self,
+        cached: &CachedModule,
+        parent: Option<Pid>,
+        config: ProcessConfig,
+        proc_user_data: TPud,
+        main_thread_user_data: TTud,
+    ) -> Result<ProcessesCollectionProc<'_, TPud, TTud>, vm::NewErr> {
+        let prepared = self.prepare_cached(cached, main_thread_user_data)?;
+        Ok(self.execute_prepared(prepared, parent, config, proc_user_data))
+    }
+
+    /// Builds the user data of the main thread of a new process.
+    fn new_main_thread(&mut self, user_data: TTud) -> Thread<TTud> {
+        Thread {
+            user_data,
+            thread_id: self.tid_pool.assign(),
+            name: None,
+            value_back: Some(None),
+            boosted: false,
+            deferred: false,
+            last_run: 0,
+            busy_streak: 0,
+            ticket: None,
+            extrinsic_called_at: None,
+        }
+    }
+
+    /// Checks whether the imports of the given module can be resolved with the extrinsics of
+    /// this collection.
+    pub fn abi_report(&self, module: &Module) -> AbiReport {
+        self.abi_report_inner(module, None)
+    }
+
+    /// Same as [`ProcessesCollection::abi_report`], but imports that aren't in the allowlist are
+    /// reported as unknown.
+    pub fn abi_report_with_allowlist(
+        &self,
+        module: &Module,
+        allowlist: &ExtrinsicsAllowlist,
+    ) -> AbiReport {
+        self.abi_report_inner(module, Some(allowlist))
+    }
+
+    fn abi_report_inner(
+        &self,
+        module: &Module,
+        allowlist: Option<&ExtrinsicsAllowlist>,
+    ) -> AbiReport {
+        module.abi_report(|interface, function| {
+            self.provided_signature(allowlist, interface, function)
+        })
+    }
+
+    /// Returns the signature of the given extrinsic, or `None` if it doesn't exist or isn't in
+    /// the allowlist.
+    fn provided_signature(
+        &self,
+        allowlist: Option<&ExtrinsicsAllowlist>,
+        interface: &str,
+        function: &str,
+    ) -> Option<Signature> {
+        if allowlist.map_or(false, |a| !a.is_allowed(interface, function)) {
+            return None;
+        }
+
+        self.extrinsics_id_assign
+            .get(&(interface.into(), function.into()))
+            .map(|(_, signature)| signature.clone())
+    }
+
+    /// Inserts in the collection a process that has been created with
+    /// [`ProcessesCollection::prepare`].
+    ///
+    /// See [`ProcessesCollection::execute`] for the meaning of `parent` and `config`.
+    pub fn execute_prepared(
+        &mut self,
+        prepared: PreparedProcess<TTud>,
+        parent: Option<Pid>,
+        config: ProcessConfig,
+        proc_user_data: TPud,
+    ) -> ProcessesCollectionProc<TPud, TTud> {
+        let mut state_machine = prepared.state_machine;
+        state_machine.set_max_memory_pages(
+            match (config.max_memory_pages, self.max_memory_pages) {
+                (Some(a), Some(b)) => Some(cmp::min(a, b)),
+                (a, b) => a.or(b),
+            },
+        );
+        state_machine.set_max_stack_depth(self.max_stack_depth);
+        let name = config.name.clone().or(prepared.name);
+
         let new_pid = self.pid_pool.assign();
+        self.lineages.insert(new_pid, parent);
         self.processes.insert(
             new_pid,
             Process {
                 state_machine,
                 user_data: proc_user_data,
+                name,
+                module_hash: prepared.module_hash,
+                config,
+                priority: DEFAULT_PRIORITY,
+                deficit: 0,
             },
         );
 
+        {
+            let process = match self.processes.get_mut(&new_pid) {
+                Some(p) => p,
+                None => unreachable!(),
+            };
+            let mut main_thread = match process.state_machine.thread(0) {
+                Some(t) => t,
+                None => unreachable!(),
+            };
+            self.ready.push(new_pid, main_thread.user_data(), false);
+        }
+
+        if let Some(observer) = &mut self.observer {
+            let name = self.processes[&new_pid].name.as_ref().map(|n| &n[..]);
+            observer.process_created(new_pid, name);
+        }
+
         // Shrink the list from time to time so that it doesn't grow too much.
         if u64::from(new_pid) % 256 == 0 {
             self.processes.shrink_to(PROCESSES_MIN_CAPACITY);
         }
 
-        Ok(match self.process_by_id(new_pid) {
+        match self.process_by_id(new_pid) {
             Some(p) => p,
             None => unreachable!(),
-        })
+        }
     }
 
-    /// Runs one thread amongst the collection.
+    /// Creates a new process whose memory and globals are a copy of the ones of the process with
+    /// the given [`Pid`]. The main thread of the new process executes the function with the given
+    /// index in the indirect function table, similar to
+    /// [`ProcessesCollectionProc::start_thread`].
     ///
-    /// Which thread is run is implementation-defined and no guarantee is made.
-    pub fn run(&mut self) -> RunOneOutcome<TExtr, TPud, TTud> {
-        // We start by finding a thread in `self.processes` that is ready to run.
-        let (mut process, inner_thread_index): (OccupiedEntry<_, _, _>, usize) = {
-            let entries = self.processes.iter_mut().collect::<Vec<_>>();
-            // TODO: entries.shuffle(&mut rand::thread_rng());
-            let entry = entries
-                .into_iter()
-                .filter_map(|(k, p)| {
-                    if let Some(i) = p.ready_to_run_thread_index() {
-                        Some((*k, i))
-                    } else {
-                        None
-                    }
-                })
-                .next();
-            match entry {
-                Some((pid, inner_thread_index)) => match self.processes.entry(pid) {
-                    Entry::Occupied(p) => (p, inner_thread_index),
-                    Entry::Vacant(_) => unreachable!(),
-                },
-                None => return RunOneOutcome::Idle,
+    /// `module` must be the module the process has been started from. The new process is a child
+    /// of the forked process, and inherits its [`ProcessConfig`].
+    ///
+    /// This makes it possible to initialize a process once, then quickly start copies of it
+    /// rather than performing the same initialization every time.
+    ///
+    /// > **Note**: The interpreter doesn't support sharing memory between instances. The memory
+    /// >           of the process is copied entirely rather than being copied on write.
+    ///
+    /// > **Note**: Processes composed of libraries can't be forked.
+    pub fn fork(
+        &mut self,
+        pid: Pid,
+        module: &Module,
+        function_id: u32,
+        params: Vec<crate::WasmValue>,
+        proc_user_data: TPud,
+        main_thread_user_data: TTud,
+    ) -> Result<ProcessesCollectionProc<'_, TPud, TTud>, ForkErr> {
+        let config = {
+            let forked = self.processes.get(&pid).ok_or(ForkErr::ProcessNotFound)?;
+            if forked.module_hash != *module.hash() {
+                return Err(ForkErr::ModuleMismatch);
             }
+            forked.config.clone()
         };
 
-        // Now run the thread until something happens.
-        let run_outcome = {
-            let mut thread = match process.get_mut().state_machine.thread(inner_thread_index) {
-                Some(t) => t,
-                None => unreachable!(),
-            };
-            let value_back = match thread.user_data().value_back.take() {
-                Some(vb) => vb,
-                None => unreachable!(),
-            };
-            thread.run(value_back)
+        let mut prepared = self
+            .prepare(module, main_thread_user_data)
+            .map_err(ForkErr::New)?;
+        let forked = match self.processes.get(&pid) {
+            Some(p) => p,
+            None => unreachable!(),
         };
+        forked
+            .state_machine
+            .copy_state_into(&mut prepared.state_machine)
+            .map_err(|()| ForkErr::ModuleMismatch)?;
+        prepared
+            .state_machine
+            .restart_main_thread(function_id, params)
+            .map_err(ForkErr::Start)?;
 
-        match run_outcome {
-            Err(vm::RunErr::BadValueTy { .. }) => panic!(), // TODO:
-            Err(vm::RunErr::Poisoned) => unreachable!(),
+        Ok(self.execute_prepared(prepared, Some(pid), config, proc_user_data))
+    }
 
-            // A process has ended.
-            Ok(vm::ExecOutcome::ThreadFinished {
-                thread_index: 0,
-                return_value,
-                user_data: main_thread_user_data,
+    /// Runs one thread amongst the collection.
+    ///
+    /// Threads run in the order in which they have become ready, except that threads that have
+    /// been [boosted](ProcessesCollectionThread::boost) are run first, and threads that have been
+    /// [deferred](ProcessesCollectionThread::defer) or
+    /// [demoted](ProcessesCollectionBuilder::with_demotion_threshold) are run last. The
+    /// [priority](ProcessesCollectionProc::set_priority) of processes determines how many times in
+    /// a row one of their threads can run.
+    ///
+    /// The thread is paused, and [`RunOneOutcome::OutOfFuel`] returned, after it has executed
+    /// approximately `fuel` instructions without calling any extrinsic.
+    pub fn run(&mut self, fuel: u64) -> RunOneOutcome<TExtr, TPud, TTud> {
+        self.run_counter = self.run_counter.wrapping_add(1);
+        let run_counter = self.run_counter;
+        let demotion_threshold = self.demotion_threshold;
+
+        // We start by popping from the queues a thread that is ready to run. Boosted threads
+        // run first, and deferred or demoted threads last.
+        let (pid, inner_thread_index) = loop {
+            let (entry, background) = if let Some(e) = self.ready.boosted.pop_front() {
+                (e, false)
+            } else if let Some(e) = self.ready.normal.pop_front() {
+                (e, false)
+            } else if let Some(e) = self.ready.background.pop_front() {
+                (e, true)
+            } else {
+                return RunOneOutcome::Idle;
+            };
+
+            // The entry might be obsolete, in which case we simply discard it.
+            let process = match self.processes.get_mut(&entry.pid) {
+                Some(p) => p,
+                None => continue,
+            };
+            let thread_index = match process.thread_index_by_id(entry.thread_id) {
+                Some(i) => i,
+                None => continue,
+            };
+            let mut thread = match process.state_machine.thread(thread_index) {
+                Some(t) => t,
+                None => unreachable!(),
+            };
+            let user_data = thread.user_data();
+            if user_data.ticket != Some(entry.ticket) {
+                continue;
+            }
+
+            if !user_data.boosted {
+                if !background
+                    && (user_data.deferred || user_data.busy_streak >= demotion_threshold)
+                {
+                    self.ready.push_background(entry.pid, user_data);
+                    continue;
+                }
+
+                // Processes whose priority is lower than the default only get to run once every
+                // few times they reach the front of the queue.
+                if process.deficit < RUN_COST {
+                    process.deficit += cmp::max(process.priority, 1);
+                    if process.deficit < RUN_COST {
+                        if background {
+                            self.ready.background.push_back(entry);
+                        } else {
+                            self.ready.normal.push_back(entry);
+                        }
+                        continue;
+                    }
+                }
+                process.deficit -= RUN_COST;
+            }
+
+            break (entry.pid, thread_index);
+        };
+
+        let mut process = match self.processes.entry(pid) {
+            Entry::Occupied(p) => p,
+            Entry::Vacant(_) => unreachable!(),
+        };
+
+        // Now run the thread until something happens.
+        let run_outcome = {
+            process.get_mut().state_machine.set_fuel(fuel);
+            let mut thread = match process.get_mut().state_machine.thread(inner_thread_index) {
+                Some(t) => t,
+                None => unreachable!(),
+            };
+            let value_back = match thread.user_data().value_back.take() {
+                Some(vb) => vb,
+                None => unreachable!(),
+            };
+            thread.user_data().ticket = None;
+            thread.user_data().boosted = false;
+            thread.user_data().deferred = false;
+            thread.user_data().last_run = run_counter;
+            if let Some(observer) = &mut self.observer {
+                let thread_id = thread.user_data().thread_id;
+                if let Some(called_at) = thread.user_data().extrinsic_called_at.take() {
+                    let duration = observer.now().saturating_sub(called_at);
+                    observer.extrinsic_returned(pid, thread_id, duration);
+                }
+                let name = thread.user_data().name.as_ref().map(|n| &n[..]);
+                observer.thread_scheduled(pid, thread_id, name);
+            }
+            match thread.run(value_back) {
+                // Exceeding the memory limit is reported in the same way as an error.
+                Ok(vm::ExecOutcome::MemoryLimitExceeded { thread, max, .. }) => {
+                    Ok(vm::ExecOutcome::Errored {
+                        thread,
+                        error: TrapKind::LimitExceeded(LimitExceeded::MemoryPages { max }).into(),
+                    })
+                }
+                Ok(vm::ExecOutcome::StackOverflow { thread, max }) => {
+                    Ok(vm::ExecOutcome::Errored {
+                        thread,
+                        error: TrapKind::LimitExceeded(LimitExceeded::StackDepth { max }).into(),
+                    })
+                }
+                outcome => outcome,
+            }
+        };
+
+        match run_outcome {
+            Err(vm::RunErr::BadValueTy { .. }) => panic!(), // TODO:
+            Err(vm::RunErr::Poisoned) => unreachable!(),
+            // Turned into `Errored` above.
+            Ok(vm::ExecOutcome::MemoryLimitExceeded { .. }) => unreachable!(),
+            Ok(vm::ExecOutcome::StackOverflow { .. }) => unreachable!(),
+
+            // A process has ended.
+            Ok(vm::ExecOutcome::ThreadFinished {
+                thread_index: 0,
+                return_value,
+                user_data: main_thread_user_data,
             }) => {
                 let (pid, proc) = process.remove_entry();
+                if let Some(observer) = &mut self.observer {
+                    observer.thread_finished(pid, main_thread_user_data.thread_id);
+                    observer.process_destroyed(pid);
+                }
+                let globals = proc.state_machine.globals();
                 let other_threads_ud = proc.state_machine.into_user_datas();
                 let mut dead_threads = Vec::with_capacity(1 + other_threads_ud.len());
                 dead_threads.push((
@@ -299,10 +1127,13 @@ impl<TExtr, TPud, TTud> ProcessesCollection<TExtr, TPud, TTud> {
                     dead_threads.push((thread.thread_id, thread.user_data));
                 }
                 debug_assert_eq!(dead_threads.len(), dead_threads.capacity());
+                let children_to_kill = self.lineages.remove(pid);
                 RunOneOutcome::ProcessFinished {
                     pid,
                     user_data: proc.user_data,
                     dead_threads,
+                    globals,
+                    children_to_kill,
                     outcome: Ok(return_value),
                 }
             }
@@ -312,15 +1143,22 @@ impl<TExtr, TPud, TTud> ProcessesCollection<TExtr, TPud, TTud> {
                 return_value,
                 user_data,
                 ..
-            }) => RunOneOutcome::ThreadFinished {
-                thread_id: user_data.thread_id,
-                process: ProcessesCollectionProc {
-                    process,
-                    tid_pool: &mut self.tid_pool,
-                },
-                user_data: user_data.user_data,
-                value: return_value,
-            },
+            }) => {
+                if let Some(observer) = &mut self.observer {
+                    observer.thread_finished(pid, user_data.thread_id);
+                }
+                RunOneOutcome::ThreadFinished {
+                    thread_id: user_data.thread_id,
+                    process: ProcessesCollectionProc {
+                        process,
+                        tid_pool: &mut self.tid_pool,
+                        ready: &mut self.ready,
+                        run_counter,
+                    },
+                    user_data: user_data.user_data,
+                    value: return_value,
+                }
+            }
 
             // Thread wants to call an extrinsic function.
             Ok(vm::ExecOutcome::Interrupted { id, params, .. }) => {
@@ -329,34 +1167,209 @@ impl<TExtr, TPud, TTud> ProcessesCollection<TExtr, TPud, TTud> {
                     Some(e) => e,
                     None => unreachable!(),
                 };
+                if let Some(observer) = &mut self.observer {
+                    let mut thread =
+                        match process.get_mut().state_machine.thread(inner_thread_index) {
+                            Some(t) => t,
+                            None => unreachable!(),
+                        };
+                    let thread_id = thread.user_data().thread_id;
+                    thread.user_data().extrinsic_called_at = Some(observer.now());
+                    observer.extrinsic_called(pid, thread_id, extrinsic);
+                }
                 RunOneOutcome::Interrupted {
                     thread: ProcessesCollectionThread {
                         process,
                         thread_index: inner_thread_index,
+                        ready: &mut self.ready,
+                        run_counter,
                     },
                     id: extrinsic,
                     params,
                 }
             }
 
+            Ok(vm::ExecOutcome::OutOfFuel { .. }) => {
+                if let Some(observer) = &mut self.observer {
+                    let mut thread =
+                        match process.get_mut().state_machine.thread(inner_thread_index) {
+                            Some(t) => t,
+                            None => unreachable!(),
+                        };
+                    observer.thread_interrupted(pid, thread.user_data().thread_id);
+                }
+                RunOneOutcome::OutOfFuel {
+                    thread: ProcessesCollectionThread {
+                        process,
+                        thread_index: inner_thread_index,
+                        ready: &mut self.ready,
+                        run_counter,
+                    },
+                }
+            }
+
+            Ok(vm::ExecOutcome::Breakpoint {
+                function_index,
+                instruction,
+                ..
+            }) => {
+                if let Some(observer) = &mut self.observer {
+                    let mut thread =
+                        match process.get_mut().state_machine.thread(inner_thread_index) {
+                            Some(t) => t,
+                            None => unreachable!(),
+                        };
+                    observer.thread_interrupted(pid, thread.user_data().thread_id);
+                }
+                RunOneOutcome::Breakpoint {
+                    thread: ProcessesCollectionThread {
+                        process,
+                        thread_index: inner_thread_index,
+                        ready: &mut self.ready,
+                        run_counter,
+                    },
+                    function_index,
+                    instruction,
+                }
+            }
+
             // An error happened during the execution. We kill the entire process.
             Ok(vm::ExecOutcome::Errored { error, .. }) => {
                 let (pid, proc) = process.remove_entry();
+                if let Some(observer) = &mut self.observer {
+                    observer.process_destroyed(pid);
+                }
+                let globals = proc.state_machine.globals();
                 let dead_threads = proc
                     .state_machine
                     .into_user_datas()
                     .map(|t| (t.thread_id, t.user_data))
                     .collect::<Vec<_>>();
+                let children_to_kill = self.lineages.remove(pid);
                 RunOneOutcome::ProcessFinished {
                     pid,
                     user_data: proc.user_data,
                     dead_threads,
+                    globals,
+                    children_to_kill,
                     outcome: Err(error),
                 }
             }
         }
     }
 
+    /// Same as [`run`](ProcessesCollection::run), except that instead of returning
+    /// [`RunOneOutcome::Idle`], the returned future waits until a thread is ready to run.
+    ///
+    /// The future is woken up when a thread is [resumed](ProcessesCollectionThread::resume),
+    /// [started](ProcessesCollectionProc::start_thread), or when a process is created.
+    pub fn run_async(&mut self, fuel: u64) -> RunAsync<'_, TExtr, TPud, TTud> {
+        RunAsync {
+            collection: Some(self),
+            fuel,
+        }
+    }
+
+    /// Discards the obsolete entries at the front of the queues of threads ready to run. Returns
+    /// true if any queue is non-empty afterwards, in which case [`ProcessesCollection::run`]
+    /// will not return [`RunOneOutcome::Idle`].
+    fn has_ready_thread(&mut self) -> bool {
+        let processes = &mut self.processes;
+        let mut has_ready = false;
+        for queue in &mut [
+            &mut self.ready.boosted,
+            &mut self.ready.normal,
+            &mut self.ready.background,
+        ] {
+            while let Some(entry) = queue.front() {
+                let is_valid =
+                    processes.get_mut(&entry.pid).map_or(false, |process| {
+                        match process.thread_index_by_id(entry.thread_id) {
+                            Some(index) => match process.state_machine.thread(index) {
+                                Some(mut thread) => thread.user_data().ticket == Some(entry.ticket),
+                                None => unreachable!(),
+                            },
+                            None => false,
+                        }
+                    });
+                if is_valid {
+                    has_ready = true;
+                    break;
+                }
+                queue.pop_front();
+            }
+        }
+        has_ready
+    }
+
+    /// Kills the process with the given [`Pid`], without resuming any of its threads.
+    ///
+    /// Returns `None` if there is no such process.
+    ///
+    /// > **Note**: The entries of the threads of the process that are still in the queues of
+    /// >           threads ready to run are ignored by [`run`](ProcessesCollection::run) when
+    /// >           they are reached.
+    pub fn kill_process(&mut self, pid: Pid) -> Option<ProcessKilled<TPud, TTud>> {
+        let proc = self.processes.remove(&pid)?;
+        if let Some(observer) = &mut self.observer {
+            observer.process_destroyed(pid);
+        }
+        let globals = proc.state_machine.globals();
+        let dead_threads = proc
+            .state_machine
+            .into_user_datas()
+            .map(|t| (t.thread_id, t.user_data))
+            .collect::<Vec<_>>();
+        let children_to_kill = self.lineages.remove(pid);
+        Some(ProcessKilled {
+            pid,
+            user_data: proc.user_data,
+            dead_threads,
+            globals,
+            children_to_kill,
+        })
+    }
+
+    /// Returns true if there is a process with the given [`Pid`] in the collection.
+    ///
+    /// Since [`Pid`]s are never reused, a stored [`Pid`] for which this returns true still
+    /// refers to the same process as when it was stored.
+    pub fn is_process_alive(&self, pid: Pid) -> bool {
+        self.processes.contains_key(&pid)
+    }
+
+    /// Returns the parent of the given process, or `None` if the process doesn't exist or
+    /// doesn't have a parent.
+    pub fn parent(&self, pid: Pid) -> Option<Pid> {
+        self.lineages.entries.get(&pid)?.parent
+    }
+
+    /// Returns the children of the given process. Empty if the process doesn't exist.
+    pub fn children<'a>(&'a self, pid: Pid) -> impl ExactSizeIterator<Item = Pid> + 'a {
+        self.lineages
+            .entries
+            .get(&pid)
+            .map(|l| &l.children[..])
+            .unwrap_or(&[])
+            .iter()
+            .cloned()
+    }
+
+    /// Returns the resources used by all the processes of the collection.
+    ///
+    /// This goes through all the processes, and is therefore not free.
+    pub fn resource_usage(&self) -> ResourceUsage {
+        let mut usage = ResourceUsage {
+            num_processes: self.processes.len(),
+            ..Default::default()
+        };
+        for process in self.processes.values() {
+            usage.num_threads += process.state_machine.num_threads();
+            usage.memory_bytes += process.state_machine.memory_size();
+        }
+        usage
+    }
+
     /// Returns an iterator to all the processes that exist in the collection.
     pub fn pids<'a>(&'a self) -> impl ExactSizeIterator<Item = Pid> + 'a {
         self.processes.keys().cloned()
@@ -369,6 +1382,8 @@ impl<TExtr, TPud, TTud> ProcessesCollection<TExtr, TPud, TTud> {
             Entry::Occupied(e) => Some(ProcessesCollectionProc {
                 process: e,
                 tid_pool: &mut self.tid_pool,
+                ready: &mut self.ready,
+                run_counter: self.run_counter,
             }),
         }
     }
@@ -398,16 +1413,151 @@ impl<TExtr, TPud, TTud> ProcessesCollection<TExtr, TPud, TTud> {
                 Entry::Occupied(e) => e,
             },
             thread_index,
+            ready: &mut self.ready,
+            run_counter: self.run_counter,
+        })
+    }
+
+    /// Returns the [`ThreadId`]s of all the threads of all the processes, alongside with the
+    /// [`Pid`] of the process they belong to.
+    pub fn thread_ids<'a>(&'a self) -> impl Iterator<Item = (Pid, ThreadId)> + 'a {
+        self.processes.iter().flat_map(|(pid, process)| {
+            process
+                .state_machine
+                .user_datas()
+                .map(move |thread| (pid.clone(), thread.thread_id))
         })
     }
+
+    /// Checks that the processes are consistent with their state machines, and returns the
+    /// problems found.
+    pub fn self_check(&self) -> Vec<Violation> {
+        let mut violations = Vec::new();
+        let mut thread_ids = HashSet::<ThreadId, BuildNoHashHasher<u64>>::default();
+
+        for (pid, process) in self.processes.iter() {
+            // Processes are removed from the collection as soon as they stop, which is the case
+            // if the state machine is poisoned or if the main thread has finished.
+            if process.state_machine.is_poisoned() {
+                violations.push(Violation::PoisonedProcess { pid: pid.clone() });
+            }
+            if process.state_machine.num_threads() == 0 {
+                violations.push(Violation::ProcessWithoutThread { pid: pid.clone() });
+            }
+
+            for thread_index in process.state_machine.threads_without_execution() {
+                violations.push(Violation::InvalidThreadState {
+                    pid: pid.clone(),
+                    thread_index,
+                });
+            }
+
+            for thread in process.state_machine.user_datas() {
+                if !thread_ids.insert(thread.thread_id) {
+                    violations.push(Violation::DuplicateThreadId {
+                        thread_id: thread.thread_id,
+                    });
+                }
+            }
+        }
+
+        violations
+    }
+}
+
+impl ExtrinsicsAllowlist {
+    /// Builds a new empty allowlist.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allows importing the given extrinsic.
+    pub fn with_extrinsic(
+        mut self,
+        interface: impl Into<Cow<'static, str>>,
+        f_name: impl Into<Cow<'static, str>>,
+    ) -> Self {
+        self.functions.insert((interface.into(), f_name.into()));
+        self
+    }
+
+    /// Allows importing all the extrinsics of the given interface.
+    pub fn with_interface(mut self, interface: impl Into<Cow<'static, str>>) -> Self {
+        self.interfaces.insert(interface.into());
+        self
+    }
+
+    /// Returns true if the given extrinsic can be imported.
+    pub fn is_allowed(&self, interface: &str, f_name: &str) -> bool {
+        self.interfaces.contains(interface)
+            || self.functions.contains(&(interface.into(), f_name.into()))
+    }
+}
+
+impl CachedModule {
+    /// Returns the module that has been cached.
+    pub fn module(&self) -> &Module {
+        &self.module
+    }
+}
+
+impl fmt::Debug for CachedModule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("CachedModule").field(&self.module).finish()
+    }
+}
+
+impl ModuleCache {
+    /// Builds a new empty cache.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Returns the cached module whose hash is the given one, if any.
+    pub fn get(&self, hash: &ModuleHash) -> Option<&CachedModule> {
+        self.modules.get(hash)
+    }
+
+    /// Adds a module to the cache. Returns the module with the same hash that was previously in
+    /// the cache, if any.
+    pub fn insert(&mut self, cached: CachedModule) -> Option<CachedModule> {
+        self.modules.insert(cached.module.hash().clone(), cached)
+    }
+
+    /// Removes the module with the given hash from the cache.
+    pub fn remove(&mut self, hash: &ModuleHash) -> Option<CachedModule> {
+        self.modules.remove(hash)
+    }
+
+    /// Returns the number of modules in the cache.
+    pub fn len(&self) -> usize {
+        self.modules.len()
+    }
+
+    /// Returns true if the cache doesn't contain any module.
+    pub fn is_empty(&self) -> bool {
+        self.modules.is_empty()
+    }
+}
+
+impl fmt::Debug for ModuleCache {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_set().entries(self.modules.keys()).finish()
+    }
 }
 
 impl<TExtr> Default for ProcessesCollectionBuilder<TExtr> {
     fn default() -> ProcessesCollectionBuilder<TExtr> {
         ProcessesCollectionBuilder {
-            pid_pool: IdPool::new(),
+            pid_pool: IdPool::without_reuse(),
             extrinsics: Default::default(),
             extrinsics_id_assign: Default::default(),
+            demotion_threshold: DEFAULT_DEMOTION_THRESHOLD,
+            orphan_policy: OrphanPolicy::Reparent,
+            module_limits: Default::default(),
+            max_memory_pages: None,
+            max_stack_depth: None,
+            observer: None,
         }
     }
 }
@@ -458,6 +1608,58 @@ impl<TExtr> ProcessesCollectionBuilder<TExtr> {
         self
     }
 
+    /// Sets the number of consecutive times a thread must be resumed without any other thread
+    /// running in between before it is demoted.
+    ///
+    /// A thread that keeps calling extrinsics that don't block, such as a thread polling for
+    /// notifications in a loop, is likely busy-looping. Demoted threads run after all the other
+    /// threads that are ready, and are no longer demoted once they block.
+    ///
+    /// Passing `u32::max_value()` disables demotion.
+    pub fn with_demotion_threshold(mut self, threshold: u32) -> Self {
+        self.demotion_threshold = threshold;
+        self
+    }
+
+    /// Sets the limits that modules must respect in order to be instantiated.
+    pub fn with_module_limits(mut self, limits: vm::ModuleLimits) -> Self {
+        self.module_limits = limits;
+        self
+    }
+
+    /// Sets the maximum number of 64kiB pages of memory of every process. Processes whose memory
+    /// grows beyond this value are stopped with [`LimitExceeded::MemoryPages`].
+    ///
+    /// Applies in addition to [`ProcessConfig::max_memory_pages`].
+    pub fn with_max_memory_pages(mut self, max: u32) -> Self {
+        self.max_memory_pages = Some(max);
+        self
+    }
+
+    /// Sets the maximum number of nested function calls of every thread. Processes whose threads
+    /// go beyond this value are stopped with [`LimitExceeded::StackDepth`].
+    ///
+    /// Regardless of this value, processes that reach the limits of the interpreter on the size
+    /// of the stack are stopped in the same way.
+    pub fn with_max_stack_depth(mut self, max: u32) -> Self {
+        self.max_stack_depth = Some(max);
+        self
+    }
+
+    /// Sets what happens to the children of a process when it ends. Defaults to
+    /// [`OrphanPolicy::Reparent`].
+    pub fn with_orphan_policy(mut self, policy: OrphanPolicy) -> Self {
+        self.orphan_policy = policy;
+        self
+    }
+
+    /// Registers an observer that is informed of the activity of the collection, for example
+    /// for tracing or profiling purposes. Replaces the previous observer, if any.
+    pub fn with_observer(mut self, observer: impl SchedulerObserver<TExtr> + 'static) -> Self {
+        self.observer = Some(Box::new(observer));
+        self
+    }
+
     /// Turns the builder into a [`ProcessesCollection`].
     pub fn build<TPud, TTud>(mut self) -> ProcessesCollection<TExtr, TPud, TTud> {
         // We're not going to modify these fields ever again, so let's free some memory.
@@ -467,26 +1669,37 @@ impl<TExtr> ProcessesCollectionBuilder<TExtr> {
 
         ProcessesCollection {
             pid_pool: self.pid_pool,
-            tid_pool: IdPool::new(),
+            tid_pool: IdPool::without_reuse(),
             processes: HashMap::with_capacity_and_hasher(
                 PROCESSES_MIN_CAPACITY,
                 Default::default(),
             ),
             extrinsics: self.extrinsics,
             extrinsics_id_assign: self.extrinsics_id_assign,
+            run_counter: 0,
+            demotion_threshold: self.demotion_threshold,
+            module_limits: self.module_limits,
+            max_memory_pages: self.max_memory_pages,
+            max_stack_depth: self.max_stack_depth,
+            lineages: Lineages {
+                entries: Default::default(),
+                orphan_policy: self.orphan_policy,
+            },
+            ready: Default::default(),
+            observer: self.observer,
         }
     }
 }
 
 impl<TPud, TTud> Process<TPud, TTud> {
-    /// Finds a thread in this process that is ready to be executed.
-    fn ready_to_run_thread_index(&mut self) -> Option<usize> {
+    /// Finds the index of the thread with the given [`ThreadId`] within this process.
+    fn thread_index_by_id(&mut self, thread_id: ThreadId) -> Option<usize> {
         for thread_n in 0..self.state_machine.num_threads() {
             let mut thread = match self.state_machine.thread(thread_n) {
                 Some(t) => t,
                 None => unreachable!(),
             };
-            if thread.user_data().value_back.is_some() {
+            if thread.user_data().thread_id == thread_id {
                 return Some(thread_n);
             }
         }
@@ -495,6 +1708,129 @@ impl<TPud, TTud> Process<TPud, TTud> {
     }
 }
 
+impl Lineages {
+    /// Adds an entry for the new process `pid`. The process has no parent if `parent` isn't in
+    /// the collection.
+    fn insert(&mut self, pid: Pid, parent: Option<Pid>) {
+        let parent = match parent.and_then(|p| self.entries.get_mut(&p).map(|l| (p, l))) {
+            Some((parent, lineage)) => {
+                lineage.children.push(pid);
+                Some(parent)
+            }
+            None => None,
+        };
+
+        self.entries.insert(
+            pid,
+            Lineage {
+                parent,
+                children: Vec::new(),
+            },
+        );
+    }
+
+    /// Removes the entry of a process that has been removed from the collection, and updates its
+    /// parent and its children according to the orphan policy. Returns the children that must be
+    /// killed.
+    fn remove(&mut self, pid: Pid) -> Vec<Pid> {
+        let removed = match self.entries.remove(&pid) {
+            Some(l) => l,
+            None => unreachable!(),
+        };
+
+        if let Some(parent) = removed.parent.and_then(|p| self.entries.get_mut(&p)) {
+            parent.children.retain(|c| *c != pid);
+        }
+
+        let new_parent = match self.orphan_policy {
+            OrphanPolicy::Reparent => removed.parent,
+            OrphanPolicy::Kill => None,
+        };
+        for child in &removed.children {
+            if let Some(child) = self.entries.get_mut(child) {
+                child.parent = new_parent;
+            }
+        }
+
+        match (self.orphan_policy, new_parent) {
+            (OrphanPolicy::Reparent, Some(new_parent)) => {
+                if let Some(new_parent) = self.entries.get_mut(&new_parent) {
+                    new_parent.children.extend(removed.children);
+                }
+                Vec::new()
+            }
+            (OrphanPolicy::Reparent, None) => Vec::new(),
+            (OrphanPolicy::Kill, _) => removed.children,
+        }
+    }
+}
+
+impl ReadyQueues {
+    /// Pushes the given thread to the back of the queue corresponding to whether it is boosted,
+    /// or to the front if `front` is true. Any other entry of this thread becomes obsolete.
+    fn push<TTud>(&mut self, pid: Pid, thread: &mut Thread<TTud>, front: bool) {
+        let entry = self.entry(pid, thread);
+        let queue = if thread.boosted {
+            &mut self.boosted
+        } else {
+            &mut self.normal
+        };
+        if front {
+            queue.push_front(entry);
+        } else {
+            queue.push_back(entry);
+        }
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+
+    /// Pushes the given thread to the back of the queue of the deferred and demoted threads. Any
+    /// other entry of this thread becomes obsolete.
+    fn push_background<TTud>(&mut self, pid: Pid, thread: &mut Thread<TTud>) {
+        let entry = self.entry(pid, thread);
+        self.background.push_back(entry);
+    }
+
+    /// Builds a new entry for the given thread, and updates [`Thread::ticket`].
+    fn entry<TTud>(&mut self, pid: Pid, thread: &mut Thread<TTud>) -> ReadyEntry {
+        let ticket = self.next_ticket;
+        self.next_ticket = self.next_ticket.wrapping_add(1);
+        thread.ticket = Some(ticket);
+        ReadyEntry {
+            pid,
+            thread_id: thread.thread_id,
+            ticket,
+        }
+    }
+}
+
+impl<'a, TExtr, TPud, TTud> Future for RunAsync<'a, TExtr, TPud, TTud> {
+    type Output = RunOneOutcome<'a, TExtr, TPud, TTud>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let collection = self
+            .collection
+            .take()
+            .expect("RunAsync polled after completion");
+        if !collection.has_ready_thread() {
+            collection.ready.waker = Some(cx.waker().clone());
+            self.collection = Some(collection);
+            return Poll::Pending;
+        }
+
+        Poll::Ready(collection.run(self.fuel))
+    }
+}
+
+impl<'a, TExtr, TPud, TTud> fmt::Debug for RunAsync<'a, TExtr, TPud, TTud> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RunAsync")
+            .field("fuel", &self.fuel)
+            .finish()
+    }
+}
+
 impl<'a, TPud, TTud> ProcessesCollectionProc<'a, TPud, TTud> {
     /// Returns the [`Pid`] of the process. Allows later retrieval by calling
     /// [`process_by_id`](ProcessesCollection::process_by_id).
@@ -507,8 +1843,62 @@ impl<'a, TPud, TTud> ProcessesCollectionProc<'a, TPud, TTud> {
         &self.process.get().user_data
     }
 
+    /// Returns the priority of the process. See [`ProcessesCollectionProc::set_priority`].
+    pub fn priority(&self) -> u32 {
+        self.process.get().priority
+    }
+
+    /// Sets the priority of the process. Defaults to [`DEFAULT_PRIORITY`].
+    ///
+    /// When multiple processes have a thread ready to run, each process runs a number of times
+    /// proportional to its priority. For example, a process with a priority of 16 runs twice as
+    /// often as a process with a priority of 8. A priority of 0 is treated as 1.
+    ///
+    /// > **Note**: [Boosted](ProcessesCollectionThread::boost) threads still run before any
+    /// >           other thread, and [deferred](ProcessesCollectionThread::defer) threads after
+    /// >           the other threads, no matter the priority.
+    pub fn set_priority(&mut self, priority: u32) {
+        self.process.get_mut().priority = priority;
+    }
+
+    /// Returns true if the main module of the process has been loaded with
+    /// [`Module::from_bytes_debuggable`](crate::module::Module::from_bytes_debuggable), in which
+    /// case its threads can be paused with breakpoints or single-stepping.
+    pub fn is_debuggable(&self) -> bool {
+        self.process.get().state_machine.is_debuggable()
+    }
+
+    /// Enables or disables single-stepping. When enabled, the threads of the process are paused
+    /// and [`RunOneOutcome::Breakpoint`] returned before each instruction of the main module.
+    pub fn set_single_step(&mut self, enabled: bool) {
+        self.process
+            .get_mut()
+            .state_machine
+            .set_single_step(enabled)
+    }
+
+    /// Adds a breakpoint at the start of the function with the given index, as found in the
+    /// name section of the module. The threads of the process are paused and
+    /// [`RunOneOutcome::Breakpoint`] returned every time this function is called.
+    pub fn add_breakpoint(&mut self, function_index: u32) {
+        self.process
+            .get_mut()
+            .state_machine
+            .add_breakpoint(function_index)
+    }
+
+    /// Removes a breakpoint previously added with
+    /// [`add_breakpoint`](ProcessesCollectionProc::add_breakpoint).
+    pub fn remove_breakpoint(&mut self, function_index: u32) {
+        self.process
+            .get_mut()
+            .state_machine
+            .remove_breakpoint(function_index)
+    }
+
     /// Adds a new thread to the process, starting the function with the given index and passing
-    /// the given parameters.
+    /// the given parameters. The thread can optionally be given a human-readable name, used for
+    /// diagnostic purposes.
     ///
     /// > **Note**: The "function ID" is the index of the function in the WASM module. WASM
     /// >           doesn't have function pointers. Instead, all the functions are part of a single
@@ -518,27 +1908,57 @@ impl<'a, TPud, TTud> ProcessesCollectionProc<'a, TPud, TTud> {
         mut self,
         fn_index: u32,
         params: Vec<crate::WasmValue>,
+        name: Option<String>,
         user_data: TTud,
     ) -> Result<ProcessesCollectionThread<'a, TPud, TTud>, vm::StartErr> {
-        let thread_id = self.tid_pool.assign(); // TODO: check for duplicates
-        let thread_data = Thread {
+        let process = self.process.get();
+        if let Some(max_threads) = process.config.max_threads {
+            if process.state_machine.num_threads() >= max_threads {
+                return Err(vm::StartErr::TooManyThreads);
+            }
+        }
+
+        let thread_id = self.tid_pool.assign();
+        let mut thread_data = Thread {
             user_data,
             thread_id,
+            name,
             value_back: Some(None),
+            boosted: false,
+            deferred: false,
+            last_run: 0,
+            busy_streak: 0,
+            ticket: None,
+            extrinsic_called_at: None,
         };
 
+        self.ready
+            .push(*self.process.key(), &mut thread_data, false);
         self.process
             .get_mut()
             .state_machine
             .start_thread_by_id(fn_index, params, thread_data)?;
 
-        let thread_index = self.process.get_mut().state_machine.num_threads();
+        let thread_index = self.process.get_mut().state_machine.num_threads() - 1;
         Ok(ProcessesCollectionThread {
             process: self.process,
             thread_index,
+            ready: self.ready,
+            run_counter: self.run_counter,
         })
     }
 
+    /// Returns the name of the process, either passed through its [`ProcessConfig`] or found in
+    /// the name section of its module.
+    pub fn name(&self) -> Option<&str> {
+        self.process.get().name.as_ref().map(|n| &n[..])
+    }
+
+    /// Returns the limits that the process must respect.
+    pub fn config(&self) -> &ProcessConfig {
+        &self.process.get().config
+    }
+
     /// Returns an object representing the main thread of this process.
     ///
     /// The "main thread" of a process is created automatically when you call
@@ -547,9 +1967,49 @@ impl<'a, TPud, TTud> ProcessesCollectionProc<'a, TPud, TTud> {
         ProcessesCollectionThread {
             process: self.process,
             thread_index: 0,
+            ready: self.ready,
+            run_counter: self.run_counter,
         }
     }
 
+    /// Returns the [`ThreadId`]s of all the threads of the process. The first element is the
+    /// main thread's.
+    pub fn thread_ids<'b>(&'b self) -> impl ExactSizeIterator<Item = ThreadId> + 'b {
+        self.process
+            .get()
+            .state_machine
+            .user_datas()
+            .map(|thread| thread.thread_id)
+    }
+
+    /// Returns the thread of this process with the given [`ThreadId`], or `None` if it isn't a
+    /// thread of this process.
+    ///
+    /// Contrary to [`ProcessesCollection::thread_by_id`], only the threads of this process are
+    /// looked up.
+    pub fn thread_by_id(
+        mut self,
+        id: ThreadId,
+    ) -> Option<ProcessesCollectionThread<'a, TPud, TTud>> {
+        let thread_index = self.process.get_mut().thread_index_by_id(id)?;
+        Some(ProcessesCollectionThread {
+            process: self.process,
+            thread_index,
+            ready: self.ready,
+            run_counter: self.run_counter,
+        })
+    }
+
+    /// Returns the size of the memory of the process, in bytes.
+    pub fn memory_size(&self) -> u64 {
+        self.process.get().state_machine.memory_size()
+    }
+
+    /// Returns the number of threads of the process, including the main thread.
+    pub fn num_threads(&self) -> usize {
+        self.process.get().state_machine.num_threads()
+    }
+
     pub fn read_memory(&mut self, offset: u32, size: u32) -> Result<Vec<u8>, ()> {
         self.process
             .get_mut()
@@ -557,6 +2017,23 @@ impl<'a, TPud, TTud> ProcessesCollectionProc<'a, TPud, TTud> {
             .read_memory(offset, size)
     }
 
+    /// Copies the memory starting at `offset` into `buffer`, filling it entirely.
+    ///
+    /// Returns an error if the range is invalid or out of range.
+    pub fn read_memory_into(&self, offset: u32, buffer: &mut [u8]) -> Result<(), ()> {
+        self.process
+            .get()
+            .state_machine
+            .read_memory_into(offset, buffer)
+    }
+
+    /// Calls `f` with the given range of the memory, without copying it.
+    ///
+    /// Returns an error if the range is invalid or out of range.
+    pub fn with_memory<R>(&self, range: Range<u32>, f: impl FnOnce(&[u8]) -> R) -> Result<R, ()> {
+        self.process.get().state_machine.with_memory(range, f)
+    }
+
     /// Write the data at the given memory location.
     ///
     /// Returns an error if the range is invalid or out of range.
@@ -566,17 +2043,6 @@ impl<'a, TPud, TTud> ProcessesCollectionProc<'a, TPud, TTud> {
             .state_machine
             .write_memory(offset, value)
     }
-
-    /// Aborts the process and returns the associated user data.
-    pub fn abort(self) -> (TPud, Vec<(ThreadId, TTud)>) {
-        let (_, proc) = self.process.remove_entry();
-        let dead_threads = proc
-            .state_machine
-            .into_user_datas()
-            .map(|t| (t.thread_id, t.user_data))
-            .collect::<Vec<_>>();
-        (proc.user_data, dead_threads)
-    }
 }
 
 impl<'a, TPud, TTud> fmt::Debug for ProcessesCollectionProc<'a, TPud, TTud>
@@ -588,6 +2054,7 @@ where
         // TODO: threads user datas
         f.debug_struct("ProcessesCollectionProc")
             .field("pid", &self.pid())
+            .field("name", &self.name())
             //.field("user_data", self.user_data())     // TODO: requires &mut self :-/
             .finish()
     }
@@ -620,7 +2087,27 @@ impl<'a, TPud, TTud> ProcessesCollectionThread<'a, TPud, TTud> {
         *self.process.key()
     }
 
-    /// Returns the following thread within the next process, or `None` if this is the last thread.
+    /// Returns the name that was passed when the thread was
+    /// [started](ProcessesCollectionProc::start_thread). Always `None` for the main thread.
+    pub fn name(&self) -> Option<&str> {
+        self.thread_data().name.as_ref().map(|n| &n[..])
+    }
+
+    /// Same as [`ProcessesCollectionThread::inner`], but doesn't require a mutable borrow.
+    fn thread_data(&self) -> &Thread<TTud> {
+        match self
+            .process
+            .get()
+            .state_machine
+            .user_datas()
+            .nth(self.thread_index)
+        {
+            Some(t) => t,
+            None => unreachable!(),
+        }
+    }
+
+    /// Returns the following thread within the same process, or `None` if this is the last thread.
     ///
     /// Threads are ordered arbitrarily. In particular, they are **not** ordered by [`ThreadId`].
     pub fn next_thread(mut self) -> Option<ProcessesCollectionThread<'a, TPud, TTud>> {
@@ -645,6 +2132,7 @@ impl<'a, TPud, TTud> ProcessesCollectionThread<'a, TPud, TTud> {
     /// After [`RunOneOutcome::Interrupted`] is returned, use this function to feed back the value
     /// to use as the return type of the function that has been called.
     pub fn resume(&mut self, value: Option<crate::WasmValue>) {
+        let run_counter = self.run_counter;
         let user_data = self.inner().into_user_data();
 
         // TODO: check type of the value?
@@ -652,7 +2140,55 @@ impl<'a, TPud, TTud> ProcessesCollectionThread<'a, TPud, TTud> {
             panic!()
         }
 
+        let continuous = user_data.last_run == run_counter;
+        if continuous {
+            user_data.busy_streak = user_data.busy_streak.saturating_add(1);
+        } else {
+            user_data.busy_streak = 0;
+        }
+
         user_data.value_back = Some(value);
+
+        // A thread that is resumed right after running stays at the front of the queue if its
+        // process can still run more threads.
+        let front = continuous && self.process.get().deficit >= RUN_COST;
+        let pid = *self.process.key();
+        let thread_index = self.thread_index;
+        let mut thread = match self.process.get_mut().state_machine.thread(thread_index) {
+            Some(t) => t,
+            None => unreachable!(),
+        };
+        self.ready.push(pid, thread.user_data(), front);
+    }
+
+    /// Makes the thread run before the threads that haven't been boosted, the next time it is
+    /// ready to run.
+    ///
+    /// This is meant to be used when a thread is woken up by an event, such as the delivery of
+    /// a message, in order to reduce the latency of its reaction.
+    pub fn boost(&mut self) {
+        let pid = *self.process.key();
+        let thread_index = self.thread_index;
+        let mut thread = match self.process.get_mut().state_machine.thread(thread_index) {
+            Some(t) => t,
+            None => unreachable!(),
+        };
+        let user_data = thread.user_data();
+        if !user_data.boosted {
+            user_data.boosted = true;
+            if user_data.value_back.is_some() {
+                self.ready.push(pid, user_data, false);
+            }
+        }
+    }
+
+    /// Makes the thread run after the other threads that are ready, the next time it is ready
+    /// to run.
+    ///
+    /// This is meant to be used when a thread voluntarily gives up the CPU, in order to let the
+    /// other threads make progress.
+    pub fn defer(&mut self) {
+        self.inner().into_user_data().deferred = true;
     }
 
     pub fn read_memory(&mut self, offset: u32, size: u32) -> Result<Vec<u8>, ()> {
@@ -662,6 +2198,23 @@ impl<'a, TPud, TTud> ProcessesCollectionThread<'a, TPud, TTud> {
             .read_memory(offset, size)
     }
 
+    /// Copies the memory starting at `offset` into `buffer`, filling it entirely.
+    ///
+    /// Returns an error if the range is invalid or out of range.
+    pub fn read_memory_into(&self, offset: u32, buffer: &mut [u8]) -> Result<(), ()> {
+        self.process
+            .get()
+            .state_machine
+            .read_memory_into(offset, buffer)
+    }
+
+    /// Calls `f` with the given range of the memory, without copying it.
+    ///
+    /// Returns an error if the range is invalid or out of range.
+    pub fn with_memory<R>(&self, range: Range<u32>, f: impl FnOnce(&[u8]) -> R) -> Result<R, ()> {
+        self.process.get().state_machine.with_memory(range, f)
+    }
+
     /// Write the data at the given memory location.
     ///
     /// Returns an error if the range is invalid or out of range.
@@ -679,24 +2232,30 @@ where
     TTud: fmt::Debug,
 {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        //let id = self.id();
-        let pid = self.pid();
-        // TODO: requires &mut self :-/
-        //let ready_to_run = self.inner().into_user_data().value_back.is_some();
-
+        let thread = self.thread_data();
         f.debug_struct("ProcessesCollectionThread")
-            .field("pid", &pid)
-            //.field("thread_id", &id)
-            //.field("user_data", self.user_data())
-            //.field("ready_to_run", &ready_to_run)
+            .field("pid", &self.pid())
+            .field("thread_id", &thread.thread_id)
+            .field("name", &thread.name)
+            .field("user_data", &thread.user_data)
+            .field("ready_to_run", &thread.value_back.is_some())
             .finish()
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::ProcessesCollectionBuilder;
-    use crate::sig;
+    use super::{
+        ExtrinsicsAllowlist, ForkErr, LimitExceeded, ModuleCache, OrphanPolicy, ProcessConfig,
+        ProcessesCollectionBuilder, RunOneOutcome,
+    };
+    use crate::scheduler::vm::{self, NewErr};
+    use crate::scheduler::SchedulerObserver;
+    use crate::{sig, TrapKind, WasmValue};
+    use alloc::{format, rc::Rc, vec, vec::Vec};
+    use core::cell::RefCell;
+    use futures::prelude::*;
+    use redshirt_syscalls::{Pid, ThreadId};
 
     #[test]
     #[should_panic]
@@ -705,4 +2264,833 @@ mod tests {
             .with_extrinsic("foo", "test", sig!(()), ())
             .with_extrinsic("foo", "test", sig!(()), ());
     }
+
+    #[test]
+    fn boosted_thread_runs_first() {
+        let module = from_wat!(
+            local,
+            r#"(module
+            (import "foo" "test" (func $test))
+            (func $_start
+                call $test)
+            (export "_start" (func $_start)))
+        "#
+        );
+
+        let mut processes = ProcessesCollectionBuilder::<()>::default()
+            .with_extrinsic("foo", "test", sig!(()), ())
+            .build::<(), ()>();
+        processes
+            .execute(&module, None, Default::default(), (), ())
+            .unwrap();
+        processes
+            .execute(&module, None, Default::default(), (), ())
+            .unwrap();
+
+        let mut interrupted = Vec::new();
+        for _ in 0..2 {
+            match processes.run(u64::max_value()) {
+                RunOneOutcome::Interrupted { mut thread, .. } => {
+                    interrupted.push((thread.pid(), thread.tid()))
+                }
+                _ => panic!(),
+            }
+        }
+
+        // Resume both threads, but boost the one that has been interrupted last.
+        processes
+            .thread_by_id(interrupted[0].1)
+            .unwrap()
+            .resume(None);
+        let mut thread = processes.thread_by_id(interrupted[1].1).unwrap();
+        thread.resume(None);
+        thread.boost();
+
+        match processes.run(u64::max_value()) {
+            RunOneOutcome::ProcessFinished { pid, .. } => assert_eq!(pid, interrupted[1].0),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn deferred_thread_runs_last() {
+        let module = from_wat!(
+            local,
+            r#"(module
+            (import "foo" "test" (func $test))
+            (func $_start
+                call $test)
+            (export "_start" (func $_start)))
+        "#
+        );
+
+        let mut processes = ProcessesCollectionBuilder::<()>::default()
+            .with_extrinsic("foo", "test", sig!(()), ())
+            .build::<(), ()>();
+        processes
+            .execute(&module, None, Default::default(), (), ())
+            .unwrap();
+        processes
+            .execute(&module, None, Default::default(), (), ())
+            .unwrap();
+
+        let mut interrupted = Vec::new();
+        for _ in 0..2 {
+            match processes.run(u64::max_value()) {
+                RunOneOutcome::Interrupted { mut thread, .. } => {
+                    interrupted.push((thread.pid(), thread.tid()))
+                }
+                _ => panic!(),
+            }
+        }
+
+        // Resume both threads, but defer the one that has been interrupted first.
+        let mut thread = processes.thread_by_id(interrupted[0].1).unwrap();
+        thread.resume(None);
+        thread.defer();
+        processes
+            .thread_by_id(interrupted[1].1)
+            .unwrap()
+            .resume(None);
+
+        match processes.run(u64::max_value()) {
+            RunOneOutcome::ProcessFinished { pid, .. } => assert_eq!(pid, interrupted[1].0),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn allowlist_restricts_imports() {
+        let module = from_wat!(
+            local,
+            r#"(module
+            (import "foo" "test" (func $test))
+            (func $_start
+                call $test)
+            (export "_start" (func $_start)))
+        "#
+        );
+
+        let mut processes = ProcessesCollectionBuilder::<()>::default()
+            .with_extrinsic("foo", "test", sig!(()), ())
+            .with_extrinsic("foo", "other", sig!(()), ())
+            .build::<(), ()>();
+
+        let allowlist = ExtrinsicsAllowlist::new().with_extrinsic("foo", "other");
+        assert!(!processes
+            .abi_report_with_allowlist(&module, &allowlist)
+            .is_compatible());
+        match processes.execute_with_allowlist(
+            &module,
+            &allowlist,
+            None,
+            Default::default(),
+            (),
+            (),
+        ) {
+            Err(NewErr::IncompatibleAbi(_)) => {}
+            _ => panic!(),
+        }
+
+        let allowlist = allowlist.with_extrinsic("foo", "test");
+        assert!(processes
+            .execute_with_allowlist(&module, &allowlist, None, Default::default(), (), ())
+            .is_ok());
+        let allowlist = ExtrinsicsAllowlist::new().with_interface("foo");
+        assert!(processes
+            .execute_with_allowlist(&module, &allowlist, None, Default::default(), (), ())
+            .is_ok());
+    }
+
+    #[test]
+    fn busy_thread_demoted() {
+        let busy = from_wat!(
+            local,
+            r#"(module
+            (import "foo" "test" (func $test))
+            (func $_start
+                (loop $l
+                    call $test
+                    br $l))
+            (export "_start" (func $_start)))
+        "#
+        );
+        let once = from_wat!(
+            local,
+            r#"(module
+            (import "foo" "test" (func $test))
+            (func $_start
+                call $test)
+            (export "_start" (func $_start)))
+        "#
+        );
+
+        let mut processes = ProcessesCollectionBuilder::<()>::default()
+            .with_extrinsic("foo", "test", sig!(()), ())
+            .with_demotion_threshold(3)
+            .build::<(), ()>();
+        let busy_pid = processes
+            .execute(&busy, None, Default::default(), (), ())
+            .unwrap()
+            .pid();
+        let once_pid = processes
+            .execute(&once, None, Default::default(), (), ())
+            .unwrap()
+            .pid();
+
+        // The busy process is always resumed immediately, while the other one is kept waiting.
+        let mut once_tid = None;
+        let mut busy_resumes = 0;
+        while busy_resumes < 3 {
+            match processes.run(u64::max_value()) {
+                RunOneOutcome::Interrupted { mut thread, .. } if thread.pid() == busy_pid => {
+                    thread.resume(None);
+                    busy_resumes += 1;
+                }
+                RunOneOutcome::Interrupted { mut thread, .. } => once_tid = Some(thread.tid()),
+                _ => panic!(),
+            }
+        }
+        if once_tid.is_none() {
+            match processes.run(u64::max_value()) {
+                RunOneOutcome::Interrupted { mut thread, .. } => {
+                    assert_eq!(thread.pid(), once_pid);
+                    once_tid = Some(thread.tid());
+                }
+                _ => panic!(),
+            }
+        }
+
+        // Both processes are ready, but the busy one has been demoted.
+        processes
+            .thread_by_id(once_tid.unwrap())
+            .unwrap()
+            .resume(None);
+        match processes.run(u64::max_value()) {
+            RunOneOutcome::ProcessFinished { pid, .. } => assert_eq!(pid, once_pid),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn ready_threads_take_turns() {
+        let module = from_wat!(
+            local,
+            r#"(module
+            (import "foo" "test" (func $test))
+            (func $_start
+                (loop $l
+                    call $test
+                    br $l))
+            (export "_start" (func $_start)))
+        "#
+        );
+
+        let mut processes = ProcessesCollectionBuilder::<()>::default()
+            .with_extrinsic("foo", "test", sig!(()), ())
+            .with_demotion_threshold(u32::max_value())
+            .build::<(), ()>();
+        let pids = (0..3)
+            .map(|_| {
+                processes
+                    .execute(&module, None, Default::default(), (), ())
+                    .unwrap()
+                    .pid()
+            })
+            .collect::<Vec<_>>();
+
+        // Every process runs once before any of them runs again, no matter how the hash map
+        // of the processes is ordered.
+        for _ in 0..4 {
+            for pid in &pids {
+                match processes.run(u64::max_value()) {
+                    RunOneOutcome::Interrupted { mut thread, .. } => {
+                        assert_eq!(thread.pid(), *pid);
+                        thread.resume(None);
+                    }
+                    _ => panic!(),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn killed_process_doesnt_run() {
+        let module = from_wat!(
+            local,
+            r#"(module
+            (import "foo" "test" (func $test))
+            (func $_start
+                call $test)
+            (export "_start" (func $_start)))
+        "#
+        );
+
+        let mut processes = ProcessesCollectionBuilder::<()>::default()
+            .with_extrinsic("foo", "test", sig!(()), ())
+            .build::<u32, ()>();
+        let killed = processes
+            .execute(&module, None, Default::default(), 1, ())
+            .unwrap()
+            .pid();
+        let survivor = processes
+            .execute(&module, None, Default::default(), 2, ())
+            .unwrap()
+            .pid();
+
+        let outcome = processes.kill_process(killed).unwrap();
+        assert_eq!(outcome.pid, killed);
+        assert_eq!(outcome.user_data, 1);
+        assert_eq!(outcome.dead_threads.len(), 1);
+        assert!(processes.kill_process(killed).is_none());
+        assert!(!processes.is_process_alive(killed));
+        assert!(processes.is_process_alive(survivor));
+
+        match processes.run(u64::max_value()) {
+            RunOneOutcome::Interrupted { thread, .. } => assert_eq!(thread.pid(), survivor),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn orphans_reparented_or_killed() {
+        let module = from_wat!(
+            local,
+            r#"(module
+            (func $_start)
+            (export "_start" (func $_start)))
+        "#
+        );
+
+        for policy in &[OrphanPolicy::Reparent, OrphanPolicy::Kill] {
+            let mut processes = ProcessesCollectionBuilder::<()>::default()
+                .with_orphan_policy(*policy)
+                .build::<(), ()>();
+            let root = processes
+                .execute(&module, None, Default::default(), (), ())
+                .unwrap()
+                .pid();
+            let middle = processes
+                .execute(&module, Some(root), Default::default(), (), ())
+                .unwrap()
+                .pid();
+            let leaf = processes
+                .execute(&module, Some(middle), Default::default(), (), ())
+                .unwrap()
+                .pid();
+            assert_eq!(processes.parent(leaf), Some(middle));
+            assert_eq!(processes.children(root).collect::<Vec<_>>(), [middle]);
+
+            let killed = processes.kill_process(middle).unwrap();
+            match policy {
+                OrphanPolicy::Reparent => {
+                    assert!(killed.children_to_kill.is_empty());
+                    assert_eq!(processes.parent(leaf), Some(root));
+                    assert_eq!(processes.children(root).collect::<Vec<_>>(), [leaf]);
+                }
+                OrphanPolicy::Kill => {
+                    assert_eq!(killed.children_to_kill, [leaf]);
+                    assert_eq!(processes.parent(leaf), None);
+                    assert_eq!(processes.children(root).len(), 0);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn priority_weights_execution() {
+        let module = from_wat!(
+            local,
+            r#"(module
+            (import "foo" "test" (func $test))
+            (func $_start
+                (loop $l
+                    call $test
+                    br $l))
+            (export "_start" (func $_start)))
+        "#
+        );
+
+        let mut processes = ProcessesCollectionBuilder::<()>::default()
+            .with_extrinsic("foo", "test", sig!(()), ())
+            .with_demotion_threshold(u32::max_value())
+            .build::<(), ()>();
+        let mut high = processes
+            .execute(&module, None, Default::default(), (), ())
+            .unwrap();
+        high.set_priority(24);
+        assert_eq!(high.priority(), 24);
+        let high_pid = high.pid();
+        processes
+            .execute(&module, None, Default::default(), (), ())
+            .unwrap();
+
+        let mut high_runs = 0;
+        for _ in 0..40 {
+            match processes.run(u64::max_value()) {
+                RunOneOutcome::Interrupted { mut thread, .. } => {
+                    if thread.pid() == high_pid {
+                        high_runs += 1;
+                    }
+                    thread.resume(None);
+                }
+                _ => panic!(),
+            }
+        }
+
+        // The default priority being 8, the first process should run three times as often.
+        assert!((29..=31).contains(&high_runs));
+    }
+
+    #[test]
+    fn run_async_waits_for_ready_thread() {
+        let module = from_wat!(
+            local,
+            r#"(module
+            (import "foo" "test" (func $test))
+            (func $_start
+                call $test)
+            (export "_start" (func $_start)))
+        "#
+        );
+
+        let mut processes = ProcessesCollectionBuilder::<()>::default()
+            .with_extrinsic("foo", "test", sig!(()), ())
+            .build::<(), ()>();
+        assert!(processes
+            .run_async(u64::max_value())
+            .now_or_never()
+            .is_none());
+
+        processes
+            .execute(&module, None, Default::default(), (), ())
+            .unwrap();
+        let tid = match futures::executor::block_on(processes.run_async(u64::max_value())) {
+            RunOneOutcome::Interrupted { mut thread, .. } => thread.tid(),
+            _ => panic!(),
+        };
+        assert!(processes
+            .run_async(u64::max_value())
+            .now_or_never()
+            .is_none());
+
+        processes.thread_by_id(tid).unwrap().resume(None);
+        match processes.run_async(u64::max_value()).now_or_never() {
+            Some(RunOneOutcome::ProcessFinished { .. }) => {}
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn threads_of_process() {
+        let module = from_wat!(
+            local,
+            r#"(module
+            (import "foo" "test" (func $test))
+            (table (export "__indirect_function_table") 1 funcref)
+            (elem (i32.const 0) $thread)
+            (func $thread
+                call $test)
+            (func $_start
+                call $test)
+            (export "_start" (func $_start)))
+        "#
+        );
+
+        let mut processes = ProcessesCollectionBuilder::<()>::default()
+            .with_extrinsic("foo", "test", sig!(()), ())
+            .build::<(), u32>();
+        let other = processes
+            .execute(&module, None, Default::default(), (), 0)
+            .unwrap()
+            .pid();
+        let pid = processes
+            .execute(&module, None, Default::default(), (), 1)
+            .unwrap()
+            .pid();
+        processes
+            .process_by_id(pid)
+            .unwrap()
+            .start_thread(0, Vec::new(), None, 2)
+            .unwrap();
+
+        let process = processes.process_by_id(pid).unwrap();
+        let thread_ids = process.thread_ids().collect::<Vec<_>>();
+        assert_eq!(thread_ids.len(), 2);
+        let mut thread = process.thread_by_id(thread_ids[1]).unwrap();
+        assert_eq!(*thread.user_data(), 2);
+
+        let other_main = processes.process_by_id(other).unwrap().main_thread().tid();
+        assert!(processes
+            .process_by_id(pid)
+            .unwrap()
+            .thread_by_id(other_main)
+            .is_none());
+    }
+
+    #[test]
+    fn names_reported() {
+        let module = from_wat!(
+            local,
+            r#"(module $foo
+            (import "foo" "test" (func $test))
+            (table (export "__indirect_function_table") 1 funcref)
+            (elem (i32.const 0) $thread)
+            (func $thread
+                call $test)
+            (func $_start
+                call $test)
+            (export "_start" (func $_start)))
+        "#
+        );
+        assert_eq!(module.name(), Some("foo"));
+
+        let mut processes = ProcessesCollectionBuilder::<()>::default()
+            .with_extrinsic("foo", "test", sig!(()), ())
+            .build::<(), ()>();
+        let process = processes
+            .execute(&module, None, Default::default(), (), ())
+            .unwrap();
+        assert_eq!(process.name(), Some("foo"));
+        let thread = process
+            .start_thread(0, Vec::new(), Some("worker".into()), ())
+            .unwrap();
+        assert_eq!(thread.name(), Some("worker"));
+        assert!(format!("{:?}", thread).contains("worker"));
+
+        let config = ProcessConfig {
+            name: Some("bar".into()),
+            ..Default::default()
+        };
+        let process = processes.execute(&module, None, config, (), ()).unwrap();
+        assert_eq!(process.name(), Some("bar"));
+        assert!(format!("{:?}", process).contains("bar"));
+    }
+
+    #[test]
+    fn fork_copies_state() {
+        let module = from_wat!(
+            local,
+            r#"(module
+            (import "foo" "test" (func $test (param i32 i32)))
+            (memory (export "memory") 1)
+            (global $counter (mut i32) (i32.const 0))
+            (table (export "__indirect_function_table") 1 funcref)
+            (elem (i32.const 0) $entry)
+            (func $entry (param i32)
+                global.get $counter
+                local.get 0
+                i32.add
+                i32.const 8
+                i32.load
+                call $test)
+            (func $_start
+                i32.const 5
+                global.set $counter
+                i32.const 8
+                i32.const 42
+                i32.store
+                global.get $counter
+                i32.const 0
+                call $test)
+            (export "_start" (func $_start)))
+        "#
+        );
+
+        let mut processes = ProcessesCollectionBuilder::<()>::default()
+            .with_extrinsic("foo", "test", sig!((I32, I32)), ())
+            .build::<(), ()>();
+        let pid = processes
+            .execute(&module, None, Default::default(), (), ())
+            .unwrap()
+            .pid();
+        match processes.run(u64::max_value()) {
+            RunOneOutcome::Interrupted { thread, .. } => assert_eq!(thread.pid(), pid),
+            _ => panic!(),
+        }
+
+        let fork = processes
+            .fork(pid, &module, 0, vec![WasmValue::I32(10)], (), ())
+            .unwrap()
+            .pid();
+        assert_eq!(processes.parent(fork), Some(pid));
+        match processes.run(u64::max_value()) {
+            RunOneOutcome::Interrupted { thread, params, .. } => {
+                assert_eq!(thread.pid(), fork);
+                assert_eq!(params[0].into_i32(), Some(15));
+                assert_eq!(params[1].into_i32(), Some(42));
+            }
+            _ => panic!(),
+        }
+
+        let other = from_wat!(
+            local,
+            r#"(module
+            (func $_start)
+            (export "_start" (func $_start)))
+        "#
+        );
+        match processes.fork(pid, &other, 0, Vec::new(), (), ()) {
+            Err(ForkErr::ModuleMismatch) => {}
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn cached_module_executed_several_times() {
+        let module = from_wat!(
+            local,
+            r#"(module
+            (import "foo" "test" (func $test))
+            (func $_start
+                call $test)
+            (export "_start" (func $_start)))
+        "#
+        );
+        let hash = module.hash().clone();
+
+        let mut processes = ProcessesCollectionBuilder::<()>::default()
+            .with_extrinsic("foo", "test", sig!(()), ())
+            .build::<(), ()>();
+        let mut cache = ModuleCache::new();
+        cache.insert(processes.cache_module(module).unwrap());
+        let cached = cache.get(&hash).unwrap();
+
+        for _ in 0..3 {
+            processes
+                .execute_cached(cached, None, Default::default(), (), ())
+                .unwrap();
+        }
+        for _ in 0..3 {
+            match processes.run(u64::max_value()) {
+                RunOneOutcome::Interrupted { .. } => {}
+                _ => panic!(),
+            }
+        }
+
+        let incompatible = from_wat!(
+            local,
+            r#"(module
+            (import "foo" "unknown" (func $unknown))
+            (func $_start
+                call $unknown)
+            (export "_start" (func $_start)))
+        "#
+        );
+        match processes.cache_module(incompatible) {
+            Err(NewErr::IncompatibleAbi(_)) => {}
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn resource_usage_reported() {
+        let module = from_wat!(
+            local,
+            r#"(module
+            (import "foo" "test" (func $test))
+            (memory (export "memory") 2)
+            (func $_start
+                call $test)
+            (export "_start" (func $_start)))
+        "#
+        );
+
+        let mut processes = ProcessesCollectionBuilder::<()>::default()
+            .with_extrinsic("foo", "test", sig!(()), ())
+            .build::<(), ()>();
+        let process = processes
+            .execute(&module, None, Default::default(), (), ())
+            .unwrap();
+        assert_eq!(process.memory_size(), 2 * 65536);
+        assert_eq!(process.num_threads(), 1);
+        processes
+            .execute(&module, None, Default::default(), (), ())
+            .unwrap();
+
+        let usage = processes.resource_usage();
+        assert_eq!(usage.num_processes, 2);
+        assert_eq!(usage.num_threads, 2);
+        assert_eq!(usage.memory_bytes, 4 * 65536);
+    }
+
+    #[test]
+    fn resource_usage_without_memory() {
+        let module = from_wat!(
+            local,
+            r#"(module
+            (import "foo" "test" (func $test))
+            (func $_start
+                call $test)
+            (export "_start" (func $_start)))
+        "#
+        );
+
+        let mut processes = ProcessesCollectionBuilder::<()>::default()
+            .with_extrinsic("foo", "test", sig!(()), ())
+            .build::<(), ()>();
+        let process = processes
+            .execute(&module, None, Default::default(), (), ())
+            .unwrap();
+        assert_eq!(process.memory_size(), 0);
+
+        let usage = processes.resource_usage();
+        assert_eq!(usage.num_processes, 1);
+        assert_eq!(usage.memory_bytes, 0);
+    }
+
+    #[test]
+    fn resource_limits_enforced() {
+        let module = from_wat!(
+            local,
+            r#"(module
+            (import "foo" "test" (func $test))
+            (memory (export "memory") 1)
+            (func $_start
+                (drop (memory.grow (i32.const 2)))
+                call $test)
+            (export "_start" (func $_start)))
+        "#
+        );
+
+        let mut processes = ProcessesCollectionBuilder::<()>::default()
+            .with_extrinsic("foo", "test", sig!(()), ())
+            .build::<(), ()>();
+        let config = ProcessConfig {
+            max_memory_pages: Some(2),
+            max_threads: Some(1),
+            ..Default::default()
+        };
+        let process = processes.execute(&module, None, config, (), ()).unwrap();
+        assert_eq!(process.config().max_threads, Some(1));
+        match process.start_thread(0, Vec::new(), None, ()) {
+            Err(vm::StartErr::TooManyThreads) => {}
+            _ => panic!(),
+        }
+
+        match processes.run(u64::max_value()) {
+            RunOneOutcome::ProcessFinished {
+                outcome: Err(trap), ..
+            } => assert_eq!(
+                trap.kind(),
+                &TrapKind::LimitExceeded(LimitExceeded::MemoryPages { max: 2 })
+            ),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn stack_depth_enforced() {
+        let module = from_wat!(
+            local,
+            r#"(module
+            (func $rec
+                call $rec)
+            (func $_start
+                call $rec)
+            (export "_start" (func $_start)))
+        "#
+        );
+
+        let mut processes = ProcessesCollectionBuilder::<()>::default()
+            .with_max_stack_depth(64)
+            .build::<(), ()>();
+        processes
+            .execute(&module, None, Default::default(), (), ())
+            .unwrap();
+
+        match processes.run(u64::max_value()) {
+            RunOneOutcome::ProcessFinished {
+                outcome: Err(trap), ..
+            } => assert_eq!(
+                trap.kind(),
+                &TrapKind::LimitExceeded(LimitExceeded::StackDepth { max: Some(64) })
+            ),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn observer_informed() {
+        #[derive(Debug, PartialEq, Eq)]
+        enum Event {
+            Created,
+            Destroyed,
+            Scheduled,
+            ExtrinsicCalled(u32),
+            ExtrinsicReturned(u64),
+            Finished,
+        }
+
+        struct Recorder(Rc<RefCell<Vec<Event>>>, u64);
+        impl SchedulerObserver<u32> for Recorder {
+            fn now(&mut self) -> u64 {
+                self.1 += 10;
+                self.1
+            }
+            fn process_created(&mut self, _: Pid, _: Option<&str>) {
+                self.0.borrow_mut().push(Event::Created);
+            }
+            fn process_destroyed(&mut self, _: Pid) {
+                self.0.borrow_mut().push(Event::Destroyed);
+            }
+            fn thread_scheduled(&mut self, _: Pid, _: ThreadId, _: Option<&str>) {
+                self.0.borrow_mut().push(Event::Scheduled);
+            }
+            fn extrinsic_called(&mut self, _: Pid, _: ThreadId, extrinsic: &u32) {
+                self.0.borrow_mut().push(Event::ExtrinsicCalled(*extrinsic));
+            }
+            fn extrinsic_returned(&mut self, _: Pid, _: ThreadId, duration: u64) {
+                self.0.borrow_mut().push(Event::ExtrinsicReturned(duration));
+            }
+            fn thread_finished(&mut self, _: Pid, _: ThreadId) {
+                self.0.borrow_mut().push(Event::Finished);
+            }
+        }
+
+        let module = from_wat!(
+            local,
+            r#"(module
+            (import "foo" "test" (func $test))
+            (func $_start
+                call $test)
+            (export "_start" (func $_start)))
+        "#
+        );
+
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let mut processes = ProcessesCollectionBuilder::<u32>::default()
+            .with_extrinsic("foo", "test", sig!(()), 7u32)
+            .with_observer(Recorder(events.clone(), 0))
+            .build::<(), ()>();
+        processes
+            .execute(&module, None, Default::default(), (), ())
+            .unwrap();
+
+        let tid = match processes.run(u64::max_value()) {
+            RunOneOutcome::Interrupted { mut thread, id, .. } => {
+                assert_eq!(*id, 7);
+                thread.tid()
+            }
+            _ => panic!(),
+        };
+        processes.thread_by_id(tid).unwrap().resume(None);
+        match processes.run(u64::max_value()) {
+            RunOneOutcome::ProcessFinished { .. } => {}
+            _ => panic!(),
+        }
+
+        assert_eq!(
+            *events.borrow(),
+            [
+                Event::Created,
+                Event::Scheduled,
+                Event::ExtrinsicCalled(7),
+                Event::ExtrinsicReturned(10),
+                Event::Scheduled,
+                Event::Finished,
+                Event::Destroyed,
+            ]
+        );
+    }
 }
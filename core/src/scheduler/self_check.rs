@@ -0,0 +1,210 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Verification of the internal invariants of the scheduler.
+//!
+//! Bugs in the bookkeeping of the scheduler, such as a message identifier that is never
+//! released, generally don't cause any visible misbehaviour in the short term, but accumulate
+//! over time. Calling [`Core::self_check`](crate::scheduler::Core::self_check) periodically
+//! during long-running tests makes it possible to detect them.
+
+use crate::{InterfaceHash, MessageId};
+use core::fmt;
+use redshirt_syscalls::{Pid, ThreadId};
+
+/// Configuration of the self-checks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SelfCheckConfig {
+    /// When the checks are performed automatically by a [`System`](crate::System), number of
+    /// iterations of its main loop between two checks.
+    pub period: u32,
+    /// Maximum number of notifications that can be queued for a process.
+    pub max_notifications_queue_len: usize,
+    /// Maximum number of messages that can be waiting for an answer at any given time.
+    pub max_pending_answers: usize,
+    /// Maximum number of messages that can be waiting for an interface to be registered.
+    pub max_interface_queue_len: usize,
+}
+
+impl Default for SelfCheckConfig {
+    fn default() -> Self {
+        SelfCheckConfig {
+            period: 10_000,
+            max_notifications_queue_len: 1024,
+            max_pending_answers: 65536,
+            max_interface_queue_len: 1024,
+        }
+    }
+}
+
+/// Invariant that has been found to be violated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Violation {
+    /// A message is waiting for an answer, but its emitter no longer exists.
+    OrphanedMessage {
+        /// Message waiting for an answer.
+        message_id: MessageId,
+        /// Emitter of the message.
+        emitter: Pid,
+    },
+    /// A message identifier that is never supposed to be assigned is in use.
+    ReservedMessageId {
+        /// The faulty identifier.
+        message_id: MessageId,
+    },
+    /// Too many messages are waiting for an answer.
+    TooManyPendingAnswers {
+        /// Number of messages waiting for an answer.
+        len: usize,
+        /// Value of [`SelfCheckConfig::max_pending_answers`].
+        max: usize,
+    },
+    /// The queue of notifications of a process is longer than allowed.
+    NotificationsQueueTooLong {
+        /// Process whose queue is too long.
+        pid: Pid,
+        /// Number of notifications in the queue.
+        len: usize,
+        /// Value of [`SelfCheckConfig::max_notifications_queue_len`].
+        max: usize,
+    },
+    /// An interface is registered by a process that no longer exists.
+    StaleInterfaceHandler {
+        /// The registered interface.
+        interface: InterfaceHash,
+        /// Process registered as the handler.
+        pid: Pid,
+    },
+    /// Too many messages are waiting for an interface to be registered.
+    InterfaceQueueTooLong {
+        /// The interface that isn't registered.
+        interface: InterfaceHash,
+        /// Number of messages waiting.
+        len: usize,
+        /// Value of [`SelfCheckConfig::max_interface_queue_len`].
+        max: usize,
+    },
+    /// A thread is marked as waiting for an interface to be registered, but no longer exists.
+    StaleWaitingThread {
+        /// The interface that isn't registered.
+        interface: InterfaceHash,
+        /// The thread that was waiting.
+        thread_id: ThreadId,
+    },
+    /// A process uses a [`Pid`] that has been reserved.
+    ReservedPidInUse {
+        /// The faulty [`Pid`].
+        pid: Pid,
+    },
+    /// The same [`ThreadId`] is assigned to multiple threads.
+    DuplicateThreadId {
+        /// The faulty identifier.
+        thread_id: ThreadId,
+    },
+    /// A process that has no thread is still alive.
+    ProcessWithoutThread {
+        /// The faulty process.
+        pid: Pid,
+    },
+    /// A process whose virtual machine is poisoned is still alive.
+    PoisonedProcess {
+        /// The faulty process.
+        pid: Pid,
+    },
+    /// The state of a thread in the virtual machine is missing.
+    InvalidThreadState {
+        /// Process the thread belongs to.
+        pid: Pid,
+        /// Index of the thread in the virtual machine.
+        thread_index: usize,
+    },
+    /// A thread is in the queue of threads ready to run, but doesn't exist or isn't ready.
+    InvalidRunQueueEntry {
+        /// The thread in the queue.
+        thread_id: ThreadId,
+    },
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Violation::OrphanedMessage {
+                message_id,
+                emitter,
+            } => write!(
+                f,
+                "Message {:?} is waiting for an answer but its emitter {:?} is dead",
+                message_id, emitter
+            ),
+            Violation::ReservedMessageId { message_id } => {
+                write!(f, "Reserved message id {:?} is in use", message_id)
+            }
+            Violation::TooManyPendingAnswers { len, max } => write!(
+                f,
+                "{} messages are waiting for an answer (maximum {})",
+                len, max
+            ),
+            Violation::NotificationsQueueTooLong { pid, len, max } => write!(
+                f,
+                "{} notifications are queued for {:?} (maximum {})",
+                len, pid, max
+            ),
+            Violation::StaleInterfaceHandler { interface, pid } => write!(
+                f,
+                "Interface {:?} is registered by dead process {:?}",
+                interface, pid
+            ),
+            Violation::InterfaceQueueTooLong {
+                interface,
+                len,
+                max,
+            } => write!(
+                f,
+                "{} messages are waiting for interface {:?} (maximum {})",
+                len, interface, max
+            ),
+            Violation::StaleWaitingThread {
+                interface,
+                thread_id,
+            } => write!(
+                f,
+                "Dead thread {:?} is waiting for interface {:?}",
+                thread_id, interface
+            ),
+            Violation::ReservedPidInUse { pid } => {
+                write!(f, "Reserved pid {:?} is used by a process", pid)
+            }
+            Violation::DuplicateThreadId { thread_id } => {
+                write!(f, "Thread id {:?} is used by multiple threads", thread_id)
+            }
+            Violation::ProcessWithoutThread { pid } => {
+                write!(f, "Process {:?} has no thread", pid)
+            }
+            Violation::PoisonedProcess { pid } => {
+                write!(f, "Process {:?} is poisoned but still alive", pid)
+            }
+            Violation::InvalidThreadState { pid, thread_index } => write!(
+                f,
+                "Thread #{} of process {:?} has no execution state",
+                thread_index, pid
+            ),
+            Violation::InvalidRunQueueEntry { thread_id } => write!(
+                f,
+                "Thread {:?} is in the run queue but isn't ready to run",
+                thread_id
+            ),
+        }
+    }
+}
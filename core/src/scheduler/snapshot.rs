@@ -0,0 +1,188 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Snapshots of the memory of a process.
+//!
+//! Capturing two [`MemorySnapshot`]s of the same process at different points in time, then
+//! calling [`MemorySnapshot::diff`], indicates which regions of the memory have been modified
+//! in between, and by how much the memory has grown. Doing so repeatedly while a program is
+//! supposed to be in a steady state is a way to track down memory leaks.
+//!
+//! A snapshot doesn't hold a copy of the memory, but only a hash of each of its pages. It is
+//! therefore cheap to keep around.
+
+use alloc::vec::Vec;
+use core::{cmp, convert::TryFrom as _, fmt};
+
+/// Granularity, in bytes, of the comparison between two snapshots.
+///
+/// This is smaller than the size of a WASM page, as WASM pages are too large to give useful
+/// information.
+pub const SNAPSHOT_PAGE_SIZE: u32 = 4096;
+
+/// Snapshot of the memory of a process.
+#[derive(Clone, PartialEq, Eq)]
+pub struct MemorySnapshot {
+    /// Size of the memory, in bytes.
+    memory_size: u64,
+    /// Hash of each page of [`SNAPSHOT_PAGE_SIZE`] bytes of the memory, in order.
+    pages: Vec<[u8; 32]>,
+}
+
+/// Differences between two [`MemorySnapshot`]s.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryDiff {
+    /// Size of the memory, in bytes, in the older snapshot.
+    pub size_before: u64,
+    /// Size of the memory, in bytes, in the newer snapshot.
+    pub size_after: u64,
+    /// Regions of the memory that exist in both snapshots and whose content has changed, in
+    /// increasing order. Adjacent modified pages are merged into a single region.
+    pub changed_regions: Vec<MemoryRegion>,
+}
+
+/// Range of the memory of a process.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryRegion {
+    /// Offset of the start of the region, in bytes.
+    pub start: u64,
+    /// Length of the region, in bytes.
+    pub len: u64,
+}
+
+impl MemorySnapshot {
+    /// Builds a snapshot of a memory of the given size.
+    ///
+    /// The closure is called with an offset and a size, and must return the content of the
+    /// memory in that range.
+    pub(crate) fn capture(
+        memory_size: u64,
+        mut read_memory: impl FnMut(u32, u32) -> Result<Vec<u8>, ()>,
+    ) -> Self {
+        let page_size = u64::from(SNAPSHOT_PAGE_SIZE);
+        let num_pages = (memory_size + page_size - 1) / page_size;
+
+        let pages = (0..num_pages)
+            .map(|page| {
+                let offset = page * page_size;
+                let size = cmp::min(page_size, memory_size - offset);
+                // The memory is at most 4GiB large, and these conversions can't fail.
+                let offset = u32::try_from(offset).unwrap();
+                let size = u32::try_from(size).unwrap();
+                // `read_memory` can only fail if the range is out of the memory.
+                let content = read_memory(offset, size).unwrap();
+                blake3::hash(&content).into()
+            })
+            .collect();
+
+        MemorySnapshot { memory_size, pages }
+    }
+
+    /// Returns the size of the memory at the time of the snapshot, in bytes.
+    pub fn memory_size(&self) -> u64 {
+        self.memory_size
+    }
+
+    /// Compares this snapshot with a more recent snapshot of the same process.
+    pub fn diff(&self, after: &MemorySnapshot) -> MemoryDiff {
+        let page_size = u64::from(SNAPSHOT_PAGE_SIZE);
+        let common_size = cmp::min(self.memory_size, after.memory_size);
+
+        let mut changed_regions = Vec::<MemoryRegion>::new();
+        for (page, (before, after)) in self.pages.iter().zip(after.pages.iter()).enumerate() {
+            if before == after {
+                continue;
+            }
+
+            let start = page as u64 * page_size;
+            let len = cmp::min(page_size, common_size - start);
+            match changed_regions.last_mut() {
+                Some(region) if region.start + region.len == start => region.len += len,
+                _ => changed_regions.push(MemoryRegion { start, len }),
+            }
+        }
+
+        MemoryDiff {
+            size_before: self.memory_size,
+            size_after: after.memory_size,
+            changed_regions,
+        }
+    }
+}
+
+impl fmt::Debug for MemorySnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MemorySnapshot")
+            .field("memory_size", &self.memory_size)
+            .finish()
+    }
+}
+
+impl MemoryDiff {
+    /// Returns by how many bytes the memory has grown. Negative if the memory has shrunk.
+    pub fn growth(&self) -> i64 {
+        self.size_after as i64 - self.size_before as i64
+    }
+
+    /// Returns the total number of bytes covered by [`MemoryDiff::changed_regions`].
+    pub fn changed_bytes(&self) -> u64 {
+        self.changed_regions.iter().map(|r| r.len).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MemoryRegion, MemorySnapshot, SNAPSHOT_PAGE_SIZE};
+    use alloc::{vec, vec::Vec};
+
+    fn snapshot(memory: &[u8]) -> MemorySnapshot {
+        MemorySnapshot::capture(memory.len() as u64, |offset, size| {
+            Ok(memory[offset as usize..][..size as usize].to_vec())
+        })
+    }
+
+    #[test]
+    fn diff_merges_adjacent_pages() {
+        let page = SNAPSHOT_PAGE_SIZE as usize;
+        let before = vec![0; page * 8];
+        let mut after: Vec<u8> = before.clone();
+        after[page * 2] = 1;
+        after[page * 3 + 5] = 1;
+        after[page * 6] = 1;
+        after.extend_from_slice(&vec![0; page * 2]);
+
+        let diff = snapshot(&before).diff(&snapshot(&after));
+        assert_eq!(diff.growth(), page as i64 * 2);
+        assert_eq!(
+            diff.changed_regions,
+            vec![
+                MemoryRegion {
+                    start: page as u64 * 2,
+                    len: page as u64 * 2
+                },
+                MemoryRegion {
+                    start: page as u64 * 6,
+                    len: page as u64
+                },
+            ]
+        );
+        assert_eq!(diff.changed_bytes(), page as u64 * 3);
+
+        assert!(snapshot(&after)
+            .diff(&snapshot(&after))
+            .changed_regions
+            .is_empty());
+    }
+}
@@ -16,7 +16,17 @@
 #![cfg(test)]
 
 mod basic_module;
+mod capabilities;
+mod emit_messages_batch;
 mod emit_not_available;
 mod emit_reserved_pid;
+mod exit_code;
+mod inbox_overflow;
+mod interface_override;
+mod memory_snapshot;
+mod pending_messages_limit;
+mod prepared_process;
+mod self_check;
 mod trapping_module;
+mod undeclared_interface;
 mod wasm_recv_interface_msg;
@@ -0,0 +1,133 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::scheduler::{Core, CoreRunOutcome};
+use crate::{InterfaceHash, Module, Pid, WasmValue};
+
+use core::iter;
+
+#[test]
+fn capabilities() {
+    // Emits an empty message on the interface `[0x22; 32]`, then on the interface
+    // `[0x11; 32]`, waiting for a handler in both cases.
+    let target = from_wat!(
+        local,
+        r#"
+(module
+    (import "redshirt" "emit_message" (func $emit (param i32 i32 i32 i32 i32 i32) (result i32)))
+    (func $main (param $p0 i32) (param $p1 i32) (result i32)
+        (drop (call $emit (i32.const 0) (i32.const 64) (i32.const 0) (i32.const 0) (i32.const 1) (i32.const 72)))
+        (drop (call $emit (i32.const 32) (i32.const 64) (i32.const 0) (i32.const 0) (i32.const 1) (i32.const 72)))
+        i32.const 0)
+    (memory $memory 1)
+    (export "memory" (memory 0))
+    (export "main" (func $main))
+    (data (i32.const 0) "\22\22\22\22\22\22\22\22\22\22\22\22\22\22\22\22\22\22\22\22\22\22\22\22\22\22\22\22\22\22\22\22")
+    (data (i32.const 32) "\11\11\11\11\11\11\11\11\11\11\11\11\11\11\11\11\11\11\11\11\11\11\11\11\11\11\11\11\11\11\11\11"))"#
+    );
+
+    // Delegates the interface `[0x11; 32]` to the process whose pid replaces the `TARGETPD`
+    // placeholder, and returns the value returned by `delegate_capability`.
+    let delegator = wat_to_bin!(
+        r#"
+(module
+    (import "redshirt" "delegate_capability" (func $delegate (param i64 i32) (result i32)))
+    (func $main (param $p0 i32) (param $p1 i32) (result i32)
+        (call $delegate (i64.load (i32.const 32)) (i32.const 0)))
+    (memory $memory 1)
+    (export "memory" (memory 0))
+    (export "main" (func $main))
+    (data (i32.const 0) "\11\11\11\11\11\11\11\11\11\11\11\11\11\11\11\11\11\11\11\11\11\11\11\11\11\11\11\11\11\11\11\11")
+    (data (i32.const 32) "TARGETPD"))"#
+    );
+    let delegator_for = |target: Pid| {
+        let mut bytes = delegator.to_vec();
+        let pos = bytes.windows(8).position(|w| w == b"TARGETPD").unwrap();
+        bytes[pos..pos + 8].copy_from_slice(&u64::from(target).to_le_bytes());
+        Module::from_bytes(bytes).unwrap()
+    };
+
+    let delegated = InterfaceHash::from_raw_hash([0x11; 32]);
+    let other = InterfaceHash::from_raw_hash([0x22; 32]);
+
+    let mut builder = Core::new();
+    let reserved_pid = builder.reserve_pid();
+    let core = builder.build();
+
+    let target_pid = {
+        let prepared = core.prepare(&target).unwrap();
+        let prepared = prepared.with_capabilities(iter::once(other.clone()));
+        core.execute_prepared(prepared).pid()
+    };
+    match core.run() {
+        CoreRunOutcome::ThreadWaitUnavailableInterface { interface, .. } => {
+            assert_eq!(interface, other);
+        }
+        _ => panic!(),
+    }
+
+    // A process can't delegate a capability that it doesn't hold.
+    let prepared = core.prepare(&delegator_for(target_pid)).unwrap();
+    let pid = core
+        .execute_prepared(prepared.with_capabilities(iter::empty()))
+        .pid();
+    match core.run() {
+        CoreRunOutcome::ProgramFinished {
+            pid: finished_pid,
+            outcome,
+            ..
+        } => {
+            assert_eq!(finished_pid, pid);
+            match outcome {
+                Ok(Some(WasmValue::I32(1))) => {}
+                _ => panic!(),
+            }
+        }
+        _ => panic!(),
+    }
+
+    let pid = core.execute(&delegator_for(target_pid)).unwrap().pid();
+    match core.run() {
+        CoreRunOutcome::ProgramFinished {
+            pid: finished_pid,
+            outcome,
+            ..
+        } => {
+            assert_eq!(finished_pid, pid);
+            match outcome {
+                Ok(Some(WasmValue::I32(0))) => {}
+                _ => panic!(),
+            }
+        }
+        _ => panic!(),
+    }
+
+    // The target emits its first message, then is allowed to emit its second one.
+    core.set_interface_handler(other.clone(), reserved_pid)
+        .unwrap();
+    match core.run() {
+        CoreRunOutcome::ReservedPidInterfaceMessage { pid, interface, .. } => {
+            assert_eq!(pid, target_pid);
+            assert_eq!(interface, other);
+        }
+        _ => panic!(),
+    }
+    match core.run() {
+        CoreRunOutcome::ThreadWaitUnavailableInterface { interface, .. } => {
+            assert_eq!(interface, delegated);
+        }
+        _ => panic!(),
+    }
+}
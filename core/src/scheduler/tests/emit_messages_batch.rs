@@ -0,0 +1,135 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::scheduler::{Core, CoreRunOutcome};
+use crate::{InterfaceHash, WasmValue};
+use redshirt_process_management_interface::ffi::ExitStatus;
+
+#[test]
+fn emit_messages_batch() {
+    // The memory contains the interface hash at offset 0, the two messages at offsets 32 and
+    // 40, and the list of messages at offset 64. The identifiers are written at offset 128.
+    let module = from_wat!(
+        local,
+        r#"
+(module
+    (type $t0 (func (param i32 i32 i32) (result i32)))
+    (type $t1 (func (param i32 i32) (result i32)))
+    (import "redshirt" "emit_messages_batch" (func $emit_messages_batch (type $t0)))
+    (func $main (type $t1) (param $p0 i32) (param $p1 i32) (result i32)
+        i32.const 64
+        i32.const 2
+        i32.const 128
+        call $emit_messages_batch)
+    (memory $memory 1)
+    (export "memory" (memory 0))
+    (export "main" (func $main))
+    (data (i32.const 0) "\11\11\11\11\11\11\11\11\11\11\11\11\11\11\11\11\11\11\11\11\11\11\11\11\11\11\11\11\11\11\11\11")
+    (data (i32.const 32) "\01\02\03")
+    (data (i32.const 40) "\04\05")
+    (data (i32.const 64) "\00\00\00\00\20\00\00\00\03\00\00\00\01\00\00\00\00\00\00\00\28\00\00\00\02\00\00\00\00\00\00\00"))"#
+    );
+
+    let interface = InterfaceHash::from_raw_hash([0x11; 32]);
+
+    let mut builder = Core::new();
+    let reserved_pid = builder.reserve_pid();
+    let core = builder.build();
+    core.set_interface_handler(interface.clone(), reserved_pid)
+        .unwrap();
+
+    let pid = core.execute(&module).unwrap().pid();
+
+    match core.run() {
+        CoreRunOutcome::ReservedPidInterfaceMessage {
+            pid: emitter_pid,
+            message_id,
+            interface: interface_obtained,
+            message,
+        } => {
+            assert!(message_id.is_some());
+            assert_eq!(emitter_pid, pid);
+            assert_eq!(interface_obtained, interface);
+            assert_eq!(message.0, &[1, 2, 3]);
+        }
+        _ => panic!(),
+    }
+
+    match core.run() {
+        CoreRunOutcome::ReservedPidInterfaceMessage {
+            message_id,
+            message,
+            ..
+        } => {
+            assert!(message_id.is_none());
+            assert_eq!(message.0, &[4, 5]);
+        }
+        _ => panic!(),
+    }
+
+    let counters = core.process_by_id(pid).unwrap().counters();
+    assert_eq!(counters.messages_emitted, 2);
+    assert_eq!(counters.message_bytes_emitted, 5);
+
+    match core.run() {
+        CoreRunOutcome::ProgramFinished {
+            pid: finished_pid,
+            outcome,
+            ..
+        } => {
+            assert_eq!(finished_pid, pid);
+            match outcome {
+                Ok(Some(WasmValue::I32(2))) => {}
+                _ => panic!(),
+            }
+        }
+        _ => panic!(),
+    }
+}
+
+#[test]
+fn too_many_messages_kills_process() {
+    let module = from_wat!(
+        local,
+        r#"
+(module
+    (type $t0 (func (param i32 i32 i32) (result i32)))
+    (type $t1 (func (param i32 i32) (result i32)))
+    (import "redshirt" "emit_messages_batch" (func $emit_messages_batch (type $t0)))
+    (func $main (type $t1) (param $p0 i32) (param $p1 i32) (result i32)
+        i32.const 0
+        i32.const 513
+        i32.const 0
+        call $emit_messages_batch)
+    (memory $memory 1)
+    (export "memory" (memory 0))
+    (export "main" (func $main)))"#
+    );
+
+    let core = Core::new().build();
+    let pid = core.execute(&module).unwrap().pid();
+
+    match core.run() {
+        CoreRunOutcome::ProgramFinished {
+            pid: finished_pid,
+            exit_status,
+            ..
+        } => {
+            assert_eq!(finished_pid, pid);
+            assert_eq!(exit_status, ExitStatus::Killed);
+        }
+        _ => panic!(),
+    }
+}
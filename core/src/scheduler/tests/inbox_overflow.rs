@@ -0,0 +1,104 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::scheduler::{Core, CoreRunOutcome, InboxConfig, OverflowPolicy};
+use crate::{EncodedMessage, InterfaceHash};
+use alloc::vec;
+
+#[test]
+fn inbox_overflow() {
+    // Program that waits forever for the answer to a message that doesn't exist, and thus
+    // never picks up the messages in its inbox.
+    let module = from_wat!(
+        local,
+        r#"(module
+        (import "redshirt" "next_notification" (func $next_notification (param i32 i32 i32 i32 i32) (result i32)))
+        (memory (export "memory") 1)
+        (data (i32.const 0) "\05\00\00\00\00\00\00\00")
+        (func $_start
+            i32.const 0
+            i32.const 1
+            i32.const 8
+            i32.const 256
+            i32.const 1
+            call $next_notification
+            drop)
+        (export "_start" (func $_start)))
+    "#
+    );
+
+    let interface = InterfaceHash::from_raw_hash([0x42; 32]);
+
+    let mut builder = Core::new().with_default_inbox(InboxConfig {
+        capacity: 1,
+        overflow: OverflowPolicy::DropOldest,
+    });
+    let reserved_pid = builder.reserve_pid();
+    let core = builder.build();
+
+    let handler_pid = core.execute(&module).unwrap().pid();
+    assert!(matches!(core.run(), CoreRunOutcome::Idle));
+    core.set_interface_handler(interface.clone(), handler_pid)
+        .unwrap();
+
+    // The second message pushes the first one out of the inbox.
+    let first = core.emit_interface_message_answer(
+        reserved_pid,
+        interface.clone(),
+        EncodedMessage(vec![1]),
+    );
+    let _second = core.emit_interface_message_answer(
+        reserved_pid,
+        interface.clone(),
+        EncodedMessage(vec![2]),
+    );
+    match core.run() {
+        CoreRunOutcome::MessageResponse {
+            message_id,
+            response,
+        } => {
+            assert_eq!(message_id, first);
+            assert!(response.is_err());
+        }
+        _ => panic!(),
+    }
+    assert!(matches!(core.run(), CoreRunOutcome::Idle));
+
+    // With this policy, the new message is refused instead.
+    core.set_inbox_config(
+        handler_pid,
+        Some(InboxConfig {
+            capacity: 1,
+            overflow: OverflowPolicy::Error,
+        }),
+    )
+    .unwrap();
+    let third = core.emit_interface_message_answer(
+        reserved_pid,
+        interface.clone(),
+        EncodedMessage(vec![3]),
+    );
+    match core.run() {
+        CoreRunOutcome::MessageResponse {
+            message_id,
+            response,
+        } => {
+            assert_eq!(message_id, third);
+            assert!(response.is_err());
+        }
+        _ => panic!(),
+    }
+    assert!(matches!(core.run(), CoreRunOutcome::Idle));
+}
@@ -0,0 +1,115 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::scheduler::{Core, CoreRunOutcome};
+use crate::{InterfaceHash, WasmValue};
+use alloc::vec::Vec;
+
+#[test]
+fn interface_override() {
+    // Program that waits for an interface message, then returns the size of the notification.
+    let proxy = from_wat!(
+        local,
+        r#"(module
+        (import "redshirt" "next_notification" (func $next_notification (param i32 i32 i32 i32 i32) (result i32)))
+        (memory (export "memory") 1)
+        (data (i32.const 0) "\01\00\00\00\00\00\00\00")
+        (func $main (param i32 i32) (result i32)
+            i32.const 0
+            i32.const 1
+            i32.const 8
+            i32.const 256
+            i32.const 1
+            call $next_notification)
+        (export "main" (func $main)))
+    "#
+    );
+
+    // Program that emits an empty message on the interface `[0x42; 32]`.
+    let emitter = from_wat!(
+        local,
+        r#"(module
+        (import "redshirt" "emit_message" (func $emit_message (param i32 i32 i32 i32 i32 i32) (result i32)))
+        (memory (export "memory") 1)
+        (data (i32.const 0) "\42\42\42\42\42\42\42\42\42\42\42\42\42\42\42\42\42\42\42\42\42\42\42\42\42\42\42\42\42\42\42\42")
+        (func $main (param i32 i32) (result i32)
+            i32.const 0
+            i32.const 32
+            i32.const 0
+            i32.const 0
+            i32.const 1
+            i32.const 40
+            call $emit_message)
+        (export "main" (func $main)))
+    "#
+    );
+
+    let interface = InterfaceHash::from_raw_hash([0x42; 32]);
+
+    let mut builder = Core::new();
+    let reserved_pid = builder.reserve_pid();
+    let core = builder.build();
+    core.set_interface_handler(interface.clone(), reserved_pid)
+        .unwrap();
+
+    let proxy_pid = core.execute(&proxy).unwrap().pid();
+    assert!(matches!(core.run(), CoreRunOutcome::Idle));
+
+    // The message of the overridden emitter goes to the proxy.
+    let overridden_pid = core.execute(&emitter).unwrap().pid();
+    core.set_interface_override(overridden_pid, interface.clone(), proxy_pid)
+        .unwrap();
+    let mut finished = Vec::new();
+    loop {
+        match core.run() {
+            CoreRunOutcome::ProgramFinished { pid, outcome, .. } => {
+                match outcome {
+                    Ok(Some(WasmValue::I32(n))) => assert!(pid == overridden_pid || n > 0),
+                    _ => panic!(),
+                }
+                finished.push(pid);
+            }
+            CoreRunOutcome::Idle => break,
+            _ => panic!(),
+        }
+    }
+    assert_eq!(finished.len(), 2);
+    assert!(finished.contains(&proxy_pid));
+    assert!(finished.contains(&overridden_pid));
+
+    // The override has been removed when the emitter has terminated.
+    assert_eq!(
+        core.remove_interface_override(overridden_pid, &interface),
+        None
+    );
+
+    // The messages of the other processes still go to the actual handler.
+    let other_pid = core.execute(&emitter).unwrap().pid();
+    match core.run() {
+        CoreRunOutcome::ReservedPidInterfaceMessage {
+            pid,
+            interface: obtained,
+            ..
+        } => {
+            assert_eq!(pid, other_pid);
+            assert_eq!(obtained, interface);
+        }
+        _ => panic!(),
+    }
+    match core.run() {
+        CoreRunOutcome::ProgramFinished { pid, .. } => assert_eq!(pid, other_pid),
+        _ => panic!(),
+    }
+}
@@ -0,0 +1,55 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::scheduler::Core;
+
+#[test]
+fn snapshot_without_memory() {
+    let module = from_wat!(
+        local,
+        r#"(module
+        (func $_start)
+        (export "_start" (func $_start)))
+    "#
+    );
+
+    let core = Core::new().build();
+    let process = core.execute(&module).unwrap();
+
+    let snapshot = process.memory_snapshot();
+    assert_eq!(snapshot.memory_size(), 0);
+    let diff = snapshot.diff(&process.memory_snapshot());
+    assert_eq!(diff.growth(), 0);
+    assert_eq!(diff.changed_bytes(), 0);
+}
+
+#[test]
+fn snapshot_with_memory() {
+    let module = from_wat!(
+        local,
+        r#"(module
+        (memory (export "memory") 1)
+        (func $_start)
+        (export "_start" (func $_start)))
+    "#
+    );
+
+    let core = Core::new().build();
+    let process = core.execute(&module).unwrap();
+
+    let snapshot = process.memory_snapshot();
+    assert_eq!(snapshot.memory_size(), 65536);
+    assert_eq!(snapshot.diff(&process.memory_snapshot()).changed_bytes(), 0);
+}
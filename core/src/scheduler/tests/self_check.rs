@@ -0,0 +1,63 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::scheduler::{Core, SelfCheckConfig, Violation};
+use crate::InterfaceHash;
+use alloc::vec;
+
+#[test]
+fn notifications_queue_quota() {
+    let module = from_wat!(
+        local,
+        r#"(module
+        (func $_start (result i32)
+            i32.const 5)
+        (export "_start" (func $_start)))
+    "#
+    );
+
+    let interface = InterfaceHash::from_raw_hash([
+        0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x10, 0x11, 0x12, 0x13, 0x14, 0x15, 0x16,
+        0x17, 0x20, 0x21, 0x22, 0x23, 0x24, 0x25, 0x26, 0x27, 0x30, 0x31, 0x32, 0x33, 0x34, 0x35,
+        0x36, 0x37,
+    ]);
+
+    let mut builder = Core::new();
+    let reserved_pid = builder.reserve_pid();
+    let core = builder.build();
+
+    let config = SelfCheckConfig {
+        max_notifications_queue_len: 2,
+        ..Default::default()
+    };
+
+    let pid = core.execute(&module).unwrap().pid();
+    core.set_interface_handler(interface.clone(), pid).unwrap();
+    assert!(core.self_check(&config).is_empty());
+
+    // The process is never run, and thus never pulls the messages from its queue.
+    for _ in 0..3 {
+        core.emit_interface_message_no_answer(reserved_pid, interface.clone(), ());
+    }
+
+    assert_eq!(
+        core.self_check(&config),
+        vec![Violation::NotificationsQueueTooLong {
+            pid,
+            len: 3,
+            max: 2
+        }]
+    );
+}
@@ -15,6 +15,7 @@
 
 use crate::scheduler::{Core, CoreRunOutcome};
 use crate::InterfaceHash;
+use redshirt_process_management_interface::ffi::ExitStatus;
 
 #[test]
 fn trapping_module() {
@@ -34,9 +35,11 @@ fn trapping_module() {
         CoreRunOutcome::ProgramFinished {
             pid,
             outcome: Err(_),
+            exit_status,
             ..
         } => {
             assert_eq!(pid, expected_pid);
+            assert_eq!(exit_status, ExitStatus::Crashed);
         }
         _ => panic!(),
     }
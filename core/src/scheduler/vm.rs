@@ -13,15 +13,31 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use crate::{module::Module, ValueType, WasmValue};
+use crate::{
+    module::{debug, fuel, stack, AbiReport, Module, ModuleStats, START_EXPORT},
+    signature::Signature,
+    BacktraceFrame, TrapError, TrapKind, ValueType, WasmValue,
+};
 
 use alloc::{
     borrow::{Cow, ToOwned as _},
     boxed::Box,
     format,
+    string::{String, ToString as _},
+    sync::Arc,
+    vec,
     vec::Vec,
 };
-use core::{cell::RefCell, convert::TryInto, fmt};
+use core::{
+    cell::RefCell,
+    cmp,
+    convert::{TryFrom as _, TryInto},
+    fmt, iter,
+    marker::PhantomData,
+    ops::Range,
+};
+use fnv::FnvBuildHasher;
+use hashbrown::{HashMap, HashSet};
 use smallvec::SmallVec;
 
 /// WASMI state machine dedicated to a process.
@@ -40,15 +56,17 @@ use smallvec::SmallVec;
 /// # Threads
 ///
 /// This struct is composed of one or multiple threads. When initialized, the VM starts with a
-/// single thread at the start of the "main" function of the WASM module.
+/// single thread at the start of the "main" function of the WASM module. If the module has a
+/// start function, as defined by the WASM specification, this thread executes it before the
+/// "main" function.
 ///
 /// In order to run the VM, grab a thread by calling [`ProcessStateMachine::threads`], then call
 /// [`Thread::run`]. The thread will then run until it either finishes (in which case the thread
 /// is then destroyed), or attempts to call an imported function.
 ///
-/// TODO: It is intended that in the future the `run` function stops after a certain period of
-/// time has elapsed, in order to do preemptive multithreading. This requires a lot of changes in
-/// the interpreter, and isn't going to happen any time soon.
+/// In order to do preemptive multithreading, call [`ProcessStateMachine::set_fuel`] before
+/// [`run`](Thread::run). The thread is then paused once it has executed approximately this
+/// number of instructions.
 ///
 /// The [`run`](Thread::run) method requires passing a value. The first time you call
 /// [`run`](Thread::run) for any given thread, you must pass the value `None`. If that thread is
@@ -71,10 +89,18 @@ use smallvec::SmallVec;
 /// The [`ProcessStateMachine`] is single-threaded. In other words, the VM can only ever run one
 /// thread simultaneously. This might change in the future.
 ///
+/// # Interpreter
+///
+/// The code is executed by the wasmi interpreter. This struct is the implementation of the
+/// [`WasmVm`] trait for this interpreter.
 pub struct ProcessStateMachine<T> {
     /// Original module, with resolved imports.
     module: wasmi::ModuleRef,
 
+    /// Instances of the libraries the module has been linked with. The interpreter only holds
+    /// weak references to them, so they must be kept alive for as long as the module is.
+    libraries: Vec<wasmi::ModuleRef>,
+
     /// Memory of the module instantiation.
     ///
     /// Right now we only support one unique `Memory` object per process. This is it.
@@ -88,9 +114,44 @@ pub struct ProcessStateMachine<T> {
     /// `__indirect_function_table`. This is this table, if it exists.
     indirect_table: Option<wasmi::TableRef>,
 
+    /// Globals containing the remaining fuel of the module and of each library. See
+    /// [`ProcessStateMachine::set_fuel`].
+    fuel_globals: Vec<wasmi::GlobalRef>,
+
+    /// Globals containing the number of nested calls that remain allowed in the module and in
+    /// each library. See [`ProcessStateMachine::set_max_stack_depth`].
+    stack_globals: Vec<wasmi::GlobalRef>,
+
+    /// Global containing the index of the function of the main module being executed. See
+    /// [`TrapError::backtrace`].
+    trace_global: Option<wasmi::GlobalRef>,
+
+    /// Names of the functions of the main module, indexed by function index.
+    function_names: Arc<HashMap<u32, String, FnvBuildHasher>>,
+
+    /// Global containing the debugging mode of the main module, if it has been loaded with
+    /// [`Module::from_bytes_debuggable`]. Updated whenever [`ProcessStateMachine::single_step`]
+    /// or [`ProcessStateMachine::breakpoints`] change.
+    debug_global: Option<wasmi::GlobalRef>,
+
+    /// If true, threads are paused before each instruction of the main module. See
+    /// [`ProcessStateMachine::set_single_step`].
+    single_step: bool,
+
+    /// Functions of the main module at the start of which threads are paused. See
+    /// [`ProcessStateMachine::add_breakpoint`].
+    breakpoints: HashSet<u32, FnvBuildHasher>,
+
     /// List of threads that this process is running.
     threads: SmallVec<[ThreadState<T>; 4]>,
 
+    /// Maximum number of pages the memory is allowed to have. See
+    /// [`ProcessStateMachine::set_max_memory_pages`].
+    max_memory_pages: Option<u32>,
+
+    /// Maximum number of nested calls. See [`ProcessStateMachine::set_max_stack_depth`].
+    max_stack_depth: Option<u32>,
+
     /// If true, the state machine is in a poisoned state and cannot run any code anymore.
     is_poisoned: bool,
 }
@@ -108,22 +169,123 @@ struct ThreadState<T> {
     /// This is a particularity of the WASM interpreter that we don't want to expose in our API.
     interrupted: bool,
 
+    /// Execution to start once `execution` has finished. Used for the main thread in order to
+    /// run the "main" function after the start function of the module.
+    then: Option<wasmi::FuncInvocation<'static>>,
+
+    /// Number of nested calls that the thread is currently in, for each entry of
+    /// [`ProcessStateMachine::stack_globals`]. The globals are shared between all the threads,
+    /// and are updated from this field every time the thread runs.
+    stack_depths: Vec<i32>,
+
     /// Opaque user data associated with the thread.
     user_data: T,
 }
 
+/// Backend executing the code of a process.
+///
+/// The backend is composed of one or multiple threads, each with a user data of type `T`, that
+/// share the same memory. See [`ProcessStateMachine`] for the details of how threads are run.
+///
+/// Threads must be paused when they call an imported function, and resumed later with the
+/// return value of that function. An interpreter can do this by suspending its execution, while
+/// a JIT compiler would have to run each thread on its own stack.
+pub trait WasmVm<T>: Sized {
+    /// Returns true if the state machine is in a poisoned state and cannot run anymore.
+    fn is_poisoned(&self) -> bool;
+
+    /// Starts executing the function with the given index in the indirect table, and puts it in
+    /// an interrupted state.
+    ///
+    /// You should call [`run`](Thread::run) afterwards with a value of `None`.
+    fn start_thread_by_id(
+        &mut self,
+        function_id: u32,
+        params: impl IntoIterator<Item = WasmValue>,
+        user_data: T,
+    ) -> Result<Thread<'_, T, Self>, StartErr>;
+
+    /// Returns the number of threads that are running.
+    fn num_threads(&self) -> usize;
+
+    /// Returns the thread with the given index, or `None` if the index is superior or equal to
+    /// what [`num_threads`](WasmVm::num_threads) would return.
+    fn thread(&mut self, index: usize) -> Option<Thread<'_, T, Self>> {
+        if index < self.num_threads() {
+            Some(Thread {
+                vm: self,
+                index,
+                marker: PhantomData,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Starts or continues execution of the thread with the given index. Called by
+    /// [`Thread::run`], which describes the value to pass.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the index is out of range.
+    fn run_thread(
+        &mut self,
+        index: usize,
+        value: Option<WasmValue>,
+    ) -> Result<ExecOutcome<'_, T, Self>, RunErr>;
+
+    /// Returns the user data of the thread with the given index.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the index is out of range.
+    fn thread_user_data(&mut self, index: usize) -> &mut T;
+
+    /// Sets the amount of fuel available to the threads. Once the fuel runs out, the thread that
+    /// is running is paused and [`ExecOutcome::OutOfFuel`] is returned.
+    fn set_fuel(&mut self, fuel: u64);
+
+    /// Sets the maximum number of 64kiB pages that the memory is allowed to have.
+    fn set_max_memory_pages(&mut self, max: Option<u32>);
+
+    /// Sets the maximum number of nested function calls that each thread is allowed to make.
+    fn set_max_stack_depth(&mut self, max: Option<u32>);
+
+    /// Returns the size of the memory of the process, in bytes, or 0 if it has no memory.
+    fn memory_size(&self) -> u64;
+
+    /// Copies the memory starting at `offset` into `buffer`, filling it entirely.
+    ///
+    /// Returns an error if the range is invalid or out of range.
+    fn read_memory_into(&self, offset: u32, buffer: &mut [u8]) -> Result<(), ()>;
+
+    /// Calls `f` with the given range of the memory, without copying it.
+    ///
+    /// Returns an error if the range is invalid or out of range.
+    fn with_memory<R>(&self, range: Range<u32>, f: impl FnOnce(&[u8]) -> R) -> Result<R, ()>;
+
+    /// Write the data at the given memory location.
+    ///
+    /// Returns an error if the range is invalid or out of range.
+    fn write_memory(&mut self, offset: u32, value: &[u8]) -> Result<(), ()>;
+}
+
 /// Access to a thread within the virtual machine.
-pub struct Thread<'a, T> {
+pub struct Thread<'a, T, V: WasmVm<T> = ProcessStateMachine<T>> {
     /// Reference to the parent object.
-    vm: &'a mut ProcessStateMachine<T>,
+    vm: &'a mut V,
 
-    // Index within [`ProcessStateMachine::threads`] of the thread we are referencing.
+    // Index within the VM of the thread we are referencing. For a `ProcessStateMachine`, this
+    // is the index within [`ProcessStateMachine::threads`].
     index: usize,
+
+    /// The type of the user data is only known through `V`.
+    marker: PhantomData<fn() -> T>,
 }
 
 /// Outcome of the [`run`](Thread::run) function.
 #[derive(Debug)]
-pub enum ExecOutcome<'a, T> {
+pub enum ExecOutcome<'a, T, V: WasmVm<T> = ProcessStateMachine<T>> {
     /// A thread has finished. The thread no longer exists in the list.
     ///
     /// If this was the main thread (i.e. `thread_index` is 0), then the state machine is now in
@@ -156,7 +318,7 @@ pub enum ExecOutcome<'a, T> {
     /// >           [`run`](Thread::run) with a value of the wrong type.
     Interrupted {
         /// Thread that was interrupted.
-        thread: Thread<'a, T>,
+        thread: Thread<'a, T, V>,
 
         /// Identifier of the function to call. Corresponds to the value provided at
         /// initialization when resolving imports.
@@ -166,25 +328,93 @@ pub enum ExecOutcome<'a, T> {
         params: Vec<WasmValue>,
     },
 
+    /// The currently-executed thread has been paused because it has run out of fuel. When you
+    /// call [`run`](Thread::run) again, you must pass `None`.
+    ///
+    /// See [`ProcessStateMachine::set_fuel`].
+    OutOfFuel {
+        /// Thread that was paused.
+        thread: Thread<'a, T, V>,
+    },
+
+    /// The currently-executed thread has been paused because it has reached a breakpoint, or
+    /// because [single-stepping](ProcessStateMachine::set_single_step) is enabled. When you call
+    /// [`run`](Thread::run) again, you must pass `None`.
+    Breakpoint {
+        /// Thread that was paused.
+        thread: Thread<'a, T, V>,
+
+        /// Index of the function of the main module that is being executed, as found in its name
+        /// section.
+        function_index: u32,
+
+        /// Index, within the original code of the function, of the instruction that is about to
+        /// be executed.
+        instruction: u32,
+    },
+
     /// The currently-executed function has finished with an error. The state machine is now in a
     /// poisoned state.
     ///
     /// Calling [`is_poisoned`](ProcessStateMachine::is_poisoned) will return true.
     Errored {
         /// Thread that error'd.
-        thread: Thread<'a, T>,
+        thread: Thread<'a, T, V>,
 
         /// Error that happened.
-        // TODO: error type should change here
-        error: wasmi::Trap,
+        error: TrapError,
+    },
+
+    /// The memory has grown beyond the limit passed to
+    /// [`set_max_memory_pages`](ProcessStateMachine::set_max_memory_pages). The state machine is
+    /// now in a poisoned state.
+    ///
+    /// > **Note**: The interpreter doesn't let us intercept the growth of the memory. Instead,
+    /// >           its size is checked every time [`run`](Thread::run) returns, which happens
+    /// >           regularly as long as the amount of [fuel](ProcessStateMachine::set_fuel) is
+    /// >           bounded.
+    MemoryLimitExceeded {
+        /// Thread that was running when the limit has been detected to be exceeded.
+        thread: Thread<'a, T, V>,
+
+        /// Number of pages of the memory.
+        pages: u32,
+
+        /// Maximum allowed number of pages.
+        max: u32,
+    },
+
+    /// The currently-executed thread has exceeded the limit passed to
+    /// [`set_max_stack_depth`](ProcessStateMachine::set_max_stack_depth), or the limits of the
+    /// interpreter on the size of the stack. The state machine is now in a poisoned state.
+    StackOverflow {
+        /// Thread whose stack has overflowed.
+        thread: Thread<'a, T, V>,
+
+        /// Limit passed to [`set_max_stack_depth`](ProcessStateMachine::set_max_stack_depth), or
+        /// `None` if it is the limits of the interpreter that have been reached.
+        max: Option<u32>,
     },
 }
 
 /// Error that can happen when initializing a VM.
 #[derive(Debug)]
 pub enum NewErr {
-    /// Error in the interpreter.
-    Interpreter(wasmi::Error),
+    /// Error in the interpreter, with its description.
+    Interpreter(String),
+    /// Some of the imports of the module can't be resolved.
+    IncompatibleAbi(AbiReport),
+    /// The closure passed to [`ProcessStateMachine::new`] couldn't resolve an imported function.
+    UnresolvedImport {
+        /// Namespace of the import.
+        interface: String,
+        /// Name of the function within its namespace.
+        function: String,
+        /// Signature of the function with that name, if any. `None` if no such function exists.
+        expected: Option<Signature>,
+        /// Signature that the module expects.
+        obtained: Signature,
+    },
     /// The "start" symbol doesn't exist.
     StartNotFound,
     /// The "start" symbol must be a function.
@@ -193,8 +423,160 @@ pub enum NewErr {
     MemoryIsntMemory,
     /// If a "__indirect_function_table" symbol is provided, it must be a table.
     IndirectTableIsntTable,
+    /// The encoded module is larger than [`ModuleLimits::max_module_size`].
+    ModuleTooLarge {
+        /// Size of the module, in bytes.
+        size: usize,
+        /// Maximum allowed size.
+        max: usize,
+    },
+    /// The data segments are larger than [`ModuleLimits::max_data_size`].
+    DataTooLarge {
+        /// Total size of the data segments, in bytes.
+        size: usize,
+        /// Maximum allowed size.
+        max: usize,
+    },
+    /// The module defines more than [`ModuleLimits::max_functions`] functions.
+    TooManyFunctions {
+        /// Number of functions defined in the module.
+        num: usize,
+        /// Maximum allowed number.
+        max: usize,
+    },
+    /// A function contains more than [`ModuleLimits::max_function_instructions`] instructions.
+    FunctionTooLarge {
+        /// Number of instructions of the largest function.
+        instructions: usize,
+        /// Maximum allowed number.
+        max: usize,
+    },
+    /// Instantiating the module took longer than [`ModuleLimits::max_instantiation_time`].
+    InstantiationTooLong {
+        /// Time the instantiation took, in nanoseconds.
+        duration: u64,
+        /// Maximum allowed time.
+        max: u64,
+    },
+    /// The module isn't signed by any of the keys passed to
+    /// [`SystemBuilder::with_trusted_keys`](crate::system::SystemBuilder::with_trusted_keys).
+    UntrustedModule,
+}
+
+/// Limits on the modules that can be instantiated.
+///
+/// Loading and instantiating a module requires validating its code and copying its data
+/// segments, which takes time proportional to their size. These limits make it possible to
+/// reject pathological modules upfront instead of stalling while instantiating them. Use
+/// [`ModuleLimits::check`] in order to check them before even loading a module.
+#[derive(Debug, Clone)]
+pub struct ModuleLimits {
+    /// Maximum size, in bytes, of the encoded module.
+    pub max_module_size: usize,
+    /// Maximum total size, in bytes, of the data segments of the module.
+    pub max_data_size: usize,
+    /// Maximum number of functions defined by the module.
+    pub max_functions: usize,
+    /// Maximum number of instructions within a single function.
+    pub max_function_instructions: usize,
+    /// Minimum number of pages of the memory provided to modules that import their memory
+    /// instead of defining it. The memory is larger if the module asks for more.
+    pub imported_memory_initial_pages: u32,
+    /// Maximum number of pages that the memory provided to modules that import their memory can
+    /// grow to. Modules whose import requires a larger memory are refused.
+    pub imported_memory_max_pages: u32,
+    /// Maximum time, in nanoseconds, that instantiating a module can take, as measured with
+    /// [`ModuleLimits::clock`].
+    ///
+    /// Instantiation can't be interrupted, and this limit is only checked once it is over. It is
+    /// the other limits that bound how long instantiation takes. This one refuses to run the
+    /// modules that are within them but are slow to instantiate nonetheless.
+    pub max_instantiation_time: u64,
+    /// Function returning the current time in nanoseconds. If `None`, the time instantiation
+    /// takes isn't measured.
+    pub clock: Option<fn() -> u64>,
+}
+
+impl Default for ModuleLimits {
+    fn default() -> Self {
+        ModuleLimits {
+            max_module_size: 64 * 1024 * 1024,
+            max_data_size: 64 * 1024 * 1024,
+            max_functions: 1 << 20,
+            max_function_instructions: 1 << 20,
+            imported_memory_initial_pages: 0,
+            imported_memory_max_pages: 65536,
+            max_instantiation_time: u64::max_value(),
+            clock: None,
+        }
+    }
+}
+
+impl ModuleLimits {
+    /// Checks whether the given encoded module respects the size limits, without validating
+    /// it.
+    ///
+    /// Loading a module validates and instruments it, which takes time proportional to its
+    /// size. Calling this function beforehand rejects the modules that are too large for a cost
+    /// only proportional to their encoded size. Malformed modules aren't reported here, but
+    /// when they are loaded.
+    pub fn check(&self, bytes: &[u8]) -> Result<(), NewErr> {
+        if bytes.len() > self.max_module_size {
+            return Err(NewErr::ModuleTooLarge {
+                size: bytes.len(),
+                max: self.max_module_size,
+            });
+        }
+
+        match parity_wasm::deserialize_buffer(bytes) {
+            Ok(parsed) => self.check_stats(&ModuleStats::measure(&parsed, bytes.len())),
+            Err(_) => Ok(()),
+        }
+    }
+
+    /// Returns an error if the module of the given sizes exceeds one of the limits.
+    fn check_stats(&self, stats: &ModuleStats) -> Result<(), NewErr> {
+        if stats.encoded_size > self.max_module_size {
+            return Err(NewErr::ModuleTooLarge {
+                size: stats.encoded_size,
+                max: self.max_module_size,
+            });
+        }
+        if stats.data_size > self.max_data_size {
+            return Err(NewErr::DataTooLarge {
+                size: stats.data_size,
+                max: self.max_data_size,
+            });
+        }
+        if stats.num_functions > self.max_functions {
+            return Err(NewErr::TooManyFunctions {
+                num: stats.num_functions,
+                max: self.max_functions,
+            });
+        }
+        if stats.max_function_instructions > self.max_function_instructions {
+            return Err(NewErr::FunctionTooLarge {
+                instructions: stats.max_function_instructions,
+                max: self.max_function_instructions,
+            });
+        }
+        Ok(())
+    }
 }
 
+/// Identifier passed to the interpreter for the function that modules call when they run out of
+/// fuel. Can't conflict with the identifiers returned by the closure passed to
+/// [`ProcessStateMachine::new`], as they are indices in an array.
+const OUT_OF_FUEL: usize = usize::max_value();
+
+/// Identifier passed to the interpreter for the function that modules call when they exceed the
+/// maximum stack depth. See [`OUT_OF_FUEL`].
+const STACK_OVERFLOW: usize = usize::max_value() - 1;
+
+/// Identifier passed to the interpreter for the function that modules loaded with
+/// [`Module::from_bytes_debuggable`] call before executing an instruction. See [`OUT_OF_FUEL`].
+const BREAKPOINT: usize = usize::max_value() - 2;
+
 /// Error that can happen when starting a new thread.
 #[derive(Debug)]
 pub enum StartErr {
@@ -204,6 +586,8 @@ pub enum StartErr {
     FunctionNotFound,
     /// The requested function has been found in the list of exports, but it is not a function.
     NotAFunction,
+    /// The process already has as many threads as it is allowed to.
+    TooManyThreads,
 }
 
 /// Error that can happen when resuming the execution of a function.
@@ -228,16 +612,106 @@ impl<T> ProcessStateMachine<T> {
     /// functions, this number will be returned back in order for the user to know how to handle
     /// the call.
     ///
+    /// The error returned by the closure is the signature of the function with the requested
+    /// name if it exists but has a different signature, or `None` if there isn't any function
+    /// with this name. It is reported as a [`NewErr::UnresolvedImport`].
+    ///
     /// A single main thread (whose user data is passed by parameter) is automatically created and
     /// is paused at the start of the "_start" function of the module.
+    ///
+    /// If the module imports a memory rather than defining one, and this memory isn't exported by
+    /// a library, a memory is allocated according to the `imported_memory_*` fields of the
+    /// limits.
+    ///
+    /// Returns an error without instantiating the module if it exceeds the given limits.
     pub fn new(
         module: &Module,
+        limits: &ModuleLimits,
         main_thread_user_data: T,
-        mut symbols: impl FnMut(&str, &str, &wasmi::Signature) -> Result<usize, ()>,
+        symbols: impl FnMut(&str, &str, &Signature) -> Result<usize, Option<Signature>>,
     ) -> Result<Self, NewErr> {
-        struct ImportResolve<'a>(
-            RefCell<&'a mut dyn FnMut(&str, &str, &wasmi::Signature) -> Result<usize, ()>>,
-        );
+        Self::new_linked(module, &[], limits, main_thread_user_data, symbols)
+    }
+
+    /// Same as [`ProcessStateMachine::new`], but the process is additionally composed of the
+    /// given library modules.
+    ///
+    /// Each library is associated with a name. The imports of the main module, and of the
+    /// libraries that come after it in the list, whose namespace is the name of a library are
+    /// resolved with the exports of that library rather than by calling `symbols`. This includes
+    /// memories, tables and globals, which makes it possible for modules to share a memory that
+    /// one of them exports.
+    ///
+    /// The memory allocated for modules that import a memory not exported by any library is
+    /// shared between the main module and all the libraries that import such a memory.
+    ///
+    /// > **Note**: Only the memory exported or imported by the main module is accessible to the
+    /// >           extrinsics. Libraries that call extrinsics must therefore use the same memory
+    /// >           as the main module, by exporting it to the main module or by importing it from
+    /// >           the same place as the main module.
+    ///
+    /// Libraries must not have a start function, and their `_start` and `main` functions, if
+    /// any, aren't called.
+    pub fn new_linked(
+        module: &Module,
+        libraries: &[(&str, &Module)],
+        limits: &ModuleLimits,
+        main_thread_user_data: T,
+        mut symbols: impl FnMut(&str, &str, &Signature) -> Result<usize, Option<Signature>>,
+    ) -> Result<Self, NewErr> {
+        check_limits(module, limits)?;
+        for (_, library) in libraries {
+            check_limits(library, limits)?;
+        }
+        let instantiation_start = limits.clock.map(|clock| clock());
+
+        struct ImportResolve<'a> {
+            symbols: RefCell<
+                &'a mut dyn FnMut(&str, &str, &Signature) -> Result<usize, Option<Signature>>,
+            >,
+            /// Libraries instantiated so far, with their names.
+            libraries: &'a [(&'a str, wasmi::ModuleRef)],
+            /// If `symbols` has failed to resolve an import, the corresponding error. Reported
+            /// instead of the error returned by the interpreter, which doesn't contain any detail.
+            unresolved: RefCell<Option<NewErr>>,
+            limits: &'a ModuleLimits,
+            /// Memory provided by the kernel to the modules that import a memory. Shared between
+            /// the main module and its libraries.
+            imported_memory: &'a RefCell<Option<wasmi::MemoryRef>>,
+        }
+        impl<'a> ImportResolve<'a> {
+            /// Instantiates the given module, resolving its imports with `self`.
+            fn instantiate<'m>(
+                &self,
+                module: &'m wasmi::Module,
+            ) -> Result<wasmi::NotStartedModuleRef<'m>, NewErr> {
+                wasmi::ModuleInstance::new(module, self).map_err(|err| {
+                    self.unresolved
+                        .borrow_mut()
+                        .take()
+                        .unwrap_or_else(|| NewErr::Interpreter(err.to_string()))
+                })
+            }
+
+            /// If `module_name` is the name of a library, returns the export of that library
+            /// named `field_name`.
+            fn library_export(
+                &self,
+                module_name: &str,
+                field_name: &str,
+            ) -> Option<Result<wasmi::ExternVal, wasmi::Error>> {
+                let (_, library) = self
+                    .libraries
+                    .iter()
+                    .find(|(name, _)| *name == module_name)?;
+                Some(library.export_by_name(field_name).ok_or_else(|| {
+                    wasmi::Error::Instantiation(format!(
+                        "Library `{}` doesn't export `{}`",
+                        module_name, field_name
+                    ))
+                }))
+            }
+        }
         impl<'a> wasmi::ImportResolver for ImportResolve<'a> {
             fn resolve_func(
                 &self,
@@ -245,14 +719,57 @@ impl<T> ProcessStateMachine<T> {
                 field_name: &str,
                 signature: &wasmi::Signature,
             ) -> Result<wasmi::FuncRef, wasmi::Error> {
-                let closure = &mut **self.0.borrow_mut();
-                let index = match closure(module_name, field_name, signature) {
+                if module_name == fuel::NAMESPACE && field_name == fuel::FUNCTION {
+                    return Ok(wasmi::FuncInstance::alloc_host(
+                        signature.clone(),
+                        OUT_OF_FUEL,
+                    ));
+                }
+                if module_name == stack::NAMESPACE && field_name == stack::FUNCTION {
+                    return Ok(wasmi::FuncInstance::alloc_host(
+                        signature.clone(),
+                        STACK_OVERFLOW,
+                    ));
+                }
+                if module_name == debug::NAMESPACE && field_name == debug::FUNCTION {
+                    if signature.params() != [wasmi::ValueType::I32, wasmi::ValueType::I32]
+                        || signature.return_type().is_some()
+                    {
+                        return Err(wasmi::Error::Instantiation(format!(
+                            "`{}`:`{}` has a wrong signature",
+                            module_name, field_name
+                        )));
+                    }
+                    return Ok(wasmi::FuncInstance::alloc_host(
+                        signature.clone(),
+                        BREAKPOINT,
+                    ));
+                }
+
+                if let Some(export) = self.library_export(module_name, field_name) {
+                    return export?.as_func().cloned().ok_or_else(|| {
+                        wasmi::Error::Instantiation(format!(
+                            "`{}`:`{}` isn't a function",
+                            module_name, field_name
+                        ))
+                    });
+                }
+
+                let closure = &mut **self.symbols.borrow_mut();
+                let obtained = Signature::from_wasmi(signature);
+                let index = match closure(module_name, field_name, &obtained) {
                     Ok(i) => i,
-                    Err(_) => {
+                    Err(expected) => {
+                        *self.unresolved.borrow_mut() = Some(NewErr::UnresolvedImport {
+                            interface: module_name.to_owned(),
+                            function: field_name.to_owned(),
+                            expected,
+                            obtained,
+                        });
                         return Err(wasmi::Error::Instantiation(format!(
                             "Couldn't resolve `{}`:`{}`",
                             module_name, field_name
-                        )))
+                        )));
                     }
                 };
 
@@ -261,10 +778,19 @@ impl<T> ProcessStateMachine<T> {
 
             fn resolve_global(
                 &self,
-                _module_name: &str,
-                _field_name: &str,
+                module_name: &str,
+                field_name: &str,
                 _global_type: &wasmi::GlobalDescriptor,
             ) -> Result<wasmi::GlobalRef, wasmi::Error> {
+                if let Some(export) = self.library_export(module_name, field_name) {
+                    return export?.as_global().cloned().ok_or_else(|| {
+                        wasmi::Error::Instantiation(format!(
+                            "`{}`:`{}` isn't a global",
+                            module_name, field_name
+                        ))
+                    });
+                }
+
                 Err(wasmi::Error::Instantiation(
                     "Importing globals is not supported yet".to_owned(),
                 ))
@@ -272,38 +798,146 @@ impl<T> ProcessStateMachine<T> {
 
             fn resolve_memory(
                 &self,
-                _module_name: &str,
-                _field_name: &str,
-                _memory_type: &wasmi::MemoryDescriptor,
+                module_name: &str,
+                field_name: &str,
+                memory_type: &wasmi::MemoryDescriptor,
             ) -> Result<wasmi::MemoryRef, wasmi::Error> {
-                Err(wasmi::Error::Instantiation(
-                    "Importing memory is not supported yet".to_owned(),
-                ))
+                if let Some(export) = self.library_export(module_name, field_name) {
+                    return export?.as_memory().cloned().ok_or_else(|| {
+                        wasmi::Error::Instantiation(format!(
+                            "`{}`:`{}` isn't a memory",
+                            module_name, field_name
+                        ))
+                    });
+                }
+
+                let mut imported_memory = self.imported_memory.borrow_mut();
+                if let Some(memory) = imported_memory.as_ref() {
+                    return Ok(memory.clone());
+                }
+
+                let max_pages = self.limits.imported_memory_max_pages;
+                let maximum = memory_type
+                    .maximum()
+                    .map_or(max_pages, |m| cmp::min(m, max_pages));
+                if memory_type.initial() > maximum {
+                    return Err(wasmi::Error::Instantiation(format!(
+                        "`{}`:`{}` requires {} pages of memory, but at most {} are allowed",
+                        module_name,
+                        field_name,
+                        memory_type.initial(),
+                        maximum
+                    )));
+                }
+
+                let initial = cmp::max(
+                    memory_type.initial(),
+                    cmp::min(self.limits.imported_memory_initial_pages, maximum),
+                );
+                let memory = wasmi::MemoryInstance::alloc(
+                    wasmi::memory_units::Pages(usize::try_from(initial).unwrap()),
+                    Some(wasmi::memory_units::Pages(
+                        usize::try_from(maximum).unwrap(),
+                    )),
+                )?;
+                *imported_memory = Some(memory.clone());
+                Ok(memory)
             }
 
             fn resolve_table(
                 &self,
-                _module_name: &str,
-                _field_name: &str,
+                module_name: &str,
+                field_name: &str,
                 _table_type: &wasmi::TableDescriptor,
             ) -> Result<wasmi::TableRef, wasmi::Error> {
+                if let Some(export) = self.library_export(module_name, field_name) {
+                    return export?.as_table().cloned().ok_or_else(|| {
+                        wasmi::Error::Instantiation(format!(
+                            "`{}`:`{}` isn't a table",
+                            module_name, field_name
+                        ))
+                    });
+                }
+
                 Err(wasmi::Error::Instantiation(
                     "Importing tables is not supported yet".to_owned(),
                 ))
             }
         }
 
-        let not_started =
-            wasmi::ModuleInstance::new(module.as_ref(), &ImportResolve(RefCell::new(&mut symbols)))
-                .map_err(NewErr::Interpreter)?;
+        let imported_memory = RefCell::new(None);
+        let mut instantiated_libraries = Vec::with_capacity(libraries.len());
+        let mut fuel_globals = Vec::with_capacity(libraries.len() + 1);
+        let mut stack_globals = Vec::with_capacity(libraries.len() + 1);
+        for (name, library) in libraries {
+            let not_started = ImportResolve {
+                symbols: RefCell::new(&mut symbols),
+                libraries: &instantiated_libraries,
+                unresolved: RefCell::new(None),
+                limits,
+                imported_memory: &imported_memory,
+            }
+            .instantiate(library.as_ref())?;
+
+            let instance = not_started.assert_no_start();
+            if instance.export_by_name(START_EXPORT).is_some() {
+                return Err(NewErr::Interpreter(format!(
+                    "Library `{}` has a start function",
+                    name
+                )));
+            }
+
+            fuel_globals.extend(instrumentation_global(
+                &instance,
+                library.fuel_global(),
+                wasmi::ValueType::I64,
+            ));
+            stack_globals.extend(instrumentation_global(
+                &instance,
+                library.stack_global(),
+                wasmi::ValueType::I32,
+            ));
+            instantiated_libraries.push((*name, instance));
+        }
+
+        let not_started = ImportResolve {
+            symbols: RefCell::new(&mut symbols),
+            libraries: &instantiated_libraries,
+            unresolved: RefCell::new(None),
+            limits,
+            imported_memory: &imported_memory,
+        }
+        .instantiate(module.as_ref())?;
+
+        if let (Some(clock), Some(start)) = (limits.clock, instantiation_start) {
+            let duration = clock().saturating_sub(start);
+            if duration > limits.max_instantiation_time {
+                return Err(NewErr::InstantiationTooLong {
+                    duration,
+                    max: limits.max_instantiation_time,
+                });
+            }
+        }
 
-        // TODO: WASM has a special "start" instruction that can be used to designate a function
-        // that must be executed before the module is considered initialized. It is unclear whether
-        // this is intended to be a function that for example initializes global variables, or if
-        // this is an equivalent of "_start". In practice, Rust never seems to generate such as
-        // "start" instruction, so for now we ignore it. The code below panics if there is such
-        // a "start" item, so we will fortunately not blindly run into troubles.
-        let module = not_started.assert_no_start();
+        // The start section of the module, if any, has been turned into an export when the module
+        // was parsed. The start function is executed below, before `_start` or `main`.
+        let instance = not_started.assert_no_start();
+        let trace_global =
+            instrumentation_global(&instance, module.trace_global(), wasmi::ValueType::I32);
+        let function_names = module.function_names().clone();
+        let debug_global =
+            instrumentation_global(&instance, module.debug_global(), wasmi::ValueType::I32);
+        fuel_globals.extend(instrumentation_global(
+            &instance,
+            module.fuel_global(),
+            wasmi::ValueType::I64,
+        ));
+        stack_globals.extend(instrumentation_global(
+            &instance,
+            module.stack_global(),
+            wasmi::ValueType::I32,
+        ));
+        let module = instance;
 
         let memory = if let Some(mem) = module.export_by_name("memory") {
             if let Some(mem) = mem.as_memory() {
@@ -312,7 +946,7 @@ impl<T> ProcessStateMachine<T> {
                 return Err(NewErr::MemoryIsntMemory);
             }
         } else {
-            None
+            imported_memory.into_inner()
         };
 
         let indirect_table = if let Some(tbl) = module.export_by_name("__indirect_function_table") {
@@ -327,8 +961,21 @@ impl<T> ProcessStateMachine<T> {
 
         let mut state_machine = ProcessStateMachine {
             module,
+            libraries: instantiated_libraries
+                .into_iter()
+                .map(|(_, library)| library)
+                .collect(),
             memory,
             indirect_table,
+            fuel_globals,
+            stack_globals,
+            trace_global,
+            function_names,
+            debug_global,
+            single_step: false,
+            breakpoints: Default::default(),
+            max_memory_pages: None,
+            max_stack_depth: None,
             is_poisoned: false,
             threads: SmallVec::new(),
         };
@@ -345,13 +992,26 @@ impl<T> ProcessStateMachine<T> {
                     Ok(_) => {}
                     Err((StartErr::FunctionNotFound, _)) => return Err(NewErr::StartNotFound),
                     Err((StartErr::Poisoned, _)) => unreachable!(),
+                    Err((StartErr::TooManyThreads, _)) => unreachable!(),
                     Err((StartErr::NotAFunction, _)) => return Err(NewErr::StartIsntAFunction),
                 }
             }
             Err((StartErr::Poisoned, _)) => unreachable!(),
+            Err((StartErr::TooManyThreads, _)) => unreachable!(),
             Err((StartErr::NotAFunction, _)) => return Err(NewErr::StartIsntAFunction),
         };
 
+        if let Some(wasmi::ExternVal::Func(start)) =
+            state_machine.module.export_by_name(START_EXPORT)
+        {
+            let execution = match wasmi::FuncInstance::invoke_resumable(&start, &[][..]) {
+                Ok(e) => e,
+                Err(err) => unreachable!("{:?}", err),
+            };
+            let main_thread = &mut state_machine.threads[0];
+            main_thread.then = main_thread.execution.replace(execution);
+        }
+
         Ok(state_machine)
     }
 
@@ -374,6 +1034,51 @@ impl<T> ProcessStateMachine<T> {
         params: impl IntoIterator<Item = WasmValue>,
         user_data: T,
     ) -> Result<Thread<T>, StartErr> {
+        let execution = self.invoke_by_id(function_id, params)?;
+        self.threads.push(ThreadState {
+            execution: Some(execution),
+            interrupted: false,
+            then: None,
+            stack_depths: vec![0; self.stack_globals.len()],
+            user_data,
+        });
+
+        let thread_id = self.threads.len() - 1;
+        Ok(Thread {
+            vm: self,
+            index: thread_id,
+            marker: PhantomData,
+        })
+    }
+
+    /// Makes the main thread execute the given function instead of the function it is currently
+    /// executing. See [`start_thread_by_id`](ProcessStateMachine::start_thread_by_id) for the
+    /// meaning of `function_id`.
+    ///
+    /// The main thread must not have started running yet. This is meant to be used in
+    /// conjunction with [`copy_state_into`](ProcessStateMachine::copy_state_into).
+    pub fn restart_main_thread(
+        &mut self,
+        function_id: u32,
+        params: impl IntoIterator<Item = WasmValue>,
+    ) -> Result<(), StartErr> {
+        let execution = self.invoke_by_id(function_id, params)?;
+        let main_thread = match self.threads.first_mut() {
+            Some(t) => t,
+            None => return Err(StartErr::Poisoned),
+        };
+        debug_assert!(!main_thread.interrupted);
+        main_thread.execution = Some(execution);
+        main_thread.then = None;
+        Ok(())
+    }
+
+    /// Prepares the execution of the function with the given index in the indirect table.
+    fn invoke_by_id(
+        &self,
+        function_id: u32,
+        params: impl IntoIterator<Item = WasmValue>,
+    ) -> Result<wasmi::FuncInvocation<'static>, StartErr> {
         if self.is_poisoned {
             return Err(StartErr::Poisoned);
         }
@@ -388,25 +1093,13 @@ impl<T> ProcessStateMachine<T> {
 
         let params = params
             .into_iter()
-            .map(wasmi::RuntimeValue::from)
+            .map(WasmValue::to_wasmi)
             .collect::<Vec<_>>();
 
-        let execution = match wasmi::FuncInstance::invoke_resumable(&function, params) {
-            Ok(e) => e,
+        match wasmi::FuncInstance::invoke_resumable(&function, params) {
+            Ok(e) => Ok(e),
             Err(err) => unreachable!("{:?}", err),
-        };
-
-        self.threads.push(ThreadState {
-            execution: Some(execution),
-            interrupted: false,
-            user_data,
-        });
-
-        let thread_id = self.threads.len() - 1;
-        Ok(Thread {
-            vm: self,
-            index: thread_id,
-        })
+        }
     }
 
     /// Same as [`start_thread_by_id`](ProcessStateMachine::start_thread_by_id), but executes a
@@ -430,6 +1123,8 @@ impl<T> ProcessStateMachine<T> {
                 self.threads.push(ThreadState {
                     execution: Some(execution),
                     interrupted: false,
+                    then: None,
+                    stack_depths: vec![0; self.stack_globals.len()],
                     user_data,
                 });
             }
@@ -441,6 +1136,7 @@ impl<T> ProcessStateMachine<T> {
         Ok(Thread {
             vm: self,
             index: thread_id,
+            marker: PhantomData,
         })
     }
 
@@ -459,55 +1155,270 @@ impl<T> ProcessStateMachine<T> {
     /// Returns `None` if the index is superior or equal to what
     /// [`num_threads`](ProcessStateMachine::num_threads) would return.
     pub fn thread(&mut self, index: usize) -> Option<Thread<T>> {
-        if index < self.threads.len() {
-            Some(Thread { vm: self, index })
-        } else {
-            None
-        }
+        WasmVm::thread(self, index)
     }
 
-    /// Consumes this VM and returns all the remaining threads' user datas.
-    pub fn into_user_datas(self) -> impl ExactSizeIterator<Item = T> {
-        self.threads.into_iter().map(|thread| thread.user_data)
+    /// Returns the current values of all the globals of the module, in index order.
+    ///
+    /// This includes the globals that aren't exported.
+    pub fn globals(&self) -> Vec<WasmValue> {
+        self.module
+            .globals()
+            .iter()
+            .map(|global| WasmValue::from_wasmi(global.get()))
+            .collect()
     }
 
-    /// Copies the given memory range into a `Vec<u8>`.
+    /// Copies the content of the memory and the values of the mutable globals of this state
+    /// machine into `target`, which must have been instantiated from the same module and
+    /// libraries.
     ///
-    /// Returns an error if the range is invalid or out of range.
-    pub fn read_memory(&self, offset: u32, size: u32) -> Result<Vec<u8>, ()> {
-        let mem = match self.memory.as_ref() {
-            Some(m) => m,
-            None => unreachable!(),
-        };
+    /// Returns an error if `target` has a different layout, in which case it is left in an
+    /// unspecified state. The tables aren't copied, as WASM code can't modify them.
+    pub fn copy_state_into<U>(&self, target: &mut ProcessStateMachine<U>) -> Result<(), ()> {
+        if self.libraries.len() != target.libraries.len() {
+            return Err(());
+        }
 
-        mem.get(offset, size.try_into().map_err(|_| ())?)
-            .map_err(|_| ())
+        let sources = iter::once(&self.module).chain(&self.libraries);
+        let targets = iter::once(&target.module).chain(&target.libraries);
+        for (source, target) in sources.zip(targets) {
+            let source_globals = source.globals();
+            let target_globals = target.globals();
+            if source_globals.len() != target_globals.len() {
+                return Err(());
+            }
+
+            for (source, target) in source_globals.iter().zip(target_globals.iter()) {
+                if source.value_type() != target.value_type()
+                    || source.is_mutable() != target.is_mutable()
+                {
+                    return Err(());
+                }
+                if source.is_mutable() {
+                    target.set(source.get()).map_err(|_| ())?;
+                }
+            }
+        }
+
+        match (&self.memory, &target.memory) {
+            (Some(source), Some(target)) => {
+                let source_pages = source.current_size().0;
+                let target_pages = target.current_size().0;
+                if source_pages < target_pages {
+                    return Err(());
+                }
+                target
+                    .grow(wasmi::memory_units::Pages(source_pages - target_pages))
+                    .map_err(|_| ())?;
+                source
+                    .with_direct_access(|bytes| target.set(0, bytes))
+                    .map_err(|_| ())?;
+            }
+            (None, None) => {}
+            _ => return Err(()),
+        }
+
+        Ok(())
     }
 
-    /// Write the data at the given memory location.
+    /// Sets the amount of fuel available to the threads. Approximately one unit of fuel is
+    /// consumed per instruction. Once the fuel runs out, the thread that is running is paused
+    /// and [`ExecOutcome::OutOfFuel`] is returned.
     ///
-    /// Returns an error if the range is invalid or out of range.
-    pub fn write_memory(&mut self, offset: u32, value: &[u8]) -> Result<(), ()> {
-        let mem = match self.memory.as_ref() {
-            Some(m) => m,
-            None => unreachable!(),
-        };
+    /// The fuel is shared between all the threads, and isn't refilled automatically. Modules
+    /// start with an amount of fuel so large that they are in practice never paused.
+    // TODO: the module and each of its libraries each have their own fuel
+    pub fn set_fuel(&mut self, fuel: u64) {
+        let fuel = i64::try_from(fuel).unwrap_or(i64::max_value());
+        for global in &self.fuel_globals {
+            // Can only fail if the type is wrong, which `instrumentation_global` has checked.
+            let _ = global.set(wasmi::RuntimeValue::I64(fuel));
+        }
+    }
 
-        mem.set(offset, value).map_err(|_| ())
+    /// Sets the maximum number of 64kiB pages that the memory is allowed to have. Pass `None` for
+    /// no limit other than the one declared by the module.
+    ///
+    /// If the limit is exceeded, [`run`](Thread::run) returns
+    /// [`ExecOutcome::MemoryLimitExceeded`].
+    pub fn set_max_memory_pages(&mut self, max: Option<u32>) {
+        self.max_memory_pages = max;
     }
-}
 
-// The fields related to `wasmi` do not implement `Send` because they use `std::rc::Rc`. `Rc`
-// does not implement `Send` because incrementing/decrementing the reference counter from
-// multiple threads simultaneously would be racy. It is however perfectly sound to move all the
-// instances of `Rc`s at once between threads, which is what we're doing here.
-//
-// This importantly means that we should never return a `Rc` (even by reference) across the API
-// boundary.
-// TODO: really annoying to have to use unsafe code
-unsafe impl<T: Send> Send for ProcessStateMachine<T> {}
+    /// Sets the maximum number of nested function calls that each thread is allowed to make.
+    /// Pass `None` for no limit other than the ones of the interpreter.
+    ///
+    /// If the limit is exceeded, [`run`](Thread::run) returns [`ExecOutcome::StackOverflow`].
+    ///
+    /// > **Note**: The module and each of its libraries count the calls made by their own
+    /// >           functions separately.
+    pub fn set_max_stack_depth(&mut self, max: Option<u32>) {
+        self.max_stack_depth = max;
+    }
 
-impl<T> fmt::Debug for ProcessStateMachine<T>
+    /// Returns true if the main module has been loaded with [`Module::from_bytes_debuggable`].
+    ///
+    /// If false, [`set_single_step`](ProcessStateMachine::set_single_step) and
+    /// [`add_breakpoint`](ProcessStateMachine::add_breakpoint) have no effect.
+    pub fn is_debuggable(&self) -> bool {
+        self.debug_global.is_some()
+    }
+
+    /// Enables or disables single-stepping. When enabled, the thread that is running is paused
+    /// and [`ExecOutcome::Breakpoint`] returned before each instruction of the main module.
+    ///
+    /// > **Note**: The functions of the libraries the module is linked with are executed without
+    /// >           interruption.
+    pub fn set_single_step(&mut self, enabled: bool) {
+        self.single_step = enabled;
+        self.update_debug_global();
+    }
+
+    /// Adds a breakpoint at the start of the function of the main module with the given index,
+    /// as found in its name section. The thread that is running is paused and
+    /// [`ExecOutcome::Breakpoint`] returned every time this function is called.
+    pub fn add_breakpoint(&mut self, function_index: u32) {
+        self.breakpoints.insert(function_index);
+        self.update_debug_global();
+    }
+
+    /// Removes a breakpoint previously added with
+    /// [`add_breakpoint`](ProcessStateMachine::add_breakpoint). Does nothing if there is no such
+    /// breakpoint.
+    pub fn remove_breakpoint(&mut self, function_index: u32) {
+        self.breakpoints.remove(&function_index);
+        self.update_debug_global();
+    }
+
+    /// Updates [`ProcessStateMachine::debug_global`] to match the breakpoints and whether
+    /// single-stepping is enabled.
+    fn update_debug_global(&mut self) {
+        let mode = if self.single_step {
+            debug::MODE_INSTRUCTIONS
+        } else if !self.breakpoints.is_empty() {
+            debug::MODE_FUNCTIONS
+        } else {
+            debug::MODE_DISABLED
+        };
+
+        if let Some(global) = &self.debug_global {
+            // Can only fail if the type is wrong, which `instrumentation_global` has checked.
+            let _ = global.set(wasmi::RuntimeValue::I32(mode));
+        }
+    }
+
+    /// Returns the function of the main module that is being executed, according to
+    /// [`ProcessStateMachine::trace_global`].
+    fn current_frame(&self) -> Option<BacktraceFrame> {
+        let index = match self.trace_global.as_ref()?.get() {
+            wasmi::RuntimeValue::I32(index) => u32::try_from(index).ok()?,
+            _ => return None,
+        };
+
+        Some(BacktraceFrame {
+            function_index: index,
+            function_name: self.function_names.get(&index).cloned(),
+        })
+    }
+
+    /// Returns the user datas of all the threads, in index order.
+    pub fn user_datas(&self) -> impl ExactSizeIterator<Item = &T> {
+        self.threads.iter().map(|thread| &thread.user_data)
+    }
+
+    /// Returns the indices of the threads whose execution context is missing.
+    ///
+    /// This should never return anything, unless the state machine has a bug.
+    pub fn threads_without_execution(&self) -> impl Iterator<Item = usize> + '_ {
+        self.threads
+            .iter()
+            .enumerate()
+            .filter(|(_, thread)| thread.execution.is_none())
+            .map(|(index, _)| index)
+    }
+
+    /// Consumes this VM and returns all the remaining threads' user datas.
+    pub fn into_user_datas(self) -> impl ExactSizeIterator<Item = T> {
+        self.threads.into_iter().map(|thread| thread.user_data)
+    }
+
+    /// Returns the size of the memory of the process, in bytes.
+    ///
+    /// Returns 0 if the module doesn't export any memory.
+    pub fn memory_size(&self) -> u64 {
+        let mem = match self.memory.as_ref() {
+            Some(m) => m,
+            None => return 0,
+        };
+
+        let size: wasmi::memory_units::Bytes = mem.current_size().into();
+        size.0 as u64
+    }
+
+    /// Copies the given memory range into a `Vec<u8>`.
+    ///
+    /// Returns an error if the range is invalid or out of range.
+    pub fn read_memory(&self, offset: u32, size: u32) -> Result<Vec<u8>, ()> {
+        let mem = match self.memory.as_ref() {
+            Some(m) => m,
+            None => return Err(()),
+        };
+
+        mem.get(offset, size.try_into().map_err(|_| ())?)
+            .map_err(|_| ())
+    }
+
+    /// Copies the memory starting at `offset` into `buffer`, filling it entirely.
+    ///
+    /// Returns an error if the range is invalid or out of range.
+    pub fn read_memory_into(&self, offset: u32, buffer: &mut [u8]) -> Result<(), ()> {
+        let mem = match self.memory.as_ref() {
+            Some(m) => m,
+            None => return Err(()),
+        };
+
+        mem.get_into(offset, buffer).map_err(|_| ())
+    }
+
+    /// Calls `f` with the given range of the memory, without copying it.
+    ///
+    /// Returns an error if the range is invalid or out of range.
+    pub fn with_memory<R>(&self, range: Range<u32>, f: impl FnOnce(&[u8]) -> R) -> Result<R, ()> {
+        let mem = match self.memory.as_ref() {
+            Some(m) => m,
+            None => return Err(()),
+        };
+
+        let start = usize::try_from(range.start).map_err(|_| ())?;
+        let end = usize::try_from(range.end).map_err(|_| ())?;
+        mem.with_direct_access(|memory| memory.get(start..end).map(f).ok_or(()))
+    }
+
+    /// Write the data at the given memory location.
+    ///
+    /// Returns an error if the range is invalid or out of range.
+    pub fn write_memory(&mut self, offset: u32, value: &[u8]) -> Result<(), ()> {
+        let mem = match self.memory.as_ref() {
+            Some(m) => m,
+            None => return Err(()),
+        };
+
+        mem.set(offset, value).map_err(|_| ())
+    }
+}
+
+// The fields related to `wasmi` do not implement `Send` because they use `std::rc::Rc`. `Rc`
+// does not implement `Send` because incrementing/decrementing the reference counter from
+// multiple threads simultaneously would be racy. It is however perfectly sound to move all the
+// instances of `Rc`s at once between threads, which is what we're doing here.
+//
+// This importantly means that we should never return a `Rc` (even by reference) across the API
+// boundary.
+// TODO: really annoying to have to use unsafe code
+unsafe impl<T: Send> Send for ProcessStateMachine<T> {}
+
+impl<T> fmt::Debug for ProcessStateMachine<T>
 where
     T: fmt::Debug,
 {
@@ -525,14 +1436,104 @@ where
     }
 }
 
-impl<'a, T> Thread<'a, T> {
+impl<T> WasmVm<T> for ProcessStateMachine<T> {
+    fn is_poisoned(&self) -> bool {
+        ProcessStateMachine::is_poisoned(self)
+    }
+
+    fn start_thread_by_id(
+        &mut self,
+        function_id: u32,
+        params: impl IntoIterator<Item = WasmValue>,
+        user_data: T,
+    ) -> Result<Thread<'_, T>, StartErr> {
+        ProcessStateMachine::start_thread_by_id(self, function_id, params, user_data)
+    }
+
+    fn num_threads(&self) -> usize {
+        ProcessStateMachine::num_threads(self)
+    }
+
+    fn run_thread(
+        &mut self,
+        index: usize,
+        value: Option<WasmValue>,
+    ) -> Result<ExecOutcome<'_, T>, RunErr> {
+        assert!(index < self.threads.len());
+        Thread {
+            vm: self,
+            index,
+            marker: PhantomData,
+        }
+        .run_wasmi(value)
+    }
+
+    fn thread_user_data(&mut self, index: usize) -> &mut T {
+        &mut self.threads[index].user_data
+    }
+
+    fn set_fuel(&mut self, fuel: u64) {
+        ProcessStateMachine::set_fuel(self, fuel)
+    }
+
+    fn set_max_memory_pages(&mut self, max: Option<u32>) {
+        ProcessStateMachine::set_max_memory_pages(self, max)
+    }
+
+    fn set_max_stack_depth(&mut self, max: Option<u32>) {
+        ProcessStateMachine::set_max_stack_depth(self, max)
+    }
+
+    fn memory_size(&self) -> u64 {
+        ProcessStateMachine::memory_size(self)
+    }
+
+    fn read_memory_into(&self, offset: u32, buffer: &mut [u8]) -> Result<(), ()> {
+        ProcessStateMachine::read_memory_into(self, offset, buffer)
+    }
+
+    fn with_memory<R>(&self, range: Range<u32>, f: impl FnOnce(&[u8]) -> R) -> Result<R, ()> {
+        ProcessStateMachine::with_memory(self, range, f)
+    }
+
+    fn write_memory(&mut self, offset: u32, value: &[u8]) -> Result<(), ()> {
+        ProcessStateMachine::write_memory(self, offset, value)
+    }
+}
+
+impl<'a, T, V: WasmVm<T>> Thread<'a, T, V> {
     /// Starts or continues execution of this thread.
     ///
     /// If this is the first call you call [`run`](Thread::run) for this thread, then you must pass
     /// a value of `None`.
     /// If, however, you call this function after a previous call to [`run`](Thread::run) that was
     /// interrupted by an external function call, then you must pass back the outcome of that call.
-    pub fn run(mut self, value: Option<WasmValue>) -> Result<ExecOutcome<'a, T>, RunErr> {
+    pub fn run(self, value: Option<WasmValue>) -> Result<ExecOutcome<'a, T, V>, RunErr> {
+        self.vm.run_thread(self.index, value)
+    }
+
+    /// Returns the index of the thread, so that you can retreive the thread later by calling
+    /// [`WasmVm::thread`].
+    ///
+    /// Keep in mind that when a thread finishes, all the indices above its index shift by one.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Returns the user data associated to that thread.
+    pub fn user_data(&mut self) -> &mut T {
+        self.vm.thread_user_data(self.index)
+    }
+
+    /// Turns this thread into the user data associated to it.
+    pub fn into_user_data(self) -> &'a mut T {
+        self.vm.thread_user_data(self.index)
+    }
+}
+
+impl<'a, T> Thread<'a, T> {
+    /// Implementation of [`WasmVm::run_thread`] for [`ProcessStateMachine`].
+    fn run_wasmi(self, value: Option<WasmValue>) -> Result<ExecOutcome<'a, T>, RunErr> {
         struct DummyExternals;
         impl wasmi::Externals for DummyExternals {
             fn invoke_index(
@@ -570,28 +1571,86 @@ impl<'a, T> Thread<'a, T> {
             Some(e) => e,
             None => unreachable!(),
         };
-        let result = if thread_state.interrupted {
-            let expected_ty = execution.resumable_value_type().map(ValueType::from);
-            let obtained_ty = value.as_ref().map(|v| v.ty());
-            if expected_ty != obtained_ty {
-                return Err(RunErr::BadValueTy {
-                    expected: expected_ty,
-                    obtained: obtained_ty,
-                });
+
+        let max_depth = self.vm.max_stack_depth.map_or(i32::max_value(), |max| {
+            i32::try_from(max).unwrap_or(i32::max_value())
+        });
+        for (global, depth) in self.vm.stack_globals.iter().zip(&thread_state.stack_depths) {
+            // Can only fail if the type is wrong, which `instrumentation_global` has checked.
+            let _ = global.set(wasmi::RuntimeValue::I32(max_depth.saturating_sub(*depth)));
+        }
+
+        let mut value = value;
+        let result = loop {
+            let result = if thread_state.interrupted {
+                let expected_ty = execution.resumable_value_type().map(ValueType::from_wasmi);
+                let obtained_ty = value.as_ref().map(|v| v.ty());
+                if expected_ty != obtained_ty {
+                    return Err(RunErr::BadValueTy {
+                        expected: expected_ty,
+                        obtained: obtained_ty,
+                    });
+                }
+                execution
+                    .resume_execution(value.take().map(WasmValue::to_wasmi), &mut DummyExternals)
+            } else {
+                if value.is_some() {
+                    return Err(RunErr::BadValueTy {
+                        expected: None,
+                        obtained: value.as_ref().map(|v| v.ty()),
+                    });
+                }
+                thread_state.interrupted = true;
+                execution.start_execution(&mut DummyExternals)
+            };
+
+            // Breakpoints at the start of functions are reported for all the functions, and the
+            // ones that don't have a breakpoint are immediately resumed.
+            if let Err(wasmi::ResumableError::Trap(trap)) = &result {
+                if let wasmi::TrapKind::Host(err) = trap.kind() {
+                    if let Some(Interrupt { index, args }) = err.downcast_ref() {
+                        if *index == BREAKPOINT
+                            && !self.vm.single_step
+                            && !self.vm.breakpoints.contains(&breakpoint_location(args).0)
+                        {
+                            continue;
+                        }
+                    }
+                }
             }
-            execution.resume_execution(value.map(From::from), &mut DummyExternals)
-        } else {
-            if value.is_some() {
-                return Err(RunErr::BadValueTy {
-                    expected: None,
-                    obtained: value.as_ref().map(|v| v.ty()),
+
+            break result;
+        };
+
+        for (global, depth) in self
+            .vm
+            .stack_globals
+            .iter()
+            .zip(&mut thread_state.stack_depths)
+        {
+            if let wasmi::RuntimeValue::I32(remaining) = global.get() {
+                *depth = max_depth.saturating_sub(remaining);
+            }
+        }
+
+        if let (Some(max), Some(memory)) = (self.vm.max_memory_pages, self.vm.memory.as_ref()) {
+            let pages = u32::try_from(memory.current_size().0).unwrap_or(u32::max_value());
+            if pages > max {
+                self.vm.is_poisoned = true;
+                return Ok(ExecOutcome::MemoryLimitExceeded {
+                    thread: self,
+                    pages,
+                    max,
                 });
             }
-            thread_state.interrupted = true;
-            execution.start_execution(&mut DummyExternals)
-        };
+        }
 
         match result {
+            Ok(_) if thread_state.then.is_some() => {
+                thread_state.execution = thread_state.then.take();
+                thread_state.interrupted = false;
+                self.run_wasmi(None)
+            }
             Ok(return_value) => {
                 let user_data = self.vm.threads.remove(self.index).user_data;
                 // If this is the "main" function, the state machine is now poisoned.
@@ -600,7 +1659,7 @@ impl<'a, T> Thread<'a, T> {
                 }
                 Ok(ExecOutcome::ThreadFinished {
                     thread_index: self.index,
-                    return_value: return_value.map(From::from),
+                    return_value: return_value.map(WasmValue::from_wasmi),
                     user_data,
                 })
             }
@@ -615,54 +1674,112 @@ impl<'a, T> Thread<'a, T> {
                     _ => unreachable!(),
                 };
                 thread_state.execution = Some(execution);
+                if interrupt.index == OUT_OF_FUEL {
+                    return Ok(ExecOutcome::OutOfFuel { thread: self });
+                }
+                if interrupt.index == STACK_OVERFLOW {
+                    self.vm.is_poisoned = true;
+                    let max = self.vm.max_stack_depth;
+                    return Ok(ExecOutcome::StackOverflow { thread: self, max });
+                }
+                if interrupt.index == BREAKPOINT {
+                    let (function_index, instruction) = breakpoint_location(&interrupt.args);
+                    return Ok(ExecOutcome::Breakpoint {
+                        thread: self,
+                        function_index,
+                        instruction,
+                    });
+                }
                 Ok(ExecOutcome::Interrupted {
                     thread: self,
                     id: interrupt.index,
-                    params: interrupt.args.iter().map(|v| From::from(*v)).collect(),
+                    params: interrupt
+                        .args
+                        .iter()
+                        .cloned()
+                        .map(WasmValue::from_wasmi)
+                        .collect(),
                 })
             }
             Err(wasmi::ResumableError::Trap(trap)) => {
                 self.vm.is_poisoned = true;
+                if let wasmi::TrapKind::StackOverflow = trap.kind() {
+                    return Ok(ExecOutcome::StackOverflow {
+                        thread: self,
+                        max: None,
+                    });
+                }
+                let backtrace = self.vm.current_frame().into_iter().collect();
                 Ok(ExecOutcome::Errored {
                     thread: self,
-                    error: trap,
+                    error: TrapError::new(TrapKind::from_wasmi(trap.kind()), backtrace),
                 })
             }
         }
     }
+}
 
-    /// Returns the index of the thread, so that you can retreive the thread later by calling
-    /// [`ProcessStateMachine::thread`].
-    ///
-    /// Keep in mind that when a thread finishes, all the indices above its index shift by one.
-    pub fn index(&self) -> usize {
-        self.index
+impl<'a, T, V: WasmVm<T>> fmt::Debug for Thread<'a, T, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("Thread").field(&self.index).finish()
     }
+}
 
-    /// Returns the user data associated to that thread.
-    pub fn user_data(&mut self) -> &mut T {
-        &mut self.vm.threads[self.index].user_data
+/// Turns the parameters passed to the [`BREAKPOINT`] function into a function index and an
+/// instruction index.
+fn breakpoint_location(args: &[wasmi::RuntimeValue]) -> (u32, u32) {
+    // The signature of the function has been checked when resolving it.
+    match args {
+        [wasmi::RuntimeValue::I32(function), wasmi::RuntimeValue::I32(instruction)] => {
+            (*function as u32, *instruction as u32)
+        }
+        _ => unreachable!(),
     }
+}
 
-    /// Turns this thread into the user data associated to it.
-    pub fn into_user_data(self) -> &'a mut T {
-        &mut self.vm.threads[self.index].user_data
+/// Returns the global of the given instance whose index is passed, after checking that it is
+/// mutable and has the given type. Used for the globals added to modules when they are loaded,
+/// such as the one that contains the remaining fuel.
+fn instrumentation_global(
+    instance: &wasmi::ModuleRef,
+    index: Option<u32>,
+    ty: wasmi::ValueType,
+) -> Option<wasmi::GlobalRef> {
+    let index = usize::try_from(index?).ok()?;
+    let global = instance.globals().get(index)?.clone();
+    if global.value_type() == ty && global.is_mutable() {
+        Some(global)
+    } else {
+        None
     }
 }
 
-impl<'a, T> fmt::Debug for Thread<'a, T>
-where
-    T: fmt::Debug,
-{
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        fmt::Debug::fmt(&self.vm.threads[self.index], f)
-    }
+/// Returns an error if the module exceeds one of the limits.
+fn check_limits(module: &Module, limits: &ModuleLimits) -> Result<(), NewErr> {
+    limits.check_stats(module.stats())
 }
 
 impl fmt::Display for NewErr {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             NewErr::Interpreter(err) => write!(f, "Error in the interpreter: {}", err),
+            NewErr::IncompatibleAbi(report) => write!(f, "Incompatible module: {}", report),
+            NewErr::UnresolvedImport {
+                interface,
+                function,
+                expected: Some(expected),
+                obtained,
+            } => write!(
+                f,
+                "`{}`:`{}` has signature {:?} but the module expects {:?}",
+                interface, function, expected, obtained
+            ),
+            NewErr::UnresolvedImport {
+                interface,
+                function,
+                expected: None,
+                ..
+            } => write!(f, "Couldn't resolve import `{}`:`{}`", interface, function),
             NewErr::StartNotFound => write!(f, "The \"start\" symbol doesn't exist"),
             NewErr::StartIsntAFunction => write!(f, "The \"start\" symbol must be a function"),
             NewErr::MemoryIsntMemory => {
@@ -672,6 +1789,34 @@ impl fmt::Display for NewErr {
                 f,
                 "If a \"__indirect_function_table\" symbol is provided, it must be a table"
             ),
+            NewErr::ModuleTooLarge { size, max } => {
+                write!(
+                    f,
+                    "Module size ({} bytes) exceeds the limit of {}",
+                    size, max
+                )
+            }
+            NewErr::DataTooLarge { size, max } => write!(
+                f,
+                "Data segments size ({} bytes) exceeds the limit of {}",
+                size, max
+            ),
+            NewErr::TooManyFunctions { num, max } => write!(
+                f,
+                "Number of functions ({}) exceeds the limit of {}",
+                num, max
+            ),
+            NewErr::FunctionTooLarge { instructions, max } => write!(
+                f,
+                "Function with {} instructions exceeds the limit of {}",
+                instructions, max
+            ),
+            NewErr::InstantiationTooLong { duration, max } => write!(
+                f,
+                "Instantiation took {}ns, which exceeds the limit of {}ns",
+                duration, max
+            ),
+            NewErr::UntrustedModule => write!(f, "Module isn't signed by a trusted key"),
         }
     }
 }
@@ -682,6 +1827,7 @@ impl fmt::Display for StartErr {
             StartErr::Poisoned => write!(f, "State machine is in a poisoned state"),
             StartErr::FunctionNotFound => write!(f, "Function to start was not found"),
             StartErr::NotAFunction => write!(f, "Symbol to start is not a function"),
+            StartErr::TooManyThreads => write!(f, "Maximum number of threads reached"),
         }
     }
 }
@@ -701,8 +1847,9 @@ impl fmt::Display for RunErr {
 
 #[cfg(test)]
 mod tests {
-    use super::{ExecOutcome, NewErr, ProcessStateMachine};
-    use crate::WasmValue;
+    use super::{ExecOutcome, ModuleLimits, NewErr, ProcessStateMachine};
+    use crate::{sig, BacktraceFrame, Module, WasmValue};
+    use alloc::vec;
 
     #[test]
     fn starts_if_main() {
@@ -716,7 +1863,8 @@ mod tests {
         );
 
         let _state_machine =
-            ProcessStateMachine::new(&module, (), |_, _, _| unreachable!()).unwrap();
+            ProcessStateMachine::new(&module, &Default::default(), (), |_, _, _| unreachable!())
+                .unwrap();
     }
 
     #[test]
@@ -730,7 +1878,7 @@ mod tests {
         "#
         );
 
-        match ProcessStateMachine::new(&module, (), |_, _, _| unreachable!()) {
+        match ProcessStateMachine::new(&module, &Default::default(), (), |_, _, _| unreachable!()) {
             Err(NewErr::StartNotFound) => {}
             _ => panic!(),
         }
@@ -748,7 +1896,8 @@ mod tests {
         );
 
         let mut state_machine =
-            ProcessStateMachine::new(&module, (), |_, _, _| unreachable!()).unwrap();
+            ProcessStateMachine::new(&module, &Default::default(), (), |_, _, _| unreachable!())
+                .unwrap();
         match state_machine.thread(0).unwrap().run(None) {
             Ok(ExecOutcome::ThreadFinished {
                 return_value: Some(WasmValue::I32(5)),
@@ -771,7 +1920,8 @@ mod tests {
         "#
         );
 
-        let mut state_machine = ProcessStateMachine::new(&module, (), |_, _, _| Ok(9876)).unwrap();
+        let mut state_machine =
+            ProcessStateMachine::new(&module, &Default::default(), (), |_, _, _| Ok(9876)).unwrap();
         match state_machine.thread(0).unwrap().run(None) {
             Ok(ExecOutcome::Interrupted {
                 id: 9876,
@@ -795,6 +1945,48 @@ mod tests {
         assert!(state_machine.thread(0).is_none());
     }
 
+    #[test]
+    fn out_of_fuel_then_resume() {
+        let module = from_wat!(
+            local,
+            r#"(module
+            (func $incr (param i32) (result i32)
+                local.get 0
+                i32.const 1
+                i32.add)
+            (func $_start (result i32)
+                (local i32)
+                (loop $l
+                    local.get 0
+                    call $incr
+                    local.tee 0
+                    i32.const 100
+                    i32.lt_u
+                    br_if $l)
+                local.get 0)
+            (export "_start" (func $_start)))
+        "#
+        );
+
+        let mut state_machine =
+            ProcessStateMachine::new(&module, &Default::default(), (), |_, _, _| Err(None))
+                .unwrap();
+
+        let mut paused = 0;
+        loop {
+            state_machine.set_fuel(50);
+            match state_machine.thread(0).unwrap().run(None) {
+                Ok(ExecOutcome::OutOfFuel { .. }) => paused += 1,
+                Ok(ExecOutcome::ThreadFinished {
+                    return_value: Some(WasmValue::I32(100)),
+                    ..
+                }) => break,
+                _ => panic!(),
+            }
+        }
+        assert!(paused >= 10);
+    }
+
     #[test]
     fn poisoning_works() {
         let module = from_wat!(
@@ -807,7 +1999,8 @@ mod tests {
         );
 
         let mut state_machine =
-            ProcessStateMachine::new(&module, (), |_, _, _| unreachable!()).unwrap();
+            ProcessStateMachine::new(&module, &Default::default(), (), |_, _, _| unreachable!())
+                .unwrap();
         match state_machine.thread(0).unwrap().run(None) {
             Ok(ExecOutcome::Errored { .. }) => {}
             _ => panic!(),
@@ -818,5 +2011,454 @@ mod tests {
         // TODO: start running another function and check that `Poisoned` error is returned
     }
 
+    #[test]
+    fn unresolved_import_reported() {
+        let module = from_wat!(
+            local,
+            r#"(module
+            (import "foo" "test" (func $test (param i32)))
+            (func $_start
+                i32.const 5
+                call $test)
+            (export "_start" (func $_start)))
+        "#
+        );
+
+        match ProcessStateMachine::new(&module, &Default::default(), (), |_, _, _| {
+            Err(Some(sig!((I64))))
+        }) {
+            Err(NewErr::UnresolvedImport {
+                interface,
+                function,
+                expected,
+                obtained,
+            }) => {
+                assert_eq!(interface, "foo");
+                assert_eq!(function, "test");
+                assert_eq!(expected, Some(sig!((I64))));
+                assert_eq!(obtained, sig!((I32)));
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn limits_enforced() {
+        let module = from_wat!(
+            local,
+            r#"(module
+            (func $_start (result i32)
+                i32.const 1
+                i32.const 2
+                i32.add)
+            (memory $memory 1)
+            (data (i32.const 0) "abcdefgh")
+            (export "_start" (func $_start)))
+        "#
+        );
+
+        let limits = ModuleLimits {
+            max_data_size: 7,
+            ..Default::default()
+        };
+        match ProcessStateMachine::new(&module, &limits, (), |_, _, _| unreachable!()) {
+            Err(NewErr::DataTooLarge { size: 8, max: 7 }) => {}
+            _ => panic!(),
+        }
+
+        let limits = ModuleLimits {
+            max_function_instructions: 2,
+            ..Default::default()
+        };
+        match ProcessStateMachine::new(&module, &limits, (), |_, _, _| unreachable!()) {
+            Err(NewErr::FunctionTooLarge { .. }) => {}
+            _ => panic!(),
+        }
+
+        let limits = ModuleLimits {
+            max_data_size: 8,
+            max_functions: 1,
+            ..Default::default()
+        };
+        assert!(ProcessStateMachine::new(&module, &limits, (), |_, _, _| unreachable!()).is_ok());
+    }
+
+    #[test]
+    fn limits_checked_before_loading() {
+        let bytes = wat_to_bin!(
+            r#"(module
+            (func $_start)
+            (memory $memory 1)
+            (data (i32.const 0) "abcdefgh")
+            (export "_start" (func $_start)))
+        "#
+        )
+        .to_vec();
+
+        let limits = ModuleLimits {
+            max_module_size: bytes.len() - 1,
+            ..Default::default()
+        };
+        match limits.check(&bytes) {
+            Err(NewErr::ModuleTooLarge { .. }) => {}
+            _ => panic!(),
+        }
+
+        let limits = ModuleLimits {
+            max_data_size: 7,
+            ..Default::default()
+        };
+        match limits.check(&bytes) {
+            Err(NewErr::DataTooLarge { size: 8, max: 7 }) => {}
+            _ => panic!(),
+        }
+
+        // Malformed modules are reported when loading them.
+        assert!(ModuleLimits::default().check(b"not a module").is_ok());
+        assert!(ModuleLimits::default().check(&bytes).is_ok());
+    }
+
+    #[test]
+    fn instantiation_time_limited() {
+        static NOW: core::sync::atomic::AtomicU64 = core::sync::atomic::AtomicU64::new(0);
+        let module = from_wat!(
+            local,
+            r#"(module
+            (func $_start)
+            (export "_start" (func $_start)))
+        "#
+        );
+
+        // Every reading of the clock is 10 nanoseconds after the previous one.
+        let limits = ModuleLimits {
+            max_instantiation_time: 5,
+            clock: Some(|| NOW.fetch_add(10, core::sync::atomic::Ordering::Relaxed)),
+            ..Default::default()
+        };
+        match ProcessStateMachine::new(&module, &limits, (), |_, _, _| unreachable!()) {
+            Err(NewErr::InstantiationTooLong {
+                duration: 10,
+                max: 5,
+            }) => {}
+            _ => panic!(),
+        }
+
+        let limits = ModuleLimits {
+            max_instantiation_time: 10,
+            ..limits
+        };
+        assert!(ProcessStateMachine::new(&module, &limits, (), |_, _, _| unreachable!()).is_ok());
+    }
+
+    #[test]
+    fn library_linked() {
+        let library = from_wat!(
+            local,
+            r#"(module
+            (memory $memory (export "memory") 1)
+            (func $get (result i32)
+                i32.const 0
+                i32.load)
+            (export "get" (func $get)))
+        "#
+        );
+
+        let module = from_wat!(
+            local,
+            r#"(module
+            (import "lib" "memory" (memory 1))
+            (import "lib" "get" (func $get (result i32)))
+            (func $_start (result i32)
+                i32.const 0
+                i32.const 7
+                i32.store
+                call $get)
+            (export "_start" (func $_start)))
+        "#
+        );
+
+        let mut state_machine = ProcessStateMachine::new_linked(
+            &module,
+            &[("lib", &library)],
+            &Default::default(),
+            (),
+            |_, _, _| unreachable!(),
+        )
+        .unwrap();
+        match state_machine.thread(0).unwrap().run(None) {
+            Ok(ExecOutcome::ThreadFinished {
+                return_value: Some(WasmValue::I32(7)),
+                ..
+            }) => {}
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn memory_imported() {
+        let module = from_wat!(
+            local,
+            r#"(module
+            (import "env" "memory" (memory 1))
+            (func $_start (result i32)
+                i32.const 70000
+                i32.const 7
+                i32.store
+                i32.const 70000
+                i32.load)
+            (export "_start" (func $_start)))
+        "#
+        );
+
+        let limits = ModuleLimits {
+            imported_memory_initial_pages: 2,
+            ..Default::default()
+        };
+        let mut state_machine =
+            ProcessStateMachine::new(&module, &limits, (), |_, _, _| unreachable!()).unwrap();
+        match state_machine.thread(0).unwrap().run(None) {
+            Ok(ExecOutcome::ThreadFinished {
+                return_value: Some(WasmValue::I32(7)),
+                ..
+            }) => {}
+            _ => panic!(),
+        }
+        assert_eq!(state_machine.read_memory(70000, 4).unwrap(), [7, 0, 0, 0]);
+        let mut buffer = [0xff; 4];
+        state_machine.read_memory_into(69999, &mut buffer).unwrap();
+        assert_eq!(buffer, [0, 7, 0, 0]);
+        assert_eq!(
+            state_machine.with_memory(70000..70002, |mem| mem.to_vec()),
+            Ok(vec![7, 0])
+        );
+        assert!(state_machine.with_memory(0..200000, |_| ()).is_err());
+
+        let limits = ModuleLimits {
+            imported_memory_max_pages: 0,
+            ..Default::default()
+        };
+        match ProcessStateMachine::new(&module, &limits, (), |_, _, _| unreachable!()) {
+            Err(NewErr::Interpreter(_)) => {}
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn start_function_executed_first() {
+        let module = from_wat!(
+            local,
+            r#"(module
+            (import "foo" "test" (func $test (result i32)))
+            (global $g (mut i32) (i32.const 0))
+            (func $init
+                call $test
+                global.set $g)
+            (func $_start (result i32)
+                global.get $g)
+            (start $init)
+            (export "_start" (func $_start)))
+        "#
+        );
+
+        let mut state_machine =
+            ProcessStateMachine::new(&module, &Default::default(), (), |_, _, _| Ok(9876)).unwrap();
+        match state_machine.thread(0).unwrap().run(None) {
+            Ok(ExecOutcome::Interrupted {
+                id: 9876,
+                ref params,
+                ..
+            }) if params.is_empty() => {}
+            _ => panic!(),
+        }
+        match state_machine
+            .thread(0)
+            .unwrap()
+            .run(Some(WasmValue::I32(42)))
+        {
+            Ok(ExecOutcome::ThreadFinished {
+                thread_index: 0,
+                return_value: Some(WasmValue::I32(42)),
+                ..
+            }) => {}
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn stack_depth_enforced() {
+        let module = from_wat!(
+            local,
+            r#"(module
+            (import "foo" "test" (func $test))
+            (func $rec (param i32)
+                local.get 0
+                if
+                    local.get 0
+                    i32.const 1
+                    i32.sub
+                    call $rec
+                else
+                    call $test
+                end)
+            (func $_start
+                i32.const 3
+                call $rec)
+            (export "_start" (func $_start)))
+        "#
+        );
+
+        // `_start` makes five nested calls, including the one to `test`.
+        let mut state_machine =
+            ProcessStateMachine::new(&module, &Default::default(), (), |_, _, _| Ok(1)).unwrap();
+        state_machine.set_max_stack_depth(Some(5));
+        match state_machine.thread(0).unwrap().run(None) {
+            Ok(ExecOutcome::Interrupted { id: 1, .. }) => {}
+            _ => panic!(),
+        }
+        match state_machine.thread(0).unwrap().run(None) {
+            Ok(ExecOutcome::ThreadFinished { .. }) => {}
+            _ => panic!(),
+        }
+
+        let mut state_machine =
+            ProcessStateMachine::new(&module, &Default::default(), (), |_, _, _| Ok(1)).unwrap();
+        state_machine.set_max_stack_depth(Some(4));
+        match state_machine.thread(0).unwrap().run(None) {
+            Ok(ExecOutcome::StackOverflow { max: Some(4), .. }) => {}
+            _ => panic!(),
+        }
+        assert!(state_machine.is_poisoned());
+    }
+
+    #[test]
+    fn trap_backtrace() {
+        let module = from_wat!(
+            local,
+            r#"(module
+            (import "foo" "test" (func $test))
+            (func $crash
+                unreachable)
+            (func $_start
+                call $test
+                call $crash)
+            (export "_start" (func $_start)))
+        "#
+        );
+
+        let mut state_machine =
+            ProcessStateMachine::new(&module, &Default::default(), (), |_, _, _| Ok(1)).unwrap();
+        match state_machine.thread(0).unwrap().run(None) {
+            Ok(ExecOutcome::Interrupted { id: 1, .. }) => {}
+            _ => panic!(),
+        }
+        match state_machine.thread(0).unwrap().run(None) {
+            Ok(ExecOutcome::Errored { error, .. }) => assert_eq!(
+                error.backtrace(),
+                &[BacktraceFrame {
+                    function_index: 1,
+                    function_name: Some("crash".into()),
+                }][..]
+            ),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn breakpoint_at_function_start() {
+        let module = Module::from_bytes_debuggable(wat_to_bin!(
+            r#"(module
+            (import "foo" "test" (func $test))
+            (func $f
+                call $test)
+            (func $_start
+                call $f
+                call $f)
+            (export "_start" (func $_start)))
+        "#
+        ))
+        .unwrap();
+
+        let mut state_machine =
+            ProcessStateMachine::new(&module, &Default::default(), (), |_, _, _| Ok(1)).unwrap();
+        assert!(state_machine.is_debuggable());
+        state_machine.add_breakpoint(1);
+        match state_machine.thread(0).unwrap().run(None) {
+            Ok(ExecOutcome::Breakpoint {
+                function_index: 1,
+                instruction: 0,
+                ..
+            }) => {}
+            _ => panic!(),
+        }
+        match state_machine.thread(0).unwrap().run(None) {
+            Ok(ExecOutcome::Interrupted { id: 1, .. }) => {}
+            _ => panic!(),
+        }
+        state_machine.remove_breakpoint(1);
+        match state_machine.thread(0).unwrap().run(None) {
+            Ok(ExecOutcome::Interrupted { id: 1, .. }) => {}
+            _ => panic!(),
+        }
+        match state_machine.thread(0).unwrap().run(None) {
+            Ok(ExecOutcome::ThreadFinished { .. }) => {}
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn single_step() {
+        let module = Module::from_bytes_debuggable(wat_to_bin!(
+            r#"(module
+            (func $_start
+                i32.const 1
+                drop)
+            (export "_start" (func $_start)))
+        "#
+        ))
+        .unwrap();
+
+        let mut state_machine =
+            ProcessStateMachine::new(&module, &Default::default(), (), |_, _, _| Err(None))
+                .unwrap();
+        state_machine.set_single_step(true);
+        // The final `end` of the function is an instruction as well.
+        for expected in 0..3 {
+            match state_machine.thread(0).unwrap().run(None) {
+                Ok(ExecOutcome::Breakpoint {
+                    function_index: 0,
+                    instruction,
+                    ..
+                }) => assert_eq!(instruction, expected),
+                _ => panic!(),
+            }
+        }
+        match state_machine.thread(0).unwrap().run(None) {
+            Ok(ExecOutcome::ThreadFinished { .. }) => {}
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn breakpoints_ignored_if_not_debuggable() {
+        let module = from_wat!(
+            local,
+            r#"(module
+            (func $_start)
+            (export "_start" (func $_start)))
+        "#
+        );
+
+        let mut state_machine =
+            ProcessStateMachine::new(&module, &Default::default(), (), |_, _, _| Err(None))
+                .unwrap();
+        assert!(!state_machine.is_debuggable());
+        state_machine.set_single_step(true);
+        state_machine.add_breakpoint(0);
+        match state_machine.thread(0).unwrap().run(None) {
+            Ok(ExecOutcome::ThreadFinished { .. }) => {}
+            _ => panic!(),
+        }
+    }
+
     // TODO: start mutiple threads
 }
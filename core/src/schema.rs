@@ -0,0 +1,411 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Description of the messages of an interface, and comparison between two versions of it.
+//!
+//! An [`InterfaceSchema`] lists the messages that can be sent on an interface, in the order in
+//! which they are declared in the enum that is encoded on the wire. Calling [`compare`] on two
+//! versions of the same interface reports whether a client and a provider built against
+//! different versions can still understand each other.
+//!
+//! Messages are encoded with the SCALE codec, which identifies the variants of an enum by their
+//! position and doesn't tolerate unknown trailing bytes. Consequently:
+//!
+//! - Renaming messages or fields doesn't change the wire format.
+//! - Adding messages at the end of the list is backwards-compatible, as the existing messages
+//! keep their position.
+//! - Removing or reordering messages, or adding, removing, or changing the type of fields, is
+//! a breaking change.
+//!
+//! # Text format
+//!
+//! [`InterfaceSchema::from_text`] parses a schema written with one message per line, in the
+//! form `Name(field: Type, other_field: Type)`. The parentheses can be omitted for messages
+//! without any field. Empty lines and lines starting with `#` are ignored.
+//!
+//! ```
+//! use redshirt_core::schema::{compare, InterfaceSchema, VersionBump};
+//!
+//! let old = InterfaceSchema::from_text("Generate(len: u16)").unwrap();
+//! let new = InterfaceSchema::from_text("Generate(len: u16)\nReseed").unwrap();
+//! assert_eq!(compare(&old, &new).bump(), VersionBump::Minor);
+//! ```
+
+use alloc::{string::String, vec::Vec};
+use core::fmt;
+
+/// Description of the messages of an interface.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InterfaceSchema {
+    /// List of messages, in the order of their encoding.
+    pub messages: Vec<MessageSchema>,
+}
+
+/// Description of a message within an [`InterfaceSchema`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MessageSchema {
+    /// Name of the message.
+    pub name: String,
+    /// List of fields of the message, in the order of their encoding.
+    pub fields: Vec<FieldSchema>,
+}
+
+/// Description of a field of a [`MessageSchema`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldSchema {
+    /// Name of the field.
+    pub name: String,
+    /// Rust type of the field. Two fields are considered to have the same type if their types
+    /// are equal after removing all whitespaces.
+    pub ty: String,
+}
+
+/// Error that can happen when calling [`InterfaceSchema::from_text`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseErr {
+    /// Line where the error happened, starting from 1.
+    pub line: usize,
+}
+
+/// Result of comparing two versions of an interface with [`compare`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompatReport {
+    changes: Vec<Change>,
+}
+
+/// Difference between two versions of an interface.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    /// A message has been added at the end of the list.
+    MessageAdded {
+        /// Name of the message in the new version.
+        name: String,
+    },
+    /// A message has been removed from the end of the list.
+    MessageRemoved {
+        /// Name of the message in the old version.
+        name: String,
+    },
+    /// A message has been renamed, without any change to its fields.
+    MessageRenamed {
+        /// Name of the message in the old version.
+        old: String,
+        /// Name of the message in the new version.
+        new: String,
+    },
+    /// A field has been renamed, without any change to its type.
+    FieldRenamed {
+        /// Name of the message in the new version.
+        message: String,
+        /// Name of the field in the old version.
+        old: String,
+        /// Name of the field in the new version.
+        new: String,
+    },
+    /// Fields have been added, removed, reordered, or have changed type. Also reported when
+    /// messages have been reordered or removed from the middle of the list.
+    FieldsChanged {
+        /// Name of the message in the new version.
+        message: String,
+    },
+}
+
+/// Version bump that a change in an interface requires.
+///
+/// Ordered from the least to the most important.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum VersionBump {
+    /// The interface hasn't changed.
+    None,
+    /// Only names have changed. The wire format is the same.
+    Patch,
+    /// Messages have been added. Clients built against the old version can talk to providers
+    /// built against the new version.
+    Minor,
+    /// The wire format has changed in an incompatible way.
+    Major,
+}
+
+impl InterfaceSchema {
+    /// Parses a schema written in the text format described in the module-level documentation.
+    pub fn from_text(text: &str) -> Result<Self, ParseErr> {
+        let mut messages = Vec::new();
+
+        for (line_num, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let err = ParseErr { line: line_num + 1 };
+            let (name, fields) = match line.find('(') {
+                Some(pos) if line.ends_with(')') => (&line[..pos], &line[pos + 1..line.len() - 1]),
+                Some(_) => return Err(err),
+                None => (line, ""),
+            };
+
+            let name = name.trim();
+            if !is_identifier(name) {
+                return Err(err);
+            }
+
+            let fields = split_top_level(fields)
+                .into_iter()
+                .map(|field| {
+                    let pos = field.find(':').ok_or_else(|| err.clone())?;
+                    let (name, ty) = (field[..pos].trim(), field[pos + 1..].trim());
+                    if !is_identifier(name) || ty.is_empty() {
+                        return Err(err.clone());
+                    }
+                    Ok(FieldSchema {
+                        name: name.into(),
+                        ty: ty.into(),
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+
+            messages.push(MessageSchema {
+                name: name.into(),
+                fields,
+            });
+        }
+
+        Ok(InterfaceSchema { messages })
+    }
+}
+
+/// Compares two versions of an interface.
+pub fn compare(old: &InterfaceSchema, new: &InterfaceSchema) -> CompatReport {
+    let mut changes = Vec::new();
+
+    for (old_msg, new_msg) in old.messages.iter().zip(new.messages.iter()) {
+        let same_types = old_msg.fields.len() == new_msg.fields.len()
+            && old_msg
+                .fields
+                .iter()
+                .zip(new_msg.fields.iter())
+                .all(|(a, b)| same_type(&a.ty, &b.ty));
+        if !same_types {
+            changes.push(Change::FieldsChanged {
+                message: new_msg.name.clone(),
+            });
+            continue;
+        }
+
+        if old_msg.name != new_msg.name {
+            changes.push(Change::MessageRenamed {
+                old: old_msg.name.clone(),
+                new: new_msg.name.clone(),
+            });
+        }
+
+        for (old_field, new_field) in old_msg.fields.iter().zip(new_msg.fields.iter()) {
+            if old_field.name != new_field.name {
+                changes.push(Change::FieldRenamed {
+                    message: new_msg.name.clone(),
+                    old: old_field.name.clone(),
+                    new: new_field.name.clone(),
+                });
+            }
+        }
+    }
+
+    for removed in old.messages.iter().skip(new.messages.len()) {
+        changes.push(Change::MessageRemoved {
+            name: removed.name.clone(),
+        });
+    }
+
+    for added in new.messages.iter().skip(old.messages.len()) {
+        changes.push(Change::MessageAdded {
+            name: added.name.clone(),
+        });
+    }
+
+    CompatReport { changes }
+}
+
+impl CompatReport {
+    /// Returns the list of differences between the two versions.
+    pub fn changes(&self) -> &[Change] {
+        &self.changes
+    }
+
+    /// Returns the version bump that the differences require.
+    pub fn bump(&self) -> VersionBump {
+        self.changes
+            .iter()
+            .map(Change::bump)
+            .max()
+            .unwrap_or(VersionBump::None)
+    }
+
+    /// Returns true if clients built against the old version can talk to providers built
+    /// against the new version.
+    pub fn is_wire_compatible(&self) -> bool {
+        self.bump() < VersionBump::Major
+    }
+}
+
+impl Change {
+    /// Returns the version bump that this change requires.
+    pub fn bump(&self) -> VersionBump {
+        match self {
+            Change::MessageRenamed { .. } | Change::FieldRenamed { .. } => VersionBump::Patch,
+            Change::MessageAdded { .. } => VersionBump::Minor,
+            Change::MessageRemoved { .. } | Change::FieldsChanged { .. } => VersionBump::Major,
+        }
+    }
+}
+
+impl fmt::Display for ParseErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid message definition at line {}", self.line)
+    }
+}
+
+impl fmt::Display for CompatReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?} version bump required", self.bump())?;
+        for change in &self.changes {
+            write!(f, "\n- {}", change)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Change::MessageAdded { name } => write!(f, "added message `{}`", name),
+            Change::MessageRemoved { name } => write!(f, "removed message `{}`", name),
+            Change::MessageRenamed { old, new } => {
+                write!(f, "renamed message `{}` to `{}`", old, new)
+            }
+            Change::FieldRenamed { message, old, new } => {
+                write!(f, "renamed field `{}` of `{}` to `{}`", old, message, new)
+            }
+            Change::FieldsChanged { message } => {
+                write!(f, "incompatible fields in message `{}`", message)
+            }
+        }
+    }
+}
+
+/// Returns true if `s` is a valid Rust identifier.
+fn is_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) if c.is_alphabetic() || c == '_' => {}
+        _ => return false,
+    }
+    chars.all(|c| c.is_alphanumeric() || c == '_')
+}
+
+/// Returns true if the two types are equal after removing all whitespaces.
+fn same_type(a: &str, b: &str) -> bool {
+    a.chars()
+        .filter(|c| !c.is_whitespace())
+        .eq(b.chars().filter(|c| !c.is_whitespace()))
+}
+
+/// Splits the list of fields on the commas that aren't within brackets.
+fn split_top_level(fields: &str) -> Vec<&str> {
+    let mut out = Vec::new();
+    let mut depth = 0u32;
+    let mut start = 0;
+
+    for (pos, c) in fields.char_indices() {
+        match c {
+            '(' | '[' | '<' | '{' => depth += 1,
+            ')' | ']' | '>' | '}' => depth = depth.saturating_sub(1),
+            ',' if depth == 0 => {
+                out.push(&fields[start..pos]);
+                start = pos + 1;
+            }
+            _ => {}
+        }
+    }
+
+    out.push(&fields[start..]);
+    // Allow a trailing comma, and messages without any field.
+    out.retain(|f| !f.trim().is_empty());
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compare, Change, InterfaceSchema, VersionBump};
+
+    #[test]
+    fn parse() {
+        let schema = InterfaceSchema::from_text(
+            "# Comment\n\nConnect(addr: [u8; 16], port: u16)\nClose\nWrite(data: HashMap<u32, Vec<u8>>,)",
+        )
+        .unwrap();
+        assert_eq!(schema.messages.len(), 3);
+        assert_eq!(schema.messages[0].fields[0].ty, "[u8; 16]");
+        assert!(schema.messages[1].fields.is_empty());
+        assert_eq!(schema.messages[2].fields.len(), 1);
+
+        assert_eq!(
+            InterfaceSchema::from_text("Ok\nBad(x)").unwrap_err().line,
+            2
+        );
+    }
+
+    #[test]
+    fn version_bumps() {
+        let check = |old: &str, new: &str| {
+            compare(
+                &InterfaceSchema::from_text(old).unwrap(),
+                &InterfaceSchema::from_text(new).unwrap(),
+            )
+            .bump()
+        };
+
+        assert_eq!(check("A(x: u8)", "A(x: u8)"), VersionBump::None);
+        assert_eq!(
+            check("A(x: Vec<u8>)", "B(y: Vec< u8 >)"),
+            VersionBump::Patch
+        );
+        assert_eq!(check("A(x: u8)", "A(x: u8)\nB"), VersionBump::Minor);
+        assert_eq!(check("A(x: u8)\nB", "A(x: u8)"), VersionBump::Major);
+        assert_eq!(check("A(x: u8)", "A(x: u8, y: u8)"), VersionBump::Major);
+        assert_eq!(check("A\nB(x: u8)", "B(x: u8)\nA"), VersionBump::Major);
+    }
+
+    #[test]
+    fn report_lists_changes() {
+        let report = compare(
+            &InterfaceSchema::from_text("Open(path: String)").unwrap(),
+            &InterfaceSchema::from_text("Open(file: String)\nClose").unwrap(),
+        );
+        assert!(report.is_wire_compatible());
+        assert_eq!(
+            report.changes(),
+            &[
+                Change::FieldRenamed {
+                    message: "Open".into(),
+                    old: "path".into(),
+                    new: "file".into(),
+                },
+                Change::MessageAdded {
+                    name: "Close".into()
+                },
+            ]
+        );
+    }
+}
@@ -15,7 +15,6 @@
 
 use crate::ValueType;
 
-use alloc::vec::Vec;
 use smallvec::SmallVec;
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -66,37 +65,11 @@ impl Signature {
         &self.ret_ty
     }
 
-    pub(crate) fn matches_wasmi(&self, sig: &wasmi::Signature) -> bool {
-        wasmi::Signature::from(self) == *sig
-    }
-}
-
-impl<'a> From<&'a Signature> for wasmi::Signature {
-    fn from(sig: &'a Signature) -> wasmi::Signature {
-        wasmi::Signature::new(
-            sig.params
-                .iter()
-                .cloned()
-                .map(wasmi::ValueType::from)
-                .collect::<Vec<_>>(),
-            sig.ret_ty.map(wasmi::ValueType::from),
+    /// Converts a signature of the interpreter.
+    pub(crate) fn from_wasmi(sig: &wasmi::Signature) -> Signature {
+        Signature::new(
+            sig.params().iter().cloned().map(ValueType::from_wasmi),
+            sig.return_type().map(ValueType::from_wasmi),
         )
     }
 }
-
-impl From<Signature> for wasmi::Signature {
-    fn from(sig: Signature) -> wasmi::Signature {
-        wasmi::Signature::from(&sig)
-    }
-}
-
-impl From<ValueType> for wasmi::ValueType {
-    fn from(ty: ValueType) -> wasmi::ValueType {
-        match ty {
-            ValueType::I32 => wasmi::ValueType::I32,
-            ValueType::I64 => wasmi::ValueType::I64,
-            ValueType::F32 => wasmi::ValueType::F32,
-            ValueType::F64 => wasmi::ValueType::F64,
-        }
-    }
-}
@@ -13,22 +13,35 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use crate::module::{Module, ModuleHash};
+use crate::instrumentation::{coverage::CoverageCollector, Instrumentation, InstrumentationPass};
+use crate::module::{AbiReport, Module, ModuleHash};
 use crate::native::{self, NativeProgramMessageIdWrite as _};
-use crate::scheduler::{Core, CoreBuilder, CoreRunOutcome, NewErr};
+use crate::scheduler::{
+    Core, CoreBuilder, CorePreparedProcess, CoreRunOutcome, InboxConfig, MemorySnapshot,
+    ModuleLimits, NewErr, ProcessConfig, ResourceUsage, SelfCheckConfig, Violation,
+};
+use crate::TrapError;
 
-use alloc::vec::Vec;
+use alloc::{boxed::Box, collections::VecDeque, vec::Vec};
 use core::{cell::RefCell, iter, num::NonZeroU64, sync::atomic, task::Poll};
 use crossbeam_queue::SegQueue;
+use fnv::FnvBuildHasher;
 use futures::prelude::*;
-use hashbrown::HashSet;
+use hashbrown::{HashMap, HashSet};
 use nohash_hasher::BuildNoHashHasher;
-use redshirt_syscalls::{Decode, Encode, MessageId, Pid};
+use redshirt_syscalls::{crash::CrashReport, Decode, Encode, InterfaceHash, MessageId, Pid};
+
+pub use self::programs::ProgramsRegistry;
+pub use redshirt_lifecycle_interface::ffi::StopReason;
+pub use redshirt_process_management_interface::ffi::ExitStatus;
+
+mod programs;
 
 /// Main struct that handles a system, including the scheduler, program loader,
 /// inter-process communication, and so on.
 ///
-/// Natively handles the "interface" interface.  TODO: indicate hashes
+/// Natively handles the "interface", "interface-registry", "perf-self", "lifecycle", "crash",
+/// "process-management" and "spawn" interfaces.  TODO: indicate hashes
 pub struct System<'a> {
     /// Inner system with inter-process communications.
     core: Core,
@@ -51,7 +64,78 @@ pub struct System<'a> {
     /// Set of messages that we emitted of requests to load a program from the loader interface.
     /// All these messages expect a `redshirt_loader_interface::ffi::LoadResponse` as answer.
     // TODO: call shink_to_fit from time to time
-    loading_programs: RefCell<HashSet<MessageId, BuildNoHashHasher<u64>>>,
+    loading_programs: RefCell<HashMap<MessageId, ModuleHash, BuildNoHashHasher<u64>>>,
+
+    /// Messages that we emitted towards the loader in order to handle messages on the `spawn`
+    /// interface. Values are the message on the `spawn` interface to answer, and its emitter.
+    // TODO: call shink_to_fit from time to time
+    spawning_programs: RefCell<HashMap<MessageId, (MessageId, Pid), BuildNoHashHasher<u64>>>,
+
+    /// Passes applied on the programs loaded through the loader interface.
+    instrumentation: Instrumentation,
+
+    /// Limits checked against the programs loaded through the loader or spawn interfaces, before
+    /// they are parsed. Also passed to the [`Core`].
+    module_limits: ModuleLimits,
+
+    /// If `Some`, notified of the processes starting and finishing.
+    coverage: Option<CoverageCollector>,
+
+    /// If `Some`, notified of the processes starting and finishing.
+    programs_registry: Option<ProgramsRegistry>,
+
+    /// If `Some`, the invariants of the core are periodically verified.
+    self_check: Option<SelfCheckConfig>,
+
+    /// If `Some`, only the modules signed by one of these public keys can be executed.
+    trusted_keys: Option<HashSet<[u8; 32], FnvBuildHasher>>,
+
+    /// Number of iterations of the main loop of [`System::run`]. Used to determine when to
+    /// perform the self-checks.
+    run_iterations: atomic::AtomicU32,
+
+    /// Pools of pre-instantiated processes, indexed by the hash of their module.
+    spawn_templates: RefCell<HashMap<ModuleHash, SpawnTemplate, FnvBuildHasher>>,
+
+    /// Processes waiting on the `lifecycle` interface to be asked to stop, and the message to
+    /// answer when that happens.
+    stop_waiters: RefCell<HashMap<Pid, MessageId, BuildNoHashHasher<u64>>>,
+
+    /// Processes that have been asked to stop with [`System::request_stop`] and that haven't
+    /// been aborted yet.
+    stopping:
+        RefCell<HashMap<Pid, redshirt_lifecycle_interface::StopRequest, BuildNoHashHasher<u64>>>,
+
+    /// Crash reports sent by processes that haven't finished yet. Reported as part of
+    /// [`SystemRunOutcome::ProgramFinished`].
+    crash_reports: RefCell<HashMap<Pid, CrashReport, BuildNoHashHasher<u64>>>,
+
+    /// Messages received on the `process-management` interface from programs waiting for a
+    /// process to end, indexed by the process being waited for. Answered with its
+    /// [`ExitStatus`].
+    process_waiters: RefCell<HashMap<Pid, Vec<MessageId>, BuildNoHashHasher<u64>>>,
+
+    /// Messages received on the `interface-registry` interface from programs waiting for the
+    /// handler of an interface to change, indexed by interface. Answered with the new handler.
+    interface_watchers: RefCell<HashMap<InterfaceHash, Vec<MessageId>, FnvBuildHasher>>,
+
+    /// If `Some`, returns the current value of the monotonic clock in nanoseconds. Used to
+    /// enforce the deadlines of the stop requests.
+    monotonic_clock: Option<Box<dyn Fn() -> u64 + Send + 'a>>,
+
+    /// Time, in nanoseconds, that the processes killed through the `process-management`
+    /// interface are given to stop. Only relevant if `monotonic_clock` is `Some`.
+    kill_grace_period: u64,
+}
+
+/// Pool of processes instantiated in advance, ready to be started by [`System::execute`].
+struct SpawnTemplate {
+    /// Module the processes are instantiated from.
+    module: Module,
+    /// Number of processes that we try to keep in `ready`.
+    pool_size: usize,
+    /// Processes ready to be started.
+    ready: VecDeque<CorePreparedProcess>,
 }
 
 /// Prototype for a [`System`].
@@ -65,6 +149,24 @@ pub struct SystemBuilder<'a> {
     /// "Virtual" pid for handling messages on the `interface` interface.
     interface_interface_pid: Pid,
 
+    /// "Virtual" pid for handling messages on the `interface-registry` interface.
+    interface_registry_interface_pid: Pid,
+
+    /// "Virtual" pid for handling messages on the `perf-self` interface.
+    perf_self_interface_pid: Pid,
+
+    /// "Virtual" pid for handling messages on the `lifecycle` interface.
+    lifecycle_interface_pid: Pid,
+
+    /// "Virtual" pid for handling messages on the `crash` interface.
+    crash_interface_pid: Pid,
+
+    /// "Virtual" pid for handling messages on the `process-management` interface.
+    process_management_interface_pid: Pid,
+
+    /// "Virtual" pid for handling messages on the `spawn` interface.
+    spawn_interface_pid: Pid,
+
     /// "Virtual" pid for the process that sends messages towards the loader.
     load_source_virtual_pid: Pid,
 
@@ -73,6 +175,33 @@ pub struct SystemBuilder<'a> {
 
     /// Same field as [`System::programs_to_load`].
     programs_to_load: SegQueue<ModuleHash>,
+
+    /// Same field as [`System::instrumentation`].
+    instrumentation: Instrumentation,
+
+    /// Same field as [`System::module_limits`].
+    module_limits: ModuleLimits,
+
+    /// Same field as [`System::coverage`].
+    coverage: Option<CoverageCollector>,
+
+    /// Same field as [`System::programs_registry`].
+    programs_registry: Option<ProgramsRegistry>,
+
+    /// Same field as [`System::self_check`].
+    self_check: Option<SelfCheckConfig>,
+
+    /// Same field as [`System::trusted_keys`].
+    trusted_keys: Option<HashSet<[u8; 32], FnvBuildHasher>>,
+
+    /// Modules passed to [`SystemBuilder::with_spawn_template`], and the size of their pool.
+    spawn_templates: Vec<(Module, usize)>,
+
+    /// Same field as [`System::monotonic_clock`].
+    monotonic_clock: Option<Box<dyn Fn() -> u64 + Send + 'a>>,
+
+    /// Same field as [`System::kill_grace_period`].
+    kill_grace_period: u64,
 }
 
 /// Outcome of running the [`System`] once.
@@ -84,9 +213,45 @@ pub enum SystemRunOutcome {
         pid: Pid,
         /// Either `Ok(())` if the main thread has ended, or the error that happened in the
         /// process.
-        // TODO: change error type
-        outcome: Result<(), wasmi::Error>,
+        outcome: Result<(), TrapError>,
+        /// Report sent by the process on the `crash` interface before stopping, if any. This
+        /// normally contains the message and location of the panic that has stopped it.
+        crash_report: Option<CrashReport>,
+        /// How the process has ended. This is what the programs waiting for the end of the
+        /// process receive.
+        exit_status: ExitStatus,
     },
+
+    /// The periodic self-check enabled with [`SystemBuilder::with_self_check`] has found
+    /// invariants of the system that are violated.
+    ///
+    /// The [`System`] continues to run normally afterwards.
+    SelfCheckFailed {
+        /// List of problems that have been found.
+        violations: Vec<Violation>,
+    },
+
+    /// A program passed to [`SystemBuilder::with_main_program`] couldn't be started, and has
+    /// been dropped.
+    ///
+    /// The [`System`] continues to run normally afterwards.
+    ProgramLoadFailed {
+        /// Hash of the program that was requested from the `loader` interface.
+        hash: ModuleHash,
+        /// Reason why the program couldn't be started.
+        error: LoadError,
+    },
+}
+
+/// Reason why a program passed to [`SystemBuilder::with_main_program`] couldn't be started.
+#[derive(Debug)]
+pub enum LoadError {
+    /// The handler of the `loader` interface couldn't provide the program.
+    NotFound,
+    /// The program provided by the `loader` interface isn't a valid Wasm module.
+    InvalidModule,
+    /// Starting the program has failed, for example because it isn't signed by a trusted key.
+    Start(NewErr),
 }
 
 #[derive(Debug)]
@@ -95,12 +260,73 @@ enum RunOnceOutcome {
     Idle,
     LoopAgain,
     LoopAgainNow,
+    /// A thread has run out of fuel. We should yield back to the executor before running again.
+    Preempted,
 }
 
 impl<'a> System<'a> {
     /// Start executing a program.
+    ///
+    /// If a spawn template has been registered for this module with
+    /// [`SystemBuilder::with_spawn_template`], one of its pre-instantiated processes is used.
+    /// The pool is then refilled while the [`System`] is idle.
+    ///
+    /// Returns [`NewErr::UntrustedModule`] if [`SystemBuilder::with_trusted_keys`] has been
+    /// called and the module isn't signed by one of the trusted keys.
     pub fn execute(&self, program: &Module) -> Result<Pid, NewErr> {
-        Ok(self.core.execute(program)?.pid())
+        self.execute_inner(program, None, None)
+    }
+
+    /// Same as [`System::execute`], but the process can only emit messages on the given
+    /// interfaces, and on the ones that other processes later delegate to it.
+    pub fn execute_with_capabilities(
+        &self,
+        program: &Module,
+        capabilities: impl IntoIterator<Item = InterfaceHash>,
+    ) -> Result<Pid, NewErr> {
+        self.execute_inner(program, Some(capabilities.into_iter().collect()), None)
+    }
+
+    /// Implementation of [`System::execute`] and [`System::execute_with_capabilities`]. If
+    /// `parent` is `Some`, the new process is a child of the given process.
+    fn execute_inner(
+        &self,
+        program: &Module,
+        capabilities: Option<Vec<InterfaceHash>>,
+        parent: Option<Pid>,
+    ) -> Result<Pid, NewErr> {
+        check_trusted(&self.trusted_keys, program)?;
+        let prepared = self
+            .spawn_templates
+            .borrow_mut()
+            .get_mut(program.hash())
+            .and_then(|template| template.ready.pop_front());
+        let mut prepared = match prepared {
+            Some(prepared) => prepared,
+            None => self.core.prepare(program)?,
+        };
+        if let Some(capabilities) = capabilities {
+            prepared = prepared.with_capabilities(capabilities);
+        }
+        let pid = match parent {
+            Some(parent) => self.core.execute_prepared_child(prepared, parent).pid(),
+            None => self.core.execute_prepared(prepared).pid(),
+        };
+        if let Some(coverage) = &self.coverage {
+            coverage.process_started(pid, program.hash());
+        }
+        if let Some(programs_registry) = &self.programs_registry {
+            programs_registry.process_started(pid, program.hash());
+        }
+        Ok(pid)
+    }
+
+    /// Checks whether the given module is compatible with the functions provided by this
+    /// [`System`].
+    ///
+    /// Incompatible modules are refused by [`System::execute`].
+    pub fn abi_report(&self, program: &Module) -> AbiReport {
+        self.core.abi_report(program)
     }
 
     /// Runs the [`System`] once and returns the outcome.
@@ -121,12 +347,31 @@ impl<'a> System<'a> {
                         let message_id = self.core.emit_interface_message_answer(
                             self.load_source_virtual_pid,
                             redshirt_loader_interface::ffi::INTERFACE,
-                            redshirt_loader_interface::ffi::LoaderMessage::Load(From::from(hash)),
+                            redshirt_loader_interface::ffi::LoaderMessage::Load(From::from(
+                                hash.clone(),
+                            )),
                         );
-                        self.loading_programs.borrow_mut().insert(message_id);
+                        self.loading_programs.borrow_mut().insert(message_id, hash);
+                    }
+                }
+
+                if let Some(config) = &self.self_check {
+                    let iteration = self
+                        .run_iterations
+                        .fetch_add(1, atomic::Ordering::Relaxed)
+                        .wrapping_add(1);
+                    if iteration % config.period.max(1) == 0 {
+                        let violations = self.core.self_check(config);
+                        if !violations.is_empty() {
+                            return Poll::Ready(SystemRunOutcome::SelfCheckFailed { violations });
+                        }
                     }
                 }
 
+                if let Some(clock) = &self.monotonic_clock {
+                    self.enforce_stop_deadlines(clock());
+                }
+
                 let run_once_outcome = self.run_once();
 
                 if let RunOnceOutcome::Report(out) = run_once_outcome {
@@ -145,6 +390,16 @@ impl<'a> System<'a> {
                         if let RunOnceOutcome::LoopAgain = run_once_outcome {
                             continue;
                         }
+                        // A thread that never stops mustn't prevent the other tasks of the
+                        // executor from running. We ask to be polled again immediately.
+                        if let RunOnceOutcome::Preempted = run_once_outcome {
+                            cx.waker().wake_by_ref();
+                            return Poll::Pending;
+                        }
+                        // Nothing else to do. Use this time to refill the spawn templates.
+                        if self.refill_spawn_template() {
+                            continue;
+                        }
                         return Poll::Pending;
                     }
                 };
@@ -184,17 +439,194 @@ impl<'a> System<'a> {
         })
     }
 
+    /// Replaces the native program that has registered the given interface with a new one.
+    ///
+    /// The new program takes over all the interfaces registered by the old one, without any
+    /// message being lost. The messages that have already been delivered to the old program
+    /// are still answered by it, after which it is destroyed.
+    ///
+    /// This makes it possible to upgrade a native program without interrupting the programs
+    /// using its interfaces.
+    ///
+    /// Returns an error if no native program has registered this interface.
+    pub fn replace_native_program<T>(
+        &mut self,
+        interface: &InterfaceHash,
+        program: T,
+    ) -> Result<(), ()>
+    where
+        T: Send + 'a,
+        for<'r> &'r T: native::NativeProgramRef<'r>,
+    {
+        self.native_programs.replace(interface, program)
+    }
+
+    /// Changes the configuration of the inbox of the given process. Pass `None` for an
+    /// unbounded inbox.
+    ///
+    /// Returns an error if there is no process with this [`Pid`].
+    pub fn set_inbox_config(&self, pid: Pid, config: Option<InboxConfig>) -> Result<(), ()> {
+        self.core.set_inbox_config(pid, config)
+    }
+
+    /// Routes the messages that `emitter` emits on `interface` to `handler` rather than to the
+    /// registered handler of the interface. See [`Core::set_interface_override`].
+    ///
+    /// Returns an error if `emitter` or `handler` isn't a running process.
+    pub fn set_interface_override(
+        &self,
+        emitter: Pid,
+        interface: InterfaceHash,
+        handler: Pid,
+    ) -> Result<(), ()> {
+        self.core
+            .set_interface_override(emitter, interface, handler)
+    }
+
+    /// Removes an override added with [`System::set_interface_override`].
+    pub fn remove_interface_override(&self, emitter: Pid, interface: &InterfaceHash) {
+        self.core.remove_interface_override(emitter, interface);
+    }
+
+    /// Verifies the internal invariants of the system, and returns the list of violations.
+    ///
+    /// This is the same verification as the one enabled with [`SystemBuilder::with_self_check`],
+    /// except that it is performed immediately.
+    pub fn self_check(&self, config: &SelfCheckConfig) -> Vec<Violation> {
+        self.core.self_check(config)
+    }
+
+    /// Returns true if the program with the given [`Pid`] is still running. A [`Pid`] is never
+    /// assigned to more than one program.
+    pub fn is_process_alive(&self, pid: Pid) -> bool {
+        self.core.is_process_alive(pid)
+    }
+
+    /// Returns the resources used by all the programs.
+    pub fn resource_usage(&self) -> ResourceUsage {
+        self.core.resource_usage()
+    }
+
+    /// Captures a snapshot of the memory of the given process. Returns `None` if there is no
+    /// process with this [`Pid`].
+    ///
+    /// Capturing two snapshots of a program at different points in time and comparing them
+    /// with [`MemorySnapshot::diff`] shows which parts of its memory have changed and how much
+    /// it has grown, which helps with finding memory leaks.
+    pub fn memory_snapshot(&self, pid: Pid) -> Option<MemorySnapshot> {
+        Some(self.core.process_by_id(pid)?.memory_snapshot())
+    }
+
+    /// Asks the given process to stop.
+    ///
+    /// If the process is waiting for a stop request on the `lifecycle` interface, it is sent the
+    /// `reason` and `deadline`, then is aborted as soon as it indicates that it is ready to stop.
+    /// If it isn't waiting for a stop request, it is aborted immediately.
+    ///
+    /// The `deadline` is a value of the monotonic clock in nanoseconds. It is enforced by
+    /// [`System::run`] if a clock has been passed to [`SystemBuilder::with_monotonic_clock`], and
+    /// otherwise only when [`System::enforce_stop_deadlines`] is called.
+    ///
+    /// Returns an error if there is no process with this [`Pid`].
+    pub fn request_stop(&self, pid: Pid, reason: StopReason, deadline: u64) -> Result<(), ()> {
+        let process = self.core.process_by_id(pid).ok_or(())?;
+
+        let message_id = match self.stop_waiters.borrow_mut().remove(&pid) {
+            Some(m) => m,
+            None => {
+                if !self.stopping.borrow().contains_key(&pid) {
+                    process.abort();
+                }
+                return Ok(());
+            }
+        };
+
+        let request = redshirt_lifecycle_interface::StopRequest { reason, deadline };
+        self.core
+            .answer_message(message_id, Ok(request.clone().encode()));
+        self.stopping.borrow_mut().insert(pid, request);
+        Ok(())
+    }
+
+    /// Aborts the processes that have been asked to stop with [`System::request_stop`] and whose
+    /// deadline is inferior or equal to `now`.
+    ///
+    /// `now` is the current value of the monotonic clock in nanoseconds.
+    ///
+    /// > **Note**: This is done automatically by [`System::run`] if a clock has been passed to
+    /// >           [`SystemBuilder::with_monotonic_clock`].
+    pub fn enforce_stop_deadlines(&self, now: u64) {
+        let stopping = self.stopping.borrow();
+        for (pid, _) in stopping.iter().filter(|(_, rq)| rq.deadline <= now) {
+            if let Some(process) = self.core.process_by_id(*pid) {
+                process.abort();
+            }
+        }
+    }
+
+    /// Instantiates a process for one of the spawn templates whose pool isn't full. Returns
+    /// `false` if all the pools are full.
+    fn refill_spawn_template(&self) -> bool {
+        let mut spawn_templates = self.spawn_templates.borrow_mut();
+        let template = match spawn_templates
+            .values_mut()
+            .find(|t| t.ready.len() < t.pool_size)
+        {
+            Some(t) => t,
+            None => return false,
+        };
+
+        // The module has already been successfully instantiated when building the `System`.
+        match self.core.prepare(&template.module) {
+            Ok(prepared) => template.ready.push_back(prepared),
+            Err(_) => unreachable!(),
+        }
+        true
+    }
+
     fn run_once(&self) -> RunOnceOutcome {
         match self.core.run() {
             CoreRunOutcome::Idle => return RunOnceOutcome::Idle,
+            CoreRunOutcome::Preempted => return RunOnceOutcome::Preempted,
+
+            CoreRunOutcome::ProgramFinished {
+                pid,
+                outcome,
+                globals,
+                exit_status,
+                unregistered_interfaces,
+                ..
+            } => {
+                for interface in &unregistered_interfaces {
+                    self.interface_handler_changed(interface);
+                }
+                if let Some(coverage) = &self.coverage {
+                    coverage.process_finished(pid, &globals);
+                }
+                if let Some(programs_registry) = &self.programs_registry {
+                    programs_registry.process_finished(pid);
+                }
 
-            CoreRunOutcome::ProgramFinished { pid, outcome, .. } => {
                 self.loader_pid
                     .compare_and_swap(u64::from(pid), 0, atomic::Ordering::AcqRel);
+                self.stop_waiters.borrow_mut().remove(&pid);
+                self.stopping.borrow_mut().remove(&pid);
+                let crash_report = self.crash_reports.borrow_mut().remove(&pid);
+                if let Some(waiters) = self.process_waiters.borrow_mut().remove(&pid) {
+                    let response = redshirt_process_management_interface::ffi::WaitResponse {
+                        result: Ok(exit_status.clone()),
+                    }
+                    .encode();
+                    for message_id in waiters {
+                        self.core.answer_message(message_id, Ok(response.clone()));
+                    }
+                }
                 self.native_programs.process_destroyed(pid);
                 return RunOnceOutcome::Report(SystemRunOutcome::ProgramFinished {
                     pid,
-                    outcome: outcome.map(|_| ()).map_err(|err| err.into()),
+                    outcome: outcome.map(|_| ()),
+                    crash_report,
+                    exit_status,
                 });
             }
 
@@ -205,15 +637,62 @@ impl<'a> System<'a> {
                 response,
                 ..
             } => {
-                if self.loading_programs.borrow_mut().remove(&message_id) {
-                    let redshirt_loader_interface::ffi::LoadResponse { result } =
-                        Decode::decode(response.unwrap()).unwrap();
-                    // TODO: don't unwrap
-                    let module = Module::from_bytes(&result.expect("loader returned error"))
-                        .expect("module isn't proper wasm");
-                    match self.core.execute(&module) {
-                        Ok(_) => {}
-                        Err(_) => panic!(),
+                let spawning = self.spawning_programs.borrow_mut().remove(&message_id);
+                if let Some((spawn_message_id, emitter)) = spawning {
+                    // The emitter might have ended while the module was being loaded, in which
+                    // case its message no longer needs answering.
+                    if self.core.is_process_alive(emitter) {
+                        let result = match response
+                            .ok()
+                            .and_then(|r| {
+                                redshirt_loader_interface::ffi::LoadResponse::decode(r).ok()
+                            })
+                            .and_then(|r| r.result.ok())
+                        {
+                            Some(bytes) => self.spawn(&bytes, emitter),
+                            None => Err(redshirt_spawn_interface::ffi::SpawnError::NotFound),
+                        };
+                        let response = redshirt_spawn_interface::ffi::SpawnResponse { result };
+                        self.core
+                            .answer_message(spawn_message_id, Ok(response.encode()));
+                    }
+                } else if let Some(hash) = self.loading_programs.borrow_mut().remove(&message_id) {
+                    let bytes = match response
+                        .ok()
+                        .and_then(|r| redshirt_loader_interface::ffi::LoadResponse::decode(r).ok())
+                        .and_then(|r| r.result.ok())
+                    {
+                        Some(bytes) => bytes,
+                        None => {
+                            return RunOnceOutcome::Report(SystemRunOutcome::ProgramLoadFailed {
+                                hash,
+                                error: LoadError::NotFound,
+                            })
+                        }
+                    };
+                    if let Err(err) = self.module_limits.check(&bytes) {
+                        return RunOnceOutcome::Report(SystemRunOutcome::ProgramLoadFailed {
+                            hash,
+                            error: LoadError::Start(err),
+                        });
+                    }
+                    let module =
+                        match Module::from_bytes_instrumented(&bytes, &self.instrumentation) {
+                            Ok(m) => m,
+                            Err(_) => {
+                                return RunOnceOutcome::Report(
+                                    SystemRunOutcome::ProgramLoadFailed {
+                                        hash,
+                                        error: LoadError::InvalidModule,
+                                    },
+                                )
+                            }
+                        };
+                    if let Err(err) = self.execute(&module) {
+                        return RunOnceOutcome::Report(SystemRunOutcome::ProgramLoadFailed {
+                            hash,
+                            error: LoadError::Start(err),
+                        });
                     }
                 } else {
                     self.native_programs.message_response(message_id, response);
@@ -233,6 +712,9 @@ impl<'a> System<'a> {
                     )) => {
                         // Set the process as interface handler, if possible.
                         let result = self.core.set_interface_handler(interface_hash.clone(), pid);
+                        if result.is_ok() {
+                            self.interface_handler_changed(&interface_hash);
+                        }
                         let response =
                             redshirt_interface_interface::ffi::InterfaceRegisterResponse {
                                 result: result.clone().map_err(|()| redshirt_interface_interface::ffi::InterfaceRegisterError::AlreadyRegistered),
@@ -259,6 +741,267 @@ impl<'a> System<'a> {
                 }
             }
 
+            CoreRunOutcome::ReservedPidInterfaceMessage {
+                message_id,
+                interface,
+                message,
+                ..
+            } if interface == redshirt_interface_registry_interface::ffi::INTERFACE => {
+                // Handling messages on the `interface-registry` interface.
+                let message_id = match message_id {
+                    Some(m) => m,
+                    None => return RunOnceOutcome::LoopAgain,
+                };
+                match redshirt_interface_registry_interface::ffi::InterfaceRegistryMessage::decode(
+                    message,
+                ) {
+                    Ok(
+                        redshirt_interface_registry_interface::ffi::InterfaceRegistryMessage::Query(
+                            interface,
+                        ),
+                    ) => {
+                        let response = redshirt_interface_registry_interface::ffi::HandlerResponse {
+                            handler: self.core.interface_handler(&interface),
+                        };
+                        self.core.answer_message(message_id, Ok(response.encode()));
+                    }
+                    Ok(
+                        redshirt_interface_registry_interface::ffi::InterfaceRegistryMessage::WaitChange(
+                            interface,
+                            current,
+                        ),
+                    ) => {
+                        // Answered immediately if the handler is already different, otherwise
+                        // the next time it changes.
+                        let handler = self.core.interface_handler(&interface);
+                        if handler != current {
+                            let response =
+                                redshirt_interface_registry_interface::ffi::HandlerResponse {
+                                    handler,
+                                };
+                            self.core.answer_message(message_id, Ok(response.encode()));
+                        } else {
+                            self.interface_watchers
+                                .borrow_mut()
+                                .entry(interface)
+                                .or_insert_with(Vec::new)
+                                .push(message_id);
+                        }
+                    }
+                    Err(_) => self.core.answer_message(message_id, Err(())),
+                }
+            }
+
+            CoreRunOutcome::ReservedPidInterfaceMessage {
+                pid,
+                message_id,
+                interface,
+                message,
+            } if interface == redshirt_perf_self_interface::ffi::INTERFACE => {
+                // Handling messages on the `perf-self` interface. Messages emitted by native
+                // programs don't have any counter and are answered with an error.
+                let message_id = match message_id {
+                    Some(m) => m,
+                    None => return RunOnceOutcome::LoopAgain,
+                };
+                let counters = match self.core.process_by_id(pid) {
+                    Some(process) => process.counters(),
+                    None => {
+                        self.core.answer_message(message_id, Err(()));
+                        return RunOnceOutcome::LoopAgain;
+                    }
+                };
+                match redshirt_perf_self_interface::ffi::PerfSelfMessage::decode(message) {
+                    Ok(redshirt_perf_self_interface::ffi::PerfSelfMessage::Counters) => {
+                        let response = redshirt_perf_self_interface::ffi::Counters {
+                            messages_emitted: counters.messages_emitted,
+                            message_bytes_emitted: counters.message_bytes_emitted,
+                            notifications_received: counters.notifications_received,
+                            notification_bytes_received: counters.notification_bytes_received,
+                        };
+                        self.core.answer_message(message_id, Ok(response.encode()));
+                    }
+                    Err(_) => self.core.answer_message(message_id, Err(())),
+                }
+            }
+
+            CoreRunOutcome::ReservedPidInterfaceMessage {
+                pid,
+                message_id,
+                interface,
+                message,
+            } if interface == redshirt_syscalls::crash::INTERFACE => {
+                // Handling messages on the `crash` interface. Reports from native programs are
+                // ignored, as they never finish with a `ProgramFinished`.
+                if let Ok(report) = CrashReport::decode(message) {
+                    if self.core.process_by_id(pid).is_some() {
+                        self.crash_reports.borrow_mut().insert(pid, report);
+                    }
+                }
+                if let Some(message_id) = message_id {
+                    self.core.answer_message(message_id, Err(()));
+                }
+            }
+
+            CoreRunOutcome::ReservedPidInterfaceMessage {
+                pid,
+                message_id,
+                interface,
+                message,
+            } if interface == redshirt_lifecycle_interface::ffi::INTERFACE => {
+                // Handling messages on the `lifecycle` interface.
+                match redshirt_lifecycle_interface::ffi::LifecycleMessage::decode(message) {
+                    Ok(redshirt_lifecycle_interface::ffi::LifecycleMessage::WaitStop) => {
+                        let message_id = match message_id {
+                            Some(m) => m,
+                            None => return RunOnceOutcome::LoopAgain,
+                        };
+                        // Native programs can't be asked to stop.
+                        if self.core.process_by_id(pid).is_none() {
+                            self.core.answer_message(message_id, Err(()));
+                            return RunOnceOutcome::LoopAgain;
+                        }
+                        if let Some(request) = self.stopping.borrow().get(&pid) {
+                            self.core
+                                .answer_message(message_id, Ok(request.clone().encode()));
+                            return RunOnceOutcome::LoopAgain;
+                        }
+                        // A previous waiting message, if any, is never answered.
+                        self.stop_waiters.borrow_mut().insert(pid, message_id);
+                    }
+                    Ok(redshirt_lifecycle_interface::ffi::LifecycleMessage::StopReady) => {
+                        if self.stopping.borrow().contains_key(&pid) {
+                            if let Some(process) = self.core.process_by_id(pid) {
+                                process.abort();
+                            }
+                        }
+                    }
+                    Err(_) => {
+                        if let Some(message_id) = message_id {
+                            self.core.answer_message(message_id, Err(()));
+                        }
+                    }
+                }
+            }
+
+            CoreRunOutcome::ReservedPidInterfaceMessage {
+                message_id,
+                interface,
+                message,
+                ..
+            } if interface == redshirt_process_management_interface::ffi::INTERFACE => {
+                // Handling messages on the `process-management` interface. Killed processes are
+                // actually destroyed the next time the core runs, and their end is then reported
+                // as a `ProgramFinished`.
+                let message_id = match message_id {
+                    Some(m) => m,
+                    None => return RunOnceOutcome::LoopAgain,
+                };
+                match redshirt_process_management_interface::ffi::ProcessManagementMessage::decode(
+                    message,
+                ) {
+                    Ok(
+                        redshirt_process_management_interface::ffi::ProcessManagementMessage::Kill(
+                            target,
+                        ),
+                    ) => {
+                        // Without a clock, the process can't be given time to stop.
+                        let result = match &self.monotonic_clock {
+                            Some(clock) => {
+                                let deadline = clock().saturating_add(self.kill_grace_period);
+                                self.request_stop(target, StopReason::Kill, deadline)
+                            }
+                            None => self
+                                .core
+                                .process_by_id(target)
+                                .map(|process| process.abort())
+                                .ok_or(()),
+                        }
+                        .map_err(|()| {
+                            redshirt_process_management_interface::ffi::KillError::NotFound
+                        });
+                        let response =
+                            redshirt_process_management_interface::ffi::KillResponse { result };
+                        self.core.answer_message(message_id, Ok(response.encode()));
+                    }
+                    Ok(
+                        redshirt_process_management_interface::ffi::ProcessManagementMessage::Wait(
+                            target,
+                        ),
+                    ) => {
+                        // Answered when the process finishes.
+                        if self.core.process_by_id(target).is_some() {
+                            self.process_waiters
+                                .borrow_mut()
+                                .entry(target)
+                                .or_insert_with(Vec::new)
+                                .push(message_id);
+                        } else {
+                            let response =
+                                redshirt_process_management_interface::ffi::WaitResponse {
+                                    result: Err(
+                                        redshirt_process_management_interface::ffi::WaitError::NotFound,
+                                    ),
+                                };
+                            self.core.answer_message(message_id, Ok(response.encode()));
+                        }
+                    }
+                    Err(_) => self.core.answer_message(message_id, Err(())),
+                }
+            }
+
+            CoreRunOutcome::ReservedPidInterfaceMessage {
+                pid,
+                message_id,
+                interface,
+                message,
+            } if interface == redshirt_spawn_interface::ffi::INTERFACE => {
+                // Handling messages on the `spawn` interface. Modules passed by hash are first
+                // fetched from the loader, and the message is answered once they are.
+                let message_id = match message_id {
+                    Some(m) => m,
+                    None => return RunOnceOutcome::LoopAgain,
+                };
+                match redshirt_spawn_interface::ffi::SpawnMessage::decode(message) {
+                    Ok(redshirt_spawn_interface::ffi::SpawnMessage::Spawn { args, .. })
+                        if !args.is_empty() =>
+                    {
+                        // TODO: pass the arguments to the new process instead
+                        let response = redshirt_spawn_interface::ffi::SpawnResponse {
+                            result: Err(
+                                redshirt_spawn_interface::ffi::SpawnError::ArgumentsNotSupported,
+                            ),
+                        };
+                        self.core.answer_message(message_id, Ok(response.encode()));
+                    }
+                    Ok(redshirt_spawn_interface::ffi::SpawnMessage::Spawn { module, .. }) => {
+                        let result = match module {
+                            redshirt_spawn_interface::ffi::ModuleSource::Bytes(bytes) => {
+                                self.spawn(&bytes, pid)
+                            }
+                            redshirt_spawn_interface::ffi::ModuleSource::Hash(hash) => {
+                                if self.loader_pid.load(atomic::Ordering::Relaxed) != 0 {
+                                    let load_message_id = self.core.emit_interface_message_answer(
+                                        self.load_source_virtual_pid,
+                                        redshirt_loader_interface::ffi::INTERFACE,
+                                        redshirt_loader_interface::ffi::LoaderMessage::Load(hash),
+                                    );
+                                    self.spawning_programs
+                                        .borrow_mut()
+                                        .insert(load_message_id, (message_id, pid));
+                                    return RunOnceOutcome::LoopAgain;
+                                }
+
+                                Err(redshirt_spawn_interface::ffi::SpawnError::NotFound)
+                            }
+                        };
+                        let response = redshirt_spawn_interface::ffi::SpawnResponse { result };
+                        self.core.answer_message(message_id, Ok(response.encode()));
+                    }
+                    Err(_) => self.core.answer_message(message_id, Err(())),
+                }
+            }
+
             CoreRunOutcome::ReservedPidInterfaceMessage {
                 pid,
                 message_id,
@@ -272,6 +1015,48 @@ impl<'a> System<'a> {
 
         RunOnceOutcome::LoopAgain
     }
+
+    /// Starts a process from the given module, as a child of `parent`, on behalf of a message on
+    /// the `spawn` interface. The new process is restricted to the same interfaces as `parent`.
+    fn spawn(
+        &self,
+        module: &[u8],
+        parent: Pid,
+    ) -> Result<Pid, redshirt_spawn_interface::ffi::SpawnError> {
+        self.module_limits
+            .check(module)
+            .map_err(|_| redshirt_spawn_interface::ffi::SpawnError::InvalidModule)?;
+        let module = Module::from_bytes_instrumented(module, &self.instrumentation)
+            .map_err(|_| redshirt_spawn_interface::ffi::SpawnError::InvalidModule)?;
+        let capabilities = self
+            .core
+            .process_by_id(parent)
+            .and_then(|p| p.capabilities());
+        match self.execute_inner(&module, capabilities, Some(parent)) {
+            Ok(pid) => Ok(pid),
+            Err(NewErr::UntrustedModule) => {
+                Err(redshirt_spawn_interface::ffi::SpawnError::Untrusted)
+            }
+            Err(_) => Err(redshirt_spawn_interface::ffi::SpawnError::InvalidModule),
+        }
+    }
+
+    /// Answers the messages on the `interface-registry` interface that are waiting for the
+    /// handler of the given interface to change. Must be called after the handler has changed.
+    fn interface_handler_changed(&self, interface: &InterfaceHash) {
+        let watchers = match self.interface_watchers.borrow_mut().remove(interface) {
+            Some(w) => w,
+            None => return,
+        };
+
+        let response = redshirt_interface_registry_interface::ffi::HandlerResponse {
+            handler: self.core.interface_handler(interface),
+        }
+        .encode();
+        for message_id in watchers {
+            self.core.answer_message(message_id, Ok(response.clone()));
+        }
+    }
 }
 
 impl<'a> SystemBuilder<'a> {
@@ -280,15 +1065,36 @@ impl<'a> SystemBuilder<'a> {
         // We handle some low-level interfaces here.
         let mut core = Core::new();
         let interface_interface_pid = core.reserve_pid();
+        let interface_registry_interface_pid = core.reserve_pid();
+        let perf_self_interface_pid = core.reserve_pid();
+        let lifecycle_interface_pid = core.reserve_pid();
+        let crash_interface_pid = core.reserve_pid();
+        let process_management_interface_pid = core.reserve_pid();
+        let spawn_interface_pid = core.reserve_pid();
         let load_source_virtual_pid = core.reserve_pid();
 
         SystemBuilder {
             core,
             interface_interface_pid,
+            interface_registry_interface_pid,
+            perf_self_interface_pid,
+            lifecycle_interface_pid,
+            crash_interface_pid,
+            process_management_interface_pid,
+            spawn_interface_pid,
             load_source_virtual_pid,
             startup_processes: Vec::new(),
             programs_to_load: SegQueue::new(),
             native_programs: native::NativeProgramsCollection::new(),
+            instrumentation: Instrumentation::new(),
+            module_limits: Default::default(),
+            coverage: None,
+            programs_registry: None,
+            self_check: None,
+            trusted_keys: None,
+            spawn_templates: Vec::new(),
+            monotonic_clock: None,
+            kill_grace_period: 5_000_000_000,
         }
     }
 
@@ -302,6 +1108,127 @@ impl<'a> SystemBuilder<'a> {
         self
     }
 
+    /// Adds a pass to apply on the programs that the [`System`] loads through the `loader`
+    /// interface.
+    ///
+    /// Passes are applied in the order in which they have been added.
+    ///
+    /// > **Note**: Modules passed to [`SystemBuilder::with_startup_process`] or
+    /// >           [`System::execute`] have already been parsed, and are therefore not
+    /// >           instrumented. Use [`Module::from_bytes_instrumented`] to build them.
+    pub fn with_instrumentation_pass(mut self, pass: impl InstrumentationPass + 'static) -> Self {
+        self.instrumentation.push(pass);
+        self
+    }
+
+    /// Enables collecting the code coverage of the programs.
+    ///
+    /// The programs loaded through the `loader` interface are instrumented with a pass of the
+    /// collector. Programs passed to [`SystemBuilder::with_startup_process`] or
+    /// [`System::execute`] are covered only if they have been built with
+    /// [`Module::from_bytes_instrumented`] using [`CoverageCollector::pass`].
+    pub fn with_coverage(mut self, collector: &CoverageCollector) -> Self {
+        self.instrumentation.push(collector.pass());
+        self.coverage = Some(collector.clone());
+        self
+    }
+
+    /// Keeps the given registry up to date with the module of each running process.
+    pub fn with_programs_registry(mut self, registry: &ProgramsRegistry) -> Self {
+        self.programs_registry = Some(registry.clone());
+        self
+    }
+
+    /// Enables periodically verifying the internal invariants of the system.
+    ///
+    /// This is meant to be used in long-running tests, in order to detect problems such as
+    /// resources that are never freed. Violations are reported through
+    /// [`SystemRunOutcome::SelfCheckFailed`].
+    pub fn with_self_check(mut self, config: SelfCheckConfig) -> Self {
+        self.self_check = Some(config);
+        self
+    }
+
+    /// Only allows executing the modules signed by one of the given ed25519 public keys.
+    ///
+    /// Unsigned modules, and modules signed by other keys, fail to start with
+    /// [`NewErr::UntrustedModule`]. This also applies to the startup processes and to the
+    /// programs fetched through the `loader` interface, which are then reported with
+    /// [`SystemRunOutcome::ProgramLoadFailed`]. See
+    /// [`SIGNATURE_SECTION`](crate::module::SIGNATURE_SECTION) for how modules are signed.
+    ///
+    /// By default, all modules are allowed. Calling this function multiple times adds keys to
+    /// the list.
+    pub fn with_trusted_keys(mut self, keys: impl IntoIterator<Item = [u8; 32]>) -> Self {
+        self.trusted_keys
+            .get_or_insert_with(Default::default)
+            .extend(keys);
+        self
+    }
+
+    /// Sets the function that returns the current value of the monotonic clock in nanoseconds.
+    ///
+    /// [`System::run`] then aborts the processes whose stop deadline has passed. The processes
+    /// killed through the `process-management` interface are also asked to stop on the
+    /// `lifecycle` interface and given some time to do so, rather than being aborted
+    /// immediately. See [`SystemBuilder::with_kill_grace_period`].
+    ///
+    /// > **Note**: Deadlines are only checked when [`System::run`] is polled. If no program or
+    /// >           native program is active, the deadline of a stopping process that doesn't
+    /// >           respond can be enforced late.
+    pub fn with_monotonic_clock(mut self, clock: impl Fn() -> u64 + Send + 'a) -> Self {
+        self.monotonic_clock = Some(Box::new(clock));
+        self
+    }
+
+    /// Sets the time, in nanoseconds, that the processes killed through the `process-management`
+    /// interface are given to stop before being aborted. Defaults to 5 seconds.
+    ///
+    /// Has no effect unless [`SystemBuilder::with_monotonic_clock`] is called.
+    pub fn with_kill_grace_period(mut self, nanos: u64) -> Self {
+        self.kill_grace_period = nanos;
+        self
+    }
+
+    /// Limits the number of messages that can be waiting to be processed by each program.
+    ///
+    /// By default, this number is unbounded. The configuration can be changed later for
+    /// individual programs with [`System::set_inbox_config`].
+    pub fn with_default_inbox(mut self, config: InboxConfig) -> Self {
+        self.core = self.core.with_default_inbox(config);
+        self
+    }
+
+    /// Limits the resources that each program can use. Programs that exceed these limits are
+    /// stopped with an [`ExitStatus::LimitExceeded`].
+    pub fn with_process_config(mut self, config: ProcessConfig) -> Self {
+        self.core = self.core.with_process_config(config);
+        self
+    }
+
+    /// Sets the limits that programs must respect in order to be started. Programs that exceed
+    /// them fail to start with an error. The programs loaded through the `loader` or `spawn`
+    /// interfaces are checked before they are even parsed.
+    pub fn with_module_limits(mut self, limits: ModuleLimits) -> Self {
+        self.core = self.core.with_module_limits(limits.clone());
+        self.module_limits = limits;
+        self
+    }
+
+    /// Keeps a pool of processes of the given module instantiated in advance.
+    ///
+    /// When [`System::execute`] is later called with the same module, one of these processes is
+    /// started instead of instantiating the module from scratch, which reduces the latency of
+    /// the spawn. This is meant for programs that are spawned often, such as per-request
+    /// workers. The pool is refilled while the [`System`] has nothing else to do.
+    ///
+    /// The processes are instantiated and their main thread paused before it starts running.
+    /// Calling this function multiple times with the same module overrides the size of the pool.
+    pub fn with_spawn_template(mut self, module: impl Into<Module>, pool_size: usize) -> Self {
+        self.spawn_templates.push((module.into(), pool_size));
+        self
+    }
+
     /// Adds a process to the list of processes that the [`System`] must start as part of the
     /// startup process.
     ///
@@ -342,12 +1269,14 @@ impl<'a> SystemBuilder<'a> {
     /// Builds the [`System`].
     ///
     /// Returns an error if any of the programs passed through
-    /// [`SystemBuilder::with_startup_process`] fails to start.
+    /// [`SystemBuilder::with_startup_process`] or [`SystemBuilder::with_spawn_template`] fails
+    /// to start.
     pub fn build(self) -> Result<System<'a>, NewErr> {
         let core = self.core.build();
 
-        // We ask the core to redirect messages for the `interface` interface towards our
-        // "virtual" `Pid`.
+        // We ask the core to redirect messages for the `interface`, `interface-registry`,
+        // `perf-self`, `lifecycle`, `crash` and `process-management` interfaces towards our
+        // "virtual" `Pid`s.
         match core.set_interface_handler(
             redshirt_interface_interface::ffi::INTERFACE,
             self.interface_interface_pid,
@@ -355,9 +1284,78 @@ impl<'a> SystemBuilder<'a> {
             Ok(()) => {}
             Err(_) => unreachable!(),
         };
+        match core.set_interface_handler(
+            redshirt_interface_registry_interface::ffi::INTERFACE,
+            self.interface_registry_interface_pid,
+        ) {
+            Ok(()) => {}
+            Err(_) => unreachable!(),
+        };
+        match core.set_interface_handler(
+            redshirt_perf_self_interface::ffi::INTERFACE,
+            self.perf_self_interface_pid,
+        ) {
+            Ok(()) => {}
+            Err(_) => unreachable!(),
+        };
+        match core.set_interface_handler(
+            redshirt_lifecycle_interface::ffi::INTERFACE,
+            self.lifecycle_interface_pid,
+        ) {
+            Ok(()) => {}
+            Err(_) => unreachable!(),
+        };
+        match core.set_interface_handler(
+            redshirt_syscalls::crash::INTERFACE,
+            self.crash_interface_pid,
+        ) {
+            Ok(()) => {}
+            Err(_) => unreachable!(),
+        };
+        match core.set_interface_handler(
+            redshirt_process_management_interface::ffi::INTERFACE,
+            self.process_management_interface_pid,
+        ) {
+            Ok(()) => {}
+            Err(_) => unreachable!(),
+        };
+        match core.set_interface_handler(
+            redshirt_spawn_interface::ffi::INTERFACE,
+            self.spawn_interface_pid,
+        ) {
+            Ok(()) => {}
+            Err(_) => unreachable!(),
+        };
 
         for program in self.startup_processes {
-            core.execute(&program)?;
+            check_trusted(&self.trusted_keys, &program)?;
+            let pid = core.execute(&program)?.pid();
+            if let Some(coverage) = &self.coverage {
+                coverage.process_started(pid, program.hash());
+            }
+            if let Some(programs_registry) = &self.programs_registry {
+                programs_registry.process_started(pid, program.hash());
+            }
+        }
+
+        let mut spawn_templates =
+            HashMap::with_capacity_and_hasher(self.spawn_templates.len(), Default::default());
+        for (module, pool_size) in self.spawn_templates {
+            check_trusted(&self.trusted_keys, &module)?;
+            // We always instantiate at least once, in order to report errors early.
+            let mut ready = VecDeque::with_capacity(pool_size);
+            for _ in 0..pool_size.max(1) {
+                ready.push_back(core.prepare(&module)?);
+            }
+            ready.truncate(pool_size);
+            spawn_templates.insert(
+                module.hash().clone(),
+                SpawnTemplate {
+                    module,
+                    pool_size,
+                    ready,
+                },
+            );
         }
 
         Ok(System {
@@ -366,7 +1364,23 @@ impl<'a> SystemBuilder<'a> {
             loader_pid: atomic::AtomicU64::new(0),
             load_source_virtual_pid: self.load_source_virtual_pid,
             loading_programs: RefCell::new(Default::default()),
+            spawning_programs: RefCell::new(Default::default()),
             programs_to_load: self.programs_to_load,
+            instrumentation: self.instrumentation,
+            module_limits: self.module_limits,
+            coverage: self.coverage,
+            programs_registry: self.programs_registry,
+            self_check: self.self_check,
+            trusted_keys: self.trusted_keys,
+            run_iterations: atomic::AtomicU32::new(0),
+            spawn_templates: RefCell::new(spawn_templates),
+            stop_waiters: RefCell::new(Default::default()),
+            stopping: RefCell::new(Default::default()),
+            crash_reports: RefCell::new(Default::default()),
+            process_waiters: RefCell::new(Default::default()),
+            interface_watchers: RefCell::new(Default::default()),
+            monotonic_clock: self.monotonic_clock,
+            kill_grace_period: self.kill_grace_period,
         })
     }
 }
@@ -376,3 +1390,367 @@ impl<'a> Default for SystemBuilder<'a> {
         SystemBuilder::new()
     }
 }
+
+/// Returns an error if `trusted_keys` is `Some` and the module isn't signed by any of its keys.
+fn check_trusted(
+    trusted_keys: &Option<HashSet<[u8; 32], FnvBuildHasher>>,
+    module: &Module,
+) -> Result<(), NewErr> {
+    let trusted_keys = match trusted_keys {
+        Some(k) => k,
+        None => return Ok(()),
+    };
+
+    match module.signer() {
+        Some(signer) if trusted_keys.contains(signer) => Ok(()),
+        _ => Err(NewErr::UntrustedModule),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ExitStatus, LoadError, SystemBuilder, SystemRunOutcome};
+    use crate::module::ModuleHash;
+    use crate::native::{DummyMessageIdWrite, NativeProgramEvent, NativeProgramRef};
+    use crate::scheduler::NewErr;
+    use alloc::{boxed::Box, sync::Arc, vec, vec::Vec};
+    use core::{pin::Pin, sync::atomic};
+    use futures::{channel::mpsc, lock::Mutex, prelude::*};
+    use redshirt_syscalls::{
+        Decode as _, Encode as _, EncodedMessage, InterfaceHash, MessageId, Pid,
+    };
+
+    /// Native program that handles the `loader` interface, and answers every request with the
+    /// same module.
+    struct Loader {
+        registered: atomic::AtomicBool,
+        module: Vec<u8>,
+        requests_tx: mpsc::UnboundedSender<MessageId>,
+        requests_rx: Mutex<mpsc::UnboundedReceiver<MessageId>>,
+    }
+
+    impl Loader {
+        fn new(module: Vec<u8>) -> Self {
+            let (requests_tx, requests_rx) = mpsc::unbounded();
+            Loader {
+                registered: atomic::AtomicBool::new(false),
+                module,
+                requests_tx,
+                requests_rx: Mutex::new(requests_rx),
+            }
+        }
+    }
+
+    impl<'a> NativeProgramRef<'a> for &'a Loader {
+        type Future =
+            Pin<Box<dyn Future<Output = NativeProgramEvent<Self::MessageIdWrite>> + Send + 'a>>;
+        type MessageIdWrite = DummyMessageIdWrite;
+
+        fn next_event(self) -> Self::Future {
+            if !self.registered.swap(true, atomic::Ordering::Relaxed) {
+                return Box::pin(future::ready(NativeProgramEvent::Emit {
+                    interface: redshirt_interface_interface::ffi::INTERFACE,
+                    message_id_write: None,
+                    message: redshirt_interface_interface::ffi::InterfaceMessage::Register(
+                        redshirt_loader_interface::ffi::INTERFACE,
+                    )
+                    .encode(),
+                }));
+            }
+
+            Box::pin(async move {
+                let message_id = self.requests_rx.lock().await.next().await.unwrap();
+                let response = redshirt_loader_interface::ffi::LoadResponse {
+                    result: Ok(self.module.clone()),
+                };
+                NativeProgramEvent::Answer {
+                    message_id,
+                    answer: Ok(response.encode()),
+                }
+            })
+        }
+
+        fn interface_message(
+            self,
+            _: InterfaceHash,
+            message_id: Option<MessageId>,
+            _: Pid,
+            _: EncodedMessage,
+        ) {
+            if let Some(message_id) = message_id {
+                self.requests_tx.unbounded_send(message_id).unwrap();
+            }
+        }
+
+        fn process_destroyed(self, _: Pid) {}
+
+        fn message_response(self, _: MessageId, _: Result<EncodedMessage, ()>) {
+            unreachable!()
+        }
+    }
+
+    /// Native program that kills the processes whose [`Pid`] is sent on `targets_tx`, through
+    /// the `process-management` interface.
+    struct Killer {
+        targets_tx: mpsc::UnboundedSender<Pid>,
+        targets_rx: Mutex<mpsc::UnboundedReceiver<Pid>>,
+    }
+
+    impl Killer {
+        fn new() -> Self {
+            let (targets_tx, targets_rx) = mpsc::unbounded();
+            Killer {
+                targets_tx,
+                targets_rx: Mutex::new(targets_rx),
+            }
+        }
+    }
+
+    impl<'a> NativeProgramRef<'a> for &'a Killer {
+        type Future =
+            Pin<Box<dyn Future<Output = NativeProgramEvent<Self::MessageIdWrite>> + Send + 'a>>;
+        type MessageIdWrite = DummyMessageIdWrite;
+
+        fn next_event(self) -> Self::Future {
+            Box::pin(async move {
+                let target = self.targets_rx.lock().await.next().await.unwrap();
+                NativeProgramEvent::Emit {
+                    interface: redshirt_process_management_interface::ffi::INTERFACE,
+                    message_id_write: Some(DummyMessageIdWrite),
+                    message:
+                        redshirt_process_management_interface::ffi::ProcessManagementMessage::Kill(
+                            target,
+                        )
+                        .encode(),
+                }
+            })
+        }
+
+        fn interface_message(
+            self,
+            _: InterfaceHash,
+            _: Option<MessageId>,
+            _: Pid,
+            _: EncodedMessage,
+        ) {
+            unreachable!()
+        }
+
+        fn process_destroyed(self, _: Pid) {}
+
+        fn message_response(self, _: MessageId, _: Result<EncodedMessage, ()>) {}
+    }
+
+    /// Native program that asks to spawn the given module once, with the given arguments, and
+    /// sends the answer on `responses_tx`.
+    struct Spawner {
+        message: Mutex<Option<redshirt_spawn_interface::ffi::SpawnMessage>>,
+        responses_tx: mpsc::UnboundedSender<Result<EncodedMessage, ()>>,
+    }
+
+    impl<'a> NativeProgramRef<'a> for &'a Spawner {
+        type Future =
+            Pin<Box<dyn Future<Output = NativeProgramEvent<Self::MessageIdWrite>> + Send + 'a>>;
+        type MessageIdWrite = DummyMessageIdWrite;
+
+        fn next_event(self) -> Self::Future {
+            Box::pin(async move {
+                match self.message.lock().await.take() {
+                    Some(message) => NativeProgramEvent::Emit {
+                        interface: redshirt_spawn_interface::ffi::INTERFACE,
+                        message_id_write: Some(DummyMessageIdWrite),
+                        message: message.encode(),
+                    },
+                    None => future::pending().await,
+                }
+            })
+        }
+
+        fn interface_message(
+            self,
+            _: InterfaceHash,
+            _: Option<MessageId>,
+            _: Pid,
+            _: EncodedMessage,
+        ) {
+            unreachable!()
+        }
+
+        fn process_destroyed(self, _: Pid) {}
+
+        fn message_response(self, _: MessageId, response: Result<EncodedMessage, ()>) {
+            self.responses_tx.unbounded_send(response).unwrap();
+        }
+    }
+
+    #[test]
+    fn spawn_with_arguments_refused() {
+        let module = wat_to_bin!(
+            r#"(module
+            (func $_start)
+            (export "_start" (func $_start)))
+        "#
+        )
+        .to_vec();
+
+        let (responses_tx, mut responses_rx) = mpsc::unbounded();
+        let system = SystemBuilder::new()
+            .with_native_program(Spawner {
+                message: Mutex::new(Some(redshirt_spawn_interface::ffi::SpawnMessage::Spawn {
+                    module: redshirt_spawn_interface::ffi::ModuleSource::Bytes(module),
+                    args: vec![b"foo".to_vec()],
+                })),
+                responses_tx,
+            })
+            .build()
+            .unwrap();
+
+        for _ in 0..3 {
+            assert!(system.run().now_or_never().is_none());
+        }
+
+        let response = responses_rx
+            .next()
+            .now_or_never()
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        let response = redshirt_spawn_interface::ffi::SpawnResponse::decode(response).unwrap();
+        assert_eq!(
+            response.result,
+            Err(redshirt_spawn_interface::ffi::SpawnError::ArgumentsNotSupported)
+        );
+    }
+
+    #[test]
+    fn untrusted_main_program_reported() {
+        let module = wat_to_bin!(
+            r#"(module
+            (func $_start)
+            (export "_start" (func $_start)))
+        "#
+        )
+        .to_vec();
+        let hash = ModuleHash::from_bytes(&module);
+
+        let system = SystemBuilder::new()
+            .with_native_program(Loader::new(module))
+            .with_trusted_keys(Some([0xaa; 32]))
+            .with_main_program(hash.clone())
+            .build()
+            .unwrap();
+
+        match futures::executor::block_on(system.run()) {
+            SystemRunOutcome::ProgramLoadFailed {
+                hash: failed,
+                error: LoadError::Start(NewErr::UntrustedModule),
+            } => assert!(failed == hash),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn infinite_loop_doesnt_block_native_programs() {
+        let module = b"not a wasm module".to_vec();
+        let hash = ModuleHash::from_bytes(&module);
+
+        let system = SystemBuilder::new()
+            .with_native_program(Loader::new(module))
+            .with_startup_process(from_wat!(
+                local,
+                r#"(module
+                (func $_start
+                    (loop $l (br $l)))
+                (export "_start" (func $_start)))
+            "#
+            ))
+            .with_main_program(hash.clone())
+            .build()
+            .unwrap();
+
+        // The startup process never stops, but the `System` must still process the answer of
+        // the loader.
+        match futures::executor::block_on(system.run()) {
+            SystemRunOutcome::ProgramLoadFailed {
+                hash: failed,
+                error: LoadError::InvalidModule,
+            } => assert!(failed == hash),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn infinite_loop_yields() {
+        let system = SystemBuilder::new()
+            .with_startup_process(from_wat!(
+                local,
+                r#"(module
+                (func $_start
+                    (loop $l (br $l)))
+                (export "_start" (func $_start)))
+            "#
+            ))
+            .build()
+            .unwrap();
+
+        assert!(system.run().now_or_never().is_none());
+    }
+
+    #[test]
+    fn killed_process_ignoring_stop_aborted_at_deadline() {
+        // Waits to be asked to stop on the `lifecycle` interface, then never stops.
+        let module = from_wat!(
+            local,
+            r#"(module
+            (import "redshirt" "emit_message" (func $emit_message (param i32 i32 i32 i32 i32 i32) (result i32)))
+            (memory $memory 1)
+            (data (i32.const 0) "\d5\06\d3\63\12\b8\20\96\77\e8\ba\b0\7d\06\cf\4f\df\15\fa\8c\e0\92\4f\ff\7b\9b\0f\b2\bc\b6\78\c2")
+            (data (i32.const 32) "\00")
+            (data (i32.const 40) "\20\00\00\00\01\00\00\00")
+            (func $_start
+                (drop (call $emit_message (i32.const 0) (i32.const 40) (i32.const 1) (i32.const 1) (i32.const 0) (i32.const 48)))
+                (loop $l (br $l)))
+            (export "memory" (memory 0))
+            (export "_start" (func $_start)))
+        "#
+        );
+
+        let now = Arc::new(atomic::AtomicU64::new(0));
+        let killer = Killer::new();
+        let targets_tx = killer.targets_tx.clone();
+        let system = SystemBuilder::new()
+            .with_native_program(killer)
+            .with_monotonic_clock({
+                let now = now.clone();
+                move || now.load(atomic::Ordering::Relaxed)
+            })
+            .with_kill_grace_period(1000)
+            .build()
+            .unwrap();
+        let pid = system.execute(&module).unwrap();
+
+        for _ in 0..3 {
+            assert!(system.run().now_or_never().is_none());
+        }
+        targets_tx.unbounded_send(pid).unwrap();
+        for _ in 0..3 {
+            assert!(system.run().now_or_never().is_none());
+        }
+
+        // The process has been asked to stop, but hasn't been aborted yet.
+        assert!(system.is_process_alive(pid));
+
+        now.store(1000, atomic::Ordering::Relaxed);
+        match futures::executor::block_on(system.run()) {
+            SystemRunOutcome::ProgramFinished {
+                pid: finished,
+                exit_status,
+                ..
+            } => {
+                assert_eq!(finished, pid);
+                assert_eq!(exit_status, ExitStatus::Killed);
+            }
+            _ => panic!(),
+        }
+    }
+}
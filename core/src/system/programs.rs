@@ -0,0 +1,57 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::module::ModuleHash;
+
+use alloc::sync::Arc;
+use hashbrown::HashMap;
+use nohash_hasher::BuildNoHashHasher;
+use redshirt_syscalls::Pid;
+use spinning_top::Spinlock;
+
+/// Keeps track of the module that each running Wasm process has been started from.
+///
+/// Native programs only know processes by their [`Pid`]. Sharing a [`ProgramsRegistry`] with
+/// them makes it possible for them to identify the program behind a [`Pid`], for example in
+/// order to remember decisions across multiple executions of the same program.
+///
+/// Cloning a [`ProgramsRegistry`] returns a handle to the same registry.
+#[derive(Clone, Default)]
+pub struct ProgramsRegistry {
+    inner: Arc<Spinlock<HashMap<Pid, ModuleHash, BuildNoHashHasher<u64>>>>,
+}
+
+impl ProgramsRegistry {
+    /// Builds a new empty registry.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Returns the hash of the module the given process has been started from, or `None` if
+    /// the process isn't running or isn't a Wasm process.
+    pub fn module_hash(&self, pid: Pid) -> Option<ModuleHash> {
+        self.inner.lock().get(&pid).cloned()
+    }
+
+    /// Notifies the registry that a process has been started with the given module.
+    pub(crate) fn process_started(&self, pid: Pid, module_hash: &ModuleHash) {
+        self.inner.lock().insert(pid, module_hash.clone());
+    }
+
+    /// Notifies the registry that a process has finished.
+    pub(crate) fn process_finished(&self, pid: Pid) {
+        self.inner.lock().remove(&pid);
+    }
+}
@@ -0,0 +1,146 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::scheduler::LimitExceeded;
+
+use alloc::{string::String, vec::Vec};
+use core::fmt;
+
+/// Error that has stopped a process.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrapError {
+    /// See [`TrapError::kind`].
+    kind: TrapKind,
+    /// See [`TrapError::backtrace`].
+    backtrace: Vec<BacktraceFrame>,
+}
+
+/// Reason why a process has been stopped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TrapKind {
+    /// An `unreachable` instruction has been executed.
+    Unreachable,
+    /// An instruction has accessed the memory out of its bounds.
+    MemoryAccessOutOfBounds,
+    /// An instruction has accessed a table out of its bounds.
+    TableAccessOutOfBounds,
+    /// An indirect call has targeted an uninitialized table element.
+    ElemUninitialized,
+    /// An integer has been divided by zero.
+    DivisionByZero,
+    /// A floating point number couldn't be converted to an integer.
+    InvalidConversionToInt,
+    /// The interpreter has run out of stack space.
+    StackOverflow,
+    /// An indirect call has targeted a function whose signature doesn't match the expected one.
+    UnexpectedSignature,
+    /// The process has been aborted by the system.
+    Aborted,
+    /// The process has exceeded one of its limits.
+    LimitExceeded(LimitExceeded),
+}
+
+/// Function that was being executed when a [`TrapError`] happened.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BacktraceFrame {
+    /// Index of the function within the module, as found in the name section of the module.
+    pub function_index: u32,
+    /// Name of the function, if the module has a name section that contains it.
+    pub function_name: Option<String>,
+}
+
+impl TrapError {
+    /// Builds a new [`TrapError`].
+    pub(crate) fn new(kind: TrapKind, backtrace: Vec<BacktraceFrame>) -> Self {
+        TrapError { kind, backtrace }
+    }
+
+    /// Returns the reason why the process has been stopped.
+    pub fn kind(&self) -> &TrapKind {
+        &self.kind
+    }
+
+    /// Returns the functions that were being executed when the error happened, the innermost
+    /// first.
+    ///
+    /// Empty if the error isn't the consequence of executing the module, for example if the
+    /// process has been aborted.
+    ///
+    /// > **Note**: The interpreter doesn't expose its call stack. Only the innermost function of
+    /// >           the main module, which is tracked by instrumenting the module when it is
+    /// >           loaded, is known.
+    // TODO: report the full call stack
+    pub fn backtrace(&self) -> &[BacktraceFrame] {
+        &self.backtrace
+    }
+}
+
+impl From<TrapKind> for TrapError {
+    fn from(kind: TrapKind) -> Self {
+        TrapError::new(kind, Vec::new())
+    }
+}
+
+impl fmt::Display for TrapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.kind)?;
+        for frame in &self.backtrace {
+            match &frame.function_name {
+                Some(name) => write!(f, "\n    at {} (#{})", name, frame.function_index)?,
+                None => write!(f, "\n    at #{}", frame.function_index)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl TrapKind {
+    /// Converts an error of the interpreter.
+    ///
+    /// # Panic
+    ///
+    /// Panics if `kind` is [`wasmi::TrapKind::Host`]. Host errors are used by the virtual
+    /// machine to interrupt threads, and never reported as such.
+    pub(crate) fn from_wasmi(kind: &wasmi::TrapKind) -> Self {
+        match kind {
+            wasmi::TrapKind::Unreachable => TrapKind::Unreachable,
+            wasmi::TrapKind::MemoryAccessOutOfBounds => TrapKind::MemoryAccessOutOfBounds,
+            wasmi::TrapKind::TableAccessOutOfBounds => TrapKind::TableAccessOutOfBounds,
+            wasmi::TrapKind::ElemUninitialized => TrapKind::ElemUninitialized,
+            wasmi::TrapKind::DivisionByZero => TrapKind::DivisionByZero,
+            wasmi::TrapKind::InvalidConversionToInt => TrapKind::InvalidConversionToInt,
+            wasmi::TrapKind::StackOverflow => TrapKind::StackOverflow,
+            wasmi::TrapKind::UnexpectedSignature => TrapKind::UnexpectedSignature,
+            wasmi::TrapKind::Host(_) => unreachable!(),
+        }
+    }
+}
+
+impl fmt::Display for TrapKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TrapKind::Unreachable => write!(f, "Unreachable instruction executed"),
+            TrapKind::MemoryAccessOutOfBounds => write!(f, "Out of bounds memory access"),
+            TrapKind::TableAccessOutOfBounds => write!(f, "Out of bounds table access"),
+            TrapKind::ElemUninitialized => write!(f, "Call to an uninitialized table element"),
+            TrapKind::DivisionByZero => write!(f, "Integer division by zero"),
+            TrapKind::InvalidConversionToInt => write!(f, "Invalid conversion to integer"),
+            TrapKind::StackOverflow => write!(f, "Stack overflow"),
+            TrapKind::UnexpectedSignature => write!(f, "Indirect call with a wrong signature"),
+            TrapKind::Aborted => write!(f, "Aborted"),
+            TrapKind::LimitExceeded(limit) => write!(f, "{}", limit),
+        }
+    }
+}
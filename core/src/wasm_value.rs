@@ -69,30 +69,35 @@ impl WasmValue {
             None
         }
     }
-}
 
-impl From<wasmi::RuntimeValue> for WasmValue {
-    fn from(val: wasmi::RuntimeValue) -> Self {
+    /// Converts a value of the interpreter.
+    pub(crate) fn from_wasmi(val: wasmi::RuntimeValue) -> Self {
         match val {
             wasmi::RuntimeValue::I32(v) => WasmValue::I32(v),
             wasmi::RuntimeValue::I64(v) => WasmValue::I64(v),
-            _ => unimplemented!(),
+            wasmi::RuntimeValue::F32(v) => WasmValue::F32(v.to_bits()),
+            wasmi::RuntimeValue::F64(v) => WasmValue::F64(v.to_bits()),
         }
     }
-}
 
-impl From<WasmValue> for wasmi::RuntimeValue {
-    fn from(val: WasmValue) -> Self {
-        match val {
+    /// Converts this value into a value of the interpreter.
+    pub(crate) fn to_wasmi(self) -> wasmi::RuntimeValue {
+        match self {
             WasmValue::I32(v) => wasmi::RuntimeValue::I32(v),
             WasmValue::I64(v) => wasmi::RuntimeValue::I64(v),
-            _ => unimplemented!(),
+            WasmValue::F32(v) => {
+                wasmi::RuntimeValue::F32(wasmi::nan_preserving_float::F32::from_bits(v))
+            }
+            WasmValue::F64(v) => {
+                wasmi::RuntimeValue::F64(wasmi::nan_preserving_float::F64::from_bits(v))
+            }
         }
     }
 }
 
-impl From<wasmi::ValueType> for ValueType {
-    fn from(val: wasmi::ValueType) -> Self {
+impl ValueType {
+    /// Converts a type of the interpreter.
+    pub(crate) fn from_wasmi(val: wasmi::ValueType) -> Self {
         match val {
             wasmi::ValueType::I32 => ValueType::I32,
             wasmi::ValueType::I64 => ValueType::I64,
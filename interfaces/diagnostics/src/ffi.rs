@@ -0,0 +1,29 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use parity_scale_codec::{Decode, Encode};
+use redshirt_syscalls::{diagnostics::ReactorState, InterfaceHash};
+
+// TODO: this has been randomly generated; instead should be a hash or something
+pub const INTERFACE: InterfaceHash = InterfaceHash::from_raw_hash([
+    0x56, 0x6c, 0x48, 0x2b, 0x5c, 0x22, 0xa1, 0x27, 0x3c, 0x2e, 0x8b, 0xe6, 0x47, 0x7a, 0xd4, 0x35,
+    0xdb, 0xb1, 0x34, 0x96, 0x14, 0xde, 0x34, 0x8e, 0x58, 0x8d, 0x6e, 0x11, 0xb7, 0x2d, 0xc1, 0x5d,
+]);
+
+#[derive(Debug, Encode, Decode)]
+pub enum DiagnosticsMessage {
+    /// State of the futures of the emitter waiting for notifications. Doesn't expect any answer.
+    ReactorState(ReactorState),
+}
@@ -0,0 +1,83 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Reporting the internal state of programs.
+//!
+//! The handler of this interface is typically a tool that shows to a human being what programs
+//! are doing, for example in order to diagnose a program that seems stuck. Programs report their
+//! state by emitting messages on this interface, and the handler can identify them through the
+//! emitter of each message.
+
+#![no_std]
+
+pub mod ffi;
+
+/// Sends the list of messages whose response this program is waiting for, and for how long, to
+/// the handler of the interface.
+///
+/// See the `diagnostics` module of `redshirt_syscalls` for how ages are measured.
+pub fn report_reactor_state() {
+    let state = redshirt_syscalls::diagnostics::reactor_state();
+    unsafe {
+        redshirt_syscalls::emit_message_without_response(
+            &ffi::INTERFACE,
+            ffi::DiagnosticsMessage::ReactorState(state),
+        )
+        .unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::sync::atomic::{AtomicU64, Ordering};
+    use futures::prelude::*;
+    use redshirt_syscalls::{diagnostics, testing, Decode as _, InterfaceHash};
+
+    static NOW: AtomicU64 = AtomicU64::new(0);
+
+    #[test]
+    fn reports_waiting_messages() {
+        testing::reset();
+        diagnostics::set_clock(|| NOW.load(Ordering::Relaxed));
+        NOW.store(1_000, Ordering::Relaxed);
+
+        const OTHER: InterfaceHash = InterfaceHash::from_raw_hash([0xaa; 32]);
+        let response =
+            unsafe { redshirt_syscalls::emit_message_with_response::<u32>(&OTHER, 5u32).unwrap() };
+        futures::pin_mut!(response);
+        let message_id = testing::next_emitted_message().unwrap().message_id;
+        assert!(message_id.is_some());
+
+        // Polling the future makes it wait for the response.
+        assert!(response.as_mut().now_or_never().is_none());
+
+        NOW.store(46_000, Ordering::Relaxed);
+        super::report_reactor_state();
+
+        let emitted = testing::next_emitted_message().unwrap();
+        assert_eq!(emitted.interface, super::ffi::INTERFACE);
+        assert!(emitted.message_id.is_none());
+        let super::ffi::DiagnosticsMessage::ReactorState(state) =
+            super::ffi::DiagnosticsMessage::decode(emitted.message).unwrap();
+        let registration = state
+            .registrations
+            .iter()
+            .find(|r| r.message_id == message_id)
+            .unwrap();
+        assert_eq!(registration.age_nanos, Some(45_000));
+        assert_eq!(registration.age_ticks, 0);
+        assert!(registration.waker_registered);
+    }
+}
@@ -0,0 +1,162 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use parity_scale_codec::{Decode, Encode};
+use redshirt_syscalls::{Handle, InterfaceHash};
+
+// TODO: this has been randomly generated; instead should be a hash or something
+pub const INTERFACE: InterfaceHash = InterfaceHash::from_raw_hash([
+    0xd5, 0x51, 0xa8, 0x3c, 0x69, 0x2b, 0xda, 0x4a, 0x81, 0xa2, 0xb3, 0x0b, 0xfa, 0x53, 0xbe, 0x36,
+    0x10, 0xd3, 0xde, 0xaa, 0x6b, 0x43, 0x2d, 0x9e, 0xa3, 0x1f, 0xf5, 0xd5, 0x2d, 0xaf, 0x2d, 0xbd,
+]);
+
+/// Message emitted on the filesystem interface.
+///
+/// Paths are made of components separated with `/`, and are relative to the root of the
+/// filesystem whether or not they start with a `/`.
+#[derive(Debug, Encode, Decode)]
+pub enum FsMessage {
+    /// Opens a file. Answered with a [`FsOpenResponse`].
+    Open(FsOpen),
+    /// Closes a file. Doesn't have any answer.
+    Close(FsClose),
+    /// Reads data from a file, starting at its current position. Answered with a
+    /// [`FsReadResponse`].
+    Read(FsRead),
+    /// Writes data to a file, starting at its current position. Answered with a
+    /// [`FsWriteResponse`] once all the data has been written.
+    Write(FsWrite),
+    /// Changes the current position of a file. Answered with a [`FsSeekResponse`].
+    Seek(FsSeek),
+    /// Lists the content of a directory. Answered with a [`FsReadDirResponse`].
+    ReadDir(FsReadDir),
+    /// Queries information about a file or directory. Answered with a [`FsMetadataResponse`].
+    Metadata(FsMetadata),
+}
+
+#[derive(Debug, Encode, Decode)]
+pub struct FsOpen {
+    pub path: String,
+    /// If true, the file can be read.
+    pub read: bool,
+    /// If true, the file can be written.
+    pub write: bool,
+    /// If true, the file is created if it doesn't exist. Requires `write`.
+    pub create: bool,
+    /// If true, the file is emptied when opened. Requires `write`.
+    pub truncate: bool,
+}
+
+#[derive(Debug, Encode, Decode)]
+pub struct FsOpenResponse {
+    /// Handle to the opened file.
+    pub result: Result<Handle, FsError>,
+}
+
+#[derive(Debug, Encode, Decode)]
+pub struct FsClose {
+    pub handle: Handle,
+}
+
+#[derive(Debug, Encode, Decode)]
+pub struct FsRead {
+    pub handle: Handle,
+    /// Maximum number of bytes to read.
+    pub len: u32,
+}
+
+#[derive(Debug, Encode, Decode)]
+pub struct FsReadResponse {
+    /// Data that has been read. Empty if the end of the file has been reached.
+    pub result: Result<Vec<u8>, FsError>,
+}
+
+#[derive(Debug, Encode, Decode)]
+pub struct FsWrite {
+    pub handle: Handle,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Encode, Decode)]
+pub struct FsWriteResponse {
+    pub result: Result<(), FsError>,
+}
+
+#[derive(Debug, Encode, Decode)]
+pub struct FsSeek {
+    pub handle: Handle,
+    pub position: FsSeekFrom,
+}
+
+/// Equivalent to [`std::io::SeekFrom`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+pub enum FsSeekFrom {
+    Start(u64),
+    End(i64),
+    Current(i64),
+}
+
+#[derive(Debug, Encode, Decode)]
+pub struct FsSeekResponse {
+    /// New position within the file, from the start.
+    pub result: Result<u64, FsError>,
+}
+
+#[derive(Debug, Encode, Decode)]
+pub struct FsReadDir {
+    pub path: String,
+}
+
+#[derive(Debug, Encode, Decode)]
+pub struct FsReadDirResponse {
+    pub result: Result<Vec<FsDirEntry>, FsError>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct FsDirEntry {
+    /// Name of the entry within the directory.
+    pub name: String,
+    pub is_dir: bool,
+}
+
+#[derive(Debug, Encode, Decode)]
+pub struct FsMetadata {
+    pub path: String,
+}
+
+#[derive(Debug, Encode, Decode)]
+pub struct FsMetadataResponse {
+    pub result: Result<FsMetadataInfo, FsError>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct FsMetadataInfo {
+    pub is_dir: bool,
+    /// Size of the file in bytes.
+    pub len: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub enum FsError {
+    /// The file or directory doesn't exist.
+    NotFound,
+    /// Not allowed to access the file or directory. Also returned for paths that are outside of
+    /// the filesystem, such as paths that start with `..`.
+    PermissionDenied,
+    /// The handle doesn't correspond to a file opened by the emitter.
+    InvalidHandle,
+    /// Any other error.
+    Other,
+}
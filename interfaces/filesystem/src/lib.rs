@@ -0,0 +1,216 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Filesystem.
+//!
+//! Allows opening, reading and writing files, and inspecting directories, similar to what the
+//! `std::fs` module does. Contrary to `std::fs`, all the operations are asynchronous.
+//!
+//! Paths are made of components separated with `/`. The handler of this interface decides what
+//! the root of the filesystem is, and programs can't access anything outside of it.
+
+pub use ffi::{
+    FsDirEntry as DirEntry, FsError, FsMetadataInfo as Metadata, FsSeekFrom as SeekFrom,
+};
+
+use redshirt_syscalls::Handle;
+
+pub mod ffi;
+
+/// Options for opening a file.
+///
+/// This type is similar to [`std::fs::OpenOptions`].
+#[derive(Debug, Clone, Default)]
+pub struct OpenOptions {
+    read: bool,
+    write: bool,
+    create: bool,
+    truncate: bool,
+}
+
+/// Open file.
+///
+/// The file is closed when this object is dropped.
+#[derive(Debug)]
+pub struct File {
+    handle: Handle,
+}
+
+impl OpenOptions {
+    /// Initializes options where all the flags are false.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Sets whether the file can be read.
+    pub fn read(&mut self, read: bool) -> &mut Self {
+        self.read = read;
+        self
+    }
+
+    /// Sets whether the file can be written.
+    pub fn write(&mut self, write: bool) -> &mut Self {
+        self.write = write;
+        self
+    }
+
+    /// Sets whether the file must be created if it doesn't exist.
+    pub fn create(&mut self, create: bool) -> &mut Self {
+        self.create = create;
+        self
+    }
+
+    /// Sets whether the file must be emptied when opened.
+    pub fn truncate(&mut self, truncate: bool) -> &mut Self {
+        self.truncate = truncate;
+        self
+    }
+
+    /// Opens the file at the given path with these options.
+    pub async fn open(&self, path: &str) -> Result<File, FsError> {
+        let message = ffi::FsMessage::Open(ffi::FsOpen {
+            path: path.to_owned(),
+            read: self.read,
+            write: self.write,
+            create: self.create,
+            truncate: self.truncate,
+        });
+
+        let response: ffi::FsOpenResponse = unsafe {
+            redshirt_syscalls::emit_message_with_response(&ffi::INTERFACE, message)
+                .unwrap()
+                .await
+                .unwrap()
+        };
+
+        Ok(File {
+            handle: response.result?,
+        })
+    }
+}
+
+impl File {
+    /// Opens an existing file in read-only mode.
+    pub async fn open(path: &str) -> Result<File, FsError> {
+        OpenOptions::new().read(true).open(path).await
+    }
+
+    /// Opens a file in write-only mode, creating it if it doesn't exist and emptying it if it
+    /// does.
+    pub async fn create(path: &str) -> Result<File, FsError> {
+        OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+            .await
+    }
+
+    /// Reads at most `len` bytes starting at the current position, and advances the position.
+    ///
+    /// Returns an empty buffer if the end of the file has been reached.
+    pub async fn read(&self, len: u32) -> Result<Vec<u8>, FsError> {
+        let message = ffi::FsMessage::Read(ffi::FsRead {
+            handle: self.handle,
+            len,
+        });
+
+        let response: ffi::FsReadResponse = unsafe {
+            redshirt_syscalls::emit_message_with_response(&ffi::INTERFACE, message)
+                .unwrap()
+                .await
+                .unwrap()
+        };
+
+        response.result
+    }
+
+    /// Writes all of `data` starting at the current position, and advances the position.
+    pub async fn write_all(&self, data: Vec<u8>) -> Result<(), FsError> {
+        let message = ffi::FsMessage::Write(ffi::FsWrite {
+            handle: self.handle,
+            data,
+        });
+
+        let response: ffi::FsWriteResponse = unsafe {
+            redshirt_syscalls::emit_message_with_response(&ffi::INTERFACE, message)
+                .unwrap()
+                .await
+                .unwrap()
+        };
+
+        response.result
+    }
+
+    /// Changes the current position. Returns the new position from the start of the file.
+    pub async fn seek(&self, position: SeekFrom) -> Result<u64, FsError> {
+        let message = ffi::FsMessage::Seek(ffi::FsSeek {
+            handle: self.handle,
+            position,
+        });
+
+        let response: ffi::FsSeekResponse = unsafe {
+            redshirt_syscalls::emit_message_with_response(&ffi::INTERFACE, message)
+                .unwrap()
+                .await
+                .unwrap()
+        };
+
+        response.result
+    }
+}
+
+impl Drop for File {
+    fn drop(&mut self) {
+        unsafe {
+            let message = ffi::FsMessage::Close(ffi::FsClose {
+                handle: self.handle,
+            });
+            let _ = redshirt_syscalls::emit_message_without_response(&ffi::INTERFACE, message);
+        }
+    }
+}
+
+/// Returns the list of entries of the directory at the given path.
+pub async fn read_dir(path: &str) -> Result<Vec<DirEntry>, FsError> {
+    let message = ffi::FsMessage::ReadDir(ffi::FsReadDir {
+        path: path.to_owned(),
+    });
+
+    let response: ffi::FsReadDirResponse = unsafe {
+        redshirt_syscalls::emit_message_with_response(&ffi::INTERFACE, message)
+            .unwrap()
+            .await
+            .unwrap()
+    };
+
+    response.result
+}
+
+/// Returns information about the file or directory at the given path.
+pub async fn metadata(path: &str) -> Result<Metadata, FsError> {
+    let message = ffi::FsMessage::Metadata(ffi::FsMetadata {
+        path: path.to_owned(),
+    });
+
+    let response: ffi::FsMetadataResponse = unsafe {
+        redshirt_syscalls::emit_message_with_response(&ffi::INTERFACE, message)
+            .unwrap()
+            .await
+            .unwrap()
+    };
+
+    response.result
+}
@@ -257,6 +257,7 @@ impl<'a> HardwareOperationsBuilder<'a> {
             let out = self.out;
             redshirt_syscalls::emit_message_with_response(&ffi::INTERFACE, msg)
                 .unwrap()
+                .map(Result::unwrap)
                 .then(move |response: Vec<ffi::HardwareAccessResponse>| {
                     for (response_elem, out) in response.into_iter().zip(out) {
                         match (response_elem, out) {
@@ -0,0 +1,102 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use parity_scale_codec::{Decode, Encode};
+use redshirt_syscalls::InterfaceHash;
+
+// TODO: this has been randomly generated; instead should be a hash or something
+pub const INTERFACE: InterfaceHash = InterfaceHash::from_raw_hash([
+    0x3a, 0x80, 0x8f, 0xc1, 0xa7, 0x37, 0x41, 0xf2, 0xf8, 0xae, 0xb6, 0x0b, 0xdb, 0x3e, 0x69, 0xec,
+    0xf9, 0x98, 0x73, 0x0e, 0x16, 0x4f, 0xdf, 0x68, 0x2f, 0x53, 0x6b, 0xeb, 0xeb, 0xa4, 0x26, 0x6c,
+]);
+
+#[derive(Debug, Encode, Decode)]
+pub enum HttpMessage {
+    /// Sends a request. Answered with an [`HttpRequestResponse`] once the status and headers of
+    /// the response have been received.
+    Request(HttpRequest),
+    /// Reads the next chunk of the body of a response. Answered with an
+    /// [`HttpReadBodyResponse`]. If multiple reads are requested on the same response, they are
+    /// performed one after the other, in order.
+    ReadBody(HttpReadBody),
+    /// Abandons a response whose body hasn't been entirely read. No response.
+    Close(HttpClose),
+}
+
+#[derive(Debug, Encode, Decode)]
+pub struct HttpRequest {
+    /// Method of the request, such as `GET` or `POST`.
+    pub method: String,
+    /// URL to send the request to. Must use the `http` or `https` scheme.
+    pub url: String,
+    /// Headers to send. The `Host`, `Connection` and `Content-Length` headers are added by the
+    /// handler, and ignored if present in this list.
+    pub headers: Vec<(String, Vec<u8>)>,
+    /// Body of the request. Can be empty.
+    pub body: Vec<u8>,
+}
+
+#[derive(Debug, Encode, Decode)]
+pub struct HttpRequestResponse {
+    pub result: Result<HttpResponseHead, HttpError>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct HttpResponseHead {
+    /// Identifier of the response, to pass when reading its body.
+    pub response_id: u32,
+    /// Status code of the response.
+    pub status: u16,
+    pub headers: Vec<(String, Vec<u8>)>,
+}
+
+#[derive(Debug, Encode, Decode)]
+pub struct HttpReadBody {
+    pub response_id: u32,
+}
+
+#[derive(Debug, Encode, Decode)]
+pub struct HttpReadBodyResponse {
+    /// Next chunk of the body. Empty if the body has been entirely read. The response no longer
+    /// exists after an empty chunk or an error has been returned.
+    pub result: Result<Vec<u8>, HttpError>,
+}
+
+#[derive(Debug, Encode, Decode)]
+pub struct HttpClose {
+    pub response_id: u32,
+}
+
+/// Reason why a request has failed.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub enum HttpError {
+    /// The URL couldn't be parsed, or its scheme isn't supported.
+    InvalidUrl,
+    /// The method or one of the headers contains invalid characters.
+    InvalidRequest,
+    /// The host of the URL couldn't be resolved.
+    ///
+    /// > **Note**: Host names aren't supported yet, and the host must be an IP address.
+    UnresolvableHost,
+    /// Connecting to the server has failed.
+    Connection(redshirt_tcp_interface::ffi::TcpError),
+    /// The TLS handshake with the server has failed.
+    Tls(redshirt_tls_interface::ffi::TlsError),
+    /// The server has sent an invalid response, or has closed the connection before the end of
+    /// the response.
+    InvalidResponse,
+    /// The response doesn't exist, or doesn't belong to the emitter of the message.
+    InvalidResponseId,
+}
@@ -0,0 +1,160 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! HTTP client.
+//!
+//! Allows sending HTTP requests. Use [`request`] to obtain the entire response at once, or
+//! [`request_streaming`] to read the body of the response progressively.
+//!
+//! The handler of this interface opens a new connection for each request. Both `http` and
+//! `https` URLs are supported.
+
+pub use ffi::HttpError;
+
+pub mod ffi;
+
+/// Response to a request, including its entire body.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    /// Status code of the response.
+    pub status: u16,
+    /// Headers of the response, in the order in which they have been received.
+    pub headers: Vec<(String, Vec<u8>)>,
+    /// Body of the response.
+    pub body: Vec<u8>,
+}
+
+/// Response to a request, whose body hasn't been read yet.
+#[derive(Debug)]
+pub struct StreamingResponse {
+    /// Status code of the response.
+    pub status: u16,
+    /// Headers of the response, in the order in which they have been received.
+    pub headers: Vec<(String, Vec<u8>)>,
+    /// Body of the response.
+    pub body: Body,
+}
+
+/// Body of a [`StreamingResponse`].
+///
+/// The connection to the server is closed when this object is dropped.
+#[derive(Debug)]
+pub struct Body {
+    response_id: u32,
+    /// If true, the body has been entirely read and the handler has forgotten about the response.
+    finished: bool,
+}
+
+/// Sends a request to the given URL, and returns the response once it has been entirely
+/// received.
+pub async fn request(
+    method: &str,
+    url: &str,
+    headers: Vec<(String, Vec<u8>)>,
+    body: Vec<u8>,
+) -> Result<Response, HttpError> {
+    let mut response = request_streaming(method, url, headers, body).await?;
+
+    let mut body = Vec::new();
+    while let Some(chunk) = response.body.next_chunk().await? {
+        body.extend_from_slice(&chunk);
+    }
+
+    Ok(Response {
+        status: response.status,
+        headers: response.headers,
+        body,
+    })
+}
+
+/// Sends a request to the given URL, and returns the response once its status and headers have
+/// been received.
+pub async fn request_streaming(
+    method: &str,
+    url: &str,
+    headers: Vec<(String, Vec<u8>)>,
+    body: Vec<u8>,
+) -> Result<StreamingResponse, HttpError> {
+    let message = ffi::HttpMessage::Request(ffi::HttpRequest {
+        method: method.to_owned(),
+        url: url.to_owned(),
+        headers,
+        body,
+    });
+
+    let response: ffi::HttpRequestResponse = unsafe {
+        redshirt_syscalls::emit_message_with_response(&ffi::INTERFACE, message)
+            .unwrap()
+            .await
+            .unwrap()
+    };
+
+    let head = response.result?;
+    Ok(StreamingResponse {
+        status: head.status,
+        headers: head.headers,
+        body: Body {
+            response_id: head.response_id,
+            finished: false,
+        },
+    })
+}
+
+impl Body {
+    /// Returns the next chunk of the body, or `None` if the body has been entirely read.
+    pub async fn next_chunk(&mut self) -> Result<Option<Vec<u8>>, HttpError> {
+        if self.finished {
+            return Ok(None);
+        }
+
+        let message = ffi::HttpMessage::ReadBody(ffi::HttpReadBody {
+            response_id: self.response_id,
+        });
+
+        let response: ffi::HttpReadBodyResponse = unsafe {
+            redshirt_syscalls::emit_message_with_response(&ffi::INTERFACE, message)
+                .unwrap()
+                .await
+                .unwrap()
+        };
+
+        match response.result {
+            Ok(chunk) if chunk.is_empty() => {
+                self.finished = true;
+                Ok(None)
+            }
+            Ok(chunk) => Ok(Some(chunk)),
+            Err(err) => {
+                self.finished = true;
+                Err(err)
+            }
+        }
+    }
+}
+
+impl Drop for Body {
+    fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
+
+        unsafe {
+            let message = ffi::HttpMessage::Close(ffi::HttpClose {
+                response_id: self.response_id,
+            });
+            let _ = redshirt_syscalls::emit_message_without_response(&ffi::INTERFACE, message);
+        }
+    }
+}
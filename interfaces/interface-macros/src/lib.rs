@@ -0,0 +1,358 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Generation of the boilerplate of an interface from a trait definition.
+//!
+//! Applying `#[redshirt_interface]` to a trait generates, next to it, a module whose name is the
+//! name of the trait in snake case followed with `_interface`. This module contains:
+//!
+//! - `INTERFACE`, the hash of the interface. It is derived from the name of the trait and the
+//! signatures of its methods, and thus changes whenever they change.
+//! - `Message`, an enum with one variant per method, which is what is sent over the interface.
+//! - One client function per method, with the same parameters. Methods that have a return type
+//! produce a function returning a `Future` that resolves to the response, or to a
+//! `redshirt_syscalls::ResponseErr` if the handler reports an erroneous message or answers with
+//! something that can't be decoded. Methods without a return type produce a function that emits
+//! a message without expecting any response.
+//! - `serve`, an async function that registers the interface and calls the methods of the
+//! trait implementation passed as parameter as messages arrive. Messages on other interfaces
+//! are left untouched, which makes it possible to serve multiple interfaces at the same time.
+//!
+//! The methods of the trait must take `&mut self`, and their parameters and return types must
+//! implement `parity_scale_codec::Encode` and `parity_scale_codec::Decode`.
+//!
+//! The generated code refers to the `futures`, `parity_scale_codec`, `redshirt_syscalls` and
+//! `redshirt_interface_interface` crates, which the crate using the macro must depend on.
+//!
+//! ```ignore
+//! #[redshirt_interface]
+//! pub trait Echo {
+//!     fn echo(&mut self, value: u32) -> u32;
+//!     fn ping(&mut self);
+//! }
+//!
+//! // Client side.
+//! let value = echo_interface::echo(5).await.unwrap();
+//!
+//! // Server side.
+//! echo_interface::serve(&mut MyEcho).await.unwrap_err();
+//! ```
+
+extern crate proc_macro;
+
+use proc_macro2::{Span, TokenStream};
+use quote::{format_ident, quote};
+
+/// Generates the boilerplate of an interface from a trait definition. See the crate-level
+/// documentation.
+#[proc_macro_attribute]
+pub fn redshirt_interface(
+    attr: proc_macro::TokenStream,
+    item: proc_macro::TokenStream,
+) -> proc_macro::TokenStream {
+    if !attr.is_empty() {
+        return syn::Error::new(Span::call_site(), "No parameter is expected")
+            .to_compile_error()
+            .into();
+    }
+
+    let item = syn::parse_macro_input!(item as syn::ItemTrait);
+    match expand(&item) {
+        Ok(out) => out.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+/// Method of the trait, as relevant for the generated code.
+struct Method {
+    name: syn::Ident,
+    /// Documentation of the method, copied to the client function.
+    docs: Vec<syn::Attribute>,
+    /// Name of the corresponding variant of the `Message` enum.
+    variant: syn::Ident,
+    params: Vec<(syn::Ident, syn::Type)>,
+    /// `None` if the method doesn't return anything, in which case no response is sent back.
+    output: Option<syn::Type>,
+}
+
+/// Generates the trait definition followed with the generated module.
+fn expand(item: &syn::ItemTrait) -> syn::Result<TokenStream> {
+    let methods = item
+        .items
+        .iter()
+        .map(parse_method)
+        .collect::<syn::Result<Vec<_>>>()?;
+
+    let vis = &item.vis;
+    let trait_name = &item.ident;
+    let mod_name = format_ident!("{}_interface", snake_case(&trait_name.to_string()));
+    let hash = interface_hash(item, &methods);
+
+    let variants = methods.iter().map(|m| {
+        let variant = &m.variant;
+        let fields = m.params.iter().map(|(name, ty)| quote! { #name: #ty });
+        quote! { #variant { #(#fields),* } }
+    });
+
+    let clients = methods.iter().map(|m| {
+        let name = &m.name;
+        let docs = &m.docs;
+        let variant = &m.variant;
+        let params = m.params.iter().map(|(name, ty)| quote! { #name: #ty });
+        let fields = m.params.iter().map(|(name, _)| name);
+        let message = quote! { Message::#variant { #(#fields),* } };
+        match &m.output {
+            Some(output) => quote! {
+                #(#docs)*
+                pub fn #name(
+                    #(#params),*
+                ) -> impl core::future::Future<
+                    Output = Result<#output, redshirt_syscalls::ResponseErr>,
+                > {
+                    unsafe {
+                        redshirt_syscalls::emit_message_with_response(&INTERFACE, #message)
+                            .unwrap()
+                    }
+                }
+            },
+            None => quote! {
+                #(#docs)*
+                pub fn #name(#(#params),*) {
+                    unsafe {
+                        redshirt_syscalls::emit_message_without_response(&INTERFACE, #message)
+                            .unwrap();
+                    }
+                }
+            },
+        }
+    });
+
+    let dispatch = methods.iter().map(|m| {
+        let name = &m.name;
+        let variant = &m.variant;
+        let fields = m.params.iter().map(|(name, _)| name).collect::<Vec<_>>();
+        let call = quote! { server.#name(#(#fields),*) };
+        let on_call = if m.output.is_some() {
+            quote! {
+                let response = #call;
+                if let Some(message_id) = notification.message_id {
+                    redshirt_syscalls::emit_answer(message_id, response);
+                }
+            }
+        } else {
+            quote! { #call; }
+        };
+        quote! {
+            Message::#variant { #(#fields),* } => { #on_call }
+        }
+    });
+
+    let doc_mod = format!("Items generated from the definition of [`{}`].", trait_name);
+    let doc_serve = format!(
+        "Registers the interface, then answers the messages that arrive on it using the given \
+         implementation of [`{}`].\n\nOnly returns if the registration fails.",
+        trait_name
+    );
+
+    Ok(quote! {
+        #item
+
+        #[doc = #doc_mod]
+        #vis mod #mod_name {
+            use super::*;
+
+            /// Hash of the interface.
+            pub const INTERFACE: redshirt_syscalls::InterfaceHash =
+                redshirt_syscalls::InterfaceHash::from_raw_hash([#(#hash),*]);
+
+            /// Message sent over the interface.
+            #[derive(parity_scale_codec::Encode, parity_scale_codec::Decode)]
+            #[allow(missing_docs)]
+            pub enum Message {
+                #(#variants),*
+            }
+
+            #(#clients)*
+
+            #[doc = #doc_serve]
+            pub async fn serve(
+                server: &mut impl #trait_name,
+            ) -> Result<
+                core::convert::Infallible,
+                redshirt_interface_interface::InterfaceRegisterError,
+            > {
+                redshirt_interface_interface::register_interface(INTERFACE).await?;
+
+                let mut messages = redshirt_syscalls::interface_messages(INTERFACE);
+                loop {
+                    let notification = match futures::StreamExt::next(&mut messages).await {
+                        Some(n) => n,
+                        None => unreachable!(),
+                    };
+
+                    let message = match <Message as redshirt_syscalls::Decode>::decode(
+                        notification.actual_data,
+                    ) {
+                        Ok(message) => message,
+                        Err(_) => {
+                            if let Some(message_id) = notification.message_id {
+                                redshirt_syscalls::emit_message_error(message_id);
+                            }
+                            continue;
+                        }
+                    };
+
+                    match message {
+                        #(#dispatch)*
+                    }
+                }
+            }
+        }
+    })
+}
+
+/// Extracts the relevant information from an item of the trait.
+fn parse_method(item: &syn::TraitItem) -> syn::Result<Method> {
+    let method = match item {
+        syn::TraitItem::Method(method) => method,
+        other => {
+            return Err(syn::Error::new_spanned(
+                other,
+                "Only methods are supported in an interface",
+            ))
+        }
+    };
+
+    let sig = &method.sig;
+    if sig.asyncness.is_some() || !sig.generics.params.is_empty() || sig.variadic.is_some() {
+        return Err(syn::Error::new_spanned(
+            sig,
+            "Interface methods can't be async, generic, or variadic",
+        ));
+    }
+
+    let mut inputs = sig.inputs.iter();
+    match inputs.next() {
+        Some(syn::FnArg::Receiver(r)) if r.reference.is_some() && r.mutability.is_some() => {}
+        _ => {
+            return Err(syn::Error::new_spanned(
+                sig,
+                "Interface methods must take `&mut self`",
+            ))
+        }
+    }
+
+    let params = inputs
+        .map(|input| match input {
+            syn::FnArg::Typed(pat) => match &*pat.pat {
+                syn::Pat::Ident(ident) => Ok((ident.ident.clone(), (*pat.ty).clone())),
+                other => Err(syn::Error::new_spanned(
+                    other,
+                    "Parameters must be identifiers",
+                )),
+            },
+            syn::FnArg::Receiver(r) => Err(syn::Error::new_spanned(r, "Unexpected `self`")),
+        })
+        .collect::<syn::Result<Vec<_>>>()?;
+
+    let output = match &sig.output {
+        syn::ReturnType::Default => None,
+        syn::ReturnType::Type(_, ty) => Some((**ty).clone()),
+    };
+
+    Ok(Method {
+        name: sig.ident.clone(),
+        docs: method
+            .attrs
+            .iter()
+            .filter(|attr| attr.path.is_ident("doc"))
+            .cloned()
+            .collect(),
+        variant: syn::Ident::new(&camel_case(&sig.ident.to_string()), sig.ident.span()),
+        params,
+        output,
+    })
+}
+
+/// Calculates the hash of the interface from the name of the trait and the signatures of its
+/// methods.
+fn interface_hash(item: &syn::ItemTrait, methods: &[Method]) -> [u8; 32] {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(item.ident.to_string().as_bytes());
+    for method in methods {
+        hasher.update(b"\0");
+        hasher.update(method.name.to_string().as_bytes());
+        for (name, ty) in &method.params {
+            let param = quote! { #name: #ty };
+            hasher.update(b"\0");
+            hasher.update(param.to_string().as_bytes());
+        }
+        if let Some(output) = &method.output {
+            hasher.update(b"\0->");
+            hasher.update(quote! { #output }.to_string().as_bytes());
+        }
+    }
+    hasher.finalize().into()
+}
+
+/// Turns `FooBar` into `foo_bar`.
+fn snake_case(name: &str) -> String {
+    let mut out = String::with_capacity(name.len() + 4);
+    for (n, c) in name.chars().enumerate() {
+        if c.is_uppercase() {
+            if n != 0 {
+                out.push('_');
+            }
+            out.extend(c.to_lowercase());
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// Turns `foo_bar` into `FooBar`.
+fn camel_case(name: &str) -> String {
+    name.split('_')
+        .map(|word| {
+            let mut chars = word.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => String::new(),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{camel_case, expand, snake_case};
+
+    #[test]
+    fn names() {
+        assert_eq!(snake_case("KernelLog"), "kernel_log");
+        assert_eq!(camel_case("get_random"), "GetRandom");
+    }
+
+    #[test]
+    fn refuses_methods_without_mut_self() {
+        let item: syn::ItemTrait = syn::parse_quote! {
+            pub trait Echo {
+                fn echo(value: u32) -> u32;
+            }
+        };
+        assert!(expand(&item).is_err());
+    }
+}
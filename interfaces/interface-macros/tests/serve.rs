@@ -0,0 +1,119 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Tests the code generated by `#[redshirt_interface]` against the mock kernel of
+//! `redshirt_syscalls`.
+
+use futures::prelude::*;
+use parity_scale_codec::{Decode as _, DecodeAll as _};
+use redshirt_interface_macros::redshirt_interface;
+use redshirt_syscalls::{testing, Encode as _, InterfaceHash, Pid};
+
+pub mod a {
+    use super::redshirt_interface;
+
+    #[redshirt_interface]
+    pub trait Echo {
+        /// Documentation doesn't influence the hash.
+        fn echo(&mut self, value: u32) -> u32;
+    }
+}
+
+pub mod b {
+    use super::redshirt_interface;
+
+    #[redshirt_interface]
+    pub trait Echo {
+        fn echo(&mut self, value: u32) -> u32;
+    }
+}
+
+pub mod c {
+    use super::redshirt_interface;
+
+    #[redshirt_interface]
+    pub trait Echo {
+        fn echo(&mut self, value: u64) -> u64;
+    }
+}
+
+struct Doubler;
+
+impl a::Echo for Doubler {
+    fn echo(&mut self, value: u32) -> u32 {
+        value * 2
+    }
+}
+
+#[test]
+fn hash_follows_signatures() {
+    assert_eq!(a::echo_interface::INTERFACE, b::echo_interface::INTERFACE);
+    assert_ne!(a::echo_interface::INTERFACE, c::echo_interface::INTERFACE);
+}
+
+#[test]
+fn serve_dispatches_and_leaves_other_interfaces() {
+    const OTHER: InterfaceHash = InterfaceHash::from_raw_hash([0xaa; 32]);
+    testing::reset();
+
+    // Serves the interface, until a message arrives on the other interface.
+    let program = std::thread::spawn(|| {
+        redshirt_syscalls::block_on(async {
+            let mut server = Doubler;
+            let serve = Box::pin(a::echo_interface::serve(&mut server));
+            let mut other = redshirt_syscalls::interface_messages(OTHER);
+            let message = match future::select(serve, other.next()).await {
+                future::Either::Right((Some(message), _)) => message,
+                _ => panic!(),
+            };
+            message
+        })
+    });
+
+    let register = loop {
+        if let Some(message) = testing::next_emitted_message() {
+            break message;
+        }
+    };
+    assert_eq!(
+        register.interface,
+        redshirt_interface_interface::ffi::INTERFACE
+    );
+    let response = redshirt_interface_interface::ffi::InterfaceRegisterResponse { result: Ok(()) };
+    testing::answer_message(register.message_id.unwrap(), Ok(response.encode()));
+
+    let message = a::echo_interface::Message::Echo { value: 21 };
+    let message_id = testing::emit_interface_message(
+        &a::echo_interface::INTERFACE,
+        Pid::from(5),
+        message.encode(),
+        true,
+    )
+    .unwrap();
+
+    let (answered, answer) = loop {
+        if let Some(answer) = testing::next_answer() {
+            break answer;
+        }
+    };
+    assert_eq!(answered, message_id);
+    assert_eq!(u32::decode_all(&answer.unwrap().0).unwrap(), 42);
+
+    // The message on the other interface must not be swallowed by `serve`.
+    testing::emit_interface_message(&OTHER, Pid::from(5), 12u32.encode(), false);
+    let received = program.join().unwrap();
+    assert_eq!(received.interface, OTHER);
+    assert_eq!(u32::decode(&mut &received.actual_data.0[..]).unwrap(), 12);
+}
@@ -0,0 +1,38 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use parity_scale_codec::{Decode, Encode};
+use redshirt_syscalls::{InterfaceHash, Pid};
+
+// TODO: this has been randomly generated; instead should be a hash or something
+pub const INTERFACE: InterfaceHash = InterfaceHash::from_raw_hash([
+    0xd5, 0x64, 0x6d, 0xd2, 0xa6, 0x1e, 0x93, 0x3b, 0x3e, 0x61, 0x4f, 0x25, 0xd0, 0x39, 0x23, 0xe7,
+    0xed, 0x19, 0x7d, 0x66, 0x3c, 0xfc, 0x2f, 0xa3, 0x98, 0xe2, 0xff, 0xc0, 0xda, 0x41, 0xc8, 0x94,
+]);
+
+#[derive(Debug, Encode, Decode)]
+pub enum InterfaceRegistryMessage {
+    /// Asks which process handles the given interface. Answered with a [`HandlerResponse`].
+    Query(InterfaceHash),
+    /// Waits until the process that handles the given interface is different from the given
+    /// one. Answered with a [`HandlerResponse`], immediately if it is already the case.
+    WaitChange(InterfaceHash, Option<Pid>),
+}
+
+#[derive(Debug, Encode, Decode)]
+pub struct HandlerResponse {
+    /// Process that handles the interface, or `None` if the interface isn't registered.
+    pub handler: Option<Pid>,
+}
@@ -0,0 +1,59 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Querying which interfaces are available.
+//!
+//! Lets a program find out whether an interface is currently handled, and by which process, in
+//! order for example to gracefully degrade when an optional interface isn't available, instead
+//! of waiting forever for a handler to be registered.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+pub use redshirt_syscalls::{InterfaceHash, Pid};
+
+pub mod ffi;
+
+/// Returns the process that currently handles the given interface, or `None` if the interface
+/// isn't registered.
+pub async fn handler(interface: &InterfaceHash) -> Option<Pid> {
+    let response: ffi::HandlerResponse = unsafe {
+        redshirt_syscalls::emit_message_with_response(
+            &ffi::INTERFACE,
+            ffi::InterfaceRegistryMessage::Query(interface.clone()),
+        )
+        .unwrap()
+        .await
+        .unwrap()
+    };
+    response.handler
+}
+
+/// Waits until the process that handles the given interface is different from `current`, and
+/// returns the new handler.
+///
+/// Pass `None` in order to wait for the interface to be registered, or the value previously
+/// returned by [`handler`] in order to be notified when the handler goes away or is replaced.
+pub async fn wait_change(interface: &InterfaceHash, current: Option<Pid>) -> Option<Pid> {
+    let response: ffi::HandlerResponse = unsafe {
+        redshirt_syscalls::emit_message_with_response(
+            &ffi::INTERFACE,
+            ffi::InterfaceRegistryMessage::WaitChange(interface.clone(), current),
+        )
+        .unwrap()
+        .await
+        .unwrap()
+    };
+    response.handler
+}
@@ -14,13 +14,20 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 //! Interfaces registration.
+//!
+//! A program that wants to serve an interface calls [`register_interface`] with the hash of
+//! this interface. Once the registration has succeeded, the kernel delivers to this program all
+//! the messages emitted on the interface. They can be retrieved with
+//! [`redshirt_syscalls::next_interface_message`] or [`redshirt_syscalls::interface_messages`],
+//! and answered with [`respond`] or [`respond_err`].
 
 #![no_std]
 
 use futures::prelude::*;
-use redshirt_syscalls::InterfaceHash;
+use redshirt_syscalls::{ErrorCode, ErrorEnvelope, InterfaceHash};
 
 pub use ffi::InterfaceRegisterError;
+pub use redshirt_syscalls::{respond, respond_err, ResponseResult};
 
 pub mod ffi;
 
@@ -38,6 +45,16 @@ pub fn register_interface(
     unsafe {
         redshirt_syscalls::emit_message_with_response(&ffi::INTERFACE, msg)
             .unwrap()
+            .map(Result::unwrap)
             .map(|response: ffi::InterfaceRegisterResponse| response.result)
     }
 }
+
+impl From<InterfaceRegisterError> for ErrorEnvelope {
+    fn from(err: InterfaceRegisterError) -> ErrorEnvelope {
+        let code = match err {
+            InterfaceRegisterError::AlreadyRegistered => ErrorCode::AlreadyExists,
+        };
+        ErrorEnvelope::new(code, "Interface already registered").with_payload(err)
+    }
+}
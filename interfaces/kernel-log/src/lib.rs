@@ -65,6 +65,7 @@ pub async fn configure_kernel(method: KernelLogMethod) {
             .add_data_raw(&encoded)
             .emit_with_response::<()>(&ffi::INTERFACE)
             .unwrap()
-            .await;
+            .await
+            .unwrap();
     }
 }
@@ -0,0 +1,50 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use parity_scale_codec::{Decode, Encode};
+use redshirt_syscalls::InterfaceHash;
+
+// TODO: this has been randomly generated; instead should be a hash or something
+pub const INTERFACE: InterfaceHash = InterfaceHash::from_raw_hash([
+    0xd5, 0x06, 0xd3, 0x63, 0x12, 0xb8, 0x20, 0x96, 0x77, 0xe8, 0xba, 0xb0, 0x7d, 0x06, 0xcf, 0x4f,
+    0xdf, 0x15, 0xfa, 0x8c, 0xe0, 0x92, 0x4f, 0xff, 0x7b, 0x9b, 0x0f, 0xb2, 0xbc, 0xb6, 0x78, 0xc2,
+]);
+
+#[derive(Debug, Encode, Decode)]
+pub enum LifecycleMessage {
+    /// Wait until the emitter of the message is asked to stop. Answered with a [`StopRequest`].
+    WaitStop,
+    /// The emitter of the message has finished preparing and can be stopped. No answer.
+    StopReady,
+}
+
+/// Sent to a process when it is asked to stop.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct StopRequest {
+    /// Why the process is being stopped.
+    pub reason: StopReason,
+    /// Value of the monotonic clock, in nanoseconds, after which the process gets aborted if it
+    /// hasn't indicated that it is ready to stop.
+    pub deadline: u64,
+}
+
+/// Reason why a process is asked to stop.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Encode, Decode)]
+pub enum StopReason {
+    /// The whole system is shutting down.
+    Shutdown,
+    /// Only this process is being stopped.
+    Kill,
+}
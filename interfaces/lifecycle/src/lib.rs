@@ -0,0 +1,58 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Notifications about the process being asked to stop.
+//!
+//! When the kernel shuts down or wants to stop a process, it can first give the process the
+//! opportunity to finish its work, for example to flush data to storage. Programs that want to
+//! be given this opportunity call [`stop_requested`] and wait for the returned future to finish.
+//! Once they are ready to be stopped, they call [`stop_ready`].
+//!
+//! Programs that don't wait for a stop request, or that don't call [`stop_ready`] before the
+//! deadline, are aborted.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+pub use ffi::{StopReason, StopRequest};
+
+pub mod ffi;
+
+/// Returns a future that resolves when the kernel asks the current process to stop.
+///
+/// The process can then do some cleaning up, and should call [`stop_ready`] once it's done.
+pub async fn stop_requested() -> StopRequest {
+    unsafe {
+        redshirt_syscalls::emit_message_with_response(
+            &ffi::INTERFACE,
+            ffi::LifecycleMessage::WaitStop,
+        )
+        .unwrap()
+        .await
+        .unwrap()
+    }
+}
+
+/// Notifies the kernel that the current process can now be stopped.
+///
+/// Has no effect if the kernel hasn't asked the process to stop.
+pub fn stop_ready() {
+    unsafe {
+        redshirt_syscalls::emit_message_without_response(
+            &ffi::INTERFACE,
+            ffi::LifecycleMessage::StopReady,
+        )
+        .unwrap();
+    }
+}
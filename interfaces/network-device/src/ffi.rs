@@ -0,0 +1,57 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use alloc::vec::Vec;
+use parity_scale_codec::{Decode, Encode};
+use redshirt_syscalls::InterfaceHash;
+
+// TODO: this has been randomly generated; instead should be a hash or something
+pub const INTERFACE: InterfaceHash = InterfaceHash::from_raw_hash([
+    0x14, 0x1d, 0xda, 0x0f, 0x96, 0xd7, 0xfd, 0x26, 0x93, 0x61, 0x75, 0x6a, 0xc8, 0x57, 0x81, 0x6b,
+    0x38, 0x7f, 0xde, 0x5a, 0x1d, 0x45, 0xcd, 0x84, 0xf8, 0xbd, 0x51, 0x6e, 0x43, 0xc1, 0x15, 0xa6,
+]);
+
+/// Message sent by a driver to the network stack.
+///
+/// Devices are identified by an `id` chosen by the driver. Ids only need to be unique amongst
+/// the devices of the same driver process. All the devices of a driver are unregistered when
+/// its process is destroyed.
+#[derive(Debug, Encode, Decode)]
+pub enum NetworkMessage {
+    /// Notify of the existence of a new Ethernet device. No response.
+    ///
+    /// The link of the device is considered up until the driver indicates otherwise with
+    /// [`NetworkMessage::SetLinkStatus`].
+    RegisterInterface { id: u64, mac_address: [u8; 6] },
+    /// Notify that a device previously registered no longer exists. No response.
+    UnregisterInterface { id: u64 },
+    /// Notify that the link of the device has gone up or down. No response.
+    ///
+    /// While the link is down, the device has no address, and no frame is sent or processed.
+    SetLinkStatus { id: u64, up: bool },
+    /// Notify that the device has received an Ethernet frame. No response.
+    InterfaceOnData { id: u64, frame: Vec<u8> },
+    /// Ask for the next Ethernet frame that the device must send. Answered with an
+    /// [`InterfaceWaitDataResponse`] once a frame is available.
+    ///
+    /// Only one such message should be pending at any given time for each device.
+    InterfaceWaitData { id: u64 },
+}
+
+#[derive(Debug, Encode, Decode)]
+pub struct InterfaceWaitDataResponse {
+    /// Ethernet frame to send, without the checksum.
+    pub frame: Vec<u8>,
+}
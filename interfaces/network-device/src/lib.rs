@@ -0,0 +1,101 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Network devices.
+//!
+//! This interface is implemented by the network stack and used by the drivers of network cards.
+//! A driver registers each of its devices, then passes to the network stack the Ethernet frames
+//! that the device receives, and asks for the Ethernet frames that the device must send.
+//!
+//! This decouples the drivers from the network stack: a driver only needs to move frames between
+//! the hardware and a [`NetworkDevice`].
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use core::{
+    future::Future,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+pub mod ffi;
+
+/// Identifier of the next device registered by this process.
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Network device registered towards the network stack.
+///
+/// The device is unregistered when this object is dropped.
+#[derive(Debug)]
+pub struct NetworkDevice {
+    id: u64,
+}
+
+impl NetworkDevice {
+    /// Registers a new Ethernet device with the given MAC address. Its link is initially
+    /// considered up.
+    pub fn new(mac_address: [u8; 6]) -> Self {
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+
+        unsafe {
+            let message = ffi::NetworkMessage::RegisterInterface { id, mac_address };
+            redshirt_syscalls::emit_message_without_response(&ffi::INTERFACE, message).unwrap();
+        }
+
+        NetworkDevice { id }
+    }
+
+    /// Indicates whether the link of the device is up.
+    pub fn set_link_up(&self, up: bool) {
+        unsafe {
+            let message = ffi::NetworkMessage::SetLinkStatus { id: self.id, up };
+            redshirt_syscalls::emit_message_without_response(&ffi::INTERFACE, message).unwrap();
+        }
+    }
+
+    /// Passes to the network stack an Ethernet frame received by the device.
+    pub fn on_frame_received(&self, frame: Vec<u8>) {
+        unsafe {
+            let message = ffi::NetworkMessage::InterfaceOnData { id: self.id, frame };
+            redshirt_syscalls::emit_message_without_response(&ffi::INTERFACE, message).unwrap();
+        }
+    }
+
+    /// Returns the next Ethernet frame that the device must send.
+    ///
+    /// Only one such `Future` should be alive at any given time.
+    pub fn next_frame_to_send(&self) -> impl Future<Output = Vec<u8>> {
+        let response = unsafe {
+            let message = ffi::NetworkMessage::InterfaceWaitData { id: self.id };
+            redshirt_syscalls::emit_message_with_response(&ffi::INTERFACE, message).unwrap()
+        };
+
+        async move {
+            let response: ffi::InterfaceWaitDataResponse = response.await.unwrap();
+            response.frame
+        }
+    }
+}
+
+impl Drop for NetworkDevice {
+    fn drop(&mut self) {
+        unsafe {
+            let message = ffi::NetworkMessage::UnregisterInterface { id: self.id };
+            let _ = redshirt_syscalls::emit_message_without_response(&ffi::INTERFACE, message);
+        }
+    }
+}
@@ -35,6 +35,7 @@ pub fn get_pci_devices() -> impl Future<Output = Vec<PciDeviceInfo>> {
         // TODO: don't unwrap?
         redshirt_syscalls::emit_message_with_response(&ffi::INTERFACE, msg)
             .unwrap()
+            .map(Result::unwrap)
             .map(|response: ffi::GetDevicesListResponse| response.devices)
     }
 }
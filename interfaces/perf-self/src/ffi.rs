@@ -0,0 +1,43 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use parity_scale_codec::{Decode, Encode};
+use redshirt_syscalls::InterfaceHash;
+
+// TODO: this has been randomly generated; instead should be a hash or something
+pub const INTERFACE: InterfaceHash = InterfaceHash::from_raw_hash([
+    0x8b, 0x84, 0x74, 0xcc, 0xb2, 0x2b, 0x46, 0xcd, 0x62, 0x88, 0x75, 0x38, 0x56, 0x02, 0x79, 0xf5,
+    0x5e, 0xb9, 0xd6, 0x02, 0x26, 0x96, 0xd1, 0x45, 0x7c, 0x6f, 0x44, 0xfb, 0xc2, 0x5d, 0xb5, 0x71,
+]);
+
+#[derive(Debug, Encode, Decode)]
+pub enum PerfSelfMessage {
+    /// Ask for the counters of the emitter of the message. Answered with a [`Counters`].
+    Counters,
+}
+
+/// Counters about the activity of a process since it has started.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Encode, Decode)]
+pub struct Counters {
+    /// Number of messages emitted by the process on interfaces.
+    pub messages_emitted: u64,
+    /// Total size, in bytes, of the messages emitted by the process.
+    pub message_bytes_emitted: u64,
+    /// Number of notifications delivered to the process. This includes messages received on
+    /// interfaces, responses to messages, and notifications about processes being destroyed.
+    pub notifications_received: u64,
+    /// Total size, in bytes, of the notifications delivered to the process.
+    pub notification_bytes_received: u64,
+}
@@ -0,0 +1,38 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Performance counters of the current process.
+//!
+//! Lets a program query statistics about its own activity, for example in order to report them
+//! or to shed load. A program can only ever access its own counters.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+pub use ffi::Counters;
+
+pub mod ffi;
+
+/// Returns the current values of the performance counters of the current process.
+pub async fn counters() -> Counters {
+    unsafe {
+        redshirt_syscalls::emit_message_with_response(
+            &ffi::INTERFACE,
+            ffi::PerfSelfMessage::Counters,
+        )
+        .unwrap()
+        .await
+        .unwrap()
+    }
+}
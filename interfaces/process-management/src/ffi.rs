@@ -0,0 +1,77 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use parity_scale_codec::{Decode, Encode};
+use redshirt_syscalls::{InterfaceHash, Pid};
+
+// TODO: this has been randomly generated; instead should be a hash or something
+pub const INTERFACE: InterfaceHash = InterfaceHash::from_raw_hash([
+    0xde, 0x35, 0x36, 0xa8, 0xba, 0x8c, 0x9d, 0x4b, 0xb8, 0xff, 0x2b, 0xf4, 0x03, 0x14, 0x82, 0x57,
+    0x20, 0xfd, 0x8f, 0xc8, 0x7f, 0x95, 0x75, 0x8e, 0xa1, 0xc6, 0xe3, 0x84, 0x72, 0x0d, 0xbc, 0xc5,
+]);
+
+#[derive(Debug, Encode, Decode)]
+pub enum ProcessManagementMessage {
+    /// Kill the process with the given [`Pid`]. Answered with a [`KillResponse`].
+    Kill(Pid),
+    /// Wait for the process with the given [`Pid`] to end. Answered with a [`WaitResponse`].
+    Wait(Pid),
+}
+
+#[derive(Debug, Encode, Decode)]
+pub struct KillResponse {
+    pub result: Result<(), KillError>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub enum KillError {
+    /// There is no process with this [`Pid`], or it isn't a program that can be killed.
+    NotFound,
+}
+
+#[derive(Debug, Encode, Decode)]
+pub struct WaitResponse {
+    pub result: Result<ExitStatus, WaitError>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub enum WaitError {
+    /// There is no process with this [`Pid`], or it isn't a program whose end can be waited for.
+    ///
+    /// > **Note**: This is also the case if the process has already ended.
+    NotFound,
+}
+
+/// How a process has ended.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub enum ExitStatus {
+    /// The process has called `exit`, or its main function has returned. A code of `0` indicates
+    /// a success, and any other value a failure.
+    Exited(u32),
+    /// The process has stopped because of an error, for example a panic.
+    Crashed,
+    /// The process has been killed.
+    Killed,
+    /// The process has been stopped because it has exceeded one of its resource limits, such as
+    /// the size of its memory.
+    LimitExceeded,
+}
+
+impl ExitStatus {
+    /// Returns `true` if the process has exited with a code of `0`.
+    pub fn is_success(&self) -> bool {
+        *self == ExitStatus::Exited(0)
+    }
+}
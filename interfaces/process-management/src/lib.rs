@@ -0,0 +1,61 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Management of the processes running on the system.
+//!
+//! Lets a program kill other processes, or wait for them to end. A killed process is stopped
+//! immediately, without being given the opportunity to finish its work, and the threads of other
+//! programs that were waiting for one of its answers get an error.
+//!
+//! > **Note**: Any program is currently allowed to kill any other program.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+pub use ffi::{ExitStatus, KillError, WaitError};
+pub use redshirt_syscalls::Pid;
+
+pub mod ffi;
+
+/// Kills the process with the given [`Pid`].
+///
+/// Returns an error if there is no such process.
+pub async fn kill(pid: Pid) -> Result<(), KillError> {
+    let response: ffi::KillResponse = unsafe {
+        redshirt_syscalls::emit_message_with_response(
+            &ffi::INTERFACE,
+            ffi::ProcessManagementMessage::Kill(pid),
+        )
+        .unwrap()
+        .await
+        .unwrap()
+    };
+    response.result
+}
+
+/// Waits for the process with the given [`Pid`] to end, and returns how it has ended.
+///
+/// Returns an error if there is no such process, including if it has already ended.
+pub async fn wait(pid: Pid) -> Result<ExitStatus, WaitError> {
+    let response: ffi::WaitResponse = unsafe {
+        redshirt_syscalls::emit_message_with_response(
+            &ffi::INTERFACE,
+            ffi::ProcessManagementMessage::Wait(pid),
+        )
+        .unwrap()
+        .await
+        .unwrap()
+    };
+    response.result
+}
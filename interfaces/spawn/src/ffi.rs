@@ -0,0 +1,62 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use alloc::vec::Vec;
+use parity_scale_codec::{Decode, Encode};
+use redshirt_syscalls::{InterfaceHash, Pid};
+
+// TODO: this has been randomly generated; instead should be a hash or something
+pub const INTERFACE: InterfaceHash = InterfaceHash::from_raw_hash([
+    0xe0, 0xa7, 0xd3, 0x3d, 0xbe, 0xdd, 0x35, 0x35, 0x7d, 0xd3, 0x9f, 0x90, 0x5b, 0x27, 0x7b, 0xa4,
+    0x5f, 0xa1, 0xc6, 0x79, 0x7f, 0xf8, 0x46, 0xcd, 0x09, 0x8c, 0xe4, 0xcf, 0x37, 0xb3, 0x9e, 0x52,
+]);
+
+#[derive(Debug, Encode, Decode)]
+pub enum SpawnMessage {
+    /// Starts a new process, child of the emitter. Answered with a [`SpawnResponse`].
+    Spawn {
+        /// Where to find the module of the new process.
+        module: ModuleSource,
+        /// Arguments passed to the new process. Must be empty at the moment, otherwise the
+        /// message is answered with [`SpawnError::ArgumentsNotSupported`].
+        args: Vec<Vec<u8>>,
+    },
+}
+
+#[derive(Debug, Encode, Decode)]
+pub enum ModuleSource {
+    /// Binary of the module.
+    Bytes(Vec<u8>),
+    /// Blake3 hash of the module, which the kernel fetches through the `loader` interface.
+    Hash([u8; 32]),
+}
+
+#[derive(Debug, Encode, Decode)]
+pub struct SpawnResponse {
+    pub result: Result<Pid, SpawnError>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub enum SpawnError {
+    /// The module couldn't be found through the `loader` interface.
+    NotFound,
+    /// The module is invalid, or can't be instantiated.
+    InvalidModule,
+    /// The system only accepts modules signed by a trusted key, and the module isn't.
+    Untrusted,
+    /// Arguments have been passed, but the system doesn't support passing arguments to new
+    /// processes yet.
+    ArgumentsNotSupported,
+}
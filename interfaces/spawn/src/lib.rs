@@ -0,0 +1,51 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Starting new processes.
+//!
+//! Lets a program start another program, either from the binary of its module or from the hash
+//! of its module. The new process is a child of the program that has started it.
+//!
+//! If the program that starts the new process is restricted to a list of interfaces, the new
+//! process is restricted to the same list. A program therefore can't escape its restrictions by
+//! starting another one.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+pub use ffi::{ModuleSource, SpawnError};
+pub use redshirt_syscalls::Pid;
+
+use alloc::vec::Vec;
+
+pub mod ffi;
+
+/// Starts a new process from the given module, and returns its [`Pid`].
+///
+/// > **Note**: Passing arguments to the new process isn't supported yet. If `args` isn't empty,
+/// >           [`SpawnError::ArgumentsNotSupported`] is returned.
+pub async fn spawn(module: ModuleSource, args: Vec<Vec<u8>>) -> Result<Pid, SpawnError> {
+    let response: ffi::SpawnResponse = unsafe {
+        redshirt_syscalls::emit_message_with_response(
+            &ffi::INTERFACE,
+            ffi::SpawnMessage::Spawn { module, args },
+        )
+        .unwrap()
+        .await
+        .unwrap()
+    };
+    response.result
+}
@@ -0,0 +1,86 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::mutex::MutexGuard;
+
+use core::{
+    fmt,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+/// Condition variable, letting threads wait for an event while releasing a [`Mutex`].
+///
+/// As with the standard library, spurious wake-ups can happen, and the condition that is
+/// waited for should be checked again after [`Condvar::wait`] returns.
+///
+/// > **Note**: Waiting threads aren't put to sleep. They repeatedly yield to the other threads
+/// >           until a notification is emitted, and keep consuming CPU time in the meantime.
+/// >           Every notification, including [`Condvar::notify_one`], wakes up all the waiting
+/// >           threads.
+///
+/// [`Mutex`]: crate::Mutex
+pub struct Condvar {
+    /// Incremented every time a notification is emitted.
+    generation: AtomicU32,
+}
+
+impl Condvar {
+    /// Builds a new condition variable.
+    pub const fn new() -> Self {
+        Condvar {
+            generation: AtomicU32::new(0),
+        }
+    }
+
+    /// Unlocks the mutex of the guard, waits for a notification, then locks the mutex again.
+    ///
+    /// The thread yields to the other threads in a loop until the notification is emitted.
+    pub fn wait<'a, T: ?Sized>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        let mutex = guard.mutex();
+        let generation = self.generation.load(Ordering::Acquire);
+        drop(guard);
+
+        while self.generation.load(Ordering::Acquire) == generation {
+            redshirt_syscalls::yield_now();
+        }
+
+        mutex.lock()
+    }
+
+    /// Wakes up one of the threads waiting on this condition variable.
+    ///
+    /// > **Note**: This currently wakes all waiters. This is allowed by the fact that spurious
+    /// >           wake-ups can happen, but all of them then compete for the mutex.
+    pub fn notify_one(&self) {
+        self.notify_all()
+    }
+
+    /// Wakes up all the threads waiting on this condition variable.
+    pub fn notify_all(&self) {
+        self.generation.fetch_add(1, Ordering::Release);
+    }
+}
+
+impl Default for Condvar {
+    fn default() -> Self {
+        Condvar::new()
+    }
+}
+
+impl fmt::Debug for Condvar {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Condvar").finish()
+    }
+}
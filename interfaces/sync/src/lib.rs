@@ -0,0 +1,35 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Synchronization primitives for programs running on redshirt.
+//!
+//! This crate provides a [`Mutex`], a [`Condvar`] and a [`Once`], similar to the ones of the
+//! standard library, that can be used from any program targeting redshirt.
+//!
+//! > **Note**: The kernel doesn't provide any futex-like way to put a thread to sleep until
+//! >           another thread wakes it up. Threads that have to wait instead repeatedly call
+//! >           [`yield_now`](redshirt_syscalls::yield_now) in order to let the other threads
+//! >           make progress.
+// TODO: block on FutexWait/FutexWake messages once the kernel supports them
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+pub use self::condvar::Condvar;
+pub use self::mutex::{Mutex, MutexGuard};
+pub use self::once::Once;
+
+mod condvar;
+mod mutex;
+mod once;
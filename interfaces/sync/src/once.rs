@@ -0,0 +1,96 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use core::{
+    fmt,
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+const INCOMPLETE: u8 = 0;
+const RUNNING: u8 = 1;
+const COMPLETE: u8 = 2;
+
+/// Runs a piece of initialization code exactly once.
+pub struct Once {
+    /// One of [`INCOMPLETE`], [`RUNNING`] or [`COMPLETE`].
+    state: AtomicU8,
+}
+
+impl Once {
+    /// Builds a new `Once` whose initialization code hasn't run yet.
+    pub const fn new() -> Self {
+        Once {
+            state: AtomicU8::new(INCOMPLETE),
+        }
+    }
+
+    /// Runs `f` if this is the first call to `call_once`. If another thread is currently running
+    /// its own closure, waits for it to finish.
+    ///
+    /// > **Note**: If `f` panics, the `Once` stays in a state where all subsequent calls wait
+    /// >           forever.
+    pub fn call_once(&self, f: impl FnOnce()) {
+        loop {
+            match self.state.compare_exchange(
+                INCOMPLETE,
+                RUNNING,
+                Ordering::Acquire,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => {
+                    f();
+                    self.state.store(COMPLETE, Ordering::Release);
+                    return;
+                }
+                Err(COMPLETE) => return,
+                Err(_) => redshirt_syscalls::yield_now(),
+            }
+        }
+    }
+
+    /// Returns true if a call to [`Once::call_once`] has finished.
+    pub fn is_completed(&self) -> bool {
+        self.state.load(Ordering::Acquire) == COMPLETE
+    }
+}
+
+impl Default for Once {
+    fn default() -> Self {
+        Once::new()
+    }
+}
+
+impl fmt::Debug for Once {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Once")
+            .field("completed", &self.is_completed())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Once;
+
+    #[test]
+    fn runs_once() {
+        let once = Once::new();
+        let mut calls = 0;
+        once.call_once(|| calls += 1);
+        once.call_once(|| calls += 1);
+        assert_eq!(calls, 1);
+        assert!(once.is_completed());
+    }
+}
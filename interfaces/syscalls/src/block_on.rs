@@ -35,17 +35,20 @@
 //!
 
 use crate::{
-    ffi, DecodedInterfaceOrDestroyed, DecodedNotification, DecodedResponseNotification, MessageId,
+    ffi, DecodedInterfaceNotification, DecodedInterfaceOrDestroyed, DecodedNotification,
+    DecodedResponseNotification, InterfaceHash, MessageId,
 };
 use alloc::{collections::VecDeque, sync::Arc, vec::Vec};
 use core::{
+    iter,
     sync::atomic::{AtomicBool, Ordering},
     task::{Context, Poll, Waker},
 };
 use futures::{prelude::*, task};
-use hashbrown::HashMap;
+use hashbrown::{HashMap, HashSet};
 use nohash_hasher::BuildNoHashHasher;
 use slab::Slab;
+use smallvec::SmallVec;
 use spinning_top::Spinlock;
 
 /// Registers a message ID (or 1 for interface messages) and a waker. The `block_on` function will
@@ -55,26 +58,144 @@ use spinning_top::Spinlock;
 /// For non-interface messages, there can only ever be one registered `Waker`. Registering a
 /// `Waker` a second time overrides the one previously registered.
 pub(crate) fn register_message_waker(message_id: MessageId, waker: Waker) -> WakerRegistration {
+    register_messages_waker(iter::once(message_id), waker)
+}
+
+/// Same as [`register_message_waker`], but registers the same `Waker` for multiple message IDs
+/// at once. The `Waker` is called when any of them is received.
+pub(crate) fn register_messages_waker(
+    message_ids: impl IntoIterator<Item = MessageId>,
+    waker: Waker,
+) -> WakerRegistration {
     let mut state = (&*STATE).lock();
+    #[cfg(feature = "diagnostics")]
+    let now = state.clock.map(|clock| clock());
 
-    let index = state.wakers.insert(Some(waker));
+    let indices = message_ids
+        .into_iter()
+        .map(|message_id| {
+            let index = state.wakers.insert(Some(waker.clone()));
 
-    if state.message_ids.len() <= index {
-        state.message_ids.resize(index + 1, 0);
-    }
+            if state.message_ids.len() <= index {
+                state.message_ids.resize(index + 1, 0);
+            }
+
+            debug_assert_eq!(state.message_ids[index], 0);
+            state.message_ids[index] = From::from(message_id);
+            if u64::from(message_id) == 1 {
+                state.interface_wakers.insert(index);
+            }
 
-    debug_assert_eq!(state.message_ids[index], 0);
-    state.message_ids[index] = From::from(message_id);
+            #[cfg(feature = "diagnostics")]
+            {
+                if state.registered_at.len() <= index {
+                    state.registered_at.resize(index + 1, (0, None));
+                }
+                state.registered_at[index] = (state.ticks, now);
+            }
+
+            index
+        })
+        .collect();
 
-    WakerRegistration { index }
+    WakerRegistration { indices }
 }
 
+/// Maximum number of notifications kept in [`BlockOnState::interface_messages_queue`]. Beyond
+/// this, the oldest ones are discarded.
+pub(crate) const MAX_UNCLAIMED_NOTIFICATIONS: usize = 1024;
+
 /// Removes one element from the global buffer of interface messages waiting to be processed.
 pub(crate) fn peek_interface_message() -> Option<DecodedInterfaceOrDestroyed> {
     let mut state = (&*STATE).lock();
     state.interface_messages_queue.pop_front()
 }
 
+/// Removes the oldest message waiting to be processed that concerns the given interface. The
+/// interface must have been passed to [`register_interface_stream`].
+pub(crate) fn peek_interface_message_for(
+    interface: &InterfaceHash,
+) -> Option<DecodedInterfaceNotification> {
+    let mut state = (&*STATE).lock();
+    let stream = state
+        .streamed_interfaces
+        .iter_mut()
+        .find(|s| s.interface == *interface)?;
+    stream.queue.pop_front()
+}
+
+/// Indicates that a stream of the messages of the given interface exists. These messages are
+/// from now on kept apart from the other notifications, until
+/// [`unregister_interface_stream`] is called as many times as this function.
+pub(crate) fn register_interface_stream(interface: &InterfaceHash) {
+    let mut state = (&*STATE).lock();
+    if let Some(stream) = state
+        .streamed_interfaces
+        .iter_mut()
+        .find(|s| s.interface == *interface)
+    {
+        stream.num_streams += 1;
+        return;
+    }
+
+    // Messages that have arrived before the stream has been created are moved to its queue.
+    let mut queue = VecDeque::new();
+    let mut remaining = VecDeque::with_capacity(state.interface_messages_queue.len());
+    for notification in state.interface_messages_queue.drain(..) {
+        match notification {
+            DecodedInterfaceOrDestroyed::Interface(msg) if msg.interface == *interface => {
+                queue.push_back(msg)
+            }
+            other => remaining.push_back(other),
+        }
+    }
+    state.interface_messages_queue = remaining;
+
+    state.streamed_interfaces.push(InterfaceStream {
+        interface: interface.clone(),
+        num_streams: 1,
+        queue,
+    });
+}
+
+/// Opposite of [`register_interface_stream`]. Once no stream remains, the messages that haven't
+/// been processed are handed back to [`peek_interface_message`].
+pub(crate) fn unregister_interface_stream(interface: &InterfaceHash) {
+    let mut state = (&*STATE).lock();
+    let position = match state
+        .streamed_interfaces
+        .iter()
+        .position(|s| s.interface == *interface)
+    {
+        Some(p) => p,
+        None => unreachable!(),
+    };
+
+    state.streamed_interfaces[position].num_streams -= 1;
+    if state.streamed_interfaces[position].num_streams == 0 {
+        let stream = state.streamed_interfaces.swap_remove(position);
+        for msg in stream.queue {
+            push_unclaimed(&mut state, DecodedInterfaceOrDestroyed::Interface(msg));
+        }
+    }
+}
+
+/// Pushes a notification to [`BlockOnState::interface_messages_queue`], discarding the oldest
+/// one if the queue is full. Discarded messages that expect an answer are answered with an
+/// error, so that their emitter doesn't wait forever.
+fn push_unclaimed(state: &mut BlockOnState, notification: DecodedInterfaceOrDestroyed) {
+    state.interface_messages_queue.push_back(notification);
+    while state.interface_messages_queue.len() > MAX_UNCLAIMED_NOTIFICATIONS {
+        if let Some(DecodedInterfaceOrDestroyed::Interface(DecodedInterfaceNotification {
+            message_id: Some(message_id),
+            ..
+        })) = state.interface_messages_queue.pop_front()
+        {
+            crate::emit_message_error(message_id);
+        }
+    }
+}
+
 /// If a response to this message ID has previously been obtained, extracts it for processing.
 pub(crate) fn peek_response(msg_id: MessageId) -> Option<DecodedResponseNotification> {
     let mut state = (&*STATE).lock();
@@ -82,17 +203,19 @@ pub(crate) fn peek_response(msg_id: MessageId) -> Option<DecodedResponseNotifica
 }
 
 pub(crate) struct WakerRegistration {
-    /// Index within `STATE::message_ids` and `STATE::wakers`.
-    index: usize,
+    /// Indices within `STATE::message_ids` and `STATE::wakers`.
+    indices: SmallVec<[usize; 1]>,
 }
 
 impl WakerRegistration {
     /// Modifies the registered waker.
     pub fn update(&self, waker: &Waker) {
         let mut state = (&*STATE).lock();
-        match &mut state.wakers[self.index] {
-            Some(w) if w.will_wake(waker) => {}
-            w @ _ => *w = Some(waker.clone()),
+        for index in &self.indices {
+            match &mut state.wakers[*index] {
+                Some(w) if w.will_wake(waker) => {}
+                w @ _ => *w = Some(waker.clone()),
+            }
         }
     }
 }
@@ -100,17 +223,54 @@ impl WakerRegistration {
 impl Drop for WakerRegistration {
     fn drop(&mut self) {
         let mut state = (&*STATE).lock();
-        state.message_ids[self.index] = 0;
-        state.wakers.remove(self.index);
+        for index in &self.indices {
+            state.message_ids[*index] = 0;
+            state.wakers.remove(*index);
+            state.interface_wakers.remove(index);
+        }
 
         // Reclaim memory if possible.
         if state.wakers.is_empty() {
             state.wakers.shrink_to_fit();
             state.message_ids = Vec::new();
+            #[cfg(feature = "diagnostics")]
+            {
+                state.registered_at = Vec::new();
+            }
+        } else {
+            // The list passed to the kernel is scanned in its entirety every time we ask for a
+            // notification. Remove the unused entries at its end in order to keep it short.
+            while !state.wakers.contains(state.message_ids.len() - 1) {
+                state.message_ids.pop();
+            }
         }
     }
 }
 
+/// Lets the kernel run the other threads that are ready before continuing.
+///
+/// Unlike [`block_on`], this doesn't wait for any notification. Programs that perform long
+/// computations can call this function from time to time in order to not monopolize the CPU.
+///
+/// > **Note**: Programs that want to wait for a certain amount of time should instead use the
+/// >           `monotonic_wait` function of the `redshirt-time-interface` crate, which puts the
+/// >           thread to sleep until the time has elapsed rather than repeatedly yielding.
+pub fn yield_now() {
+    #[cfg(target_arch = "wasm32")] // TODO: we should have a proper operating system name instead
+    fn imp() {
+        unsafe { ffi::yield_now() }
+    }
+    #[cfg(all(not(target_arch = "wasm32"), feature = "testing"))]
+    fn imp() {
+        // The mock kernel doesn't schedule the code under test.
+    }
+    #[cfg(all(not(target_arch = "wasm32"), not(feature = "testing")))]
+    fn imp() {
+        unreachable!()
+    }
+    imp()
+}
+
 /// Blocks the current thread until the [`Future`](core::future::Future) passed as parameter
 /// finishes.
 pub fn block_on<T>(future: impl Future<Output = T>) -> T {
@@ -147,52 +307,106 @@ pub fn block_on<T>(future: impl Future<Output = T>) -> T {
             }
         }
 
-        let mut state = (&*STATE).lock();
-        debug_assert_eq!(state.message_ids.len(), state.wakers.len());
-
-        // `block` indicates whether we should block the thread or just peek. Always `true` during
-        // the first iteration, and `false` in further iterations.
-        let mut block = true;
-
-        // We process in a loop all pending messages.
-        while let Some(msg) = next_notification(&mut state.message_ids, block) {
-            block = false;
-
-            match msg {
-                DecodedNotification::Response(msg) => {
-                    // Value is zero-ed by the kernel.
-                    debug_assert_eq!(state.message_ids[msg.index_in_list as usize], 0);
-                    if let Some(waker) = state.wakers[msg.index_in_list as usize].take() {
-                        waker.wake();
-                    }
+        let _processed = process_notifications(true);
+        debug_assert!(_processed);
+    }
+}
 
-                    let _was_in = state.pending_messages.insert(msg.message_id, msg);
-                    debug_assert!(_was_in.is_none());
-                }
-                DecodedNotification::Interface(msg) => {
-                    // Value is zero-ed by the kernel.
-                    debug_assert_eq!(state.message_ids[msg.index_in_list as usize], 0);
-                    if let Some(waker) = state.wakers[msg.index_in_list as usize].take() {
-                        waker.wake();
-                    }
+/// Asks the kernel for notifications, and wakes up the `Future`s of this crate that they
+/// concern. Returns `true` if at least one notification has been processed.
+///
+/// If `block` is true, the thread is paused until at least one notification is available, and
+/// the return value is always `true`. If `block` is false, only the notifications that are
+/// already available are processed.
+///
+/// [`block_on`] calls this function whenever the `Future` it is blocking upon is pending. Custom
+/// executors must do the same, for example when they don't have any task ready to be polled,
+/// otherwise the `Future`s of this crate never get woken up.
+pub fn process_notifications(block: bool) -> bool {
+    let mut state = (&*STATE).lock();
+    debug_assert!(state.message_ids.len() >= state.wakers.len());
+    #[cfg(feature = "diagnostics")]
+    {
+        state.ticks += 1;
+    }
 
-                    let msg = DecodedInterfaceOrDestroyed::Interface(msg);
-                    state.interface_messages_queue.push_back(msg);
-                }
-                DecodedNotification::ProcessDestroyed(msg) => {
-                    // Value is zero-ed by the kernel.
-                    debug_assert_eq!(state.message_ids[msg.index_in_list as usize], 0);
-                    if let Some(waker) = state.wakers[msg.index_in_list as usize].take() {
-                        waker.wake();
+    // `block` indicates whether we should block the thread or just peek. Set to `false` after
+    // the first notification.
+    let mut block = block;
+    let mut processed = false;
+    // Wakers are invoked only after the lock has been released, as they might poll futures.
+    let mut to_wake = SmallVec::<[Waker; 4]>::new();
+
+    // We process in a loop all pending messages.
+    while let Some(msg) = next_notification(&mut state.message_ids, block) {
+        block = false;
+        processed = true;
+
+        match msg {
+            DecodedNotification::Response(msg) => {
+                // Value is zero-ed by the kernel.
+                debug_assert_eq!(state.message_ids[msg.index_in_list as usize], 0);
+                to_wake.extend(state.wakers[msg.index_in_list as usize].take());
+
+                let _was_in = state.pending_messages.insert(msg.message_id, msg);
+                debug_assert!(_was_in.is_none());
+            }
+            DecodedNotification::Interface(msg) => {
+                // Value is zero-ed by the kernel.
+                debug_assert_eq!(state.message_ids[msg.index_in_list as usize], 0);
+                to_wake.extend(state.wakers[msg.index_in_list as usize].take());
+
+                take_interface_messages_wakers(&mut state, &mut to_wake);
+
+                match state
+                    .streamed_interfaces
+                    .iter_mut()
+                    .find(|s| s.interface == msg.interface)
+                {
+                    Some(stream) => stream.queue.push_back(msg),
+                    None => {
+                        let msg = DecodedInterfaceOrDestroyed::Interface(msg);
+                        push_unclaimed(&mut state, msg);
                     }
-
-                    let msg = DecodedInterfaceOrDestroyed::ProcessDestroyed(msg);
-                    state.interface_messages_queue.push_back(msg);
                 }
-            };
-        }
+            }
+            DecodedNotification::ProcessDestroyed(msg) => {
+                // Value is zero-ed by the kernel.
+                debug_assert_eq!(state.message_ids[msg.index_in_list as usize], 0);
+                to_wake.extend(state.wakers[msg.index_in_list as usize].take());
 
-        debug_assert!(!block);
+                take_interface_messages_wakers(&mut state, &mut to_wake);
+
+                let msg = DecodedInterfaceOrDestroyed::ProcessDestroyed(msg);
+                push_unclaimed(&mut state, msg);
+            }
+        };
+    }
+
+    drop(state);
+    for waker in to_wake {
+        waker.wake();
+    }
+
+    processed
+}
+
+/// Extracts all the wakers waiting for an interface message or a process destroyed
+/// notification, and pushes them to `out`.
+///
+/// The kernel only indicates one of the futures waiting for such a notification, but some of
+/// these futures, such as the streams returned by
+/// [`interface_messages`](crate::interface_messages), only accept a subset of the
+/// notifications. All of them therefore need to check the new notification.
+fn take_interface_messages_wakers(state: &mut BlockOnState, out: &mut impl Extend<Waker>) {
+    let BlockOnState {
+        interface_wakers,
+        wakers,
+        ..
+    } = state;
+
+    for index in interface_wakers.iter() {
+        out.extend(wakers[*index].take());
     }
 }
 
@@ -203,8 +417,16 @@ lazy_static::lazy_static! {
         Spinlock::new(BlockOnState {
             message_ids: Vec::new(),
             wakers: Slab::new(),
+            interface_wakers: HashSet::with_hasher(Default::default()),
             pending_messages: HashMap::with_capacity_and_hasher(6, Default::default()),
             interface_messages_queue: VecDeque::with_capacity(2),
+            streamed_interfaces: Vec::new(),
+            #[cfg(feature = "diagnostics")]
+            registered_at: Vec::new(),
+            #[cfg(feature = "diagnostics")]
+            ticks: 0,
+            #[cfg(feature = "diagnostics")]
+            clock: None,
         })
     };
 }
@@ -217,11 +439,18 @@ struct BlockOnState {
     /// to the kernel.
     message_ids: Vec<u64>,
 
-    /// List whose length is identical to [`BlockOnState::message_ids`]. For each element in
-    /// [`BlockOnState::message_ids`], contains a corresponding `Waker` that must be waken up
-    /// when a response comes.
+    /// For each element in [`BlockOnState::message_ids`] that is in use, contains a
+    /// corresponding `Waker` that must be waken up when a response comes. The keys of this slab
+    /// are indices within [`BlockOnState::message_ids`].
     wakers: Slab<Option<Waker>>,
 
+    /// Keys within [`BlockOnState::wakers`] of the registrations waiting for an interface
+    /// message, in other words whose value in [`BlockOnState::message_ids`] is 1.
+    ///
+    /// Kept separately so that waking them up doesn't require going through all the
+    /// registrations, of which there can be thousands if many responses are being waited for.
+    interface_wakers: HashSet<usize, BuildNoHashHasher<usize>>,
+
     /// Queue of response messages waiting to be delivered.
     ///
     /// > **Note**: We have to maintain this queue as a global variable rather than a per-future
@@ -229,12 +458,114 @@ struct BlockOnState {
     /// >           already been received.
     pending_messages: HashMap<MessageId, DecodedResponseNotification, BuildNoHashHasher<u64>>,
 
-    /// Queue of interface messages waiting to be delivered.
+    /// Queue of interface messages waiting to be delivered, except for the ones of the
+    /// interfaces in [`BlockOnState::streamed_interfaces`]. Contains at most
+    /// [`MAX_UNCLAIMED_NOTIFICATIONS`] elements.
     ///
     /// > **Note**: We have to maintain this queue as a global variable rather than a per-future
     /// >           channel, otherwise dropping a `Future` would silently drop messages that have
     /// >           already been received.
     interface_messages_queue: VecDeque<DecodedInterfaceOrDestroyed>,
+
+    /// Interfaces for which at least one stream returned by
+    /// [`interface_messages`](crate::interface_messages) exists, and their messages waiting to
+    /// be delivered. Programs only ever serve a handful of interfaces, which is why this isn't
+    /// a map.
+    streamed_interfaces: Vec<InterfaceStream>,
+
+    /// List whose length is superior or equal to the one of [`BlockOnState::message_ids`]. For
+    /// each element in [`BlockOnState::message_ids`], contains the value of
+    /// [`BlockOnState::ticks`] and of [`BlockOnState::clock`] when it has been registered.
+    #[cfg(feature = "diagnostics")]
+    registered_at: Vec<(u64, Option<u64>)>,
+
+    /// Number of times [`block_on`] has asked the kernel for notifications.
+    #[cfg(feature = "diagnostics")]
+    ticks: u64,
+
+    /// Function returning the current time in nanoseconds. See
+    /// [`set_clock`](crate::diagnostics::set_clock).
+    #[cfg(feature = "diagnostics")]
+    clock: Option<fn() -> u64>,
+}
+
+/// Messages of an interface for which a stream exists. See
+/// [`BlockOnState::streamed_interfaces`].
+struct InterfaceStream {
+    interface: InterfaceHash,
+    /// Number of streams of this interface that currently exist.
+    num_streams: usize,
+    /// Messages waiting to be yielded by one of the streams.
+    queue: VecDeque<DecodedInterfaceNotification>,
+}
+
+/// Discards the notifications that have been received but not processed yet. Used to reset the
+/// mock kernel between tests.
+#[cfg(all(not(target_arch = "wasm32"), feature = "testing"))]
+pub(crate) fn clear_queues() {
+    let mut state = (&*STATE).lock();
+    state.pending_messages.clear();
+    state.interface_messages_queue.clear();
+    for stream in &mut state.streamed_interfaces {
+        stream.queue.clear();
+    }
+}
+
+/// Returns the number of notifications about interfaces that have been received but not
+/// processed yet.
+#[cfg(all(test, not(target_arch = "wasm32"), feature = "testing"))]
+pub(crate) fn num_pending_interface_messages() -> usize {
+    let state = (&*STATE).lock();
+    state.interface_messages_queue.len()
+        + state
+            .streamed_interfaces
+            .iter()
+            .map(|s| s.queue.len())
+            .sum::<usize>()
+}
+
+/// Sets the function used to timestamp the registrations.
+#[cfg(feature = "diagnostics")]
+pub(crate) fn set_clock(clock: fn() -> u64) {
+    let mut state = (&*STATE).lock();
+    state.clock = Some(clock);
+}
+
+/// Returns the current state of the `block_on` mechanism.
+#[cfg(feature = "diagnostics")]
+pub(crate) fn reactor_state() -> crate::diagnostics::ReactorState {
+    let state = (&*STATE).lock();
+    let now = state.clock.map(|clock| clock());
+
+    let registrations = state
+        .wakers
+        .iter()
+        .filter(|(index, _)| state.message_ids[*index] != 0)
+        .map(|(index, waker)| crate::diagnostics::Registration {
+            message_id: match state.message_ids[index] {
+                1 => None,
+                id => Some(MessageId::from(id)),
+            },
+            age_nanos: match (now, state.registered_at[index].1) {
+                (Some(now), Some(registered_at)) => Some(now.saturating_sub(registered_at)),
+                _ => None,
+            },
+            age_ticks: state.ticks - state.registered_at[index].0,
+            waker_registered: waker.is_some(),
+        })
+        .collect();
+
+    crate::diagnostics::ReactorState {
+        ticks: state.ticks,
+        registrations,
+        pending_responses: state.pending_messages.len() as u32,
+        pending_interface_messages: (state.interface_messages_queue.len()
+            + state
+                .streamed_interfaces
+                .iter()
+                .map(|s| s.queue.len())
+                .sum::<usize>()) as u32,
+    }
 }
 
 /// Checks whether a new message arrives, optionally blocking the thread.
@@ -271,7 +602,12 @@ fn next_notification_impl(to_poll: &mut [u64], block: bool) -> Option<DecodedNot
     }
 }
 
-#[cfg(not(target_arch = "wasm32"))]
+#[cfg(all(not(target_arch = "wasm32"), feature = "testing"))]
+fn next_notification_impl(to_poll: &mut [u64], block: bool) -> Option<DecodedNotification> {
+    crate::testing::next_notification(to_poll, block)
+}
+
+#[cfg(all(not(target_arch = "wasm32"), not(feature = "testing")))]
 fn next_notification_impl(_: &mut [u64], _: bool) -> Option<DecodedNotification> {
     unimplemented!()
 }
@@ -0,0 +1,61 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Capabilities.
+//!
+//! A process can be restricted to a list of interfaces when it is spawned. Such a process can
+//! only emit messages on these interfaces, plus the ones that other processes delegate to it.
+//! Processes that aren't restricted can emit messages on any interface.
+
+use crate::{InterfaceHash, Pid};
+
+use core::fmt;
+
+/// Grants the process `target` the right to emit messages on `interface`.
+///
+/// Fails if `target` doesn't exist, or if the current process doesn't itself have the right to
+/// emit messages on `interface`. Delegating to a process that isn't restricted succeeds and has
+/// no effect.
+pub fn delegate_capability(target: Pid, interface: &InterfaceHash) -> Result<(), DelegateErr> {
+    #[cfg(target_arch = "wasm32")] // TODO: we should have a proper operating system name instead
+    fn imp(target: Pid, interface: &InterfaceHash) -> Result<(), DelegateErr> {
+        let ret = unsafe { crate::ffi::delegate_capability(u64::from(target), interface) };
+        if ret == 0 {
+            Ok(())
+        } else {
+            Err(DelegateErr)
+        }
+    }
+    #[cfg(all(not(target_arch = "wasm32"), feature = "testing"))]
+    fn imp(_: Pid, _: &InterfaceHash) -> Result<(), DelegateErr> {
+        // The mock kernel doesn't restrict the interfaces that can be used.
+        Ok(())
+    }
+    #[cfg(all(not(target_arch = "wasm32"), not(feature = "testing")))]
+    fn imp(_: Pid, _: &InterfaceHash) -> Result<(), DelegateErr> {
+        unreachable!()
+    }
+    imp(target, interface)
+}
+
+/// Error that can be returned by [`delegate_capability`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DelegateErr;
+
+impl fmt::Display for DelegateErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Failed to delegate the capability")
+    }
+}
@@ -0,0 +1,79 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Reporting panics to the kernel.
+//!
+//! When a program panics, the kernel normally only sees the process trapping. Calling
+//! [`report_crash`] from the panic hook of the program sends the panic message and location to
+//! the kernel beforehand, so that the kernel can report why the program has crashed.
+//!
+//! When the `std` feature is enabled, [`install_panic_hook`] installs such a hook.
+
+use crate::InterfaceHash;
+use alloc::string::{String, ToString as _};
+
+// TODO: this has been randomly generated; instead should be a hash or something
+pub const INTERFACE: InterfaceHash = InterfaceHash::from_raw_hash([
+    0xf2, 0xd3, 0x54, 0x24, 0x32, 0xcd, 0xfb, 0x80, 0xa2, 0x65, 0xf8, 0x7b, 0x3a, 0x23, 0x0b, 0xcf,
+    0xa9, 0x4f, 0xee, 0x8a, 0xd0, 0xfe, 0xf6, 0xce, 0x26, 0x88, 0x2b, 0x9f, 0x22, 0xb8, 0xc7, 0x0f,
+]);
+
+/// Message sent on [`INTERFACE`] by a process that is about to crash. No answer is expected.
+#[derive(Debug, Clone, PartialEq, Eq, parity_scale_codec::Encode, parity_scale_codec::Decode)]
+pub struct CrashReport {
+    /// Message passed to `panic!`.
+    pub message: String,
+    /// File where the panic happened, or empty if unknown.
+    pub file: String,
+    /// Line where the panic happened, or 0 if unknown.
+    pub line: u32,
+}
+
+/// Sends a [`CrashReport`] to the kernel.
+///
+/// Meant to be called when the program panics, right before it aborts.
+pub fn report_crash(message: String, file: &str, line: u32) {
+    let report = CrashReport {
+        message,
+        file: file.to_string(),
+        line,
+    };
+
+    // Errors are ignored, as there isn't anything better to do while panicking.
+    let _ = unsafe { crate::emit_message_without_response(&INTERFACE, report) };
+}
+
+/// Installs a panic hook that calls [`report_crash`], then the hook that was previously
+/// installed.
+#[cfg(feature = "std")]
+pub fn install_panic_hook() {
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(alloc::boxed::Box::new(move |info| {
+        let message = if let Some(s) = info.payload().downcast_ref::<&str>() {
+            (*s).to_string()
+        } else if let Some(s) = info.payload().downcast_ref::<String>() {
+            s.clone()
+        } else {
+            info.to_string()
+        };
+
+        match info.location() {
+            Some(location) => report_crash(message, location.file(), location.line()),
+            None => report_crash(message, "", 0),
+        }
+
+        previous(info);
+    }));
+}
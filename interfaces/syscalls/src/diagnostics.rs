@@ -0,0 +1,76 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Introspection of the futures waiting for notifications.
+//!
+//! When a program seems stuck, it is generally waiting for a response that never comes. The
+//! [`reactor_state`] function returns the list of messages whose response is being waited upon,
+//! and for how long they have been waited upon.
+//!
+//! This crate has no access to a clock on its own. Ages are measured in nanoseconds if the
+//! program has passed a clock to [`set_clock`], and in any case in "ticks", where a tick is one
+//! request for notifications made to the kernel by [`block_on`](crate::block_on).
+//!
+//! [`ReactorState`] can be encoded. The `diagnostics` interface lets a program send it to the
+//! tools that inspect the state of programs from the outside.
+//!
+//! This module is only available if the `diagnostics` feature is enabled, as keeping track of
+//! this information has a small cost.
+
+use crate::MessageId;
+use alloc::vec::Vec;
+
+/// Snapshot of the state of the futures waiting for notifications.
+#[derive(Debug, Clone, PartialEq, Eq, parity_scale_codec::Encode, parity_scale_codec::Decode)]
+pub struct ReactorState {
+    /// Number of times [`block_on`](crate::block_on) has asked the kernel for notifications.
+    pub ticks: u64,
+    /// Notifications currently waited upon, in no particular order.
+    pub registrations: Vec<Registration>,
+    /// Number of responses that have been received but not processed yet.
+    pub pending_responses: u32,
+    /// Number of interface messages that have been received but not processed yet.
+    pub pending_interface_messages: u32,
+}
+
+/// Notification waited upon.
+#[derive(Debug, Clone, PartialEq, Eq, parity_scale_codec::Encode, parity_scale_codec::Decode)]
+pub struct Registration {
+    /// Message whose response is waited upon, or `None` for interface messages and process
+    /// destroyed notifications.
+    pub message_id: Option<MessageId>,
+    /// Number of nanoseconds since the future has started waiting, or `None` if no clock has
+    /// been passed to [`set_clock`] at the time.
+    pub age_nanos: Option<u64>,
+    /// Number of ticks since the future has started waiting.
+    pub age_ticks: u64,
+    /// False if the notification has already woken up the future, but the future hasn't been
+    /// polled again yet.
+    pub waker_registered: bool,
+}
+
+/// Returns the current state of the futures waiting for notifications.
+pub fn reactor_state() -> ReactorState {
+    crate::block_on::reactor_state()
+}
+
+/// Sets the function that returns the current time, in nanoseconds, used to measure how long
+/// futures have been waiting. Only futures that start waiting afterwards are measured.
+///
+/// The function is called every time a future starts waiting, and must not itself wait for a
+/// notification. On WASI targets, `std::time::Instant` can be used.
+pub fn set_clock(clock: fn() -> u64) {
+    crate::block_on::set_clock(clock)
+}
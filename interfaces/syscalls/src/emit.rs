@@ -13,9 +13,9 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use crate::{Decode, Encode, EncodedMessage, InterfaceHash, MessageId};
+use crate::{Decode, Encode, EncodedMessage, InterfaceHash, MessageId, ResponseErr};
+use alloc::vec::Vec;
 use core::{
-    convert::TryFrom as _,
     fmt,
     marker::PhantomData,
     mem::MaybeUninit,
@@ -32,11 +32,15 @@ use generic_array::{
 /// Prototype for a message in construction.
 ///
 /// Use this struct if you want to send out a message split between multiple slices.
-pub struct MessageBuilder<'a, TLen: ArrayLength<u32>> {
+pub struct MessageBuilder<'a, TLen: ArrayLength<usize>> {
     /// Parameter for the FFI function.
     allow_delay: bool,
     /// Array of slices, passed to the FFI function.
-    array: GenericArray<u32, TLen>,
+    ///
+    /// > **Note**: The FFI function expects 32 bits values. Pointers are 32 bits on the platforms
+    /// >           where the FFI function exists, but we use `usize` in order to be able to
+    /// >           store host pointers when the kernel is mocked.
+    array: GenericArray<usize, TLen>,
     /// Pin the lifetime. The lifetime corresponds to the lifetime of buffers pointer to
     /// within `array`.
     marker: PhantomData<&'a ()>,
@@ -55,7 +59,7 @@ impl<'a> MessageBuilder<'a, U0> {
 
 impl<'a, TLen> MessageBuilder<'a, TLen>
 where
-    TLen: ArrayLength<u32>,
+    TLen: ArrayLength<usize>,
 {
     /// If called, emitting the message will fail if no interface handler is available. Otherwise,
     /// emitting the message will block the thread until a handler is available.
@@ -71,7 +75,7 @@ where
     pub fn add_data<TOutLen>(self, buffer: &'a EncodedMessage) -> MessageBuilder<'a, TOutLen>
     where
         TLen: core::ops::Add<U2, Output = TOutLen>,
-        TOutLen: ArrayLength<u32>,
+        TOutLen: ArrayLength<usize>,
     {
         self.add_data_raw(&buffer.0)
     }
@@ -83,11 +87,11 @@ where
     pub fn add_data_raw<TOutLen>(self, buffer: &'a [u8]) -> MessageBuilder<'a, TOutLen>
     where
         TLen: core::ops::Add<U2, Output = TOutLen>,
-        TOutLen: ArrayLength<u32>,
+        TOutLen: ArrayLength<usize>,
     {
-        let mut new_pair = GenericArray::<u32, U2>::default();
-        new_pair[0] = u32::try_from(buffer.as_ptr() as usize).unwrap();
-        new_pair[1] = u32::try_from(buffer.len()).unwrap();
+        let mut new_pair = GenericArray::<usize, U2>::default();
+        new_pair[0] = buffer.as_ptr() as usize;
+        new_pair[1] = buffer.len();
 
         MessageBuilder {
             allow_delay: self.allow_delay,
@@ -96,12 +100,13 @@ where
         }
     }
 
-    /// Emit the message and returns a `Future` that will yield the response.
+    /// Emit the message and returns a `Future` that will yield the response, or an error if the
+    /// handler indicates that the message was erroneous or the response couldn't be decoded.
     // TODO: could we remove the error type?
     pub unsafe fn emit_with_response<T>(
         self,
         interface: &InterfaceHash,
-    ) -> Result<impl Future<Output = T>, EmitErr>
+    ) -> Result<impl Future<Output = Result<T, ResponseErr>>, EmitErr>
     where
         T: Decode,
     {
@@ -150,11 +155,13 @@ where
         interface: &InterfaceHash,
         needs_answer: bool,
     ) -> Result<Option<MessageId>, EmitErr> {
+        use core::convert::TryFrom as _;
+
         let mut message_id_out = MaybeUninit::uninit();
 
         let ret = crate::ffi::emit_message(
-            interface as *const InterfaceHash as *const _,
-            self.array.as_ptr(),
+            interface,
+            self.array.as_ptr() as *const u32,
             u32::try_from(self.array.len() / 2).unwrap(),
             needs_answer,
             self.allow_delay,
@@ -166,13 +173,32 @@ where
         }
 
         if needs_answer {
-            Ok(Some(MessageId::from(message_id_out.assume_init())))
+            Ok(Some(message_id_out.assume_init()))
         } else {
             Ok(None)
         }
     }
 
-    #[cfg(not(target_arch = "wasm32"))]
+    #[cfg(all(not(target_arch = "wasm32"), feature = "testing"))]
+    unsafe fn emit_raw_impl(
+        self,
+        interface: &InterfaceHash,
+        needs_answer: bool,
+    ) -> Result<Option<MessageId>, EmitErr> {
+        let mut message = alloc::vec::Vec::new();
+        for slice in self.array.chunks(2) {
+            let slice = core::slice::from_raw_parts(slice[0] as *const u8, slice[1]);
+            message.extend_from_slice(slice);
+        }
+
+        Ok(crate::testing::emit_message(
+            interface,
+            EncodedMessage(message),
+            needs_answer,
+        ))
+    }
+
+    #[cfg(all(not(target_arch = "wasm32"), not(feature = "testing")))]
     unsafe fn emit_raw_impl(
         self,
         _: &InterfaceHash,
@@ -190,7 +216,7 @@ impl<'a> Default for MessageBuilder<'a, U0> {
 
 impl<'a, TLen> fmt::Debug for MessageBuilder<'a, TLen>
 where
-    TLen: ArrayLength<u32>,
+    TLen: ArrayLength<usize>,
 {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_tuple("MessageBuilder").finish()
@@ -226,7 +252,9 @@ pub unsafe fn emit_message_without_response<'a>(
 /// Whether this function succeeds only depends on whether an interface handler is available. This
 /// function doesn't perform any validity check on the message itself.
 ///
-/// The returned future will cancel the message if it is dropped early.
+/// The returned future yields an error if the handler indicates that the message was erroneous,
+/// or if the response can't be decoded into `T`. It will cancel the message if it is dropped
+/// early.
 ///
 /// # Safety
 ///
@@ -236,22 +264,111 @@ pub unsafe fn emit_message_without_response<'a>(
 pub unsafe fn emit_message_with_response<'a, T: Decode>(
     interface: &InterfaceHash,
     msg: impl Encode,
-) -> Result<impl Future<Output = T>, EmitErr> {
+) -> Result<impl Future<Output = Result<T, ResponseErr>>, EmitErr> {
     let msg = msg.encode();
     MessageBuilder::new()
         .add_data(&msg)
         .emit_with_response(interface)
 }
 
+/// Maximum number of messages that the kernel accepts in a single call to
+/// `emit_messages_batch`.
+#[cfg(target_arch = "wasm32")]
+const MAX_BATCH_LEN: usize = 512;
+
+/// Emits multiple messages with a single call to the kernel, or a few calls if there are a lot of
+/// messages.
+///
+/// Each element of `messages` contains the interface to emit the message on, the body of the
+/// message, and whether the message needs an answer. Returns, for each message that has been
+/// emitted, the identifier of the message if it needs an answer.
+///
+/// The messages are emitted in order, and the thread blocks if necessary until a handler is
+/// available for the interfaces. If a message fails to be emitted, then the ones that follow
+/// aren't emitted either and the returned `Vec` is shorter than `messages`.
+///
+/// # Safety
+///
+/// While the action of sending a message is totally safe, the message itself might instruct the
+/// environment to perform actions that would lead to unsafety.
+///
+pub unsafe fn emit_messages_batch(
+    messages: &[(InterfaceHash, &[u8], bool)],
+) -> Vec<Option<MessageId>> {
+    #[cfg(target_arch = "wasm32")] // TODO: we should have a proper operating system name instead
+    unsafe fn imp(messages: &[(InterfaceHash, &[u8], bool)]) -> Vec<Option<MessageId>> {
+        use core::convert::TryFrom as _;
+
+        let mut out = Vec::with_capacity(messages.len());
+
+        // The kernel kills processes that emit more than `MAX_BATCH_LEN` messages at once.
+        for batch in messages.chunks(MAX_BATCH_LEN) {
+            let mut msgs_ptrs = Vec::with_capacity(batch.len() * 4);
+            for (interface, message, needs_answer) in batch {
+                msgs_ptrs.push(interface as *const InterfaceHash as u32);
+                msgs_ptrs.push(message.as_ptr() as u32);
+                msgs_ptrs.push(u32::try_from(message.len()).unwrap());
+                msgs_ptrs.push(if *needs_answer { 1 } else { 0 });
+            }
+
+            let mut message_ids = alloc::vec![MessageId::from(0); batch.len()];
+            let num_emitted = crate::ffi::emit_messages_batch(
+                msgs_ptrs.as_ptr(),
+                u32::try_from(batch.len()).unwrap(),
+                message_ids.as_mut_ptr(),
+            );
+            let num_emitted = usize::try_from(num_emitted).unwrap();
+
+            out.extend(batch.iter().zip(message_ids).take(num_emitted).map(
+                |((_, _, needs_answer), id)| {
+                    if *needs_answer {
+                        Some(id)
+                    } else {
+                        None
+                    }
+                },
+            ));
+
+            if num_emitted != batch.len() {
+                break;
+            }
+        }
+
+        out
+    }
+    #[cfg(all(not(target_arch = "wasm32"), feature = "testing"))]
+    unsafe fn imp(messages: &[(InterfaceHash, &[u8], bool)]) -> Vec<Option<MessageId>> {
+        messages
+            .iter()
+            .map(|(interface, message, needs_answer)| {
+                crate::testing::emit_message(
+                    interface,
+                    EncodedMessage(message.to_vec()),
+                    *needs_answer,
+                )
+            })
+            .collect()
+    }
+    #[cfg(all(not(target_arch = "wasm32"), not(feature = "testing")))]
+    unsafe fn imp(_: &[(InterfaceHash, &[u8], bool)]) -> Vec<Option<MessageId>> {
+        unimplemented!()
+    }
+    imp(messages)
+}
+
 /// Cancel the given message. No answer will be received.
 ///
 /// Has no effect if the message is invalid.
 pub fn cancel_message(message_id: MessageId) {
     #[cfg(target_arch = "wasm32")] // TODO: we should have a proper operating system name instead
     fn imp(message_id: MessageId) {
-        unsafe { crate::ffi::cancel_message(&u64::from(message_id)) }
+        unsafe { crate::ffi::cancel_message(&message_id) }
+    }
+    #[cfg(all(not(target_arch = "wasm32"), feature = "testing"))]
+    fn imp(message_id: MessageId) {
+        crate::testing::cancel_message(message_id)
     }
-    #[cfg(not(target_arch = "wasm32"))]
+    #[cfg(all(not(target_arch = "wasm32"), not(feature = "testing")))]
     fn imp(message_id: MessageId) {
         unreachable!()
     }
@@ -276,15 +393,15 @@ impl fmt::Display for EmitErr {
 /// Future that drives [`emit_message_with_response`] to completion.
 #[must_use]
 #[pin_project::pin_project(PinnedDrop)]
-pub struct EmitMessageWithResponse<T> {
+pub struct EmitMessageWithResponse<F> {
     #[pin]
-    inner: Option<crate::MessageResponseFuture<T>>,
+    inner: Option<F>,
     // TODO: redundant with `inner`
     msg_id: MessageId,
 }
 
-impl<T: Decode> Future for EmitMessageWithResponse<T> {
-    type Output = T;
+impl<F: Future> Future for EmitMessageWithResponse<F> {
+    type Output = F::Output;
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
         unsafe {
@@ -298,14 +415,14 @@ impl<T: Decode> Future for EmitMessageWithResponse<T> {
                 Poll::Ready(val) => val,
                 Poll::Pending => return Poll::Pending,
             };
-            *this.inner = None;
+            this.inner.set(None);
             Poll::Ready(val)
         }
     }
 }
 
 #[pin_project::pinned_drop]
-impl<T> PinnedDrop for EmitMessageWithResponse<T> {
+impl<F> PinnedDrop for EmitMessageWithResponse<F> {
     fn drop(self: Pin<&mut Self>) {
         if self.inner.is_some() {
             let _ = cancel_message(self.msg_id);
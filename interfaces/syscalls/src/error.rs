@@ -0,0 +1,126 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Standard way to report errors when answering a message.
+//!
+//! Interface handlers are free to answer messages in any way they want. However, most interfaces
+//! need to report errors, and letting each of them invent its own error type makes it hard for
+//! clients to handle errors consistently, for example in order to log them.
+//!
+//! This module provides an [`ErrorEnvelope`] containing an [`ErrorCode`] common to all
+//! interfaces, an optional payload specific to the interface, and a message meant for humans.
+//! Interfaces that use it answer with a [`ResponseResult`], which handlers can produce with
+//! [`respond`](crate::respond) and [`respond_err`](crate::respond_err).
+//!
+//! The envelope is a convention between handlers and emitters, and the kernel isn't aware of
+//! it: an error is delivered as a regular answer. Emitters must decode the answer as a
+//! [`ResponseResult`], for example with [`message_result`](crate::message_result), in
+//! order to see the error. The kernel-level [`emit_message_error`](crate::emit_message_error)
+//! remains the way to indicate that a message is malformed.
+
+use crate::{Decode, Encode, EncodedMessage};
+
+use alloc::{string::String, vec::Vec};
+use core::fmt;
+
+/// Answer to a message of an interface that uses the standard error envelope.
+///
+/// > **Note**: An `Err` is encoded the same way no matter what `T` is. An error can therefore be
+/// >           sent without knowing the type of the successful answer.
+pub type ResponseResult<T> = Result<T, ErrorEnvelope>;
+
+/// Error returned by an interface handler.
+#[derive(Debug, Clone, PartialEq, Eq, parity_scale_codec::Encode, parity_scale_codec::Decode)]
+pub struct ErrorEnvelope {
+    /// Category of the error, common to all interfaces.
+    pub code: ErrorCode,
+    /// Encoded interface-specific details about the error. Empty if there isn't any.
+    pub payload: Vec<u8>,
+    /// Description of the error, meant to be read by humans.
+    pub message: String,
+}
+
+/// Category of an error, common to all interfaces.
+#[derive(
+    Debug, Copy, Clone, PartialEq, Eq, parity_scale_codec::Encode, parity_scale_codec::Decode,
+)]
+pub enum ErrorCode {
+    /// The message couldn't be decoded or has invalid parameters.
+    InvalidMessage,
+    /// The object that the message refers to doesn't exist.
+    NotFound,
+    /// The object that the message tries to create already exists.
+    AlreadyExists,
+    /// The emitter isn't allowed to perform this operation.
+    PermissionDenied,
+    /// The handler has run out of some resource.
+    ResourceExhausted,
+    /// The operation is temporarily impossible. Emitting the same message later might succeed.
+    Unavailable,
+    /// The operation isn't supported by this handler.
+    Unsupported,
+    /// Error in the handler itself.
+    Internal,
+    /// Interface-specific error code.
+    Other(u32),
+}
+
+impl ErrorEnvelope {
+    /// Builds a new envelope with an empty payload.
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        ErrorEnvelope {
+            code,
+            payload: Vec::new(),
+            message: message.into(),
+        }
+    }
+
+    /// Sets the interface-specific payload of the error.
+    pub fn with_payload(mut self, payload: impl Encode) -> Self {
+        self.payload = payload.encode().0;
+        self
+    }
+
+    /// Decodes the interface-specific payload of the error.
+    pub fn payload<T: Decode>(&self) -> Result<T, T::Error> {
+        T::decode(EncodedMessage(self.payload.clone()))
+    }
+}
+
+impl fmt::Display for ErrorEnvelope {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.message.is_empty() {
+            write!(f, "{:?}", self.code)
+        } else {
+            write!(f, "{:?}: {}", self.code, self.message)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ErrorCode, ErrorEnvelope, ResponseResult};
+    use crate::{Decode as _, Encode as _};
+
+    #[test]
+    fn err_independent_of_ok_type() {
+        let error = ErrorEnvelope::new(ErrorCode::NotFound, "no such file").with_payload(12u32);
+        let encoded = Err::<(), _>(error.clone()).encode();
+
+        let decoded = ResponseResult::<u64>::decode(encoded).unwrap();
+        assert_eq!(decoded, Err(error.clone()));
+        assert_eq!(decoded.unwrap_err().payload::<u32>(), Ok(12));
+    }
+}
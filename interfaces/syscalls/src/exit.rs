@@ -0,0 +1,35 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+/// Ends the current process with the given code. A code of `0` indicates a success, and any
+/// other value a failure.
+///
+/// All the threads of the process are stopped immediately, and destructors aren't run.
+pub fn exit(code: u32) -> ! {
+    #[cfg(target_arch = "wasm32")] // TODO: we should have a proper operating system name instead
+    fn imp(code: u32) -> ! {
+        unsafe { crate::ffi::exit(code) }
+    }
+    #[cfg(all(not(target_arch = "wasm32"), feature = "testing"))]
+    fn imp(code: u32) -> ! {
+        // The mock kernel has no process to end.
+        panic!("Process exited with code {}", code)
+    }
+    #[cfg(all(not(target_arch = "wasm32"), not(feature = "testing")))]
+    fn imp(_: u32) -> ! {
+        unreachable!()
+    }
+    imp(code)
+}
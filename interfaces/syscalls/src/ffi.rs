@@ -16,6 +16,7 @@
 use crate::{EncodedMessage, InterfaceHash, MessageId, Pid};
 
 use alloc::vec::Vec;
+use smallvec::SmallVec;
 
 #[cfg(target_arch = "wasm32")] // TODO: we should have a proper operating system name instead
 #[link(wasm_import_module = "redshirt")]
@@ -74,7 +75,7 @@ extern "C" {
     ///
     /// Returns `0` on success, and `1` in case of error.
     ///
-    /// On success, if `needs_answer` is true, will write the ID of new event into the memory
+    /// On success, if `needs_answer` is true, will write the ID of new message into the memory
     /// pointed by `message_id_out`.
     ///
     /// If `allow_delay` is true, the kernel is allowed to block the thread in order to
@@ -87,12 +88,38 @@ extern "C" {
     /// function is running.
     // TODO: document error that can happen
     pub(crate) fn emit_message(
-        interface_hash: *const u8,
+        interface_hash: *const InterfaceHash,
         msg_bufs_ptrs: *const u32,
         msg_bufs_num: u32,
         needs_answer: bool,
         allow_delay: bool,
-        message_id_out: *mut u64,
+        message_id_out: *mut MessageId,
+    ) -> u32;
+
+    /// Sends multiple messages at once. Equivalent to calling `emit_message` multiple times,
+    /// with `allow_delay` set to true.
+    ///
+    /// `msgs_num` must be at most 512. The process is killed if this isn't the case.
+    ///
+    /// The memory area pointed to by `msgs_ptrs` must contain a list of `msgs_num` groups of four
+    /// 32-bits values encoded in little endian. Each group is composed of a pointer to an
+    /// interface hash, a pointer to the message body, the length of the message body, and a
+    /// value that is `1` if the message needs an answer or `0` otherwise.
+    ///
+    /// For each message that needs an answer, the ID of the message is written in the memory
+    /// pointed by `message_ids_out` at the same index as the message in the list. In other
+    /// words, `message_ids_out` must point to a buffer of `msgs_num` 64-bits values.
+    ///
+    /// The messages are emitted in order. If a message fails to be emitted, then the ones that
+    /// follow aren't emitted either. Returns the number of messages that have been emitted.
+    ///
+    /// When this function is being called, a "lock" is being held on the memory pointed by
+    /// `msgs_ptrs`, `message_ids_out`, and all the buffers referred to within `msgs_ptrs`. In
+    /// particular, it is invalid to modify these buffers while the function is running.
+    pub(crate) fn emit_messages_batch(
+        msgs_ptrs: *const u32,
+        msgs_num: u32,
+        message_ids_out: *mut MessageId,
     ) -> u32;
 
     /// Sends an answer back to the emitter of given `message_id`.
@@ -100,7 +127,37 @@ extern "C" {
     /// When this function is being called, a "lock" is being held on the memory pointed by
     /// `message_id` and `msg`. In particular, it is invalid to modify these buffers while the
     /// function is running.
-    pub(crate) fn emit_answer(message_id: *const u64, msg: *const u8, msg_len: u32);
+    pub(crate) fn emit_answer(message_id: *const MessageId, msg: *const u8, msg_len: u32);
+
+    /// Sends one of the answers to the given `message_id`. Contrary to `emit_answer`, the message
+    /// can still be answered afterwards. Use `close_answer` to indicate that no more answer will
+    /// be sent.
+    ///
+    /// The emitter of the message receives each answer as a response notification.
+    ///
+    /// Returns `0` on success, and `1` if the message doesn't exist or if its emitter can't
+    /// receive multiple answers, which is the case of the programs that aren't processes. The
+    /// message can then still be answered with `emit_answer`.
+    ///
+    /// When this function is being called, a "lock" is being held on the memory pointed by
+    /// `message_id` and `msg`. In particular, it is invalid to modify these buffers while the
+    /// function is running.
+    pub(crate) fn emit_answer_chunk(
+        message_id: *const MessageId,
+        msg: *const u8,
+        msg_len: u32,
+    ) -> u32;
+
+    /// Indicates that no more answer will be sent to the given `message_id`.
+    ///
+    /// The emitter of the message receives this as a response notification whose
+    /// [`closed`](DecodedResponseNotification::closed) field is true. If the emitter isn't a
+    /// process, it instead receives the same error as with `emit_message_error`.
+    ///
+    /// When this function is being called, a "lock" is being held on the memory pointed by
+    /// `message_id`. In particular, it is invalid to modify this buffer while the function is
+    /// running.
+    pub(crate) fn close_answer(message_id: *const MessageId);
 
     /// Notifies the kernel that the given message is invalid and cannot reasonably be answered.
     ///
@@ -110,7 +167,7 @@ extern "C" {
     /// When this function is being called, a "lock" is being held on the memory pointed by
     /// `message_id`. In particular, it is invalid to modify these buffers while the function is
     /// running.
-    pub(crate) fn emit_message_error(message_id: *const u64);
+    pub(crate) fn emit_message_error(message_id: *const MessageId);
 
     /// Cancel an expected answer.
     ///
@@ -123,7 +180,30 @@ extern "C" {
     /// When this function is being called, a "lock" is being held on the memory pointed by
     /// `message_id`. In particular, it is invalid to modify this buffer while the function is
     /// running.
-    pub(crate) fn cancel_message(message_id: *const u64);
+    pub(crate) fn cancel_message(message_id: *const MessageId);
+
+    /// Gives the kernel the opportunity to run other threads.
+    ///
+    /// The calling thread isn't put to sleep and stays ready to run, but the kernel runs all the
+    /// other threads that are ready before resuming it.
+    pub(crate) fn yield_now();
+
+    /// Ends the current process with the given code. A code of `0` indicates a success, and any
+    /// other value a failure.
+    ///
+    /// All the threads of the process are stopped, and this function never returns.
+    pub(crate) fn exit(code: u32) -> !;
+
+    /// Grants the process `target` the right to emit messages on the interface whose hash is
+    /// pointed to by `interface_hash`.
+    ///
+    /// Returns `0` on success, and `1` if `target` doesn't exist or if the current process
+    /// doesn't itself have the right to emit messages on that interface.
+    ///
+    /// When this function is being called, a "lock" is being held on the memory pointed by
+    /// `interface_hash`. In particular, it is invalid to modify this buffer while the function
+    /// is running.
+    pub(crate) fn delegate_capability(target: u64, interface_hash: *const InterfaceHash) -> u32;
 }
 
 /// Prototype for a message.
@@ -147,6 +227,15 @@ impl NotificationBuilder {
         }
     }
 
+    /// Returns the encoded message, without consuming the builder.
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            NotificationBuilder::Interface(msg) => msg.as_bytes(),
+            NotificationBuilder::Response(msg) => msg.as_bytes(),
+            NotificationBuilder::ProcessDestroyed(msg) => msg.as_bytes(),
+        }
+    }
+
     // TODO: change to a more strongly typed API
     pub fn into_bytes(self) -> Vec<u8> {
         match self {
@@ -198,6 +287,17 @@ pub enum DecodedNotification {
     ProcessDestroyed(DecodedProcessDestroyedNotification),
 }
 
+/// Number of bytes a notification builder can hold before its storage is moved to the heap.
+///
+/// Most messages (futex operations, timer requests, acknowledgements, ...) are small. This
+/// capacity is large enough for the header of an interface notification (53 bytes) followed
+/// with a 64 bytes body, which means that building and queueing these notifications doesn't
+/// require any heap allocation.
+pub const NOTIFICATION_INLINE_CAPACITY: usize = 128;
+
+/// Storage for the content of a notification in construction.
+type NotificationBuffer = SmallVec<[u8; NOTIFICATION_INLINE_CAPACITY]>;
+
 // TODO: all the decoding performs unaligned reads, which isn't great
 
 /// Attempt to decode a notification.
@@ -233,7 +333,7 @@ pub fn build_interface_notification(
     index_in_list: u32,
     actual_data: &EncodedMessage,
 ) -> InterfaceNotificationBuilder {
-    let mut buffer = Vec::with_capacity(1 + 32 + 8 + 8 + 4 + actual_data.0.len());
+    let mut buffer = NotificationBuffer::with_capacity(1 + 32 + 8 + 8 + 4 + actual_data.0.len());
     buffer.push(0);
     buffer.extend_from_slice(&interface.0);
     buffer.extend_from_slice(&message_id.map(u64::from).unwrap_or(0).to_le_bytes());
@@ -241,13 +341,14 @@ pub fn build_interface_notification(
     buffer.extend_from_slice(&index_in_list.to_le_bytes());
     buffer.extend_from_slice(&actual_data.0);
 
-    debug_assert_eq!(buffer.capacity(), buffer.len());
+    debug_assert!(buffer.spilled() || buffer.len() <= NOTIFICATION_INLINE_CAPACITY);
+    debug_assert!(!buffer.spilled() || buffer.capacity() == buffer.len());
     InterfaceNotificationBuilder { data: buffer }
 }
 
 #[derive(Debug, Clone)]
 pub struct InterfaceNotificationBuilder {
-    data: Vec<u8>,
+    data: NotificationBuffer,
 }
 
 impl InterfaceNotificationBuilder {
@@ -256,12 +357,37 @@ impl InterfaceNotificationBuilder {
         self.data[49..53].copy_from_slice(&value.to_le_bytes());
     }
 
+    /// Returns the `message_id` field of the message.
+    pub fn message_id(&self) -> Option<MessageId> {
+        let id = u64::from_le_bytes([
+            self.data[33],
+            self.data[34],
+            self.data[35],
+            self.data[36],
+            self.data[37],
+            self.data[38],
+            self.data[39],
+            self.data[40],
+        ]);
+
+        if id == 0 {
+            None
+        } else {
+            Some(From::from(id))
+        }
+    }
+
     pub fn len(&self) -> usize {
         self.data.len()
     }
 
+    /// Returns the encoded message, without consuming the builder.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
     pub fn into_bytes(self) -> Vec<u8> {
-        self.data
+        self.data.into_vec()
     }
 }
 
@@ -322,8 +448,9 @@ pub fn build_response_notification(
     index_in_list: u32,
     actual_data: Result<&EncodedMessage, ()>,
 ) -> ResponseNotificationBuilder {
-    let mut buffer =
-        Vec::with_capacity(1 + 8 + 4 + 1 + actual_data.map(|m| m.0.len()).unwrap_or(0));
+    let mut buffer = NotificationBuffer::with_capacity(
+        1 + 8 + 4 + 1 + actual_data.map(|m| m.0.len()).unwrap_or(0),
+    );
     buffer.push(1);
     buffer.extend_from_slice(&u64::from(message_id).to_le_bytes());
     buffer.extend_from_slice(&index_in_list.to_le_bytes());
@@ -334,13 +461,31 @@ pub fn build_response_notification(
         buffer.push(1);
     }
 
-    debug_assert_eq!(buffer.capacity(), buffer.len());
+    debug_assert!(buffer.spilled() || buffer.len() <= NOTIFICATION_INLINE_CAPACITY);
+    debug_assert!(!buffer.spilled() || buffer.capacity() == buffer.len());
+    ResponseNotificationBuilder { data: buffer }
+}
+
+/// Builds the notification indicating that no more answer will be sent to a message that has
+/// been answered with `emit_answer_chunk`.
+pub fn build_response_closed_notification(
+    message_id: MessageId,
+    index_in_list: u32,
+) -> ResponseNotificationBuilder {
+    let mut buffer = NotificationBuffer::with_capacity(1 + 8 + 4 + 1);
+    buffer.push(1);
+    buffer.extend_from_slice(&u64::from(message_id).to_le_bytes());
+    buffer.extend_from_slice(&index_in_list.to_le_bytes());
+    buffer.push(2);
+
+    debug_assert!(buffer.spilled() || buffer.len() <= NOTIFICATION_INLINE_CAPACITY);
+    debug_assert!(!buffer.spilled() || buffer.capacity() == buffer.len());
     ResponseNotificationBuilder { data: buffer }
 }
 
 #[derive(Debug, Clone)]
 pub struct ResponseNotificationBuilder {
-    data: Vec<u8>,
+    data: NotificationBuffer,
 }
 
 impl ResponseNotificationBuilder {
@@ -366,8 +511,13 @@ impl ResponseNotificationBuilder {
         self.data.len()
     }
 
+    /// Returns the encoded message, without consuming the builder.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
     pub fn into_bytes(self) -> Vec<u8> {
-        self.data
+        self.data.into_vec()
     }
 }
 
@@ -384,6 +534,7 @@ pub fn decode_response_notification(buffer: &[u8]) -> Result<DecodedResponseNoti
     if !success && buffer.len() != 1 + 8 + 4 + 1 {
         return Err(());
     }
+    let closed = buffer[13] == 2;
 
     Ok(DecodedResponseNotification {
         message_id: From::from(u64::from_le_bytes([
@@ -395,6 +546,7 @@ pub fn decode_response_notification(buffer: &[u8]) -> Result<DecodedResponseNoti
         } else {
             Err(())
         },
+        closed,
     })
 }
 
@@ -410,26 +562,32 @@ pub struct DecodedResponseNotification {
     ///
     /// - The interface handler has crashed.
     /// - The interface handler marked our message as invalid.
+    /// - The interface handler won't send any more answer. See [`closed`](Self::closed).
     ///
     pub actual_data: Result<EncodedMessage, ()>,
+
+    /// True if the interface handler has indicated, after answering the message with
+    /// `emit_answer_chunk`, that it won't send any more answer. `actual_data` is then `Err`.
+    pub closed: bool,
 }
 
 pub fn build_process_destroyed_notification(
     pid: Pid,
     index_in_list: u32,
 ) -> ProcessDestroyedNotificationBuilder {
-    let mut buffer = Vec::with_capacity(1 + 8 + 4);
+    let mut buffer = NotificationBuffer::with_capacity(1 + 8 + 4);
     buffer.push(2);
     buffer.extend_from_slice(&u64::from(pid).to_le_bytes());
     buffer.extend_from_slice(&index_in_list.to_le_bytes());
 
-    debug_assert_eq!(buffer.capacity(), buffer.len());
+    debug_assert!(buffer.spilled() || buffer.len() <= NOTIFICATION_INLINE_CAPACITY);
+    debug_assert!(!buffer.spilled() || buffer.capacity() == buffer.len());
     ProcessDestroyedNotificationBuilder { data: buffer }
 }
 
 #[derive(Debug, Clone)]
 pub struct ProcessDestroyedNotificationBuilder {
-    data: Vec<u8>,
+    data: NotificationBuffer,
 }
 
 impl ProcessDestroyedNotificationBuilder {
@@ -442,8 +600,13 @@ impl ProcessDestroyedNotificationBuilder {
         self.data.len()
     }
 
+    /// Returns the encoded message, without consuming the builder.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
     pub fn into_bytes(self) -> Vec<u8> {
-        self.data
+        self.data.into_vec()
     }
 }
 
@@ -528,6 +691,23 @@ mod tests {
         assert_eq!(decoded.message_id, message_id);
         assert_eq!(decoded.index_in_list, index_in_list);
         assert_eq!(decoded.actual_data, Err(()));
+        assert!(!decoded.closed);
+    }
+
+    #[test]
+    fn response_closed_encode_decode() {
+        let message_id = From::from(0xa123456789abcdef);
+        let index_in_list = 0xdeadbeef;
+
+        let mut resp_notif = build_response_closed_notification(message_id, 0xf00baa);
+        resp_notif.set_index_in_list(index_in_list);
+        assert_eq!(resp_notif.message_id(), message_id);
+
+        let decoded = decode_response_notification(&resp_notif.into_bytes()).unwrap();
+        assert_eq!(decoded.message_id, message_id);
+        assert_eq!(decoded.index_in_list, index_in_list);
+        assert_eq!(decoded.actual_data, Err(()));
+        assert!(decoded.closed);
     }
 
     #[test]
@@ -542,4 +722,22 @@ mod tests {
         assert_eq!(decoded.pid, pid);
         assert_eq!(decoded.index_in_list, index_in_list);
     }
+
+    #[test]
+    fn small_notifications_inline() {
+        let message = EncodedMessage(vec![0xff; 64]);
+        let int_notif =
+            build_interface_notification(&From::from([0xca; 32]), None, From::from(1), 0, &message);
+        assert!(!int_notif.data.spilled());
+
+        let resp_notif = build_response_notification(From::from(2), 0, Ok(&message));
+        assert!(!resp_notif.data.spilled());
+
+        let message = EncodedMessage(vec![0xff; NOTIFICATION_INLINE_CAPACITY]);
+        let int_notif =
+            build_interface_notification(&From::from([0xca; 32]), None, From::from(1), 0, &message);
+        assert!(int_notif.data.spilled());
+        let decoded = decode_interface_notification(int_notif.as_bytes()).unwrap();
+        assert_eq!(decoded.actual_data, message);
+    }
 }
@@ -13,8 +13,12 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use crate::{ffi::DecodedInterfaceOrDestroyed, Encode, MessageId};
+use crate::{
+    ffi::{DecodedInterfaceNotification, DecodedInterfaceOrDestroyed},
+    Encode, ErrorCode, ErrorEnvelope, InterfaceHash, MessageId, ResponseResult,
+};
 
+use alloc::string::String;
 use core::{
     pin::Pin,
     task::{Context, Poll},
@@ -31,6 +35,27 @@ pub fn next_interface_message() -> InterfaceMessageFuture {
     }
 }
 
+/// Returns a stream of the messages received on the given interface.
+///
+/// The interface must have been registered beforehand. Multiple streams for different
+/// interfaces can be used at the same time. Messages on other interfaces and notifications
+/// about destroyed processes are left to the other streams and to [`next_interface_message`].
+///
+/// While a stream exists, the messages of its interface are no longer returned by
+/// [`next_interface_message`].
+///
+/// > **Note**: The messages and notifications that no stream is interested in are kept for
+/// >           [`next_interface_message`]. Only the most recent ones are kept if it isn't called,
+/// >           and the discarded messages are answered with an error.
+// TODO: move to interface interface?
+pub fn interface_messages(interface: InterfaceHash) -> InterfaceMessages {
+    crate::block_on::register_interface_stream(&interface);
+    InterfaceMessages {
+        interface,
+        registration: None,
+    }
+}
+
 /// Answers the given message.
 // TODO: move to interface interface?
 pub fn emit_answer(message_id: MessageId, msg: impl Encode) {
@@ -38,30 +63,127 @@ pub fn emit_answer(message_id: MessageId, msg: impl Encode) {
     fn imp(message_id: MessageId, msg: impl Encode) {
         unsafe {
             let buf = msg.encode();
-            crate::ffi::emit_answer(&u64::from(message_id), buf.0.as_ptr(), buf.0.len() as u32);
+            crate::ffi::emit_answer(&message_id, buf.0.as_ptr(), buf.0.len() as u32);
         }
     }
-    #[cfg(not(target_arch = "wasm32"))]
+    #[cfg(all(not(target_arch = "wasm32"), feature = "testing"))]
+    fn imp(message_id: MessageId, msg: impl Encode) {
+        crate::testing::emit_answer(message_id, Ok(msg.encode()))
+    }
+    #[cfg(all(not(target_arch = "wasm32"), not(feature = "testing")))]
     fn imp(message_id: MessageId, msg: impl Encode) {
         unreachable!()
     }
     imp(message_id, msg)
 }
 
+/// Sends one of the answers to the given message, without preventing further answers.
+///
+/// This makes it possible to answer a message multiple times, for example to notify the emitter
+/// of every event it has subscribed to. The emitter receives the answers with
+/// [`message_subscription`](crate::message_subscription). Call [`close_answer`] once no more
+/// answer will be sent.
+///
+/// Returns an error if the message doesn't exist, or if its emitter isn't a process and thus
+/// can't receive multiple answers. The message must then be answered with [`emit_answer`].
+// TODO: move to interface interface?
+pub fn emit_answer_chunk(message_id: MessageId, msg: impl Encode) -> Result<(), ()> {
+    #[cfg(target_arch = "wasm32")] // TODO: we should have a proper operating system name instead
+    fn imp(message_id: MessageId, msg: impl Encode) -> Result<(), ()> {
+        unsafe {
+            let buf = msg.encode();
+            let ret =
+                crate::ffi::emit_answer_chunk(&message_id, buf.0.as_ptr(), buf.0.len() as u32);
+            if ret == 0 {
+                Ok(())
+            } else {
+                Err(())
+            }
+        }
+    }
+    #[cfg(all(not(target_arch = "wasm32"), feature = "testing"))]
+    fn imp(message_id: MessageId, msg: impl Encode) -> Result<(), ()> {
+        crate::testing::emit_answer(message_id, Ok(msg.encode()));
+        Ok(())
+    }
+    #[cfg(all(not(target_arch = "wasm32"), not(feature = "testing")))]
+    fn imp(_: MessageId, _: impl Encode) -> Result<(), ()> {
+        unreachable!()
+    }
+    imp(message_id, msg)
+}
+
+/// Indicates that no more answer will be sent to a message previously answered with
+/// [`emit_answer_chunk`].
+// TODO: move to interface interface?
+pub fn close_answer(message_id: MessageId) {
+    #[cfg(target_arch = "wasm32")] // TODO: we should have a proper operating system name instead
+    fn imp(message_id: MessageId) {
+        unsafe { crate::ffi::close_answer(&message_id) }
+    }
+    #[cfg(all(not(target_arch = "wasm32"), feature = "testing"))]
+    fn imp(message_id: MessageId) {
+        crate::testing::emit_answer(message_id, Err(()))
+    }
+    #[cfg(all(not(target_arch = "wasm32"), not(feature = "testing")))]
+    fn imp(_: MessageId) {
+        unreachable!()
+    }
+    imp(message_id)
+}
+
 /// Answers the given message by notifying of an error in the message.
 // TODO: move to interface interface?
 pub fn emit_message_error(message_id: MessageId) {
     #[cfg(target_arch = "wasm32")] // TODO: we should have a proper operating system name instead
     fn imp(message_id: MessageId) {
-        unsafe { crate::ffi::emit_message_error(&u64::from(message_id)) }
+        unsafe { crate::ffi::emit_message_error(&message_id) }
+    }
+    #[cfg(all(not(target_arch = "wasm32"), feature = "testing"))]
+    fn imp(message_id: MessageId) {
+        crate::testing::emit_answer(message_id, Err(()))
     }
-    #[cfg(not(target_arch = "wasm32"))]
+    #[cfg(all(not(target_arch = "wasm32"), not(feature = "testing")))]
     fn imp(message_id: MessageId) {
         unreachable!()
     }
     imp(message_id)
 }
 
+/// Answers the given message with an error code.
+///
+/// This is a shortcut for [`respond_err`] with an [`ErrorEnvelope`] that has no payload and no
+/// message. The emitter receives the error as [`ResponseErr::Interface`](crate::ResponseErr)
+/// when waiting for the response with [`message_result`](crate::message_result).
+///
+/// > **Note**: Contrary to [`emit_message_error`], this isn't a system call. The kernel sees a
+/// >           regular answer, and only emitters that decode the answer as a
+/// >           [`ResponseResult`] see the error. This function must therefore only be used with
+/// >           interfaces that use the standard error envelope.
+// TODO: move to interface interface?
+pub fn emit_answer_err(message_id: MessageId, code: ErrorCode) {
+    respond_err(message_id, ErrorEnvelope::new(code, String::new()))
+}
+
+/// Answers the given message with a [`ResponseResult`].
+// TODO: move to interface interface?
+pub fn respond<T>(message_id: MessageId, result: ResponseResult<T>)
+where
+    T: parity_scale_codec::Encode,
+{
+    emit_answer(message_id, result)
+}
+
+/// Answers the given message with an error.
+///
+/// The emitter is expected to decode the answer as a [`ResponseResult`]. Since errors are
+/// encoded the same way regardless of the type of the successful answer, this function can be
+/// used with any interface that uses the standard error envelope.
+// TODO: move to interface interface?
+pub fn respond_err(message_id: MessageId, error: ErrorEnvelope) {
+    respond::<()>(message_id, Err(error))
+}
+
 /// Future that drives [`next_interface_message`] to completion.
 #[must_use]
 pub struct InterfaceMessageFuture {
@@ -78,15 +200,12 @@ impl Future for InterfaceMessageFuture {
             self.finished = true;
             Poll::Ready(message)
         } else {
-            match &mut self.registration {
-                Some(r) => r.update(cx.waker()),
-                r @ None => {
-                    *r = Some(crate::block_on::register_message_waker(
-                        From::from(1),
-                        cx.waker().clone(),
-                    ))
-                }
-            };
+            // The kernel consumes the registration when it delivers a notification, even if
+            // this notification is then picked by a stream. We therefore register again.
+            self.registration = Some(crate::block_on::register_message_waker(
+                From::from(1),
+                cx.waker().clone(),
+            ));
 
             Poll::Pending
         }
@@ -94,3 +213,46 @@ impl Future for InterfaceMessageFuture {
 }
 
 impl Unpin for InterfaceMessageFuture {}
+
+/// Stream returned by [`interface_messages`].
+#[must_use]
+pub struct InterfaceMessages {
+    /// Interface whose messages to yield.
+    interface: InterfaceHash,
+    /// Registration for interface messages. Created every time the stream returns `Pending`.
+    registration: Option<crate::block_on::WakerRegistration>,
+}
+
+impl InterfaceMessages {
+    /// Returns the interface whose messages are yielded.
+    pub fn interface(&self) -> &InterfaceHash {
+        &self.interface
+    }
+}
+
+impl Stream for InterfaceMessages {
+    type Item = DecodedInterfaceNotification;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        if let Some(message) = crate::block_on::peek_interface_message_for(&self.interface) {
+            return Poll::Ready(Some(message));
+        }
+
+        // The kernel consumes the registration when it delivers a notification, even if this
+        // notification concerns another interface. We therefore register again.
+        self.registration = Some(crate::block_on::register_message_waker(
+            From::from(1),
+            cx.waker().clone(),
+        ));
+
+        Poll::Pending
+    }
+}
+
+impl Drop for InterfaceMessages {
+    fn drop(&mut self) {
+        crate::block_on::unregister_interface_stream(&self.interface);
+    }
+}
+
+impl Unpin for InterfaceMessages {}
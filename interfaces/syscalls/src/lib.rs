@@ -34,7 +34,11 @@
 //! expect any response.
 //!
 //! The two primary and recommended ways to emit a message are the
-//! [`emit_message_without_response`] and [`emit_message_with_response`] functions.
+//! [`emit_message_without_response`] and [`emit_message_with_response`] functions. The latter
+//! decodes the response into the expected type, and yields a [`ResponseErr`] if the handler
+//! indicates that the message was erroneous or if the response can't be decoded.
+//!
+//! In order to wait for the responses to multiple messages at once, use [`message_responses`].
 //!
 //! # Interface handling
 //!
@@ -45,10 +49,19 @@
 //! The message can later be optionally be answered using the [`emit_answer`] function. If the
 //! mesage is malformed, you can also use the [`emit_message_error`] function.
 //!
+//! Interfaces that need to report errors are encouraged to answer with a [`ResponseResult`],
+//! using the [`respond`] and [`respond_err`] functions. See the [`error`] module.
+//!
 //! There is no way for an interface handler to pro-actively send data to a process. Communication
 //! can only be done as a response to a message. This must be taken into account when designing
 //! interfaces.
 //!
+//! # Testing
+//!
+//! Outside of WASM, the functions of this crate that interact with the kernel are only
+//! available if the `testing` feature is enabled, in which case they interact with a mock
+//! kernel. See the `testing` module.
+//!
 //! # About threads
 //!
 //! Multithreading in WASM isn't specified yet, and Rust doesn't allow multithreaded WASM code.
@@ -60,6 +73,9 @@
 //! invoked is when we explicitly call a function whose role is to do that. The only reasonable
 //! choice for such function is the [`block_on()`] function, or similar functions.
 //!
+//! Programs that use their own executor instead of [`block_on()`] must call
+//! [`process_notifications`] when they have nothing else to do.
+//!
 //! For the same reason, it is also challenging to write an implementation of [`block_on()`].
 //! Putting the current thread to sleep is not enough, because the lack of background threads
 //! makes it impossible for the `Waker` to be invoked. An implementation of [`block_on()`] **must**
@@ -82,36 +98,57 @@
 #![no_std]
 
 extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
 
-pub use block_on::block_on;
+pub use block_on::{block_on, process_notifications, yield_now};
+pub use capability::delegate_capability;
 pub use emit::{
-    cancel_message, emit_message_with_response, emit_message_without_response, MessageBuilder,
+    cancel_message, emit_message_with_response, emit_message_without_response, emit_messages_batch,
+    MessageBuilder,
 };
+pub use error::{ErrorCode, ErrorEnvelope, ResponseResult};
+pub use exit::exit;
 pub use ffi::{
     DecodedInterfaceNotification, DecodedInterfaceOrDestroyed, DecodedNotification,
     DecodedResponseNotification,
 };
 pub use interface_message::{
-    emit_answer, emit_message_error, next_interface_message, InterfaceMessageFuture,
+    close_answer, emit_answer, emit_answer_chunk, emit_answer_err, emit_message_error,
+    interface_messages, next_interface_message, respond, respond_err, InterfaceMessageFuture,
+    InterfaceMessages,
+};
+pub use response::{
+    message_response, message_response_sync_raw, message_responses, message_result,
+    message_subscription, MessageResponseFuture, MessageResponses, MessageResultFuture,
+    ResponseErr, Subscription,
 };
-pub use response::{message_response, message_response_sync_raw, MessageResponseFuture};
 pub use traits::{Decode, Encode, EncodedMessage};
 
 use core::{cmp::PartialEq, fmt};
 
 mod block_on;
 mod emit;
+mod exit;
 mod interface_message;
 mod response;
 mod traits;
 
+pub mod capability;
+pub mod crash;
+#[cfg(feature = "diagnostics")]
+pub mod diagnostics;
+pub mod error;
 pub mod ffi;
+#[cfg(all(feature = "testing", not(target_arch = "wasm32")))]
+pub mod testing;
 
 /// Identifier of a running process within a core.
 // TODO: move to a Pid module?
 #[derive(
     Copy, Clone, PartialEq, Eq, Hash, parity_scale_codec::Encode, parity_scale_codec::Decode,
 )]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Pid(u64);
 
 impl From<u64> for Pid {
@@ -137,6 +174,7 @@ impl fmt::Debug for Pid {
 #[derive(
     Copy, Clone, PartialEq, Eq, Hash, parity_scale_codec::Encode, parity_scale_codec::Decode,
 )]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ThreadId(u64);
 
 impl From<u64> for ThreadId {
@@ -162,6 +200,8 @@ impl fmt::Debug for ThreadId {
 #[derive(
     Copy, Clone, PartialEq, Eq, Hash, parity_scale_codec::Encode, parity_scale_codec::Decode,
 )]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[repr(transparent)]
 pub struct MessageId(u64); // TODO: should be NonZeroU64
 
 impl From<u64> for MessageId {
@@ -182,8 +222,38 @@ impl fmt::Debug for MessageId {
     }
 }
 
-/// Hash of a module.
+/// Identifier of a resource that the kernel or a handler holds on behalf of a process.
+///
+/// Handles are only meaningful for the process that owns them. A handle whose resource has been
+/// freed is never reused for a different resource.
+#[derive(
+    Copy, Clone, PartialEq, Eq, Hash, parity_scale_codec::Encode, parity_scale_codec::Decode,
+)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Handle(u64);
+
+impl From<u64> for Handle {
+    fn from(id: u64) -> Handle {
+        Handle(id)
+    }
+}
+
+impl From<Handle> for u64 {
+    fn from(handle: Handle) -> u64 {
+        handle.0
+    }
+}
+
+impl fmt::Debug for Handle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#{:020}", self.0)
+    }
+}
+
+/// Hash of an interface.
 #[derive(Clone, parity_scale_codec::Encode, parity_scale_codec::Decode, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[repr(transparent)]
 pub struct InterfaceHash([u8; 32]);
 
 impl InterfaceHash {
@@ -13,9 +13,13 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use crate::{ffi::DecodedNotification, Decode, EncodedMessage, MessageId};
+use crate::{
+    ffi::DecodedNotification, Decode, EncodedMessage, ErrorEnvelope, MessageId, ResponseResult,
+};
 
+use alloc::vec::Vec;
 use core::{
+    fmt,
     marker::PhantomData,
     pin::Pin,
     task::{Context, Poll},
@@ -35,7 +39,8 @@ pub fn message_response_sync_raw(msg_id: MessageId) -> EncodedMessage {
 
 /// Returns a future that is ready when a response to the given message comes back.
 ///
-/// The return value is the type the message decodes to.
+/// The return value is the type the message decodes to. The future yields an error if the
+/// handler indicates that the message was erroneous, or if the response can't be decoded.
 pub fn message_response<T: Decode>(msg_id: MessageId) -> MessageResponseFuture<T> {
     MessageResponseFuture {
         finished: false,
@@ -45,9 +50,76 @@ pub fn message_response<T: Decode>(msg_id: MessageId) -> MessageResponseFuture<T
     }
 }
 
-// TODO: add a variant of message_response but for multiple messages
+/// Returns a future that is ready when a response to the given message comes back, for
+/// interfaces whose answers are a [`ResponseResult`].
+///
+/// Errors reported by the handler, for example with [`respond_err`](crate::respond_err) or
+/// [`emit_answer_err`](crate::emit_answer_err), are returned as [`ResponseErr::Interface`].
+pub fn message_result<T>(msg_id: MessageId) -> MessageResultFuture<T>
+where
+    T: parity_scale_codec::Decode,
+{
+    MessageResultFuture {
+        inner: message_response(msg_id),
+    }
+}
+
+/// Returns a stream that yields the responses to the given messages, in the order in which they
+/// come back.
+///
+/// The stream ends once a response has been yielded for each message. All the messages are
+/// waited upon at once, which is more efficient than waiting for each of them individually.
+pub fn message_responses<T: Decode>(
+    msg_ids: impl IntoIterator<Item = MessageId>,
+) -> MessageResponses<T> {
+    MessageResponses {
+        pending: msg_ids.into_iter().collect(),
+        registration: None,
+        marker: PhantomData,
+    }
+}
+
+/// Returns a stream that yields the answers to the given message, for interfaces that answer
+/// a message multiple times with [`emit_answer_chunk`](crate::emit_answer_chunk).
+///
+/// The stream ends when the handler calls [`close_answer`](crate::close_answer). If the handler
+/// instead indicates that the message is erroneous, the stream yields
+/// [`ResponseErr::MessageError`] then ends. Answers that can't be decoded are yielded as
+/// [`ResponseErr::Decode`].
+///
+/// Destroying the stream before it has ended cancels the message, and no more answer is
+/// received.
+pub fn message_subscription<T: Decode>(msg_id: MessageId) -> Subscription<T> {
+    Subscription {
+        msg_id,
+        finished: false,
+        registration: None,
+        marker: PhantomData,
+    }
+}
+
+/// Error that can happen when waiting for the response to a message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ResponseErr {
+    /// The handler of the interface has indicated that the message was erroneous.
+    MessageError,
+    /// The response couldn't be decoded into the expected type.
+    Decode,
+    /// The handler of the interface has answered with an error.
+    Interface(ErrorEnvelope),
+}
+
+impl fmt::Display for ResponseErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ResponseErr::MessageError => write!(f, "The handler reported an erroneous message"),
+            ResponseErr::Decode => write!(f, "Failed to decode the response"),
+            ResponseErr::Interface(err) => fmt::Display::fmt(err, f),
+        }
+    }
+}
 
-/// Future that drives `message_response` to completion.
+/// Future that drives [`message_response`] to completion.
 #[must_use]
 pub struct MessageResponseFuture<T> {
     msg_id: MessageId,
@@ -60,13 +132,17 @@ impl<T> Future for MessageResponseFuture<T>
 where
     T: Decode,
 {
-    type Output = T;
+    type Output = Result<T, ResponseErr>;
 
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
         assert!(!self.finished);
         if let Some(response) = crate::block_on::peek_response(self.msg_id) {
             self.finished = true;
-            Poll::Ready(Decode::decode(response.actual_data.unwrap()).unwrap())
+            let response = response
+                .actual_data
+                .map_err(|()| ResponseErr::MessageError)
+                .and_then(|data| Decode::decode(data).map_err(|_| ResponseErr::Decode));
+            Poll::Ready(response)
         } else {
             let msg_id = self.msg_id;
             match &mut self.registration {
@@ -84,3 +160,152 @@ where
 }
 
 impl<T> Unpin for MessageResponseFuture<T> {}
+
+/// Future that drives [`message_result`] to completion.
+#[must_use]
+pub struct MessageResultFuture<T> {
+    inner: MessageResponseFuture<ResponseResult<T>>,
+}
+
+impl<T> Future for MessageResultFuture<T>
+where
+    T: parity_scale_codec::Decode,
+{
+    type Output = Result<T, ResponseErr>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        match Pin::new(&mut self.inner).poll(cx) {
+            Poll::Ready(Ok(Ok(response))) => Poll::Ready(Ok(response)),
+            Poll::Ready(Ok(Err(err))) => Poll::Ready(Err(ResponseErr::Interface(err))),
+            Poll::Ready(Err(err)) => Poll::Ready(Err(err)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl<T> Unpin for MessageResultFuture<T> {}
+
+/// Stream returned by [`message_responses`].
+#[must_use]
+pub struct MessageResponses<T> {
+    /// Messages whose response hasn't been yielded yet.
+    pending: Vec<MessageId>,
+    /// Registration covering all the messages of `pending`. Created the first time the stream
+    /// returns `Pending`.
+    registration: Option<crate::block_on::WakerRegistration>,
+    marker: PhantomData<T>,
+}
+
+impl<T> MessageResponses<T> {
+    /// Returns the messages whose response hasn't been yielded yet.
+    pub fn pending(&self) -> &[MessageId] {
+        &self.pending
+    }
+}
+
+impl<T> Stream for MessageResponses<T>
+where
+    T: Decode,
+{
+    type Item = (MessageId, Result<T, ResponseErr>);
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        for n in 0..self.pending.len() {
+            let msg_id = self.pending[n];
+            if let Some(response) = crate::block_on::peek_response(msg_id) {
+                self.pending.swap_remove(n);
+                let response = response
+                    .actual_data
+                    .map_err(|()| ResponseErr::MessageError)
+                    .and_then(|data| Decode::decode(data).map_err(|_| ResponseErr::Decode));
+                return Poll::Ready(Some((msg_id, response)));
+            }
+        }
+
+        if self.pending.is_empty() {
+            return Poll::Ready(None);
+        }
+
+        // The registration still covers the messages that have already been yielded. This is
+        // harmless, as the kernel never delivers a response to the same message twice.
+        let this = &mut *self;
+        match &mut this.registration {
+            Some(r) => r.update(cx.waker()),
+            r @ None => {
+                *r = Some(crate::block_on::register_messages_waker(
+                    this.pending.iter().cloned(),
+                    cx.waker().clone(),
+                ))
+            }
+        };
+        Poll::Pending
+    }
+}
+
+impl<T> Unpin for MessageResponses<T> {}
+
+/// Stream returned by [`message_subscription`].
+#[must_use]
+pub struct Subscription<T> {
+    msg_id: MessageId,
+    /// True if the handler has closed the answers or reported an erroneous message.
+    finished: bool,
+    /// Registration for the next answer. Created the first time the stream returns `Pending`
+    /// after an answer has been yielded.
+    registration: Option<crate::block_on::WakerRegistration>,
+    marker: PhantomData<T>,
+}
+
+impl<T> Stream for Subscription<T>
+where
+    T: Decode,
+{
+    type Item = Result<T, ResponseErr>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        if self.finished {
+            return Poll::Ready(None);
+        }
+
+        if let Some(response) = crate::block_on::peek_response(self.msg_id) {
+            // The registration has been consumed by the kernel when delivering the answer, and
+            // must be created again in order to receive the next one.
+            self.registration = None;
+            return match response.actual_data {
+                Ok(data) => {
+                    Poll::Ready(Some(Decode::decode(data).map_err(|_| ResponseErr::Decode)))
+                }
+                Err(()) if response.closed => {
+                    self.finished = true;
+                    Poll::Ready(None)
+                }
+                Err(()) => {
+                    self.finished = true;
+                    Poll::Ready(Some(Err(ResponseErr::MessageError)))
+                }
+            };
+        }
+
+        let msg_id = self.msg_id;
+        match &mut self.registration {
+            Some(r) => r.update(cx.waker()),
+            r @ None => {
+                *r = Some(crate::block_on::register_message_waker(
+                    msg_id,
+                    cx.waker().clone(),
+                ))
+            }
+        };
+        Poll::Pending
+    }
+}
+
+impl<T> Drop for Subscription<T> {
+    fn drop(&mut self) {
+        if !self.finished {
+            crate::cancel_message(self.msg_id);
+        }
+    }
+}
+
+impl<T> Unpin for Subscription<T> {}
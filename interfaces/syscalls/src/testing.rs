@@ -0,0 +1,569 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Mock kernel, for testing programs on the host.
+//!
+//! When the `testing` feature is enabled and the target isn't WASM, the functions of this crate
+//! that normally interact with the kernel interact instead with an in-process mock kernel. The
+//! functions of this module make it possible for tests to play the role of the other programs
+//! of the system: inspecting the messages emitted by the code under test, answering them, or
+//! sending messages to the interfaces it handles.
+//!
+//! The code under test blocks when it waits for a notification that hasn't been delivered yet.
+//! It is therefore generally run in a separate thread, while the test drives the mock kernel.
+//!
+//! # Example
+//!
+//! ```ignore
+//! let thread = std::thread::spawn(|| {
+//!     redshirt_syscalls::block_on(async {
+//!         let response: u32 = unsafe {
+//!             redshirt_syscalls::emit_message_with_response(&MY_INTERFACE, MyMessage::Get)
+//!                 .unwrap()
+//!                 .await
+//!                 .unwrap()
+//!         };
+//!         response
+//!     })
+//! });
+//!
+//! let message = loop {
+//!     if let Some(message) = redshirt_syscalls::testing::next_emitted_message() {
+//!         break message;
+//!     }
+//! };
+//! redshirt_syscalls::testing::answer_message(message.message_id.unwrap(), Ok(5u32.encode()));
+//! assert_eq!(thread.join().unwrap(), 5);
+//! ```
+//!
+//! > **Note**: There exists only one mock kernel, shared between all the threads. Tests that use
+//! >           it must not run concurrently, for example by passing `--test-threads=1`.
+
+use crate::{ffi, DecodedNotification, EncodedMessage, InterfaceHash, MessageId, Pid};
+
+use alloc::collections::VecDeque;
+use core::sync::atomic;
+use spinning_top::Spinlock;
+
+/// Message emitted by the code under test.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EmittedMessage {
+    /// Interface the message has been emitted on.
+    pub interface: InterfaceHash,
+    /// Identifier of the message, if it expects an answer.
+    pub message_id: Option<MessageId>,
+    /// Body of the message.
+    pub message: EncodedMessage,
+}
+
+lazy_static::lazy_static! {
+    static ref STATE: Spinlock<MockState> = Spinlock::new(MockState::new());
+}
+
+/// State of the mock kernel.
+struct MockState {
+    /// Identifier to assign to the next message. Values 0 and 1 are reserved.
+    next_message_id: u64,
+    /// Notifications waiting to be delivered to the code under test, in order.
+    notifications: VecDeque<ffi::NotificationBuilder>,
+    /// Messages emitted by the code under test, in order.
+    emitted: VecDeque<EmittedMessage>,
+    /// Answers emitted by the code under test, in order.
+    answers: VecDeque<(MessageId, Result<EncodedMessage, ()>)>,
+}
+
+impl MockState {
+    fn new() -> Self {
+        MockState {
+            next_message_id: 2,
+            notifications: VecDeque::new(),
+            emitted: VecDeque::new(),
+            answers: VecDeque::new(),
+        }
+    }
+
+    fn allocate_message_id(&mut self) -> MessageId {
+        let id = self.next_message_id;
+        self.next_message_id += 1;
+        MessageId::from(id)
+    }
+}
+
+/// Removes the oldest message emitted by the code under test that hasn't been retrieved yet.
+pub fn next_emitted_message() -> Option<EmittedMessage> {
+    STATE.lock().emitted.pop_front()
+}
+
+/// Delivers a response to a message emitted by the code under test. Passing `Err` indicates
+/// that the message is erroneous.
+pub fn answer_message(message_id: MessageId, response: Result<EncodedMessage, ()>) {
+    let notification =
+        ffi::build_response_notification(message_id, 0, response.as_ref().map_err(|_| ()));
+    STATE.lock().notifications.push_back(notification.into());
+}
+
+/// Indicates to the code under test that no more response will be delivered to a message, as
+/// if the handler had called [`close_answer`](crate::close_answer).
+pub fn close_answers(message_id: MessageId) {
+    let notification = ffi::build_response_closed_notification(message_id, 0);
+    STATE.lock().notifications.push_back(notification.into());
+}
+
+/// Delivers a message on an interface to the code under test, as if it had been emitted by
+/// `emitter`. Returns the identifier of the message if `needs_answer` is true.
+///
+/// The answer can then be retrieved with [`next_answer`].
+pub fn emit_interface_message(
+    interface: &InterfaceHash,
+    emitter: Pid,
+    message: EncodedMessage,
+    needs_answer: bool,
+) -> Option<MessageId> {
+    let mut state = STATE.lock();
+    let message_id = if needs_answer {
+        Some(state.allocate_message_id())
+    } else {
+        None
+    };
+
+    let notification =
+        ffi::build_interface_notification(interface, message_id, emitter, 0, &message);
+    state.notifications.push_back(notification.into());
+    message_id
+}
+
+/// Notifies the code under test that the given process has been destroyed.
+pub fn emit_process_destroyed(pid: Pid) {
+    let notification = ffi::build_process_destroyed_notification(pid, 0);
+    STATE.lock().notifications.push_back(notification.into());
+}
+
+/// Removes the oldest answer emitted by the code under test that hasn't been retrieved yet.
+/// The answer is `Err` if the code under test has indicated that the message was erroneous, or
+/// that no more answer will be sent to it.
+pub fn next_answer() -> Option<(MessageId, Result<EncodedMessage, ()>)> {
+    STATE.lock().answers.pop_front()
+}
+
+/// Resets the mock kernel to its initial state. Notifications that have been received by the
+/// code under test but not processed yet are discarded as well.
+pub fn reset() {
+    *STATE.lock() = MockState::new();
+    crate::block_on::clear_queues();
+}
+
+/// Mock of the `next_notification` syscall.
+pub(crate) fn next_notification(to_poll: &mut [u64], block: bool) -> Option<DecodedNotification> {
+    loop {
+        {
+            let mut state = STATE.lock();
+
+            let found = state
+                .notifications
+                .iter()
+                .enumerate()
+                .find_map(|(n, notif)| {
+                    let expected = match notif {
+                        ffi::NotificationBuilder::Response(response) => {
+                            u64::from(response.message_id())
+                        }
+                        ffi::NotificationBuilder::Interface(_)
+                        | ffi::NotificationBuilder::ProcessDestroyed(_) => 1,
+                    };
+                    to_poll
+                        .iter()
+                        .position(|p| *p == expected)
+                        .map(|index| (n, index))
+                });
+
+            if let Some((n, index_in_list)) = found {
+                let mut notification = match state.notifications.remove(n) {
+                    Some(n) => n,
+                    None => unreachable!(),
+                };
+                to_poll[index_in_list] = 0;
+                notification.set_index_in_list(index_in_list as u32);
+                return Some(ffi::decode_notification(notification.as_bytes()).unwrap());
+            }
+        }
+
+        if !block {
+            return None;
+        }
+
+        atomic::spin_loop_hint();
+    }
+}
+
+/// Mock of the `emit_message` syscall.
+pub(crate) fn emit_message(
+    interface: &InterfaceHash,
+    message: EncodedMessage,
+    needs_answer: bool,
+) -> Option<MessageId> {
+    let mut state = STATE.lock();
+    let message_id = if needs_answer {
+        Some(state.allocate_message_id())
+    } else {
+        None
+    };
+
+    state.emitted.push_back(EmittedMessage {
+        interface: interface.clone(),
+        message_id,
+        message,
+    });
+    message_id
+}
+
+/// Mock of the `emit_answer`, `emit_answer_chunk`, `close_answer` and `emit_message_error`
+/// syscalls.
+pub(crate) fn emit_answer(message_id: MessageId, answer: Result<EncodedMessage, ()>) {
+    STATE.lock().answers.push_back((message_id, answer));
+}
+
+/// Mock of the `cancel_message` syscall.
+pub(crate) fn cancel_message(message_id: MessageId) {
+    let mut state = STATE.lock();
+    state.notifications.retain(|notif| match notif {
+        ffi::NotificationBuilder::Response(response) => response.message_id() != message_id,
+        _ => true,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use crate::{Encode as _, EncodedMessage, InterfaceHash};
+    use alloc::{vec, vec::Vec};
+    use spinning_top::Spinlock;
+
+    lazy_static::lazy_static! {
+        /// Held by the tests for their whole duration, as there is only one mock kernel.
+        static ref TEST_LOCK: Spinlock<()> = Spinlock::new(());
+    }
+
+    #[test]
+    fn emit_and_answer() {
+        let _lock = TEST_LOCK.lock();
+        super::reset();
+        const INTERFACE: InterfaceHash = InterfaceHash::from_raw_hash([0xaa; 32]);
+
+        let program = std::thread::spawn(|| {
+            crate::block_on(async {
+                let response: EncodedMessage = unsafe {
+                    crate::emit_message_with_response(&INTERFACE, 12u32)
+                        .unwrap()
+                        .await
+                        .unwrap()
+                };
+                let message = crate::next_interface_message().await;
+                (response, message)
+            })
+        });
+
+        let emitted = loop {
+            if let Some(emitted) = super::next_emitted_message() {
+                break emitted;
+            }
+        };
+        assert_eq!(emitted.interface, INTERFACE);
+        assert_eq!(emitted.message, 12u32.encode());
+        super::answer_message(emitted.message_id.unwrap(), Ok(34u32.encode()));
+
+        let message_id =
+            super::emit_interface_message(&INTERFACE, From::from(7), 56u32.encode(), true);
+
+        let (response, message) = program.join().unwrap();
+        assert_eq!(response, 34u32.encode());
+        match message {
+            crate::DecodedInterfaceOrDestroyed::Interface(message) => {
+                assert_eq!(message.message_id, message_id);
+                assert_eq!(message.actual_data, 56u32.encode());
+            }
+            _ => panic!(),
+        }
+
+        crate::emit_answer(message_id.unwrap(), 78u32);
+        assert_eq!(
+            super::next_answer(),
+            Some((message_id.unwrap(), Ok(78u32.encode())))
+        );
+    }
+
+    #[test]
+    fn subscription() {
+        let _lock = TEST_LOCK.lock();
+        super::reset();
+        const INTERFACE: InterfaceHash = InterfaceHash::from_raw_hash([0xbb; 32]);
+
+        let program = std::thread::spawn(|| {
+            crate::block_on(async {
+                use futures::stream::StreamExt as _;
+                let message = 1u8.encode();
+                let message_id = unsafe {
+                    crate::emit_messages_batch(&[(INTERFACE, &message.0, true)])[0].unwrap()
+                };
+                crate::message_subscription::<u32>(message_id)
+                    .collect::<std::vec::Vec<_>>()
+                    .await
+            })
+        });
+
+        let emitted = loop {
+            if let Some(emitted) = super::next_emitted_message() {
+                break emitted;
+            }
+        };
+        let message_id = emitted.message_id.unwrap();
+        super::answer_message(message_id, Ok(5u32.encode()));
+        super::answer_message(message_id, Ok(6u32.encode()));
+        super::close_answers(message_id);
+
+        assert_eq!(program.join().unwrap(), std::vec![Ok(5), Ok(6)]);
+    }
+
+    #[test]
+    fn subscription_error() {
+        let _lock = TEST_LOCK.lock();
+        super::reset();
+        const INTERFACE: InterfaceHash = InterfaceHash::from_raw_hash([0xcc; 32]);
+
+        let program = std::thread::spawn(|| {
+            crate::block_on(async {
+                use futures::stream::StreamExt as _;
+                let message = 1u8.encode();
+                let message_id = unsafe {
+                    crate::emit_messages_batch(&[(INTERFACE, &message.0, true)])[0].unwrap()
+                };
+                crate::message_subscription::<u32>(message_id)
+                    .collect::<std::vec::Vec<_>>()
+                    .await
+            })
+        });
+
+        let emitted = loop {
+            if let Some(emitted) = super::next_emitted_message() {
+                break emitted;
+            }
+        };
+        let message_id = emitted.message_id.unwrap();
+        super::answer_message(message_id, Ok(5u32.encode()));
+        super::answer_message(message_id, Err(()));
+
+        assert_eq!(
+            program.join().unwrap(),
+            std::vec![Ok(5), Err(crate::ResponseErr::MessageError)]
+        );
+    }
+
+    #[test]
+    fn interface_messages_stream() {
+        let _lock = TEST_LOCK.lock();
+        super::reset();
+        const INTERFACE: InterfaceHash = InterfaceHash::from_raw_hash([0xdd; 32]);
+        const OTHER: InterfaceHash = InterfaceHash::from_raw_hash([0xde; 32]);
+
+        // Delivered before the stream exists.
+        super::emit_interface_message(&INTERFACE, From::from(7), 1u32.encode(), false);
+
+        let program = std::thread::spawn(|| {
+            crate::block_on(async {
+                use futures::stream::StreamExt as _;
+                let mut messages = crate::interface_messages(INTERFACE);
+                let first = messages.next().await.unwrap();
+                let second = messages.next().await.unwrap();
+                let third = messages.next().await.unwrap();
+                let other = crate::next_interface_message().await;
+                (first, second, third, other)
+            })
+        });
+
+        super::emit_interface_message(&OTHER, From::from(7), 2u32.encode(), false);
+        super::emit_interface_message(&INTERFACE, From::from(7), 3u32.encode(), false);
+        super::emit_interface_message(&INTERFACE, From::from(7), 4u32.encode(), false);
+
+        let (first, second, third, other) = program.join().unwrap();
+        assert_eq!(first.actual_data, 1u32.encode());
+        assert_eq!(second.actual_data, 3u32.encode());
+        assert_eq!(third.actual_data, 4u32.encode());
+        match other {
+            crate::DecodedInterfaceOrDestroyed::Interface(message) => {
+                assert_eq!(message.interface, OTHER);
+                assert_eq!(message.actual_data, 2u32.encode());
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn dropped_stream_hands_messages_back() {
+        let _lock = TEST_LOCK.lock();
+        super::reset();
+        const INTERFACE: InterfaceHash = InterfaceHash::from_raw_hash([0xee; 32]);
+
+        let program = std::thread::spawn(|| {
+            crate::block_on(async {
+                use futures::stream::StreamExt as _;
+                let mut messages = crate::interface_messages(INTERFACE);
+                let first = messages.next().await.unwrap();
+                // Wait for the second message to have been received before dropping the stream.
+                assert!(futures::FutureExt::now_or_never(messages.next()).is_none());
+                while crate::block_on::num_pending_interface_messages() == 0 {
+                    crate::block_on::process_notifications(false);
+                }
+                drop(messages);
+                (first, crate::next_interface_message().await)
+            })
+        });
+
+        super::emit_interface_message(&INTERFACE, From::from(7), 1u32.encode(), false);
+        super::emit_interface_message(&INTERFACE, From::from(7), 2u32.encode(), false);
+
+        let (first, second) = program.join().unwrap();
+        assert_eq!(first.actual_data, 1u32.encode());
+        match second {
+            crate::DecodedInterfaceOrDestroyed::Interface(message) => {
+                assert_eq!(message.actual_data, 2u32.encode());
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn unclaimed_notifications_bounded() {
+        let _lock = TEST_LOCK.lock();
+        super::reset();
+        const INTERFACE: InterfaceHash = InterfaceHash::from_raw_hash([0xef; 32]);
+        const OTHER: InterfaceHash = InterfaceHash::from_raw_hash([0xf0; 32]);
+        const MAX: usize = crate::block_on::MAX_UNCLAIMED_NOTIFICATIONS;
+
+        let program = std::thread::spawn(|| {
+            crate::block_on(async {
+                use futures::stream::StreamExt as _;
+                crate::interface_messages(INTERFACE).next().await.unwrap()
+            })
+        });
+
+        let mut message_ids = std::vec::Vec::new();
+        for n in 0..MAX as u32 + 3 {
+            let id = super::emit_interface_message(&OTHER, From::from(7), n.encode(), true);
+            message_ids.push(id.unwrap());
+        }
+        super::emit_process_destroyed(From::from(8));
+        super::emit_interface_message(&INTERFACE, From::from(7), 1u32.encode(), false);
+
+        assert_eq!(program.join().unwrap().actual_data, 1u32.encode());
+        assert_eq!(crate::block_on::num_pending_interface_messages(), MAX);
+
+        // The oldest messages have been discarded and answered with an error.
+        for message_id in &message_ids[..4] {
+            assert_eq!(super::next_answer(), Some((*message_id, Err(()))));
+        }
+        assert_eq!(super::next_answer(), None);
+
+        // The most recent ones are still there.
+        match crate::block_on::peek_interface_message() {
+            Some(crate::DecodedInterfaceOrDestroyed::Interface(message)) => {
+                assert_eq!(message.message_id, Some(message_ids[4]));
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn answer_err_round_trip() {
+        let _lock = TEST_LOCK.lock();
+        super::reset();
+        const INTERFACE: InterfaceHash = InterfaceHash::from_raw_hash([0xf1; 32]);
+
+        let program = std::thread::spawn(|| {
+            crate::block_on(async {
+                let message = 1u8.encode();
+                let message_id = unsafe {
+                    crate::emit_messages_batch(&[(INTERFACE, &message.0, true)])[0].unwrap()
+                };
+                crate::message_result::<u32>(message_id).await
+            })
+        });
+
+        let emitted = loop {
+            if let Some(emitted) = super::next_emitted_message() {
+                break emitted;
+            }
+        };
+        let message_id = emitted.message_id.unwrap();
+
+        // The error is a regular answer as far as the kernel is concerned.
+        crate::emit_answer_err(message_id, crate::ErrorCode::NotFound);
+        let answer = match super::next_answer() {
+            Some((id, Ok(answer))) if id == message_id => answer,
+            _ => panic!(),
+        };
+        super::answer_message(message_id, Ok(answer));
+
+        match program.join().unwrap() {
+            Err(crate::ResponseErr::Interface(err)) => {
+                assert_eq!(err.code, crate::ErrorCode::NotFound);
+                assert!(err.payload.is_empty());
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn response_errors() {
+        let _lock = TEST_LOCK.lock();
+        super::reset();
+        const INTERFACE: InterfaceHash = InterfaceHash::from_raw_hash([0xf2; 32]);
+
+        let program = std::thread::spawn(|| {
+            crate::block_on(async {
+                let responses = (0..3)
+                    .map(|_| unsafe {
+                        crate::emit_message_with_response::<u32>(&INTERFACE, 0u8).unwrap()
+                    })
+                    .collect::<Vec<_>>();
+                let mut out = Vec::new();
+                for response in responses {
+                    out.push(response.await);
+                }
+                out
+            })
+        });
+
+        let mut emitted = Vec::new();
+        while emitted.len() < 3 {
+            if let Some(message) = super::next_emitted_message() {
+                emitted.push(message.message_id.unwrap());
+            }
+        }
+
+        super::answer_message(emitted[0], Ok(34u32.encode()));
+        super::answer_message(emitted[1], Err(()));
+        // A single byte can't be decoded as a `u32`.
+        super::answer_message(emitted[2], Ok(1u8.encode()));
+
+        assert_eq!(
+            program.join().unwrap(),
+            vec![
+                Ok(34),
+                Err(crate::ResponseErr::MessageError),
+                Err(crate::ResponseErr::Decode),
+            ]
+        );
+    }
+}
@@ -19,6 +19,10 @@ use core::fmt;
 /// Message already encoded.
 ///
 /// The [`Encode`] and [`Decode`] trait implementations are no-op.
+///
+/// Encoding a message once then passing a reference to the `EncodedMessage` to the functions
+/// that emit messages makes it possible to send the same message multiple times without
+/// encoding it again.
 // TODO: make field private
 #[derive(Clone, PartialEq, Eq)]
 pub struct EncodedMessage(pub Vec<u8>);
@@ -51,6 +55,12 @@ impl Encode for EncodedMessage {
     }
 }
 
+impl<'a> Encode for &'a EncodedMessage {
+    fn encode(self) -> EncodedMessage {
+        self.clone()
+    }
+}
+
 impl<T> Encode for T
 where
     T: parity_scale_codec::Encode,
@@ -84,3 +94,15 @@ impl fmt::Debug for EncodedMessage {
         fmt::Debug::fmt(&self.0, f)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Encode as _, EncodedMessage};
+
+    #[test]
+    fn encode_by_ref() {
+        let msg = (5u32, 12u8).encode();
+        assert_eq!((&msg).encode(), msg);
+        assert_eq!((&msg).encode(), EncodedMessage(alloc::vec![5, 0, 0, 0, 12]));
+    }
+}
@@ -28,6 +28,8 @@ pub mod ffi;
 pub fn system_clock() -> impl Future<Output = u128> {
     unsafe {
         let msg = ffi::TimeMessage::GetSystem;
-        redshirt_syscalls::emit_message_with_response(&ffi::INTERFACE, msg).unwrap()
+        redshirt_syscalls::emit_message_with_response(&ffi::INTERFACE, msg)
+            .unwrap()
+            .map(Result::unwrap)
     }
 }
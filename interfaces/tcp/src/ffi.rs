@@ -14,7 +14,7 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 use parity_scale_codec::{Decode, Encode};
-use redshirt_syscalls::InterfaceHash;
+use redshirt_syscalls::{Handle, InterfaceHash};
 
 // TODO: this has been randomly generated; instead should be a hash or something
 pub const INTERFACE: InterfaceHash = InterfaceHash::from_raw_hash([
@@ -26,12 +26,20 @@ pub const INTERFACE: InterfaceHash = InterfaceHash::from_raw_hash([
 pub enum TcpMessage {
     Open(TcpOpen),
     Close(TcpClose),
-    /// Ask to read data from a socket. The response contains the data. For each socket, only one
-    /// read can exist at any given point in time.
+    /// Ask to read data from a socket. The response contains the data. If multiple reads are
+    /// requested on the same socket, they are performed one after the other, in order.
     Read(TcpRead),
-    /// Ask to write data to a socket. A response is sent back once written. For each socket, only
-    /// one write can exist at any given point in time.
+    /// Ask to write data to a socket. A response is sent back once written. If multiple writes
+    /// are requested on the same socket, they are performed one after the other, in order.
+    ///
+    /// The handler limits the amount of data waiting to be written on each socket, and refuses
+    /// writes above this limit with [`TcpError::WouldBlock`].
     Write(TcpWrite),
+    /// Changes an option of a socket. Answered with a [`TcpSetOptionResponse`].
+    SetOption(TcpSetOption),
+    /// Queries the addresses and the state of a socket. Answered with a
+    /// [`TcpSocketInfoResponse`].
+    SocketInfo(TcpSocketInfo),
 }
 
 #[derive(Debug, Encode, Decode)]
@@ -47,16 +55,20 @@ pub struct TcpOpen {
     pub ip: [u16; 8],
     /// TCP port.
     pub port: u16,
+    /// If `Some`, maximum number of milliseconds that connecting can take. If the connection
+    /// isn't established in time, the response contains [`TcpError::TimedOut`]. Ignored if
+    /// `listen` is true.
+    pub timeout_ms: Option<u64>,
 }
 
 #[derive(Debug, Encode, Decode)]
 pub struct TcpOpenResponse {
-    pub result: Result<TcpSocketOpen, ()>,
+    pub result: Result<TcpSocketOpen, TcpError>,
 }
 
 #[derive(Debug, Encode, Decode)]
 pub struct TcpSocketOpen {
-    pub socket_id: u32,
+    pub socket_id: Handle,
     pub local_ip: [u16; 8],
     pub local_port: u16,
     pub remote_ip: [u16; 8],
@@ -65,26 +77,109 @@ pub struct TcpSocketOpen {
 
 #[derive(Debug, Encode, Decode)]
 pub struct TcpClose {
-    pub socket_id: u32,
+    pub socket_id: Handle,
 }
 
 #[derive(Debug, Encode, Decode)]
 pub struct TcpRead {
-    pub socket_id: u32,
+    pub socket_id: Handle,
+    /// Maximum number of bytes to read. The handler might return fewer bytes than that, and
+    /// enforces a limit of its own.
+    pub max_len: u32,
 }
 
 #[derive(Debug, Encode, Decode)]
 pub struct TcpReadResponse {
-    pub result: Result<Vec<u8>, ()>,
+    pub result: Result<Vec<u8>, TcpError>,
 }
 
 #[derive(Debug, Encode, Decode)]
 pub struct TcpWrite {
-    pub socket_id: u32,
+    pub socket_id: Handle,
     pub data: Vec<u8>,
 }
 
 #[derive(Debug, Encode, Decode)]
 pub struct TcpWriteResponse {
-    pub result: Result<(), ()>,
+    pub result: Result<(), TcpError>,
+}
+
+#[derive(Debug, Encode, Decode)]
+pub struct TcpSetOption {
+    pub socket_id: Handle,
+    pub option: TcpOption,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub enum TcpOption {
+    /// If true, data is sent as soon as possible instead of being buffered (`TCP_NODELAY`).
+    NoDelay(bool),
+    /// If `Some`, enables keepalive probes after the given number of seconds of inactivity. If
+    /// `None`, disables them.
+    KeepAlive(Option<u32>),
+    /// Time-to-live of the IP packets.
+    Ttl(u32),
+    /// If `Some`, reads that take longer than the given number of milliseconds fail. If `None`,
+    /// reads never time out.
+    ReadTimeout(Option<u64>),
+    /// If `Some`, writes that take longer than the given number of milliseconds fail. If `None`,
+    /// writes never time out.
+    WriteTimeout(Option<u64>),
+}
+
+#[derive(Debug, Encode, Decode)]
+pub struct TcpSetOptionResponse {
+    pub result: Result<(), TcpError>,
+}
+
+#[derive(Debug, Encode, Decode)]
+pub struct TcpSocketInfo {
+    pub socket_id: Handle,
+}
+
+#[derive(Debug, Encode, Decode)]
+pub struct TcpSocketInfoResponse {
+    pub result: Result<TcpSocketDetails, TcpError>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct TcpSocketDetails {
+    pub local_ip: [u16; 8],
+    pub local_port: u16,
+    pub remote_ip: [u16; 8],
+    pub remote_port: u16,
+    pub state: TcpConnectionState,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+pub enum TcpConnectionState {
+    /// Data can be read from and written to the socket.
+    Connected,
+    /// The remote has closed the connection, or the connection has failed.
+    Closed,
+}
+
+/// Reason why an operation on a socket has failed.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub enum TcpError {
+    /// The remote has refused the connection.
+    ConnectionRefused,
+    /// The remote has reset or aborted the connection.
+    ConnectionReset,
+    /// The operation has taken too long.
+    TimedOut,
+    /// The remote can't be reached.
+    Unreachable,
+    /// The local address is already in use.
+    AddrInUse,
+    /// The program isn't allowed to perform this operation.
+    PermissionDenied,
+    /// The socket doesn't exist, or isn't connected yet.
+    InvalidSocket,
+    /// Too much data is waiting to be written on the socket. The write can be attempted again
+    /// once the previous ones have finished.
+    WouldBlock,
+    /// Any other error. Contains the error code of the operating system of the handler, if
+    /// known.
+    Other(Option<i32>),
 }
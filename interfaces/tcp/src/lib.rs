@@ -17,23 +17,34 @@
 //!
 //! Allows opening asynchronous TCP sockets and listeners, similar to what the `tokio` or
 //! `async-std` libraries do.
+//!
+//! [`TcpStream`] implements the `AsyncRead` and `AsyncWrite` traits of both the `futures` and
+//! `tokio` libraries, so that code written against these traits can use it unmodified.
 
 use futures::{lock::Mutex, prelude::*, ready};
-use redshirt_syscalls::{Encode as _, MessageResponseFuture};
+use redshirt_syscalls::{Encode as _, Handle, MessageResponseFuture};
 use std::{
-    cmp, io, mem,
+    cmp,
+    convert::TryFrom as _,
+    io, mem,
     net::{IpAddr, Ipv6Addr, SocketAddr},
     pin::Pin,
     task::{Context, Poll},
+    time::Duration,
 };
 
+pub use ffi::TcpError;
+
+/// Maximum number of bytes sent in a single write message.
+const MAX_WRITE_LEN: usize = 64 * 1024;
+
 pub mod ffi;
 
 /// Active TCP connection to a remote.
 ///
 /// This type is similar to [`std::net::TcpStream`].
 pub struct TcpStream {
-    handle: u32,
+    handle: Handle,
     /// Buffer of data that has been read from the socket but not transmitted to the user yet.
     read_buffer: Vec<u8>,
     /// If Some, we have sent out a "read" message and are waiting for a response.
@@ -49,7 +60,7 @@ pub struct TcpListener {
     local_addr: SocketAddr,
     next_incoming: Mutex<
         stream::FuturesUnordered<
-            Pin<Box<dyn Future<Output = Result<(TcpStream, SocketAddr), ()>> + Send>>,
+            Pin<Box<dyn Future<Output = Result<(TcpStream, SocketAddr), TcpError>> + Send>>,
         >,
     >,
 }
@@ -57,27 +68,132 @@ pub struct TcpListener {
 impl TcpStream {
     /// Start connecting to the given address. Returns a `TcpStream` if the connection is
     /// successful.
-    pub fn connect(socket_addr: &SocketAddr) -> impl Future<Output = Result<TcpStream, ()>> {
-        let fut = TcpStream::new(socket_addr, false);
+    pub fn connect(socket_addr: &SocketAddr) -> impl Future<Output = Result<TcpStream, TcpError>> {
+        let fut = TcpStream::new(socket_addr, false, None);
+        async move { Ok(fut.await?.0) }
+    }
+
+    /// Same as [`TcpStream::connect`], but returns [`TcpError::TimedOut`] if the connection
+    /// isn't established after `timeout`.
+    pub fn connect_timeout(
+        socket_addr: &SocketAddr,
+        timeout: Duration,
+    ) -> impl Future<Output = Result<TcpStream, TcpError>> {
+        let timeout_ms = u64::try_from(timeout.as_millis()).unwrap_or(u64::max_value());
+        let fut = TcpStream::new(socket_addr, false, Some(timeout_ms));
         async move { Ok(fut.await?.0) }
     }
 
+    /// Returns the identifier of the socket in the messages of the interface.
+    pub fn socket_id(&self) -> Handle {
+        self.handle
+    }
+
+    /// Sets whether data is sent as soon as possible instead of being buffered.
+    ///
+    /// This corresponds to the `TCP_NODELAY` option.
+    pub async fn set_nodelay(&self, no_delay: bool) -> Result<(), TcpError> {
+        self.set_option(ffi::TcpOption::NoDelay(no_delay)).await
+    }
+
+    /// Enables keepalive probes after the given duration of inactivity, or disables them if
+    /// `None`.
+    pub async fn set_keepalive(&self, idle: Option<Duration>) -> Result<(), TcpError> {
+        let idle = idle.map(|d| u32::try_from(d.as_secs()).unwrap_or(u32::max_value()));
+        self.set_option(ffi::TcpOption::KeepAlive(idle)).await
+    }
+
+    /// Sets the time-to-live of the IP packets sent on this socket.
+    pub async fn set_ttl(&self, ttl: u32) -> Result<(), TcpError> {
+        self.set_option(ffi::TcpOption::Ttl(ttl)).await
+    }
+
+    /// Sets the maximum duration of reads, after which they fail. Reads never time out if
+    /// `None`.
+    ///
+    /// Only affects the reads started after this method has returned.
+    pub async fn set_read_timeout(&self, timeout: Option<Duration>) -> Result<(), TcpError> {
+        let timeout = timeout.map(|d| u64::try_from(d.as_millis()).unwrap_or(u64::max_value()));
+        self.set_option(ffi::TcpOption::ReadTimeout(timeout)).await
+    }
+
+    /// Sets the maximum duration of writes, after which they fail. Writes never time out if
+    /// `None`.
+    ///
+    /// Only affects the writes started after this method has returned.
+    pub async fn set_write_timeout(&self, timeout: Option<Duration>) -> Result<(), TcpError> {
+        let timeout = timeout.map(|d| u64::try_from(d.as_millis()).unwrap_or(u64::max_value()));
+        self.set_option(ffi::TcpOption::WriteTimeout(timeout)).await
+    }
+
+    /// Returns the local address of the connection.
+    pub async fn local_addr(&self) -> Result<SocketAddr, TcpError> {
+        let details = self.details().await?;
+        Ok(socket_addr_from(details.local_ip, details.local_port))
+    }
+
+    /// Returns the address of the remote.
+    pub async fn peer_addr(&self) -> Result<SocketAddr, TcpError> {
+        let details = self.details().await?;
+        Ok(socket_addr_from(details.remote_ip, details.remote_port))
+    }
+
+    /// Returns true if the remote has closed the connection, or if the connection has failed.
+    pub async fn is_closed(&self) -> Result<bool, TcpError> {
+        let details = self.details().await?;
+        Ok(details.state == ffi::TcpConnectionState::Closed)
+    }
+
+    async fn details(&self) -> Result<ffi::TcpSocketDetails, TcpError> {
+        let message = ffi::TcpMessage::SocketInfo(ffi::TcpSocketInfo {
+            socket_id: self.handle,
+        });
+
+        let response: ffi::TcpSocketInfoResponse = unsafe {
+            redshirt_syscalls::emit_message_with_response(&ffi::INTERFACE, message)
+                .unwrap()
+                .await
+                .unwrap()
+        };
+
+        response.result
+    }
+
+    async fn set_option(&self, option: ffi::TcpOption) -> Result<(), TcpError> {
+        let message = ffi::TcpMessage::SetOption(ffi::TcpSetOption {
+            socket_id: self.handle,
+            option,
+        });
+
+        let response: ffi::TcpSetOptionResponse = unsafe {
+            redshirt_syscalls::emit_message_with_response(&ffi::INTERFACE, message)
+                .unwrap()
+                .await
+                .unwrap()
+        };
+
+        response.result
+    }
+
     /// Dialing and listening use the same underlying messages. The only different being a boolean
     /// indicating whether the address is a binding point or a destination.
     fn new(
         socket_addr: &SocketAddr,
         listen: bool,
-    ) -> impl Future<Output = Result<(TcpStream, SocketAddr), ()>> {
+        timeout_ms: Option<u64>,
+    ) -> impl Future<Output = Result<(TcpStream, SocketAddr), TcpError>> {
         let tcp_open = ffi::TcpMessage::Open(match socket_addr {
             SocketAddr::V4(addr) => ffi::TcpOpen {
                 ip: addr.ip().to_ipv6_mapped().segments(),
                 port: addr.port(),
                 listen,
+                timeout_ms,
             },
             SocketAddr::V6(addr) => ffi::TcpOpen {
                 ip: addr.ip().segments(),
                 port: addr.port(),
                 listen,
+                timeout_ms,
             },
         });
 
@@ -89,13 +205,12 @@ impl TcpStream {
                     .emit_with_response(&ffi::INTERFACE)
                     .unwrap()
                     .await
+                    .unwrap()
             };
 
             let socket_open_info = message.result?;
-            let remote_addr = {
-                let ip = Ipv6Addr::from(socket_open_info.remote_ip);
-                SocketAddr::new(IpAddr::from(ip), socket_open_info.remote_port)
-            };
+            let remote_addr =
+                socket_addr_from(socket_open_info.remote_ip, socket_open_info.remote_port);
 
             let stream = TcpStream {
                 handle: socket_open_info.socket_id,
@@ -109,19 +224,52 @@ impl TcpStream {
     }
 }
 
+fn socket_addr_from(ip: [u16; 8], port: u16) -> SocketAddr {
+    SocketAddr::new(IpAddr::from(Ipv6Addr::from(ip)), port)
+}
+
+impl From<TcpError> for io::Error {
+    fn from(err: TcpError) -> io::Error {
+        match err {
+            TcpError::ConnectionRefused => io::ErrorKind::ConnectionRefused.into(),
+            TcpError::ConnectionReset => io::ErrorKind::ConnectionReset.into(),
+            TcpError::TimedOut => io::ErrorKind::TimedOut.into(),
+            TcpError::Unreachable => io::Error::new(io::ErrorKind::Other, "unreachable"),
+            TcpError::AddrInUse => io::ErrorKind::AddrInUse.into(),
+            TcpError::PermissionDenied => io::ErrorKind::PermissionDenied.into(),
+            TcpError::InvalidSocket => io::ErrorKind::NotConnected.into(),
+            TcpError::WouldBlock => io::ErrorKind::WouldBlock.into(),
+            TcpError::Other(_) => io::ErrorKind::Other.into(),
+        }
+    }
+}
+
 impl AsyncRead for TcpStream {
     fn poll_read(
         mut self: Pin<&mut Self>,
         cx: &mut Context,
         buf: &mut [u8],
     ) -> Poll<Result<usize, io::Error>> {
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+
         loop {
             if let Some(pending_read) = self.pending_read.as_mut() {
-                self.read_buffer = match ready!(Future::poll(Pin::new(pending_read), cx)).result {
+                self.read_buffer = match ready!(Future::poll(Pin::new(pending_read), cx))
+                    .unwrap()
+                    .result
+                {
                     Ok(d) => d,
-                    Err(_) => return Poll::Ready(Err(io::ErrorKind::Other.into())), // TODO:
+                    Err(err) => return Poll::Ready(Err(err.into())),
                 };
                 self.pending_read = None;
+
+                // The handler answers with an empty buffer if the remote has closed the
+                // connection.
+                if self.read_buffer.is_empty() {
+                    return Poll::Ready(Ok(0));
+                }
             }
 
             debug_assert!(self.pending_read.is_none());
@@ -137,6 +285,7 @@ impl AsyncRead for TcpStream {
             self.pending_read = {
                 let tcp_read = ffi::TcpMessage::Read(ffi::TcpRead {
                     socket_id: self.handle,
+                    max_len: u32::try_from(buf.len()).unwrap_or(u32::max_value()),
                 });
 
                 let msg_id = unsafe {
@@ -164,14 +313,21 @@ impl AsyncWrite for TcpStream {
     ) -> Poll<Result<usize, io::Error>> {
         // Try to finish the previous write, if any is in progress.
         if let Some(pending_write) = self.pending_write.as_mut() {
-            match ready!(Future::poll(Pin::new(pending_write), cx)).result {
+            match ready!(Future::poll(Pin::new(pending_write), cx))
+                .unwrap()
+                .result
+            {
                 Ok(()) => self.pending_write = None,
-                Err(_) => return Poll::Ready(Err(io::ErrorKind::Other.into())), // TODO:
+                Err(err) => return Poll::Ready(Err(err.into())),
             }
         }
 
         debug_assert!(self.pending_write.is_none());
 
+        // Large buffers are written in multiple messages, in order to not copy all of them at
+        // once and to stay below the limit of the handler.
+        let buf = &buf[..cmp::min(buf.len(), MAX_WRITE_LEN)];
+
         // Perform the write, and store into `self.pending_write` a future to when we can start
         // the next write.
         self.pending_write = {
@@ -196,12 +352,20 @@ impl AsyncWrite for TcpStream {
 
     // TODO: implement poll_write_vectored
 
-    fn poll_flush(self: Pin<&mut Self>, _: &mut Context) -> Poll<Result<(), io::Error>> {
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), io::Error>> {
+        if let Some(pending_write) = self.pending_write.as_mut() {
+            let result = ready!(Future::poll(Pin::new(pending_write), cx))
+                .unwrap()
+                .result;
+            self.pending_write = None;
+            result?;
+        }
+
         Poll::Ready(Ok(()))
     }
 
-    fn poll_close(self: Pin<&mut Self>, _: &mut Context) -> Poll<Result<(), io::Error>> {
-        Poll::Ready(Ok(()))
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), io::Error>> {
+        AsyncWrite::poll_flush(self, cx)
     }
 }
 
@@ -254,7 +418,7 @@ impl TcpListener {
     pub fn bind(socket_addr: &SocketAddr) -> impl Future<Output = Result<TcpListener, ()>> {
         let next_incoming = Mutex::new(
             (0..10)
-                .map(|_| Box::pin(TcpStream::new(socket_addr, true)) as Pin<Box<_>>)
+                .map(|_| Box::pin(TcpStream::new(socket_addr, true, None)) as Pin<Box<_>>)
                 .collect(),
         );
 
@@ -284,7 +448,7 @@ impl TcpListener {
             }
         };
 
-        next_incoming.push(Box::pin(TcpStream::new(&self.local_addr, true)));
+        next_incoming.push(Box::pin(TcpStream::new(&self.local_addr, true, None)));
         (tcp_stream, remote_addr)
     }
 }
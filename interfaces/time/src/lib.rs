@@ -14,6 +14,12 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 //! Time.
+//!
+//! Provides access to the monotonic clock, through [`monotonic_clock`], and timers, through
+//! [`monotonic_wait_until`] and the [`Delay`] and [`Instant`] types.
+//!
+//! > **Note**: The monotonic clock doesn't correspond to any actual date. The time since the
+//! >           Epoch is provided by the separate `system-time` interface.
 
 #![no_std]
 
@@ -24,9 +30,11 @@ use futures::prelude::*;
 
 pub use self::delay::Delay;
 pub use self::instant::Instant;
+pub use self::timeout::{message_response_with_timeout, MessageResponseWithTimeout, TimeoutErr};
 
 mod delay;
 mod instant;
+mod timeout;
 
 pub mod ffi;
 
@@ -34,7 +42,9 @@ pub mod ffi;
 pub fn monotonic_clock() -> impl Future<Output = u128> {
     unsafe {
         let msg = ffi::TimeMessage::GetMonotonic;
-        redshirt_syscalls::emit_message_with_response(&ffi::INTERFACE, msg).unwrap()
+        redshirt_syscalls::emit_message_with_response(&ffi::INTERFACE, msg)
+            .unwrap()
+            .map(Result::unwrap)
     }
 }
 
@@ -42,7 +52,9 @@ pub fn monotonic_clock() -> impl Future<Output = u128> {
 pub fn monotonic_wait_until(until: u128) -> impl Future<Output = ()> {
     unsafe {
         let msg = ffi::TimeMessage::WaitMonotonic(until);
-        redshirt_syscalls::emit_message_with_response(&ffi::INTERFACE, msg).unwrap()
+        redshirt_syscalls::emit_message_with_response(&ffi::INTERFACE, msg)
+            .unwrap()
+            .map(Result::unwrap)
     }
 }
 
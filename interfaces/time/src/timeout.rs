@@ -0,0 +1,97 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::monotonic_wait;
+use alloc::boxed::Box;
+use core::{fmt, future::Future, pin::Pin, task::Context, task::Poll, time::Duration};
+use redshirt_syscalls::{Decode, MessageId, MessageResponseFuture, ResponseErr};
+
+/// Returns a future that is ready when a response to the given message comes back, or when
+/// `timeout` has elapsed.
+///
+/// If the timeout is reached first, the message is cancelled and its response, if any, will be
+/// ignored.
+pub fn message_response_with_timeout<T: Decode>(
+    msg_id: MessageId,
+    timeout: Duration,
+) -> MessageResponseWithTimeout<T> {
+    MessageResponseWithTimeout {
+        msg_id,
+        response: redshirt_syscalls::message_response(msg_id),
+        timeout: Box::pin(monotonic_wait(timeout)),
+        finished: false,
+    }
+}
+
+/// Error that can happen when waiting for the response to a message with a timeout.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TimeoutErr {
+    /// No response has come back before the timeout.
+    Timeout,
+    /// A response has come back, but is erroneous.
+    Response(ResponseErr),
+}
+
+impl fmt::Display for TimeoutErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TimeoutErr::Timeout => write!(f, "No response before the timeout"),
+            TimeoutErr::Response(err) => fmt::Display::fmt(err, f),
+        }
+    }
+}
+
+/// Future that drives [`message_response_with_timeout`] to completion.
+#[must_use]
+pub struct MessageResponseWithTimeout<T> {
+    msg_id: MessageId,
+    response: MessageResponseFuture<T>,
+    timeout: Pin<Box<dyn Future<Output = ()> + Send>>,
+    finished: bool,
+}
+
+impl<T> Future for MessageResponseWithTimeout<T>
+where
+    T: Decode,
+{
+    type Output = Result<T, TimeoutErr>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        assert!(!self.finished);
+
+        if let Poll::Ready(response) = Pin::new(&mut self.response).poll(cx) {
+            self.finished = true;
+            return Poll::Ready(response.map_err(TimeoutErr::Response));
+        }
+
+        if let Poll::Ready(()) = self.timeout.as_mut().poll(cx) {
+            self.finished = true;
+            redshirt_syscalls::cancel_message(self.msg_id);
+            return Poll::Ready(Err(TimeoutErr::Timeout));
+        }
+
+        Poll::Pending
+    }
+}
+
+impl<T> Unpin for MessageResponseWithTimeout<T> {}
+
+impl<T> fmt::Debug for MessageResponseWithTimeout<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MessageResponseWithTimeout")
+            .field("msg_id", &self.msg_id)
+            .finish()
+    }
+}
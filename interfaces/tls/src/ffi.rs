@@ -0,0 +1,64 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use parity_scale_codec::{Decode, Encode};
+use redshirt_syscalls::{Handle, InterfaceHash};
+use redshirt_tcp_interface::ffi::TcpError;
+
+// TODO: this has been randomly generated; instead should be a hash or something
+pub const INTERFACE: InterfaceHash = InterfaceHash::from_raw_hash([
+    0xa3, 0xad, 0x07, 0x68, 0xa7, 0x10, 0x66, 0x89, 0xc4, 0x3f, 0x11, 0x53, 0x43, 0xf4, 0xc0, 0x72,
+    0xce, 0xdb, 0x86, 0x76, 0xfc, 0xae, 0xb2, 0x24, 0x6c, 0xc0, 0x9c, 0x2f, 0x47, 0xa8, 0x8e, 0xc6,
+]);
+
+#[derive(Debug, Encode, Decode)]
+pub enum TlsMessage {
+    /// Starts a TLS session, as a client, on a socket of the `tcp` interface. Answered with a
+    /// [`TlsConnectResponse`] once the handshake is finished.
+    ///
+    /// On success, the data read and written on the socket through the `tcp` interface is
+    /// decrypted and encrypted. On failure, the socket can no longer be used.
+    ///
+    /// Refused if a read or a write is in progress on the socket.
+    Connect(TlsConnect),
+}
+
+#[derive(Debug, Encode, Decode)]
+pub struct TlsConnect {
+    /// Identifier of the socket in the `tcp` interface.
+    pub socket_id: Handle,
+    /// Name of the server, sent to it and checked against its certificate.
+    pub server_name: String,
+}
+
+#[derive(Debug, Encode, Decode)]
+pub struct TlsConnectResponse {
+    pub result: Result<(), TlsError>,
+}
+
+/// Reason why a TLS session couldn't be started.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub enum TlsError {
+    /// The socket doesn't exist, isn't connected, or already has a TLS session.
+    InvalidSocket,
+    /// A read or a write is in progress on the socket.
+    Busy,
+    /// The server name isn't a valid DNS name.
+    InvalidServerName,
+    /// The handshake has failed, for example because the certificate of the server is invalid.
+    Handshake,
+    /// The connection has failed during the handshake.
+    Connection(TcpError),
+}
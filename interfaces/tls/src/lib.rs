@@ -0,0 +1,46 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! TLS.
+//!
+//! Allows encrypting a TCP connection opened with the `tcp` interface. The certificate of the
+//! server is checked by the handler of this interface.
+
+use redshirt_tcp_interface::TcpStream;
+
+pub mod ffi;
+
+/// Starts a TLS session on the given connection, and returns it once the handshake is finished.
+///
+/// From then on, the data read from and written to the [`TcpStream`] is decrypted and
+/// encrypted. The `server_name` is the name of the host, and must match its certificate.
+///
+/// The connection is closed if the handshake fails.
+pub async fn connect(stream: TcpStream, server_name: &str) -> Result<TcpStream, ffi::TlsError> {
+    let message = ffi::TlsMessage::Connect(ffi::TlsConnect {
+        socket_id: stream.socket_id(),
+        server_name: server_name.to_owned(),
+    });
+
+    let response: ffi::TlsConnectResponse = unsafe {
+        redshirt_syscalls::emit_message_with_response(&ffi::INTERFACE, message)
+            .unwrap()
+            .await
+            .unwrap()
+    };
+
+    response.result?;
+    Ok(stream)
+}
@@ -0,0 +1,108 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use parity_scale_codec::{Decode, Encode};
+use redshirt_syscalls::{Handle, InterfaceHash};
+
+// TODO: this has been randomly generated; instead should be a hash or something
+pub const INTERFACE: InterfaceHash = InterfaceHash::from_raw_hash([
+    0xf1, 0xf2, 0xc6, 0x4a, 0x8c, 0x17, 0x6f, 0x1e, 0xe1, 0x13, 0x14, 0xaf, 0x86, 0xb1, 0x25, 0xba,
+    0x30, 0x3d, 0x5b, 0x7f, 0x5b, 0x20, 0xc7, 0x2f, 0xa7, 0xa8, 0xe1, 0x8b, 0x1c, 0xc0, 0xb4, 0x87,
+]);
+
+#[derive(Debug, Encode, Decode)]
+pub enum UdpMessage {
+    /// Opens a socket bound to a local address. Answered with a [`UdpBindResponse`].
+    Bind(UdpBind),
+    /// Closes a socket. Doesn't have any answer.
+    Close(UdpClose),
+    /// Sends a datagram. Answered with a [`UdpSendToResponse`] once sent.
+    SendTo(UdpSendTo),
+    /// Waits for a datagram to arrive. Answered with a [`UdpRecvFromResponse`] containing the
+    /// datagram. Multiple receptions can be in progress at the same time, in which case each
+    /// datagram answers only one of them.
+    RecvFrom(UdpRecvFrom),
+    /// Enables or disables sending datagrams to broadcast addresses. Answered with a
+    /// [`UdpSetBroadcastResponse`].
+    SetBroadcast(UdpSetBroadcast),
+}
+
+#[derive(Debug, Encode, Decode)]
+pub struct UdpBind {
+    /// IPv6 address.
+    pub ip: [u16; 8],
+    /// UDP port. If 0, a port is chosen by the handler.
+    pub port: u16,
+}
+
+#[derive(Debug, Encode, Decode)]
+pub struct UdpBindResponse {
+    pub result: Result<UdpSocketOpen, ()>,
+}
+
+#[derive(Debug, Encode, Decode)]
+pub struct UdpSocketOpen {
+    pub socket_id: Handle,
+    pub local_ip: [u16; 8],
+    pub local_port: u16,
+}
+
+#[derive(Debug, Encode, Decode)]
+pub struct UdpClose {
+    pub socket_id: Handle,
+}
+
+#[derive(Debug, Encode, Decode)]
+pub struct UdpSendTo {
+    pub socket_id: Handle,
+    /// IPv6 address of the destination.
+    pub ip: [u16; 8],
+    pub port: u16,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Encode, Decode)]
+pub struct UdpSendToResponse {
+    pub result: Result<(), ()>,
+}
+
+#[derive(Debug, Encode, Decode)]
+pub struct UdpRecvFrom {
+    pub socket_id: Handle,
+}
+
+#[derive(Debug, Encode, Decode)]
+pub struct UdpRecvFromResponse {
+    pub result: Result<UdpDatagram, ()>,
+}
+
+#[derive(Debug, Encode, Decode)]
+pub struct UdpDatagram {
+    /// IPv6 address of the sender.
+    pub ip: [u16; 8],
+    pub port: u16,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Encode, Decode)]
+pub struct UdpSetBroadcast {
+    pub socket_id: Handle,
+    pub broadcast: bool,
+}
+
+#[derive(Debug, Encode, Decode)]
+pub struct UdpSetBroadcastResponse {
+    pub result: Result<(), ()>,
+}
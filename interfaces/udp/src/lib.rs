@@ -0,0 +1,144 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! UDP/IP.
+//!
+//! Allows sending and receiving UDP datagrams, similar to what [`std::net::UdpSocket`] does.
+
+use redshirt_syscalls::Handle;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+
+pub mod ffi;
+
+/// UDP socket bound to a local address.
+///
+/// This type is similar to [`std::net::UdpSocket`]. The socket is closed when this object is
+/// dropped.
+#[derive(Debug)]
+pub struct UdpSocket {
+    handle: Handle,
+    local_addr: SocketAddr,
+}
+
+impl UdpSocket {
+    /// Opens a socket bound to the given local address.
+    ///
+    /// If the port is 0, a port is chosen automatically. Use [`UdpSocket::local_addr`] to know
+    /// which one.
+    pub async fn bind(socket_addr: &SocketAddr) -> Result<UdpSocket, ()> {
+        let message = ffi::UdpMessage::Bind(ffi::UdpBind {
+            ip: ip_segments(socket_addr.ip()),
+            port: socket_addr.port(),
+        });
+
+        let response: ffi::UdpBindResponse = unsafe {
+            redshirt_syscalls::emit_message_with_response(&ffi::INTERFACE, message)
+                .unwrap()
+                .await
+                .unwrap()
+        };
+
+        let open = response.result?;
+        Ok(UdpSocket {
+            handle: open.socket_id,
+            local_addr: socket_addr_from(open.local_ip, open.local_port),
+        })
+    }
+
+    /// Returns the local address of the socket.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Sends a datagram to the given address.
+    pub async fn send_to(&self, data: &[u8], target: &SocketAddr) -> Result<(), ()> {
+        let message = ffi::UdpMessage::SendTo(ffi::UdpSendTo {
+            socket_id: self.handle,
+            ip: ip_segments(target.ip()),
+            port: target.port(),
+            data: data.to_vec(),
+        });
+
+        let response: ffi::UdpSendToResponse = unsafe {
+            redshirt_syscalls::emit_message_with_response(&ffi::INTERFACE, message)
+                .unwrap()
+                .await
+                .unwrap()
+        };
+
+        response.result
+    }
+
+    /// Waits for a datagram to arrive, and returns its content and the address of its sender.
+    pub async fn recv_from(&self) -> Result<(Vec<u8>, SocketAddr), ()> {
+        let message = ffi::UdpMessage::RecvFrom(ffi::UdpRecvFrom {
+            socket_id: self.handle,
+        });
+
+        let response: ffi::UdpRecvFromResponse = unsafe {
+            redshirt_syscalls::emit_message_with_response(&ffi::INTERFACE, message)
+                .unwrap()
+                .await
+                .unwrap()
+        };
+
+        let datagram = response.result?;
+        Ok((datagram.data, socket_addr_from(datagram.ip, datagram.port)))
+    }
+
+    /// Enables or disables sending datagrams to broadcast addresses.
+    pub async fn set_broadcast(&self, broadcast: bool) -> Result<(), ()> {
+        let message = ffi::UdpMessage::SetBroadcast(ffi::UdpSetBroadcast {
+            socket_id: self.handle,
+            broadcast,
+        });
+
+        let response: ffi::UdpSetBroadcastResponse = unsafe {
+            redshirt_syscalls::emit_message_with_response(&ffi::INTERFACE, message)
+                .unwrap()
+                .await
+                .unwrap()
+        };
+
+        response.result
+    }
+}
+
+impl Drop for UdpSocket {
+    fn drop(&mut self) {
+        unsafe {
+            let message = ffi::UdpMessage::Close(ffi::UdpClose {
+                socket_id: self.handle,
+            });
+            let _ = redshirt_syscalls::emit_message_without_response(&ffi::INTERFACE, message);
+        }
+    }
+}
+
+fn ip_segments(ip: IpAddr) -> [u16; 8] {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped().segments(),
+        IpAddr::V6(ip) => ip.segments(),
+    }
+}
+
+fn socket_addr_from(ip: [u16; 8], port: u16) -> SocketAddr {
+    let ip = Ipv6Addr::from(ip);
+    if let Some(ip) = ip.to_ipv4() {
+        SocketAddr::new(ip.into(), port)
+    } else {
+        SocketAddr::new(ip.into(), port)
+    }
+}
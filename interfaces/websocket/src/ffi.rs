@@ -0,0 +1,142 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use parity_scale_codec::{Decode, Encode};
+use redshirt_syscalls::InterfaceHash;
+
+// TODO: this has been randomly generated; instead should be a hash or something
+pub const INTERFACE: InterfaceHash = InterfaceHash::from_raw_hash([
+    0xc0, 0x19, 0x88, 0xce, 0x0e, 0x2a, 0xa5, 0xcd, 0x1c, 0xc0, 0xd2, 0x08, 0xf0, 0xdd, 0x81, 0x01,
+    0x2f, 0xd8, 0xea, 0x75, 0x7b, 0xdf, 0x21, 0x90, 0x7d, 0x46, 0xa3, 0xdb, 0xb3, 0x66, 0xb9, 0x4b,
+]);
+
+#[derive(Debug, Encode, Decode)]
+pub enum WebSocketMessage {
+    /// Opens a connection. Answered with a [`WebSocketConnectResponse`] once the opening
+    /// handshake has finished.
+    Connect(WebSocketConnect),
+    /// Sends a text or binary message. Answered with a [`WebSocketSendResponse`] once the message
+    /// has been written on the connection.
+    Send(WebSocketSend),
+    /// Waits for the next message sent by the server. Answered with a
+    /// [`WebSocketNextFrameResponse`]. If multiple messages are requested on the same socket,
+    /// they are answered in order.
+    NextFrame(WebSocketNextFrame),
+    /// Performs the closing handshake, then closes the connection. If a response is expected,
+    /// answered with a [`WebSocketCloseResponse`] once the connection has been closed.
+    ///
+    /// Unless the code or reason is invalid, the [`WebSocketMessage::NextFrame`] messages that
+    /// haven't been answered yet are answered with an error, and the socket no longer exists
+    /// afterwards.
+    Close(WebSocketClose),
+}
+
+#[derive(Debug, Encode, Decode)]
+pub struct WebSocketConnect {
+    /// URL to connect to. Must use the `ws` or `wss` scheme.
+    pub url: String,
+}
+
+#[derive(Debug, Encode, Decode)]
+pub struct WebSocketConnectResponse {
+    /// Identifier of the new socket.
+    pub result: Result<u32, WebSocketError>,
+}
+
+#[derive(Debug, Encode, Decode)]
+pub struct WebSocketSend {
+    pub socket_id: u32,
+    pub data: WebSocketData,
+}
+
+#[derive(Debug, Encode, Decode)]
+pub struct WebSocketSendResponse {
+    pub result: Result<(), WebSocketError>,
+}
+
+#[derive(Debug, Encode, Decode)]
+pub struct WebSocketNextFrame {
+    pub socket_id: u32,
+}
+
+#[derive(Debug, Encode, Decode)]
+pub struct WebSocketNextFrameResponse {
+    /// Next event on the socket. The socket no longer exists after
+    /// [`WebSocketEvent::Closed`] or an error has been returned.
+    pub result: Result<WebSocketEvent, WebSocketError>,
+}
+
+#[derive(Debug, Encode, Decode)]
+pub struct WebSocketClose {
+    pub socket_id: u32,
+    /// Status code indicating the reason for closing, as defined in RFC 6455. Must be `1000`, or
+    /// between `3000` and `4999`.
+    pub code: u16,
+    /// Human-readable reason for closing. Must be at most 123 bytes long.
+    pub reason: String,
+}
+
+#[derive(Debug, Encode, Decode)]
+pub struct WebSocketCloseResponse {
+    pub result: Result<(), WebSocketError>,
+}
+
+/// Content of a message.
+///
+/// Messages fragmented by the server are reassembled before being reported. Ping and pong frames
+/// are handled by the implementation of the interface and never reported.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub enum WebSocketData {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub enum WebSocketEvent {
+    /// The server has sent a message.
+    Message(WebSocketData),
+    /// The server has closed the connection.
+    Closed {
+        /// Status code sent by the server, if any.
+        code: Option<u16>,
+        reason: String,
+    },
+}
+
+/// Reason why an operation on a socket has failed.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub enum WebSocketError {
+    /// The URL couldn't be parsed, or its scheme isn't supported.
+    InvalidUrl,
+    /// The host of the URL couldn't be resolved.
+    ///
+    /// > **Note**: Host names aren't supported yet, and the host must be an IP address.
+    UnresolvableHost,
+    /// The connection to the server has failed.
+    Connection(redshirt_tcp_interface::ffi::TcpError),
+    /// The TLS handshake with the server has failed.
+    Tls(redshirt_tls_interface::ffi::TlsError),
+    /// The server has answered the opening handshake with the given HTTP status code instead of
+    /// accepting the connection.
+    Rejected(u16),
+    /// The server has violated the protocol.
+    Protocol,
+    /// The code or the reason passed when closing the socket is invalid.
+    InvalidCloseCode,
+    /// The connection is being closed, and no more message can be sent.
+    Closed,
+    /// The socket doesn't exist, or doesn't belong to the emitter of the message.
+    InvalidSocketId,
+}
@@ -0,0 +1,161 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! WebSocket client.
+//!
+//! Use [`WebSocket::connect`] to open a connection to a server. Both `ws` and `wss` URLs are
+//! supported.
+
+pub use ffi::{WebSocketData, WebSocketError, WebSocketEvent};
+
+pub mod ffi;
+
+/// Open WebSocket connection to a server.
+///
+/// The connection is closed with the `1000` status code when this object is dropped.
+#[derive(Debug)]
+pub struct WebSocket {
+    socket_id: u32,
+    /// If true, the handler has forgotten about the socket.
+    closed: bool,
+}
+
+impl WebSocket {
+    /// Connects to the given URL and performs the opening handshake.
+    pub async fn connect(url: &str) -> Result<WebSocket, WebSocketError> {
+        let message = ffi::WebSocketMessage::Connect(ffi::WebSocketConnect {
+            url: url.to_owned(),
+        });
+
+        let response: ffi::WebSocketConnectResponse = unsafe {
+            redshirt_syscalls::emit_message_with_response(&ffi::INTERFACE, message)
+                .unwrap()
+                .await
+                .unwrap()
+        };
+
+        Ok(WebSocket {
+            socket_id: response.result?,
+            closed: false,
+        })
+    }
+
+    /// Sends a text message to the server.
+    pub async fn send_text(&self, text: impl Into<String>) -> Result<(), WebSocketError> {
+        self.send(WebSocketData::Text(text.into())).await
+    }
+
+    /// Sends a binary message to the server.
+    pub async fn send_binary(&self, data: impl Into<Vec<u8>>) -> Result<(), WebSocketError> {
+        self.send(WebSocketData::Binary(data.into())).await
+    }
+
+    /// Sends a message to the server.
+    pub async fn send(&self, data: WebSocketData) -> Result<(), WebSocketError> {
+        if self.closed {
+            return Err(WebSocketError::Closed);
+        }
+
+        let message = ffi::WebSocketMessage::Send(ffi::WebSocketSend {
+            socket_id: self.socket_id,
+            data,
+        });
+
+        let response: ffi::WebSocketSendResponse = unsafe {
+            redshirt_syscalls::emit_message_with_response(&ffi::INTERFACE, message)
+                .unwrap()
+                .await
+                .unwrap()
+        };
+
+        response.result
+    }
+
+    /// Waits for the next message sent by the server, or for the server to close the connection.
+    ///
+    /// Returns [`WebSocketError::Closed`] if the connection has already been closed.
+    pub async fn next_event(&mut self) -> Result<WebSocketEvent, WebSocketError> {
+        if self.closed {
+            return Err(WebSocketError::Closed);
+        }
+
+        let message = ffi::WebSocketMessage::NextFrame(ffi::WebSocketNextFrame {
+            socket_id: self.socket_id,
+        });
+
+        let response: ffi::WebSocketNextFrameResponse = unsafe {
+            redshirt_syscalls::emit_message_with_response(&ffi::INTERFACE, message)
+                .unwrap()
+                .await
+                .unwrap()
+        };
+
+        match response.result {
+            Ok(WebSocketEvent::Message(data)) => Ok(WebSocketEvent::Message(data)),
+            other => {
+                self.closed = true;
+                other
+            }
+        }
+    }
+
+    /// Closes the connection with the given status code and reason, and waits for the server to
+    /// acknowledge it.
+    ///
+    /// The code must be `1000`, or between `3000` and `4999`, and the reason must be at most 123
+    /// bytes long. Otherwise, [`WebSocketError::InvalidCloseCode`] is returned and the connection
+    /// is closed with the `1000` status code.
+    pub async fn close(mut self, code: u16, reason: &str) -> Result<(), WebSocketError> {
+        if self.closed {
+            return Ok(());
+        }
+
+        let message = ffi::WebSocketMessage::Close(ffi::WebSocketClose {
+            socket_id: self.socket_id,
+            code,
+            reason: reason.to_owned(),
+        });
+
+        let response: ffi::WebSocketCloseResponse = unsafe {
+            redshirt_syscalls::emit_message_with_response(&ffi::INTERFACE, message)
+                .unwrap()
+                .await
+                .unwrap()
+        };
+
+        if response.result != Err(WebSocketError::InvalidCloseCode) {
+            self.closed = true;
+        }
+
+        response.result
+    }
+}
+
+impl Drop for WebSocket {
+    fn drop(&mut self) {
+        if self.closed {
+            return;
+        }
+
+        unsafe {
+            let message = ffi::WebSocketMessage::Close(ffi::WebSocketClose {
+                socket_id: self.socket_id,
+                code: 1000,
+                reason: String::new(),
+            });
+            let _ = redshirt_syscalls::emit_message_without_response(&ffi::INTERFACE, message);
+        }
+    }
+}
@@ -13,8 +13,15 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use futures::{channel::oneshot, future::BoxFuture, prelude::*};
 use redshirt_core::{build_wasm_module, module::ModuleHash};
-use std::{fs, path::PathBuf, process};
+use std::{
+    convert::TryFrom,
+    fs,
+    io::{self, BufRead as _, Write as _},
+    path::PathBuf,
+    process, thread,
+};
 use structopt::StructOpt;
 
 #[derive(Debug, StructOpt)]
@@ -43,6 +50,54 @@ struct CliOptions {
     /// Contrary to `module_hash`, the kernel will not stop if this module stops.
     #[structopt(long, parse(try_from_str = ModuleHash::from_base58))]
     background_module_hash: Vec<ModuleHash>,
+
+    /// If set, the internal invariants of the kernel are verified every time the given number
+    /// of scheduling iterations have passed. Violations are printed on stderr.
+    ///
+    /// Meant to be used for catching leaks when running modules for a long time.
+    #[structopt(long)]
+    self_check_period: Option<u32>,
+
+    /// If set, prints which ABIs the modules passed with `module_path` and
+    /// `background_module_path` rely on, and whether they are compatible with this kernel, then
+    /// exits without running them.
+    #[structopt(long)]
+    inspect: bool,
+
+    /// Path to the old and new versions of the schema of an interface. If set, prints whether
+    /// the new version is wire-compatible with the old one and which version bump it requires,
+    /// then exits without running anything.
+    ///
+    /// The exit code is 1 if the change is breaking.
+    #[structopt(long, parse(from_os_str), number_of_values = 2, value_names = &["OLD", "NEW"])]
+    check_interface_compat: Vec<PathBuf>,
+
+    /// If set, asks on the terminal for permission the first time a program tries to connect to
+    /// a TCP host. Decisions are remembered for as long as the kernel runs.
+    #[structopt(long)]
+    ask_network_permission: bool,
+
+    /// If set, the logs of each program are also written to a file in this directory, named
+    /// after the hash of the module of the program.
+    ///
+    /// If `fs_root` is set, a relative path is relative to it, making the logs readable by
+    /// programs through the filesystem interface.
+    #[structopt(long, parse(from_os_str))]
+    log_dir: Option<PathBuf>,
+
+    /// Size, in bytes, above which the log file of a program is rotated. Only relevant if
+    /// `log_dir` is set.
+    #[structopt(long, default_value = "1048576")]
+    log_max_file_size: u64,
+
+    /// Number of rotated log files to keep for each program. Only relevant if `log_dir` is set.
+    #[structopt(long, default_value = "4")]
+    log_max_rotated_files: u32,
+
+    /// If set, programs have access to the content of this directory through the filesystem
+    /// interface. It is the root of the filesystem that they see.
+    #[structopt(long, parse(from_os_str))]
+    fs_root: Option<PathBuf>,
 }
 
 fn main() {
@@ -52,35 +107,108 @@ fn main() {
 async fn async_main() {
     let cli_opts = CliOptions::from_args();
 
+    if let [old, new] = &cli_opts.check_interface_compat[..] {
+        let parse = |path: &PathBuf| {
+            let text = fs::read_to_string(path).expect("failed to read schema file");
+            redshirt_core::schema::InterfaceSchema::from_text(&text)
+                .unwrap_or_else(|err| panic!("failed to parse {}: {}", path.display(), err))
+        };
+        let report = redshirt_core::schema::compare(&parse(old), &parse(new));
+        println!("{}", report);
+        process::exit(if report.is_wire_compatible() { 0 } else { 1 });
+    }
+
     let mut cli_requested_processes = Vec::new();
 
     for module_path in cli_opts.module_path {
         let wasm_file_content = fs::read(&module_path).expect("failed to read input file");
         let module = redshirt_core::module::Module::from_bytes(&wasm_file_content)
-            .expect("failed to parse input file");
+            .unwrap_or_else(|err| panic!("failed to parse {}: {}", module_path.display(), err));
         cli_requested_processes.push((module_path, module, true));
     }
 
     for module_path in cli_opts.background_module_path {
         let wasm_file_content = fs::read(&module_path).expect("failed to read input file");
         let module = redshirt_core::module::Module::from_bytes(&wasm_file_content)
-            .expect("failed to parse input file");
+            .unwrap_or_else(|err| panic!("failed to parse {}: {}", module_path.display(), err));
         cli_requested_processes.push((module_path, module, false));
     }
 
-    let system = redshirt_core::system::SystemBuilder::new()
+    let programs_registry = redshirt_core::system::ProgramsRegistry::new();
+    let tcp_handler = if cli_opts.ask_network_permission {
+        redshirt_tcp_hosted::TcpHandler::new()
+            .with_authorization(&programs_registry, ask_connect_permission)
+    } else {
+        redshirt_tcp_hosted::TcpHandler::new()
+    };
+
+    let log_handler = if let Some(directory) = cli_opts.log_dir {
+        let directory = match &cli_opts.fs_root {
+            Some(root) => root.join(directory),
+            None => directory,
+        };
+        redshirt_log_hosted::LogHandler::new()
+            .with_log_files(
+                redshirt_log_hosted::LogFilesConfig {
+                    directory,
+                    max_file_size: cli_opts.log_max_file_size,
+                    max_rotated_files: cli_opts.log_max_rotated_files,
+                },
+                &programs_registry,
+            )
+            .expect("failed to create the logs directory")
+    } else {
+        redshirt_log_hosted::LogHandler::new()
+    };
+
+    let mut system_builder = redshirt_core::system::SystemBuilder::new()
+        .with_programs_registry(&programs_registry)
+        .with_monotonic_clock(|| {
+            u64::try_from(redshirt_time_hosted::monotonic_clock()).unwrap_or(u64::max_value())
+        })
         .with_native_program(redshirt_time_hosted::TimerHandler::new())
-        .with_native_program(redshirt_tcp_hosted::TcpHandler::new())
-        .with_native_program(redshirt_log_hosted::LogHandler::new())
+        .with_native_program(
+            redshirt_threadpool_hosted::ThreadPoolNativeProgram::with_dedicated_thread(
+                tcp_handler,
+                256,
+            ),
+        )
+        .with_native_program(redshirt_udp_hosted::UdpHandler::new())
+        .with_native_program(log_handler)
         .with_native_program(redshirt_random_hosted::RandomNativeProgram::new())
         .with_startup_process(build_wasm_module!(
             "../../../modules/p2p-loader",
             "modules-loader"
         ))
+        .with_startup_process(build_wasm_module!("../../../modules/http-client"))
+        .with_startup_process(build_wasm_module!("../../../modules/websocket-client"))
         .with_main_programs(cli_opts.module_hash)
-        .with_main_programs(cli_opts.background_module_hash)
-        .build()
-        .expect("Failed to start system");
+        .with_main_programs(cli_opts.background_module_hash);
+
+    if let Some(root) = cli_opts.fs_root {
+        system_builder = system_builder
+            .with_native_program(redshirt_filesystem_hosted::FilesystemHandler::new(root));
+    }
+
+    if let Some(period) = cli_opts.self_check_period {
+        system_builder =
+            system_builder.with_self_check(redshirt_core::scheduler::SelfCheckConfig {
+                period,
+                ..Default::default()
+            });
+    }
+
+    let system = system_builder.build().expect("Failed to start system");
+
+    if cli_opts.inspect {
+        let mut all_compatible = true;
+        for (module_path, module, _) in &cli_requested_processes {
+            let report = system.abi_report(module);
+            all_compatible &= report.is_compatible();
+            println!("{}: {}", module_path.display(), report);
+        }
+        process::exit(if all_compatible { 0 } else { 1 });
+    }
 
     let mut cli_pids = Vec::with_capacity(cli_requested_processes.len());
     // TODO: should also contain the `module_hash`es
@@ -103,20 +231,75 @@ async fn async_main() {
             redshirt_core::system::SystemRunOutcome::ProgramFinished {
                 pid,
                 outcome: Err(err),
+                crash_report,
+                ..
             } if cli_pids.iter().any(|p| *p == pid) => {
-                eprintln!("{:?}", err);
+                if let Some(report) = crash_report {
+                    eprintln!("{}:{}: {}", report.file, report.line, report.message);
+                }
+                eprintln!("{}", err);
                 process::exit(1);
             }
+            redshirt_core::system::SystemRunOutcome::ProgramFinished {
+                pid,
+                exit_status: redshirt_core::system::ExitStatus::Exited(code),
+                ..
+            } if code != 0 && cli_pids.iter().any(|p| *p == pid) => {
+                process::exit(code as i32);
+            }
             redshirt_core::system::SystemRunOutcome::ProgramFinished {
                 pid,
                 outcome: Ok(()),
+                ..
             } => {
                 cli_pids.retain(|p| *p != pid);
                 if cli_pids.is_empty() {
                     process::exit(0);
                 }
             }
+            redshirt_core::system::SystemRunOutcome::SelfCheckFailed { violations } => {
+                for violation in violations {
+                    eprintln!("Self-check failed: {}", violation);
+                }
+            }
+            redshirt_core::system::SystemRunOutcome::ProgramLoadFailed { hash, error } => {
+                eprintln!("Failed to start {:?}: {:?}", hash, error);
+            }
             _ => panic!(),
         }
     }
 }
+
+/// Asks the user on the terminal whether a program is allowed to connect to a host.
+fn ask_connect_permission(
+    request: redshirt_tcp_hosted::ConnectRequest,
+) -> BoxFuture<'static, bool> {
+    let (tx, rx) = oneshot::channel();
+
+    // Reading from stdin is blocking, so we do it in a separate thread. Locking stdin before
+    // printing the question guarantees that questions are asked one at a time.
+    thread::spawn(move || {
+        let stdin = io::stdin();
+        let mut stdin = stdin.lock();
+
+        let program = match &request.program {
+            Some(hash) => format!("{:?}", hash),
+            None => format!("{:?}", request.pid),
+        };
+        eprint!(
+            "Allow {} to connect to {}? [y/N] ",
+            program,
+            request.remote.ip()
+        );
+        let _ = io::stderr().flush();
+
+        let mut answer = String::new();
+        let allowed = match stdin.read_line(&mut answer) {
+            Ok(_) => answer.trim().eq_ignore_ascii_case("y"),
+            Err(_) => false,
+        };
+        let _ = tx.send(allowed);
+    });
+
+    rx.map(|allowed| allowed.unwrap_or(false)).boxed()
+}
@@ -0,0 +1,406 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Implements the filesystem interface.
+//!
+//! Programs only have access to the content of a directory of the host, passed to
+//! [`FilesystemHandler::new`], which they see as the root of the filesystem.
+//!
+//! > **Note**: Symbolic links found within this directory are followed, and can point outside
+//! >           of it.
+
+use async_std::{fs, sync::Mutex, task};
+use futures::{channel::mpsc, prelude::*};
+use redshirt_core::handles::HandleTable;
+use redshirt_core::native::{
+    DummyMessageIdWrite, NativeProgramEvent, NativeProgramRef, ResourceHandle, ResourceKind,
+};
+use redshirt_core::{
+    Decode as _, Encode as _, EncodedMessage, Handle, InterfaceHash, MessageId, Pid,
+};
+use redshirt_filesystem_interface::ffi;
+use std::{
+    fmt, io,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{atomic, Arc},
+};
+
+/// Maximum number of bytes that are read from a file in response to a single message.
+const MAX_READ_LEN: u32 = 1024 * 1024;
+
+/// Native process that gives access to a directory of the host.
+pub struct FilesystemHandler {
+    /// If true, we have sent the interface registration message.
+    registered: atomic::AtomicBool,
+
+    /// Directory that programs see as the root of the filesystem.
+    root: Arc<PathBuf>,
+
+    /// List of open files. Each file belongs to the process that has opened it, and only this
+    /// process can use its handle. The files are locked by the background tasks while they
+    /// access them.
+    files: Arc<parking_lot::Mutex<HandleTable<Arc<Mutex<fs::File>>>>>,
+
+    /// Receives the events to emit, from the background tasks. Answers, and the notifications
+    /// about the files that are opened and closed.
+    receiver: Mutex<mpsc::UnboundedReceiver<NativeProgramEvent<DummyMessageIdWrite>>>,
+
+    /// Sending side of `receiver`. Meant to be cloned and sent to background tasks.
+    sender: mpsc::UnboundedSender<NativeProgramEvent<DummyMessageIdWrite>>,
+}
+
+impl FilesystemHandler {
+    /// Initializes a new [`FilesystemHandler`] giving access to the given directory.
+    ///
+    /// The directory isn't created if it doesn't exist, in which case all the operations fail.
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        let (sender, receiver) = mpsc::unbounded();
+
+        FilesystemHandler {
+            registered: atomic::AtomicBool::new(false),
+            root: Arc::new(root.into()),
+            files: Arc::new(parking_lot::Mutex::new(HandleTable::new())),
+            receiver: Mutex::new(receiver),
+            sender,
+        }
+    }
+
+    /// Returns the file with the given handle, if it has been opened by `emitter_pid`.
+    fn file(&self, handle: Handle, emitter_pid: Pid) -> Option<Arc<Mutex<fs::File>>> {
+        self.files
+            .lock()
+            .get(emitter_pid, handle, ResourceKind::File)
+            .ok()
+            .cloned()
+    }
+}
+
+impl<'a> NativeProgramRef<'a> for &'a FilesystemHandler {
+    type Future =
+        Pin<Box<dyn Future<Output = NativeProgramEvent<Self::MessageIdWrite>> + Send + 'a>>;
+    type MessageIdWrite = DummyMessageIdWrite;
+
+    fn next_event(self) -> Self::Future {
+        Box::pin(async move {
+            if !self.registered.swap(true, atomic::Ordering::Relaxed) {
+                return NativeProgramEvent::Emit {
+                    interface: redshirt_interface_interface::ffi::INTERFACE,
+                    message_id_write: None,
+                    message: redshirt_interface_interface::ffi::InterfaceMessage::Register(
+                        ffi::INTERFACE,
+                    )
+                    .encode(),
+                };
+            }
+
+            let mut receiver = self.receiver.lock().await;
+            receiver.next().await.unwrap()
+        })
+    }
+
+    fn interface_message(
+        self,
+        interface: InterfaceHash,
+        message_id: Option<MessageId>,
+        emitter_pid: Pid,
+        message: EncodedMessage,
+    ) {
+        debug_assert_eq!(interface, ffi::INTERFACE);
+
+        let message = match ffi::FsMessage::decode(message) {
+            Ok(msg) => msg,
+            Err(_) => return, // TODO: produce error
+        };
+
+        // Closing is the only message that doesn't expect an answer.
+        if let ffi::FsMessage::Close(close) = message {
+            let mut files = self.files.lock();
+            if files
+                .remove(emitter_pid, close.handle, ResourceKind::File)
+                .is_ok()
+            {
+                let _ = self
+                    .sender
+                    .unbounded_send(NativeProgramEvent::ReleaseResource {
+                        owner: emitter_pid,
+                        resource: file_resource(close.handle),
+                    });
+            }
+            return;
+        }
+
+        let message_id = match message_id {
+            Some(m) => m,
+            None => return,
+        };
+
+        let sender = self.sender.clone();
+        let answer = move |answer: EncodedMessage| {
+            let _ = sender.unbounded_send(NativeProgramEvent::Answer {
+                message_id,
+                answer: Ok(answer),
+            });
+        };
+
+        match message {
+            ffi::FsMessage::Close(_) => unreachable!(),
+
+            ffi::FsMessage::Open(open) => {
+                let path = match resolve(&self.root, &open.path) {
+                    Ok(p) => p,
+                    Err(err) => return answer(ffi::FsOpenResponse { result: Err(err) }.encode()),
+                };
+
+                let files = self.files.clone();
+                let events = self.sender.clone();
+                task::spawn(async move {
+                    let result = fs::OpenOptions::new()
+                        .read(open.read)
+                        .write(open.write)
+                        .create(open.create)
+                        .truncate(open.truncate)
+                        .open(path)
+                        .await
+                        .map_err(fs_error)
+                        .map(|file| {
+                            let handle = files.lock().insert(
+                                emitter_pid,
+                                ResourceKind::File,
+                                Arc::new(Mutex::new(file)),
+                            );
+                            // Reported before the answer, so that the file is closed if the
+                            // process dies before closing it.
+                            let _ = events.unbounded_send(NativeProgramEvent::AcquireResource {
+                                owner: emitter_pid,
+                                resource: file_resource(handle),
+                            });
+                            handle
+                        });
+                    answer(ffi::FsOpenResponse { result }.encode());
+                });
+            }
+
+            ffi::FsMessage::Read(read) => {
+                let file = match self.file(read.handle, emitter_pid) {
+                    Some(f) => f,
+                    None => {
+                        return answer(
+                            ffi::FsReadResponse {
+                                result: Err(ffi::FsError::InvalidHandle),
+                            }
+                            .encode(),
+                        )
+                    }
+                };
+
+                task::spawn(async move {
+                    let mut buffer = vec![0; read.len.min(MAX_READ_LEN) as usize];
+                    let result = match file.lock().await.read(&mut buffer).await {
+                        Ok(num_read) => {
+                            buffer.truncate(num_read);
+                            Ok(buffer)
+                        }
+                        Err(err) => Err(fs_error(err)),
+                    };
+                    answer(ffi::FsReadResponse { result }.encode());
+                });
+            }
+
+            ffi::FsMessage::Write(write) => {
+                let file = match self.file(write.handle, emitter_pid) {
+                    Some(f) => f,
+                    None => {
+                        return answer(
+                            ffi::FsWriteResponse {
+                                result: Err(ffi::FsError::InvalidHandle),
+                            }
+                            .encode(),
+                        )
+                    }
+                };
+
+                task::spawn(async move {
+                    let result = file
+                        .lock()
+                        .await
+                        .write_all(&write.data)
+                        .await
+                        .map_err(fs_error);
+                    answer(ffi::FsWriteResponse { result }.encode());
+                });
+            }
+
+            ffi::FsMessage::Seek(seek) => {
+                let file = match self.file(seek.handle, emitter_pid) {
+                    Some(f) => f,
+                    None => {
+                        return answer(
+                            ffi::FsSeekResponse {
+                                result: Err(ffi::FsError::InvalidHandle),
+                            }
+                            .encode(),
+                        )
+                    }
+                };
+
+                let position = match seek.position {
+                    ffi::FsSeekFrom::Start(n) => io::SeekFrom::Start(n),
+                    ffi::FsSeekFrom::End(n) => io::SeekFrom::End(n),
+                    ffi::FsSeekFrom::Current(n) => io::SeekFrom::Current(n),
+                };
+
+                task::spawn(async move {
+                    let result = file.lock().await.seek(position).await.map_err(fs_error);
+                    answer(ffi::FsSeekResponse { result }.encode());
+                });
+            }
+
+            ffi::FsMessage::ReadDir(read_dir) => {
+                let path = match resolve(&self.root, &read_dir.path) {
+                    Ok(p) => p,
+                    Err(err) => {
+                        return answer(ffi::FsReadDirResponse { result: Err(err) }.encode())
+                    }
+                };
+
+                task::spawn(async move {
+                    let result = read_dir_entries(&path).await.map_err(fs_error);
+                    answer(ffi::FsReadDirResponse { result }.encode());
+                });
+            }
+
+            ffi::FsMessage::Metadata(metadata) => {
+                let path = match resolve(&self.root, &metadata.path) {
+                    Ok(p) => p,
+                    Err(err) => {
+                        return answer(ffi::FsMetadataResponse { result: Err(err) }.encode())
+                    }
+                };
+
+                task::spawn(async move {
+                    let result = fs::metadata(&path)
+                        .await
+                        .map(|metadata| ffi::FsMetadataInfo {
+                            is_dir: metadata.is_dir(),
+                            len: metadata.len(),
+                        })
+                        .map_err(fs_error);
+                    answer(ffi::FsMetadataResponse { result }.encode());
+                });
+            }
+        }
+    }
+
+    fn process_destroyed(self, _: Pid) {
+        // The files of the process are closed in `resources_released`.
+    }
+
+    fn resources_released(self, pid: Pid, resources: Vec<ResourceHandle>) {
+        let mut files = self.files.lock();
+        for resource in resources {
+            let _ = files.remove(pid, Handle::from(resource.id), resource.kind);
+        }
+    }
+
+    fn message_response(self, _: MessageId, _: Result<EncodedMessage, ()>) {
+        unreachable!()
+    }
+}
+
+impl fmt::Debug for FilesystemHandler {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("FilesystemHandler")
+            .field(&self.root)
+            .finish()
+    }
+}
+
+/// Builds the [`ResourceHandle`] reported to the kernel for the given file.
+fn file_resource(handle: Handle) -> ResourceHandle {
+    ResourceHandle {
+        kind: ResourceKind::File,
+        id: u64::from(handle),
+    }
+}
+
+/// Turns a path passed by a program into a path of the host.
+///
+/// Returns an error if the path designates something outside of `root`.
+fn resolve(root: &Path, path: &str) -> Result<PathBuf, ffi::FsError> {
+    let mut components = Vec::new();
+    for component in path.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                if components.pop().is_none() {
+                    return Err(ffi::FsError::PermissionDenied);
+                }
+            }
+            // Backslashes are separators on Windows, and colons designate drives.
+            c if c.contains(|c| c == '\\' || c == ':' || c == '\0') => {
+                return Err(ffi::FsError::PermissionDenied)
+            }
+            c => components.push(c),
+        }
+    }
+
+    let mut out = root.to_owned();
+    out.extend(components);
+    Ok(out)
+}
+
+/// Returns the list of entries of the given directory.
+async fn read_dir_entries(path: &Path) -> Result<Vec<ffi::FsDirEntry>, io::Error> {
+    let mut entries = fs::read_dir(path).await?;
+    let mut out = Vec::new();
+    while let Some(entry) = entries.next().await {
+        let entry = entry?;
+        out.push(ffi::FsDirEntry {
+            name: entry.file_name().to_string_lossy().into_owned(),
+            is_dir: entry.file_type().await?.is_dir(),
+        });
+    }
+    Ok(out)
+}
+
+/// Converts an error of the host into an error of the interface.
+fn fs_error(err: io::Error) -> ffi::FsError {
+    match err.kind() {
+        io::ErrorKind::NotFound => ffi::FsError::NotFound,
+        io::ErrorKind::PermissionDenied => ffi::FsError::PermissionDenied,
+        _ => ffi::FsError::Other,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::resolve;
+    use redshirt_filesystem_interface::ffi::FsError;
+    use std::path::Path;
+
+    #[test]
+    fn paths_stay_within_root() {
+        let root = Path::new("root");
+        assert_eq!(resolve(root, "/a/./b/../c"), Ok(root.join("a").join("c")));
+        assert_eq!(resolve(root, ""), Ok(root.to_owned()));
+        assert_eq!(resolve(root, "a/../.."), Err(FsError::PermissionDenied));
+        assert_eq!(
+            resolve(root, "a\\..\\..\\b"),
+            Err(FsError::PermissionDenied)
+        );
+        assert_eq!(resolve(root, "C:"), Err(FsError::PermissionDenied));
+    }
+}
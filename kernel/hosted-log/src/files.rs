@@ -0,0 +1,264 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Persisting the logs of each program to files.
+//!
+//! The logs of each program are written to a file named after the base58 encoding of the hash
+//! of its module, so that they can be found across restarts of the program. Processes started
+//! from the same module share the same file. Processes whose module isn't known, such as native
+//! programs, get a file named after their [`Pid`].
+//!
+//! Once a file reaches a certain size, it is renamed by appending `.1` to its name, the previous
+//! `.1` file becomes `.2`, and so on. Files beyond the configured retention are deleted.
+
+use redshirt_core::{module::ModuleHash, Pid};
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    fs,
+    io::{self, Write as _},
+    path::PathBuf,
+    sync::Mutex,
+};
+
+/// Configuration for persisting the logs of each program to files.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogFilesConfig {
+    /// Directory where to write the files. Created if it doesn't exist.
+    pub directory: PathBuf,
+    /// Size, in bytes, above which a log file gets rotated.
+    pub max_file_size: u64,
+    /// Number of rotated files to keep for each program, in addition to the one being written.
+    pub max_rotated_files: u32,
+}
+
+impl Default for LogFilesConfig {
+    fn default() -> Self {
+        LogFilesConfig {
+            directory: PathBuf::from("logs"),
+            max_file_size: 1024 * 1024,
+            max_rotated_files: 4,
+        }
+    }
+}
+
+/// Log files currently being written.
+pub(crate) struct LogFiles {
+    config: LogFilesConfig,
+    inner: Mutex<Inner>,
+}
+
+struct Inner {
+    /// Name of the log file of each process that has emitted logs, without extension.
+    names: HashMap<Pid, String>,
+    /// Files currently open, indexed by name.
+    files: HashMap<String, OpenFile>,
+}
+
+struct OpenFile {
+    file: fs::File,
+    /// Current size of the file, in bytes.
+    size: u64,
+    /// Number of entries in [`Inner::names`] that refer to this file.
+    processes: usize,
+}
+
+impl LogFiles {
+    /// Creates the directory indicated in the configuration if necessary.
+    pub(crate) fn new(config: LogFilesConfig) -> io::Result<Self> {
+        fs::create_dir_all(&config.directory)?;
+        Ok(LogFiles {
+            config,
+            inner: Mutex::new(Inner {
+                names: HashMap::new(),
+                files: HashMap::new(),
+            }),
+        })
+    }
+
+    /// Appends a line to the log file of the given process, rotating the file if necessary.
+    ///
+    /// `program` is the hash of the module the process has been started from, if known. It is
+    /// only looked at the first time a process emits logs.
+    pub(crate) fn write_line(
+        &self,
+        pid: Pid,
+        program: Option<&ModuleHash>,
+        line: &str,
+    ) -> io::Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        let inner = &mut *inner;
+
+        let name = match inner.names.get(&pid) {
+            Some(name) => name.clone(),
+            None => {
+                let name = match program {
+                    Some(hash) => hash.to_base58(),
+                    None => format!("pid-{}", u64::from(pid)),
+                };
+                let file = match inner.files.entry(name.clone()) {
+                    Entry::Occupied(e) => e.into_mut(),
+                    Entry::Vacant(e) => e.insert(self.open(&name)?),
+                };
+                file.processes += 1;
+                inner.names.insert(pid, name.clone());
+                name
+            }
+        };
+
+        let file = match inner.files.get_mut(&name) {
+            Some(f) => f,
+            None => unreachable!(),
+        };
+
+        let line_len = line.len() as u64 + 1;
+        if file.size != 0 && file.size.saturating_add(line_len) > self.config.max_file_size {
+            self.rotate(&name)?;
+            file.file = fs::File::create(self.path(&name, 0))?;
+            file.size = 0;
+        }
+
+        writeln!(file.file, "{}", line)?;
+        file.size += line_len;
+        Ok(())
+    }
+
+    /// Closes the log file of the given process, unless other processes of the same program
+    /// are still using it.
+    pub(crate) fn close(&self, pid: Pid) {
+        let mut inner = self.inner.lock().unwrap();
+        let name = match inner.names.remove(&pid) {
+            Some(n) => n,
+            None => return,
+        };
+
+        let unused = match inner.files.get_mut(&name) {
+            Some(file) => {
+                file.processes -= 1;
+                file.processes == 0
+            }
+            None => unreachable!(),
+        };
+        if unused {
+            inner.files.remove(&name);
+        }
+    }
+
+    /// Opens the log file with the given name, creating it if necessary.
+    fn open(&self, name: &str) -> io::Result<OpenFile> {
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.path(name, 0))?;
+        let size = file.metadata()?.len();
+        Ok(OpenFile {
+            file,
+            size,
+            processes: 0,
+        })
+    }
+
+    /// Shifts the rotated files with the given name, and deletes the oldest one if the
+    /// retention limit is reached.
+    fn rotate(&self, name: &str) -> io::Result<()> {
+        let oldest = self.path(name, self.config.max_rotated_files);
+        if oldest.exists() {
+            fs::remove_file(oldest)?;
+        }
+
+        for n in (0..self.config.max_rotated_files).rev() {
+            let path = self.path(name, n);
+            if path.exists() {
+                fs::rename(path, self.path(name, n + 1))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the path of the log file with the given name. `rotation` is 0 for the file
+    /// being written, 1 for the most recently rotated file, and so on.
+    fn path(&self, name: &str, rotation: u32) -> PathBuf {
+        let name = if rotation == 0 {
+            format!("{}.log", name)
+        } else {
+            format!("{}.log.{}", name, rotation)
+        };
+        self.config.directory.join(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LogFiles, LogFilesConfig};
+    use redshirt_core::{module::ModuleHash, Pid};
+    use std::{env, fs, path::PathBuf, process};
+
+    /// Returns an empty directory dedicated to the given test.
+    fn test_dir(test: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("redshirt-log-hosted-{}-{}", process::id(), test));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn files_named_after_program() {
+        let directory = test_dir("named");
+        let files = LogFiles::new(LogFilesConfig {
+            directory: directory.clone(),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let program = ModuleHash::from_bytes(b"foo");
+        files.write_line(Pid::from(1), Some(&program), "a").unwrap();
+        files.write_line(Pid::from(2), Some(&program), "b").unwrap();
+        files.write_line(Pid::from(3), None, "c").unwrap();
+        // The file of the program remains open as long as one of its processes is alive.
+        files.close(Pid::from(1));
+        files.write_line(Pid::from(2), None, "d").unwrap();
+        files.close(Pid::from(2));
+        files.close(Pid::from(3));
+
+        let program_file = directory.join(format!("{}.log", program.to_base58()));
+        assert_eq!(fs::read_to_string(program_file).unwrap(), "a\nb\nd\n");
+        let pid_file = directory.join("pid-3.log");
+        assert_eq!(fs::read_to_string(pid_file).unwrap(), "c\n");
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn rotation() {
+        let directory = test_dir("rotation");
+        let files = LogFiles::new(LogFilesConfig {
+            directory: directory.clone(),
+            max_file_size: 10,
+            max_rotated_files: 2,
+        })
+        .unwrap();
+
+        for line in &["1111111", "2222222", "3333333", "4444444"] {
+            files.write_line(Pid::from(1), None, line).unwrap();
+        }
+
+        let read = |name: &str| fs::read_to_string(directory.join(name)).ok();
+        assert_eq!(read("pid-1.log").as_deref(), Some("4444444\n"));
+        assert_eq!(read("pid-1.log.1").as_deref(), Some("3333333\n"));
+        assert_eq!(read("pid-1.log.2").as_deref(), Some("2222222\n"));
+        assert_eq!(read("pid-1.log.3"), None);
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
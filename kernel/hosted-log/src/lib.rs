@@ -14,12 +14,19 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 //! Implements the log interface by printing logs to stdout.
+//!
+//! The logs of each program can additionally be written to files. See [`LogFilesConfig`].
 
 use futures::prelude::*;
 use redshirt_core::native::{DummyMessageIdWrite, NativeProgramEvent, NativeProgramRef};
+use redshirt_core::system::ProgramsRegistry;
 use redshirt_core::{Decode as _, Encode as _, EncodedMessage, InterfaceHash, MessageId, Pid};
 use redshirt_log_interface::ffi::{DecodedLogMessage, Level, INTERFACE};
-use std::{borrow::Cow, pin::Pin, sync::atomic};
+use std::{borrow::Cow, io, pin::Pin, sync::atomic};
+
+pub use self::files::LogFilesConfig;
+
+mod files;
 
 /// Native program for `log` interface messages handling.
 pub struct LogHandler {
@@ -27,6 +34,10 @@ pub struct LogHandler {
     registered: atomic::AtomicBool,
     /// If true, enable terminal colors when printing the log messages.
     enable_colors: bool,
+    /// If `Some`, the logs are also written to files.
+    files: Option<files::LogFiles>,
+    /// Used to determine the program behind each process when writing logs to files.
+    programs: Option<ProgramsRegistry>,
 }
 
 impl LogHandler {
@@ -35,8 +46,28 @@ impl LogHandler {
         LogHandler {
             registered: atomic::AtomicBool::new(false),
             enable_colors: atty::is(atty::Stream::Stdout),
+            files: None,
+            programs: None,
         }
     }
+
+    /// Additionally writes the logs of each program to files, according to the given
+    /// configuration.
+    ///
+    /// The `registry` must have been passed to
+    /// [`SystemBuilder::with_programs_registry`](redshirt_core::system::SystemBuilder::with_programs_registry),
+    /// and is used to name the files after the program behind each process.
+    ///
+    /// Returns an error if the directory where to write the files can't be created.
+    pub fn with_log_files(
+        mut self,
+        config: LogFilesConfig,
+        registry: &ProgramsRegistry,
+    ) -> io::Result<Self> {
+        self.files = Some(files::LogFiles::new(config)?);
+        self.programs = Some(registry.clone());
+        Ok(self)
+    }
 }
 
 impl<'a> NativeProgramRef<'a> for &'a LogHandler {
@@ -106,12 +137,27 @@ impl<'a> NativeProgramRef<'a> for &'a LogHandler {
                     header_style.suffix(),
                     message
                 );
+
+                if let Some(files) = &self.files {
+                    let line = format!("[{}] {}", level, message);
+                    let program = self
+                        .programs
+                        .as_ref()
+                        .and_then(|p| p.module_hash(emitter_pid));
+                    if let Err(err) = files.write_line(emitter_pid, program.as_ref(), &line) {
+                        println!("failed to write logs of {:?}: {}", emitter_pid, err);
+                    }
+                }
             }
             Err(_) => println!("bad log message from {:?}", emitter_pid),
         }
     }
 
-    fn process_destroyed(self, _: Pid) {}
+    fn process_destroyed(self, pid: Pid) {
+        if let Some(files) = &self.files {
+            files.close(pid);
+        }
+    }
 
     fn message_response(self, _: MessageId, _: Result<EncodedMessage, ()>) {
         unreachable!()
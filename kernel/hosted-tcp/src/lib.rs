@@ -14,41 +14,101 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 //! Implements the TCP interface.
+//!
+//! By default, programs are allowed to connect to any host. Use
+//! [`TcpHandler::with_authorization`] in order to ask for permission the first time a program
+//! connects to a host.
+//!
+//! Also implements the TLS interface, which starts TLS sessions on the sockets of the TCP
+//! interface.
 
 use async_std::{
+    io,
     net::{TcpListener, TcpStream},
     sync::Mutex,
     task,
 };
 use fnv::FnvHashMap;
-use futures::{channel::mpsc, prelude::*};
-use redshirt_core::native::{DummyMessageIdWrite, NativeProgramEvent, NativeProgramRef};
-use redshirt_core::{Decode as _, Encode as _, EncodedMessage, InterfaceHash, MessageId, Pid};
+use futures::{channel::mpsc, future::BoxFuture, prelude::*};
+use redshirt_core::handles::HandleTable;
+use redshirt_core::module::ModuleHash;
+use redshirt_core::native::{
+    DummyMessageIdWrite, NativeProgramEvent, NativeProgramRef, ResourceHandle, ResourceKind,
+};
+use redshirt_core::system::ProgramsRegistry;
+use redshirt_core::{
+    Decode as _, Encode as _, EncodedMessage, Handle, InterfaceHash, MessageId, Pid,
+};
 use redshirt_tcp_interface::ffi;
+use redshirt_tls_interface::ffi as tls_ffi;
 use std::{
-    collections::{hash_map::Entry, VecDeque},
+    collections::VecDeque,
     fmt, mem,
-    net::{Ipv6Addr, SocketAddr},
+    net::{IpAddr, Ipv6Addr, SocketAddr},
     pin::Pin,
-    sync::atomic,
+    sync::{atomic, Arc},
+    time::{Duration, Instant},
 };
 
+/// Maximum number of bytes that are read from a socket in response to a single message.
+const MAX_READ_LEN: u32 = 64 * 1024;
+
+/// Maximum number of bytes waiting to be written on a socket. Writes that would go above this
+/// limit are refused, unless nothing is waiting to be written.
+const MAX_QUEUED_WRITE_LEN: usize = 256 * 1024;
+
 /// Native process for TCP/IP connections that use the host operating system.
 pub struct TcpHandler {
     /// If true, we have sent the interface registration message.
     registered: atomic::AtomicBool,
 
+    /// If true, we have sent the TLS interface registration message.
+    tls_registered: atomic::AtomicBool,
+
     /// Receives messages from the sockets background tasks.
     receiver: Mutex<mpsc::Receiver<BackToFront>>,
 
-    /// List of all active sockets. Contains both open and non-open sockets.
-    sockets: parking_lot::Mutex<FnvHashMap<u32, FrontSocketState>>,
+    /// List of all active sockets, each owned by the process that has opened it. Contains both
+    /// open and non-open sockets.
+    sockets: parking_lot::Mutex<HandleTable<FrontSocketState>>,
 
     /// List of open TCP listeners by port.
     listeners: parking_lot::Mutex<FnvHashMap<u16, mpsc::UnboundedSender<FrontToBackListener>>>,
 
     /// Sending side of `receiver`. Meant to be cloned and sent to background tasks.
     sender: mpsc::Sender<BackToFront>,
+
+    /// Receives the notifications about the sockets that are opened and closed, to report to
+    /// the kernel.
+    resource_events: Mutex<mpsc::UnboundedReceiver<NativeProgramEvent<DummyMessageIdWrite>>>,
+
+    /// Sending side of `resource_events`.
+    resource_events_tx: mpsc::UnboundedSender<NativeProgramEvent<DummyMessageIdWrite>>,
+
+    /// If `Some`, outgoing connections must be authorized.
+    authorization: Option<Arc<Authorization>>,
+}
+
+/// Request to open an outgoing connection, passed to the hook registered with
+/// [`TcpHandler::with_authorization`].
+#[derive(Debug, Clone)]
+pub struct ConnectRequest {
+    /// Process that wants to connect.
+    pub pid: Pid,
+    /// Module the process has been started from, or `None` if unknown.
+    pub program: Option<ModuleHash>,
+    /// Address the process wants to connect to.
+    pub remote: SocketAddr,
+}
+
+/// Decides whether outgoing connections are allowed.
+struct Authorization {
+    /// Used to determine the module of the processes that connect.
+    programs: ProgramsRegistry,
+    /// Called for each connection whose decision isn't in `decisions`.
+    hook: Box<dyn Fn(ConnectRequest) -> BoxFuture<'static, bool> + Send + Sync>,
+    /// Decisions that have already been made, by program and remote host.
+    decisions: parking_lot::Mutex<FnvHashMap<(ModuleHash, IpAddr), bool>>,
 }
 
 /// State of a socket known from the front state.
@@ -67,17 +127,30 @@ enum FrontSocketState {
 enum FrontToBackSocket {
     Read {
         message_id: MessageId,
+        max_len: u32,
     },
     Write {
         message_id: MessageId,
         data: Vec<u8>,
     },
+    StartTls {
+        message_id: MessageId,
+        server_name: String,
+    },
+    SetOption {
+        message_id: MessageId,
+        option: ffi::TcpOption,
+    },
+    Info {
+        message_id: MessageId,
+    },
 }
 
 /// Message sent from the main task to the background task for listeners.
 enum FrontToBackListener {
     NewSocket {
-        socket_id: u32,
+        owner: Pid,
+        socket_id: Handle,
         open_message_id: MessageId,
     },
 }
@@ -86,20 +159,37 @@ enum FrontToBackListener {
 enum BackToFront {
     OpenOk {
         open_message_id: MessageId,
-        socket_id: u32,
+        owner: Pid,
+        socket_id: Handle,
         sender: mpsc::UnboundedSender<FrontToBackSocket>,
+        local_addr: SocketAddr,
+        remote_addr: SocketAddr,
     },
     OpenErr {
         open_message_id: MessageId,
-        socket_id: u32,
+        owner: Pid,
+        socket_id: Handle,
+        error: ffi::TcpError,
     },
     Read {
         message_id: MessageId,
-        result: Result<Vec<u8>, ()>,
+        result: Result<Vec<u8>, ffi::TcpError>,
     },
     Write {
         message_id: MessageId,
-        result: Result<(), ()>,
+        result: Result<(), ffi::TcpError>,
+    },
+    StartTls {
+        message_id: MessageId,
+        result: Result<(), tls_ffi::TlsError>,
+    },
+    SetOption {
+        message_id: MessageId,
+        result: Result<(), ffi::TcpError>,
+    },
+    Info {
+        message_id: MessageId,
+        result: Result<ffi::TcpSocketDetails, ffi::TcpError>,
     },
 }
 
@@ -107,15 +197,136 @@ impl TcpHandler {
     /// Initializes a new empty [`TcpHandler`].
     pub fn new() -> Self {
         let (sender, receiver) = mpsc::channel(32);
+        let (resource_events_tx, resource_events) = mpsc::unbounded();
 
         TcpHandler {
             registered: atomic::AtomicBool::new(false),
-            sockets: parking_lot::Mutex::new(FnvHashMap::default()),
+            tls_registered: atomic::AtomicBool::new(false),
+            sockets: parking_lot::Mutex::new(HandleTable::new()),
             listeners: parking_lot::Mutex::new(FnvHashMap::default()),
             receiver: Mutex::new(receiver),
             sender,
+            resource_events: Mutex::new(resource_events),
+            resource_events_tx,
+            authorization: None,
         }
     }
+
+    /// Requires outgoing connections to be authorized by the given hook.
+    ///
+    /// The hook is called the first time a program connects to a given host, and must return
+    /// whether the connection is allowed. The decision is then remembered for this program and
+    /// host, including across multiple executions of the program. Connections are refused if
+    /// the hook returns `false`.
+    ///
+    /// The `registry` must have been passed to
+    /// [`SystemBuilder::with_programs_registry`](redshirt_core::system::SystemBuilder::with_programs_registry),
+    /// and is used to determine which program is behind a process.
+    pub fn with_authorization(
+        mut self,
+        registry: &ProgramsRegistry,
+        hook: impl Fn(ConnectRequest) -> BoxFuture<'static, bool> + Send + Sync + 'static,
+    ) -> Self {
+        self.authorization = Some(Arc::new(Authorization {
+            programs: registry.clone(),
+            hook: Box::new(hook),
+            decisions: parking_lot::Mutex::new(FnvHashMap::default()),
+        }));
+        self
+    }
+}
+
+impl TcpHandler {
+    /// Reports to the kernel that `owner` now holds the given socket.
+    fn report_acquired(&self, owner: Pid, socket_id: Handle) {
+        let _ = self
+            .resource_events_tx
+            .unbounded_send(NativeProgramEvent::AcquireResource {
+                owner,
+                resource: socket_resource(socket_id),
+            });
+    }
+
+    /// Reports to the kernel that `owner` no longer holds the given socket.
+    fn report_released(&self, owner: Pid, socket_id: Handle) {
+        let _ = self
+            .resource_events_tx
+            .unbounded_send(NativeProgramEvent::ReleaseResource {
+                owner,
+                resource: socket_resource(socket_id),
+            });
+    }
+
+    /// Handles a message on the TLS interface.
+    fn tls_message(
+        &self,
+        message_id: Option<MessageId>,
+        emitter_pid: Pid,
+        message: EncodedMessage,
+    ) {
+        let message = match tls_ffi::TlsMessage::decode(message) {
+            Ok(msg) => msg,
+            Err(_) => return, // TODO: produce error
+        };
+
+        let message_id = match message_id {
+            Some(m) => m,
+            None => return,
+        };
+
+        match message {
+            tls_ffi::TlsMessage::Connect(connect) => {
+                let mut sockets = self.sockets.lock();
+                let sent = sockets
+                    .get_mut(emitter_pid, connect.socket_id, ResourceKind::Socket)
+                    .ok()
+                    .and_then(|socket| socket.as_mut_connected())
+                    .map_or(false, |socket| {
+                        socket
+                            .unbounded_send(FrontToBackSocket::StartTls {
+                                message_id,
+                                server_name: connect.server_name,
+                            })
+                            .is_ok()
+                    });
+
+                if !sent {
+                    let mut sender = self.sender.clone();
+                    task::spawn(async move {
+                        let msg_to_front = BackToFront::StartTls {
+                            message_id,
+                            result: Err(tls_ffi::TlsError::InvalidSocket),
+                        };
+                        let _ = sender.send(msg_to_front).await;
+                    });
+                }
+            }
+        }
+    }
+}
+
+impl Authorization {
+    /// Returns whether the given connection is allowed, calling the hook if necessary.
+    // TODO: if multiple connections to the same host are requested at the same time, the hook
+    // is called multiple times
+    async fn authorize(&self, request: ConnectRequest) -> bool {
+        // Decisions about processes whose program is unknown can't be remembered.
+        let key = request
+            .program
+            .clone()
+            .map(|program| (program, request.remote.ip()));
+        if let Some(key) = &key {
+            if let Some(decision) = self.decisions.lock().get(key) {
+                return *decision;
+            }
+        }
+
+        let decision = (self.hook)(request).await;
+        if let Some(key) = key {
+            self.decisions.lock().insert(key, decision);
+        }
+        decision
+    }
 }
 
 impl<'a> NativeProgramRef<'a> for &'a TcpHandler {
@@ -136,31 +347,63 @@ impl<'a> NativeProgramRef<'a> for &'a TcpHandler {
                 };
             }
 
+            if !self.tls_registered.swap(true, atomic::Ordering::Relaxed) {
+                return NativeProgramEvent::Emit {
+                    interface: redshirt_interface_interface::ffi::INTERFACE,
+                    message_id_write: None,
+                    message: redshirt_interface_interface::ffi::InterfaceMessage::Register(
+                        tls_ffi::INTERFACE,
+                    )
+                    .encode(),
+                };
+            }
+
             let message = {
+                let mut resource_events = self.resource_events.lock().await;
                 let mut receiver = self.receiver.lock().await;
-                receiver.next().await.unwrap()
+                // Resource events go first, so that a socket is known to the kernel before
+                // the answer that hands it to its owner.
+                match future::select(resource_events.next(), receiver.next()).await {
+                    future::Either::Left((event, _)) => return event.unwrap(),
+                    future::Either::Right((message, _)) => message.unwrap(),
+                }
             };
 
             match message {
                 BackToFront::OpenOk {
                     open_message_id,
+                    owner,
                     socket_id,
                     sender,
+                    local_addr,
+                    remote_addr,
                 } => {
                     let mut sockets = self.sockets.lock();
-                    let front_state = sockets.get_mut(&socket_id).unwrap();
-                    // TODO: debug_assert is orphan
-                    *front_state = FrontSocketState::Connected(sender);
+                    match sockets.get_mut(owner, socket_id, ResourceKind::Socket) {
+                        // TODO: debug_assert is orphan
+                        Ok(front_state) => *front_state = FrontSocketState::Connected(sender),
+                        // The socket has been closed while it was being opened. Dropping
+                        // `sender` stops the background task.
+                        Err(_) => {
+                            return NativeProgramEvent::Answer {
+                                message_id: open_message_id,
+                                answer: Ok(redshirt_tcp_interface::ffi::TcpOpenResponse {
+                                    result: Err(ffi::TcpError::InvalidSocket),
+                                }
+                                .encode()),
+                            }
+                        }
+                    }
 
                     return NativeProgramEvent::Answer {
                         message_id: open_message_id,
                         answer: Ok(redshirt_tcp_interface::ffi::TcpOpenResponse {
                             result: Ok(redshirt_tcp_interface::ffi::TcpSocketOpen {
                                 socket_id,
-                                local_ip: [0; 8],  // FIXME:
-                                local_port: 0,     // FIXME:
-                                remote_ip: [0; 8], // FIXME:
-                                remote_port: 0,    // FIXME:
+                                local_ip: ip_segments(local_addr.ip()),
+                                local_port: local_addr.port(),
+                                remote_ip: ip_segments(remote_addr.ip()),
+                                remote_port: remote_addr.port(),
                             }),
                         }
                         .encode()),
@@ -169,19 +412,25 @@ impl<'a> NativeProgramRef<'a> for &'a TcpHandler {
 
                 BackToFront::OpenErr {
                     open_message_id,
+                    owner,
                     socket_id,
+                    error,
                 } => {
+                    // The socket might have been closed in the meanwhile.
                     let mut sockets = self.sockets.lock();
-                    let _front_state = sockets.remove(&socket_id);
-                    debug_assert!(match _front_state {
-                        Some(FrontSocketState::Orphan) => true,
+                    let front_state = sockets.remove(owner, socket_id, ResourceKind::Socket);
+                    debug_assert!(match front_state {
+                        Ok(FrontSocketState::Orphan) | Err(_) => true,
                         _ => false,
                     });
+                    if front_state.is_ok() {
+                        self.report_released(owner, socket_id);
+                    }
 
                     return NativeProgramEvent::Answer {
                         message_id: open_message_id,
                         answer: Ok(redshirt_tcp_interface::ffi::TcpOpenResponse {
-                            result: Err(()),
+                            result: Err(error),
                         }
                         .encode()),
                     };
@@ -202,6 +451,31 @@ impl<'a> NativeProgramRef<'a> for &'a TcpHandler {
                         ),
                     }
                 }
+
+                BackToFront::Info { message_id, result } => {
+                    return NativeProgramEvent::Answer {
+                        message_id,
+                        answer: Ok(
+                            redshirt_tcp_interface::ffi::TcpSocketInfoResponse { result }.encode(),
+                        ),
+                    }
+                }
+
+                BackToFront::SetOption { message_id, result } => {
+                    return NativeProgramEvent::Answer {
+                        message_id,
+                        answer: Ok(
+                            redshirt_tcp_interface::ffi::TcpSetOptionResponse { result }.encode()
+                        ),
+                    }
+                }
+
+                BackToFront::StartTls { message_id, result } => {
+                    return NativeProgramEvent::Answer {
+                        message_id,
+                        answer: Ok(tls_ffi::TlsConnectResponse { result }.encode()),
+                    }
+                }
             }
         })
     }
@@ -210,9 +484,14 @@ impl<'a> NativeProgramRef<'a> for &'a TcpHandler {
         self,
         interface: InterfaceHash,
         message_id: Option<MessageId>,
-        _emitter_pid: Pid, // TODO: use to check ownership of sockets
+        emitter_pid: Pid,
         message: EncodedMessage,
     ) {
+        if interface == tls_ffi::INTERFACE {
+            self.tls_message(message_id, emitter_pid, message);
+            return;
+        }
+
         debug_assert_eq!(interface, ffi::INTERFACE);
 
         let message = match ffi::TcpMessage::decode(message) {
@@ -238,20 +517,6 @@ impl<'a> NativeProgramRef<'a> for &'a TcpHandler {
                     }
                 };
 
-                // Find a vacant entry in `self.sockets` with a socket id.
-                let vacant_entry = {
-                    let mut tentative_socket_id = rand::random();
-                    loop {
-                        match sockets.entry(tentative_socket_id) {
-                            Entry::Vacant(e) => break e,
-                            Entry::Occupied(_) => {
-                                tentative_socket_id = tentative_socket_id.wrapping_add(1);
-                                continue;
-                            }
-                        }
-                    }
-                };
-
                 if open.listen {
                     let mut listeners = self.listeners.lock();
                     let listener_sender = listeners
@@ -265,27 +530,51 @@ impl<'a> NativeProgramRef<'a> for &'a TcpHandler {
                             tx
                         })
                         .clone();
+                    let socket_id = sockets.insert(
+                        emitter_pid,
+                        ResourceKind::Socket,
+                        FrontSocketState::Listener(listener_sender.clone()),
+                    );
+                    self.report_acquired(emitter_pid, socket_id);
                     listener_sender
                         .unbounded_send(FrontToBackListener::NewSocket {
-                            socket_id: *vacant_entry.key(),
+                            owner: emitter_pid,
+                            socket_id,
                             open_message_id: message_id,
                         })
                         .unwrap();
-                    vacant_entry.insert(FrontSocketState::Listener(listener_sender));
                 } else {
+                    let authorization = self.authorization.as_ref().map(|authorization| {
+                        let request = ConnectRequest {
+                            pid: emitter_pid,
+                            program: authorization.programs.module_hash(emitter_pid),
+                            remote: socket_addr,
+                        };
+                        (authorization.clone(), request)
+                    });
+
+                    let socket_id =
+                        sockets.insert(emitter_pid, ResourceKind::Socket, FrontSocketState::Orphan);
+                    self.report_acquired(emitter_pid, socket_id);
                     task::spawn(socket_task(
-                        *vacant_entry.key(),
+                        emitter_pid,
+                        socket_id,
                         message_id,
                         socket_addr,
+                        open.timeout_ms.map(Duration::from_millis),
+                        authorization,
                         self.sender.clone(),
                     ));
-
-                    vacant_entry.insert(FrontSocketState::Orphan);
                 }
             }
 
             ffi::TcpMessage::Close(close) => {
-                let _ = sockets.remove(&close.socket_id);
+                if sockets
+                    .remove(emitter_pid, close.socket_id, ResourceKind::Socket)
+                    .is_ok()
+                {
+                    self.report_released(emitter_pid, close.socket_id);
+                }
             }
 
             ffi::TcpMessage::Read(read) => {
@@ -294,13 +583,88 @@ impl<'a> NativeProgramRef<'a> for &'a TcpHandler {
                     None => return,
                 };
 
-                sockets
-                    .get_mut(&read.socket_id)
-                    .unwrap() // TODO: don't unwrap; but what to do?
-                    .as_mut_connected()
-                    .unwrap()
-                    .unbounded_send(FrontToBackSocket::Read { message_id })
-                    .unwrap(); // TODO: don't unwrap; but what to do?
+                let sent = sockets
+                    .get_mut(emitter_pid, read.socket_id, ResourceKind::Socket)
+                    .ok()
+                    .and_then(|socket| socket.as_mut_connected())
+                    .map_or(false, |socket| {
+                        socket
+                            .unbounded_send(FrontToBackSocket::Read {
+                                message_id,
+                                max_len: read.max_len,
+                            })
+                            .is_ok()
+                    });
+
+                if !sent {
+                    let mut sender = self.sender.clone();
+                    task::spawn(async move {
+                        let msg_to_front = BackToFront::Read {
+                            message_id,
+                            result: Err(ffi::TcpError::InvalidSocket),
+                        };
+                        let _ = sender.send(msg_to_front).await;
+                    });
+                }
+            }
+
+            ffi::TcpMessage::SetOption(set_option) => {
+                let message_id = match message_id {
+                    Some(m) => m,
+                    None => return,
+                };
+
+                let sent = sockets
+                    .get_mut(emitter_pid, set_option.socket_id, ResourceKind::Socket)
+                    .ok()
+                    .and_then(|socket| socket.as_mut_connected())
+                    .map_or(false, |socket| {
+                        socket
+                            .unbounded_send(FrontToBackSocket::SetOption {
+                                message_id,
+                                option: set_option.option,
+                            })
+                            .is_ok()
+                    });
+
+                if !sent {
+                    let mut sender = self.sender.clone();
+                    task::spawn(async move {
+                        let msg_to_front = BackToFront::SetOption {
+                            message_id,
+                            result: Err(ffi::TcpError::InvalidSocket),
+                        };
+                        let _ = sender.send(msg_to_front).await;
+                    });
+                }
+            }
+
+            ffi::TcpMessage::SocketInfo(info) => {
+                let message_id = match message_id {
+                    Some(m) => m,
+                    None => return,
+                };
+
+                let sent = sockets
+                    .get_mut(emitter_pid, info.socket_id, ResourceKind::Socket)
+                    .ok()
+                    .and_then(|socket| socket.as_mut_connected())
+                    .map_or(false, |socket| {
+                        socket
+                            .unbounded_send(FrontToBackSocket::Info { message_id })
+                            .is_ok()
+                    });
+
+                if !sent {
+                    let mut sender = self.sender.clone();
+                    task::spawn(async move {
+                        let msg_to_front = BackToFront::Info {
+                            message_id,
+                            result: Err(ffi::TcpError::InvalidSocket),
+                        };
+                        let _ = sender.send(msg_to_front).await;
+                    });
+                }
             }
 
             ffi::TcpMessage::Write(write) => {
@@ -309,22 +673,43 @@ impl<'a> NativeProgramRef<'a> for &'a TcpHandler {
                     None => return,
                 };
 
-                sockets
-                    .get_mut(&write.socket_id)
-                    .unwrap() // TODO: don't unwrap; but what to do?
-                    .as_mut_connected()
-                    .unwrap()
-                    .unbounded_send(FrontToBackSocket::Write {
-                        message_id,
-                        data: write.data,
-                    })
-                    .unwrap(); // TODO: don't unwrap; but what to do?
+                let sent = sockets
+                    .get_mut(emitter_pid, write.socket_id, ResourceKind::Socket)
+                    .ok()
+                    .and_then(|socket| socket.as_mut_connected())
+                    .map_or(false, |socket| {
+                        socket
+                            .unbounded_send(FrontToBackSocket::Write {
+                                message_id,
+                                data: write.data,
+                            })
+                            .is_ok()
+                    });
+
+                if !sent {
+                    let mut sender = self.sender.clone();
+                    task::spawn(async move {
+                        let msg_to_front = BackToFront::Write {
+                            message_id,
+                            result: Err(ffi::TcpError::InvalidSocket),
+                        };
+                        let _ = sender.send(msg_to_front).await;
+                    });
+                }
             }
         }
     }
 
     fn process_destroyed(self, _: Pid) {
-        // TODO: implement
+        // The sockets of the process are closed in `resources_released`.
+    }
+
+    fn resources_released(self, pid: Pid, resources: Vec<ResourceHandle>) {
+        // Dropping the state of a socket stops its background task.
+        let mut sockets = self.sockets.lock();
+        for resource in resources {
+            let _ = sockets.remove(pid, Handle::from(resource.id), resource.kind);
+        }
     }
 
     fn message_response(self, _: MessageId, _: Result<EncodedMessage, ()>) {
@@ -360,21 +745,55 @@ impl FrontSocketState {
     }
 }
 
+/// Builds the [`ResourceHandle`] reported to the kernel for the given socket.
+fn socket_resource(socket_id: Handle) -> ResourceHandle {
+    ResourceHandle {
+        kind: ResourceKind::Socket,
+        id: u64::from(socket_id),
+    }
+}
+
 /// Function executed in the background for each TCP socket.
 async fn socket_task(
-    socket_id: u32,
+    owner: Pid,
+    socket_id: Handle,
     open_message_id: MessageId,
     socket_addr: SocketAddr,
+    connect_timeout: Option<Duration>,
+    authorization: Option<(Arc<Authorization>, ConnectRequest)>,
     mut back_to_front: mpsc::Sender<BackToFront>,
 ) {
-    // First step is to try connect to the destination.
-    let (socket, commands_rx) = match TcpStream::connect(socket_addr).await {
+    if let Some((authorization, request)) = authorization {
+        if !authorization.authorize(request).await {
+            let msg_to_front = BackToFront::OpenErr {
+                owner,
+                socket_id,
+                open_message_id,
+                error: ffi::TcpError::PermissionDenied,
+            };
+            let _ = back_to_front.send(msg_to_front).await;
+            return;
+        }
+    }
+
+    // First step is to try connect to the destination. The timeout, if any, doesn't include the
+    // time spent waiting for the authorization.
+    let connect = TcpStream::connect(socket_addr);
+    let result = match connect_timeout {
+        Some(timeout) => io::timeout(timeout, connect).await,
+        None => connect.await,
+    };
+
+    let (socket, commands_rx) = match result {
         Ok(s) => {
             let (tx, rx) = mpsc::unbounded::<FrontToBackSocket>();
             let msg_to_front = BackToFront::OpenOk {
+                owner,
                 socket_id,
                 open_message_id,
                 sender: tx,
+                local_addr: s.local_addr().unwrap_or(socket_addr),
+                remote_addr: s.peer_addr().unwrap_or(socket_addr),
             };
 
             if back_to_front.send(msg_to_front).await.is_err() {
@@ -383,10 +802,12 @@ async fn socket_task(
 
             (s, rx)
         }
-        Err(_) => {
+        Err(err) => {
             let msg_to_front = BackToFront::OpenErr {
+                owner,
                 socket_id,
                 open_message_id,
+                error: tcp_error(&err),
             };
             let _ = back_to_front.send(msg_to_front).await;
             return;
@@ -402,6 +823,149 @@ async fn open_socket_task(
     mut commands_rx: mpsc::UnboundedReceiver<FrontToBackSocket>,
     mut back_to_front: mpsc::Sender<BackToFront>,
 ) {
+    let mut options = SocketOptions {
+        stream: socket.clone(),
+        read_timeout: None,
+        write_timeout: None,
+    };
+
+    let (socket, message_id, server_name) = match socket_io(
+        socket,
+        true,
+        &mut options,
+        &mut commands_rx,
+        &mut back_to_front,
+    )
+    .await
+    {
+        Some(v) => v,
+        None => return,
+    };
+
+    let socket = match async_tls::TlsConnector::new()
+        .connect(&server_name, socket)
+        .await
+    {
+        Ok(socket) => socket,
+        Err(err) => {
+            let error = tcp_error(&err);
+            let msg_to_front = BackToFront::StartTls {
+                message_id,
+                result: Err(match err.kind() {
+                    io::ErrorKind::InvalidInput => tls_ffi::TlsError::InvalidServerName,
+                    io::ErrorKind::InvalidData => tls_ffi::TlsError::Handshake,
+                    _ => tls_ffi::TlsError::Connection(error.clone()),
+                }),
+            };
+            if back_to_front.send(msg_to_front).await.is_err() {
+                return;
+            }
+            // The connection is unusable after a failed handshake.
+            while let Some(command) = commands_rx.next().await {
+                let msg_to_front = match command {
+                    FrontToBackSocket::Read { message_id, .. } => BackToFront::Read {
+                        message_id,
+                        result: Err(error.clone()),
+                    },
+                    FrontToBackSocket::Write { message_id, .. } => BackToFront::Write {
+                        message_id,
+                        result: Err(error.clone()),
+                    },
+                    FrontToBackSocket::StartTls { message_id, .. } => BackToFront::StartTls {
+                        message_id,
+                        result: Err(tls_ffi::TlsError::Connection(error.clone())),
+                    },
+                    FrontToBackSocket::SetOption { message_id, .. } => BackToFront::SetOption {
+                        message_id,
+                        result: Err(error.clone()),
+                    },
+                    FrontToBackSocket::Info { message_id } => BackToFront::Info {
+                        message_id,
+                        result: Err(error.clone()),
+                    },
+                };
+                if back_to_front.send(msg_to_front).await.is_err() {
+                    return;
+                }
+            }
+            return;
+        }
+    };
+
+    let msg_to_front = BackToFront::StartTls {
+        message_id,
+        result: Ok(()),
+    };
+    if back_to_front.send(msg_to_front).await.is_err() {
+        return;
+    }
+
+    let _ = socket_io(
+        socket,
+        false,
+        &mut options,
+        &mut commands_rx,
+        &mut back_to_front,
+    )
+    .await;
+}
+
+/// Options of a socket that can be changed by the program.
+struct SocketOptions {
+    /// Clone of the socket, used to change its options while it is being read and written.
+    stream: TcpStream,
+    /// Maximum duration of a read.
+    read_timeout: Option<Duration>,
+    /// Maximum duration of a write.
+    write_timeout: Option<Duration>,
+}
+
+impl SocketOptions {
+    /// Applies the given option.
+    fn set(&mut self, option: ffi::TcpOption) -> Result<(), ffi::TcpError> {
+        match option {
+            ffi::TcpOption::NoDelay(no_delay) => self
+                .stream
+                .set_nodelay(no_delay)
+                .map_err(|err| tcp_error(&err)),
+            ffi::TcpOption::Ttl(ttl) => self.stream.set_ttl(ttl).map_err(|err| tcp_error(&err)),
+            ffi::TcpOption::KeepAlive(None) => socket2::SockRef::from(&self.stream)
+                .set_keepalive(false)
+                .map_err(|err| tcp_error(&err)),
+            ffi::TcpOption::KeepAlive(Some(idle_secs)) => {
+                let keepalive =
+                    socket2::TcpKeepalive::new().with_time(Duration::from_secs(idle_secs.into()));
+                socket2::SockRef::from(&self.stream)
+                    .set_tcp_keepalive(&keepalive)
+                    .map_err(|err| tcp_error(&err))
+            }
+            ffi::TcpOption::ReadTimeout(timeout) => {
+                self.read_timeout = timeout.map(Duration::from_millis);
+                Ok(())
+            }
+            ffi::TcpOption::WriteTimeout(timeout) => {
+                self.write_timeout = timeout.map(Duration::from_millis);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Reads and writes data on the socket according to the commands.
+///
+/// If `tls_allowed` is true and a TLS session is requested while no read or write is in
+/// progress, returns the socket alongside the message to answer and the server name. TLS
+/// sessions requested in any other situation are refused. Returns `None` if the socket must be
+/// closed.
+async fn socket_io<S: AsyncRead + AsyncWrite + Unpin>(
+    socket: S,
+    tls_allowed: bool,
+    options: &mut SocketOptions,
+    commands_rx: &mut mpsc::UnboundedReceiver<FrontToBackSocket>,
+    back_to_front: &mut mpsc::Sender<BackToFront>,
+) -> Option<(S, MessageId, String)> {
+    let (mut socket_read, mut socket_write) = socket.split();
+
     // Buffer of data to write to the TCP socket.
     let mut write_buffer = Vec::new();
     // Value between 0 and `write_buffer.len()` indicating how many bytes at the start of
@@ -409,24 +973,65 @@ async fn open_socket_task(
     let mut write_buffer_offset = 0;
     // Message to answer when we finish writing the write buffer.
     let mut write_message = None;
+    // Moment when the current write times out, if any.
+    let mut write_deadline = None;
     // Buffer where to read data into.
     let mut read_buffer = Vec::new();
     // Message to answer if we read data.
     let mut read_message = None;
+    // Moment when the current read times out, if any.
+    let mut read_deadline = None;
+    // Reads and writes requested while another one was in progress, in order.
+    let mut queued_reads = VecDeque::<(MessageId, u32)>::new();
+    let mut queued_writes = VecDeque::new();
+    // True if the connection has been closed by the remote or has failed.
+    let mut closed = false;
 
     // Now that we're connected and we have a `socket` and `commands_rx`, we can start reading
     // and writing.
     loop {
+        if read_message.is_none() {
+            if let Some((message_id, max_len)) = queued_reads.pop_front() {
+                debug_assert!(read_buffer.is_empty());
+                read_message = Some(message_id);
+                read_deadline = options.read_timeout.map(|t| Instant::now() + t);
+                read_buffer = vec![0; max_len.max(1).min(MAX_READ_LEN) as usize];
+            }
+        }
+
+        if write_message.is_none() {
+            if let Some((message_id, data)) = queued_writes.pop_front() {
+                debug_assert!(write_buffer.is_empty());
+                debug_assert_eq!(write_buffer_offset, 0);
+                write_message = Some(message_id);
+                write_deadline = options.write_timeout.map(|t| Instant::now() + t);
+                write_buffer = data;
+                write_buffer_offset = 0;
+            }
+        }
+
         enum WhatHappened {
             ReadCmd {
                 message_id: MessageId,
+                max_len: u32,
             },
             WriteCmd {
                 message_id: MessageId,
                 data: Vec<u8>,
             },
-            ReadFinished,
-            WriteFinished,
+            StartTlsCmd {
+                message_id: MessageId,
+                server_name: String,
+            },
+            SetOptionCmd {
+                message_id: MessageId,
+                option: ffi::TcpOption,
+            },
+            InfoCmd {
+                message_id: MessageId,
+            },
+            ReadFinished(Result<(), ffi::TcpError>),
+            WriteFinished(Result<(), ffi::TcpError>),
         }
 
         let what_happened = {
@@ -434,12 +1039,20 @@ async fn open_socket_task(
                 if write_message.is_some() {
                     debug_assert!(!write_buffer.is_empty());
                     debug_assert!(write_buffer_offset < write_buffer.len());
-                    let num_written = (&socket)
-                        .write(&write_buffer[write_buffer_offset..])
-                        .await
-                        .unwrap(); // TODO: don't unwrap :(
-                    debug_assert!(write_buffer_offset + num_written <= write_buffer.len());
-                    write_buffer_offset += num_written;
+                    let write = socket_write.write(&write_buffer[write_buffer_offset..]);
+                    let result = match write_deadline {
+                        Some(deadline) => io::timeout(until(deadline), write).await,
+                        None => write.await,
+                    };
+                    match result {
+                        Ok(0) => Err(ffi::TcpError::Other(None)),
+                        Err(err) => Err(tcp_error(&err)),
+                        Ok(num_written) => {
+                            debug_assert!(write_buffer_offset + num_written <= write_buffer.len());
+                            write_buffer_offset += num_written;
+                            Ok(())
+                        }
+                    }
                 } else {
                     loop {
                         futures::pending!()
@@ -450,8 +1063,14 @@ async fn open_socket_task(
             let read = async {
                 if read_message.is_some() {
                     assert!(!read_buffer.is_empty());
-                    let num_read = (&socket).read(&mut read_buffer[..]).await.unwrap(); // TODO: don't unwrap :(
+                    let read = socket_read.read(&mut read_buffer[..]);
+                    let result = match read_deadline {
+                        Some(deadline) => io::timeout(until(deadline), read).await,
+                        None => read.await,
+                    };
+                    let num_read = result.map_err(|err| tcp_error(&err))?;
                     read_buffer.truncate(num_read);
+                    Ok(())
                 } else {
                     loop {
                         futures::pending!()
@@ -463,51 +1082,146 @@ async fn open_socket_task(
             futures::pin_mut!(next_command);
 
             match future::select(future::select(partial_write, read), next_command).await {
-                future::Either::Right((Some(FrontToBackSocket::Read { message_id }), _)) => {
-                    WhatHappened::ReadCmd { message_id }
-                }
+                future::Either::Right((
+                    Some(FrontToBackSocket::Read {
+                        message_id,
+                        max_len,
+                    }),
+                    _,
+                )) => WhatHappened::ReadCmd {
+                    message_id,
+                    max_len,
+                },
                 future::Either::Right((Some(FrontToBackSocket::Write { message_id, data }), _)) => {
                     WhatHappened::WriteCmd { message_id, data }
                 }
+                future::Either::Right((
+                    Some(FrontToBackSocket::StartTls {
+                        message_id,
+                        server_name,
+                    }),
+                    _,
+                )) => WhatHappened::StartTlsCmd {
+                    message_id,
+                    server_name,
+                },
+                future::Either::Right((
+                    Some(FrontToBackSocket::SetOption { message_id, option }),
+                    _,
+                )) => WhatHappened::SetOptionCmd { message_id, option },
+                future::Either::Right((Some(FrontToBackSocket::Info { message_id }), _)) => {
+                    WhatHappened::InfoCmd { message_id }
+                }
                 future::Either::Right((None, _)) => {
                     // `commands_rx` is closed, so let's stop the task.
-                    return;
+                    return None;
                 }
-                future::Either::Left((future::Either::Left((_, _)), _)) => {
-                    WhatHappened::WriteFinished
+                future::Either::Left((future::Either::Left((result, _)), _)) => {
+                    WhatHappened::WriteFinished(result)
                 }
-                future::Either::Left((future::Either::Right((_, _)), _)) => {
-                    WhatHappened::ReadFinished
+                future::Either::Left((future::Either::Right((result, _)), _)) => {
+                    WhatHappened::ReadFinished(result)
                 }
             }
         };
 
         match what_happened {
-            WhatHappened::ReadCmd { message_id } => {
-                // Read already in progress.
-                if read_message.is_some() {
-                    panic!(); // TODO: don't panic
+            // Reads and writes are started at the beginning of the next iteration, once the
+            // ones in progress are finished.
+            WhatHappened::ReadCmd {
+                message_id,
+                max_len,
+            } => queued_reads.push_back((message_id, max_len)),
+            WhatHappened::WriteCmd { message_id, data } => {
+                let queued_len = queued_writes
+                    .iter()
+                    .map(|(_, data): &(_, Vec<u8>)| data.len())
+                    .sum::<usize>()
+                    + (write_buffer.len() - write_buffer_offset);
+                if queued_len != 0 && queued_len + data.len() > MAX_QUEUED_WRITE_LEN {
+                    let msg_to_front = BackToFront::Write {
+                        message_id,
+                        result: Err(ffi::TcpError::WouldBlock),
+                    };
+                    if back_to_front.send(msg_to_front).await.is_err() {
+                        return None;
+                    }
+                } else {
+                    queued_writes.push_back((message_id, data));
                 }
+            }
 
-                assert!(read_buffer.is_empty());
-                read_message = Some(message_id);
-                read_buffer = vec![0; 512];
+            WhatHappened::StartTlsCmd {
+                message_id,
+                server_name,
+            } => {
+                if tls_allowed && read_message.is_none() && write_message.is_none() {
+                    let socket = socket_read.reunite(socket_write).unwrap();
+                    return Some((socket, message_id, server_name));
+                }
+
+                let error = if !tls_allowed {
+                    tls_ffi::TlsError::InvalidSocket
+                } else {
+                    tls_ffi::TlsError::Busy
+                };
+                let msg_to_front = BackToFront::StartTls {
+                    message_id,
+                    result: Err(error),
+                };
+                if back_to_front.send(msg_to_front).await.is_err() {
+                    return None;
+                }
             }
 
-            WhatHappened::WriteCmd { message_id, data } => {
-                // Write already in progress.
-                if write_message.is_some() {
-                    panic!(); // TODO: don't panic
+            WhatHappened::SetOptionCmd { message_id, option } => {
+                let msg_to_front = BackToFront::SetOption {
+                    message_id,
+                    result: options.set(option),
+                };
+                if back_to_front.send(msg_to_front).await.is_err() {
+                    return None;
                 }
+            }
 
-                debug_assert!(write_buffer.is_empty());
-                debug_assert_eq!(write_buffer_offset, 0);
-                write_message = Some(message_id);
-                write_buffer = data;
+            WhatHappened::InfoCmd { message_id } => {
+                let result = options
+                    .stream
+                    .local_addr()
+                    .and_then(|local| Ok((local, options.stream.peer_addr()?)))
+                    .map(|(local, remote)| ffi::TcpSocketDetails {
+                        local_ip: ip_segments(local.ip()),
+                        local_port: local.port(),
+                        remote_ip: ip_segments(remote.ip()),
+                        remote_port: remote.port(),
+                        state: if closed {
+                            ffi::TcpConnectionState::Closed
+                        } else {
+                            ffi::TcpConnectionState::Connected
+                        },
+                    })
+                    .map_err(|err| tcp_error(&err));
+                let msg_to_front = BackToFront::Info { message_id, result };
+                if back_to_front.send(msg_to_front).await.is_err() {
+                    return None;
+                }
+            }
+
+            WhatHappened::WriteFinished(Err(error)) => {
+                closed = true;
+                let message_id = write_message.take().unwrap();
+                write_buffer.clear();
                 write_buffer_offset = 0;
+                let msg_to_front = BackToFront::Write {
+                    message_id,
+                    result: Err(error),
+                };
+                if back_to_front.send(msg_to_front).await.is_err() {
+                    return None;
+                }
             }
 
-            WhatHappened::WriteFinished => {
+            WhatHappened::WriteFinished(Ok(())) => {
                 // Finished a partial write.
                 if write_buffer_offset == write_buffer.len() {
                     let message_id = write_message.take().unwrap();
@@ -518,27 +1232,53 @@ async fn open_socket_task(
                         result: Ok(()),
                     };
                     if back_to_front.send(msg_to_front).await.is_err() {
-                        return;
+                        return None;
                     }
                 }
             }
 
-            WhatHappened::ReadFinished => {
+            WhatHappened::ReadFinished(result) => {
                 // Finished a read.
                 let read_message = read_message.take().unwrap();
                 let buf = mem::replace(&mut read_buffer, Vec::new());
+                // An empty read means that the remote has closed its side of the connection.
+                if result.is_err() || buf.is_empty() {
+                    closed = true;
+                }
                 let msg_to_front = BackToFront::Read {
                     message_id: read_message,
-                    result: Ok(buf),
+                    result: result.map(|()| buf),
                 };
                 if back_to_front.send(msg_to_front).await.is_err() {
-                    return;
+                    return None;
                 }
             }
         }
     }
 }
 
+/// Converts an error of the host into an error of the interface.
+fn tcp_error(err: &io::Error) -> ffi::TcpError {
+    match err.kind() {
+        io::ErrorKind::ConnectionRefused => ffi::TcpError::ConnectionRefused,
+        io::ErrorKind::ConnectionReset | io::ErrorKind::ConnectionAborted => {
+            ffi::TcpError::ConnectionReset
+        }
+        io::ErrorKind::TimedOut => ffi::TcpError::TimedOut,
+        io::ErrorKind::HostUnreachable | io::ErrorKind::NetworkUnreachable => {
+            ffi::TcpError::Unreachable
+        }
+        io::ErrorKind::AddrInUse => ffi::TcpError::AddrInUse,
+        io::ErrorKind::PermissionDenied => ffi::TcpError::PermissionDenied,
+        _ => ffi::TcpError::Other(err.raw_os_error()),
+    }
+}
+
+/// Returns the duration between now and `deadline`, or zero if it is in the past.
+fn until(deadline: Instant) -> Duration {
+    deadline.saturating_duration_since(Instant::now())
+}
+
 /// Function executed in the background for each TCP listener.
 async fn listener_task(
     local_socket_addr: SocketAddr,
@@ -547,8 +1287,30 @@ async fn listener_task(
 ) {
     let socket = match TcpListener::bind(&local_socket_addr).await {
         Ok(socket) => socket,
-        Err(_) => return, // TODO: somehow report this
+        Err(err) => {
+            let error = tcp_error(&err);
+            // Refuse all the sockets waiting for a connection on this listener. The channel is
+            // kept open so that the sockets opened later are refused as well.
+            while let Some(FrontToBackListener::NewSocket {
+                owner,
+                socket_id,
+                open_message_id,
+            }) = front_to_back.next().await
+            {
+                let msg_to_front = BackToFront::OpenErr {
+                    owner,
+                    socket_id,
+                    open_message_id,
+                    error: error.clone(),
+                };
+                if back_to_front.send(msg_to_front).await.is_err() {
+                    return;
+                }
+            }
+            return;
+        }
     };
+    let local_addr = socket.local_addr().unwrap_or(local_socket_addr);
 
     let mut pending_sockets = VecDeque::new();
 
@@ -576,20 +1338,24 @@ async fn listener_task(
 
         match what_happened {
             WhatHappened::Cmd(FrontToBackListener::NewSocket {
+                owner,
                 socket_id,
                 open_message_id,
             }) => {
-                pending_sockets.push_back((socket_id, open_message_id));
+                pending_sockets.push_back((owner, socket_id, open_message_id));
             }
             WhatHappened::NewSocket(socket, addr) => {
-                if let Some((socket_id, open_message_id)) = pending_sockets.pop_front() {
+                if let Some((owner, socket_id, open_message_id)) = pending_sockets.pop_front() {
                     let (tx, rx) = mpsc::unbounded();
                     task::spawn(open_socket_task(socket, rx, back_to_front.clone()));
 
                     let msg_to_front = BackToFront::OpenOk {
                         open_message_id,
+                        owner,
                         socket_id,
                         sender: tx,
+                        local_addr,
+                        remote_addr: addr,
                     };
 
                     if back_to_front.send(msg_to_front).await.is_err() {
@@ -600,3 +1366,198 @@ async fn listener_task(
         }
     }
 }
+
+/// Turns an IP address into the format of the interface.
+fn ip_segments(ip: IpAddr) -> [u16; 8] {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped().segments(),
+        IpAddr::V6(ip) => ip.segments(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TcpHandler, MAX_QUEUED_WRITE_LEN};
+    use futures::executor::block_on;
+    use redshirt_core::native::{NativeProgramsCollection, NativeProgramsCollectionEvent};
+    use redshirt_core::{Decode as _, Encode as _, EncodedMessage, Handle, MessageId, Pid};
+    use redshirt_tcp_interface::ffi;
+    use std::{
+        collections::HashMap,
+        io::{Read as _, Write as _},
+        net::{Ipv4Addr, TcpListener, TcpStream},
+    };
+
+    /// Builds a collection containing a [`TcpHandler`], with its interfaces registered.
+    fn collection() -> NativeProgramsCollection<'static> {
+        let mut collection = NativeProgramsCollection::new();
+        collection.push(Pid::from(1), TcpHandler::new());
+
+        // Registration of the TCP and TLS interfaces.
+        for _ in 0..2 {
+            match block_on(collection.next_event()) {
+                NativeProgramsCollectionEvent::Emit { .. } => {}
+                _ => panic!(),
+            }
+        }
+
+        collection
+    }
+
+    /// Waits for the next answer produced by the collection.
+    fn next_answer(collection: &NativeProgramsCollection) -> (MessageId, EncodedMessage) {
+        match block_on(collection.next_event()) {
+            NativeProgramsCollectionEvent::Answer {
+                message_id,
+                answer: Ok(answer),
+            } => (message_id, answer),
+            _ => panic!(),
+        }
+    }
+
+    /// Makes `owner` connect to a listener on the loopback interface. Returns the socket of
+    /// `owner` and the remote side of the connection.
+    fn connect(collection: &mut NativeProgramsCollection, owner: Pid) -> (Handle, TcpStream) {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let open = ffi::TcpMessage::Open(ffi::TcpOpen {
+            listen: false,
+            ip: Ipv4Addr::LOCALHOST.to_ipv6_mapped().segments(),
+            port: listener.local_addr().unwrap().port(),
+            timeout_ms: None,
+        });
+        let message_id = MessageId::from(1);
+        collection.interface_message(ffi::INTERFACE, Some(message_id), owner, open.encode());
+
+        let (remote, _) = listener.accept().unwrap();
+        let (answered, answer) = next_answer(collection);
+        assert_eq!(answered, message_id);
+        let socket_id = ffi::TcpOpenResponse::decode(answer)
+            .unwrap()
+            .result
+            .unwrap()
+            .socket_id;
+        (socket_id, remote)
+    }
+
+    #[test]
+    fn queued_reads_and_writes() {
+        let mut collection = collection();
+        let owner = Pid::from(7);
+        let (socket_id, mut remote) = connect(&mut collection, owner);
+
+        // All the messages are sent at once, before anything has been written or read.
+        for (n, data) in [&b"hello "[..], &b"world"[..]].iter().enumerate() {
+            let write = ffi::TcpMessage::Write(ffi::TcpWrite {
+                socket_id,
+                data: data.to_vec(),
+            });
+            let message_id = MessageId::from(10 + n as u64);
+            collection.interface_message(ffi::INTERFACE, Some(message_id), owner, write.encode());
+        }
+        for n in 0..2 {
+            let read = ffi::TcpMessage::Read(ffi::TcpRead {
+                socket_id,
+                max_len: 1,
+            });
+            let message_id = MessageId::from(20 + n);
+            collection.interface_message(ffi::INTERFACE, Some(message_id), owner, read.encode());
+        }
+
+        let mut written = [0; 11];
+        remote.read_exact(&mut written).unwrap();
+        assert_eq!(&written, b"hello world");
+        remote.write_all(b"ab").unwrap();
+
+        let mut answers = HashMap::new();
+        while answers.len() < 4 {
+            let (message_id, answer) = next_answer(&collection);
+            answers.insert(message_id, answer);
+        }
+
+        for n in 0..2 {
+            let answer = answers.remove(&MessageId::from(10 + n)).unwrap();
+            let response = ffi::TcpWriteResponse::decode(answer).unwrap();
+            assert_eq!(response.result, Ok(()));
+        }
+        for (n, expected) in [b"a", b"b"].iter().enumerate() {
+            let answer = answers.remove(&MessageId::from(20 + n as u64)).unwrap();
+            let response = ffi::TcpReadResponse::decode(answer).unwrap();
+            assert_eq!(response.result, Ok(expected.to_vec()));
+        }
+    }
+
+    #[test]
+    fn write_would_block_on_overflow() {
+        let mut collection = collection();
+        let owner = Pid::from(7);
+        // The remote never reads, so that the data accumulates in the handler.
+        let (socket_id, _remote) = connect(&mut collection, owner);
+
+        // Way more than what the buffers of the host can hold, so that most of it stays queued.
+        let write = ffi::TcpMessage::Write(ffi::TcpWrite {
+            socket_id,
+            data: vec![0; 128 * MAX_QUEUED_WRITE_LEN],
+        });
+        collection.interface_message(
+            ffi::INTERFACE,
+            Some(MessageId::from(10)),
+            owner,
+            write.encode(),
+        );
+
+        let write = ffi::TcpMessage::Write(ffi::TcpWrite {
+            socket_id,
+            data: vec![0; 1],
+        });
+        collection.interface_message(
+            ffi::INTERFACE,
+            Some(MessageId::from(11)),
+            owner,
+            write.encode(),
+        );
+
+        let (answered, answer) = next_answer(&collection);
+        assert_eq!(answered, MessageId::from(11));
+        let response = ffi::TcpWriteResponse::decode(answer).unwrap();
+        assert_eq!(response.result, Err(ffi::TcpError::WouldBlock));
+    }
+
+    #[test]
+    fn set_option_invalid_socket() {
+        let mut collection = collection();
+        let owner = Pid::from(7);
+        let (socket_id, _remote) = connect(&mut collection, owner);
+
+        let set_option = |socket_id| {
+            ffi::TcpMessage::SetOption(ffi::TcpSetOption {
+                socket_id,
+                option: ffi::TcpOption::NoDelay(true),
+            })
+            .encode()
+        };
+
+        // Socket that doesn't exist, socket of another process, then socket of the emitter.
+        let attempts = [
+            (
+                Pid::from(7),
+                Handle::from(0xdead_beef),
+                Err(ffi::TcpError::InvalidSocket),
+            ),
+            (Pid::from(8), socket_id, Err(ffi::TcpError::InvalidSocket)),
+            (owner, socket_id, Ok(())),
+        ];
+        for (n, (emitter, socket_id, expected)) in attempts.iter().enumerate() {
+            let message_id = MessageId::from(10 + n as u64);
+            collection.interface_message(
+                ffi::INTERFACE,
+                Some(message_id),
+                *emitter,
+                set_option(*socket_id),
+            );
+            let (answered, answer) = next_answer(&collection);
+            assert_eq!(answered, message_id);
+            let response = ffi::TcpSetOptionResponse::decode(answer).unwrap();
+            assert_eq!(&response.result, expected);
+        }
+    }
+}
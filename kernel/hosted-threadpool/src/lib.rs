@@ -0,0 +1,401 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Runs a native program on a thread pool rather than on the kernel's main loop.
+//!
+//! Native programs are normally polled by the same task that routes messages between programs.
+//! As a consequence, a native program that takes a long time to process an event stalls the
+//! entire system.
+//!
+//! The [`ThreadPoolNativeProgram`] struct wraps around a native program and moves its execution
+//! to a [`ThreadPool`]. The kernel then only communicates with it through queues. Multiple
+//! native programs can share the same [`ThreadPool`], or each of them can be given its own.
+//!
+//! The number of interface messages that haven't been processed yet by the wrapped program is
+//! bounded. If this limit is reached, further messages are immediately answered with an error
+//! rather than blocking the routing of messages.
+
+use fnv::FnvHashMap;
+use futures::{channel::mpsc, executor::ThreadPool, future::BoxFuture, lock::Mutex, prelude::*};
+use redshirt_core::native::{
+    NativeProgramEvent, NativeProgramMessageIdWrite, NativeProgramRef, ResourceHandle,
+};
+use redshirt_core::{EncodedMessage, InterfaceHash, MessageId, Pid};
+use std::{
+    pin::Pin,
+    sync::{atomic, Arc},
+};
+
+/// Native program whose execution is delegated to a [`ThreadPool`].
+pub struct ThreadPoolNativeProgram {
+    /// Sending side of the queue of notifications destined to the wrapped program.
+    to_worker: mpsc::UnboundedSender<ToWorker>,
+    /// Number of interface messages in [`ThreadPoolNativeProgram::to_worker`] that haven't been
+    /// processed by the wrapped program yet.
+    pending_messages: Arc<atomic::AtomicUsize>,
+    /// Maximum value of [`ThreadPoolNativeProgram::pending_messages`].
+    max_pending_messages: usize,
+    /// Sending side of [`Receivers::refused`].
+    refused_tx: mpsc::UnboundedSender<MessageId>,
+    /// Accessed only by `next_event`.
+    receivers: Mutex<Receivers>,
+    /// The thread pool that runs the wrapped program. Kept alive for as long as this struct is
+    /// alive.
+    _pool: ThreadPool,
+}
+
+/// Separate struct behind a mutex.
+struct Receivers {
+    /// Events generated by the wrapped program.
+    events: mpsc::Receiver<NativeProgramEvent<u64>>,
+    /// Messages that have been refused because the queue was full, and that we must answer.
+    refused: mpsc::UnboundedReceiver<MessageId>,
+}
+
+/// Message sent from the kernel to the task running the wrapped program.
+enum ToWorker {
+    InterfaceMessage {
+        interface: InterfaceHash,
+        message_id: Option<MessageId>,
+        emitter_pid: Pid,
+        message: EncodedMessage,
+    },
+    ProcessDestroyed(Pid),
+    ResourcesReleased(Pid, Vec<ResourceHandle>),
+    MessageResponse {
+        message_id: MessageId,
+        response: Result<EncodedMessage, ()>,
+    },
+    /// The [`MessageId`] of the message whose `message_id_write` has been assigned the given
+    /// key is now known.
+    Acknowledge {
+        key: u64,
+        message_id: MessageId,
+    },
+}
+
+impl ThreadPoolNativeProgram {
+    /// Starts running `program` on the given thread pool.
+    ///
+    /// `queue_size` is the maximum number of interface messages that can be waiting to be
+    /// processed by `program`. Messages that arrive while the queue is full are answered with
+    /// an error.
+    ///
+    /// # Panic
+    ///
+    /// Panics if `queue_size` is 0.
+    ///
+    pub fn new<T>(program: T, pool: &ThreadPool, queue_size: usize) -> Self
+    where
+        T: Send + Sync + 'static,
+        for<'r> &'r T: NativeProgramRef<'r>,
+    {
+        assert_ne!(queue_size, 0);
+
+        let (to_worker, to_worker_rx) = mpsc::unbounded();
+        let (events_tx, events) = mpsc::channel(queue_size);
+        let (refused_tx, refused) = mpsc::unbounded();
+        let pending_messages = Arc::new(atomic::AtomicUsize::new(0));
+
+        pool.spawn_ok(run_program(
+            program,
+            to_worker_rx,
+            events_tx,
+            pending_messages.clone(),
+        ));
+
+        ThreadPoolNativeProgram {
+            to_worker,
+            pending_messages,
+            max_pending_messages: queue_size,
+            refused_tx,
+            receivers: Mutex::new(Receivers { events, refused }),
+            _pool: pool.clone(),
+        }
+    }
+
+    /// Starts running `program` on a thread dedicated to it.
+    ///
+    /// See [`ThreadPoolNativeProgram::new`] for the meaning of `queue_size`.
+    pub fn with_dedicated_thread<T>(program: T, queue_size: usize) -> Self
+    where
+        T: Send + Sync + 'static,
+        for<'r> &'r T: NativeProgramRef<'r>,
+    {
+        let pool = ThreadPool::builder()
+            .pool_size(1)
+            .create()
+            .expect("Failed to spawn native program thread");
+        ThreadPoolNativeProgram::new(program, &pool, queue_size)
+    }
+}
+
+impl<'a> NativeProgramRef<'a> for &'a ThreadPoolNativeProgram {
+    type Future =
+        Pin<Box<dyn Future<Output = NativeProgramEvent<Self::MessageIdWrite>> + Send + 'a>>;
+    type MessageIdWrite = ThreadPoolMessageIdWrite;
+
+    fn next_event(self) -> Self::Future {
+        Box::pin(async move {
+            let mut receivers = self.receivers.lock().await;
+            let receivers = &mut *receivers;
+
+            match future::select(receivers.refused.next(), receivers.events.next()).await {
+                future::Either::Left((Some(message_id), _)) => NativeProgramEvent::Answer {
+                    message_id,
+                    answer: Err(()),
+                },
+                future::Either::Right((Some(event), _)) => match event {
+                    NativeProgramEvent::Emit {
+                        interface,
+                        message_id_write,
+                        message,
+                    } => NativeProgramEvent::Emit {
+                        interface,
+                        message_id_write: message_id_write.map(|key| ThreadPoolMessageIdWrite {
+                            key,
+                            to_worker: self.to_worker.clone(),
+                        }),
+                        message,
+                    },
+                    NativeProgramEvent::CancelMessage { message_id } => {
+                        NativeProgramEvent::CancelMessage { message_id }
+                    }
+                    NativeProgramEvent::Answer { message_id, answer } => {
+                        NativeProgramEvent::Answer { message_id, answer }
+                    }
+                    NativeProgramEvent::AcquireResource { owner, resource } => {
+                        NativeProgramEvent::AcquireResource { owner, resource }
+                    }
+                    NativeProgramEvent::ReleaseResource { owner, resource } => {
+                        NativeProgramEvent::ReleaseResource { owner, resource }
+                    }
+                },
+                // `refused_tx` is never closed.
+                future::Either::Left((None, _)) => unreachable!(),
+                // The wrapped program has panicked. Since we have no way to report this, we
+                // simply never produce any event anymore.
+                future::Either::Right((None, _)) => future::pending().await,
+            }
+        })
+    }
+
+    fn interface_message(
+        self,
+        interface: InterfaceHash,
+        message_id: Option<MessageId>,
+        emitter_pid: Pid,
+        message: EncodedMessage,
+    ) {
+        let pending = self.pending_messages.fetch_add(1, atomic::Ordering::SeqCst);
+        if pending >= self.max_pending_messages {
+            self.pending_messages.fetch_sub(1, atomic::Ordering::SeqCst);
+            // Messages that don't expect an answer are silently dropped.
+            if let Some(message_id) = message_id {
+                self.refused_tx.unbounded_send(message_id).unwrap();
+            }
+            return;
+        }
+
+        let _ = self.to_worker.unbounded_send(ToWorker::InterfaceMessage {
+            interface,
+            message_id,
+            emitter_pid,
+            message,
+        });
+    }
+
+    fn process_destroyed(self, pid: Pid) {
+        let _ = self
+            .to_worker
+            .unbounded_send(ToWorker::ProcessDestroyed(pid));
+    }
+
+    fn resources_released(self, pid: Pid, resources: Vec<ResourceHandle>) {
+        let _ = self
+            .to_worker
+            .unbounded_send(ToWorker::ResourcesReleased(pid, resources));
+    }
+
+    fn message_response(self, message_id: MessageId, response: Result<EncodedMessage, ()>) {
+        let _ = self.to_worker.unbounded_send(ToWorker::MessageResponse {
+            message_id,
+            response,
+        });
+    }
+}
+
+/// Implementation of [`NativeProgramMessageIdWrite`] for [`ThreadPoolNativeProgram`].
+pub struct ThreadPoolMessageIdWrite {
+    /// Key that the worker task uses to find back the wrapped program's `MessageIdWrite`.
+    key: u64,
+    to_worker: mpsc::UnboundedSender<ToWorker>,
+}
+
+impl NativeProgramMessageIdWrite for ThreadPoolMessageIdWrite {
+    fn acknowledge(self, message_id: MessageId) {
+        let _ = self.to_worker.unbounded_send(ToWorker::Acknowledge {
+            key: self.key,
+            message_id,
+        });
+    }
+}
+
+/// Task that runs on the thread pool and drives the wrapped program.
+///
+/// Finishes when the [`ThreadPoolNativeProgram`] is destroyed.
+async fn run_program<T>(
+    program: T,
+    mut to_worker: mpsc::UnboundedReceiver<ToWorker>,
+    mut events_tx: mpsc::Sender<NativeProgramEvent<u64>>,
+    pending_messages: Arc<atomic::AtomicUsize>,
+) where
+    for<'r> &'r T: NativeProgramRef<'r>,
+{
+    let program = &program;
+
+    // `MessageIdWrite`s of the emitted messages, waiting for the kernel to assign an ID.
+    let mut message_id_writes =
+        FnvHashMap::<u64, Box<dyn AbstractMessageIdWrite + Send>>::default();
+    let mut next_key: u64 = 0;
+
+    loop {
+        let event = {
+            match future::select(next_event(program), to_worker.next()).await {
+                future::Either::Left((event, _)) => event,
+                future::Either::Right((Some(message), _)) => {
+                    match message {
+                        ToWorker::InterfaceMessage {
+                            interface,
+                            message_id,
+                            emitter_pid,
+                            message,
+                        } => {
+                            pending_messages.fetch_sub(1, atomic::Ordering::SeqCst);
+                            program.interface_message(interface, message_id, emitter_pid, message);
+                        }
+                        ToWorker::ProcessDestroyed(pid) => program.process_destroyed(pid),
+                        ToWorker::ResourcesReleased(pid, resources) => {
+                            program.resources_released(pid, resources)
+                        }
+                        ToWorker::MessageResponse {
+                            message_id,
+                            response,
+                        } => program.message_response(message_id, response),
+                        ToWorker::Acknowledge { key, message_id } => {
+                            match message_id_writes.remove(&key) {
+                                Some(mut write) => write.acknowledge(message_id),
+                                None => unreachable!(),
+                            }
+                        }
+                    }
+                    continue;
+                }
+                future::Either::Right((None, _)) => return,
+            }
+        };
+
+        let event = match event {
+            NativeProgramEvent::Emit {
+                interface,
+                message_id_write,
+                message,
+            } => NativeProgramEvent::Emit {
+                interface,
+                message_id_write: message_id_write.map(|write| {
+                    let key = next_key;
+                    next_key = next_key.wrapping_add(1);
+                    message_id_writes.insert(key, write);
+                    key
+                }),
+                message,
+            },
+            NativeProgramEvent::CancelMessage { message_id } => {
+                NativeProgramEvent::CancelMessage { message_id }
+            }
+            NativeProgramEvent::Answer { message_id, answer } => {
+                NativeProgramEvent::Answer { message_id, answer }
+            }
+            NativeProgramEvent::AcquireResource { owner, resource } => {
+                NativeProgramEvent::AcquireResource { owner, resource }
+            }
+            NativeProgramEvent::ReleaseResource { owner, resource } => {
+                NativeProgramEvent::ReleaseResource { owner, resource }
+            }
+        };
+
+        if events_tx.send(event).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Returns the next event generated by `program`, with its `MessageIdWrite` boxed.
+///
+/// > **Note**: This is a separate function, rather than being inlined in `run_program`, because
+/// >           the compiler can't otherwise prove that the future returned by `run_program`
+/// >           implements `Send`.
+fn next_event<'a, T>(
+    program: &'a T,
+) -> BoxFuture<'a, NativeProgramEvent<Box<dyn AbstractMessageIdWrite + Send + 'a>>>
+where
+    &'a T: NativeProgramRef<'a>,
+{
+    Box::pin(program.next_event().map(|event| match event {
+        NativeProgramEvent::Emit {
+            interface,
+            message_id_write,
+            message,
+        } => {
+            NativeProgramEvent::Emit {
+                interface,
+                message_id_write:
+                    message_id_write.map(|write| {
+                        Box::new(Some(write)) as Box<dyn AbstractMessageIdWrite + Send>
+                    }),
+                message,
+            }
+        }
+        NativeProgramEvent::CancelMessage { message_id } => {
+            NativeProgramEvent::CancelMessage { message_id }
+        }
+        NativeProgramEvent::Answer { message_id, answer } => {
+            NativeProgramEvent::Answer { message_id, answer }
+        }
+        NativeProgramEvent::AcquireResource { owner, resource } => {
+            NativeProgramEvent::AcquireResource { owner, resource }
+        }
+        NativeProgramEvent::ReleaseResource { owner, resource } => {
+            NativeProgramEvent::ReleaseResource { owner, resource }
+        }
+    }))
+}
+
+/// Abstracts over the `MessageIdWrite` of the wrapped program so that we can box it.
+trait AbstractMessageIdWrite {
+    fn acknowledge(&mut self, message_id: MessageId);
+}
+
+impl<T> AbstractMessageIdWrite for Option<T>
+where
+    T: NativeProgramMessageIdWrite,
+{
+    fn acknowledge(&mut self, message_id: MessageId) {
+        match self.take() {
+            Some(inner) => inner.acknowledge(message_id),
+            None => unreachable!(),
+        }
+    }
+}
@@ -211,7 +211,8 @@ impl<'a> NativeProgramRef<'a> for &'a TimerHandler {
     }
 }
 
-fn monotonic_clock() -> u128 {
+/// Returns the value of the monotonic clock reported to programs, in nanoseconds.
+pub fn monotonic_clock() -> u128 {
     lazy_static::lazy_static! {
         static ref CLOCK_START: Instant = Instant::now();
     }
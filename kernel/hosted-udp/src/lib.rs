@@ -0,0 +1,397 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Implements the UDP interface.
+
+use async_std::{net::UdpSocket, sync::Mutex, task};
+use futures::{channel::mpsc, prelude::*};
+use redshirt_core::handles::HandleTable;
+use redshirt_core::native::{
+    DummyMessageIdWrite, NativeProgramEvent, NativeProgramRef, ResourceHandle, ResourceKind,
+};
+use redshirt_core::{
+    Decode as _, Encode as _, EncodedMessage, Handle, InterfaceHash, MessageId, Pid,
+};
+use redshirt_udp_interface::ffi;
+use std::{
+    fmt,
+    net::{IpAddr, Ipv6Addr, SocketAddr},
+    pin::Pin,
+    sync::{atomic, Arc},
+};
+
+/// Maximum size of a UDP datagram.
+const MAX_DATAGRAM_LEN: usize = 65536;
+
+/// Native process for UDP sockets that use the host operating system.
+pub struct UdpHandler {
+    /// If true, we have sent the interface registration message.
+    registered: atomic::AtomicBool,
+
+    /// List of open sockets. Each socket belongs to the process that has opened it, and only
+    /// this process can use it.
+    sockets: Arc<parking_lot::Mutex<HandleTable<Arc<UdpSocket>>>>,
+
+    /// Receives the events to emit, from the background tasks. Answers, and the notifications
+    /// about the sockets that are opened and closed.
+    receiver: Mutex<mpsc::UnboundedReceiver<NativeProgramEvent<DummyMessageIdWrite>>>,
+
+    /// Sending side of `receiver`. Meant to be cloned and sent to background tasks.
+    sender: mpsc::UnboundedSender<NativeProgramEvent<DummyMessageIdWrite>>,
+}
+
+impl UdpHandler {
+    /// Initializes a new empty [`UdpHandler`].
+    pub fn new() -> Self {
+        let (sender, receiver) = mpsc::unbounded();
+
+        UdpHandler {
+            registered: atomic::AtomicBool::new(false),
+            sockets: Arc::new(parking_lot::Mutex::new(HandleTable::new())),
+            receiver: Mutex::new(receiver),
+            sender,
+        }
+    }
+
+    /// Returns the socket with the given id, if it has been opened by `emitter_pid`.
+    fn socket(&self, socket_id: Handle, emitter_pid: Pid) -> Option<Arc<UdpSocket>> {
+        self.sockets
+            .lock()
+            .get(emitter_pid, socket_id, ResourceKind::Socket)
+            .ok()
+            .cloned()
+    }
+}
+
+impl<'a> NativeProgramRef<'a> for &'a UdpHandler {
+    type Future =
+        Pin<Box<dyn Future<Output = NativeProgramEvent<Self::MessageIdWrite>> + Send + 'a>>;
+    type MessageIdWrite = DummyMessageIdWrite;
+
+    fn next_event(self) -> Self::Future {
+        Box::pin(async move {
+            if !self.registered.swap(true, atomic::Ordering::Relaxed) {
+                return NativeProgramEvent::Emit {
+                    interface: redshirt_interface_interface::ffi::INTERFACE,
+                    message_id_write: None,
+                    message: redshirt_interface_interface::ffi::InterfaceMessage::Register(
+                        ffi::INTERFACE,
+                    )
+                    .encode(),
+                };
+            }
+
+            let mut receiver = self.receiver.lock().await;
+            receiver.next().await.unwrap()
+        })
+    }
+
+    fn interface_message(
+        self,
+        interface: InterfaceHash,
+        message_id: Option<MessageId>,
+        emitter_pid: Pid,
+        message: EncodedMessage,
+    ) {
+        debug_assert_eq!(interface, ffi::INTERFACE);
+
+        let message = match ffi::UdpMessage::decode(message) {
+            Ok(msg) => msg,
+            Err(_) => return, // TODO: produce error
+        };
+
+        // Closing is the only message that doesn't expect an answer.
+        if let ffi::UdpMessage::Close(close) = message {
+            let mut sockets = self.sockets.lock();
+            if sockets
+                .remove(emitter_pid, close.socket_id, ResourceKind::Socket)
+                .is_ok()
+            {
+                let _ = self
+                    .sender
+                    .unbounded_send(NativeProgramEvent::ReleaseResource {
+                        owner: emitter_pid,
+                        resource: socket_resource(close.socket_id),
+                    });
+            }
+            return;
+        }
+
+        let message_id = match message_id {
+            Some(m) => m,
+            None => return,
+        };
+
+        let sender = self.sender.clone();
+        let answer = move |answer: EncodedMessage| {
+            let _ = sender.unbounded_send(NativeProgramEvent::Answer {
+                message_id,
+                answer: Ok(answer),
+            });
+        };
+
+        match message {
+            ffi::UdpMessage::Close(_) => unreachable!(),
+
+            ffi::UdpMessage::Bind(bind) => {
+                let socket_addr = socket_addr_from(bind.ip, bind.port);
+                let sockets = self.sockets.clone();
+                let events = self.sender.clone();
+                task::spawn(async move {
+                    let result = match UdpSocket::bind(socket_addr).await {
+                        Ok(socket) => {
+                            let local_addr = socket.local_addr().unwrap_or(socket_addr);
+                            let socket_id = sockets.lock().insert(
+                                emitter_pid,
+                                ResourceKind::Socket,
+                                Arc::new(socket),
+                            );
+                            // Reported before the answer, so that the socket is released if the
+                            // process dies before closing it.
+                            let _ = events.unbounded_send(NativeProgramEvent::AcquireResource {
+                                owner: emitter_pid,
+                                resource: socket_resource(socket_id),
+                            });
+
+                            Ok(ffi::UdpSocketOpen {
+                                socket_id,
+                                local_ip: ip_segments(local_addr.ip()),
+                                local_port: local_addr.port(),
+                            })
+                        }
+                        Err(_) => Err(()),
+                    };
+                    answer(ffi::UdpBindResponse { result }.encode());
+                });
+            }
+
+            ffi::UdpMessage::SendTo(send_to) => {
+                let socket = match self.socket(send_to.socket_id, emitter_pid) {
+                    Some(s) => s,
+                    None => return answer(ffi::UdpSendToResponse { result: Err(()) }.encode()),
+                };
+
+                let target = socket_addr_from(send_to.ip, send_to.port);
+                task::spawn(async move {
+                    let result = match socket.send_to(&send_to.data, target).await {
+                        Ok(n) if n == send_to.data.len() => Ok(()),
+                        _ => Err(()),
+                    };
+                    answer(ffi::UdpSendToResponse { result }.encode());
+                });
+            }
+
+            ffi::UdpMessage::RecvFrom(recv_from) => {
+                let socket = match self.socket(recv_from.socket_id, emitter_pid) {
+                    Some(s) => s,
+                    None => return answer(ffi::UdpRecvFromResponse { result: Err(()) }.encode()),
+                };
+
+                // TODO: the task keeps running if the socket is closed or the process destroyed
+                task::spawn(async move {
+                    let mut buffer = vec![0; MAX_DATAGRAM_LEN];
+                    let result = match socket.recv_from(&mut buffer).await {
+                        Ok((num_read, from)) => {
+                            buffer.truncate(num_read);
+                            Ok(ffi::UdpDatagram {
+                                ip: ip_segments(from.ip()),
+                                port: from.port(),
+                                data: buffer,
+                            })
+                        }
+                        Err(_) => Err(()),
+                    };
+                    answer(ffi::UdpRecvFromResponse { result }.encode());
+                });
+            }
+
+            ffi::UdpMessage::SetBroadcast(set_broadcast) => {
+                let result = match self.socket(set_broadcast.socket_id, emitter_pid) {
+                    Some(socket) => socket
+                        .set_broadcast(set_broadcast.broadcast)
+                        .map_err(|_| ()),
+                    None => Err(()),
+                };
+                answer(ffi::UdpSetBroadcastResponse { result }.encode());
+            }
+        }
+    }
+
+    fn process_destroyed(self, _: Pid) {
+        // The sockets of the process are closed in `resources_released`.
+    }
+
+    fn resources_released(self, pid: Pid, resources: Vec<ResourceHandle>) {
+        let mut sockets = self.sockets.lock();
+        for resource in resources {
+            let _ = sockets.remove(pid, Handle::from(resource.id), resource.kind);
+        }
+    }
+
+    fn message_response(self, _: MessageId, _: Result<EncodedMessage, ()>) {
+        unreachable!()
+    }
+}
+
+impl Default for UdpHandler {
+    fn default() -> Self {
+        UdpHandler::new()
+    }
+}
+
+impl fmt::Debug for UdpHandler {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("UdpHandler").finish()
+    }
+}
+
+/// Builds the [`ResourceHandle`] reported to the kernel for the given socket.
+fn socket_resource(socket_id: Handle) -> ResourceHandle {
+    ResourceHandle {
+        kind: ResourceKind::Socket,
+        id: u64::from(socket_id),
+    }
+}
+
+/// Turns an IP address into the format of the interface.
+fn ip_segments(ip: IpAddr) -> [u16; 8] {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped().segments(),
+        IpAddr::V6(ip) => ip.segments(),
+    }
+}
+
+/// Turns an address in the format of the interface into a socket address.
+fn socket_addr_from(ip: [u16; 8], port: u16) -> SocketAddr {
+    let ip = Ipv6Addr::from(ip);
+    if let Some(ip) = ip.to_ipv4() {
+        SocketAddr::new(ip.into(), port)
+    } else {
+        SocketAddr::new(ip.into(), port)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::UdpHandler;
+    use futures::executor::block_on;
+    use redshirt_core::native::{NativeProgramsCollection, NativeProgramsCollectionEvent};
+    use redshirt_core::{Decode as _, Encode as _, Handle, MessageId, Pid};
+    use redshirt_udp_interface::ffi;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn socket_closed_when_owner_destroyed() {
+        let handler = UdpHandler::new();
+        let sockets = handler.sockets.clone();
+        let mut collection = NativeProgramsCollection::new();
+        collection.push(Pid::from(1), handler);
+
+        // Registration of the interface.
+        match block_on(collection.next_event()) {
+            NativeProgramsCollectionEvent::Emit { .. } => {}
+            _ => panic!(),
+        }
+
+        let owner = Pid::from(7);
+        let message_id = MessageId::from(1234);
+        let bind = ffi::UdpMessage::Bind(ffi::UdpBind {
+            ip: Ipv4Addr::LOCALHOST.to_ipv6_mapped().segments(),
+            port: 0,
+        });
+        collection.interface_message(ffi::INTERFACE, Some(message_id), owner, bind.encode());
+
+        match block_on(collection.next_event()) {
+            NativeProgramsCollectionEvent::Answer {
+                message_id: answered,
+                answer: Ok(answer),
+            } => {
+                assert_eq!(answered, message_id);
+                assert!(ffi::UdpBindResponse::decode(answer).unwrap().result.is_ok());
+            }
+            _ => panic!(),
+        }
+        assert_eq!(sockets.lock().num_owned(owner), 1);
+
+        // The owner is killed without closing its socket.
+        collection.process_destroyed(owner);
+        assert!(sockets.lock().is_empty());
+    }
+
+    #[test]
+    fn set_broadcast_invalid_socket() {
+        let mut collection = NativeProgramsCollection::new();
+        collection.push(Pid::from(1), UdpHandler::new());
+        match block_on(collection.next_event()) {
+            NativeProgramsCollectionEvent::Emit { .. } => {}
+            _ => panic!(),
+        }
+
+        let owner = Pid::from(7);
+        let bind = ffi::UdpMessage::Bind(ffi::UdpBind {
+            ip: Ipv4Addr::LOCALHOST.to_ipv6_mapped().segments(),
+            port: 0,
+        });
+        collection.interface_message(
+            ffi::INTERFACE,
+            Some(MessageId::from(1)),
+            owner,
+            bind.encode(),
+        );
+        let socket_id = match block_on(collection.next_event()) {
+            NativeProgramsCollectionEvent::Answer {
+                answer: Ok(answer), ..
+            } => {
+                ffi::UdpBindResponse::decode(answer)
+                    .unwrap()
+                    .result
+                    .unwrap()
+                    .socket_id
+            }
+            _ => panic!(),
+        };
+
+        // Socket that doesn't exist, socket of another process, then socket of the emitter.
+        let attempts = [
+            (owner, Handle::from(0xdead_beef), Err(())),
+            (Pid::from(8), socket_id, Err(())),
+            (owner, socket_id, Ok(())),
+        ];
+        for (n, (emitter, socket_id, expected)) in attempts.iter().enumerate() {
+            let message_id = MessageId::from(10 + n as u64);
+            let set_broadcast = ffi::UdpMessage::SetBroadcast(ffi::UdpSetBroadcast {
+                socket_id: *socket_id,
+                broadcast: true,
+            });
+            collection.interface_message(
+                ffi::INTERFACE,
+                Some(message_id),
+                *emitter,
+                set_broadcast.encode(),
+            );
+            match block_on(collection.next_event()) {
+                NativeProgramsCollectionEvent::Answer {
+                    message_id: answered,
+                    answer: Ok(answer),
+                } => {
+                    assert_eq!(answered, message_id);
+                    let response = ffi::UdpSetBroadcastResponse::decode(answer).unwrap();
+                    assert_eq!(&response.result, expected);
+                }
+                _ => panic!(),
+            }
+        }
+    }
+}
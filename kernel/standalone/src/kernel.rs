@@ -25,6 +25,7 @@
 use crate::arch::PlatformSpecific;
 
 use alloc::sync::Arc;
+use core::convert::TryFrom;
 use core::pin::Pin;
 use core::sync::atomic::{AtomicBool, Ordering};
 use redshirt_core::build_wasm_module;
@@ -58,7 +59,12 @@ where
             }
         }
 
+        let platform_specific = self.platform_specific.clone();
         let mut system_builder = redshirt_core::system::SystemBuilder::new()
+            .with_monotonic_clock(move || {
+                let now = platform_specific.as_ref().monotonic_clock();
+                u64::try_from(now).unwrap_or(u64::max_value())
+            })
             .with_native_program(crate::hardware::HardwareHandler::new(
                 self.platform_specific.clone(),
             ))
@@ -76,6 +82,9 @@ where
                 "passive-node"
             ))
             .with_startup_process(build_wasm_module!("../../../modules/log-to-kernel"))
+            .with_startup_process(build_wasm_module!("../../../modules/network-manager"))
+            .with_startup_process(build_wasm_module!("../../../modules/http-client"))
+            .with_startup_process(build_wasm_module!("../../../modules/websocket-client"))
             .with_startup_process(build_wasm_module!("../../../modules/hello-world"));
 
         // TODO: use a better system than cfgs
@@ -96,6 +105,7 @@ where
         loop {
             match system.run().await {
                 redshirt_core::system::SystemRunOutcome::ProgramFinished { .. } => {}
+                redshirt_core::system::SystemRunOutcome::ProgramLoadFailed { .. } => {}
                 _ => panic!(),
             }
         }
@@ -0,0 +1,382 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Virtual implementation of the `time` and `system-time` interfaces.
+//!
+//! A [`VirtualClock`] only advances when asked to, either manually with
+//! [`VirtualClock::advance`], or automatically by the [`TestHarness`](crate::TestHarness)
+//! whenever the tested program is blocked waiting for a timer. Programs that sleep or use
+//! timeouts therefore run instantly and deterministically.
+//!
+//! Only the processes selected with [`VirtualClock::drive`] see the virtual time. The other
+//! processes are given the time of the host, and their timers fire in real time.
+
+use futures::{channel::mpsc, lock::Mutex as AsyncMutex, prelude::*};
+use redshirt_core::native::{DummyMessageIdWrite, NativeProgramEvent, NativeProgramRef};
+use redshirt_core::{Decode as _, Encode as _, EncodedMessage, InterfaceHash, MessageId, Pid};
+use redshirt_system_time_interface::ffi as system_time_ffi;
+use redshirt_time_interface::ffi as time_ffi;
+use std::{
+    collections::{BTreeMap, HashSet},
+    convert::TryFrom as _,
+    pin::Pin,
+    sync::{atomic, Arc, Mutex},
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+/// Clock whose time only advances when asked to.
+///
+/// Cloning a [`VirtualClock`] returns a handle to the same clock.
+#[derive(Clone)]
+pub struct VirtualClock {
+    inner: Arc<Mutex<ClockInner>>,
+}
+
+struct ClockInner {
+    /// Current value of the monotonic clock, in nanoseconds.
+    monotonic: u128,
+    /// Value of the system clock, in nanoseconds since the UNIX epoch, when `monotonic` is 0.
+    system_offset: u128,
+    /// Messages to answer when the monotonic clock reaches the key.
+    timers: BTreeMap<u128, Vec<MessageId>>,
+    /// Processes that use this clock. The others use the time of the host.
+    processes: HashSet<Pid>,
+    /// Sending side of the channel of the [`ClockProgram`], if any.
+    answers_tx: Option<mpsc::UnboundedSender<(MessageId, EncodedMessage)>>,
+}
+
+impl VirtualClock {
+    /// Builds a new clock. Both the monotonic clock and the system clock start at 0.
+    pub fn new() -> Self {
+        VirtualClock {
+            inner: Arc::new(Mutex::new(ClockInner {
+                monotonic: 0,
+                system_offset: 0,
+                timers: BTreeMap::new(),
+                processes: HashSet::new(),
+                answers_tx: None,
+            })),
+        }
+    }
+
+    /// Makes the given process use this clock, instead of the time of the host.
+    ///
+    /// Must be called before the process asks for the time for the first time, as otherwise it
+    /// might see the time go backwards.
+    pub fn drive(&self, pid: Pid) {
+        self.inner.lock().unwrap().processes.insert(pid);
+    }
+
+    /// Returns the current value of the monotonic clock.
+    pub fn now(&self) -> Duration {
+        u128_to_duration(self.inner.lock().unwrap().monotonic)
+    }
+
+    /// Sets the current value of the system clock, as a duration since the UNIX epoch.
+    pub fn set_system_time(&self, since_epoch: Duration) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.system_offset = since_epoch.as_nanos().saturating_sub(inner.monotonic);
+    }
+
+    /// Advances the clocks by the given duration, and fires the timers that have expired.
+    pub fn advance(&self, duration: Duration) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.monotonic = inner.monotonic.saturating_add(duration.as_nanos());
+        inner.fire_expired();
+    }
+
+    /// If any timer is pending, advances the clocks to the earliest one and fires it. Returns
+    /// `false` if no timer is pending.
+    pub fn advance_to_next_timer(&self) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        let next = match inner.timers.keys().next() {
+            Some(n) => *n,
+            None => return false,
+        };
+        inner.monotonic = next;
+        inner.fire_expired();
+        true
+    }
+
+    /// Returns the number of timers that are waiting for the clock to advance.
+    pub fn pending_timers(&self) -> usize {
+        self.inner
+            .lock()
+            .unwrap()
+            .timers
+            .values()
+            .map(|t| t.len())
+            .sum()
+    }
+
+    /// Builds the native program implementing the interfaces.
+    ///
+    /// Only the last program built for a given clock receives the expired timers.
+    pub(crate) fn program(&self) -> ClockProgram {
+        let (answers_tx, answers_rx) = mpsc::unbounded();
+        self.inner.lock().unwrap().answers_tx = Some(answers_tx);
+        ClockProgram {
+            clock: self.clone(),
+            host_start: Instant::now(),
+            registered: atomic::AtomicUsize::new(0),
+            answers_rx: AsyncMutex::new(answers_rx),
+        }
+    }
+}
+
+impl Default for VirtualClock {
+    fn default() -> Self {
+        VirtualClock::new()
+    }
+}
+
+impl ClockInner {
+    /// Answers the messages whose timer has expired.
+    fn fire_expired(&mut self) {
+        let not_expired = self.timers.split_off(&self.monotonic.saturating_add(1));
+        let expired = std::mem::replace(&mut self.timers, not_expired);
+        for message_id in expired.into_iter().flat_map(|(_, ids)| ids) {
+            self.answer(message_id, ().encode());
+        }
+    }
+
+    fn answer(&self, message_id: MessageId, answer: EncodedMessage) {
+        if let Some(answers_tx) = &self.answers_tx {
+            let _ = answers_tx.unbounded_send((message_id, answer));
+        }
+    }
+}
+
+/// Native program that implements the `time` and `system-time` interfaces using a
+/// [`VirtualClock`].
+pub(crate) struct ClockProgram {
+    clock: VirtualClock,
+    /// Instant when the monotonic clock of the processes that use the time of the host was 0.
+    host_start: Instant,
+    /// Number of interface registration messages that have been emitted.
+    registered: atomic::AtomicUsize,
+    /// Receives the answers to send back. Accessed only by `next_event`.
+    answers_rx: AsyncMutex<mpsc::UnboundedReceiver<(MessageId, EncodedMessage)>>,
+}
+
+impl<'a> NativeProgramRef<'a> for &'a ClockProgram {
+    type Future =
+        Pin<Box<dyn Future<Output = NativeProgramEvent<Self::MessageIdWrite>> + Send + 'a>>;
+    type MessageIdWrite = DummyMessageIdWrite;
+
+    fn next_event(self) -> Self::Future {
+        Box::pin(async move {
+            let interfaces = [time_ffi::INTERFACE, system_time_ffi::INTERFACE];
+            let index = self.registered.fetch_add(1, atomic::Ordering::Relaxed);
+            if let Some(interface) = interfaces.get(index) {
+                return NativeProgramEvent::Emit {
+                    interface: redshirt_interface_interface::ffi::INTERFACE,
+                    message_id_write: None,
+                    message: redshirt_interface_interface::ffi::InterfaceMessage::Register(
+                        interface.clone(),
+                    )
+                    .encode(),
+                };
+            }
+            self.registered
+                .store(interfaces.len(), atomic::Ordering::Relaxed);
+
+            let mut answers_rx = self.answers_rx.lock().await;
+            match answers_rx.next().await {
+                Some((message_id, answer)) => NativeProgramEvent::Answer {
+                    message_id,
+                    answer: Ok(answer),
+                },
+                // A more recent program has been built for the same clock.
+                None => future::pending().await,
+            }
+        })
+    }
+
+    fn interface_message(
+        self,
+        interface: InterfaceHash,
+        message_id: Option<MessageId>,
+        emitter_pid: Pid,
+        message: EncodedMessage,
+    ) {
+        let message_id = match message_id {
+            Some(m) => m,
+            None => return,
+        };
+
+        let mut inner = self.clock.inner.lock().unwrap();
+        if !inner.processes.contains(&emitter_pid) {
+            self.host_time_message(&inner, interface, message_id, message);
+            return;
+        }
+
+        if interface == time_ffi::INTERFACE {
+            match time_ffi::TimeMessage::decode(message) {
+                Ok(time_ffi::TimeMessage::GetMonotonic) => {
+                    let now = inner.monotonic;
+                    inner.answer(message_id, now.encode());
+                }
+                Ok(time_ffi::TimeMessage::WaitMonotonic(until)) => {
+                    inner.timers.entry(until).or_default().push(message_id);
+                    inner.fire_expired();
+                }
+                Err(_) => {}
+            }
+        } else if interface == system_time_ffi::INTERFACE {
+            match system_time_ffi::TimeMessage::decode(message) {
+                Ok(system_time_ffi::TimeMessage::GetSystem) => {
+                    let now = inner.system_offset.saturating_add(inner.monotonic);
+                    inner.answer(message_id, now.encode());
+                }
+                Err(_) => {}
+            }
+        } else {
+            unreachable!()
+        }
+    }
+
+    fn process_destroyed(self, pid: Pid) {
+        self.clock.inner.lock().unwrap().processes.remove(&pid);
+    }
+
+    fn message_response(self, _: MessageId, _: Result<EncodedMessage, ()>) {
+        unreachable!()
+    }
+}
+
+impl ClockProgram {
+    /// Answers a message emitted by a process that doesn't use the virtual clock.
+    fn host_time_message(
+        &self,
+        inner: &ClockInner,
+        interface: InterfaceHash,
+        message_id: MessageId,
+        message: EncodedMessage,
+    ) {
+        if interface == time_ffi::INTERFACE {
+            match time_ffi::TimeMessage::decode(message) {
+                Ok(time_ffi::TimeMessage::GetMonotonic) => {
+                    let now = self.host_start.elapsed().as_nanos();
+                    inner.answer(message_id, now.encode());
+                }
+                Ok(time_ffi::TimeMessage::WaitMonotonic(until)) => {
+                    let answers_tx = match &inner.answers_tx {
+                        Some(tx) => tx.clone(),
+                        None => return,
+                    };
+                    let deadline = self.host_start + u128_to_duration(until);
+                    thread::spawn(move || {
+                        thread::sleep(deadline.saturating_duration_since(Instant::now()));
+                        let _ = answers_tx.unbounded_send((message_id, ().encode()));
+                    });
+                }
+                Err(_) => {}
+            }
+        } else if interface == system_time_ffi::INTERFACE {
+            match system_time_ffi::TimeMessage::decode(message) {
+                Ok(system_time_ffi::TimeMessage::GetSystem) => {
+                    let now = SystemTime::now()
+                        .duration_since(SystemTime::UNIX_EPOCH)
+                        .map_or(0, |d| d.as_nanos());
+                    inner.answer(message_id, now.encode());
+                }
+                Err(_) => {}
+            }
+        } else {
+            unreachable!()
+        }
+    }
+}
+
+fn u128_to_duration(nanos: u128) -> Duration {
+    let secs = u64::try_from(nanos / 1_000_000_000).unwrap_or(u64::max_value());
+    Duration::new(secs, (nanos % 1_000_000_000) as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::VirtualClock;
+    use futures::{executor::block_on, prelude::*};
+    use redshirt_core::native::{NativeProgramEvent, NativeProgramRef as _};
+    use redshirt_core::{Decode as _, Encode as _, MessageId, Pid};
+    use redshirt_time_interface::ffi as time_ffi;
+    use std::time::Duration;
+
+    #[test]
+    fn advance_doesnt_overflow() {
+        let clock = VirtualClock::new();
+        let max = Duration::new(u64::max_value(), 999_999_999);
+        clock.advance(max);
+        assert_eq!(clock.now(), max);
+    }
+
+    #[test]
+    fn only_driven_processes_use_virtual_time() {
+        let clock = VirtualClock::new();
+        let program = clock.program();
+        let driven = Pid::from(1);
+        let other = Pid::from(2);
+        clock.drive(driven);
+
+        // Registration of the `time` and `system-time` interfaces.
+        for _ in 0..2 {
+            match block_on((&program).next_event()) {
+                NativeProgramEvent::Emit { .. } => {}
+                _ => panic!(),
+            }
+        }
+
+        let wait = time_ffi::TimeMessage::WaitMonotonic(1_000_000_000_000).encode();
+        (&program).interface_message(
+            time_ffi::INTERFACE,
+            Some(MessageId::from(1)),
+            driven,
+            wait.clone(),
+        );
+        (&program).interface_message(time_ffi::INTERFACE, Some(MessageId::from(2)), other, wait);
+        assert_eq!(clock.pending_timers(), 1);
+
+        assert!(clock.advance_to_next_timer());
+        assert_eq!(clock.now(), Duration::from_secs(1000));
+        match block_on((&program).next_event()) {
+            NativeProgramEvent::Answer { message_id, .. } => {
+                assert_eq!(message_id, MessageId::from(1))
+            }
+            _ => panic!(),
+        }
+
+        // The timer of the other process uses the time of the host, and hasn't fired.
+        assert!((&program).next_event().now_or_never().is_none());
+
+        (&program).interface_message(
+            time_ffi::INTERFACE,
+            Some(MessageId::from(3)),
+            other,
+            time_ffi::TimeMessage::GetMonotonic.encode(),
+        );
+        match block_on((&program).next_event()) {
+            NativeProgramEvent::Answer {
+                message_id,
+                answer: Ok(answer),
+            } => {
+                assert_eq!(message_id, MessageId::from(3));
+                assert!(u128::decode(answer).unwrap() < 1_000_000_000_000);
+            }
+            _ => panic!(),
+        }
+    }
+}
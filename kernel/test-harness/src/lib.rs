@@ -0,0 +1,269 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Runs Wasm programs to completion for testing purposes.
+//!
+//! The [`TestHarness`] builds a [`System`](redshirt_core::System), executes a single program
+//! within it, and runs the system until this program has finished.
+//!
+//! # Code coverage
+//!
+//! Calling [`TestHarness::with_coverage`] instruments the program in order to record which of
+//! its functions and blocks have been executed. The coverage can then be retrieved in the
+//! `lcov` format with [`TestOutcome::lcov`], or written to a file with
+//! [`TestOutcome::write_lcov`]. This makes it possible to measure the coverage of code that
+//! only ever runs inside of the virtual machine.
+//!
+//! # Mocking interfaces
+//!
+//! The interfaces that the program uses can be replaced with a [`Mock`] passed to
+//! [`TestHarness::with_mock`]. The mock answers the messages emitted by the program using the
+//! handlers it has been given, and [`TestHarness::run`] panics if the program hasn't emitted the
+//! messages that the mock expects. See the [`mock`] module for more information.
+//!
+//! # Virtual time
+//!
+//! Calling [`TestHarness::with_virtual_clock`] makes the `time` and `system-time` interfaces use
+//! a [`VirtualClock`] instead of the real time for the tested program. Other processes keep
+//! using the real time, unless selected with [`VirtualClock::drive`]. Whenever the program is
+//! blocked and a timer is pending, the harness advances the clock to this timer. Sleeping and timeouts are therefore
+//! instantaneous, and don't depend on the speed of the machine running the test.
+
+use self::mock::{MockProgram, MockVerifier};
+use futures::prelude::*;
+use redshirt_core::instrumentation::{coverage::CoverageCollector, Instrumentation};
+use redshirt_core::native::NativeProgramRef;
+use redshirt_core::{Module, Pid, SystemBuilder, SystemRunOutcome};
+use std::{fs, io, path::Path, task::Poll};
+
+pub use self::clock::VirtualClock;
+pub use self::mock::{Expectation, InterfaceMock, Mock, Sequence};
+pub use redshirt_core::instrumentation::coverage::CoverageReport;
+
+pub mod clock;
+pub mod mock;
+
+/// Prototype for running a test program.
+pub struct TestHarness<'a> {
+    /// Builder for the system the program runs in.
+    system: SystemBuilder<'a>,
+    /// If `Some`, the program is instrumented for code coverage.
+    coverage: Option<CoverageCollector>,
+    /// Verifiers of the mocks passed to [`TestHarness::with_mock`].
+    mocks: Vec<MockVerifier>,
+    /// If `Some`, clock to advance whenever the program is waiting for a timer.
+    clock: Option<VirtualClock>,
+}
+
+/// Outcome of running a program with a [`TestHarness`].
+#[derive(Debug)]
+pub struct TestOutcome {
+    /// Pid that the program had.
+    pub pid: Pid,
+    /// How the program has finished.
+    pub outcome: SystemRunOutcome,
+    /// Coverage of the program, if [`TestHarness::with_coverage`] has been called.
+    pub coverage: Option<CoverageReport>,
+}
+
+impl<'a> TestHarness<'a> {
+    /// Builds a new harness with no native program.
+    pub fn new() -> Self {
+        TestHarness {
+            system: SystemBuilder::new(),
+            coverage: None,
+            mocks: Vec::new(),
+            clock: None,
+        }
+    }
+
+    /// Registers a native program in the system the tested program runs in.
+    pub fn with_native_program<T>(mut self, program: T) -> Self
+    where
+        T: Send + 'a,
+        for<'r> &'r T: NativeProgramRef<'r>,
+    {
+        self.system = self.system.with_native_program(program);
+        self
+    }
+
+    /// Registers the interfaces of the given [`Mock`] in the system the tested program runs in.
+    ///
+    /// After the program has finished, [`TestHarness::run`] checks that the expectations of the
+    /// mock have been fulfilled.
+    pub fn with_mock(mut self, mock: Mock) -> Self {
+        let (program, verifier) = MockProgram::new(mock);
+        self.system = self.system.with_native_program(program);
+        self.mocks.push(verifier);
+        self
+    }
+
+    /// Implements the `time` and `system-time` interfaces using the given clock for the tested
+    /// program, and using the time of the host for the other processes.
+    ///
+    /// While the program runs, the clock automatically advances to the next pending timer
+    /// whenever nothing else can make progress. It can also be advanced manually, for example
+    /// from the handler of a [`Mock`].
+    pub fn with_virtual_clock(mut self, clock: &VirtualClock) -> Self {
+        self.system = self.system.with_native_program(clock.program());
+        self.clock = Some(clock.clone());
+        self
+    }
+
+    /// Enables recording the code coverage of the tested program.
+    pub fn with_coverage(mut self) -> Self {
+        let collector = CoverageCollector::new();
+        self.system = self.system.with_coverage(&collector);
+        self.coverage = Some(collector);
+        self
+    }
+
+    /// Parses the given Wasm bytes, then runs the program until it finishes.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the bytes aren't a valid module, or if the program can't be started.
+    ///
+    /// Panics if the program has emitted messages that a mock didn't expect, or hasn't emitted
+    /// messages that a mock expected.
+    ///
+    pub fn run(self, module: impl AsRef<[u8]>) -> TestOutcome {
+        let module = match &self.coverage {
+            Some(collector) => Module::from_bytes_instrumented(
+                module,
+                &Instrumentation::new().with_pass(collector.pass()),
+            )
+            .expect("Failed to parse module"),
+            None => Module::from_bytes(module).expect("Failed to parse module"),
+        };
+
+        let system = self.system.build().expect("Failed to start system");
+        let pid = system.execute(&module).expect("Failed to start program");
+        if let Some(clock) = &self.clock {
+            clock.drive(pid);
+        }
+
+        let clock = self.clock.as_ref();
+        let outcome = futures::executor::block_on(async {
+            loop {
+                let run = system.run();
+                futures::pin_mut!(run);
+                let outcome = future::poll_fn(|cx| loop {
+                    if let Poll::Ready(outcome) = run.as_mut().poll(cx) {
+                        return Poll::Ready(outcome);
+                    }
+
+                    // Nothing can make progress. Jump to the next timer, if any.
+                    match clock {
+                        Some(clock) if clock.advance_to_next_timer() => {}
+                        _ => return Poll::Pending,
+                    }
+                })
+                .await;
+
+                match outcome {
+                    SystemRunOutcome::ProgramFinished { pid: p, .. } if p != pid => {}
+                    outcome => break outcome,
+                }
+            }
+        });
+
+        let failures = self
+            .mocks
+            .iter()
+            .flat_map(|m| m.failures())
+            .collect::<Vec<_>>();
+        if !failures.is_empty() {
+            panic!("Mock expectations not met:\n{}", failures.join("\n"));
+        }
+
+        TestOutcome {
+            pid,
+            outcome,
+            coverage: self.coverage.map(|c| {
+                c.process_report(pid)
+                    .expect("Coverage of the program is missing")
+            }),
+        }
+    }
+}
+
+impl<'a> Default for TestHarness<'a> {
+    fn default() -> Self {
+        TestHarness::new()
+    }
+}
+
+impl TestOutcome {
+    /// Returns true if the program has finished successfully.
+    pub fn is_success(&self) -> bool {
+        match self.outcome {
+            SystemRunOutcome::ProgramFinished {
+                outcome: Ok(()),
+                ref exit_status,
+                ..
+            } => exit_status.is_success(),
+            _ => false,
+        }
+    }
+
+    /// Returns the coverage report in the `lcov` format, if coverage was enabled.
+    pub fn lcov(&self) -> Option<String> {
+        self.coverage.as_ref().map(|c| c.to_lcov())
+    }
+
+    /// Writes the coverage report in the `lcov` format to the given file.
+    ///
+    /// # Panic
+    ///
+    /// Panics if coverage wasn't enabled.
+    ///
+    pub fn write_lcov(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let lcov = self.lcov().expect("Coverage wasn't enabled");
+        fs::write(path, lcov)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Mock, TestHarness};
+    use redshirt_core::{wat_to_bin, InterfaceHash};
+
+    fn empty_program() -> Vec<u8> {
+        wat_to_bin!(
+            r#"(module
+            (func $_start)
+            (export "_start" (func $_start)))
+        "#
+        )
+        .to_vec()
+    }
+
+    #[test]
+    fn runs_program() {
+        let outcome = TestHarness::new().run(empty_program());
+        assert!(outcome.is_success());
+    }
+
+    #[test]
+    #[should_panic(expected = "Mock expectations not met")]
+    fn unmet_expectation_panics() {
+        let mut mock = Mock::new();
+        mock.interface(InterfaceHash::from_raw_hash([1; 32]))
+            .on(|_: u32| ())
+            .expect_calls(1);
+        TestHarness::new().with_mock(mock).run(empty_program());
+    }
+}
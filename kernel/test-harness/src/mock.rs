@@ -0,0 +1,541 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Declarative mocks of interfaces.
+//!
+//! A [`Mock`] describes, for each mocked interface, the list of messages that the tested
+//! program is expected to emit and how to answer them:
+//!
+//! ```ignore
+//! let mut mock = Mock::new();
+//! mock.interface(tcp::ffi::INTERFACE)
+//!     .on(|msg: TcpMessage| open_response(msg))
+//!     .expect_calls(2);
+//! ```
+//!
+//! Each call to [`InterfaceMock::on`] adds an [`Expectation`]. When a message arrives on a mocked
+//! interface, the expectations of this interface are tried in the order in which they have been
+//! declared, and the first one whose type successfully decodes the message handles it. The value
+//! returned by the handler is sent back as the answer, if the emitter expects one.
+//!
+//! Messages that no expectation can decode, wrong numbers of calls, and calls that violate the
+//! order of a [`Sequence`] are all reported as failures when the test finishes.
+
+use futures::{channel::mpsc, lock::Mutex as AsyncMutex, prelude::*};
+use redshirt_core::native::{DummyMessageIdWrite, NativeProgramEvent, NativeProgramRef};
+use redshirt_core::{Decode, Encode, EncodedMessage, InterfaceHash, MessageId, Pid};
+use std::{
+    any, fmt,
+    pin::Pin,
+    sync::{atomic, Arc, Mutex},
+};
+
+/// Description of the interfaces to mock and of the messages expected on them.
+#[derive(Default)]
+pub struct Mock {
+    /// List of mocked interfaces, in the order in which they have been declared.
+    interfaces: Vec<InterfaceMock>,
+}
+
+/// Expectations of a single interface of a [`Mock`].
+pub struct InterfaceMock {
+    /// Interface being mocked.
+    interface: InterfaceHash,
+    /// List of expectations, in the order in which they have been declared.
+    expectations: Vec<Expectation>,
+}
+
+/// Message expected on an interface, and how to answer it.
+pub struct Expectation {
+    /// Name of the type of the message. Used for failure reports.
+    type_name: &'static str,
+    /// Returns true if the message decodes as the type this expectation is about.
+    decodes: fn(EncodedMessage) -> bool,
+    /// Generates the answer to a message. Must only be called with messages that `decodes`
+    /// accepts.
+    handler: Box<dyn FnMut(EncodedMessage) -> EncodedMessage + Send>,
+    /// If `Some`, the exact number of times the expectation must be matched.
+    expected_calls: Option<usize>,
+    /// Number of times this expectation has been matched so far.
+    calls: usize,
+    /// If `Some`, the sequence this expectation belongs to and its position within it.
+    sequence: Option<(Sequence, usize)>,
+}
+
+/// Ordering constraint between [`Expectation`]s.
+///
+/// The expectations added to a sequence with [`Expectation::in_sequence`] must be matched in the
+/// order in which they have been added, even if they concern different interfaces. An expectation
+/// is considered done with once its expected number of calls is reached, or once it has been
+/// called at least once if no number of calls has been set.
+#[derive(Clone, Default)]
+pub struct Sequence {
+    /// Number of expectations added to the sequence. Also used as the identity of the sequence.
+    len: Arc<atomic::AtomicUsize>,
+}
+
+impl Mock {
+    /// Builds a new mock with no interface.
+    pub fn new() -> Self {
+        Mock::default()
+    }
+
+    /// Returns the expectations of the given interface, starting to mock it if necessary.
+    pub fn interface(&mut self, interface: InterfaceHash) -> &mut InterfaceMock {
+        let position = match self
+            .interfaces
+            .iter()
+            .position(|i| i.interface == interface)
+        {
+            Some(p) => p,
+            None => {
+                self.interfaces.push(InterfaceMock {
+                    interface,
+                    expectations: Vec::new(),
+                });
+                self.interfaces.len() - 1
+            }
+        };
+
+        &mut self.interfaces[position]
+    }
+}
+
+impl InterfaceMock {
+    /// Adds an expectation for messages that decode as a `T`.
+    ///
+    /// The handler is called with the decoded message, and its return value is sent back as the
+    /// answer to the message. It is only called for the messages that this expectation ends up
+    /// handling.
+    ///
+    /// > **Note**: Messages are decoded in their entirety. Interfaces whose messages are an
+    /// >           `enum` should typically be mocked using this `enum` as `T`.
+    pub fn on<T, R>(&mut self, mut handler: impl FnMut(T) -> R + Send + 'static) -> &mut Expectation
+    where
+        T: Decode,
+        R: Encode,
+    {
+        self.expectations.push(Expectation {
+            type_name: any::type_name::<T>(),
+            decodes: |message| T::decode(message).is_ok(),
+            handler: Box::new(move |message| match T::decode(message) {
+                Ok(msg) => handler(msg).encode(),
+                Err(_) => unreachable!(),
+            }),
+            expected_calls: None,
+            calls: 0,
+            sequence: None,
+        });
+
+        self.expectations.last_mut().unwrap()
+    }
+}
+
+impl Expectation {
+    /// Requires the expectation to be matched exactly `calls` times.
+    ///
+    /// Once this number is reached, the following messages are handed to the next expectations
+    /// of the same interface.
+    pub fn expect_calls(&mut self, calls: usize) -> &mut Self {
+        self.expected_calls = Some(calls);
+        self
+    }
+
+    /// Adds the expectation at the end of the given [`Sequence`].
+    pub fn in_sequence(&mut self, sequence: &Sequence) -> &mut Self {
+        let position = sequence.len.fetch_add(1, atomic::Ordering::Relaxed);
+        self.sequence = Some((sequence.clone(), position));
+        self
+    }
+
+    /// Returns true if no more calls are required.
+    fn is_satisfied(&self) -> bool {
+        match self.expected_calls {
+            Some(n) => self.calls == n,
+            None => self.calls >= 1,
+        }
+    }
+
+    /// Returns true if a call would exceed the expected number of calls.
+    fn is_saturated(&self) -> bool {
+        self.expected_calls.map_or(false, |n| self.calls >= n)
+    }
+}
+
+impl Sequence {
+    /// Builds a new empty sequence.
+    pub fn new() -> Self {
+        Sequence::default()
+    }
+
+    fn is(&self, other: &Sequence) -> bool {
+        Arc::ptr_eq(&self.len, &other.len)
+    }
+}
+
+/// Native program that implements the interfaces described by a [`Mock`].
+pub(crate) struct MockProgram {
+    /// Number of interface registration messages that have been emitted.
+    registered: atomic::AtomicUsize,
+    /// Mocked interfaces. Copied from the state in order to not have to lock it in `next_event`.
+    interfaces: Vec<InterfaceHash>,
+    /// Expectations and failures. Shared with the [`MockVerifier`].
+    state: Arc<Mutex<MockState>>,
+    /// Send on this channel the answers to messages.
+    answers_tx: mpsc::UnboundedSender<(MessageId, Result<EncodedMessage, ()>)>,
+    /// Receiving side of [`MockProgram::answers_tx`]. Accessed only by `next_event`.
+    answers_rx: AsyncMutex<mpsc::UnboundedReceiver<(MessageId, Result<EncodedMessage, ()>)>>,
+}
+
+/// Checks, once the test is over, that a [`Mock`] has been used as expected.
+pub(crate) struct MockVerifier {
+    state: Arc<Mutex<MockState>>,
+}
+
+struct MockState {
+    interfaces: Vec<InterfaceMock>,
+    /// List of failures that have happened while handling messages.
+    failures: Vec<String>,
+}
+
+impl MockProgram {
+    /// Turns a [`Mock`] into a native program, plus an object to verify it afterwards.
+    pub(crate) fn new(mock: Mock) -> (Self, MockVerifier) {
+        let (answers_tx, answers_rx) = mpsc::unbounded();
+        let state = Arc::new(Mutex::new(MockState {
+            interfaces: mock.interfaces,
+            failures: Vec::new(),
+        }));
+
+        let program = MockProgram {
+            registered: atomic::AtomicUsize::new(0),
+            interfaces: state
+                .lock()
+                .unwrap()
+                .interfaces
+                .iter()
+                .map(|i| i.interface.clone())
+                .collect(),
+            state: state.clone(),
+            answers_tx,
+            answers_rx: AsyncMutex::new(answers_rx),
+        };
+
+        (program, MockVerifier { state })
+    }
+}
+
+impl<'a> NativeProgramRef<'a> for &'a MockProgram {
+    type Future =
+        Pin<Box<dyn Future<Output = NativeProgramEvent<Self::MessageIdWrite>> + Send + 'a>>;
+    type MessageIdWrite = DummyMessageIdWrite;
+
+    fn next_event(self) -> Self::Future {
+        Box::pin(async move {
+            let index = self.registered.fetch_add(1, atomic::Ordering::Relaxed);
+            if let Some(interface) = self.interfaces.get(index) {
+                return NativeProgramEvent::Emit {
+                    interface: redshirt_interface_interface::ffi::INTERFACE,
+                    message_id_write: None,
+                    message: redshirt_interface_interface::ffi::InterfaceMessage::Register(
+                        interface.clone(),
+                    )
+                    .encode(),
+                };
+            }
+            // Prevent the counter from eventually overflowing.
+            self.registered
+                .store(self.interfaces.len(), atomic::Ordering::Relaxed);
+
+            let mut answers_rx = self.answers_rx.lock().await;
+            match answers_rx.next().await {
+                Some((message_id, answer)) => NativeProgramEvent::Answer { message_id, answer },
+                None => unreachable!(),
+            }
+        })
+    }
+
+    fn interface_message(
+        self,
+        interface: InterfaceHash,
+        message_id: Option<MessageId>,
+        _: Pid,
+        message: EncodedMessage,
+    ) {
+        let answer = self.state.lock().unwrap().handle(&interface, message);
+        if let Some(message_id) = message_id {
+            self.answers_tx
+                .unbounded_send((message_id, answer))
+                .unwrap();
+        }
+    }
+
+    fn process_destroyed(self, _: Pid) {}
+
+    fn message_response(self, _: MessageId, _: Result<EncodedMessage, ()>) {
+        unreachable!()
+    }
+}
+
+impl MockState {
+    /// Dispatches a message to the matching expectation, and returns the answer to send back.
+    fn handle(
+        &mut self,
+        interface: &InterfaceHash,
+        message: EncodedMessage,
+    ) -> Result<EncodedMessage, ()> {
+        let interface_index = match self
+            .interfaces
+            .iter()
+            .position(|i| i.interface == *interface)
+        {
+            Some(i) => i,
+            None => unreachable!(),
+        };
+
+        // Find the first expectation that accepts the message, skipping the ones that have
+        // already been called as many times as they should. Only the handler of the expectation
+        // that is picked is called.
+        let mut saturated_match = None;
+        let mut matched = None;
+        for (index, expectation) in self.interfaces[interface_index]
+            .expectations
+            .iter()
+            .enumerate()
+        {
+            if !(expectation.decodes)(message.clone()) {
+                continue;
+            }
+
+            if expectation.is_saturated() {
+                if saturated_match.is_none() {
+                    saturated_match = Some(index);
+                }
+                continue;
+            }
+
+            matched = Some(index);
+            break;
+        }
+
+        let index = match (matched, saturated_match) {
+            (Some(m), _) => m,
+            (None, Some(index)) => {
+                let expectation = &mut self.interfaces[interface_index].expectations[index];
+                expectation.calls += 1;
+                self.failures.push(format!(
+                    "{} on {:?}: expected {} call(s), got at least {}",
+                    expectation.type_name,
+                    interface,
+                    expectation.expected_calls.unwrap(),
+                    expectation.calls
+                ));
+                return Err(());
+            }
+            (None, None) => {
+                self.failures.push(format!(
+                    "Unexpected message on {:?}: {:?}",
+                    interface, message
+                ));
+                return Err(());
+            }
+        };
+
+        if let Some(failure) = self.check_order(interface_index, index) {
+            self.failures.push(failure);
+        }
+
+        let expectation = &mut self.interfaces[interface_index].expectations[index];
+        expectation.calls += 1;
+        Ok((expectation.handler)(message))
+    }
+
+    /// Checks whether calling the given expectation respects its sequence, if any.
+    fn check_order(&self, interface_index: usize, index: usize) -> Option<String> {
+        let expectation = &self.interfaces[interface_index].expectations[index];
+        let (sequence, position) = expectation.sequence.as_ref()?;
+
+        for other in self.interfaces.iter().flat_map(|i| i.expectations.iter()) {
+            let other_position = match &other.sequence {
+                Some((s, p)) if s.is(sequence) => *p,
+                _ => continue,
+            };
+
+            if other_position < *position && !other.is_satisfied() {
+                return Some(format!(
+                    "{} called before {} was done with, which comes earlier in the sequence",
+                    expectation.type_name, other.type_name
+                ));
+            }
+
+            if other_position > *position && other.calls != 0 {
+                return Some(format!(
+                    "{} called after {}, which comes later in the sequence",
+                    expectation.type_name, other.type_name
+                ));
+            }
+        }
+
+        None
+    }
+}
+
+impl MockVerifier {
+    /// Returns the list of failures: unexpected messages, wrong numbers of calls, and ordering
+    /// violations.
+    pub(crate) fn failures(&self) -> Vec<String> {
+        let state = self.state.lock().unwrap();
+        let mut failures = state.failures.clone();
+
+        for interface in &state.interfaces {
+            for expectation in &interface.expectations {
+                match expectation.expected_calls {
+                    Some(n) if expectation.calls < n => failures.push(format!(
+                        "{} on {:?}: expected {} call(s), got {}",
+                        expectation.type_name, interface.interface, n, expectation.calls
+                    )),
+                    _ => {}
+                }
+            }
+        }
+
+        failures
+    }
+}
+
+impl fmt::Debug for Mock {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list()
+            .entries(self.interfaces.iter().map(|i| &i.interface))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Mock, MockProgram, Sequence};
+    use redshirt_core::{Encode as _, InterfaceHash};
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    const A: InterfaceHash = InterfaceHash::from_raw_hash([1; 32]);
+    const B: InterfaceHash = InterfaceHash::from_raw_hash([2; 32]);
+
+    #[test]
+    fn only_picked_handler_called() {
+        let first_calls = Arc::new(AtomicUsize::new(0));
+        let second_calls = Arc::new(AtomicUsize::new(0));
+
+        let mut mock = Mock::new();
+        mock.interface(A)
+            .on({
+                let first_calls = first_calls.clone();
+                move |v: u32| {
+                    first_calls.fetch_add(1, Ordering::Relaxed);
+                    v + 1
+                }
+            })
+            .expect_calls(1);
+        mock.interface(A).on({
+            let second_calls = second_calls.clone();
+            move |v: u32| {
+                second_calls.fetch_add(1, Ordering::Relaxed);
+                v + 2
+            }
+        });
+
+        let (program, verifier) = MockProgram::new(mock);
+        let mut state = program.state.lock().unwrap();
+        assert_eq!(state.handle(&A, 5u32.encode()), Ok(6u32.encode()));
+        assert_eq!(state.handle(&A, 5u32.encode()), Ok(7u32.encode()));
+        drop(state);
+
+        assert_eq!(first_calls.load(Ordering::Relaxed), 1);
+        assert_eq!(second_calls.load(Ordering::Relaxed), 1);
+        assert!(verifier.failures().is_empty());
+    }
+
+    #[test]
+    fn too_many_calls_reported() {
+        let calls = Arc::new(AtomicUsize::new(0));
+
+        let mut mock = Mock::new();
+        mock.interface(A)
+            .on({
+                let calls = calls.clone();
+                move |_: u32| {
+                    calls.fetch_add(1, Ordering::Relaxed);
+                }
+            })
+            .expect_calls(1);
+
+        let (program, verifier) = MockProgram::new(mock);
+        let mut state = program.state.lock().unwrap();
+        assert!(state.handle(&A, 5u32.encode()).is_ok());
+        assert!(state.handle(&A, 5u32.encode()).is_err());
+        drop(state);
+
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+        assert_eq!(verifier.failures().len(), 1);
+    }
+
+    #[test]
+    fn missing_calls_reported() {
+        let mut mock = Mock::new();
+        mock.interface(A).on(|_: u32| ()).expect_calls(2);
+
+        let (program, verifier) = MockProgram::new(mock);
+        assert!(program
+            .state
+            .lock()
+            .unwrap()
+            .handle(&A, 5u32.encode())
+            .is_ok());
+        assert_eq!(verifier.failures().len(), 1);
+    }
+
+    #[test]
+    fn unexpected_message_reported() {
+        let mut mock = Mock::new();
+        mock.interface(A).on(|_: u64| ());
+
+        let (program, verifier) = MockProgram::new(mock);
+        assert!(program
+            .state
+            .lock()
+            .unwrap()
+            .handle(&A, 5u8.encode())
+            .is_err());
+        assert_eq!(verifier.failures().len(), 1);
+    }
+
+    #[test]
+    fn sequence_order_enforced() {
+        let sequence = Sequence::new();
+        let mut mock = Mock::new();
+        mock.interface(A).on(|_: u32| ()).in_sequence(&sequence);
+        mock.interface(B).on(|_: u32| ()).in_sequence(&sequence);
+
+        let (program, verifier) = MockProgram::new(mock);
+        let mut state = program.state.lock().unwrap();
+        assert!(state.handle(&B, 5u32.encode()).is_ok());
+        assert!(state.handle(&A, 5u32.encode()).is_ok());
+        drop(state);
+
+        assert_eq!(verifier.failures().len(), 2);
+    }
+}
@@ -0,0 +1,132 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Implements the HTTP interface.
+//!
+//! Each request is performed by opening a connection through the TCP interface, and the TLS
+//! interface for `https` URLs. Connections aren't reused between requests.
+
+use futures::{channel::mpsc, prelude::*, stream::FuturesUnordered};
+use parity_scale_codec::DecodeAll;
+use redshirt_http_interface::ffi;
+use redshirt_syscalls::{DecodedInterfaceOrDestroyed, MessageId, Pid};
+use std::{collections::HashMap, pin::Pin};
+
+mod request;
+
+fn main() {
+    redshirt_syscalls::block_on(async_main());
+}
+
+async fn async_main() {
+    redshirt_interface_interface::register_interface(ffi::INTERFACE)
+        .await
+        .unwrap();
+
+    // Requests in progress. Each future yields the identifier of its response when finished.
+    let mut requests = FuturesUnordered::<Pin<Box<dyn Future<Output = u32>>>>::new();
+    // For each response, the process that has sent the request and a channel where to send the
+    // messages asking to read the body.
+    let mut responses = HashMap::<u32, (Pid, mpsc::UnboundedSender<MessageId>)>::new();
+    let mut next_response_id: u32 = 0;
+
+    loop {
+        let event = {
+            let next_message = redshirt_syscalls::next_interface_message();
+            let next_finished = async {
+                if requests.is_empty() {
+                    future::pending().await
+                } else {
+                    requests.next().await.unwrap()
+                }
+            };
+            futures::pin_mut!(next_finished);
+            match future::select(next_message, next_finished).await {
+                future::Either::Left((msg, _)) => future::Either::Left(msg),
+                future::Either::Right((response_id, _)) => future::Either::Right(response_id),
+            }
+        };
+
+        let msg = match event {
+            future::Either::Left(DecodedInterfaceOrDestroyed::Interface(msg)) => msg,
+            future::Either::Left(DecodedInterfaceOrDestroyed::ProcessDestroyed(destroyed)) => {
+                responses.retain(|_, (pid, _)| *pid != destroyed.pid);
+                continue;
+            }
+            future::Either::Right(response_id) => {
+                // The identifier might have been closed and reused in the meantime, in which
+                // case the channel belongs to the new request and is still open.
+                if responses
+                    .get(&response_id)
+                    .map_or(false, |(_, tx)| tx.is_closed())
+                {
+                    responses.remove(&response_id);
+                }
+                continue;
+            }
+        };
+
+        let decoded = match ffi::HttpMessage::decode_all(&msg.actual_data.0) {
+            Ok(m) => m,
+            Err(_) => {
+                if let Some(message_id) = msg.message_id {
+                    redshirt_syscalls::emit_message_error(message_id);
+                }
+                continue;
+            }
+        };
+
+        match (decoded, msg.message_id) {
+            (ffi::HttpMessage::Request(request), Some(message_id)) => {
+                let response_id = loop {
+                    let id = next_response_id;
+                    next_response_id = next_response_id.wrapping_add(1);
+                    if !responses.contains_key(&id) {
+                        break id;
+                    }
+                };
+
+                let (tx, rx) = mpsc::unbounded();
+                responses.insert(response_id, (msg.emitter_pid, tx));
+                requests.push(Box::pin(
+                    request::run(message_id, request, response_id, rx).map(move |()| response_id),
+                ));
+            }
+            (ffi::HttpMessage::ReadBody(read), Some(message_id)) => {
+                let sent = match responses.get(&read.response_id) {
+                    Some((pid, tx)) if *pid == msg.emitter_pid => {
+                        tx.unbounded_send(message_id).is_ok()
+                    }
+                    _ => false,
+                };
+
+                if !sent {
+                    let response = ffi::HttpReadBodyResponse {
+                        result: Err(ffi::HttpError::InvalidResponseId),
+                    };
+                    redshirt_syscalls::emit_answer(message_id, &response);
+                }
+            }
+            (ffi::HttpMessage::Close(close), _) => {
+                if let Some((pid, _)) = responses.get(&close.response_id) {
+                    if *pid == msg.emitter_pid {
+                        responses.remove(&close.response_id);
+                    }
+                }
+            }
+            (_, None) => {}
+        }
+    }
+}
@@ -0,0 +1,403 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Performing a single request.
+
+use futures::{channel::mpsc, prelude::*};
+use redshirt_http_interface::ffi::{self, HttpError};
+use redshirt_syscalls::MessageId;
+use redshirt_tcp_interface::{TcpError, TcpStream};
+use std::{
+    cmp, io,
+    net::{IpAddr, SocketAddr},
+};
+
+/// Maximum size of the status line and headers of a response.
+const MAX_HEAD_LEN: usize = 64 * 1024;
+
+/// Maximum number of headers of a response.
+const MAX_HEADERS: usize = 128;
+
+/// Maximum size of a line of the chunked encoding.
+const MAX_LINE_LEN: usize = 8 * 1024;
+
+/// Maximum number of bytes of the body returned in response to a single message.
+const MAX_CHUNK_LEN: usize = 64 * 1024;
+
+/// Performs the request, answers `message_id` with the status and headers of the response, then
+/// answers each message received on `reads` with a chunk of the body.
+///
+/// Returns once the body has been entirely read, an error has happened, or `reads` is closed.
+pub async fn run(
+    message_id: MessageId,
+    request: ffi::HttpRequest,
+    response_id: u32,
+    mut reads: mpsc::UnboundedReceiver<MessageId>,
+) {
+    let mut connection = match send_request(request).await {
+        Ok((status, headers, connection)) => {
+            let response = ffi::HttpRequestResponse {
+                result: Ok(ffi::HttpResponseHead {
+                    response_id,
+                    status,
+                    headers,
+                }),
+            };
+            redshirt_syscalls::emit_answer(message_id, &response);
+            connection
+        }
+        Err(err) => {
+            let response = ffi::HttpRequestResponse { result: Err(err) };
+            redshirt_syscalls::emit_answer(message_id, &response);
+            return;
+        }
+    };
+
+    while let Some(message_id) = reads.next().await {
+        let result = connection.next_chunk().await;
+        let finished = result.as_ref().map_or(true, |chunk| chunk.is_empty());
+        let response = ffi::HttpReadBodyResponse { result };
+        redshirt_syscalls::emit_answer(message_id, &response);
+        if finished {
+            break;
+        }
+    }
+
+    // The response no longer exists. Reads that have been queued in the meantime are refused,
+    // and closing the channel makes the main task refuse the next ones.
+    reads.close();
+    while let Ok(Some(message_id)) = reads.try_next() {
+        let response = ffi::HttpReadBodyResponse {
+            result: Err(HttpError::InvalidResponseId),
+        };
+        redshirt_syscalls::emit_answer(message_id, &response);
+    }
+}
+
+/// Connection to a server whose response head has been read.
+struct Connection {
+    stream: TcpStream,
+    /// Data received from the server and not processed yet.
+    buffer: Vec<u8>,
+    /// How the end of the body is determined.
+    framing: Framing,
+}
+
+#[derive(Debug, Copy, Clone)]
+enum Framing {
+    /// The body is over.
+    Finished,
+    /// The body has a known length. Contains the number of bytes remaining.
+    Length(u64),
+    /// The body ends when the server closes the connection.
+    UntilClose,
+    /// Chunked encoding, expecting the line containing the size of the next chunk.
+    ChunkSize,
+    /// Chunked encoding, in the middle of a chunk. Contains the number of bytes remaining.
+    ChunkData(u64),
+    /// Chunked encoding, expecting the empty line that follows a chunk.
+    ChunkEnd,
+    /// Chunked encoding, after the last chunk, expecting the trailers.
+    Trailers,
+}
+
+/// Connects to the server, sends the request, and reads the status and headers of the response.
+async fn send_request(
+    request: ffi::HttpRequest,
+) -> Result<(u16, Vec<(String, Vec<u8>)>, Connection), HttpError> {
+    let url = url::Url::parse(&request.url).map_err(|_| HttpError::InvalidUrl)?;
+    let tls = match url.scheme() {
+        "http" => false,
+        "https" => true,
+        _ => return Err(HttpError::InvalidUrl),
+    };
+    let ip = match url.host() {
+        Some(url::Host::Ipv4(ip)) => IpAddr::V4(ip),
+        Some(url::Host::Ipv6(ip)) => IpAddr::V6(ip),
+        // TODO: resolve the name once a DNS interface exists
+        Some(url::Host::Domain(_)) => return Err(HttpError::UnresolvableHost),
+        None => return Err(HttpError::InvalidUrl),
+    };
+    let port = url.port_or_known_default().ok_or(HttpError::InvalidUrl)?;
+    let host = url.host_str().ok_or(HttpError::InvalidUrl)?;
+
+    let head = request_head(&request, &url)?;
+
+    let stream = TcpStream::connect(&SocketAddr::new(ip, port))
+        .await
+        .map_err(HttpError::Connection)?;
+    let stream = if tls {
+        redshirt_tls_interface::connect(stream, host)
+            .await
+            .map_err(HttpError::Tls)?
+    } else {
+        stream
+    };
+
+    let mut connection = Connection {
+        stream,
+        buffer: Vec::new(),
+        framing: Framing::Finished,
+    };
+
+    connection
+        .stream
+        .write_all(&head)
+        .await
+        .map_err(connection_error)?;
+    connection
+        .stream
+        .write_all(&request.body)
+        .await
+        .map_err(connection_error)?;
+    connection.stream.flush().await.map_err(connection_error)?;
+
+    let (status, headers) = loop {
+        let (status, headers) = connection.read_head().await?;
+        // Informational responses, such as `100 Continue`, are followed with the actual one.
+        if status >= 200 {
+            break (status, headers);
+        }
+    };
+
+    connection.framing =
+        if request.method.eq_ignore_ascii_case("HEAD") || status == 204 || status == 304 {
+            Framing::Finished
+        } else if let Some((_, value)) = headers
+            .iter()
+            .rev()
+            .find(|(name, _)| name.eq_ignore_ascii_case("Transfer-Encoding"))
+        {
+            let last_coding = value.rsplit(|b| *b == b',').next().unwrap_or(&[]);
+            if String::from_utf8_lossy(last_coding)
+                .trim()
+                .eq_ignore_ascii_case("chunked")
+            {
+                Framing::ChunkSize
+            } else {
+                Framing::UntilClose
+            }
+        } else if let Some((_, value)) = headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("Content-Length"))
+        {
+            let len = std::str::from_utf8(value)
+                .ok()
+                .and_then(|v| v.trim().parse().ok())
+                .ok_or(HttpError::InvalidResponse)?;
+            Framing::Length(len)
+        } else {
+            Framing::UntilClose
+        };
+
+    Ok((status, headers, connection))
+}
+
+/// Builds the request line and the headers of the request.
+fn request_head(request: &ffi::HttpRequest, url: &url::Url) -> Result<Vec<u8>, HttpError> {
+    let is_token = |s: &str| {
+        !s.is_empty()
+            && s.bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+    };
+
+    if !is_token(&request.method) {
+        return Err(HttpError::InvalidRequest);
+    }
+
+    let mut head = Vec::new();
+    head.extend_from_slice(request.method.as_bytes());
+    head.push(b' ');
+    head.extend_from_slice(url[url::Position::BeforePath..url::Position::AfterQuery].as_bytes());
+    head.extend_from_slice(b" HTTP/1.1\r\nHost: ");
+    head.extend_from_slice(url[url::Position::BeforeHost..url::Position::AfterPort].as_bytes());
+    head.extend_from_slice(b"\r\nConnection: close\r\n");
+    if !request.body.is_empty() {
+        head.extend_from_slice(format!("Content-Length: {}\r\n", request.body.len()).as_bytes());
+    }
+
+    for (name, value) in &request.headers {
+        if !is_token(name) || value.iter().any(|b| *b == b'\r' || *b == b'\n') {
+            return Err(HttpError::InvalidRequest);
+        }
+        if name.eq_ignore_ascii_case("Host")
+            || name.eq_ignore_ascii_case("Connection")
+            || name.eq_ignore_ascii_case("Content-Length")
+        {
+            continue;
+        }
+
+        head.extend_from_slice(name.as_bytes());
+        head.extend_from_slice(b": ");
+        head.extend_from_slice(value);
+        head.extend_from_slice(b"\r\n");
+    }
+
+    head.extend_from_slice(b"\r\n");
+    Ok(head)
+}
+
+impl Connection {
+    /// Reads the status line and the headers of a response.
+    async fn read_head(&mut self) -> Result<(u16, Vec<(String, Vec<u8>)>), HttpError> {
+        loop {
+            let parsed = {
+                let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+                let mut response = httparse::Response::new(&mut headers);
+                match response.parse(&self.buffer) {
+                    Ok(httparse::Status::Complete(len)) => {
+                        let status = response.code.ok_or(HttpError::InvalidResponse)?;
+                        let headers = response
+                            .headers
+                            .iter()
+                            .map(|h| (h.name.to_owned(), h.value.to_vec()))
+                            .collect::<Vec<_>>();
+                        Some((len, status, headers))
+                    }
+                    Ok(httparse::Status::Partial) => None,
+                    Err(_) => return Err(HttpError::InvalidResponse),
+                }
+            };
+
+            if let Some((len, status, headers)) = parsed {
+                self.buffer.drain(..len);
+                return Ok((status, headers));
+            }
+
+            if self.buffer.len() >= MAX_HEAD_LEN || self.read_more().await? == 0 {
+                return Err(HttpError::InvalidResponse);
+            }
+        }
+    }
+
+    /// Returns the next chunk of the body, or an empty chunk if the body is over.
+    async fn next_chunk(&mut self) -> Result<Vec<u8>, HttpError> {
+        loop {
+            match self.framing {
+                Framing::Finished => return Ok(Vec::new()),
+                Framing::Length(0) => {
+                    self.framing = Framing::Finished;
+                    return Ok(Vec::new());
+                }
+                Framing::Length(remaining) => {
+                    let chunk = self.take_data(remaining).await?;
+                    self.framing = Framing::Length(remaining - chunk.len() as u64);
+                    return Ok(chunk);
+                }
+                Framing::UntilClose => {
+                    if self.buffer.is_empty() && self.read_more().await? == 0 {
+                        self.framing = Framing::Finished;
+                        return Ok(Vec::new());
+                    }
+                    let len = cmp::min(self.buffer.len(), MAX_CHUNK_LEN);
+                    return Ok(self.buffer.drain(..len).collect());
+                }
+                Framing::ChunkSize => {
+                    let line = self.read_line().await?;
+                    let size = line.split(|b| *b == b';').next().unwrap_or(&[]);
+                    let size = std::str::from_utf8(size)
+                        .ok()
+                        .and_then(|s| u64::from_str_radix(s.trim(), 16).ok())
+                        .ok_or(HttpError::InvalidResponse)?;
+                    self.framing = if size == 0 {
+                        Framing::Trailers
+                    } else {
+                        Framing::ChunkData(size)
+                    };
+                }
+                Framing::ChunkData(remaining) => {
+                    let chunk = self.take_data(remaining).await?;
+                    let remaining = remaining - chunk.len() as u64;
+                    self.framing = if remaining == 0 {
+                        Framing::ChunkEnd
+                    } else {
+                        Framing::ChunkData(remaining)
+                    };
+                    return Ok(chunk);
+                }
+                Framing::ChunkEnd => {
+                    if !self.read_line().await?.is_empty() {
+                        return Err(HttpError::InvalidResponse);
+                    }
+                    self.framing = Framing::ChunkSize;
+                }
+                Framing::Trailers => {
+                    if self.read_line().await?.is_empty() {
+                        self.framing = Framing::Finished;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns between 1 and `max` bytes of the body. `max` must not be 0.
+    async fn take_data(&mut self, max: u64) -> Result<Vec<u8>, HttpError> {
+        debug_assert_ne!(max, 0);
+        if self.buffer.is_empty() && self.read_more().await? == 0 {
+            return Err(HttpError::InvalidResponse);
+        }
+
+        let len = cmp::min(
+            self.buffer.len() as u64,
+            cmp::min(max, MAX_CHUNK_LEN as u64),
+        );
+        Ok(self.buffer.drain(..len as usize).collect())
+    }
+
+    /// Reads a line terminated with `\r\n`, and returns it without the terminator.
+    async fn read_line(&mut self) -> Result<Vec<u8>, HttpError> {
+        loop {
+            if let Some(pos) = self.buffer.windows(2).position(|w| w == b"\r\n") {
+                let mut line = self.buffer.drain(..pos + 2).collect::<Vec<_>>();
+                line.truncate(pos);
+                return Ok(line);
+            }
+
+            if self.buffer.len() >= MAX_LINE_LEN || self.read_more().await? == 0 {
+                return Err(HttpError::InvalidResponse);
+            }
+        }
+    }
+
+    /// Reads more data from the server into the buffer. Returns 0 if the server has closed the
+    /// connection.
+    async fn read_more(&mut self) -> Result<usize, HttpError> {
+        let mut data = [0; 16 * 1024];
+        let len = self
+            .stream
+            .read(&mut data)
+            .await
+            .map_err(connection_error)?;
+        self.buffer.extend_from_slice(&data[..len]);
+        Ok(len)
+    }
+}
+
+/// Turns an error of the connection into an [`HttpError`].
+fn connection_error(err: io::Error) -> HttpError {
+    let err = match err.kind() {
+        io::ErrorKind::ConnectionRefused => TcpError::ConnectionRefused,
+        io::ErrorKind::ConnectionReset | io::ErrorKind::ConnectionAborted => {
+            TcpError::ConnectionReset
+        }
+        io::ErrorKind::TimedOut => TcpError::TimedOut,
+        io::ErrorKind::AddrInUse => TcpError::AddrInUse,
+        io::ErrorKind::PermissionDenied => TcpError::PermissionDenied,
+        io::ErrorKind::NotConnected => TcpError::InvalidSocket,
+        io::ErrorKind::WouldBlock => TcpError::WouldBlock,
+        _ => TcpError::Other(err.raw_os_error()),
+    };
+    HttpError::Connection(err)
+}
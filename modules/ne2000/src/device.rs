@@ -61,7 +61,7 @@ pub struct Device {
     pending_packet: Option<Vec<u8>>,
     /// Page that contains or will contain the next incoming Ethernet packet.
     next_to_read: u8,
-    /// MAC address of the device. // TODO: keep as a field? it isn't really useful
+    /// MAC address of the device.
     mac_address: [u8; 6],
 }
 
@@ -179,6 +179,11 @@ impl Device {
         }
     }
 
+    /// Returns the MAC address of the device.
+    pub fn mac_address(&self) -> [u8; 6] {
+        self.mac_address
+    }
+
     /// Reads one packet of incoming data from the device's buffer.
     ///
     /// Returns `None` if there's no packet available.
@@ -268,7 +273,7 @@ impl Device {
     ///
     /// Panics if the packet is too large.
     ///
-    unsafe fn send_packet(&mut self, packet: impl Into<Vec<u8>>) -> Result<(), ()> {
+    pub unsafe fn send_packet(&mut self, packet: impl Into<Vec<u8>>) -> Result<(), ()> {
         if self.pending_packet.is_some() {
             return Err(());
         }
@@ -281,6 +286,26 @@ impl Device {
         Ok(())
     }
 
+    /// Checks whether the device has finished transmitting out a packet, and if so starts
+    /// transmitting the next one.
+    // TODO: use interrupts instead
+    pub async unsafe fn poll_transmit(&mut self) {
+        if self.transmitting.is_none() {
+            return;
+        }
+
+        // Read the ISR (Interrupt Status Register). Bits 1 and 3 are set when the transmission is
+        // respectively successful or aborted. We only clear these two bits, as the others are
+        // unrelated to transmitting.
+        let status = redshirt_hardware_interface::port_read_u8(self.base_port + 7).await;
+        let transmit_bits = status & ((1 << 1) | (1 << 3));
+        if transmit_bits != 0 {
+            redshirt_hardware_interface::port_write_u8(self.base_port + 7, transmit_bits);
+            self.transmitting = None;
+            self.flush_out();
+        }
+    }
+
     /// Updates the state of the writing.
     unsafe fn flush_out(&mut self) {
         // Ask the device to transmit out more data, if some is ready.
@@ -16,7 +16,7 @@
 //! Driver for the ne2000 network card.
 //!
 //! This program scans the PCI space for the ne2000. If it finds it, it registers a new network
-//! interface towards the network manager, and handles the communication between the network
+//! device towards the network manager, and handles the communication between the network
 //! manager and the hardware.
 //!
 //! Bibliography:
@@ -28,7 +28,7 @@
 
 mod device;
 
-use std::convert::TryFrom as _;
+use futures::prelude::*;
 
 fn main() {
     redshirt_syscalls::block_on(async_main());
@@ -69,28 +69,35 @@ async fn async_main() {
         return;
     }
 
-    ne2k_devices.shrink_to_fit();
+    // TODO: support multiple devices
+    let mut device = ne2k_devices.remove(0);
+    let network_device =
+        redshirt_network_device_interface::NetworkDevice::new(device.mac_address());
+
+    let mut next_frame = Box::pin(network_device.next_frame_to_send());
+    // Frame to send that the device couldn't accept yet.
+    let mut frame_to_send = None;
 
     loop {
-        //redshirt_log_interface::log(redshirt_log_interface::Level::Info, "Polling");
-        let packet = match unsafe { ne2k_devices[0].read_one_incoming().await } {
-            Some(p) => p,
-            None => continue,
-        };
+        unsafe {
+            if let Some(frame) = device.read_one_incoming().await {
+                network_device.on_frame_received(frame);
+            }
+
+            device.poll_transmit().await;
+
+            if frame_to_send.is_none() {
+                if let Some(frame) = (&mut next_frame).now_or_never() {
+                    frame_to_send = Some(frame);
+                    next_frame = Box::pin(network_device.next_frame_to_send());
+                }
+            }
 
-        let (header, data) = etherparse::Ethernet2Header::read_from_slice(&packet).unwrap();
-        if header.ether_type == 0x86dd {
-            let (ip_header, ip_data) = etherparse::Ipv6Header::read_from_slice(&data).unwrap();
-            if ip_header.next_header == 0x11 {
-                let (udp_header, udp_data) =
-                    etherparse::UdpHeader::read_from_slice(&ip_data).unwrap();
-                redshirt_log_interface::log(
-                    redshirt_log_interface::Level::Info,
-                    &format!("Headers: {:?} {:?}", ip_header, udp_header),
-                );
+            if let Some(frame) = frame_to_send.take() {
+                if device.send_packet(&frame[..]).is_err() {
+                    frame_to_send = Some(frame);
+                }
             }
         }
-
-        //redshirt_log_interface::log(redshirt_log_interface::Level::Info, &format!("Header: {:?}", header));
     }
 }
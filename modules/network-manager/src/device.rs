@@ -0,0 +1,310 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Network device registered by a driver.
+//!
+//! Each device has its own smoltcp interface and set of sockets. The device itself is a queue of
+//! frames: frames received by the driver are pushed to it, and frames to send are held until the
+//! driver asks for them.
+
+use redshirt_syscalls::MessageId;
+use smoltcp::{
+    iface::{Config, Interface, SocketHandle, SocketSet},
+    phy::{self, DeviceCapabilities, Medium},
+    socket::{dhcpv4, tcp},
+    time::Instant,
+    wire::{EthernetAddress, HardwareAddress, IpCidr},
+};
+use std::collections::VecDeque;
+
+/// Maximum number of received frames waiting to be processed. Frames received above this limit
+/// are dropped.
+const MAX_RECEIVED_FRAMES: usize = 64;
+
+/// Maximum number of frames waiting to be sent. Frames sent above this limit are dropped.
+const MAX_FRAMES_TO_SEND: usize = 64;
+
+/// Maximum size of an Ethernet frame, without the checksum.
+const MTU: usize = 1514;
+
+pub struct Device {
+    /// State of the IP layer of the device.
+    interface: Interface,
+    /// Frames waiting to be processed or sent.
+    queues: Queues,
+    /// Sockets that use this device, including the DHCP one.
+    sockets: SocketSet<'static>,
+    /// Socket used to obtain an IPv4 address.
+    dhcp: SocketHandle,
+    /// TCP sockets that have been closed by their user and that are gracefully shutting down.
+    closing: Vec<SocketHandle>,
+    /// Messages of the driver asking for a frame to send, in the order in which they arrived.
+    wait_data: VecDeque<MessageId>,
+}
+
+/// Implementation of the smoltcp device.
+struct Queues {
+    /// If false, frames are neither received nor sent.
+    link_up: bool,
+    received: VecDeque<Vec<u8>>,
+    to_send: VecDeque<Vec<u8>>,
+}
+
+impl Device {
+    /// Initializes a new device. `mac_address` must be a unicast address.
+    pub fn new(mac_address: [u8; 6], random_seed: u64, now: Instant) -> Device {
+        let mut queues = Queues {
+            link_up: true,
+            received: VecDeque::new(),
+            to_send: VecDeque::new(),
+        };
+
+        let mut config = Config::new(HardwareAddress::Ethernet(EthernetAddress(mac_address)));
+        config.random_seed = random_seed;
+        let interface = Interface::new(config, &mut queues, now);
+
+        let mut sockets = SocketSet::new(Vec::new());
+        let dhcp = sockets.add(dhcpv4::Socket::new());
+
+        Device {
+            interface,
+            queues,
+            sockets,
+            dhcp,
+            closing: Vec::new(),
+            wait_data: VecDeque::new(),
+        }
+    }
+
+    /// Returns true if the device has an IPv4 address.
+    pub fn is_configured(&self) -> bool {
+        self.interface.ipv4_addr().is_some()
+    }
+
+    /// Returns true if `ip` is one of the addresses of the device.
+    pub fn has_ip_addr(&self, ip: smoltcp::wire::IpAddress) -> bool {
+        self.interface.has_ip_addr(ip)
+    }
+
+    /// Indicates whether the link of the device is up. When it goes down, the device loses its
+    /// address, and a new one is requested once it is back up.
+    pub fn set_link_up(&mut self, up: bool) {
+        if self.queues.link_up == up {
+            return;
+        }
+
+        self.queues.link_up = up;
+        if !up {
+            self.queues.received.clear();
+            self.queues.to_send.clear();
+            self.interface.update_ip_addrs(|addrs| addrs.clear());
+            self.interface.routes_mut().remove_default_ipv4_route();
+            self.sockets.get_mut::<dhcpv4::Socket>(self.dhcp).reset();
+        }
+    }
+
+    /// Adds a frame received by the driver.
+    pub fn inject_frame(&mut self, frame: Vec<u8>) {
+        if self.queues.link_up && self.queues.received.len() < MAX_RECEIVED_FRAMES {
+            self.queues.received.push_back(frame);
+        }
+    }
+
+    /// Registers a message of the driver asking for the next frame to send. The message is
+    /// answered by [`Device::poll`].
+    pub fn wait_data(&mut self, message_id: MessageId) {
+        self.wait_data.push_back(message_id);
+    }
+
+    /// Returns the list of messages of the driver waiting for a frame. Used when the device is
+    /// removed.
+    pub fn into_wait_data(self) -> VecDeque<MessageId> {
+        self.wait_data
+    }
+
+    /// Builds a new TCP socket and adds it to the device.
+    pub fn add_tcp_socket(&mut self, rx_buffer_len: usize, tx_buffer_len: usize) -> SocketHandle {
+        let socket = tcp::Socket::new(
+            tcp::SocketBuffer::new(vec![0; rx_buffer_len]),
+            tcp::SocketBuffer::new(vec![0; tx_buffer_len]),
+        );
+        self.sockets.add(socket)
+    }
+
+    /// Returns the TCP socket with the given handle, and the interface it must use.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the handle is invalid.
+    pub fn tcp_socket(
+        &mut self,
+        handle: SocketHandle,
+    ) -> (&mut tcp::Socket<'static>, &mut Interface) {
+        (self.sockets.get_mut(handle), &mut self.interface)
+    }
+
+    /// Removes a TCP socket from the device, without notifying the remote.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the handle is invalid.
+    pub fn remove_tcp_socket(&mut self, handle: SocketHandle) {
+        self.sockets.remove(handle);
+    }
+
+    /// Starts gracefully closing a TCP socket. The socket is removed from the device once
+    /// closed.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the handle is invalid.
+    pub fn close_tcp_socket(&mut self, handle: SocketHandle) {
+        self.sockets.get_mut::<tcp::Socket>(handle).close();
+        self.closing.push(handle);
+    }
+
+    /// Closes a TCP socket and sends a reset to the remote. The socket is removed from the
+    /// device once the reset has been sent.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the handle is invalid.
+    pub fn abort_tcp_socket(&mut self, handle: SocketHandle) {
+        self.sockets.get_mut::<tcp::Socket>(handle).abort();
+        self.closing.push(handle);
+    }
+
+    /// Processes the received frames and the state of the sockets, and answers the messages of
+    /// the driver waiting for a frame to send.
+    pub fn poll(&mut self, now: Instant) {
+        self.interface
+            .poll(now, &mut self.queues, &mut self.sockets);
+
+        match self.sockets.get_mut::<dhcpv4::Socket>(self.dhcp).poll() {
+            Some(dhcpv4::Event::Configured(config)) => {
+                self.interface.update_ip_addrs(|addrs| {
+                    addrs.clear();
+                    let _ = addrs.push(IpCidr::Ipv4(config.address));
+                });
+                let routes = self.interface.routes_mut();
+                routes.remove_default_ipv4_route();
+                if let Some(router) = config.router {
+                    let _ = routes.add_default_ipv4_route(router);
+                }
+                redshirt_log_interface::log(
+                    redshirt_log_interface::Level::Info,
+                    &format!("Obtained IPv4 address {}", config.address),
+                );
+            }
+            Some(dhcpv4::Event::Deconfigured) => {
+                self.interface.update_ip_addrs(|addrs| addrs.clear());
+                self.interface.routes_mut().remove_default_ipv4_route();
+            }
+            None => {}
+        }
+
+        let sockets = &mut self.sockets;
+        self.closing.retain(|handle| {
+            let state = sockets.get::<tcp::Socket>(*handle).state();
+            if state == tcp::State::Closed || state == tcp::State::TimeWait {
+                sockets.remove(*handle);
+                false
+            } else {
+                true
+            }
+        });
+
+        while !self.wait_data.is_empty() {
+            let frame = match self.queues.to_send.pop_front() {
+                Some(f) => f,
+                None => break,
+            };
+            let message_id = self.wait_data.pop_front().unwrap();
+            redshirt_syscalls::emit_answer(
+                message_id,
+                &redshirt_network_device_interface::ffi::InterfaceWaitDataResponse { frame },
+            );
+        }
+    }
+
+    /// Returns the moment when [`Device::poll`] must be called again at the latest, if nothing
+    /// else happens in the meanwhile.
+    pub fn poll_at(&mut self, now: Instant) -> Option<Instant> {
+        if !self.queues.received.is_empty() {
+            return Some(now);
+        }
+
+        self.interface.poll_at(now, &self.sockets)
+    }
+}
+
+impl phy::Device for Queues {
+    type RxToken<'a> = RxToken;
+    type TxToken<'a> = TxToken<'a>;
+
+    fn receive(&mut self, _: Instant) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
+        let frame = self.received.pop_front()?;
+        Some((
+            RxToken(frame),
+            TxToken {
+                to_send: &mut self.to_send,
+            },
+        ))
+    }
+
+    fn transmit(&mut self, _: Instant) -> Option<Self::TxToken<'_>> {
+        if !self.link_up || self.to_send.len() >= MAX_FRAMES_TO_SEND {
+            return None;
+        }
+
+        Some(TxToken {
+            to_send: &mut self.to_send,
+        })
+    }
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        let mut capabilities = DeviceCapabilities::default();
+        capabilities.medium = Medium::Ethernet;
+        capabilities.max_transmission_unit = MTU;
+        capabilities
+    }
+}
+
+struct RxToken(Vec<u8>);
+
+impl phy::RxToken for RxToken {
+    fn consume<R, F>(mut self, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        f(&mut self.0)
+    }
+}
+
+struct TxToken<'a> {
+    to_send: &'a mut VecDeque<Vec<u8>>,
+}
+
+impl<'a> phy::TxToken for TxToken<'a> {
+    fn consume<R, F>(self, len: usize, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        let mut frame = vec![0; len];
+        let result = f(&mut frame);
+        self.to_send.push_back(frame);
+        result
+    }
+}
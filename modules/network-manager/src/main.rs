@@ -0,0 +1,111 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Network stack.
+//!
+//! This program implements the TCP interface on top of the network devices registered by the
+//! drivers through the network-device interface, using the `smoltcp` library. It makes the TCP
+//! interface available when the kernel doesn't run on top of an operating system that provides
+//! it.
+//!
+//! Each device obtains an IPv4 address through DHCP. IPv6 isn't supported yet.
+
+use futures::prelude::*;
+use parity_scale_codec::DecodeAll;
+use redshirt_syscalls::DecodedInterfaceOrDestroyed;
+use std::convert::TryFrom as _;
+
+mod device;
+mod manager;
+
+fn main() {
+    redshirt_syscalls::block_on(async_main());
+}
+
+async fn async_main() {
+    redshirt_interface_interface::register_interface(
+        redshirt_network_device_interface::ffi::INTERFACE,
+    )
+    .await
+    .unwrap();
+    redshirt_interface_interface::register_interface(redshirt_tcp_interface::ffi::INTERFACE)
+        .await
+        .unwrap();
+
+    let random_seed = {
+        let mut bytes = [0; 8];
+        redshirt_random_interface::generate_in(&mut bytes).await;
+        u64::from_le_bytes(bytes)
+    };
+
+    let mut manager = manager::NetworkManager::new(random_seed);
+    let mut next_message = None;
+
+    loop {
+        let now = now().await;
+
+        match next_message.take() {
+            Some(DecodedInterfaceOrDestroyed::Interface(msg))
+                if msg.interface == redshirt_network_device_interface::ffi::INTERFACE =>
+            {
+                match DecodeAll::decode_all(&msg.actual_data.0) {
+                    Ok(m) => manager.device_message(msg.emitter_pid, msg.message_id, m, now),
+                    Err(_) => {
+                        if let Some(message_id) = msg.message_id {
+                            redshirt_syscalls::emit_message_error(message_id);
+                        }
+                    }
+                }
+            }
+            Some(DecodedInterfaceOrDestroyed::Interface(msg)) => {
+                debug_assert_eq!(msg.interface, redshirt_tcp_interface::ffi::INTERFACE);
+                match DecodeAll::decode_all(&msg.actual_data.0) {
+                    Ok(m) => manager.tcp_message(msg.emitter_pid, msg.message_id, m, now),
+                    Err(_) => {
+                        if let Some(message_id) = msg.message_id {
+                            redshirt_syscalls::emit_message_error(message_id);
+                        }
+                    }
+                }
+            }
+            Some(DecodedInterfaceOrDestroyed::ProcessDestroyed(destroyed)) => {
+                manager.process_destroyed(destroyed.pid);
+            }
+            None => {}
+        }
+
+        manager.poll(now);
+
+        let interface_message = redshirt_syscalls::next_interface_message();
+        next_message = match manager.poll_at(now) {
+            Some(when) => {
+                let nanos = u128::try_from(when.total_micros()).unwrap_or(0) * 1000;
+                let timer = redshirt_time_interface::monotonic_wait_until(nanos);
+                futures::pin_mut!(timer);
+                match future::select(interface_message, timer).await {
+                    future::Either::Left((msg, _)) => Some(msg),
+                    future::Either::Right(((), _)) => None,
+                }
+            }
+            None => Some(interface_message.await),
+        };
+    }
+}
+
+/// Returns the current value of the monotonic clock, in the format of smoltcp.
+async fn now() -> smoltcp::time::Instant {
+    let nanos = redshirt_time_interface::monotonic_clock().await;
+    smoltcp::time::Instant::from_micros(i64::try_from(nanos / 1000).unwrap_or(i64::max_value()))
+}
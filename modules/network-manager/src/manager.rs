@@ -0,0 +1,663 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! State of the network stack.
+//!
+//! The [`NetworkManager`] holds the devices registered by the drivers and the TCP sockets opened
+//! by the other programs. Messages are passed to it as they arrive, and [`NetworkManager::poll`]
+//! must be called afterwards, and whenever the moment returned by [`NetworkManager::poll_at`] is
+//! reached, in order to make progress and send the answers.
+
+use crate::device::Device;
+
+use redshirt_network_device_interface::ffi as device_ffi;
+use redshirt_syscalls::{Handle, MessageId, Pid};
+use redshirt_tcp_interface::ffi as tcp_ffi;
+use smoltcp::{
+    iface::SocketHandle,
+    socket::tcp,
+    time::{Duration, Instant},
+    wire::{IpAddress, IpEndpoint, IpListenEndpoint, Ipv4Address},
+};
+use std::{
+    collections::{HashMap, VecDeque},
+    convert::TryFrom as _,
+    net::{Ipv4Addr, Ipv6Addr},
+};
+
+/// Size of the receive and send buffers of each TCP socket.
+const TCP_BUFFER_LEN: usize = 64 * 1024;
+
+/// Maximum number of bytes that a single read can return.
+const MAX_READ_LEN: usize = 64 * 1024;
+
+/// Maximum number of bytes waiting to be written on a socket. Writes above this limit are
+/// refused, unless nothing is waiting.
+const MAX_QUEUED_WRITE_LEN: usize = 256 * 1024;
+
+/// First port used for outgoing connections.
+const FIRST_EPHEMERAL_PORT: u16 = 49152;
+
+pub struct NetworkManager {
+    /// Devices registered by the drivers, indexed by the driver and by the id that the driver
+    /// has chosen.
+    devices: HashMap<(Pid, u64), Device>,
+    /// TCP sockets, indexed by the identifier used in the messages of the TCP interface.
+    sockets: HashMap<Handle, Socket>,
+    /// Identifier of the next socket to open. Identifiers are never reused, so that a process
+    /// can't accidentally use a socket that has replaced one it has closed.
+    next_socket_id: u64,
+    /// Local port to try next when connecting.
+    next_ephemeral_port: u16,
+    /// Seed passed to smoltcp for the next device.
+    random_seed: u64,
+}
+
+struct Socket {
+    /// Process that has opened the socket. Only this process can use it.
+    owner: Pid,
+    /// Device the socket belongs to.
+    device: (Pid, u64),
+    /// Handle of the socket within the device.
+    handle: SocketHandle,
+    state: SocketState,
+    /// Maximum duration of the reads. Applies to reads started after it has been set.
+    read_timeout: Option<Duration>,
+    /// Maximum duration of the writes. Applies to writes started after it has been set.
+    write_timeout: Option<Duration>,
+    /// Reads waiting for data, with the maximum length and the moment when they time out.
+    queued_reads: VecDeque<(MessageId, usize, Option<Instant>)>,
+    /// Writes waiting for space in the send buffer, with the moment when they time out.
+    queued_writes: VecDeque<(MessageId, Vec<u8>, Option<Instant>)>,
+    /// Number of bytes of the first entry of `queued_writes` that are already in the send buffer.
+    written: usize,
+}
+
+enum SocketState {
+    /// Connecting to a remote. The open message is answered once connected.
+    Connecting {
+        message_id: MessageId,
+        deadline: Option<Instant>,
+    },
+    /// Waiting for a remote to connect. The open message is answered once connected.
+    Listening { message_id: MessageId },
+    /// The open message has been answered.
+    Open,
+}
+
+impl NetworkManager {
+    /// Initializes a new manager, without any device.
+    pub fn new(random_seed: u64) -> Self {
+        NetworkManager {
+            devices: HashMap::new(),
+            sockets: HashMap::new(),
+            next_socket_id: 0,
+            next_ephemeral_port: FIRST_EPHEMERAL_PORT,
+            random_seed,
+        }
+    }
+
+    /// Processes a message of the network-device interface.
+    pub fn device_message(
+        &mut self,
+        emitter: Pid,
+        message_id: Option<MessageId>,
+        message: device_ffi::NetworkMessage,
+        now: Instant,
+    ) {
+        match message {
+            device_ffi::NetworkMessage::RegisterInterface { id, mac_address } => {
+                // Multicast addresses can't be used as the address of a device.
+                if mac_address[0] & 0x1 != 0 {
+                    return;
+                }
+                self.random_seed = self.random_seed.wrapping_add(1);
+                let device = Device::new(mac_address, self.random_seed, now);
+                self.devices.insert((emitter, id), device);
+            }
+            device_ffi::NetworkMessage::UnregisterInterface { id } => {
+                self.remove_device((emitter, id));
+            }
+            device_ffi::NetworkMessage::SetLinkStatus { id, up } => {
+                if let Some(device) = self.devices.get_mut(&(emitter, id)) {
+                    device.set_link_up(up);
+                }
+            }
+            device_ffi::NetworkMessage::InterfaceOnData { id, frame } => {
+                if let Some(device) = self.devices.get_mut(&(emitter, id)) {
+                    device.inject_frame(frame);
+                }
+            }
+            device_ffi::NetworkMessage::InterfaceWaitData { id } => {
+                let message_id = match message_id {
+                    Some(m) => m,
+                    None => return,
+                };
+                match self.devices.get_mut(&(emitter, id)) {
+                    Some(device) => device.wait_data(message_id),
+                    None => redshirt_syscalls::emit_message_error(message_id),
+                }
+            }
+        }
+    }
+
+    /// Processes a message of the TCP interface.
+    pub fn tcp_message(
+        &mut self,
+        emitter: Pid,
+        message_id: Option<MessageId>,
+        message: tcp_ffi::TcpMessage,
+        now: Instant,
+    ) {
+        // Closing is the only message that doesn't expect an answer.
+        if let tcp_ffi::TcpMessage::Close(close) = &message {
+            if self.sockets.get(&close.socket_id).map(|s| s.owner) == Some(emitter) {
+                let socket = self.sockets.remove(&close.socket_id).unwrap();
+                if let Some(device) = self.devices.get_mut(&socket.device) {
+                    device.close_tcp_socket(socket.handle);
+                }
+            }
+            return;
+        }
+
+        let message_id = match message_id {
+            Some(m) => m,
+            None => return,
+        };
+
+        match message {
+            tcp_ffi::TcpMessage::Open(open) => self.open(emitter, message_id, open, now),
+            tcp_ffi::TcpMessage::Close(_) => unreachable!(),
+            tcp_ffi::TcpMessage::Read(read) => {
+                let socket = match self.open_socket(emitter, read.socket_id) {
+                    Some(s) => s,
+                    None => {
+                        let response = tcp_ffi::TcpReadResponse {
+                            result: Err(tcp_ffi::TcpError::InvalidSocket),
+                        };
+                        redshirt_syscalls::emit_answer(message_id, &response);
+                        return;
+                    }
+                };
+
+                let max_len = usize::try_from(read.max_len)
+                    .unwrap_or(usize::max_value())
+                    .max(1)
+                    .min(MAX_READ_LEN);
+                let deadline = socket.read_timeout.map(|t| now + t);
+                socket
+                    .queued_reads
+                    .push_back((message_id, max_len, deadline));
+            }
+            tcp_ffi::TcpMessage::Write(write) => {
+                let socket = match self.open_socket(emitter, write.socket_id) {
+                    Some(s) => s,
+                    None => {
+                        let response = tcp_ffi::TcpWriteResponse {
+                            result: Err(tcp_ffi::TcpError::InvalidSocket),
+                        };
+                        redshirt_syscalls::emit_answer(message_id, &response);
+                        return;
+                    }
+                };
+
+                let queued_len = socket
+                    .queued_writes
+                    .iter()
+                    .fold(0, |len, (_, data, _)| len + data.len())
+                    - socket.written;
+                if queued_len != 0 && queued_len + write.data.len() > MAX_QUEUED_WRITE_LEN {
+                    let response = tcp_ffi::TcpWriteResponse {
+                        result: Err(tcp_ffi::TcpError::WouldBlock),
+                    };
+                    redshirt_syscalls::emit_answer(message_id, &response);
+                    return;
+                }
+
+                let deadline = socket.write_timeout.map(|t| now + t);
+                socket
+                    .queued_writes
+                    .push_back((message_id, write.data, deadline));
+            }
+            tcp_ffi::TcpMessage::SetOption(set_option) => {
+                let result = self.set_option(emitter, set_option);
+                let response = tcp_ffi::TcpSetOptionResponse { result };
+                redshirt_syscalls::emit_answer(message_id, &response);
+            }
+            tcp_ffi::TcpMessage::SocketInfo(info) => {
+                let result = match self.open_socket(emitter, info.socket_id) {
+                    Some(socket) => {
+                        let (handle, device) = (socket.handle, socket.device);
+                        let (tcp_socket, _) =
+                            self.devices.get_mut(&device).unwrap().tcp_socket(handle);
+                        let local = tcp_socket.local_endpoint();
+                        let remote = tcp_socket.remote_endpoint();
+                        Ok(tcp_ffi::TcpSocketDetails {
+                            local_ip: local.map_or([0; 8], |e| ip_segments(e.addr)),
+                            local_port: local.map_or(0, |e| e.port),
+                            remote_ip: remote.map_or([0; 8], |e| ip_segments(e.addr)),
+                            remote_port: remote.map_or(0, |e| e.port),
+                            state: if tcp_socket.may_recv() {
+                                tcp_ffi::TcpConnectionState::Connected
+                            } else {
+                                tcp_ffi::TcpConnectionState::Closed
+                            },
+                        })
+                    }
+                    None => Err(tcp_ffi::TcpError::InvalidSocket),
+                };
+
+                let response = tcp_ffi::TcpSocketInfoResponse { result };
+                redshirt_syscalls::emit_answer(message_id, &response);
+            }
+        }
+    }
+
+    /// Removes all the devices and sockets that belong to the given process.
+    pub fn process_destroyed(&mut self, pid: Pid) {
+        let devices = self
+            .devices
+            .keys()
+            .filter(|(driver, _)| *driver == pid)
+            .cloned()
+            .collect::<Vec<_>>();
+        for device in devices {
+            self.remove_device(device);
+        }
+
+        let devices = &mut self.devices;
+        self.sockets.retain(|_, socket| {
+            if socket.owner != pid {
+                return true;
+            }
+            if let Some(device) = devices.get_mut(&socket.device) {
+                device.abort_tcp_socket(socket.handle);
+            }
+            false
+        });
+    }
+
+    /// Makes progress on all the devices and sockets, and sends the answers to the messages that
+    /// are ready.
+    pub fn poll(&mut self, now: Instant) {
+        for device in self.devices.values_mut() {
+            device.poll(now);
+        }
+
+        let devices = &mut self.devices;
+        self.sockets.retain(|socket_id, socket| {
+            let device = devices.get_mut(&socket.device).unwrap();
+            update_socket(*socket_id, socket, device, now)
+        });
+
+        // Polling again in order to immediately send the data that has been written to the
+        // sockets.
+        for device in self.devices.values_mut() {
+            device.poll(now);
+        }
+    }
+
+    /// Returns the moment when [`NetworkManager::poll`] must be called again at the latest, if
+    /// no message arrives in the meanwhile.
+    pub fn poll_at(&mut self, now: Instant) -> Option<Instant> {
+        let devices = self.devices.values_mut().filter_map(|d| d.poll_at(now));
+        let deadlines = self.sockets.values().flat_map(|socket| {
+            let connect = match socket.state {
+                SocketState::Connecting { deadline, .. } => deadline,
+                _ => None,
+            };
+            let read = socket.queued_reads.front().and_then(|(_, _, d)| *d);
+            let write = socket.queued_writes.front().and_then(|(_, _, d)| *d);
+            connect.into_iter().chain(read).chain(write)
+        });
+        devices.chain(deadlines).min()
+    }
+
+    /// Processes an open message.
+    fn open(&mut self, emitter: Pid, message_id: MessageId, open: tcp_ffi::TcpOpen, now: Instant) {
+        let ip = Ipv6Addr::from(open.ip);
+
+        let result = if open.listen {
+            // An unspecified address means listening on any device.
+            let ip = if ip.is_unspecified() {
+                None
+            } else {
+                match ip.to_ipv4_mapped() {
+                    Some(ip) => Some(ipv4_address(ip)),
+                    None => {
+                        answer_open_err(message_id, tcp_ffi::TcpError::Unreachable);
+                        return;
+                    }
+                }
+            };
+
+            self.listen(ip, open.port)
+                .map(|(device, handle)| (device, handle, SocketState::Listening { message_id }))
+        } else {
+            let ip = match ip.to_ipv4_mapped() {
+                Some(ip) => ipv4_address(ip),
+                None => {
+                    answer_open_err(message_id, tcp_ffi::TcpError::Unreachable);
+                    return;
+                }
+            };
+
+            let deadline = open.timeout_ms.map(|ms| now + Duration::from_millis(ms));
+            self.connect(IpEndpoint::new(ip, open.port))
+                .map(|(device, handle)| {
+                    (
+                        device,
+                        handle,
+                        SocketState::Connecting {
+                            message_id,
+                            deadline,
+                        },
+                    )
+                })
+        };
+
+        let (device, handle, state) = match result {
+            Ok(v) => v,
+            Err(err) => {
+                answer_open_err(message_id, err);
+                return;
+            }
+        };
+
+        let socket_id = Handle::from(self.next_socket_id);
+        self.next_socket_id += 1;
+
+        self.sockets.insert(
+            socket_id,
+            Socket {
+                owner: emitter,
+                device,
+                handle,
+                state,
+                read_timeout: None,
+                write_timeout: None,
+                queued_reads: VecDeque::new(),
+                queued_writes: VecDeque::new(),
+                written: 0,
+            },
+        );
+    }
+
+    /// Creates a socket that connects to the given remote, using the first device that has an
+    /// address.
+    fn connect(
+        &mut self,
+        remote: IpEndpoint,
+    ) -> Result<((Pid, u64), SocketHandle), tcp_ffi::TcpError> {
+        // TODO: choose the device according to the routes instead
+        let (device_key, device) = self
+            .devices
+            .iter_mut()
+            .find(|(_, device)| device.is_configured())
+            .ok_or(tcp_ffi::TcpError::Unreachable)?;
+
+        let local_port = self.next_ephemeral_port;
+        self.next_ephemeral_port = self
+            .next_ephemeral_port
+            .checked_add(1)
+            .unwrap_or(FIRST_EPHEMERAL_PORT);
+
+        let handle = device.add_tcp_socket(TCP_BUFFER_LEN, TCP_BUFFER_LEN);
+        let (socket, interface) = device.tcp_socket(handle);
+        if socket
+            .connect(interface.context(), remote, local_port)
+            .is_err()
+        {
+            device.remove_tcp_socket(handle);
+            return Err(tcp_ffi::TcpError::Unreachable);
+        }
+
+        Ok((*device_key, handle))
+    }
+
+    /// Creates a socket that waits for a remote to connect to the given port. If `ip` is `None`,
+    /// any device can be used.
+    fn listen(
+        &mut self,
+        ip: Option<IpAddress>,
+        port: u16,
+    ) -> Result<((Pid, u64), SocketHandle), tcp_ffi::TcpError> {
+        // TODO: listen on all the devices if `ip` is `None`
+        let (device_key, device) = self
+            .devices
+            .iter_mut()
+            .find(|(_, device)| ip.map_or(true, |ip| device.has_ip_addr(ip)))
+            .ok_or(tcp_ffi::TcpError::Other(None))?;
+
+        let handle = device.add_tcp_socket(TCP_BUFFER_LEN, TCP_BUFFER_LEN);
+        let (socket, _) = device.tcp_socket(handle);
+        if socket.listen(IpListenEndpoint { addr: ip, port }).is_err() {
+            device.remove_tcp_socket(handle);
+            return Err(tcp_ffi::TcpError::Other(None));
+        }
+
+        Ok((*device_key, handle))
+    }
+
+    /// Processes a set option message.
+    fn set_option(
+        &mut self,
+        emitter: Pid,
+        set_option: tcp_ffi::TcpSetOption,
+    ) -> Result<(), tcp_ffi::TcpError> {
+        let socket = self
+            .open_socket(emitter, set_option.socket_id)
+            .ok_or(tcp_ffi::TcpError::InvalidSocket)?;
+
+        match set_option.option {
+            tcp_ffi::TcpOption::ReadTimeout(ms) => {
+                socket.read_timeout = ms.map(Duration::from_millis);
+                return Ok(());
+            }
+            tcp_ffi::TcpOption::WriteTimeout(ms) => {
+                socket.write_timeout = ms.map(Duration::from_millis);
+                return Ok(());
+            }
+            _ => {}
+        }
+
+        let (handle, device) = (socket.handle, socket.device);
+        let (tcp_socket, _) = self.devices.get_mut(&device).unwrap().tcp_socket(handle);
+        match set_option.option {
+            tcp_ffi::TcpOption::NoDelay(no_delay) => tcp_socket.set_nagle_enabled(!no_delay),
+            tcp_ffi::TcpOption::KeepAlive(secs) => {
+                tcp_socket.set_keep_alive(secs.map(|s| Duration::from_secs(u64::from(s))))
+            }
+            tcp_ffi::TcpOption::Ttl(ttl) => match u8::try_from(ttl) {
+                Ok(ttl) if ttl != 0 => tcp_socket.set_hop_limit(Some(ttl)),
+                _ => return Err(tcp_ffi::TcpError::Other(None)),
+            },
+            tcp_ffi::TcpOption::ReadTimeout(_) | tcp_ffi::TcpOption::WriteTimeout(_) => {
+                unreachable!()
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the socket with the given identifier if it is open and belongs to `emitter`.
+    fn open_socket(&mut self, emitter: Pid, socket_id: Handle) -> Option<&mut Socket> {
+        match self.sockets.get_mut(&socket_id) {
+            Some(socket) if socket.owner == emitter => match socket.state {
+                SocketState::Open => Some(socket),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Removes a device, and fails all the operations on the sockets that use it.
+    fn remove_device(&mut self, key: (Pid, u64)) {
+        let device = match self.devices.remove(&key) {
+            Some(d) => d,
+            None => return,
+        };
+
+        for message_id in device.into_wait_data() {
+            redshirt_syscalls::emit_message_error(message_id);
+        }
+
+        self.sockets.retain(|_, socket| {
+            if socket.device != key {
+                return true;
+            }
+
+            match socket.state {
+                SocketState::Connecting { message_id, .. }
+                | SocketState::Listening { message_id } => {
+                    answer_open_err(message_id, tcp_ffi::TcpError::Unreachable)
+                }
+                SocketState::Open => {}
+            }
+            for (message_id, _, _) in socket.queued_reads.drain(..) {
+                let response = tcp_ffi::TcpReadResponse {
+                    result: Err(tcp_ffi::TcpError::ConnectionReset),
+                };
+                redshirt_syscalls::emit_answer(message_id, &response);
+            }
+            for (message_id, _, _) in socket.queued_writes.drain(..) {
+                let response = tcp_ffi::TcpWriteResponse {
+                    result: Err(tcp_ffi::TcpError::ConnectionReset),
+                };
+                redshirt_syscalls::emit_answer(message_id, &response);
+            }
+            false
+        });
+    }
+}
+
+/// Answers the pending messages of a socket that are ready. Returns false if the socket must be
+/// removed.
+fn update_socket(
+    socket_id: Handle,
+    socket: &mut Socket,
+    device: &mut Device,
+    now: Instant,
+) -> bool {
+    let (tcp_socket, _) = device.tcp_socket(socket.handle);
+
+    match socket.state {
+        SocketState::Connecting {
+            message_id,
+            deadline,
+        } => match tcp_socket.state() {
+            tcp::State::SynSent | tcp::State::SynReceived => {
+                if deadline.map_or(false, |d| d <= now) {
+                    device.abort_tcp_socket(socket.handle);
+                    answer_open_err(message_id, tcp_ffi::TcpError::TimedOut);
+                    return false;
+                }
+                return true;
+            }
+            tcp::State::Closed => {
+                device.remove_tcp_socket(socket.handle);
+                answer_open_err(message_id, tcp_ffi::TcpError::ConnectionRefused);
+                return false;
+            }
+            _ => {
+                answer_open_ok(socket_id, message_id, tcp_socket);
+                socket.state = SocketState::Open;
+            }
+        },
+        SocketState::Listening { message_id } => match tcp_socket.state() {
+            tcp::State::Listen | tcp::State::SynReceived => return true,
+            _ => {
+                answer_open_ok(socket_id, message_id, tcp_socket);
+                socket.state = SocketState::Open;
+            }
+        },
+        SocketState::Open => {}
+    }
+
+    while let Some((message_id, max_len, deadline)) = socket.queued_reads.front() {
+        let result = if tcp_socket.can_recv() {
+            let mut data = vec![0; *max_len];
+            let len = tcp_socket.recv_slice(&mut data).unwrap_or(0);
+            data.truncate(len);
+            Ok(data)
+        } else if !tcp_socket.may_recv() {
+            // The remote has closed its side of the connection, or the connection has failed.
+            Ok(Vec::new())
+        } else if deadline.map_or(false, |d| d <= now) {
+            Err(tcp_ffi::TcpError::TimedOut)
+        } else {
+            break;
+        };
+
+        let response = tcp_ffi::TcpReadResponse { result };
+        redshirt_syscalls::emit_answer(*message_id, &response);
+        socket.queued_reads.pop_front();
+    }
+
+    while let Some((message_id, data, deadline)) = socket.queued_writes.front() {
+        let result = if !tcp_socket.may_send() {
+            Err(tcp_ffi::TcpError::ConnectionReset)
+        } else {
+            socket.written += tcp_socket.send_slice(&data[socket.written..]).unwrap_or(0);
+            if socket.written == data.len() {
+                Ok(())
+            } else if deadline.map_or(false, |d| d <= now) {
+                Err(tcp_ffi::TcpError::TimedOut)
+            } else {
+                break;
+            }
+        };
+
+        let response = tcp_ffi::TcpWriteResponse { result };
+        redshirt_syscalls::emit_answer(*message_id, &response);
+        socket.queued_writes.pop_front();
+        socket.written = 0;
+    }
+
+    true
+}
+
+/// Answers an open message with the addresses of the socket.
+fn answer_open_ok(socket_id: Handle, message_id: MessageId, socket: &tcp::Socket) {
+    let local = socket.local_endpoint();
+    let remote = socket.remote_endpoint();
+    let response = tcp_ffi::TcpOpenResponse {
+        result: Ok(tcp_ffi::TcpSocketOpen {
+            socket_id,
+            local_ip: local.map_or([0; 8], |e| ip_segments(e.addr)),
+            local_port: local.map_or(0, |e| e.port),
+            remote_ip: remote.map_or([0; 8], |e| ip_segments(e.addr)),
+            remote_port: remote.map_or(0, |e| e.port),
+        }),
+    };
+    redshirt_syscalls::emit_answer(message_id, &response);
+}
+
+/// Answers an open message with an error.
+fn answer_open_err(message_id: MessageId, error: tcp_ffi::TcpError) {
+    let response = tcp_ffi::TcpOpenResponse { result: Err(error) };
+    redshirt_syscalls::emit_answer(message_id, &response);
+}
+
+fn ipv4_address(ip: Ipv4Addr) -> IpAddress {
+    IpAddress::Ipv4(Ipv4Address(ip.octets()))
+}
+
+/// Turns an IP address into the format of the interface.
+fn ip_segments(ip: IpAddress) -> [u16; 8] {
+    match ip {
+        IpAddress::Ipv4(ip) => Ipv4Addr::from(ip.0).to_ipv6_mapped().segments(),
+    }
+}
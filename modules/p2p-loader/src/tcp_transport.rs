@@ -109,7 +109,7 @@ impl Transport for TcpConfig {
         Ok(Box::pin(async move {
             redshirt_tcp_interface::TcpStream::connect(&socket_addr)
                 .await
-                .map_err(|()| io::Error::from(io::ErrorKind::Other))
+                .map_err(io::Error::from)
         }))
     }
 }
@@ -0,0 +1,147 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Encoding and decoding of WebSocket frames, as defined in RFC 6455.
+
+use std::convert::TryFrom as _;
+
+pub const OPCODE_CONTINUATION: u8 = 0x0;
+pub const OPCODE_TEXT: u8 = 0x1;
+pub const OPCODE_BINARY: u8 = 0x2;
+pub const OPCODE_CLOSE: u8 = 0x8;
+pub const OPCODE_PING: u8 = 0x9;
+pub const OPCODE_PONG: u8 = 0xa;
+
+/// Frame sent by the server.
+#[derive(Debug)]
+pub struct Frame {
+    /// True if this is the last frame of a message.
+    pub fin: bool,
+    pub opcode: u8,
+    pub payload: Vec<u8>,
+}
+
+/// Error while decoding a frame.
+#[derive(Debug)]
+pub struct InvalidFrame;
+
+/// Tries to decode a frame at the start of `buffer`, and removes it from the buffer if
+/// successful.
+///
+/// Returns `Ok(None)` if the buffer doesn't contain an entire frame yet. Frames whose payload is
+/// larger than `max_payload` are refused.
+pub fn decode(buffer: &mut Vec<u8>, max_payload: usize) -> Result<Option<Frame>, InvalidFrame> {
+    if buffer.len() < 2 {
+        return Ok(None);
+    }
+
+    let fin = (buffer[0] & 0x80) != 0;
+    let opcode = buffer[0] & 0xf;
+    // Reserved bits must be 0, as no extension is negotiated.
+    if (buffer[0] & 0x70) != 0 {
+        return Err(InvalidFrame);
+    }
+    // Frames sent by a server are never masked.
+    if (buffer[1] & 0x80) != 0 {
+        return Err(InvalidFrame);
+    }
+
+    let (header_len, payload_len) = match buffer[1] & 0x7f {
+        126 => {
+            if buffer.len() < 4 {
+                return Ok(None);
+            }
+            (4, u64::from(u16::from_be_bytes([buffer[2], buffer[3]])))
+        }
+        127 => {
+            if buffer.len() < 10 {
+                return Ok(None);
+            }
+            let mut len = [0; 8];
+            len.copy_from_slice(&buffer[2..10]);
+            (10, u64::from_be_bytes(len))
+        }
+        len => (2, u64::from(len)),
+    };
+
+    let is_control = (opcode & 0x8) != 0;
+    if is_control && (!fin || payload_len > 125) {
+        return Err(InvalidFrame);
+    }
+
+    let payload_len = match usize::try_from(payload_len) {
+        Ok(len) if len <= max_payload => len,
+        _ => return Err(InvalidFrame),
+    };
+
+    if buffer.len() < header_len + payload_len {
+        return Ok(None);
+    }
+
+    let payload = buffer[header_len..header_len + payload_len].to_vec();
+    buffer.drain(..header_len + payload_len);
+    Ok(Some(Frame {
+        fin,
+        opcode,
+        payload,
+    }))
+}
+
+/// Encodes a frame sent by the client, marked as being the last frame of its message.
+///
+/// The payload is masked with `mask`, which must be unpredictable by the server.
+pub fn encode(opcode: u8, payload: &[u8], mask: [u8; 4]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(payload.len() + 14);
+    out.push(0x80 | opcode);
+
+    if payload.len() < 126 {
+        out.push(0x80 | payload.len() as u8);
+    } else if let Ok(len) = u16::try_from(payload.len()) {
+        out.push(0x80 | 126);
+        out.extend_from_slice(&len.to_be_bytes());
+    } else {
+        out.push(0x80 | 127);
+        out.extend_from_slice(&(payload.len() as u64).to_be_bytes());
+    }
+
+    out.extend_from_slice(&mask);
+    out.extend(
+        payload
+            .iter()
+            .zip(mask.iter().cycle())
+            .map(|(byte, mask)| byte ^ mask),
+    );
+    out
+}
+
+/// Builds the payload of a close frame.
+pub fn close_payload(code: u16, reason: &str) -> Vec<u8> {
+    let mut payload = code.to_be_bytes().to_vec();
+    payload.extend_from_slice(reason.as_bytes());
+    payload
+}
+
+/// Decodes the payload of a close frame into a status code and a reason.
+pub fn decode_close_payload(payload: &[u8]) -> Result<(Option<u16>, String), InvalidFrame> {
+    match payload {
+        [] => Ok((None, String::new())),
+        [_] => Err(InvalidFrame),
+        [code0, code1, reason @ ..] => {
+            let code = u16::from_be_bytes([*code0, *code1]);
+            let reason = String::from_utf8(reason.to_vec()).map_err(|_| InvalidFrame)?;
+            Ok((Some(code), reason))
+        }
+    }
+}
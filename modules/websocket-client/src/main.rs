@@ -0,0 +1,132 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Implements the WebSocket interface.
+//!
+//! Each socket is a connection opened through the TCP interface, and the TLS interface for `wss`
+//! URLs, on top of which the WebSocket protocol is implemented.
+
+use futures::{channel::mpsc, prelude::*, stream::FuturesUnordered};
+use parity_scale_codec::DecodeAll;
+use redshirt_syscalls::{DecodedInterfaceOrDestroyed, Pid};
+use redshirt_websocket_interface::ffi;
+use std::{collections::HashMap, pin::Pin};
+
+mod frame;
+mod socket;
+
+fn main() {
+    redshirt_syscalls::block_on(async_main());
+}
+
+async fn async_main() {
+    redshirt_interface_interface::register_interface(ffi::INTERFACE)
+        .await
+        .unwrap();
+
+    // Sockets being handled. Each future yields the identifier of its socket when finished.
+    let mut tasks = FuturesUnordered::<Pin<Box<dyn Future<Output = u32>>>>::new();
+    // For each socket, the process that has opened it and a channel where to send the messages
+    // concerning it.
+    let mut sockets = HashMap::<u32, (Pid, mpsc::UnboundedSender<socket::Command>)>::new();
+    let mut next_socket_id: u32 = 0;
+
+    loop {
+        let event = {
+            let next_message = redshirt_syscalls::next_interface_message();
+            let next_finished = async {
+                if tasks.is_empty() {
+                    future::pending().await
+                } else {
+                    tasks.next().await.unwrap()
+                }
+            };
+            futures::pin_mut!(next_finished);
+            match future::select(next_message, next_finished).await {
+                future::Either::Left((msg, _)) => future::Either::Left(msg),
+                future::Either::Right((socket_id, _)) => future::Either::Right(socket_id),
+            }
+        };
+
+        let msg = match event {
+            future::Either::Left(DecodedInterfaceOrDestroyed::Interface(msg)) => msg,
+            future::Either::Left(DecodedInterfaceOrDestroyed::ProcessDestroyed(destroyed)) => {
+                // Dropping the channels makes the tasks close their connection.
+                sockets.retain(|_, (pid, _)| *pid != destroyed.pid);
+                continue;
+            }
+            future::Either::Right(socket_id) => {
+                // The identifier might have been reused in the meantime, in which case the
+                // channel belongs to the new socket and is still open.
+                if sockets
+                    .get(&socket_id)
+                    .map_or(false, |(_, tx)| tx.is_closed())
+                {
+                    sockets.remove(&socket_id);
+                }
+                continue;
+            }
+        };
+
+        let decoded = match ffi::WebSocketMessage::decode_all(&msg.actual_data.0) {
+            Ok(m) => m,
+            Err(_) => {
+                if let Some(message_id) = msg.message_id {
+                    redshirt_syscalls::emit_message_error(message_id);
+                }
+                continue;
+            }
+        };
+
+        let (socket_id, command) = match (decoded, msg.message_id) {
+            (ffi::WebSocketMessage::Connect(connect), Some(message_id)) => {
+                let socket_id = loop {
+                    let id = next_socket_id;
+                    next_socket_id = next_socket_id.wrapping_add(1);
+                    if !sockets.contains_key(&id) {
+                        break id;
+                    }
+                };
+
+                let (tx, rx) = mpsc::unbounded();
+                sockets.insert(socket_id, (msg.emitter_pid, tx));
+                tasks.push(Box::pin(
+                    socket::run(message_id, connect, socket_id, rx).map(move |()| socket_id),
+                ));
+                continue;
+            }
+            (ffi::WebSocketMessage::Send(send), Some(message_id)) => {
+                (send.socket_id, socket::Command::Send(message_id, send.data))
+            }
+            (ffi::WebSocketMessage::NextFrame(next), Some(message_id)) => {
+                (next.socket_id, socket::Command::NextFrame(message_id))
+            }
+            (ffi::WebSocketMessage::Close(close), message_id) => (
+                close.socket_id,
+                socket::Command::Close(message_id, close.code, close.reason),
+            ),
+            (_, None) => continue,
+        };
+
+        match sockets.get(&socket_id) {
+            Some((pid, tx)) if *pid == msg.emitter_pid => {
+                if let Err(err) = tx.unbounded_send(command) {
+                    err.into_inner().refuse();
+                }
+            }
+            _ => command.refuse(),
+        }
+    }
+}
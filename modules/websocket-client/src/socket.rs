@@ -0,0 +1,539 @@
+// Copyright (C) 2019-2020  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Handling of a single connection.
+
+use crate::frame;
+
+use futures::{channel::mpsc, prelude::*};
+use redshirt_syscalls::MessageId;
+use redshirt_tcp_interface::{TcpError, TcpStream};
+use redshirt_websocket_interface::ffi::{self, WebSocketData, WebSocketError, WebSocketEvent};
+use std::{
+    collections::VecDeque,
+    io,
+    net::{IpAddr, SocketAddr},
+    time::Duration,
+};
+
+/// Value appended to the key of the opening handshake before hashing it, as defined in RFC 6455.
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Maximum size of the response to the opening handshake.
+const MAX_HEAD_LEN: usize = 64 * 1024;
+
+/// Maximum size of a message sent by the server, after reassembling its fragments.
+const MAX_MESSAGE_LEN: usize = 16 * 1024 * 1024;
+
+/// Maximum time to wait for the server to answer the closing handshake.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Status code sent when the program that has opened the socket no longer exists.
+const CLOSE_GOING_AWAY: u16 = 1001;
+
+/// Status code sent when the server has violated the protocol.
+const CLOSE_PROTOCOL_ERROR: u16 = 1002;
+
+/// Message concerning a socket, sent by the program that has opened it.
+#[derive(Debug)]
+pub enum Command {
+    Send(MessageId, WebSocketData),
+    NextFrame(MessageId),
+    /// Closing handshake. Contains the message to answer, if any, the code, and the reason.
+    Close(Option<MessageId>, u16, String),
+}
+
+impl Command {
+    /// Answers the message with [`WebSocketError::InvalidSocketId`].
+    pub fn refuse(self) {
+        match self {
+            Command::Send(message_id, _) => {
+                let response = ffi::WebSocketSendResponse {
+                    result: Err(WebSocketError::InvalidSocketId),
+                };
+                redshirt_syscalls::emit_answer(message_id, &response);
+            }
+            Command::NextFrame(message_id) => {
+                let response = ffi::WebSocketNextFrameResponse {
+                    result: Err(WebSocketError::InvalidSocketId),
+                };
+                redshirt_syscalls::emit_answer(message_id, &response);
+            }
+            Command::Close(Some(message_id), _, _) => {
+                let response = ffi::WebSocketCloseResponse {
+                    result: Err(WebSocketError::InvalidSocketId),
+                };
+                redshirt_syscalls::emit_answer(message_id, &response);
+            }
+            Command::Close(None, _, _) => {}
+        }
+    }
+}
+
+/// Connects to the URL, answers `message_id`, then processes the messages received on
+/// `commands`.
+///
+/// Returns once the connection has been closed. If `commands` is closed while the connection is
+/// still open, the connection is closed as well.
+pub async fn run(
+    message_id: MessageId,
+    connect: ffi::WebSocketConnect,
+    socket_id: u32,
+    mut commands: mpsc::UnboundedReceiver<Command>,
+) {
+    let mut connection = match Connection::open(&connect.url).await {
+        Ok(c) => {
+            let response = ffi::WebSocketConnectResponse {
+                result: Ok(socket_id),
+            };
+            redshirt_syscalls::emit_answer(message_id, &response);
+            c
+        }
+        Err(err) => {
+            let response = ffi::WebSocketConnectResponse { result: Err(err) };
+            redshirt_syscalls::emit_answer(message_id, &response);
+            return;
+        }
+    };
+
+    // `NextFrame` messages that haven't been answered yet.
+    let mut reads = VecDeque::<MessageId>::new();
+
+    loop {
+        while !reads.is_empty() && !connection.events.is_empty() {
+            let message_id = reads.pop_front().unwrap();
+            let result = connection.events.pop_front().unwrap();
+            let is_last = !is_message(&result);
+            let response = ffi::WebSocketNextFrameResponse { result };
+            redshirt_syscalls::emit_answer(message_id, &response);
+            if is_last {
+                refuse_remaining(reads, commands);
+                return;
+            }
+        }
+
+        let command = if connection.ended {
+            commands.next().await
+        } else {
+            let next_command = commands.next();
+            let receive = connection.receive();
+            futures::pin_mut!(receive);
+            match future::select(next_command, receive).await {
+                future::Either::Left((command, _)) => command,
+                future::Either::Right(((), _)) => continue,
+            }
+        };
+
+        match command {
+            Some(Command::Send(message_id, data)) => {
+                let result = if connection.ended {
+                    Err(WebSocketError::Closed)
+                } else {
+                    connection.send(data).await
+                };
+                if let Err(err) = &result {
+                    if !connection.ended {
+                        connection.push_event(Err(err.clone()));
+                    }
+                }
+                let response = ffi::WebSocketSendResponse { result };
+                redshirt_syscalls::emit_answer(message_id, &response);
+            }
+            Some(Command::NextFrame(message_id)) => reads.push_back(message_id),
+            Some(Command::Close(message_id, code, reason)) => {
+                let valid = (code == 1000 || (3000..5000).contains(&code)) && reason.len() <= 123;
+                if !valid {
+                    if let Some(message_id) = message_id {
+                        let response = ffi::WebSocketCloseResponse {
+                            result: Err(WebSocketError::InvalidCloseCode),
+                        };
+                        redshirt_syscalls::emit_answer(message_id, &response);
+                    }
+                    continue;
+                }
+
+                if !connection.ended {
+                    connection.close(code, &reason).await;
+                }
+                for message_id in reads.drain(..) {
+                    let response = ffi::WebSocketNextFrameResponse {
+                        result: Err(WebSocketError::Closed),
+                    };
+                    redshirt_syscalls::emit_answer(message_id, &response);
+                }
+                if let Some(message_id) = message_id {
+                    let response = ffi::WebSocketCloseResponse { result: Ok(()) };
+                    redshirt_syscalls::emit_answer(message_id, &response);
+                }
+                refuse_remaining(reads, commands);
+                return;
+            }
+            None => {
+                if !connection.ended {
+                    connection.close(CLOSE_GOING_AWAY, "").await;
+                }
+                return;
+            }
+        }
+    }
+}
+
+/// Returns true if the event is a message, after which more events can follow.
+fn is_message(event: &Result<WebSocketEvent, WebSocketError>) -> bool {
+    match event {
+        Ok(WebSocketEvent::Message(_)) => true,
+        _ => false,
+    }
+}
+
+/// Answers the `NextFrame` messages in `reads` and the messages queued in `commands` with an
+/// error, as the socket no longer exists.
+fn refuse_remaining(reads: VecDeque<MessageId>, mut commands: mpsc::UnboundedReceiver<Command>) {
+    for message_id in reads {
+        let response = ffi::WebSocketNextFrameResponse {
+            result: Err(WebSocketError::Closed),
+        };
+        redshirt_syscalls::emit_answer(message_id, &response);
+    }
+
+    // Closing the channel makes the main task refuse the next messages.
+    commands.close();
+    while let Ok(Some(command)) = commands.try_next() {
+        command.refuse();
+    }
+}
+
+/// Connection to a server whose opening handshake has finished.
+struct Connection {
+    stream: TcpStream,
+    /// Data received from the server and not processed yet.
+    buffer: Vec<u8>,
+    /// If the server is in the middle of sending a fragmented message, its opcode and the
+    /// payload received so far.
+    fragmented: Option<(u8, Vec<u8>)>,
+    /// Events received from the server that haven't been requested yet.
+    events: VecDeque<Result<WebSocketEvent, WebSocketError>>,
+    /// True if an event that ends the connection has been pushed to `events`. Nothing is read
+    /// from the server anymore afterwards.
+    ended: bool,
+}
+
+impl Connection {
+    /// Connects to the server and performs the opening handshake.
+    async fn open(url: &str) -> Result<Connection, WebSocketError> {
+        let url = url::Url::parse(url).map_err(|_| WebSocketError::InvalidUrl)?;
+        let tls = match url.scheme() {
+            "ws" => false,
+            "wss" => true,
+            _ => return Err(WebSocketError::InvalidUrl),
+        };
+        let ip = match url.host() {
+            Some(url::Host::Ipv4(ip)) => IpAddr::V4(ip),
+            Some(url::Host::Ipv6(ip)) => IpAddr::V6(ip),
+            // TODO: resolve the name once a DNS interface exists
+            Some(url::Host::Domain(_)) => return Err(WebSocketError::UnresolvableHost),
+            None => return Err(WebSocketError::InvalidUrl),
+        };
+        let port = url
+            .port_or_known_default()
+            .unwrap_or(if tls { 443 } else { 80 });
+        let host = url.host_str().ok_or(WebSocketError::InvalidUrl)?;
+
+        let stream = TcpStream::connect(&SocketAddr::new(ip, port))
+            .await
+            .map_err(WebSocketError::Connection)?;
+        let stream = if tls {
+            redshirt_tls_interface::connect(stream, host)
+                .await
+                .map_err(WebSocketError::Tls)?
+        } else {
+            stream
+        };
+
+        let mut connection = Connection {
+            stream,
+            buffer: Vec::new(),
+            fragmented: None,
+            events: VecDeque::new(),
+            ended: false,
+        };
+
+        let key = base64::encode(&redshirt_random_interface::generate(16).await);
+        let request = format!(
+            "GET {} HTTP/1.1\r\n\
+             Host: {}\r\n\
+             Upgrade: websocket\r\n\
+             Connection: Upgrade\r\n\
+             Sec-WebSocket-Key: {}\r\n\
+             Sec-WebSocket-Version: 13\r\n\r\n",
+            &url[url::Position::BeforePath..url::Position::AfterQuery],
+            &url[url::Position::BeforeHost..url::Position::AfterPort],
+            key
+        );
+        connection.write(request.as_bytes()).await?;
+
+        let expected_accept = {
+            let mut hasher = sha1::Sha1::new();
+            hasher.update(key.as_bytes());
+            hasher.update(ACCEPT_GUID.as_bytes());
+            base64::encode(&hasher.digest().bytes())
+        };
+        connection.read_handshake_response(&expected_accept).await?;
+        Ok(connection)
+    }
+
+    /// Reads the response to the opening handshake, and checks that the server has accepted it.
+    async fn read_handshake_response(
+        &mut self,
+        expected_accept: &str,
+    ) -> Result<(), WebSocketError> {
+        loop {
+            let mut headers = [httparse::EMPTY_HEADER; 64];
+            let mut response = httparse::Response::new(&mut headers);
+            let len = match response.parse(&self.buffer) {
+                Ok(httparse::Status::Complete(len)) => len,
+                Ok(httparse::Status::Partial) => {
+                    if self.buffer.len() >= MAX_HEAD_LEN || self.read_more().await? == 0 {
+                        return Err(WebSocketError::Protocol);
+                    }
+                    continue;
+                }
+                Err(_) => return Err(WebSocketError::Protocol),
+            };
+
+            match response.code {
+                Some(101) => {}
+                Some(code) => return Err(WebSocketError::Rejected(code)),
+                None => return Err(WebSocketError::Protocol),
+            }
+
+            let header = |name: &str| {
+                response
+                    .headers
+                    .iter()
+                    .find(|h| h.name.eq_ignore_ascii_case(name))
+                    .and_then(|h| std::str::from_utf8(h.value).ok())
+                    .unwrap_or("")
+            };
+            let upgrade_ok = header("Upgrade").trim().eq_ignore_ascii_case("websocket");
+            let connection_ok = header("Connection")
+                .split(',')
+                .any(|v| v.trim().eq_ignore_ascii_case("upgrade"));
+            let accept_ok = header("Sec-WebSocket-Accept").trim() == expected_accept;
+            if !upgrade_ok || !connection_ok || !accept_ok {
+                return Err(WebSocketError::Protocol);
+            }
+
+            // Whatever follows the response already belongs to the frames.
+            self.buffer.drain(..len);
+            return Ok(());
+        }
+    }
+
+    /// Reads data from the server until at least one event has been pushed to `events`. Pings
+    /// are answered, and so is the closing handshake if the server starts it.
+    ///
+    /// The future can be dropped at any point without losing events.
+    async fn receive(&mut self) {
+        debug_assert!(!self.ended);
+        let num_events = self.events.len();
+        while !self.ended && self.events.len() == num_events {
+            if let Err(err) = self.receive_step().await {
+                self.push_event(Err(err));
+            }
+        }
+    }
+
+    /// Processes the next frame in the buffer, or reads more data if there isn't any.
+    async fn receive_step(&mut self) -> Result<(), WebSocketError> {
+        let frame = match self.next_frame().await? {
+            Some(f) => f,
+            None => {
+                if self.read_more().await? == 0 {
+                    return Err(WebSocketError::Connection(TcpError::ConnectionReset));
+                }
+                return Ok(());
+            }
+        };
+
+        match frame.opcode {
+            frame::OPCODE_PING => self.write_frame(frame::OPCODE_PONG, &frame.payload).await?,
+            frame::OPCODE_PONG => {}
+            frame::OPCODE_CLOSE => {
+                let (code, reason) = match frame::decode_close_payload(&frame.payload) {
+                    Ok(c) => c,
+                    Err(frame::InvalidFrame) => return Err(self.protocol_error().await),
+                };
+                // Echo the status code, as required by the protocol. The connection is closed
+                // right after, so errors don't matter.
+                let payload = code
+                    .map(|c| frame::close_payload(c, ""))
+                    .unwrap_or_default();
+                let _ = self.write_frame(frame::OPCODE_CLOSE, &payload).await;
+                self.push_event(Ok(WebSocketEvent::Closed { code, reason }));
+            }
+            _ => {
+                if let Some(data) = self.reassemble(frame).await? {
+                    self.push_event(Ok(WebSocketEvent::Message(data)));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Pushes an event to `events`, and updates `ended`.
+    fn push_event(&mut self, event: Result<WebSocketEvent, WebSocketError>) {
+        debug_assert!(!self.ended);
+        self.ended = !is_message(&event);
+        self.events.push_back(event);
+    }
+
+    /// Decodes the next frame in the buffer, if any.
+    async fn next_frame(&mut self) -> Result<Option<frame::Frame>, WebSocketError> {
+        match frame::decode(&mut self.buffer, MAX_MESSAGE_LEN) {
+            Ok(frame) => Ok(frame),
+            Err(frame::InvalidFrame) => Err(self.protocol_error().await),
+        }
+    }
+
+    /// Adds a text, binary or continuation frame to the message being received. Returns the
+    /// message if it is complete.
+    async fn reassemble(
+        &mut self,
+        frame: frame::Frame,
+    ) -> Result<Option<WebSocketData>, WebSocketError> {
+        let (opcode, payload) = match (frame.opcode, self.fragmented.take()) {
+            (frame::OPCODE_TEXT, None) | (frame::OPCODE_BINARY, None) => {
+                (frame.opcode, frame.payload)
+            }
+            (frame::OPCODE_CONTINUATION, Some((opcode, mut payload)))
+                if payload.len() + frame.payload.len() <= MAX_MESSAGE_LEN =>
+            {
+                payload.extend_from_slice(&frame.payload);
+                (opcode, payload)
+            }
+            _ => return Err(self.protocol_error().await),
+        };
+
+        if !frame.fin {
+            self.fragmented = Some((opcode, payload));
+            return Ok(None);
+        }
+
+        if opcode == frame::OPCODE_TEXT {
+            match String::from_utf8(payload) {
+                Ok(text) => Ok(Some(WebSocketData::Text(text))),
+                Err(_) => Err(self.protocol_error().await),
+            }
+        } else {
+            Ok(Some(WebSocketData::Binary(payload)))
+        }
+    }
+
+    /// Sends a message to the server.
+    async fn send(&mut self, data: WebSocketData) -> Result<(), WebSocketError> {
+        match data {
+            WebSocketData::Text(text) => {
+                self.write_frame(frame::OPCODE_TEXT, text.as_bytes()).await
+            }
+            WebSocketData::Binary(data) => self.write_frame(frame::OPCODE_BINARY, &data).await,
+        }
+    }
+
+    /// Sends a close frame, then waits for the server to answer it or to close the connection.
+    /// Messages received in the meantime are discarded.
+    async fn close(&mut self, code: u16, reason: &str) {
+        let payload = frame::close_payload(code, reason);
+        if self
+            .write_frame(frame::OPCODE_CLOSE, &payload)
+            .await
+            .is_err()
+        {
+            return;
+        }
+
+        let wait_close = async {
+            loop {
+                match self.read_more().await {
+                    Ok(0) | Err(_) => return,
+                    Ok(_) => {}
+                }
+                while let Ok(Some(frame)) = frame::decode(&mut self.buffer, MAX_MESSAGE_LEN) {
+                    if frame.opcode == frame::OPCODE_CLOSE {
+                        return;
+                    }
+                }
+            }
+        };
+
+        futures::pin_mut!(wait_close);
+        let timeout = redshirt_time_interface::Delay::new(CLOSE_TIMEOUT);
+        future::select(wait_close, timeout).await;
+    }
+
+    /// Sends a close frame indicating a protocol error, and returns the error to report.
+    async fn protocol_error(&mut self) -> WebSocketError {
+        let payload = frame::close_payload(CLOSE_PROTOCOL_ERROR, "");
+        let _ = self.write_frame(frame::OPCODE_CLOSE, &payload).await;
+        WebSocketError::Protocol
+    }
+
+    /// Writes a frame with the given opcode and payload.
+    async fn write_frame(&mut self, opcode: u8, payload: &[u8]) -> Result<(), WebSocketError> {
+        let mut mask = [0; 4];
+        redshirt_random_interface::generate_in(&mut mask).await;
+        self.write(&frame::encode(opcode, payload, mask)).await
+    }
+
+    /// Writes data on the connection.
+    async fn write(&mut self, data: &[u8]) -> Result<(), WebSocketError> {
+        self.stream
+            .write_all(data)
+            .await
+            .map_err(connection_error)?;
+        self.stream.flush().await.map_err(connection_error)
+    }
+
+    /// Reads more data from the server into the buffer. Returns 0 if the server has closed the
+    /// connection.
+    async fn read_more(&mut self) -> Result<usize, WebSocketError> {
+        let mut data = [0; 16 * 1024];
+        let len = self
+            .stream
+            .read(&mut data)
+            .await
+            .map_err(connection_error)?;
+        self.buffer.extend_from_slice(&data[..len]);
+        Ok(len)
+    }
+}
+
+/// Turns an error of the connection into a [`WebSocketError`].
+fn connection_error(err: io::Error) -> WebSocketError {
+    let err = match err.kind() {
+        io::ErrorKind::ConnectionRefused => TcpError::ConnectionRefused,
+        io::ErrorKind::ConnectionReset | io::ErrorKind::ConnectionAborted => {
+            TcpError::ConnectionReset
+        }
+        io::ErrorKind::TimedOut => TcpError::TimedOut,
+        io::ErrorKind::AddrInUse => TcpError::AddrInUse,
+        io::ErrorKind::PermissionDenied => TcpError::PermissionDenied,
+        io::ErrorKind::NotConnected => TcpError::InvalidSocket,
+        io::ErrorKind::WouldBlock => TcpError::WouldBlock,
+        _ => TcpError::Other(err.raw_os_error()),
+    };
+    WebSocketError::Connection(err)
+}